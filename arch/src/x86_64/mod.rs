@@ -21,8 +21,10 @@ use linux_loader::loader::bootparam::boot_params;
 use linux_loader::loader::elf::start_info::{
     hvm_memmap_table_entry, hvm_modlist_entry, hvm_start_info,
 };
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::mem;
+use std::str::FromStr;
 use vm_memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryAtomic,
     GuestMemoryRegion, GuestUsize,
@@ -33,6 +35,7 @@ use std::arch::x86_64;
 pub mod tdx;
 
 // CPUID feature bits
+const X2APIC_ECX_BIT: u8 = 21; // x2APIC ecx bit.
 const TSC_DEADLINE_TIMER_ECX_BIT: u8 = 24; // tsc deadline timer ecx bit.
 const HYPERVISOR_ECX_BIT: u8 = 31; // Hypervisor ecx bit.
 const MTRR_EDX_BIT: u8 = 12; // Hypervisor ecx bit.
@@ -45,12 +48,52 @@ const KVM_FEATURE_CLOCKSOURCE_BIT: u8 = 0;
 const KVM_FEATURE_CLOCKSOURCE2_BIT: u8 = 3;
 #[cfg(feature = "tdx")]
 const KVM_FEATURE_CLOCKSOURCE_STABLE_BIT: u8 = 24;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_ASYNC_PF_BIT: u8 = 4;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_ASYNC_PF_VMEXIT_BIT: u8 = 10;
-#[cfg(feature = "tdx")]
+const KVM_FEATURE_ASYNC_PF_INT_BIT: u8 = 14;
 const KVM_FEATURE_STEAL_TIME_BIT: u8 = 5;
+const KVM_FEATURE_PV_EOI_BIT: u8 = 6;
+const KVM_FEATURE_MSI_EXT_DEST_ID_BIT: u8 = 15;
+
+/// KVM paravirtualized features which can be explicitly toggled for the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KvmPvFeature {
+    /// Paravirtualized end of interrupt (MSR_KVM_PV_EOI_EN)
+    KvmPvEoi,
+    /// Steal time accounting (MSR_KVM_STEAL_TIME)
+    StealTime,
+    /// Asynchronous page faults (MSR_KVM_ASYNC_PF_EN)
+    AsyncPf,
+}
+
+impl KvmPvFeature {
+    /// Bits of CPUID leaf 0x4000_0001 EAX advertising the feature.
+    fn cpuid_mask(&self) -> u32 {
+        match self {
+            KvmPvFeature::KvmPvEoi => 1 << KVM_FEATURE_PV_EOI_BIT,
+            KvmPvFeature::StealTime => 1 << KVM_FEATURE_STEAL_TIME_BIT,
+            KvmPvFeature::AsyncPf => {
+                1 << KVM_FEATURE_ASYNC_PF_BIT
+                    | 1 << KVM_FEATURE_ASYNC_PF_VMEXIT_BIT
+                    | 1 << KVM_FEATURE_ASYNC_PF_INT_BIT
+            }
+        }
+    }
+}
+
+impl FromStr for KvmPvFeature {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "kvm_pv_eoi" => Ok(KvmPvFeature::KvmPvEoi),
+            "steal_time" => Ok(KvmPvFeature::StealTime),
+            "async_pf" => Ok(KvmPvFeature::AsyncPf),
+            _ => Err(s.to_owned()),
+        }
+    }
+}
 
 pub const _NSIG: i32 = 65;
 
@@ -61,6 +104,8 @@ pub const _NSIG: i32 = 65;
 pub struct EntryPoint {
     /// Address in guest memory where the guest must start execution
     pub entry_addr: GuestAddress,
+    /// Start address and size of the guest memory the payload was loaded into
+    pub load_range: (GuestAddress, u64),
 }
 
 const E820_RAM: u32 = 1;
@@ -552,10 +597,11 @@ impl CpuidFeatureEntry {
 
 pub fn generate_common_cpuid(
     hypervisor: &Arc<dyn hypervisor::Hypervisor>,
-    topology: Option<(u8, u8, u8)>,
+    topology: Option<(u32, u32, u32)>,
     sgx_epc_sections: Option<Vec<SgxEpcSection>>,
     phys_bits: u8,
     kvm_hyperv: bool,
+    kvm_pv_features: &BTreeMap<KvmPvFeature, bool>,
     #[cfg(feature = "tdx")] tdx_enabled: bool,
 ) -> super::Result<Vec<CpuIdEntry>> {
     // SAFETY: cpuid called with valid leaves
@@ -576,6 +622,16 @@ pub fn generate_common_cpuid(
 
     info!("Generating guest CPUID for with physical address size: {phys_bits}");
     let cpuid_patches = vec![
+        // Patch x2APIC bit, required to address more than 255 vCPUs
+        CpuidPatch {
+            function: 1,
+            index: 0,
+            flags_bit: None,
+            eax_bit: None,
+            ebx_bit: None,
+            ecx_bit: Some(X2APIC_ECX_BIT),
+            edx_bit: None,
+        },
         // Patch tsc deadline timer bit
         CpuidPatch {
             function: 1,
@@ -675,6 +731,15 @@ pub fn generate_common_cpuid(
                 entry.eax = (entry.eax & 0xffff_ff00) | (phys_bits as u32 & 0xff);
             }
             0x4000_0001 => {
+                for (feature, enabled) in kvm_pv_features.iter() {
+                    let mask = feature.cpuid_mask();
+                    if !enabled {
+                        entry.eax &= !mask;
+                    } else if entry.eax & mask == 0 {
+                        warn!("KVM PV feature {:?} is not supported by the host", feature);
+                    }
+                }
+
                 // These features are not supported by TDX
                 #[cfg(feature = "tdx")]
                 if tdx_enabled {
@@ -755,27 +820,125 @@ pub fn generate_common_cpuid(
     Ok(cpuid)
 }
 
+// Encode a CPU signature as reported by CPUID leaf 0x1 EAX. Families above
+// 0xf and models above 0xf (for families 0x6 and 0xf only) are spread over
+// the extended family and model fields.
+fn cpu_signature(family: u16, model: u8, stepping: u8) -> u32 {
+    let (base_family, ext_family) = if family >= 0xf {
+        (0xf, u32::from(family - 0xf))
+    } else {
+        (u32::from(family), 0)
+    };
+    let (base_model, ext_model) = (u32::from(model & 0xf), u32::from(model >> 4));
+
+    ext_family << 20
+        | ext_model << 16
+        | base_family << 8
+        | base_model << 4
+        | u32::from(stepping & 0xf)
+}
+
+/// Replace the CPU signature (family, model and stepping) and the brand
+/// string seen by the guest, hiding the identity of the host CPU. The brand
+/// string is truncated to 47 bytes so that it remains NUL terminated.
+pub fn set_cpu_identity(
+    cpuid: &mut Vec<CpuIdEntry>,
+    family: u16,
+    model: u8,
+    stepping: u8,
+    brand: &str,
+) {
+    let signature = cpu_signature(family, model, stepping);
+    for entry in cpuid.iter_mut() {
+        match entry.function {
+            0x1 => entry.eax = signature,
+            // AMD mirrors the signature in the extended leaf, Intel reports 0
+            0x8000_0001 if entry.eax != 0 => entry.eax = signature,
+            _ => {}
+        }
+    }
+
+    let mut bytes = [0u8; 48];
+    let len = brand.len().min(47);
+    bytes[..len].copy_from_slice(&brand.as_bytes()[..len]);
+    let reg = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+    for (i, function) in (0x8000_0002..=0x8000_0004).enumerate() {
+        cpuid.retain(|c| c.function != function);
+        cpuid.push(CpuIdEntry {
+            function,
+            eax: reg(i * 4),
+            ebx: reg(i * 4 + 1),
+            ecx: reg(i * 4 + 2),
+            edx: reg(i * 4 + 3),
+            ..Default::default()
+        });
+    }
+}
+
+/// Hide from the guest that it runs in a virtual machine, by clearing the
+/// hypervisor present bit and removing the hypervisor CPUID leaves. The KVM
+/// PV features being advertised through these leaves, the guest no longer
+/// uses any of them.
+pub fn hide_hypervisor(cpuid: &mut Vec<CpuIdEntry>) {
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0x1 {
+            entry.ecx &= !(1 << HYPERVISOR_ECX_BIT);
+        }
+    }
+    cpuid.retain(|c| !(0x4000_0000..=0x4000_00ff).contains(&c.function));
+}
+
+/// Hide the TSC-deadline mode of the local APIC timer, so that the guest
+/// programs the timer through its initial count register instead.
+pub fn hide_tsc_deadline_timer(cpuid: &mut [CpuIdEntry]) {
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0x1 {
+            entry.ecx &= !(1 << TSC_DEADLINE_TIMER_ECX_BIT);
+        }
+    }
+}
+
+/// Advertise or hide the KVM extended destination ID feature, letting the
+/// guest target APIC ids above 255 through bits 11:5 of the MSI address.
+/// Returns false if the feature must be advertised but the host doesn't
+/// support it.
+pub fn set_msi_ext_dest_id(cpuid: &mut [CpuIdEntry], enable: bool) -> bool {
+    let mask = 1 << KVM_FEATURE_MSI_EXT_DEST_ID_BIT;
+    let mut supported = false;
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0x4000_0001 {
+            supported = entry.eax & mask == mask;
+            if !enable {
+                entry.eax &= !mask;
+            }
+        }
+    }
+    !enable || supported
+}
+
 pub fn configure_vcpu(
     vcpu: &Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     boot_setup: Option<(EntryPoint, &GuestMemoryAtomic<GuestMemoryMmap>)>,
     cpuid: Vec<CpuIdEntry>,
     kvm_hyperv: bool,
 ) -> super::Result<()> {
     // Per vCPU CPUID changes; common are handled via generate_common_cpuid()
     let mut cpuid = cpuid;
-    CpuidPatch::set_cpuid_reg(&mut cpuid, 0xb, None, CpuidReg::EDX, u32::from(id));
-    CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1f, None, CpuidReg::EDX, u32::from(id));
+    // The extended topology leaves report the full 32-bit x2APIC id
+    CpuidPatch::set_cpuid_reg(&mut cpuid, 0xb, None, CpuidReg::EDX, id);
+    CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1f, None, CpuidReg::EDX, id);
 
-    // Set ApicId in cpuid for each vcpu
+    // Set ApicId in cpuid for each vcpu, only the low 8 bits fit in leaf 0x1
     // SAFETY: get host cpuid when eax=1
     let mut cpu_ebx = unsafe { core::arch::x86_64::__cpuid(1) }.ebx;
     cpu_ebx &= 0xffffff;
-    cpu_ebx |= (id as u32) << 24;
+    cpu_ebx |= (id & 0xff) << 24;
     CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1, None, CpuidReg::EBX, cpu_ebx);
 
-    // The TSC frequency CPUID leaf should not be included when running with HyperV emulation
-    if !kvm_hyperv {
+    // The TSC frequency CPUID leaf should not be included when running with HyperV emulation,
+    // nor when the hypervisor leaves are hidden from the guest
+    if !kvm_hyperv && cpuid.iter().any(|c| c.function == 0x4000_0000) {
         if let Some(tsc_khz) = vcpu.tsc_khz().map_err(Error::GetTscFrequency)? {
             // Need to check that the TSC doesn't vary with dynamic frequency
             // SAFETY: cpuid called with valid leaves
@@ -857,17 +1020,19 @@ pub fn arch_memory_regions() -> Vec<(GuestAddress, usize, RegionType)> {
 /// * `cmdline_addr` - Address in `guest_mem` where the kernel command line was loaded.
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `level_irqs` - IOAPIC pins of the devices described through the kernel command line.
 #[allow(clippy::too_many_arguments)]
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     initramfs: &Option<InitramfsConfig>,
-    _num_cpus: u8,
+    num_cpus: u32,
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
     serial_number: Option<&str>,
     uuid: Option<&str>,
     oem_strings: Option<&[&str]>,
+    level_irqs: &[u32],
 ) -> super::Result<()> {
     // Write EBDA address to location where ACPICA expects to find it
     guest_mem
@@ -880,7 +1045,14 @@ pub fn configure_system(
     // Place the MP table after the SMIOS table aligned to 16 bytes
     let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
     let offset = GuestAddress((offset.0 + 16) & !0xf);
-    mptable::setup_mptable(offset, guest_mem, _num_cpus).map_err(Error::MpTableSetup)?;
+    // The MP table can't describe more than 254 CPUs, in which case the
+    // guest has to rely on the ACPI MADT only.
+    if let Ok(num_cpus) = u8::try_from(num_cpus) {
+        if u32::from(num_cpus) <= mptable::MAX_SUPPORTED_CPUS {
+            mptable::setup_mptable(offset, guest_mem, num_cpus, level_irqs)
+                .map_err(Error::MpTableSetup)?;
+        }
+    }
 
     // Check that the RAM is not smaller than the RSDP start address
     if let Some(rsdp_addr) = rsdp_addr {
@@ -1157,23 +1329,17 @@ pub fn get_host_cpu_phys_bits(hypervisor: &Arc<dyn hypervisor::Hypervisor>) -> u
 
 fn update_cpuid_topology(
     cpuid: &mut Vec<CpuIdEntry>,
-    threads_per_core: u8,
-    cores_per_die: u8,
-    dies_per_package: u8,
+    threads_per_core: u32,
+    cores_per_die: u32,
+    dies_per_package: u32,
 ) {
-    let thread_width = 8 - (threads_per_core - 1).leading_zeros();
-    let core_width = (8 - (cores_per_die - 1).leading_zeros()) + thread_width;
-    let die_width = (8 - (dies_per_package - 1).leading_zeros()) + core_width;
+    let thread_width = 32 - (threads_per_core - 1).leading_zeros();
+    let core_width = (32 - (cores_per_die - 1).leading_zeros()) + thread_width;
+    let die_width = (32 - (dies_per_package - 1).leading_zeros()) + core_width;
 
     // CPU Topology leaf 0xb
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(0), CpuidReg::EAX, thread_width);
-    CpuidPatch::set_cpuid_reg(
-        cpuid,
-        0xb,
-        Some(0),
-        CpuidReg::EBX,
-        u32::from(threads_per_core),
-    );
+    CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(0), CpuidReg::EBX, threads_per_core);
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(0), CpuidReg::ECX, 1 << 8);
 
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(1), CpuidReg::EAX, die_width);
@@ -1182,19 +1348,13 @@ fn update_cpuid_topology(
         0xb,
         Some(1),
         CpuidReg::EBX,
-        u32::from(dies_per_package * cores_per_die * threads_per_core),
+        dies_per_package * cores_per_die * threads_per_core,
     );
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(1), CpuidReg::ECX, 2 << 8);
 
     // CPU Topology leaf 0x1f
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(0), CpuidReg::EAX, thread_width);
-    CpuidPatch::set_cpuid_reg(
-        cpuid,
-        0x1f,
-        Some(0),
-        CpuidReg::EBX,
-        u32::from(threads_per_core),
-    );
+    CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(0), CpuidReg::EBX, threads_per_core);
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(0), CpuidReg::ECX, 1 << 8);
 
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(1), CpuidReg::EAX, core_width);
@@ -1203,7 +1363,7 @@ fn update_cpuid_topology(
         0x1f,
         Some(1),
         CpuidReg::EBX,
-        u32::from(cores_per_die * threads_per_core),
+        cores_per_die * threads_per_core,
     );
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(1), CpuidReg::ECX, 2 << 8);
 
@@ -1213,7 +1373,7 @@ fn update_cpuid_topology(
         0x1f,
         Some(2),
         CpuidReg::EBX,
-        u32::from(dies_per_package * cores_per_die * threads_per_core),
+        dies_per_package * cores_per_die * threads_per_core,
     );
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(2), CpuidReg::ECX, 5 << 8);
 }
@@ -1294,6 +1454,7 @@ mod tests {
             None,
             None,
             None,
+            &[],
         );
         assert!(config_err.is_err());
 
@@ -1316,6 +1477,7 @@ mod tests {
             None,
             None,
             None,
+            &[],
         )
         .unwrap();
 
@@ -1343,6 +1505,7 @@ mod tests {
             None,
             None,
             None,
+            &[],
         )
         .unwrap();
 
@@ -1356,6 +1519,7 @@ mod tests {
             None,
             None,
             None,
+            &[],
         )
         .unwrap();
     }
@@ -1384,4 +1548,77 @@ mod tests {
 
         assert_eq!(format!("{memmap:?}"), format!("{expected_memmap:?}"));
     }
+
+    #[test]
+    fn test_cpu_identity() {
+        assert_eq!(cpu_signature(6, 0x55, 7), 0x0005_0657);
+        assert_eq!(cpu_signature(0x19, 0x01, 1), 0x00a0_0f11);
+        assert_eq!(cpu_signature(15, 6, 1), 0x0000_0f61);
+
+        let mut cpuid = vec![
+            CpuIdEntry {
+                function: 0x1,
+                eax: 0x0005_0657,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0002,
+                eax: 0xdead_beef,
+                ..Default::default()
+            },
+        ];
+        set_cpu_identity(&mut cpuid, 15, 6, 1, "Stable vCPU");
+        assert_eq!(cpuid[0].eax, 0x0000_0f61);
+
+        let brand: Vec<u8> = (0x8000_0002..=0x8000_0004)
+            .flat_map(|function| {
+                let e = cpuid.iter().find(|c| c.function == function).unwrap();
+                [e.eax, e.ebx, e.ecx, e.edx]
+                    .iter()
+                    .flat_map(|r| r.to_le_bytes())
+                    .collect::<Vec<u8>>()
+            })
+            .collect();
+        assert_eq!(&brand[..11], b"Stable vCPU");
+        assert!(brand[11..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_hide_hypervisor() {
+        let mut cpuid = vec![
+            CpuIdEntry {
+                function: 0x1,
+                ecx: 1 << HYPERVISOR_ECX_BIT | 1 << X2APIC_ECX_BIT,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x4000_0000,
+                eax: 0x4000_0001,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x4000_0001,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0000,
+                ..Default::default()
+            },
+        ];
+        hide_hypervisor(&mut cpuid);
+        assert_eq!(cpuid.len(), 2);
+        assert_eq!(cpuid[0].ecx, 1 << X2APIC_ECX_BIT);
+        assert_eq!(cpuid[1].function, 0x8000_0000);
+    }
+
+    #[test]
+    fn test_hide_tsc_deadline_timer() {
+        let mut cpuid = vec![CpuIdEntry {
+            function: 0x1,
+            ecx: 1 << TSC_DEADLINE_TIMER_ECX_BIT | 1 << X2APIC_ECX_BIT,
+            ..Default::default()
+        }];
+        hide_tsc_deadline_timer(&mut cpuid);
+        assert_eq!(cpuid[0].ecx, 1 << X2APIC_ECX_BIT);
+    }
 }
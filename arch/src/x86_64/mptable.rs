@@ -99,6 +99,8 @@ const APIC_VERSION: u8 = 0x14;
 const CPU_STEPPING: u32 = 0x600;
 const CPU_FEATURE_APIC: u32 = 0x200;
 const CPU_FEATURE_FPU: u32 = 0x001;
+const MP_IRQPOL_ACTIVE_HIGH: u16 = 0x1;
+const MP_IRQTRIG_LEVEL: u16 = 0xc;
 
 fn compute_checksum<T: Copy>(v: &T) -> u8 {
     // SAFETY: we are only reading the bytes within the size of the `T` reference `v`.
@@ -115,18 +117,35 @@ fn mpf_intel_compute_checksum(v: &mpspec::mpf_intel) -> u8 {
     (!checksum).wrapping_add(1)
 }
 
-fn compute_mp_size(num_cpus: u8) -> usize {
+fn compute_mp_size(num_cpus: u8, num_intsrc: usize) -> usize {
     mem::size_of::<MpfIntelWrapper>()
         + mem::size_of::<MpcTableWrapper>()
         + mem::size_of::<MpcCpuWrapper>() * (num_cpus as usize)
         + mem::size_of::<MpcIoapicWrapper>()
         + mem::size_of::<MpcBusWrapper>()
-        + mem::size_of::<MpcIntsrcWrapper>() * 16
+        + mem::size_of::<MpcIntsrcWrapper>() * num_intsrc
         + mem::size_of::<MpcLintsrcWrapper>() * 2
 }
 
-/// Performs setup of the MP table for the given `num_cpus`.
-pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
+// The 16 legacy interrupts, followed by the level triggered ones above them.
+fn intsrc_irqs(level_irqs: &[u32]) -> Vec<u32> {
+    let mut irqs: Vec<u32> = (0..16).collect();
+    for irq in level_irqs {
+        if !irqs.contains(irq) {
+            irqs.push(*irq);
+        }
+    }
+    irqs
+}
+
+/// Performs setup of the MP table for the given `num_cpus`, the IOAPIC pins
+/// listed in `level_irqs` being described as level triggered.
+pub fn setup_mptable(
+    offset: GuestAddress,
+    mem: &GuestMemoryMmap,
+    num_cpus: u8,
+    level_irqs: &[u32],
+) -> Result<()> {
     if num_cpus as u32 > MAX_SUPPORTED_CPUS {
         return Err(Error::TooManyCpus);
     }
@@ -134,7 +153,8 @@ pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, num_cpus: u8)
     // Used to keep track of the next base pointer into the MP table.
     let mut base_mp = offset;
 
-    let mp_size = compute_mp_size(num_cpus);
+    let irqs = intsrc_irqs(level_irqs);
+    let mp_size = compute_mp_size(num_cpus, irqs.len());
 
     if offset.unchecked_add(mp_size as u64) >= HIGH_RAM_START {
         warn!("Skipping mptable creation due to insufficient space");
@@ -220,17 +240,22 @@ pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, num_cpus: u8)
         base_mp = base_mp.unchecked_add(size as u64);
         checksum = checksum.wrapping_add(compute_checksum(&mpc_ioapic.0));
     }
-    // Per kvm_setup_default_irq_routing() in kernel
-    for i in 0..16 {
+    // Per kvm_setup_default_irq_routing() in kernel, along with the pins
+    // used by the devices described through the kernel command line.
+    for i in irqs {
         let size = mem::size_of::<MpcIntsrcWrapper>();
         let mut mpc_intsrc = MpcIntsrcWrapper(mpspec::mpc_intsrc::default());
         mpc_intsrc.0.type_ = mpspec::MP_INTSRC as u8;
         mpc_intsrc.0.irqtype = mpspec::MP_IRQ_SOURCE_TYPES_MP_INT as u8;
-        mpc_intsrc.0.irqflag = mpspec::MP_IRQDIR_DEFAULT as u16;
+        mpc_intsrc.0.irqflag = if level_irqs.contains(&i) {
+            MP_IRQPOL_ACTIVE_HIGH | MP_IRQTRIG_LEVEL
+        } else {
+            mpspec::MP_IRQDIR_DEFAULT as u16
+        };
         mpc_intsrc.0.srcbus = 0;
-        mpc_intsrc.0.srcbusirq = i;
+        mpc_intsrc.0.srcbusirq = i as u8;
         mpc_intsrc.0.dstapic = ioapicid;
-        mpc_intsrc.0.dstirq = i;
+        mpc_intsrc.0.dstirq = i as u8;
         mem.write_obj(mpc_intsrc, base_mp)
             .map_err(Error::WriteMpcIntsrc)?;
         base_mp = base_mp.unchecked_add(size as u64);
@@ -307,28 +332,29 @@ mod tests {
     #[test]
     fn bounds_check() {
         let num_cpus = 4;
-        let mem =
-            GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus))]).unwrap();
+        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus, 16))])
+            .unwrap();
 
-        setup_mptable(MPTABLE_START, &mem, num_cpus).unwrap();
+        setup_mptable(MPTABLE_START, &mem, num_cpus, &[]).unwrap();
     }
 
     #[test]
     fn bounds_check_fails() {
         let num_cpus = 4;
-        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus) - 1)])
-            .unwrap();
+        let mem =
+            GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus, 16) - 1)])
+                .unwrap();
 
-        assert!(setup_mptable(MPTABLE_START, &mem, num_cpus).is_err());
+        assert!(setup_mptable(MPTABLE_START, &mem, num_cpus, &[]).is_err());
     }
 
     #[test]
     fn mpf_intel_checksum() {
         let num_cpus = 1;
-        let mem =
-            GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus))]).unwrap();
+        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus, 16))])
+            .unwrap();
 
-        setup_mptable(MPTABLE_START, &mem, num_cpus).unwrap();
+        setup_mptable(MPTABLE_START, &mem, num_cpus, &[]).unwrap();
 
         let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
 
@@ -341,10 +367,10 @@ mod tests {
     #[test]
     fn mpc_table_checksum() {
         let num_cpus = 4;
-        let mem =
-            GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus))]).unwrap();
+        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus, 16))])
+            .unwrap();
 
-        setup_mptable(MPTABLE_START, &mem, num_cpus).unwrap();
+        setup_mptable(MPTABLE_START, &mem, num_cpus, &[]).unwrap();
 
         let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
         let mpc_offset = GuestAddress(mpf_intel.0.physptr as GuestUsize);
@@ -373,12 +399,12 @@ mod tests {
     fn cpu_entry_count() {
         let mem = GuestMemoryMmap::from_ranges(&[(
             MPTABLE_START,
-            compute_mp_size(MAX_SUPPORTED_CPUS as u8),
+            compute_mp_size(MAX_SUPPORTED_CPUS as u8, 16),
         )])
         .unwrap();
 
         for i in 0..MAX_SUPPORTED_CPUS as u8 {
-            setup_mptable(MPTABLE_START, &mem, i).unwrap();
+            setup_mptable(MPTABLE_START, &mem, i, &[]).unwrap();
 
             let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
             let mpc_offset = GuestAddress(mpf_intel.0.physptr as GuestUsize);
@@ -405,13 +431,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn level_irq_entries() {
+        let num_cpus = 1;
+        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus, 18))])
+            .unwrap();
+
+        setup_mptable(MPTABLE_START, &mem, num_cpus, &[15, 16, 17]).unwrap();
+
+        let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
+        let mpc_offset = GuestAddress(mpf_intel.0.physptr as GuestUsize);
+        let mpc_table: MpcTableWrapper = mem.read_obj(mpc_offset).unwrap();
+        let mpc_end = mpc_offset
+            .checked_add(mpc_table.0.length as GuestUsize)
+            .unwrap();
+
+        let mut entry_offset = mpc_offset
+            .checked_add(mem::size_of::<MpcTableWrapper>() as GuestUsize)
+            .unwrap();
+        let mut level_irqs = Vec::new();
+        let mut intsrc_count = 0;
+        while entry_offset < mpc_end {
+            let entry_type: u8 = mem.read_obj(entry_offset).unwrap();
+            if entry_type as u32 == mpspec::MP_INTSRC {
+                let intsrc: MpcIntsrcWrapper = mem.read_obj(entry_offset).unwrap();
+                if intsrc.0.irqflag == MP_IRQPOL_ACTIVE_HIGH | MP_IRQTRIG_LEVEL {
+                    level_irqs.push(intsrc.0.dstirq);
+                }
+                intsrc_count += 1;
+            }
+            entry_offset = entry_offset
+                .checked_add(table_entry_size(entry_type) as GuestUsize)
+                .unwrap();
+        }
+        assert_eq!(intsrc_count, 18);
+        assert_eq!(level_irqs, vec![15, 16, 17]);
+    }
+
     #[test]
     fn cpu_entry_count_max() {
         let cpus = MAX_SUPPORTED_CPUS + 1;
-        let mem =
-            GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(cpus as u8))]).unwrap();
+        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(cpus as u8, 16))])
+            .unwrap();
 
-        let result = setup_mptable(MPTABLE_START, &mem, cpus as u8);
+        let result = setup_mptable(MPTABLE_START, &mem, cpus as u8, &[]);
         assert!(result.is_err());
     }
 }
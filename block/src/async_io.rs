@@ -37,6 +37,9 @@ pub enum AsyncIoError {
     /// Failed synchronizing file.
     #[error("Failed synchronizing file: {0}")]
     Fsync(#[source] std::io::Error),
+    /// Failed cancelling a request.
+    #[error("Failed cancelling a request: {0}")]
+    Cancel(#[source] std::io::Error),
 }
 
 pub type AsyncIoResult<T> = std::result::Result<T, AsyncIoError>;
@@ -57,4 +60,12 @@ pub trait AsyncIo: Send {
     ) -> AsyncIoResult<()>;
     fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()>;
     fn next_completed_request(&mut self) -> Option<(u64, i32)>;
+    // Ask for a request which hasn't completed yet to be given up on. The
+    // request still completes through next_completed_request(), possibly
+    // with -ECANCELED, and until then the backend may access its buffers.
+    fn cancel(&mut self, _user_data: u64) -> AsyncIoResult<()> {
+        Err(AsyncIoError::Cancel(std::io::Error::from_raw_os_error(
+            libc::ENOTSUP,
+        )))
+    }
 }
@@ -0,0 +1,918 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Builds an ext2 filesystem image out of a host directory tree, so that the
+//! tree can be exposed read-only to the guest through a block device.
+//!
+//! The image is laid out in a single pass, each file being stored in
+//! contiguous blocks interleaved with its indirect blocks, as nothing is ever
+//! going to be allocated on the filesystem afterwards.
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read {0:?}: {1}")]
+    ReadSource(PathBuf, #[source] io::Error),
+    #[error("Failed to write the image: {0}")]
+    WriteImage(#[source] io::Error),
+    #[error("File name {0:?} is longer than 255 bytes")]
+    NameTooLong(OsString),
+    #[error("File {0:?} is too large for an ext2 filesystem")]
+    FileTooLarge(PathBuf),
+    #[error("Directory tree is too large for an ext2 filesystem")]
+    TreeTooLarge,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+const BLOCK_SIZE: usize = 4096;
+// Block size expressed as a shift of 1024
+const LOG_BLOCK_SIZE: u32 = 2;
+// Each group is described by a block bitmap, which must fit in a block.
+const BLOCKS_PER_GROUP: u64 = 8 * BLOCK_SIZE as u64;
+const INODE_SIZE: usize = 128;
+const INODES_PER_BLOCK: u64 = (BLOCK_SIZE / INODE_SIZE) as u64;
+const GROUP_DESC_SIZE: usize = 32;
+const SUPERBLOCK_OFFSET: usize = 1024;
+// Number of block addresses held by an indirect block
+const ADDR_PER_BLOCK: u64 = (BLOCK_SIZE / 4) as u64;
+const DIRECT_BLOCKS: usize = 12;
+// Symlinks shorter than this are stored in the inode itself.
+const FAST_SYMLINK_MAX_LEN: usize = 60;
+const MAX_NAME_LEN: usize = 255;
+
+const ROOT_INO: u32 = 2;
+const FIRST_INO: u32 = 11;
+
+const EXT2_MAGIC: u16 = 0xef53;
+const EXT2_VALID_FS: u16 = 1;
+const EXT2_ERRORS_CONTINUE: u16 = 1;
+const EXT2_DYNAMIC_REV: u32 = 1;
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x2;
+const FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x2;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_FIFO: u8 = 5;
+const FT_SOCK: u8 = 6;
+const FT_SYMLINK: u8 = 7;
+
+enum Kind {
+    Directory(Vec<(OsString, usize)>),
+    File(PathBuf),
+    Symlink(Vec<u8>),
+    Device,
+    Other,
+}
+
+struct Node {
+    kind: Kind,
+    metadata: Metadata,
+    // Directory the node was first found in
+    parent: usize,
+    ino: u32,
+    links: u16,
+}
+
+impl Node {
+    fn file_type(&self) -> u8 {
+        let file_type = self.metadata.file_type();
+        match self.kind {
+            Kind::Directory(_) => FT_DIR,
+            Kind::File(_) => FT_REG_FILE,
+            Kind::Symlink(_) => FT_SYMLINK,
+            Kind::Device if file_type.is_char_device() => FT_CHRDEV,
+            Kind::Device => FT_BLKDEV,
+            Kind::Other if file_type.is_fifo() => FT_FIFO,
+            Kind::Other => FT_SOCK,
+        }
+    }
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    // Host files already added, to preserve hard links
+    hard_links: HashMap<(u64, u64), usize>,
+    next_ino: u32,
+}
+
+impl Tree {
+    fn new(dir: &Path) -> Result<Self> {
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            hard_links: HashMap::new(),
+            next_ino: FIRST_INO,
+        };
+        let metadata = fs::metadata(dir).map_err(|e| Error::ReadSource(dir.to_path_buf(), e))?;
+        tree.add(dir, metadata, 0)?;
+
+        Ok(tree)
+    }
+
+    fn add(&mut self, path: &Path, metadata: Metadata, parent: usize) -> Result<usize> {
+        if !metadata.is_dir() {
+            if let Some(&index) = self.hard_links.get(&(metadata.dev(), metadata.ino())) {
+                self.nodes[index].links += 1;
+                return Ok(index);
+            }
+        }
+
+        let file_type = metadata.file_type();
+        let kind = if metadata.is_dir() {
+            Kind::Directory(Vec::new())
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(path).map_err(|e| Error::ReadSource(path.into(), e))?;
+            Kind::Symlink(target.as_os_str().as_bytes().to_vec())
+        } else if metadata.is_file() {
+            Kind::File(path.to_path_buf())
+        } else if file_type.is_char_device() || file_type.is_block_device() {
+            Kind::Device
+        } else {
+            Kind::Other
+        };
+
+        let index = self.nodes.len();
+        let ino = if index == 0 {
+            ROOT_INO
+        } else {
+            let ino = self.next_ino;
+            self.next_ino = ino.checked_add(1).ok_or(Error::TreeTooLarge)?;
+            ino
+        };
+
+        if !metadata.is_dir() {
+            self.hard_links
+                .insert((metadata.dev(), metadata.ino()), index);
+        }
+        let is_dir = metadata.is_dir();
+        self.nodes.push(Node {
+            kind,
+            metadata,
+            parent,
+            ino,
+            // A directory is referenced by its parent and by its own "."
+            // entry, the root directory being its own parent.
+            links: if is_dir { 2 } else { 1 },
+        });
+
+        if is_dir {
+            let mut entries = fs::read_dir(path)
+                .map_err(|e| Error::ReadSource(path.into(), e))?
+                .map(|entry| entry.map(|e| e.file_name()))
+                .collect::<io::Result<Vec<_>>>()
+                .map_err(|e| Error::ReadSource(path.into(), e))?;
+            entries.sort();
+
+            let mut children = Vec::with_capacity(entries.len());
+            for name in entries {
+                if name.len() > MAX_NAME_LEN {
+                    return Err(Error::NameTooLong(name));
+                }
+                let child_path = path.join(&name);
+                let child_metadata = fs::symlink_metadata(&child_path)
+                    .map_err(|e| Error::ReadSource(child_path.clone(), e))?;
+                if child_metadata.is_dir() {
+                    // Referenced by the ".." entry of the subdirectory
+                    self.nodes[index].links += 1;
+                }
+                let child = self.add(&child_path, child_metadata, index)?;
+                children.push((name, child));
+            }
+            self.nodes[index].kind = Kind::Directory(children);
+        }
+
+        Ok(index)
+    }
+
+    fn inodes_count(&self) -> u64 {
+        u64::from(self.next_ino - 1)
+    }
+
+    fn directory_data(&self, index: usize) -> Vec<u8> {
+        let node = &self.nodes[index];
+        let mut entries = vec![
+            (node.ino, FT_DIR, &b"."[..]),
+            (self.nodes[node.parent].ino, FT_DIR, &b".."[..]),
+        ];
+        if let Kind::Directory(children) = &node.kind {
+            for (name, child) in children {
+                let child = &self.nodes[*child];
+                entries.push((child.ino, child.file_type(), name.as_bytes()));
+            }
+        }
+        directory_blocks(&entries)
+    }
+
+    // Number of data blocks, including the indirect ones, needed by each node
+    fn data_blocks(&self) -> Result<Vec<u64>> {
+        let mut blocks = vec![0; self.nodes.len()];
+        for (index, node) in self.nodes.iter().enumerate() {
+            let len = match &node.kind {
+                Kind::Directory(_) => self.directory_data(index).len() as u64,
+                Kind::File(_) => node.metadata.len(),
+                Kind::Symlink(target) if target.len() >= FAST_SYMLINK_MAX_LEN => {
+                    target.len() as u64
+                }
+                _ => 0,
+            };
+            blocks[index] = mapped_blocks(div_ceil(len, BLOCK_SIZE as u64)).ok_or_else(|| {
+                match &node.kind {
+                    Kind::File(path) => Error::FileTooLarge(path.clone()),
+                    _ => Error::TreeTooLarge,
+                }
+            })?;
+        }
+
+        Ok(blocks)
+    }
+}
+
+fn div_ceil(value: u64, divisor: u64) -> u64 {
+    (value + divisor - 1) / divisor
+}
+
+// Total number of blocks, including the indirect ones, needed to map the
+// given number of data blocks, or None if they can't be mapped.
+fn mapped_blocks(data_blocks: u64) -> Option<u64> {
+    let mut total = data_blocks;
+    let mut remaining = data_blocks.saturating_sub(DIRECT_BLOCKS as u64);
+    for level in 1..=3 {
+        if remaining == 0 {
+            return Some(total);
+        }
+        let covered = cmp::min(remaining, ADDR_PER_BLOCK.pow(level));
+        // A tree of indirect blocks needs one block at each level for every
+        // ADDR_PER_BLOCK blocks of the level below.
+        total += (1..=level)
+            .map(|l| div_ceil(covered, ADDR_PER_BLOCK.pow(l)))
+            .sum::<u64>();
+        remaining -= covered;
+    }
+
+    (remaining == 0).then_some(total)
+}
+
+// Directory entries are packed in blocks they can't span, the last entry of
+// each block covering the rest of it.
+fn directory_blocks(entries: &[(u32, u8, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut block_start = 0;
+    let mut last_entry = 0;
+    for (ino, file_type, name) in entries {
+        let rec_len = (8 + name.len() + 3) & !3;
+        if data.len() + rec_len > block_start + BLOCK_SIZE {
+            close_directory_block(&mut data, block_start, last_entry);
+            block_start += BLOCK_SIZE;
+        }
+        last_entry = data.len();
+        let mut entry = vec![0u8; rec_len];
+        LittleEndian::write_u32(&mut entry[0..4], *ino);
+        LittleEndian::write_u16(&mut entry[4..6], rec_len as u16);
+        entry[6] = name.len() as u8;
+        entry[7] = *file_type;
+        entry[8..8 + name.len()].copy_from_slice(name);
+        data.extend_from_slice(&entry);
+    }
+    close_directory_block(&mut data, block_start, last_entry);
+
+    data
+}
+
+fn close_directory_block(data: &mut Vec<u8>, block_start: usize, last_entry: usize) {
+    let block_end = block_start + BLOCK_SIZE;
+    LittleEndian::write_u16(
+        &mut data[last_entry + 4..last_entry + 6],
+        (block_end - last_entry) as u16,
+    );
+    data.resize(block_end, 0);
+}
+
+// Groups holding a copy of the superblock and of the group descriptors, as
+// defined by the sparse_super feature.
+fn has_superblock(group: u64) -> bool {
+    fn is_power_of(mut n: u64, base: u64) -> bool {
+        while n > 1 && n % base == 0 {
+            n /= base;
+        }
+        n == 1
+    }
+
+    group <= 1 || is_power_of(group, 3) || is_power_of(group, 5) || is_power_of(group, 7)
+}
+
+struct Layout {
+    groups: u64,
+    blocks: u64,
+    inodes_per_group: u64,
+    inode_table_blocks: u64,
+    gdt_blocks: u64,
+}
+
+impl Layout {
+    fn new(inodes: u64, data_blocks: u64) -> Result<Self> {
+        for groups in 1.. {
+            if (groups - 1) * BLOCKS_PER_GROUP > u64::from(u32::MAX) {
+                break;
+            }
+
+            let inodes_per_group =
+                div_ceil(div_ceil(inodes, groups), INODES_PER_BLOCK) * INODES_PER_BLOCK;
+            // The inode bitmap must fit in a block as well.
+            if inodes_per_group > BLOCKS_PER_GROUP {
+                continue;
+            }
+
+            let layout = Layout {
+                groups,
+                blocks: 0,
+                inodes_per_group,
+                inode_table_blocks: inodes_per_group / INODES_PER_BLOCK,
+                gdt_blocks: div_ceil(groups * GROUP_DESC_SIZE as u64, BLOCK_SIZE as u64),
+            };
+            let total = (0..groups).map(|g| layout.overhead(g)).sum::<u64>() + data_blocks;
+            if total > groups * BLOCKS_PER_GROUP {
+                continue;
+            }
+
+            // The last group must at least hold its own metadata.
+            let blocks = cmp::max(
+                total,
+                (groups - 1) * BLOCKS_PER_GROUP + layout.overhead(groups - 1),
+            );
+            if blocks > u64::from(u32::MAX) {
+                break;
+            }
+
+            return Ok(Layout { blocks, ..layout });
+        }
+
+        Err(Error::TreeTooLarge)
+    }
+
+    fn overhead(&self, group: u64) -> u64 {
+        self.superblock_blocks(group) + 2 + self.inode_table_blocks
+    }
+
+    fn superblock_blocks(&self, group: u64) -> u64 {
+        if has_superblock(group) {
+            1 + self.gdt_blocks
+        } else {
+            0
+        }
+    }
+
+    fn group_start(&self, group: u64) -> u64 {
+        group * BLOCKS_PER_GROUP
+    }
+
+    fn group_blocks(&self, group: u64) -> u64 {
+        cmp::min(BLOCKS_PER_GROUP, self.blocks - self.group_start(group))
+    }
+
+    fn block_bitmap(&self, group: u64) -> u64 {
+        self.group_start(group) + self.superblock_blocks(group)
+    }
+
+    fn inode_bitmap(&self, group: u64) -> u64 {
+        self.block_bitmap(group) + 1
+    }
+
+    fn inode_table(&self, group: u64) -> u64 {
+        self.block_bitmap(group) + 2
+    }
+}
+
+// Hands out the data blocks in order, skipping the metadata of each group.
+struct BlockAllocator<'a> {
+    layout: &'a Layout,
+    group: u64,
+    next: u64,
+    used: Vec<u64>,
+}
+
+impl<'a> BlockAllocator<'a> {
+    fn new(layout: &'a Layout) -> Self {
+        BlockAllocator {
+            layout,
+            group: 0,
+            next: layout.overhead(0),
+            used: (0..layout.groups).map(|g| layout.overhead(g)).collect(),
+        }
+    }
+
+    fn allocate(&mut self) -> u32 {
+        while self.next
+            >= self.layout.group_start(self.group) + self.layout.group_blocks(self.group)
+        {
+            self.group += 1;
+            self.next = self.layout.group_start(self.group) + self.layout.overhead(self.group);
+        }
+        let block = self.next;
+        self.next += 1;
+        self.used[self.group as usize] += 1;
+        block as u32
+    }
+}
+
+#[derive(Default)]
+struct BlockMap {
+    i_block: [u32; 15],
+    data: Vec<u32>,
+    indirect: Vec<(u32, Vec<u32>)>,
+}
+
+impl BlockMap {
+    fn new(data_blocks: u64, allocator: &mut BlockAllocator) -> Self {
+        let mut map = BlockMap::default();
+        let mut remaining = data_blocks;
+        for i in 0..DIRECT_BLOCKS {
+            if remaining == 0 {
+                break;
+            }
+            map.i_block[i] = allocator.allocate();
+            map.data.push(map.i_block[i]);
+            remaining -= 1;
+        }
+        for level in 1..=3 {
+            if remaining == 0 {
+                break;
+            }
+            map.i_block[DIRECT_BLOCKS + level - 1] =
+                map.map_indirect(level, &mut remaining, allocator);
+        }
+        map
+    }
+
+    fn map_indirect(
+        &mut self,
+        level: usize,
+        remaining: &mut u64,
+        allocator: &mut BlockAllocator,
+    ) -> u32 {
+        let block = allocator.allocate();
+        let mut entries = Vec::new();
+        while *remaining > 0 && (entries.len() as u64) < ADDR_PER_BLOCK {
+            let entry = if level == 1 {
+                *remaining -= 1;
+                let data = allocator.allocate();
+                self.data.push(data);
+                data
+            } else {
+                self.map_indirect(level - 1, remaining, allocator)
+            };
+            entries.push(entry);
+        }
+        self.indirect.push((block, entries));
+        block
+    }
+
+    fn blocks(&self) -> u64 {
+        (self.data.len() + self.indirect.len()) as u64
+    }
+}
+
+struct Writer<'a> {
+    image: &'a File,
+}
+
+impl<'a> Writer<'a> {
+    fn write(&self, block: u64, offset: usize, data: &[u8]) -> Result<()> {
+        self.image
+            .write_all_at(data, block * BLOCK_SIZE as u64 + offset as u64)
+            .map_err(Error::WriteImage)
+    }
+
+    fn write_data(&self, blocks: &[u32], data: &[u8]) -> Result<()> {
+        for (block, chunk) in blocks.iter().zip(data.chunks(BLOCK_SIZE)) {
+            self.write(u64::from(*block), 0, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn copy_file(&self, blocks: &[u32], path: &Path) -> Result<()> {
+        let mut file = File::open(path).map_err(|e| Error::ReadSource(path.into(), e))?;
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        for block in blocks {
+            let mut len = 0;
+            while len < BLOCK_SIZE {
+                match file.read(&mut buf[len..]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(Error::ReadSource(path.into(), e)),
+                }
+            }
+            self.write(u64::from(*block), 0, &buf[..len])?;
+        }
+        Ok(())
+    }
+}
+
+fn set_bits(bitmap: &mut [u8], range: std::ops::Range<u64>) {
+    for bit in range {
+        bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+    }
+}
+
+fn timestamp(seconds: i64) -> u32 {
+    seconds.clamp(0, i64::from(u32::MAX)) as u32
+}
+
+fn encode_inode(node: &Node, size: u64, map: &BlockMap) -> [u8; INODE_SIZE] {
+    let metadata = &node.metadata;
+    let mut inode = [0u8; INODE_SIZE];
+    LittleEndian::write_u16(&mut inode[0..2], metadata.mode() as u16);
+    LittleEndian::write_u16(&mut inode[2..4], metadata.uid() as u16);
+    LittleEndian::write_u32(&mut inode[4..8], size as u32);
+    LittleEndian::write_u32(&mut inode[8..12], timestamp(metadata.atime()));
+    LittleEndian::write_u32(&mut inode[12..16], timestamp(metadata.ctime()));
+    LittleEndian::write_u32(&mut inode[16..20], timestamp(metadata.mtime()));
+    LittleEndian::write_u16(&mut inode[24..26], metadata.gid() as u16);
+    LittleEndian::write_u16(&mut inode[26..28], node.links);
+    LittleEndian::write_u32(
+        &mut inode[28..32],
+        (map.blocks() * (BLOCK_SIZE as u64 / 512)) as u32,
+    );
+
+    match &node.kind {
+        Kind::Symlink(target) if target.len() < FAST_SYMLINK_MAX_LEN => {
+            inode[40..40 + target.len()].copy_from_slice(target);
+        }
+        Kind::Device => {
+            let rdev = metadata.rdev();
+            let (major, minor) = (libc::major(rdev), libc::minor(rdev));
+            if major < 256 && minor < 256 {
+                LittleEndian::write_u32(&mut inode[40..44], (major << 8) | minor);
+            } else {
+                LittleEndian::write_u32(
+                    &mut inode[44..48],
+                    (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12),
+                );
+            }
+        }
+        _ => {
+            for (i, block) in map.i_block.iter().enumerate() {
+                LittleEndian::write_u32(&mut inode[40 + i * 4..44 + i * 4], *block);
+            }
+        }
+    }
+
+    // The upper 32 bits of the size of a regular file
+    if let Kind::File(_) = node.kind {
+        LittleEndian::write_u32(&mut inode[108..112], (size >> 32) as u32);
+    }
+    LittleEndian::write_u16(&mut inode[120..122], (metadata.uid() >> 16) as u16);
+    LittleEndian::write_u16(&mut inode[122..124], (metadata.gid() >> 16) as u16);
+
+    inode
+}
+
+/// Writes an ext2 filesystem holding a copy of the directory tree `dir` to
+/// `image`, which is resized to fit. Ownership, permissions, timestamps, hard
+/// links, symlinks and device nodes are preserved.
+pub fn create_image(dir: &Path, image: &File) -> Result<()> {
+    let tree = Tree::new(dir)?;
+    let data_blocks = tree.data_blocks()?;
+    let layout = Layout::new(tree.inodes_count(), data_blocks.iter().sum())?;
+    image
+        .set_len(layout.blocks * BLOCK_SIZE as u64)
+        .map_err(Error::WriteImage)?;
+    let writer = Writer { image };
+
+    let mut allocator = BlockAllocator::new(&layout);
+    let mut inode_tables =
+        vec![vec![0u8; layout.inode_table_blocks as usize * BLOCK_SIZE]; layout.groups as usize];
+    let mut used_dirs = vec![0u64; layout.groups as usize];
+
+    for (index, node) in tree.nodes.iter().enumerate() {
+        let directory_data = match node.kind {
+            Kind::Directory(_) => Some(tree.directory_data(index)),
+            _ => None,
+        };
+        let (size, len) = match &node.kind {
+            Kind::Directory(_) => {
+                let len = directory_data.as_ref().unwrap().len() as u64;
+                (len, len)
+            }
+            Kind::File(_) => (node.metadata.len(), node.metadata.len()),
+            Kind::Symlink(target) if target.len() >= FAST_SYMLINK_MAX_LEN => {
+                (target.len() as u64, target.len() as u64)
+            }
+            Kind::Symlink(target) => (target.len() as u64, 0),
+            _ => (0, 0),
+        };
+
+        let map = BlockMap::new(div_ceil(len, BLOCK_SIZE as u64), &mut allocator);
+        debug_assert_eq!(map.blocks(), data_blocks[index]);
+
+        match &node.kind {
+            Kind::Directory(_) => writer.write_data(&map.data, &directory_data.unwrap())?,
+            Kind::File(path) => writer.copy_file(&map.data, path)?,
+            Kind::Symlink(target) if !map.data.is_empty() => {
+                writer.write_data(&map.data, target)?
+            }
+            _ => {}
+        }
+        for (block, entries) in &map.indirect {
+            let mut data = vec![0u8; entries.len() * 4];
+            LittleEndian::write_u32_into(entries, &mut data);
+            writer.write(u64::from(*block), 0, &data)?;
+        }
+
+        let inode_index = u64::from(node.ino - 1);
+        let group = (inode_index / layout.inodes_per_group) as usize;
+        let offset = (inode_index % layout.inodes_per_group) as usize * INODE_SIZE;
+        inode_tables[group][offset..offset + INODE_SIZE]
+            .copy_from_slice(&encode_inode(node, size, &map));
+        if let Kind::Directory(_) = node.kind {
+            used_dirs[group] += 1;
+        }
+    }
+
+    let inodes_count = layout.groups * layout.inodes_per_group;
+    let used_inodes = tree.inodes_count();
+    let mut group_descs = vec![0u8; layout.gdt_blocks as usize * BLOCK_SIZE];
+    let mut free_blocks = 0;
+    for group in 0..layout.groups {
+        let group_blocks = layout.group_blocks(group);
+        let used_blocks = allocator.used[group as usize];
+        let mut block_bitmap = vec![0u8; BLOCK_SIZE];
+        // Blocks are allocated in order, and the bits beyond the end of the
+        // last group are set as well.
+        set_bits(&mut block_bitmap, 0..used_blocks);
+        set_bits(&mut block_bitmap, group_blocks..BLOCKS_PER_GROUP);
+        writer.write(layout.block_bitmap(group), 0, &block_bitmap)?;
+
+        let first_inode = group * layout.inodes_per_group;
+        let group_used_inodes = used_inodes
+            .saturating_sub(first_inode)
+            .min(layout.inodes_per_group);
+        let mut inode_bitmap = vec![0u8; BLOCK_SIZE];
+        set_bits(&mut inode_bitmap, 0..group_used_inodes);
+        set_bits(
+            &mut inode_bitmap,
+            layout.inodes_per_group..8 * BLOCK_SIZE as u64,
+        );
+        writer.write(layout.inode_bitmap(group), 0, &inode_bitmap)?;
+        writer.write(layout.inode_table(group), 0, &inode_tables[group as usize])?;
+
+        let group_free_blocks = group_blocks - used_blocks;
+        free_blocks += group_free_blocks;
+        let desc = &mut group_descs[group as usize * GROUP_DESC_SIZE..];
+        LittleEndian::write_u32(&mut desc[0..4], layout.block_bitmap(group) as u32);
+        LittleEndian::write_u32(&mut desc[4..8], layout.inode_bitmap(group) as u32);
+        LittleEndian::write_u32(&mut desc[8..12], layout.inode_table(group) as u32);
+        LittleEndian::write_u16(&mut desc[12..14], group_free_blocks as u16);
+        LittleEndian::write_u16(
+            &mut desc[14..16],
+            (layout.inodes_per_group - group_used_inodes) as u16,
+        );
+        LittleEndian::write_u16(&mut desc[16..18], used_dirs[group as usize] as u16);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or_default();
+    let mut superblock = [0u8; 1024];
+    LittleEndian::write_u32(&mut superblock[0..4], inodes_count as u32);
+    LittleEndian::write_u32(&mut superblock[4..8], layout.blocks as u32);
+    LittleEndian::write_u32(&mut superblock[12..16], free_blocks as u32);
+    LittleEndian::write_u32(&mut superblock[16..20], (inodes_count - used_inodes) as u32);
+    LittleEndian::write_u32(&mut superblock[24..28], LOG_BLOCK_SIZE);
+    LittleEndian::write_u32(&mut superblock[28..32], LOG_BLOCK_SIZE);
+    LittleEndian::write_u32(&mut superblock[32..36], BLOCKS_PER_GROUP as u32);
+    LittleEndian::write_u32(&mut superblock[36..40], BLOCKS_PER_GROUP as u32);
+    LittleEndian::write_u32(&mut superblock[40..44], layout.inodes_per_group as u32);
+    LittleEndian::write_u32(&mut superblock[48..52], now);
+    LittleEndian::write_i16(&mut superblock[54..56], -1);
+    LittleEndian::write_u16(&mut superblock[56..58], EXT2_MAGIC);
+    LittleEndian::write_u16(&mut superblock[58..60], EXT2_VALID_FS);
+    LittleEndian::write_u16(&mut superblock[60..62], EXT2_ERRORS_CONTINUE);
+    LittleEndian::write_u32(&mut superblock[64..68], now);
+    LittleEndian::write_u32(&mut superblock[76..80], EXT2_DYNAMIC_REV);
+    LittleEndian::write_u32(&mut superblock[84..88], FIRST_INO);
+    LittleEndian::write_u16(&mut superblock[88..90], INODE_SIZE as u16);
+    LittleEndian::write_u32(&mut superblock[96..100], FEATURE_INCOMPAT_FILETYPE);
+    LittleEndian::write_u32(
+        &mut superblock[100..104],
+        FEATURE_RO_COMPAT_SPARSE_SUPER | FEATURE_RO_COMPAT_LARGE_FILE,
+    );
+    superblock[104..120].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+
+    for group in (0..layout.groups).filter(|g| has_superblock(*g)) {
+        LittleEndian::write_u16(&mut superblock[90..92], group as u16);
+        let start = layout.group_start(group);
+        // The superblock always starts 1024 bytes into the filesystem, which
+        // for the first group is within its first block.
+        let offset = if group == 0 { SUPERBLOCK_OFFSET } else { 0 };
+        writer.write(start, offset, &superblock)?;
+        writer.write(start + 1, 0, &group_descs)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use vmm_sys_util::tempdir::TempDir;
+    use vmm_sys_util::tempfile::TempFile;
+
+    struct Image {
+        file: File,
+        inodes_per_group: u64,
+    }
+
+    impl Image {
+        fn read(&self, offset: u64, len: usize) -> Vec<u8> {
+            let mut data = vec![0u8; len];
+            self.file.read_exact_at(&mut data, offset).unwrap();
+            data
+        }
+
+        fn inode(&self, ino: u32) -> Vec<u8> {
+            let index = u64::from(ino - 1);
+            let group = index / self.inodes_per_group;
+            let desc = self.read(
+                BLOCK_SIZE as u64 + group * GROUP_DESC_SIZE as u64,
+                GROUP_DESC_SIZE,
+            );
+            let table = u64::from(LittleEndian::read_u32(&desc[8..12]));
+            self.read(
+                table * BLOCK_SIZE as u64 + (index % self.inodes_per_group) * INODE_SIZE as u64,
+                INODE_SIZE,
+            )
+        }
+
+        fn block(&self, inode: &[u8], index: usize) -> u64 {
+            let i_block = |i: usize| u64::from(LittleEndian::read_u32(&inode[40 + i * 4..]));
+            if index < DIRECT_BLOCKS {
+                i_block(index)
+            } else {
+                let indirect = i_block(DIRECT_BLOCKS);
+                let entry = self.read(
+                    indirect * BLOCK_SIZE as u64 + (index - DIRECT_BLOCKS) as u64 * 4,
+                    4,
+                );
+                u64::from(LittleEndian::read_u32(&entry))
+            }
+        }
+
+        fn entries(&self, ino: u32) -> Vec<(String, u32, u8)> {
+            let inode = self.inode(ino);
+            let data = self.read(self.block(&inode, 0) * BLOCK_SIZE as u64, BLOCK_SIZE);
+            let mut entries = Vec::new();
+            let mut offset = 0;
+            while offset < BLOCK_SIZE {
+                let rec_len = LittleEndian::read_u16(&data[offset + 4..]) as usize;
+                let name_len = data[offset + 6] as usize;
+                entries.push((
+                    String::from_utf8(data[offset + 8..offset + 8 + name_len].to_vec()).unwrap(),
+                    LittleEndian::read_u32(&data[offset..]),
+                    data[offset + 7],
+                ));
+                offset += rec_len;
+            }
+            entries
+        }
+    }
+
+    #[test]
+    fn test_create_image() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.as_path();
+        fs::create_dir(root.join("etc")).unwrap();
+        fs::write(root.join("etc/hostname"), b"guest\n").unwrap();
+        fs::hard_link(root.join("etc/hostname"), root.join("hostname")).unwrap();
+        // Large enough to require an indirect block
+        let large: Vec<u8> = (0..20 * BLOCK_SIZE)
+            .map(|i| (i / BLOCK_SIZE) as u8)
+            .collect();
+        fs::write(root.join("large"), &large).unwrap();
+        symlink("etc/hostname", root.join("short")).unwrap();
+        symlink("a".repeat(100), root.join("long")).unwrap();
+
+        let file = TempFile::new().unwrap().into_file();
+        create_image(root, &file).unwrap();
+
+        let superblock = {
+            let mut data = vec![0u8; 1024];
+            file.read_exact_at(&mut data, SUPERBLOCK_OFFSET as u64)
+                .unwrap();
+            data
+        };
+        assert_eq!(LittleEndian::read_u16(&superblock[56..58]), EXT2_MAGIC);
+        assert_eq!(
+            u64::from(LittleEndian::read_u32(&superblock[4..8])) * BLOCK_SIZE as u64,
+            file.metadata().unwrap().len()
+        );
+        let image = Image {
+            file,
+            inodes_per_group: u64::from(LittleEndian::read_u32(&superblock[40..44])),
+        };
+
+        let root_entries = image.entries(ROOT_INO);
+        let names: Vec<&str> = root_entries.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![".", "..", "etc", "hostname", "large", "long", "short"]
+        );
+        assert_eq!(root_entries[1].1, ROOT_INO);
+        let find = |name: &str| root_entries.iter().find(|e| e.0 == name).unwrap().clone();
+
+        // The root directory is linked from its own "." and "..", and from
+        // the ".." of its subdirectory.
+        assert_eq!(LittleEndian::read_u16(&image.inode(ROOT_INO)[26..28]), 3);
+
+        let (_, etc_ino, etc_type) = find("etc");
+        assert_eq!(etc_type, FT_DIR);
+        let etc_entries = image.entries(etc_ino);
+        assert_eq!(etc_entries[1].1, ROOT_INO);
+        let (_, hostname_ino, hostname_type) = find("hostname");
+        assert_eq!(hostname_type, FT_REG_FILE);
+        assert_eq!(
+            etc_entries[2],
+            ("hostname".to_string(), hostname_ino, FT_REG_FILE)
+        );
+        let hostname = image.inode(hostname_ino);
+        assert_eq!(LittleEndian::read_u16(&hostname[26..28]), 2);
+        assert_eq!(LittleEndian::read_u32(&hostname[4..8]), 6);
+        assert_eq!(
+            image.read(image.block(&hostname, 0) * BLOCK_SIZE as u64, 6),
+            b"guest\n"
+        );
+
+        let large_inode = image.inode(find("large").1);
+        assert_eq!(
+            LittleEndian::read_u32(&large_inode[4..8]) as usize,
+            large.len()
+        );
+        // 20 data blocks and an indirect block, in 512 bytes units
+        assert_eq!(LittleEndian::read_u32(&large_inode[28..32]), 21 * 8);
+        for index in 0..20 {
+            let block = image.block(&large_inode, index);
+            assert_eq!(
+                image.read(block * BLOCK_SIZE as u64, BLOCK_SIZE),
+                &large[index * BLOCK_SIZE..(index + 1) * BLOCK_SIZE]
+            );
+        }
+
+        let (_, short_ino, short_type) = find("short");
+        assert_eq!(short_type, FT_SYMLINK);
+        let short = image.inode(short_ino);
+        assert_eq!(&short[40..52], b"etc/hostname");
+        assert_eq!(LittleEndian::read_u32(&short[28..32]), 0);
+
+        let long = image.inode(find("long").1);
+        assert_eq!(LittleEndian::read_u32(&long[4..8]), 100);
+        assert_eq!(
+            image.read(image.block(&long, 0) * BLOCK_SIZE as u64, 100),
+            "a".repeat(100).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_mapped_blocks() {
+        assert_eq!(mapped_blocks(0), Some(0));
+        assert_eq!(mapped_blocks(12), Some(12));
+        assert_eq!(mapped_blocks(13), Some(14));
+        assert_eq!(mapped_blocks(12 + 1024), Some(12 + 1024 + 1));
+        assert_eq!(mapped_blocks(12 + 1024 + 1), Some(12 + 1024 + 1 + 1 + 2));
+        assert_eq!(
+            mapped_blocks(12 + 1024 + 1024 * 1024 + 1024u64.pow(3) + 1),
+            None
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout::new(100, 10).unwrap();
+        assert_eq!(layout.groups, 1);
+        assert_eq!(layout.inodes_per_group, 128);
+        assert_eq!(layout.blocks, 1 + 1 + 2 + 4 + 10);
+
+        // Spanning two groups, the second one holding a superblock copy
+        let layout = Layout::new(100, BLOCKS_PER_GROUP).unwrap();
+        assert_eq!(layout.groups, 2);
+        assert!(layout.blocks > BLOCKS_PER_GROUP + layout.overhead(1));
+
+        assert!(has_superblock(49));
+        assert!(!has_superblock(50));
+    }
+}
@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::async_io::{AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileResult};
+use crate::http::{HttpDisk, HttpDiskError, Result as HttpResult};
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use vmm_sys_util::eventfd::EventFd;
+
+// Requests of a queue completed by the fetcher thread.
+struct Completions {
+    // None once the queue is gone, for its buffers not to be written anymore.
+    list: Mutex<Option<VecDeque<(u64, i32)>>>,
+    eventfd: EventFd,
+}
+
+// Read request handed over to the fetcher thread.
+struct FetchRequest {
+    offset: u64,
+    // Guest memory buffers, as (address, length) pairs.
+    buffers: Vec<(usize, usize)>,
+    user_data: u64,
+    completions: Arc<Completions>,
+}
+
+impl FetchRequest {
+    fn complete(self, disk: &mut HttpDisk) {
+        // The blocks are fetched before taking the lock, for the queue not
+        // to wait for the network.
+        let mut data = vec![0u8; self.buffers.iter().map(|(_, len)| len).sum::<usize>()];
+        let result = disk
+            .seek(SeekFrom::Start(self.offset))
+            .and_then(|_| disk.read(&mut data));
+
+        let mut list = self.completions.list.lock().unwrap();
+        let list = match list.as_mut() {
+            Some(list) => list,
+            None => return,
+        };
+        let result = match result {
+            Ok(count) => {
+                let mut copied = 0;
+                for (addr, len) in self.buffers {
+                    let len = cmp::min(len, count - copied);
+                    // SAFETY: the buffers were given by the queue, which
+                    // is still there as checked above, and are not accessed
+                    // by the queue until the request completes.
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(data[copied..].as_ptr(), addr as *mut u8, len)
+                    };
+                    copied += len;
+                }
+                count as i32
+            }
+            Err(e) => -e.raw_os_error().unwrap_or(libc::EIO),
+        };
+        list.push_back((self.user_data, result));
+        self.completions.eventfd.write(1).unwrap();
+    }
+}
+
+pub struct HttpDiskSync {
+    disk_size: u64,
+    requests: Sender<FetchRequest>,
+}
+
+impl HttpDiskSync {
+    pub fn new(url: &str, cache_size: u64) -> HttpResult<Self> {
+        let mut disk = HttpDisk::new(url, cache_size)?;
+        let disk_size = disk.disk_size();
+        let (requests, receiver) = channel::<FetchRequest>();
+
+        // The blocks are fetched from a dedicated thread rather than from the
+        // queues, which keep going while the server answers. It is spawned
+        // from the VMM thread and shares its seccomp filter, which allows
+        // connecting to the server.
+        thread::Builder::new()
+            .name("http-disk".to_string())
+            .spawn(move || {
+                for request in receiver.iter() {
+                    request.complete(&mut disk);
+                }
+            })
+            .map_err(HttpDiskError::SpawnThread)?;
+
+        Ok(HttpDiskSync {
+            disk_size,
+            requests,
+        })
+    }
+}
+
+impl DiskFile for HttpDiskSync {
+    fn size(&mut self) -> DiskFileResult<u64> {
+        Ok(self.disk_size)
+    }
+
+    fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
+        Ok(Box::new(HttpSync::new(self.requests.clone())) as Box<dyn AsyncIo>)
+    }
+}
+
+pub struct HttpSync {
+    requests: Sender<FetchRequest>,
+    completions: Arc<Completions>,
+}
+
+impl HttpSync {
+    fn new(requests: Sender<FetchRequest>) -> Self {
+        HttpSync {
+            requests,
+            completions: Arc::new(Completions {
+                list: Mutex::new(Some(VecDeque::new())),
+                eventfd: EventFd::new(libc::EFD_NONBLOCK)
+                    .expect("Failed creating EventFd for HttpSync"),
+            }),
+        }
+    }
+}
+
+impl Drop for HttpSync {
+    fn drop(&mut self) {
+        // Requests still being fetched are dropped once they complete.
+        *self.completions.list.lock().unwrap() = None;
+    }
+}
+
+impl AsyncIo for HttpSync {
+    fn notifier(&self) -> &EventFd {
+        &self.completions.eventfd
+    }
+
+    fn read_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        let request = FetchRequest {
+            offset: offset as u64,
+            buffers: iovecs
+                .iter()
+                .map(|iovec| (iovec.iov_base as usize, iovec.iov_len))
+                .collect(),
+            user_data,
+            completions: self.completions.clone(),
+        };
+        self.requests.send(request).map_err(|_| {
+            AsyncIoError::ReadVectored(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The thread fetching the disk blocks is gone",
+            ))
+        })
+    }
+
+    fn write_vectored(
+        &mut self,
+        _offset: libc::off_t,
+        _iovecs: &[libc::iovec],
+        _user_data: u64,
+    ) -> AsyncIoResult<()> {
+        Err(AsyncIoError::WriteVectored(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "HTTP disk images are read-only",
+        )))
+    }
+
+    fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()> {
+        if let Some(user_data) = user_data {
+            if let Some(list) = self.completions.list.lock().unwrap().as_mut() {
+                list.push_back((user_data, 0));
+            }
+            self.completions.eventfd.write(1).unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+        self.completions
+            .list
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|list| list.pop_front())
+    }
+}
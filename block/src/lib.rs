@@ -12,11 +12,16 @@
 extern crate log;
 
 pub mod async_io;
+pub mod ext2;
 pub mod fixed_vhd;
 #[cfg(feature = "io_uring")]
 /// Enabled with the `"io_uring"` feature
 pub mod fixed_vhd_async;
 pub mod fixed_vhd_sync;
+pub mod http;
+pub mod http_sync;
+pub mod overlay;
+pub mod overlay_sync;
 pub mod qcow;
 pub mod qcow_sync;
 #[cfg(feature = "io_uring")]
@@ -28,11 +33,17 @@ pub mod raw_sync;
 pub mod vhd;
 pub mod vhdx;
 pub mod vhdx_sync;
+pub mod zoned;
 
 use crate::async_io::{AsyncIo, AsyncIoError, AsyncIoResult};
 use crate::fixed_vhd::FixedVhd;
 use crate::qcow::{QcowFile, RawFile};
 use crate::vhdx::{Vhdx, VhdxError};
+use crate::zoned::{
+    VIRTIO_BLK_S_ZONE_INVALID_CMD, VIRTIO_BLK_S_ZONE_UNALIGNED_WP, VIRTIO_BLK_T_ZONE_APPEND,
+    VIRTIO_BLK_T_ZONE_CLOSE, VIRTIO_BLK_T_ZONE_FINISH, VIRTIO_BLK_T_ZONE_OPEN,
+    VIRTIO_BLK_T_ZONE_REPORT, VIRTIO_BLK_T_ZONE_RESET, VIRTIO_BLK_T_ZONE_RESET_ALL,
+};
 #[cfg(feature = "io_uring")]
 use io_uring::{opcode, IoUring, Probe};
 use libc::{ioctl, S_IFBLK, S_IFMT};
@@ -62,7 +73,7 @@ use vm_memory::{
 };
 use vm_virtio::{AccessPlatform, Translatable};
 use vmm_sys_util::eventfd::EventFd;
-use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr};
+use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr};
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 
@@ -159,6 +170,12 @@ pub enum ExecuteError {
     AsyncFlush(AsyncIoError),
     #[error("Failed allocating a temporary buffer: {0}")]
     TemporaryBufferAllocation(io::Error),
+    #[error("Invalid command for the zone")]
+    ZoneInvalidCommand,
+    #[error("Write not at the write pointer of the zone")]
+    ZoneUnalignedWritePointer,
+    #[error("Request crossing a zone boundary")]
+    ZoneBoundary,
 }
 
 impl ExecuteError {
@@ -176,6 +193,9 @@ impl ExecuteError {
             ExecuteError::AsyncWrite(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::AsyncFlush(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::TemporaryBufferAllocation(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::ZoneInvalidCommand => VIRTIO_BLK_S_ZONE_INVALID_CMD,
+            ExecuteError::ZoneUnalignedWritePointer => VIRTIO_BLK_S_ZONE_UNALIGNED_WP,
+            ExecuteError::ZoneBoundary => VIRTIO_BLK_S_IOERR,
         }
     }
 }
@@ -186,9 +206,49 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceId,
+    ZoneAppend,
+    ZoneReport,
+    ZoneOpen,
+    ZoneClose,
+    ZoneFinish,
+    ZoneReset,
+    ZoneResetAll,
     Unsupported(u32),
 }
 
+impl RequestType {
+    /// Zone management requests, which don't carry any data.
+    pub fn is_zone_management(&self) -> bool {
+        matches!(
+            self,
+            RequestType::ZoneOpen
+                | RequestType::ZoneClose
+                | RequestType::ZoneFinish
+                | RequestType::ZoneReset
+                | RequestType::ZoneResetAll
+        )
+    }
+}
+
+impl From<RequestType> for u32 {
+    fn from(request_type: RequestType) -> u32 {
+        match request_type {
+            RequestType::In => VIRTIO_BLK_T_IN,
+            RequestType::Out => VIRTIO_BLK_T_OUT,
+            RequestType::Flush => VIRTIO_BLK_T_FLUSH,
+            RequestType::GetDeviceId => VIRTIO_BLK_T_GET_ID,
+            RequestType::ZoneAppend => VIRTIO_BLK_T_ZONE_APPEND,
+            RequestType::ZoneReport => VIRTIO_BLK_T_ZONE_REPORT,
+            RequestType::ZoneOpen => VIRTIO_BLK_T_ZONE_OPEN,
+            RequestType::ZoneClose => VIRTIO_BLK_T_ZONE_CLOSE,
+            RequestType::ZoneFinish => VIRTIO_BLK_T_ZONE_FINISH,
+            RequestType::ZoneReset => VIRTIO_BLK_T_ZONE_RESET,
+            RequestType::ZoneResetAll => VIRTIO_BLK_T_ZONE_RESET_ALL,
+            RequestType::Unsupported(t) => t,
+        }
+    }
+}
+
 pub fn request_type(
     mem: &GuestMemoryMmap,
     desc_addr: GuestAddress,
@@ -199,6 +259,13 @@ pub fn request_type(
         VIRTIO_BLK_T_OUT => Ok(RequestType::Out),
         VIRTIO_BLK_T_FLUSH => Ok(RequestType::Flush),
         VIRTIO_BLK_T_GET_ID => Ok(RequestType::GetDeviceId),
+        VIRTIO_BLK_T_ZONE_APPEND => Ok(RequestType::ZoneAppend),
+        VIRTIO_BLK_T_ZONE_REPORT => Ok(RequestType::ZoneReport),
+        VIRTIO_BLK_T_ZONE_OPEN => Ok(RequestType::ZoneOpen),
+        VIRTIO_BLK_T_ZONE_CLOSE => Ok(RequestType::ZoneClose),
+        VIRTIO_BLK_T_ZONE_FINISH => Ok(RequestType::ZoneFinish),
+        VIRTIO_BLK_T_ZONE_RESET => Ok(RequestType::ZoneReset),
+        VIRTIO_BLK_T_ZONE_RESET_ALL => Ok(RequestType::ZoneResetAll),
         t => Ok(RequestType::Unsupported(t)),
     }
 }
@@ -275,21 +342,29 @@ impl Request {
 
         if !desc.has_next() {
             status_desc = desc;
-            // Only flush requests are allowed to skip the data descriptor.
-            if req.request_type != RequestType::Flush {
+            // Only flush and zone management requests are allowed to skip
+            // the data descriptor.
+            if req.request_type != RequestType::Flush && !req.request_type.is_zone_management() {
                 error!("Need a data descriptor: request = {:?}", req);
                 return Err(Error::DescriptorChainTooShort);
             }
         } else {
             req.data_descriptors.reserve_exact(1);
             while desc.has_next() {
-                if desc.is_write_only() && req.request_type == RequestType::Out {
+                if desc.is_write_only()
+                    && matches!(req.request_type, RequestType::Out | RequestType::ZoneAppend)
+                {
                     return Err(Error::UnexpectedWriteOnlyDescriptor);
                 }
                 if !desc.is_write_only() && req.request_type == RequestType::In {
                     return Err(Error::UnexpectedReadOnlyDescriptor);
                 }
-                if !desc.is_write_only() && req.request_type == RequestType::GetDeviceId {
+                if !desc.is_write_only()
+                    && matches!(
+                        req.request_type,
+                        RequestType::GetDeviceId | RequestType::ZoneReport
+                    )
+                {
                     return Err(Error::UnexpectedReadOnlyDescriptor);
                 }
 
@@ -322,6 +397,22 @@ impl Request {
             .addr()
             .translate_gva(access_platform, status_desc.len() as usize);
 
+        // Zone append requests get the sector the data was written at
+        // reported before the status.
+        if req.request_type == RequestType::ZoneAppend {
+            const APPEND_SECTOR_SIZE: u32 = std::mem::size_of::<u64>() as u32;
+            if status_desc.len() < APPEND_SECTOR_SIZE + 1 {
+                return Err(Error::DescriptorLengthTooSmall);
+            }
+            req.status_addr = desc_chain
+                .memory()
+                .checked_offset(req.status_addr, APPEND_SECTOR_SIZE as usize)
+                .ok_or(Error::CheckedOffset(
+                    req.status_addr,
+                    APPEND_SECTOR_SIZE as usize,
+                ))?;
+        }
+
         Ok(req)
     }
 
@@ -332,6 +423,14 @@ impl Request {
         mem: &GuestMemoryMmap,
         serial: &[u8],
     ) -> result::Result<u32, ExecuteError> {
+        // Flush requests usually come without any data descriptor, which
+        // means they must be handled outside of the loop below, otherwise
+        // the backing file would never be synchronized.
+        if self.request_type == RequestType::Flush {
+            disk.flush().map_err(ExecuteError::Flush)?;
+            return Ok(0);
+        }
+
         disk.seek(SeekFrom::Start(self.sector << SECTOR_SHIFT))
             .map_err(ExecuteError::Seek)?;
         let mut len = 0;
@@ -360,7 +459,7 @@ impl Request {
                         disk.flush().map_err(ExecuteError::Flush)?;
                     }
                 }
-                RequestType::Flush => disk.flush().map_err(ExecuteError::Flush)?,
+                RequestType::Flush => unreachable!("Flush requests are handled above"),
                 RequestType::GetDeviceId => {
                     if (*data_len as usize) < serial.len() {
                         return Err(ExecuteError::BadRequest(Error::InvalidOffset));
@@ -368,7 +467,7 @@ impl Request {
                     mem.write_slice(serial, *data_addr)
                         .map_err(ExecuteError::Write)?;
                 }
-                RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
+                t => return Err(ExecuteError::Unsupported(t.into())),
             };
         }
         Ok(len)
@@ -488,7 +587,9 @@ impl Request {
                     .map_err(ExecuteError::Write)?;
                 return Ok(false);
             }
-            RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
+            // Zoned requests are only supported by emulated zoned devices,
+            // which handle them before reaching this point.
+            t => return Err(ExecuteError::Unsupported(t.into())),
         }
 
         Ok(true)
@@ -810,6 +911,42 @@ ioctl_io_nr!(BLKSSZGET, 0x12, 104);
 ioctl_io_nr!(BLKPBSZGET, 0x12, 123);
 ioctl_io_nr!(BLKIOMIN, 0x12, 120);
 ioctl_io_nr!(BLKIOOPT, 0x12, 121);
+ioctl_ior_nr!(BLKGETSIZE64, 0x12, 114, u64);
+
+// Largest physical block size reported for a file, as the guest expects the
+// minimum IO size to fit in 16 bits once expressed in logical blocks.
+const MAX_PROBED_PHYSICAL_BLOCK_SIZE: u64 = 1 << 16;
+
+/// Whether the file is a host block device rather than a regular file.
+pub fn is_block_device(f: &File) -> std::io::Result<bool> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: FFI call with a valid fd and buffer
+    let ret = unsafe { libc::fstat(f.as_raw_fd(), stat.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: stat is valid at this point
+    let is_block = unsafe { (*stat.as_ptr()).st_mode & S_IFMT == S_IFBLK };
+    Ok(is_block)
+}
+
+/// Size in bytes of a disk file. The length reported for a host block device
+/// is zero, hence its capacity is queried from the kernel instead.
+pub fn query_device_size(f: &File) -> std::io::Result<u64> {
+    if !is_block_device(f)? {
+        return Ok(f.metadata()?.len());
+    }
+
+    let mut size: u64 = 0;
+    // SAFETY: FFI call with a valid fd and buffer
+    let ret = unsafe { ioctl(f.as_raw_fd(), BLKGETSIZE64() as _, &mut size) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(size)
+}
 
 enum BlockSize {
     LogicalBlock,
@@ -819,19 +956,6 @@ enum BlockSize {
 }
 
 impl DiskTopology {
-    fn is_block_device(f: &File) -> std::io::Result<bool> {
-        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
-        // SAFETY: FFI call with a valid fd and buffer
-        let ret = unsafe { libc::fstat(f.as_raw_fd(), stat.as_mut_ptr()) };
-        if ret != 0 {
-            return Err(std::io::Error::last_os_error());
-        }
-
-        // SAFETY: stat is valid at this point
-        let is_block = unsafe { (*stat.as_ptr()).st_mode & S_IFMT == S_IFBLK };
-        Ok(is_block)
-    }
-
     // libc::ioctl() takes different types on different architectures
     fn query_block_size(f: &File, block_size_type: BlockSize) -> std::io::Result<u64> {
         let mut block_size = 0;
@@ -856,7 +980,7 @@ impl DiskTopology {
     }
 
     pub fn probe(f: &File) -> std::io::Result<Self> {
-        if !Self::is_block_device(f)? {
+        if !is_block_device(f)? {
             return Ok(DiskTopology::default());
         }
 
@@ -867,4 +991,40 @@ impl DiskTopology {
             optimal_io_size: Self::query_block_size(f, BlockSize::OptimalIo)?,
         })
     }
+
+    /// Probes the sizes the IO should be aligned on to perform best on the
+    /// host. A block device reports its own topology, while a file gets the
+    /// block size of its filesystem as physical block size, and its preferred
+    /// IO size as optimal IO size when larger.
+    pub fn probe_alignment(f: &File) -> std::io::Result<Self> {
+        if is_block_device(f)? {
+            return Self::probe(f);
+        }
+
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: FFI call with a valid fd and buffer
+        let ret = unsafe { libc::fstatvfs(f.as_raw_fd(), stat.as_mut_ptr()) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: stat is valid at this point
+        let fs_block_size = unsafe { (*stat.as_ptr()).f_bsize } as u64;
+        let preferred_io_size = f.metadata()?.st_blksize();
+
+        let mut topology = DiskTopology::default();
+        if fs_block_size.is_power_of_two()
+            && fs_block_size > topology.logical_block_size
+            && fs_block_size <= MAX_PROBED_PHYSICAL_BLOCK_SIZE
+        {
+            topology.physical_block_size = fs_block_size;
+            topology.minimum_io_size = fs_block_size;
+        }
+        if preferred_io_size > topology.physical_block_size
+            && preferred_io_size % topology.physical_block_size == 0
+        {
+            topology.optimal_io_size = preferred_io_size;
+        }
+
+        Ok(topology)
+    }
 }
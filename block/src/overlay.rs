@@ -0,0 +1,429 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Copy-on-write overlay on top of a raw disk image.
+//!
+//! The base image is never written to. Data written by the guest lands in a
+//! separate overlay file, at the same offset it has on the disk past the
+//! overlay metadata, so that the overlay only allocates the host blocks that
+//! were actually written. An allocation bitmap records which clusters live in
+//! the overlay, reads of all other clusters fall through to the base image.
+//!
+//! The overlay file is laid out as follows:
+//!
+//! | Offset        | Content                                          |
+//! |---------------|--------------------------------------------------|
+//! | 0             | header: magic, version, cluster and disk sizes   |
+//! | 4096          | allocation bitmap, one bit per cluster           |
+//! | `data_offset` | disk data, rounded up to the cluster size        |
+//!
+//! Clusters are copied up from the base image as a whole on their first
+//! write, and the bitmap is updated once the data has been written. Neither
+//! is synced until the guest flushes the disk, as for any other write.
+
+use byteorder::{ByteOrder, LittleEndian};
+use remain::sorted;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use thiserror::Error;
+
+const OVERLAY_MAGIC: &[u8; 8] = b"CHOVRLAY";
+const OVERLAY_VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 4096;
+const DEFAULT_CLUSTER_SIZE: u64 = 64 * 1024;
+const MIN_CLUSTER_SIZE: u64 = 512;
+
+#[sorted]
+#[derive(Error, Debug)]
+pub enum OverlayError {
+    #[error("Failed getting the size of the base image: {0}")]
+    BaseSize(#[source] io::Error),
+    #[error("Invalid overlay cluster size {0}")]
+    InvalidClusterSize(u64),
+    #[error("Not an overlay file")]
+    InvalidMagic,
+    #[error("Overlay created for a {0} bytes disk, but the base image is {1} bytes")]
+    MismatchedSize(u64, u64),
+    #[error("Failed reading the overlay metadata: {0}")]
+    ReadMetadata(#[source] io::Error),
+    #[error("Unsupported overlay version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Failed writing the overlay metadata: {0}")]
+    WriteMetadata(#[source] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, OverlayError>;
+
+#[derive(Debug)]
+pub struct Overlay {
+    base: File,
+    overlay: File,
+    disk_size: u64,
+    cluster_size: u64,
+    data_offset: u64,
+    bitmap: Vec<u8>,
+    position: u64,
+}
+
+impl Overlay {
+    /// Stacks `overlay` on top of the raw image `base`. An empty overlay file
+    /// is initialized, otherwise it must have been created for a disk of the
+    /// same size.
+    pub fn new(mut base: File, overlay: File) -> Result<Self> {
+        let disk_size = base
+            .seek(SeekFrom::End(0))
+            .map_err(OverlayError::BaseSize)?;
+        let overlay_len = overlay
+            .metadata()
+            .map_err(OverlayError::ReadMetadata)?
+            .len();
+
+        let cluster_size = if overlay_len == 0 {
+            let mut header = [0u8; 24];
+            header[0..8].copy_from_slice(OVERLAY_MAGIC);
+            LittleEndian::write_u32(&mut header[8..12], OVERLAY_VERSION);
+            LittleEndian::write_u32(&mut header[12..16], DEFAULT_CLUSTER_SIZE as u32);
+            LittleEndian::write_u64(&mut header[16..24], disk_size);
+            overlay
+                .write_all_at(&header, 0)
+                .map_err(OverlayError::WriteMetadata)?;
+            DEFAULT_CLUSTER_SIZE
+        } else {
+            let mut header = [0u8; 24];
+            overlay
+                .read_exact_at(&mut header, 0)
+                .map_err(OverlayError::ReadMetadata)?;
+            if &header[0..8] != OVERLAY_MAGIC {
+                return Err(OverlayError::InvalidMagic);
+            }
+            let version = LittleEndian::read_u32(&header[8..12]);
+            if version != OVERLAY_VERSION {
+                return Err(OverlayError::UnsupportedVersion(version));
+            }
+            let cluster_size = u64::from(LittleEndian::read_u32(&header[12..16]));
+            if cluster_size < MIN_CLUSTER_SIZE || !cluster_size.is_power_of_two() {
+                return Err(OverlayError::InvalidClusterSize(cluster_size));
+            }
+            let overlay_disk_size = LittleEndian::read_u64(&header[16..24]);
+            if overlay_disk_size != disk_size {
+                return Err(OverlayError::MismatchedSize(overlay_disk_size, disk_size));
+            }
+            cluster_size
+        };
+
+        let clusters = (disk_size + cluster_size - 1) / cluster_size;
+        let bitmap_size = (clusters + 7) / 8;
+        let data_offset =
+            (HEADER_SIZE + bitmap_size + cluster_size - 1) / cluster_size * cluster_size;
+
+        let mut bitmap = vec![0u8; bitmap_size as usize];
+        if overlay_len == 0 {
+            // Leave the bitmap and the data as a hole in the file.
+            overlay
+                .set_len(data_offset)
+                .map_err(OverlayError::WriteMetadata)?;
+        } else {
+            overlay
+                .read_exact_at(&mut bitmap, HEADER_SIZE)
+                .map_err(OverlayError::ReadMetadata)?;
+        }
+
+        Ok(Overlay {
+            base,
+            overlay,
+            disk_size,
+            cluster_size,
+            data_offset,
+            bitmap,
+            position: 0,
+        })
+    }
+
+    pub fn disk_size(&self) -> u64 {
+        self.disk_size
+    }
+
+    fn is_allocated(&self, cluster: u64) -> bool {
+        self.bitmap[(cluster / 8) as usize] & (1 << (cluster % 8)) != 0
+    }
+
+    fn set_allocated(&mut self, cluster: u64) -> io::Result<()> {
+        let index = (cluster / 8) as usize;
+        self.bitmap[index] |= 1 << (cluster % 8);
+        self.overlay
+            .write_all_at(&self.bitmap[index..index + 1], HEADER_SIZE + index as u64)
+    }
+
+    // Length of the access at `offset` which stays within its cluster.
+    fn chunk_len(&self, offset: u64, len: usize) -> usize {
+        cmp::min(len as u64, self.cluster_size - offset % self.cluster_size) as usize
+    }
+
+    // Length of the access at the current position which stays within the
+    // disk.
+    fn access_len(&self, len: usize) -> usize {
+        cmp::min(len as u64, self.disk_size.saturating_sub(self.position)) as usize
+    }
+
+    // Writes `data` at `offset` to a cluster which isn't in the overlay yet,
+    // completing it with the content of the base image. The data is synced
+    // before the cluster is marked as allocated, so that a crash can't leave
+    // the bitmap pointing to a cluster which was never written.
+    fn copy_up(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let cluster = offset / self.cluster_size;
+        let start = cluster * self.cluster_size;
+        let end = cmp::min(start + self.cluster_size, self.disk_size);
+
+        let mut buf = vec![0u8; (end - start) as usize];
+        if data.len() < buf.len() {
+            self.base.read_exact_at(&mut buf, start)?;
+        }
+        let data_start = (offset - start) as usize;
+        buf[data_start..data_start + data.len()].copy_from_slice(data);
+
+        self.overlay.write_all_at(&buf, self.data_offset + start)?;
+        self.overlay.sync_data()?;
+        self.set_allocated(cluster)
+    }
+}
+
+impl Read for Overlay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.access_len(buf.len());
+        let mut done = 0;
+        while done < len {
+            let offset = self.position + done as u64;
+            let count = self.chunk_len(offset, len - done);
+            let chunk = &mut buf[done..done + count];
+            if self.is_allocated(offset / self.cluster_size) {
+                self.overlay
+                    .read_exact_at(chunk, self.data_offset + offset)?;
+            } else {
+                self.base.read_exact_at(chunk, offset)?;
+            }
+            done += count;
+        }
+
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    // Fill all the buffers of a request rather than only the first one.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs {
+            let count = self.read(buf)?;
+            total += count;
+            if count < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+}
+
+impl Write for Overlay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.access_len(buf.len());
+        let mut done = 0;
+        while done < len {
+            let offset = self.position + done as u64;
+            let count = self.chunk_len(offset, len - done);
+            let chunk = &buf[done..done + count];
+            if self.is_allocated(offset / self.cluster_size) {
+                self.overlay
+                    .write_all_at(chunk, self.data_offset + offset)?;
+            } else {
+                self.copy_up(offset, chunk)?;
+            }
+            done += count;
+        }
+
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs {
+            let count = self.write(buf)?;
+            total += count;
+            if count < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.overlay.sync_all()
+    }
+}
+
+impl Seek for Overlay {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::End(offset) => (self.disk_size, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seeking to a negative or overflowing offset",
+            )
+        })?;
+
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempfile::TempFile;
+
+    const DISK_SIZE: u64 = 4 * DEFAULT_CLUSTER_SIZE + 512;
+
+    fn base_image() -> TempFile {
+        let base = TempFile::new().unwrap();
+        let content: Vec<u8> = (0..DISK_SIZE).map(|i| (i % 251) as u8).collect();
+        base.as_file().write_all_at(&content, 0).unwrap();
+        base
+    }
+
+    fn open(base: &TempFile, overlay: &TempFile) -> Overlay {
+        Overlay::new(
+            base.as_file().try_clone().unwrap(),
+            overlay.as_file().try_clone().unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn read_at(overlay: &mut Overlay, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        overlay.seek(SeekFrom::Start(offset)).unwrap();
+        overlay.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    fn write_at(overlay: &mut Overlay, offset: u64, data: &[u8]) {
+        overlay.seek(SeekFrom::Start(offset)).unwrap();
+        overlay.write_all(data).unwrap();
+    }
+
+    #[test]
+    fn test_overlay_reads_base() {
+        let base = base_image();
+        let overlay_file = TempFile::new().unwrap();
+        let mut overlay = open(&base, &overlay_file);
+
+        assert_eq!(overlay.disk_size(), DISK_SIZE);
+        assert_eq!(overlay.seek(SeekFrom::End(0)).unwrap(), DISK_SIZE);
+        let data = read_at(&mut overlay, 1000, 200);
+        assert!(data
+            .iter()
+            .enumerate()
+            .all(|(i, b)| *b == ((1000 + i as u64) % 251) as u8));
+
+        // Reads stop at the end of the disk.
+        let mut buf = [0u8; 1024];
+        overlay.seek(SeekFrom::Start(DISK_SIZE - 512)).unwrap();
+        assert_eq!(overlay.read(&mut buf).unwrap(), 512);
+        assert_eq!(overlay.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_overlay_copy_on_write() {
+        let base = base_image();
+        let overlay_file = TempFile::new().unwrap();
+        let mut overlay = open(&base, &overlay_file);
+
+        // A write spanning two clusters, and one to the last partial cluster.
+        let offset = DEFAULT_CLUSTER_SIZE - 100;
+        write_at(&mut overlay, offset, &[0xaa; 200]);
+        write_at(&mut overlay, DISK_SIZE - 10, &[0xbb; 10]);
+
+        assert_eq!(read_at(&mut overlay, offset, 200), vec![0xaa; 200]);
+        assert_eq!(read_at(&mut overlay, DISK_SIZE - 10, 10), vec![0xbb; 10]);
+        // The rest of the copied up clusters still has the base content.
+        assert_eq!(
+            read_at(&mut overlay, offset - 1, 1),
+            vec![((offset - 1) % 251) as u8]
+        );
+        assert_eq!(
+            read_at(&mut overlay, offset + 200, 1),
+            vec![((offset + 200) % 251) as u8]
+        );
+        assert!(overlay.is_allocated(0));
+        assert!(overlay.is_allocated(1));
+        assert!(!overlay.is_allocated(2));
+        assert!(overlay.is_allocated(4));
+
+        // Vectored accesses cover all the buffers.
+        overlay.seek(SeekFrom::Start(0)).unwrap();
+        let written = overlay
+            .write_vectored(&[IoSlice::new(&[1; 10]), IoSlice::new(&[2; 10])])
+            .unwrap();
+        assert_eq!(written, 20);
+        let (mut first, mut second) = ([0u8; 10], [0u8; 10]);
+        overlay.seek(SeekFrom::Start(0)).unwrap();
+        let read = overlay
+            .read_vectored(&mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)])
+            .unwrap();
+        assert_eq!(read, 20);
+        assert_eq!((first, second), ([1; 10], [2; 10]));
+
+        // The base image is left untouched.
+        let mut buf = [0u8; 200];
+        base.as_file().read_exact_at(&mut buf, offset).unwrap();
+        assert!(buf
+            .iter()
+            .enumerate()
+            .all(|(i, b)| *b == ((offset + i as u64) % 251) as u8));
+    }
+
+    #[test]
+    fn test_overlay_reopen() {
+        let base = base_image();
+        let overlay_file = TempFile::new().unwrap();
+        let mut overlay = open(&base, &overlay_file);
+        write_at(&mut overlay, 3 * DEFAULT_CLUSTER_SIZE, &[0xcc; 512]);
+        overlay.flush().unwrap();
+        drop(overlay);
+
+        let mut overlay = open(&base, &overlay_file);
+        assert_eq!(
+            read_at(&mut overlay, 3 * DEFAULT_CLUSTER_SIZE, 512),
+            vec![0xcc; 512]
+        );
+
+        // Each overlay is bound to the size of its base image.
+        let other_base = TempFile::new().unwrap();
+        other_base.as_file().set_len(DISK_SIZE * 2).unwrap();
+        assert!(matches!(
+            Overlay::new(
+                other_base.as_file().try_clone().unwrap(),
+                overlay_file.as_file().try_clone().unwrap()
+            ),
+            Err(OverlayError::MismatchedSize(DISK_SIZE, _))
+        ));
+
+        // Something which isn't an overlay is rejected.
+        assert!(matches!(
+            Overlay::new(
+                overlay_file.as_file().try_clone().unwrap(),
+                base.as_file().try_clone().unwrap()
+            ),
+            Err(OverlayError::InvalidMagic)
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::async_io::{AsyncIo, AsyncIoResult, DiskFile, DiskFileResult};
+use crate::overlay::{Overlay, Result as OverlayResult};
+use crate::AsyncAdaptor;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::sync::{Arc, Mutex, MutexGuard};
+use vmm_sys_util::eventfd::EventFd;
+
+pub struct OverlayDiskSync {
+    overlay: Arc<Mutex<Overlay>>,
+}
+
+impl OverlayDiskSync {
+    pub fn new(base: File, overlay: File) -> OverlayResult<Self> {
+        Ok(OverlayDiskSync {
+            overlay: Arc::new(Mutex::new(Overlay::new(base, overlay)?)),
+        })
+    }
+}
+
+impl DiskFile for OverlayDiskSync {
+    fn size(&mut self) -> DiskFileResult<u64> {
+        Ok(self.overlay.lock().unwrap().disk_size())
+    }
+
+    fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
+        Ok(Box::new(OverlaySync::new(self.overlay.clone())) as Box<dyn AsyncIo>)
+    }
+}
+
+pub struct OverlaySync {
+    overlay: Arc<Mutex<Overlay>>,
+    eventfd: EventFd,
+    completion_list: VecDeque<(u64, i32)>,
+}
+
+impl OverlaySync {
+    pub fn new(overlay: Arc<Mutex<Overlay>>) -> Self {
+        OverlaySync {
+            overlay,
+            eventfd: EventFd::new(libc::EFD_NONBLOCK)
+                .expect("Failed creating EventFd for OverlaySync"),
+            completion_list: VecDeque::new(),
+        }
+    }
+}
+
+impl AsyncAdaptor<Overlay> for Arc<Mutex<Overlay>> {
+    fn file(&mut self) -> MutexGuard<Overlay> {
+        self.lock().unwrap()
+    }
+}
+
+impl AsyncIo for OverlaySync {
+    fn notifier(&self) -> &EventFd {
+        &self.eventfd
+    }
+
+    fn read_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.overlay.read_vectored_sync(
+            offset,
+            iovecs,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    fn write_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.overlay.write_vectored_sync(
+            offset,
+            iovecs,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()> {
+        self.overlay
+            .fsync_sync(user_data, &self.eventfd, &mut self.completion_list)
+    }
+
+    fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+        self.completion_list.pop_front()
+    }
+}
@@ -353,7 +353,7 @@ impl SeekHole for RawFile {
 
 impl BlockBackend for RawFile {
     fn size(&self) -> std::result::Result<u64, crate::Error> {
-        Ok(self.metadata().map_err(crate::Error::RawFileError)?.len())
+        crate::query_device_size(&self.file).map_err(crate::Error::RawFileError)
     }
 }
 
@@ -5,13 +5,17 @@
 use crate::async_io::{
     AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult,
 };
-use crate::DiskTopology;
+use crate::{query_device_size, DiskTopology};
 use io_uring::{opcode, squeue, types, IoUring};
 use std::fs::File;
-use std::io::{Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
 use vmm_sys_util::eventfd::EventFd;
 
+// Tags the cancellation requests, whose completions are consumed here. The
+// block device tags its requests with the descriptor head in the low 16 bits,
+// which never reaches 0xffff.
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
 pub struct RawFileDisk {
     file: File,
 }
@@ -24,9 +28,7 @@ impl RawFileDisk {
 
 impl DiskFile for RawFileDisk {
     fn size(&mut self) -> DiskFileResult<u64> {
-        self.file
-            .seek(SeekFrom::End(0))
-            .map_err(DiskFileError::Size)
+        query_device_size(&self.file).map_err(DiskFileError::Size)
     }
 
     fn new_async_io(&self, ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
@@ -150,7 +152,10 @@ impl AsyncIo for RawFileAsync {
             submitter.submit().map_err(AsyncIoError::Fsync)?;
         } else {
             // SAFETY: FFI call with a valid fd
-            unsafe { libc::fsync(self.fd) };
+            let result = unsafe { libc::fsync(self.fd) };
+            if result < 0 {
+                return Err(AsyncIoError::Fsync(std::io::Error::last_os_error()));
+            }
         }
 
         Ok(())
@@ -159,7 +164,33 @@ impl AsyncIo for RawFileAsync {
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.io_uring
             .completion()
-            .next()
+            .find(|entry| entry.user_data() != CANCEL_USER_DATA)
             .map(|entry| (entry.user_data(), entry.result()))
     }
+
+    fn cancel(&mut self, user_data: u64) -> AsyncIoResult<()> {
+        let (submitter, mut sq, _) = self.io_uring.split();
+
+        // SAFETY: the cancellation doesn't reference any buffer.
+        unsafe {
+            sq.push(
+                &opcode::AsyncCancel::new(user_data)
+                    .build()
+                    .user_data(CANCEL_USER_DATA),
+            )
+        }
+        .map_err(|_| {
+            AsyncIoError::Cancel(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Submission queue is full",
+            ))
+        })?;
+
+        // Update the submission queue and submit new operations to the
+        // io_uring instance.
+        sq.sync();
+        submitter.submit().map_err(AsyncIoError::Cancel)?;
+
+        Ok(())
+    }
 }
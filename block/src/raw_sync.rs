@@ -5,10 +5,9 @@
 use crate::async_io::{
     AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult,
 };
-use crate::DiskTopology;
+use crate::{query_device_size, DiskTopology};
 use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
 use vmm_sys_util::eventfd::EventFd;
 
@@ -24,9 +23,7 @@ impl RawFileDiskSync {
 
 impl DiskFile for RawFileDiskSync {
     fn size(&mut self) -> DiskFileResult<u64> {
-        self.file
-            .seek(SeekFrom::End(0))
-            .map_err(DiskFileError::Size)
+        query_device_size(&self.file).map_err(DiskFileError::Size)
     }
 
     fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
@@ -133,3 +130,45 @@ impl AsyncIo for RawFileSync {
         self.completion_list.pop_front()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_size() {
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(0x10_0000).unwrap();
+        let mut disk = RawFileDiskSync::new(file);
+        assert_eq!(disk.size().unwrap(), 0x10_0000);
+    }
+
+    #[test]
+    fn test_fsync_completes_after_write() {
+        let mut file = TempFile::new().unwrap().into_file();
+        file.set_len(0x1000).unwrap();
+        let disk = RawFileDiskSync::new(file.try_clone().unwrap());
+        let mut async_io = disk.new_async_io(1).unwrap();
+
+        let mut buf = vec![0xa5u8; 0x200];
+        let iovecs = [libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        }];
+        async_io.write_vectored(0x200, &iovecs, 1).unwrap();
+        async_io.fsync(Some(2)).unwrap();
+
+        // The flush request must only be reported as completed once the
+        // data has been synchronized, hence after the write request.
+        assert_eq!(async_io.next_completed_request(), Some((1, 0x200)));
+        assert_eq!(async_io.next_completed_request(), Some((2, 0)));
+        assert_eq!(async_io.next_completed_request(), None);
+
+        let mut data = vec![0u8; 0x200];
+        file.seek(SeekFrom::Start(0x200)).unwrap();
+        file.read_exact(&mut data).unwrap();
+        assert_eq!(data, buf);
+    }
+}
@@ -0,0 +1,481 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Emulation of a host-managed zoned block device on top of a regular disk
+//! image, following the zoned extension of the virtio-blk specification.
+//!
+//! The disk is split into zones of identical size, the first ones being
+//! conventional zones which can be written randomly, while the others only
+//! accept sequential writes at their write pointer. The state of the zones is
+//! kept in memory, meaning all the sequential zones are empty when the device
+//! is created.
+
+use crate::{Error, ExecuteError, GuestMemoryMmap, Request, RequestType, SECTOR_SIZE};
+use vm_memory::{ByteValued, Bytes, GuestAddress};
+
+pub const VIRTIO_BLK_F_ZONED: u32 = 17;
+
+pub const VIRTIO_BLK_T_ZONE_APPEND: u32 = 15;
+pub const VIRTIO_BLK_T_ZONE_REPORT: u32 = 16;
+pub const VIRTIO_BLK_T_ZONE_OPEN: u32 = 18;
+pub const VIRTIO_BLK_T_ZONE_CLOSE: u32 = 20;
+pub const VIRTIO_BLK_T_ZONE_FINISH: u32 = 22;
+pub const VIRTIO_BLK_T_ZONE_RESET: u32 = 24;
+pub const VIRTIO_BLK_T_ZONE_RESET_ALL: u32 = 26;
+
+pub const VIRTIO_BLK_S_ZONE_INVALID_CMD: u32 = 3;
+pub const VIRTIO_BLK_S_ZONE_UNALIGNED_WP: u32 = 4;
+
+// Host-managed zoned device model.
+const VIRTIO_BLK_Z_HM: u8 = 1;
+
+// Zone types.
+const VIRTIO_BLK_ZT_CONV: u8 = 1;
+const VIRTIO_BLK_ZT_SWR: u8 = 2;
+
+// Zone states.
+const VIRTIO_BLK_ZS_NOT_WP: u8 = 0;
+const VIRTIO_BLK_ZS_EMPTY: u8 = 1;
+const VIRTIO_BLK_ZS_IOPEN: u8 = 2;
+const VIRTIO_BLK_ZS_EOPEN: u8 = 3;
+const VIRTIO_BLK_ZS_CLOSED: u8 = 4;
+const VIRTIO_BLK_ZS_FULL: u8 = 14;
+
+// Size of the header of a zone report, followed by the zone descriptors.
+const ZONE_REPORT_HEADER_SIZE: usize = 64;
+const ZONE_DESCRIPTOR_SIZE: usize = 64;
+
+/// Part of the virtio-blk configuration space following [`VirtioBlockConfig`]
+/// for zoned devices, starting with the unused secure erase fields.
+///
+/// [`VirtioBlockConfig`]: crate::VirtioBlockConfig
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct VirtioBlockZonedConfig {
+    pub max_secure_erase_sectors: u32,
+    pub max_secure_erase_seg: u32,
+    pub secure_erase_sector_alignment: u32,
+    pub zone_sectors: u32,
+    pub max_open_zones: u32,
+    pub max_active_zones: u32,
+    pub max_append_sectors: u32,
+    pub write_granularity: u32,
+    pub model: u8,
+    pub unused2: [u8; 3],
+}
+
+// SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for VirtioBlockZonedConfig {}
+
+/// Layout of the zones of an emulated zoned device.
+#[derive(Clone, Copy, Debug)]
+pub struct ZoneLayout {
+    /// Size of each zone, in sectors.
+    pub zone_sectors: u64,
+    /// Number of conventional zones at the beginning of the device.
+    pub conventional_zones: u32,
+}
+
+impl ZoneLayout {
+    /// Capacity exposed to the guest for a disk of `disk_nsectors`, which
+    /// only covers complete zones.
+    pub fn capacity(&self, disk_nsectors: u64) -> u64 {
+        disk_nsectors - disk_nsectors % self.zone_sectors
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ZoneCondition {
+    NotWritePointer,
+    Empty,
+    ImplicitlyOpen,
+    ExplicitlyOpen,
+    Closed,
+    Full,
+}
+
+#[derive(Debug)]
+struct Zone {
+    start: u64,
+    capacity: u64,
+    write_pointer: u64,
+    condition: ZoneCondition,
+}
+
+impl Zone {
+    fn end(&self) -> u64 {
+        self.start + self.capacity
+    }
+
+    fn is_conventional(&self) -> bool {
+        self.condition == ZoneCondition::NotWritePointer
+    }
+
+    fn reset(&mut self) {
+        if !self.is_conventional() {
+            self.write_pointer = self.start;
+            self.condition = ZoneCondition::Empty;
+        }
+    }
+
+    fn advance(&mut self, sectors: u64) {
+        self.write_pointer += sectors;
+        if self.write_pointer == self.end() {
+            self.condition = ZoneCondition::Full;
+        } else if matches!(self.condition, ZoneCondition::Empty | ZoneCondition::Closed) {
+            self.condition = ZoneCondition::ImplicitlyOpen;
+        }
+    }
+
+    fn descriptor(&self) -> [u8; ZONE_DESCRIPTOR_SIZE] {
+        let (zone_type, zone_state) = match self.condition {
+            ZoneCondition::NotWritePointer => (VIRTIO_BLK_ZT_CONV, VIRTIO_BLK_ZS_NOT_WP),
+            ZoneCondition::Empty => (VIRTIO_BLK_ZT_SWR, VIRTIO_BLK_ZS_EMPTY),
+            ZoneCondition::ImplicitlyOpen => (VIRTIO_BLK_ZT_SWR, VIRTIO_BLK_ZS_IOPEN),
+            ZoneCondition::ExplicitlyOpen => (VIRTIO_BLK_ZT_SWR, VIRTIO_BLK_ZS_EOPEN),
+            ZoneCondition::Closed => (VIRTIO_BLK_ZT_SWR, VIRTIO_BLK_ZS_CLOSED),
+            ZoneCondition::Full => (VIRTIO_BLK_ZT_SWR, VIRTIO_BLK_ZS_FULL),
+        };
+        // Conventional zones have no write pointer.
+        let write_pointer = if self.is_conventional() {
+            self.end()
+        } else {
+            self.write_pointer
+        };
+
+        let mut descriptor = [0u8; ZONE_DESCRIPTOR_SIZE];
+        descriptor[0..8].copy_from_slice(&self.capacity.to_le_bytes());
+        descriptor[8..16].copy_from_slice(&self.start.to_le_bytes());
+        descriptor[16..24].copy_from_slice(&write_pointer.to_le_bytes());
+        descriptor[24] = zone_type;
+        descriptor[25] = zone_state;
+        descriptor
+    }
+}
+
+/// State of the zones of an emulated zoned device, shared by all its queues.
+pub struct ZoneTable {
+    zone_sectors: u64,
+    zones: Vec<Zone>,
+}
+
+impl ZoneTable {
+    pub fn new(layout: ZoneLayout, capacity: u64) -> Self {
+        let zones = (0..capacity / layout.zone_sectors)
+            .map(|i| {
+                let start = i * layout.zone_sectors;
+                let condition = if i < u64::from(layout.conventional_zones) {
+                    ZoneCondition::NotWritePointer
+                } else {
+                    ZoneCondition::Empty
+                };
+                Zone {
+                    start,
+                    capacity: layout.zone_sectors,
+                    write_pointer: start,
+                    condition,
+                }
+            })
+            .collect();
+
+        ZoneTable {
+            zone_sectors: layout.zone_sectors,
+            zones,
+        }
+    }
+
+    /// Zoned characteristics reported through the configuration space. The
+    /// number of open and active zones is not limited.
+    pub fn config(&self, write_granularity: u32) -> VirtioBlockZonedConfig {
+        VirtioBlockZonedConfig {
+            zone_sectors: self.zone_sectors as u32,
+            max_append_sectors: self.zone_sectors as u32,
+            write_granularity,
+            model: VIRTIO_BLK_Z_HM,
+            ..Default::default()
+        }
+    }
+
+    fn zone_index(&self, sector: u64) -> Result<usize, ExecuteError> {
+        let index = (sector / self.zone_sectors) as usize;
+        if index >= self.zones.len() {
+            return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+        }
+        Ok(index)
+    }
+
+    // Zone management and append requests must target the start of a
+    // sequential zone.
+    fn sequential_zone(&mut self, sector: u64) -> Result<&mut Zone, ExecuteError> {
+        let index = self.zone_index(sector)?;
+        let zone = &mut self.zones[index];
+        if zone.start != sector || zone.is_conventional() {
+            return Err(ExecuteError::ZoneInvalidCommand);
+        }
+        Ok(zone)
+    }
+
+    fn write(&mut self, sector: u64, sectors: u64) -> Result<(), ExecuteError> {
+        let index = self.zone_index(sector)?;
+        let zone = &mut self.zones[index];
+        if zone.is_conventional() {
+            // Conventional zones are contiguous, at the beginning of the
+            // device, hence the write must not go past the last of them.
+            let conventional_end = self
+                .zones
+                .iter()
+                .take_while(|zone| zone.is_conventional())
+                .last()
+                .map_or(0, |zone| zone.end());
+            if sector + sectors > conventional_end {
+                return Err(ExecuteError::ZoneBoundary);
+            }
+            return Ok(());
+        }
+
+        if zone.condition == ZoneCondition::Full {
+            return Err(ExecuteError::ZoneInvalidCommand);
+        }
+        if sector != zone.write_pointer {
+            return Err(ExecuteError::ZoneUnalignedWritePointer);
+        }
+        if sector + sectors > zone.end() {
+            return Err(ExecuteError::ZoneBoundary);
+        }
+        zone.advance(sectors);
+
+        Ok(())
+    }
+
+    fn append(&mut self, sector: u64, sectors: u64) -> Result<u64, ExecuteError> {
+        let zone = self.sequential_zone(sector)?;
+        if zone.condition == ZoneCondition::Full {
+            return Err(ExecuteError::ZoneInvalidCommand);
+        }
+        let write_pointer = zone.write_pointer;
+        if write_pointer + sectors > zone.end() {
+            return Err(ExecuteError::ZoneBoundary);
+        }
+        zone.advance(sectors);
+
+        Ok(write_pointer)
+    }
+
+    fn manage(&mut self, request_type: RequestType, sector: u64) -> Result<(), ExecuteError> {
+        if request_type == RequestType::ZoneResetAll {
+            self.zones.iter_mut().for_each(Zone::reset);
+            return Ok(());
+        }
+
+        let zone = self.sequential_zone(sector)?;
+        match request_type {
+            RequestType::ZoneOpen => {
+                if zone.condition != ZoneCondition::Full {
+                    zone.condition = ZoneCondition::ExplicitlyOpen;
+                }
+            }
+            RequestType::ZoneClose => {
+                if matches!(
+                    zone.condition,
+                    ZoneCondition::ImplicitlyOpen | ZoneCondition::ExplicitlyOpen
+                ) {
+                    zone.condition = if zone.write_pointer == zone.start {
+                        ZoneCondition::Empty
+                    } else {
+                        ZoneCondition::Closed
+                    };
+                }
+            }
+            RequestType::ZoneFinish => {
+                zone.write_pointer = zone.end();
+                zone.condition = ZoneCondition::Full;
+            }
+            RequestType::ZoneReset => zone.reset(),
+            _ => unreachable!("Not a zone management request"),
+        }
+
+        Ok(())
+    }
+
+    // Reports the zones starting with the one holding the requested sector,
+    // as many as the buffer provided by the guest can hold.
+    fn report(&self, request: &Request, mem: &GuestMemoryMmap) -> Result<(), ExecuteError> {
+        let buffer_len = request
+            .data_descriptors
+            .iter()
+            .map(|(_, len)| *len as usize)
+            .sum::<usize>();
+        if buffer_len < ZONE_REPORT_HEADER_SIZE {
+            return Err(ExecuteError::BadRequest(Error::DescriptorLengthTooSmall));
+        }
+
+        let first = self.zone_index(request.sector)?;
+        let count = std::cmp::min(
+            self.zones.len() - first,
+            (buffer_len - ZONE_REPORT_HEADER_SIZE) / ZONE_DESCRIPTOR_SIZE,
+        );
+
+        let mut report = vec![0u8; ZONE_REPORT_HEADER_SIZE];
+        report[0..8].copy_from_slice(&(count as u64).to_le_bytes());
+        for zone in &self.zones[first..first + count] {
+            report.extend_from_slice(&zone.descriptor());
+        }
+
+        let mut offset = 0;
+        for (data_addr, data_len) in &request.data_descriptors {
+            let len = std::cmp::min(*data_len as usize, report.len() - offset);
+            mem.write_slice(&report[offset..offset + len], *data_addr)
+                .map_err(ExecuteError::Write)?;
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the request to the zones, returning whether it still needs to
+    /// be submitted to the disk image. Zone reports and zone management
+    /// requests are completed right away, while writes are only accepted at
+    /// the write pointer of sequential zones. Zone appends are turned into
+    /// regular writes at the write pointer, which is reported to the guest.
+    pub fn execute(
+        &mut self,
+        request: &mut Request,
+        mem: &GuestMemoryMmap,
+    ) -> Result<bool, ExecuteError> {
+        let bytes = request
+            .data_descriptors
+            .iter()
+            .map(|(_, len)| u64::from(*len))
+            .sum::<u64>();
+        let sectors = (bytes + SECTOR_SIZE - 1) / SECTOR_SIZE;
+
+        match request.request_type {
+            RequestType::Out => self.write(request.sector, sectors)?,
+            RequestType::ZoneAppend => {
+                let sector = self.append(request.sector, sectors)?;
+                // The sector the data is appended at precedes the status.
+                mem.write_obj(
+                    sector.to_le(),
+                    GuestAddress(request.status_addr.0 - std::mem::size_of::<u64>() as u64),
+                )
+                .map_err(ExecuteError::Write)?;
+                request.request_type = RequestType::Out;
+                request.sector = sector;
+            }
+            RequestType::ZoneReport => {
+                self.report(request, mem)?;
+                return Ok(false);
+            }
+            t if t.is_zone_management() => {
+                self.manage(t, request.sector)?;
+                return Ok(false);
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone_table() -> ZoneTable {
+        // Four zones of 8 sectors, the first one being conventional.
+        let layout = ZoneLayout {
+            zone_sectors: 8,
+            conventional_zones: 1,
+        };
+        ZoneTable::new(layout, layout.capacity(36))
+    }
+
+    #[test]
+    fn test_zone_write() {
+        let mut zones = zone_table();
+        assert_eq!(zones.zones.len(), 4);
+
+        // Conventional zones accept random writes, within their boundaries.
+        assert!(zones.write(4, 2).is_ok());
+        assert!(zones.write(2, 2).is_ok());
+        assert!(matches!(zones.write(6, 4), Err(ExecuteError::ZoneBoundary)));
+
+        // Sequential zones only accept writes at their write pointer.
+        assert!(matches!(
+            zones.write(10, 2),
+            Err(ExecuteError::ZoneUnalignedWritePointer)
+        ));
+        assert!(zones.write(8, 2).is_ok());
+        assert_eq!(zones.zones[1].condition, ZoneCondition::ImplicitlyOpen);
+        assert!(zones.write(10, 2).is_ok());
+        assert!(matches!(
+            zones.write(12, 6),
+            Err(ExecuteError::ZoneBoundary)
+        ));
+        assert!(zones.write(12, 4).is_ok());
+        assert_eq!(zones.zones[1].condition, ZoneCondition::Full);
+        assert!(matches!(
+            zones.write(12, 1),
+            Err(ExecuteError::ZoneInvalidCommand)
+        ));
+        assert!(matches!(
+            zones.write(32, 1),
+            Err(ExecuteError::BadRequest(Error::InvalidOffset))
+        ));
+    }
+
+    #[test]
+    fn test_zone_append() {
+        let mut zones = zone_table();
+        assert_eq!(zones.append(16, 3).unwrap(), 16);
+        assert_eq!(zones.append(16, 3).unwrap(), 19);
+        assert!(matches!(
+            zones.append(16, 3),
+            Err(ExecuteError::ZoneBoundary)
+        ));
+        assert!(matches!(
+            zones.append(17, 1),
+            Err(ExecuteError::ZoneInvalidCommand)
+        ));
+        assert!(matches!(
+            zones.append(0, 1),
+            Err(ExecuteError::ZoneInvalidCommand)
+        ));
+    }
+
+    #[test]
+    fn test_zone_management() {
+        let mut zones = zone_table();
+
+        zones.manage(RequestType::ZoneOpen, 8).unwrap();
+        assert_eq!(zones.zones[1].condition, ZoneCondition::ExplicitlyOpen);
+        zones.manage(RequestType::ZoneClose, 8).unwrap();
+        assert_eq!(zones.zones[1].condition, ZoneCondition::Empty);
+
+        zones.write(8, 2).unwrap();
+        zones.manage(RequestType::ZoneClose, 8).unwrap();
+        assert_eq!(zones.zones[1].condition, ZoneCondition::Closed);
+
+        zones.manage(RequestType::ZoneFinish, 16).unwrap();
+        assert_eq!(zones.zones[2].condition, ZoneCondition::Full);
+        assert_eq!(zones.zones[2].write_pointer, 24);
+
+        zones.manage(RequestType::ZoneReset, 16).unwrap();
+        assert_eq!(zones.zones[2].condition, ZoneCondition::Empty);
+        assert_eq!(zones.zones[2].write_pointer, 16);
+
+        zones.manage(RequestType::ZoneResetAll, 0).unwrap();
+        assert_eq!(zones.zones[0].condition, ZoneCondition::NotWritePointer);
+        assert_eq!(zones.zones[1].condition, ZoneCondition::Empty);
+        assert_eq!(zones.zones[1].write_pointer, 8);
+
+        assert!(matches!(
+            zones.manage(RequestType::ZoneReset, 0),
+            Err(ExecuteError::ZoneInvalidCommand)
+        ));
+        assert!(matches!(
+            zones.manage(RequestType::ZoneOpen, 9),
+            Err(ExecuteError::ZoneInvalidCommand)
+        ));
+    }
+}
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use std::fs;
 use std::process::Command;
 
 fn main() {
@@ -16,9 +17,31 @@ fn main() {
         }
     }
 
+    let mut git_commit = "unknown".to_owned();
+
+    if let Ok(git_out) = Command::new("git").args(["rev-parse", "HEAD"]).output() {
+        if git_out.status.success() {
+            if let Ok(git_out_str) = String::from_utf8(git_out.stdout) {
+                git_commit = git_out_str.trim().to_owned();
+            }
+        }
+    }
+
     // This println!() has a special behavior, as it will set the environment
     // variable BUILD_VERSION, so that it can be reused from the binary.
     // Particularly, this is used from src/main.rs to display the exact
     // version.
     println!("cargo:rustc-env=BUILD_VERSION={version}");
+    println!("cargo:rustc-env=GIT_COMMIT={git_commit}");
+
+    // Regenerate the version and the commit whenever HEAD moves, be it by
+    // checking out another branch or by committing to the current one, and
+    // whenever the index changes for the "-dirty" suffix to be refreshed.
+    if let Ok(head) = fs::read_to_string(".git/HEAD") {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/index");
+        if let Some(reference) = head.strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", reference.trim());
+        }
+    }
 }
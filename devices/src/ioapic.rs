@@ -35,7 +35,8 @@ type Result<T> = result::Result<T, Error>;
 // split between two 32 bits registers as follow:
 //
 // 63-56: Destination Field - R/W
-// 55-17: Reserved
+// 55-49: Extended Destination ID - R/W
+// 48-17: Reserved
 // 16:    Interrupt Mask - R/W
 // 15:    Trigger Mode - R/W
 // 14:    Remote IRR - RO
@@ -72,6 +73,11 @@ fn destination_field(entry: RedirectionTableEntry) -> u8 {
     // retrieve the destination field based on bits 56-63.
     ((entry >> 56) & 0xffu64) as u8
 }
+fn extended_destination_id(entry: RedirectionTableEntry) -> u8 {
+    // Bits 8-14 of the destination APIC id, programmed by guests relying on
+    // the KVM extended destination ID feature to target APIC ids above 255.
+    ((entry >> 49) & 0x7fu64) as u8
+}
 fn set_delivery_status(entry: &mut RedirectionTableEntry, val: u8) {
     // Clear bit 12
     *entry &= 0xffff_ffff_ffff_efff;
@@ -349,6 +355,7 @@ impl Ioapic {
         // Generate MSI message address
         let low_addr: u32 = self.apic_address.0 as u32
             | u32::from(destination_id) << 12
+            | u32::from(extended_destination_id(entry)) << 5
             | u32::from(redirection_hint) << 3
             | u32::from(destination_mode) << 2;
 
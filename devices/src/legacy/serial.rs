@@ -33,6 +33,7 @@ const DLAB_HIGH: u8 = 1;
 
 const IER_RECV_BIT: u8 = 0x1;
 const IER_THR_BIT: u8 = 0x2;
+const IER_MODEM_BIT: u8 = 0x8;
 const IER_FIFO_BITS: u8 = 0x0f;
 
 const IIR_FIFO_BITS: u8 = 0xc0;
@@ -46,13 +47,28 @@ const LSR_DATA_BIT: u8 = 0x1;
 const LSR_EMPTY_BIT: u8 = 0x20;
 const LSR_IDLE_BIT: u8 = 0x40;
 
+const MCR_DTR_BIT: u8 = 0x1;
+const MCR_RTS_BIT: u8 = 0x2;
+const MCR_OUT1_BIT: u8 = 0x4;
+const MCR_OUT2_BIT: u8 = 0x8;
 const MCR_LOOP_BIT: u8 = 0x10;
 
+const MSR_DCTS_BIT: u8 = 0x1;
+const MSR_DDSR_BIT: u8 = 0x2;
+const MSR_TERI_BIT: u8 = 0x4;
+const MSR_DDCD_BIT: u8 = 0x8;
+const MSR_CTS_BIT: u8 = 0x10;
+const MSR_DSR_BIT: u8 = 0x20;
+const MSR_RI_BIT: u8 = 0x40;
+const MSR_DCD_BIT: u8 = 0x80;
+const MSR_DELTA_BITS: u8 = 0x0f;
+const MSR_LINE_BITS: u8 = 0xf0;
+
 const DEFAULT_INTERRUPT_IDENTIFICATION: u8 = IIR_NONE_BIT; // no pending interrupt
 const DEFAULT_LINE_STATUS: u8 = LSR_EMPTY_BIT | LSR_IDLE_BIT; // THR empty and line is idle
 const DEFAULT_LINE_CONTROL: u8 = 0x3; // 8-bits per character
 const DEFAULT_MODEM_CONTROL: u8 = 0x8; // Auxiliary output 2
-const DEFAULT_MODEM_STATUS: u8 = 0x20 | 0x10 | 0x80; // data ready, clear to send, carrier detect
+const DEFAULT_MODEM_STATUS: u8 = MSR_DSR_BIT | MSR_CTS_BIT | MSR_DCD_BIT; // data set ready, clear to send, carrier detect
 const DEFAULT_BAUD_DIVISOR: u16 = 12; // 9600 bps
 
 /// Emulates serial COM ports commonly seen on x86 I/O ports 0x3f8/0x2f8/0x3e8/0x2e8.
@@ -72,6 +88,8 @@ pub struct Serial {
     in_buffer: VecDeque<u8>,
     interrupt: Arc<dyn InterruptSourceGroup>,
     out: Option<Box<dyn io::Write + Send>>,
+    flow_control: bool,
+    out_ready: bool,
 }
 
 #[derive(Versionize)]
@@ -144,6 +162,8 @@ impl Serial {
             in_buffer,
             interrupt,
             out,
+            flow_control: false,
+            out_ready: true,
         }
     }
 
@@ -170,12 +190,27 @@ impl Serial {
         self.out = Some(out);
     }
 
+    /// Enables RTS/CTS hardware flow control. The guest is told through CTS and DSR whether the
+    /// output is ready to accept data, and input is only signaled while the guest asserts RTS.
+    pub fn set_flow_control(&mut self, flow_control: bool) {
+        self.flow_control = flow_control;
+    }
+
+    /// Reports whether the output is ready to accept data, which is reflected through CTS and
+    /// DSR when flow control is enabled.
+    pub fn set_out_ready(&mut self, ready: bool) -> Result<()> {
+        self.out_ready = ready;
+        self.update_modem_status()
+    }
+
     /// Queues raw bytes for the guest to read and signals the interrupt if the line status would
     /// change.
     pub fn queue_input_bytes(&mut self, c: &[u8]) -> Result<()> {
         if !self.is_loop() {
             self.in_buffer.extend(c);
-            self.recv_data()?;
+            if self.is_recv_allowed() {
+                self.recv_data()?;
+            }
         }
         Ok(())
     }
@@ -199,10 +234,71 @@ impl Serial {
         (self.interrupt_enable & IER_THR_BIT) != 0
     }
 
+    fn is_modem_intr_enabled(&self) -> bool {
+        (self.interrupt_enable & IER_MODEM_BIT) != 0
+    }
+
     fn is_loop(&self) -> bool {
         (self.modem_control & MCR_LOOP_BIT) != 0
     }
 
+    fn is_recv_allowed(&self) -> bool {
+        !self.flow_control || (self.modem_control & MCR_RTS_BIT) != 0
+    }
+
+    // State of the modem status lines. In loopback mode they are driven by the modem control
+    // outputs, otherwise they are asserted unless flow control reports the output as busy.
+    fn modem_lines(&self) -> u8 {
+        if self.is_loop() {
+            [
+                (MCR_DTR_BIT, MSR_DSR_BIT),
+                (MCR_RTS_BIT, MSR_CTS_BIT),
+                (MCR_OUT1_BIT, MSR_RI_BIT),
+                (MCR_OUT2_BIT, MSR_DCD_BIT),
+            ]
+            .iter()
+            .filter(|(mcr_bit, _)| (self.modem_control & mcr_bit) != 0)
+            .fold(0, |lines, (_, msr_bit)| lines | msr_bit)
+        } else if self.flow_control && !self.out_ready {
+            DEFAULT_MODEM_STATUS & !(MSR_CTS_BIT | MSR_DSR_BIT)
+        } else {
+            DEFAULT_MODEM_STATUS
+        }
+    }
+
+    fn update_modem_status(&mut self) -> Result<()> {
+        let old_lines = self.modem_status & MSR_LINE_BITS;
+        let new_lines = self.modem_lines();
+        let changed = old_lines ^ new_lines;
+        if changed == 0 {
+            return Ok(());
+        }
+
+        let mut deltas = 0;
+        if (changed & MSR_CTS_BIT) != 0 {
+            deltas |= MSR_DCTS_BIT;
+        }
+        if (changed & MSR_DSR_BIT) != 0 {
+            deltas |= MSR_DDSR_BIT;
+        }
+        // Only the trailing edge of the ring indicator is reported.
+        if (old_lines & !new_lines & MSR_RI_BIT) != 0 {
+            deltas |= MSR_TERI_BIT;
+        }
+        if (changed & MSR_DCD_BIT) != 0 {
+            deltas |= MSR_DDCD_BIT;
+        }
+        self.modem_status = (self.modem_status & MSR_DELTA_BITS) | deltas | new_lines;
+
+        if deltas != 0 && self.is_modem_intr_enabled() {
+            // The modem status interrupt has the lowest priority, and it is
+            // identified by an empty interrupt identification.
+            self.add_intr_bit(0);
+            self.trigger_interrupt()?
+        }
+        Ok(())
+    }
+
     fn add_intr_bit(&mut self, bit: u8) {
         self.interrupt_identification &= !IIR_NONE_BIT;
         self.interrupt_identification |= bit;
@@ -264,7 +360,14 @@ impl Serial {
             }
             IER => self.interrupt_enable = v & IER_FIFO_BITS,
             LCR => self.line_control = v,
-            MCR => self.modem_control = v,
+            MCR => {
+                let recv_allowed = self.is_recv_allowed();
+                self.modem_control = v;
+                self.update_modem_status()?;
+                if !recv_allowed && self.is_recv_allowed() && !self.in_buffer.is_empty() {
+                    self.recv_data()?;
+                }
+            }
             SCR => self.scratch = v,
             _ => {}
         }
@@ -311,7 +414,15 @@ impl BusDevice for Serial {
             LCR => self.line_control,
             MCR => self.modem_control,
             LSR => self.line_status,
-            MSR => self.modem_status,
+            MSR => {
+                let v = self.modem_status;
+                self.modem_status &= !MSR_DELTA_BITS;
+                // Reading the modem status clears the modem status interrupt.
+                if self.interrupt_identification == 0 {
+                    self.iir_reset();
+                }
+                v
+            }
             SCR => self.scratch,
             _ => 0,
         };
@@ -524,9 +635,13 @@ mod tests {
         serial.write(0, DATA as u64, &[b'b']);
         serial.write(0, DATA as u64, &[b'c']);
 
+        // Entering loopback mode with all the modem control outputs
+        // deasserted drops all the modem status lines.
         let mut data = [0u8];
         serial.read(0, MSR as u64, &mut data[..]);
-        assert_eq!(data[0], DEFAULT_MODEM_STATUS);
+        assert_eq!(data[0], MSR_DCTS_BIT | MSR_DDSR_BIT | MSR_DDCD_BIT);
+        serial.read(0, MSR as u64, &mut data[..]);
+        assert_eq!(data[0], 0);
         serial.read(0, MCR as u64, &mut data[..]);
         assert_eq!(data[0], MCR_LOOP_BIT);
         serial.read(0, DATA as u64, &mut data[..]);
@@ -537,6 +652,72 @@ mod tests {
         assert_eq!(data[0], b'c');
     }
 
+    #[test]
+    fn serial_modem_loopback() {
+        let intr_evt = EventFd::new(0).unwrap();
+        let mut serial = Serial::new_sink(
+            String::from(SERIAL_NAME),
+            Arc::new(TestInterrupt::new(intr_evt.try_clone().unwrap())),
+            None,
+        );
+
+        let mut data = [0u8];
+        serial.write(0, MCR as u64, &[MCR_LOOP_BIT | MCR_RTS_BIT | MCR_OUT2_BIT]);
+        serial.read(0, MSR as u64, &mut data[..]);
+        assert_eq!(data[0] & MSR_LINE_BITS, MSR_CTS_BIT | MSR_DCD_BIT);
+
+        serial.write(0, MCR as u64, &[MCR_LOOP_BIT | MCR_DTR_BIT | MCR_OUT1_BIT]);
+        serial.read(0, MSR as u64, &mut data[..]);
+        assert_eq!(
+            data[0],
+            MSR_DSR_BIT | MSR_RI_BIT | MSR_DCTS_BIT | MSR_DDSR_BIT | MSR_DDCD_BIT
+        );
+
+        // Only the trailing edge of the ring indicator is reported, and it
+        // raises a modem status interrupt when enabled.
+        serial.write(0, IER as u64, &[IER_MODEM_BIT]);
+        serial.write(0, MCR as u64, &[MCR_LOOP_BIT | MCR_DTR_BIT]);
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        serial.read(0, IIR as u64, &mut data[..]);
+        assert_eq!(data[0] & !IIR_FIFO_BITS, 0);
+        serial.read(0, MSR as u64, &mut data[..]);
+        assert_eq!(data[0], MSR_DSR_BIT | MSR_TERI_BIT);
+    }
+
+    #[test]
+    fn serial_flow_control() {
+        let intr_evt = EventFd::new(0).unwrap();
+        let mut serial = Serial::new_sink(
+            String::from(SERIAL_NAME),
+            Arc::new(TestInterrupt::new(intr_evt.try_clone().unwrap())),
+            None,
+        );
+
+        let mut data = [0u8];
+        serial.set_flow_control(true);
+        serial.set_out_ready(false).unwrap();
+        serial.read(0, MSR as u64, &mut data[..]);
+        assert_eq!(data[0], MSR_DCD_BIT | MSR_DCTS_BIT | MSR_DDSR_BIT);
+
+        serial.write(0, IER as u64, &[IER_MODEM_BIT]);
+        serial.set_out_ready(true).unwrap();
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        serial.read(0, MSR as u64, &mut data[..]);
+        assert_eq!(data[0], DEFAULT_MODEM_STATUS | MSR_DCTS_BIT | MSR_DDSR_BIT);
+        serial.read(0, IIR as u64, &mut data[..]);
+        assert_eq!(data[0] & IIR_NONE_BIT, IIR_NONE_BIT);
+
+        // Input is held back until the guest asserts RTS.
+        serial.queue_input_bytes(&[b'a']).unwrap();
+        serial.read(0, LSR as u64, &mut data[..]);
+        assert_eq!(data[0] & LSR_DATA_BIT, 0);
+        serial.write(0, MCR as u64, &[MCR_RTS_BIT]);
+        serial.read(0, LSR as u64, &mut data[..]);
+        assert_eq!(data[0] & LSR_DATA_BIT, LSR_DATA_BIT);
+        serial.read(0, DATA as u64, &mut data[..]);
+        assert_eq!(data[0], b'a');
+    }
+
     #[test]
     fn serial_scratch() {
         let intr_evt = EventFd::new(0).unwrap();
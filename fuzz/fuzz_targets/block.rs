@@ -55,11 +55,22 @@ fuzz_target!(|bytes| {
         PathBuf::from(""),
         false,
         false,
+        false,
         2,
         256,
         None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
         SeccompAction::Allow,
         None,
+        None,
+        None,
         EventFd::new(EFD_NONBLOCK).unwrap(),
         None,
     )
@@ -22,7 +22,7 @@ fuzz_target!(|bytes| {
         firmware: None,
         kernel: None,
         cmdline: Some(String::from_utf8_lossy(&bytes).to_string()),
-        initramfs: None,
+        initramfs: Vec::new(),
     };
     let kernel_cmdline = match vmm::vm::Vm::generate_cmdline(&payload_config) {
         Ok(cmdline) => cmdline,
@@ -138,6 +138,7 @@ fn create_dummy_virtio_mem(bytes: &[u8; VIRTIO_MEM_DATA_SIZE]) -> (Mem, Arc<Gues
         numa_id,
         None,
         false,
+        1,
     )
     .unwrap();
 
@@ -151,6 +152,7 @@ fn create_dummy_virtio_mem(bytes: &[u8; VIRTIO_MEM_DATA_SIZE]) -> (Mem, Arc<Gues
             numa_id.map(|i| i as u16),
             0,
             false,
+            false,
             EventFd::new(EFD_NONBLOCK).unwrap(),
             blocks_state.clone(),
             None,
@@ -72,6 +72,7 @@ fuzz_target!(|bytes| {
         false, // iommu
         QUEUE_NUM,
         QUEUE_SIZE,
+        QUEUE_SIZE,
         SeccompAction::Allow,
         None,
         EventFd::new(EFD_NONBLOCK).unwrap(),
@@ -79,6 +80,8 @@ fuzz_target!(|bytes| {
         true,
         true,
         true,
+        true,
+        false, // vhost
     )
     .unwrap();
 
@@ -61,6 +61,7 @@ fuzz_target!(|bytes| {
         "fuzzer_rng".to_owned(),
         "/dev/urandom",
         false,
+        QUEUE_SIZE,
         SeccompAction::Allow,
         EventFd::new(EFD_NONBLOCK).unwrap(),
         None,
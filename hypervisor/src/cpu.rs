@@ -257,6 +257,24 @@ pub enum HypervisorCpuError {
     ///
     #[error("Failed to set TSC frequency: {0}")]
     SetTscKhz(#[source] anyhow::Error),
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Error getting TSC offset
+    ///
+    #[error("Failed to get TSC offset: {0}")]
+    GetTscOffset(#[source] anyhow::Error),
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Error setting TSC offset
+    ///
+    #[error("Failed to set TSC offset: {0}")]
+    SetTscOffset(#[source] anyhow::Error),
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Error injecting NMI
+    ///
+    #[error("Failed to inject NMI: {0}")]
+    Nmi(#[source] anyhow::Error),
 }
 
 #[derive(Debug)]
@@ -392,7 +410,7 @@ pub trait Vcpu: Send + Sync {
     /// Configure core registers for a given CPU.
     ///
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> Result<()>;
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> Result<()>;
     ///
     /// Check if the CPU supports PMU
     ///
@@ -467,4 +485,26 @@ pub trait Vcpu: Send + Sync {
     fn set_tsc_khz(&self, _freq: u32) -> Result<()> {
         Ok(())
     }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Get the offset between the host and the guest TSC if available
+    ///
+    fn tsc_offset(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Set the offset between the host and the guest TSC if available
+    ///
+    fn set_tsc_offset(&self, _offset: u64) -> Result<()> {
+        Ok(())
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Queue an NMI to be delivered to the vCPU the next time it enters
+    /// the guest
+    ///
+    fn nmi(&self) -> Result<()> {
+        Err(HypervisorCpuError::Nmi(anyhow!("unimplemented")))
+    }
 }
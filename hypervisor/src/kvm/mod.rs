@@ -33,12 +33,12 @@ use std::convert::TryInto;
 use std::fs::File;
 #[cfg(target_arch = "x86_64")]
 use std::os::unix::io::AsRawFd;
-#[cfg(feature = "tdx")]
+#[cfg(any(feature = "tdx", feature = "sev"))]
 use std::os::unix::io::RawFd;
 use std::result;
 #[cfg(target_arch = "x86_64")]
 use std::sync::atomic::{AtomicBool, Ordering};
-#[cfg(target_arch = "aarch64")]
+#[cfg(any(target_arch = "aarch64", feature = "sev"))]
 use std::sync::Mutex;
 use std::sync::{Arc, RwLock};
 use vmm_sys_util::eventfd::EventFd;
@@ -61,7 +61,8 @@ use aarch64::{RegList, Register, StandardRegisters};
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
     kvm_enable_cap, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC, KVM_CAP_SPLIT_IRQCHIP,
-    KVM_GUESTDBG_USE_HW_BP,
+    KVM_CAP_X2APIC_API, KVM_GUESTDBG_USE_HW_BP, KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK,
+    KVM_X2APIC_API_USE_32BIT_IDS,
 };
 #[cfg(target_arch = "x86_64")]
 use x86_64::check_required_kvm_extensions;
@@ -71,7 +72,7 @@ pub use x86_64::{CpuId, ExtendedControlRegisters, MsrEntries, VcpuKvmState, Xsav
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
 pub use kvm_bindings;
-#[cfg(feature = "tdx")]
+#[cfg(target_arch = "x86_64")]
 use kvm_bindings::KVMIO;
 pub use kvm_bindings::{
     kvm_clock_data, kvm_create_device, kvm_device_type_KVM_DEV_TYPE_VFIO, kvm_guest_debug,
@@ -93,7 +94,13 @@ use std::mem;
 use thiserror::Error;
 use vfio_ioctls::VfioDeviceFd;
 #[cfg(feature = "tdx")]
-use vmm_sys_util::{ioctl::ioctl_with_val, ioctl_ioc_nr, ioctl_iowr_nr};
+use vmm_sys_util::ioctl::ioctl_with_val;
+#[cfg(any(feature = "tdx", feature = "sev"))]
+use vmm_sys_util::ioctl_iowr_nr;
+#[cfg(feature = "sev")]
+use vmm_sys_util::{ioctl::ioctl_with_mut_ref, ioctl_ior_nr};
+#[cfg(target_arch = "x86_64")]
+use vmm_sys_util::{ioctl::ioctl_with_ref, ioctl_ioc_nr, ioctl_iow_nr};
 ///
 /// Export generically-named wrappers of kvm-bindings for Unix-based platforms
 ///
@@ -105,6 +112,36 @@ pub use {
 #[cfg(target_arch = "x86_64")]
 const KVM_CAP_SGX_ATTRIBUTE: u32 = 196;
 
+const KVM_CAP_HALT_POLL: u32 = 182;
+
+// Offset of the guest TSC, exposed as a vCPU attribute since Linux 5.16.
+#[cfg(target_arch = "x86_64")]
+const KVM_VCPU_TSC_CTRL: u32 = 0;
+#[cfg(target_arch = "x86_64")]
+const KVM_VCPU_TSC_OFFSET: u64 = 0;
+
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(
+    KVM_SET_DEVICE_ATTR,
+    KVMIO,
+    0xe1,
+    kvm_bindings::kvm_device_attr
+);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(
+    KVM_GET_DEVICE_ATTR,
+    KVMIO,
+    0xe2,
+    kvm_bindings::kvm_device_attr
+);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(
+    KVM_HAS_DEVICE_ATTR,
+    KVMIO,
+    0xe3,
+    kvm_bindings::kvm_device_attr
+);
+
 #[cfg(feature = "tdx")]
 const KVM_EXIT_TDX: u32 = 50;
 #[cfg(feature = "tdx")]
@@ -116,9 +153,35 @@ const TDG_VP_VMCALL_SUCCESS: u64 = 0;
 #[cfg(feature = "tdx")]
 const TDG_VP_VMCALL_INVALID_OPERAND: u64 = 0x8000000000000000;
 
-#[cfg(feature = "tdx")]
+#[cfg(any(feature = "tdx", feature = "sev"))]
 ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, std::os::raw::c_ulong);
 
+#[cfg(feature = "sev")]
+#[repr(C)]
+struct KvmEncRegion {
+    addr: u64,
+    size: u64,
+}
+
+#[cfg(feature = "sev")]
+ioctl_ior_nr!(KVM_MEMORY_ENCRYPT_REG_REGION, KVMIO, 0xbb, KvmEncRegion);
+
+#[cfg(feature = "sev")]
+#[repr(u32)]
+enum SevCommand {
+    EsInit = 1,
+    LaunchStart,
+    LaunchUpdateData,
+    LaunchUpdateVmsa,
+    LaunchMeasure = 6,
+    LaunchFinish,
+}
+
+// Size of the launch measurement: a 32 bytes HMAC followed by a 16 bytes
+// nonce.
+#[cfg(feature = "sev")]
+const SEV_LAUNCH_MEASUREMENT_SIZE: usize = 48;
+
 #[cfg(feature = "tdx")]
 #[repr(u32)]
 enum TdxCommand {
@@ -315,6 +378,8 @@ pub struct KvmVm {
     #[cfg(target_arch = "x86_64")]
     msrs: Vec<MsrEntry>,
     dirty_log_slots: Arc<RwLock<HashMap<u32, KvmDirtyLogSlot>>>,
+    #[cfg(feature = "sev")]
+    sev: Mutex<Option<File>>,
 }
 
 impl KvmVm {
@@ -394,7 +459,7 @@ impl vm::Vm for KvmVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
         let vc = self
@@ -646,6 +711,21 @@ impl vm::Vm for KvmVm {
         Ok(())
     }
     #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        // Let the guest use 32-bit APIC ids, needed as soon as there are
+        // more vCPUs than what xAPIC can address.
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_X2APIC_API,
+            ..Default::default()
+        };
+        cap.args[0] =
+            (KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK) as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::EnableX2ApicApi(e.into()))?;
+        Ok(())
+    }
+    #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> vm::Result<()> {
         let mut cap = kvm_enable_cap {
             cap: KVM_CAP_SGX_ATTRIBUTE,
@@ -657,6 +737,18 @@ impl vm::Vm for KvmVm {
             .map_err(|e| vm::HypervisorVmError::EnableSgxAttribute(e.into()))?;
         Ok(())
     }
+    fn set_halt_poll(&self, poll_ns: u32) -> vm::Result<()> {
+        // Overrides the halt_poll_ns module parameter for this VM only
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_HALT_POLL,
+            ..Default::default()
+        };
+        cap.args[0] = poll_ns as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::SetHaltPoll(e.into()))?;
+        Ok(())
+    }
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> vm::Result<ClockData> {
@@ -826,12 +918,175 @@ impl vm::Vm for KvmVm {
         )
         .map_err(vm::HypervisorVmError::InitMemRegionTdx)
     }
+
+    ///
+    /// Initialize SEV-ES for this VM and start its launch
+    ///
+    #[cfg(feature = "sev")]
+    fn sev_es_init(&self, policy: u32) -> vm::Result<()> {
+        let sev = File::options()
+            .read(true)
+            .write(true)
+            .open("/dev/sev")
+            .map_err(vm::HypervisorVmError::InitializeSev)?;
+
+        sev_command(
+            &self.fd.as_raw_fd(),
+            &sev.as_raw_fd(),
+            SevCommand::EsInit,
+            0,
+        )
+        .map_err(vm::HypervisorVmError::InitializeSev)?;
+
+        // No session is established with the guest owner, which leaves the
+        // Diffie-Hellman certificate and the session blobs empty.
+        #[repr(C)]
+        #[derive(Default)]
+        struct SevLaunchStart {
+            handle: u32,
+            policy: u32,
+            dh_uaddr: u64,
+            dh_len: u32,
+            session_uaddr: u64,
+            session_len: u32,
+        }
+        let data = SevLaunchStart {
+            policy,
+            ..Default::default()
+        };
+
+        sev_command(
+            &self.fd.as_raw_fd(),
+            &sev.as_raw_fd(),
+            SevCommand::LaunchStart,
+            &data as *const _ as u64,
+        )
+        .map_err(vm::HypervisorVmError::InitializeSev)?;
+
+        *self.sev.lock().unwrap() = Some(sev);
+        Ok(())
+    }
+
+    ///
+    /// Register a memory region holding encrypted memory, pinning it
+    ///
+    #[cfg(feature = "sev")]
+    fn sev_register_memory_region(&self, host_address: u64, size: u64) -> vm::Result<()> {
+        let region = KvmEncRegion {
+            addr: host_address,
+            size,
+        };
+        // SAFETY: IOCTL with correct arguments
+        if unsafe { ioctl_with_ref(self.fd.as_ref(), KVM_MEMORY_ENCRYPT_REG_REGION(), &region) } < 0
+        {
+            return Err(vm::HypervisorVmError::RegisterMemRegionSev(
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+
+    ///
+    /// Encrypt a memory region in place, adding it to the launch measurement
+    ///
+    #[cfg(feature = "sev")]
+    fn sev_launch_update_data(&self, host_address: u64, size: u64) -> vm::Result<()> {
+        #[repr(C)]
+        struct SevLaunchUpdateData {
+            uaddr: u64,
+            len: u32,
+        }
+
+        // The length of a single update is limited to 32 bits.
+        let mut offset = 0;
+        while offset < size {
+            let len = std::cmp::min(size - offset, 1 << 30);
+            let data = SevLaunchUpdateData {
+                uaddr: host_address + offset,
+                len: len as u32,
+            };
+            self.sev_vm_command(SevCommand::LaunchUpdateData, &data as *const _ as u64)
+                .map_err(vm::HypervisorVmError::LaunchUpdateDataSev)?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Encrypt the vCPUs state and finalize the SEV-ES launch
+    ///
+    #[cfg(feature = "sev")]
+    fn sev_launch_finish(&self) -> vm::Result<Vec<u8>> {
+        self.sev_vm_command(SevCommand::LaunchUpdateVmsa, 0)
+            .map_err(vm::HypervisorVmError::FinalizeSev)?;
+
+        #[repr(C)]
+        struct SevLaunchMeasure {
+            uaddr: u64,
+            len: u32,
+        }
+        let mut measurement = vec![0u8; SEV_LAUNCH_MEASUREMENT_SIZE];
+        let data = SevLaunchMeasure {
+            uaddr: measurement.as_mut_ptr() as u64,
+            len: measurement.len() as u32,
+        };
+        self.sev_vm_command(SevCommand::LaunchMeasure, &data as *const _ as u64)
+            .map_err(vm::HypervisorVmError::FinalizeSev)?;
+
+        self.sev_vm_command(SevCommand::LaunchFinish, 0)
+            .map_err(vm::HypervisorVmError::FinalizeSev)?;
+
+        Ok(measurement)
+    }
     /// Downcast to the underlying KvmVm type
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
 
+#[cfg(feature = "sev")]
+impl KvmVm {
+    fn sev_vm_command(&self, command: SevCommand, data: u64) -> std::io::Result<()> {
+        let sev = self.sev.lock().unwrap();
+        let sev = sev.as_ref().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "SEV-ES is not initialized")
+        })?;
+        sev_command(&self.fd.as_raw_fd(), &sev.as_raw_fd(), command, data)
+    }
+}
+
+#[cfg(feature = "sev")]
+fn sev_command(fd: &RawFd, sev_fd: &RawFd, command: SevCommand, data: u64) -> std::io::Result<()> {
+    #[repr(C)]
+    struct SevIoctlCmd {
+        id: SevCommand,
+        data: u64,
+        error: u32,
+        sev_fd: u32,
+    }
+    let mut cmd = SevIoctlCmd {
+        id: command,
+        data,
+        error: 0,
+        sev_fd: *sev_fd as u32,
+    };
+    // SAFETY: FFI call. All input parameters are valid, and the firmware
+    // error code is written to the local variable cmd points to.
+    let ret = unsafe { ioctl_with_mut_ref(fd, KVM_MEMORY_ENCRYPT_OP(), &mut cmd) };
+
+    if ret < 0 {
+        let e = std::io::Error::last_os_error();
+        if cmd.error != 0 {
+            return Err(std::io::Error::new(
+                e.kind(),
+                format!("{e} (SEV firmware error {:#x})", cmd.error),
+            ));
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
 #[cfg(feature = "tdx")]
 fn tdx_command(
     fd: &RawFd,
@@ -985,6 +1240,8 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                 fd: vm_fd,
                 msrs,
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
+                #[cfg(feature = "sev")]
+                sev: Mutex::new(None),
             }))
         }
 
@@ -1737,7 +1994,7 @@ impl cpu::Vcpu for KvmVcpu {
     /// Configure core registers for a given CPU.
     ///
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
         #[allow(non_upper_case_globals)]
         // PSR (Processor State Register) bits.
         // Taken from arch/arm64/include/uapi/asm/ptrace.h.
@@ -1891,6 +2148,7 @@ impl cpu::Vcpu for KvmVcpu {
 
         let vcpu_events = self.get_vcpu_events()?;
         let tsc_khz = self.tsc_khz()?;
+        let tsc_offset = self.tsc_offset()?;
 
         Ok(VcpuKvmState {
             cpuid,
@@ -1904,6 +2162,7 @@ impl cpu::Vcpu for KvmVcpu {
             xcrs,
             mp_state,
             tsc_khz,
+            tsc_offset,
         }
         .into())
     }
@@ -2209,6 +2468,74 @@ impl cpu::Vcpu for KvmVcpu {
             Ok(_) => Ok(()),
         }
     }
+
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Get the offset between the host and the guest TSC if available
+    ///
+    fn tsc_offset(&self) -> cpu::Result<Option<u64>> {
+        let mut offset: u64 = 0;
+        let attr = kvm_bindings::kvm_device_attr {
+            group: KVM_VCPU_TSC_CTRL,
+            attr: KVM_VCPU_TSC_OFFSET,
+            addr: &mut offset as *mut u64 as u64,
+            flags: 0,
+        };
+
+        // SAFETY: IOCTL with correct arguments
+        if unsafe { ioctl_with_ref(&self.fd, KVM_HAS_DEVICE_ATTR(), &attr) } < 0 {
+            return Ok(None);
+        }
+
+        // SAFETY: IOCTL with correct arguments, the offset is written to the
+        // local variable attr points to.
+        if unsafe { ioctl_with_ref(&self.fd, KVM_GET_DEVICE_ATTR(), &attr) } < 0 {
+            return Err(cpu::HypervisorCpuError::GetTscOffset(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+
+        Ok(Some(offset))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Set the offset between the host and the guest TSC if available
+    ///
+    fn set_tsc_offset(&self, offset: u64) -> cpu::Result<()> {
+        let attr = kvm_bindings::kvm_device_attr {
+            group: KVM_VCPU_TSC_CTRL,
+            attr: KVM_VCPU_TSC_OFFSET,
+            addr: &offset as *const u64 as u64,
+            flags: 0,
+        };
+
+        // SAFETY: IOCTL with correct arguments
+        if unsafe { ioctl_with_ref(&self.fd, KVM_HAS_DEVICE_ATTR(), &attr) } < 0 {
+            return Ok(());
+        }
+
+        // SAFETY: IOCTL with correct arguments, the offset is read from the
+        // local variable attr points to.
+        if unsafe { ioctl_with_ref(&self.fd, KVM_SET_DEVICE_ATTR(), &attr) } < 0 {
+            return Err(cpu::HypervisorCpuError::SetTscOffset(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Queue an NMI to be delivered to the vCPU the next time it enters
+    /// the guest
+    ///
+    fn nmi(&self) -> cpu::Result<()> {
+        self.fd
+            .nmi()
+            .map_err(|e| cpu::HypervisorCpuError::Nmi(e.into()))
+    }
 }
 
 impl KvmVcpu {
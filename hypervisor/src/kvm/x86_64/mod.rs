@@ -68,6 +68,10 @@ pub struct VcpuKvmState {
     pub xcrs: ExtendedControlRegisters,
     pub mp_state: MpState,
     pub tsc_khz: Option<u32>,
+    // Not restored along with the rest of the state, see
+    // CpuState::tsc_offset().
+    #[serde(default)]
+    pub tsc_offset: Option<u64>,
 }
 
 impl From<StandardRegisters> for kvm_regs {
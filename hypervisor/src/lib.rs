@@ -158,6 +158,22 @@ pub enum CpuState {
     Mshv(mshv::VcpuMshvState),
 }
 
+impl CpuState {
+    /// Offset between the host and the guest TSC when the state was saved.
+    ///
+    /// Restoring it as is would make the guest TSC leap by the time elapsed
+    /// since then, it is only meaningful relative to the other vCPUs.
+    #[cfg(target_arch = "x86_64")]
+    pub fn tsc_offset(&self) -> Option<u64> {
+        match self {
+            #[cfg(feature = "kvm")]
+            CpuState::Kvm(s) => s.tsc_offset,
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[cfg(target_arch = "x86_64")]
 pub enum ClockData {
@@ -1019,9 +1019,11 @@ impl vm::Vm for MshvVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
+        // The MSHV ioctl API only takes 8-bit vCPU ids
+        let id = u8::try_from(id).map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
         let vcpu_fd = self
             .fd
             .create_vcpu(id)
@@ -1040,9 +1042,16 @@ impl vm::Vm for MshvVm {
         Ok(())
     }
     #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        Ok(())
+    }
+    #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, _file: File) -> vm::Result<()> {
         Ok(())
     }
+    fn set_halt_poll(&self, _poll_ns: u32) -> vm::Result<()> {
+        Ok(())
+    }
     fn register_ioevent(
         &self,
         fd: &EventFd,
@@ -121,11 +121,21 @@ pub enum HypervisorVmError {
     #[error("Failed to enable split Irq: {0}")]
     EnableSplitIrq(#[source] anyhow::Error),
     ///
+    /// Enable x2APIC API error
+    ///
+    #[error("Failed to enable x2APIC API: {0}")]
+    EnableX2ApicApi(#[source] anyhow::Error),
+    ///
     /// Enable SGX attribute error
     ///
     #[error("Failed to enable SGX attribute: {0}")]
     EnableSgxAttribute(#[source] anyhow::Error),
     ///
+    /// Set halt polling error
+    ///
+    #[error("Failed to set halt polling: {0}")]
+    SetHaltPoll(#[source] anyhow::Error),
+    ///
     /// Get clock error
     ///
     #[error("Failed to get clock: {0}")]
@@ -208,6 +218,30 @@ pub enum HypervisorVmError {
     ///
     #[error("Failed to initialize memory region TDX: {0}")]
     InitMemRegionTdx(#[source] std::io::Error),
+    #[cfg(feature = "sev")]
+    ///
+    /// Error initializing SEV-ES on the VM
+    ///
+    #[error("Failed to initialize SEV-ES: {0}")]
+    InitializeSev(#[source] std::io::Error),
+    #[cfg(feature = "sev")]
+    ///
+    /// Error registering an encrypted memory region of the VM
+    ///
+    #[error("Failed to register SEV memory region: {0}")]
+    RegisterMemRegionSev(#[source] std::io::Error),
+    #[cfg(feature = "sev")]
+    ///
+    /// Error encrypting a memory region of the VM
+    ///
+    #[error("Failed to encrypt SEV memory region: {0}")]
+    LaunchUpdateDataSev(#[source] std::io::Error),
+    #[cfg(feature = "sev")]
+    ///
+    /// Error completing the SEV-ES launch of the VM
+    ///
+    #[error("Failed to finalize SEV-ES launch: {0}")]
+    FinalizeSev(#[source] std::io::Error),
     ///
     /// Create Vgic error
     ///
@@ -271,7 +305,7 @@ pub trait Vm: Send + Sync + Any {
     /// Unregister an event that will, when signaled, trigger the `gsi` IRQ.
     fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()>;
     /// Creates a new KVM vCPU file descriptor and maps the memory corresponding
-    fn create_vcpu(&self, id: u8, vm_ops: Option<Arc<dyn VmOps>>) -> Result<Arc<dyn Vcpu>>;
+    fn create_vcpu(&self, id: u32, vm_ops: Option<Arc<dyn VmOps>>) -> Result<Arc<dyn Vcpu>>;
     #[cfg(target_arch = "aarch64")]
     fn create_vgic(&self, config: VgicConfig) -> Result<Arc<Mutex<dyn Vgic>>>;
 
@@ -308,8 +342,13 @@ pub trait Vm: Send + Sync + Any {
     /// Enable split Irq capability
     #[cfg(target_arch = "x86_64")]
     fn enable_split_irq(&self) -> Result<()>;
+    /// Enable 32-bit x2APIC ids for the in-kernel local APIC
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> Result<()>;
     #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> Result<()>;
+    /// Set how long a halted vCPU polls for wakeup events before blocking
+    fn set_halt_poll(&self, poll_ns: u32) -> Result<()>;
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> Result<ClockData>;
@@ -345,10 +384,40 @@ pub trait Vm: Send + Sync + Any {
     ) -> Result<()> {
         unimplemented!()
     }
+    #[cfg(feature = "sev")]
+    /// Initialize SEV-ES on this VM and start its launch with the given policy
+    fn sev_es_init(&self, _policy: u32) -> Result<()> {
+        Err(HypervisorVmError::InitializeSev(sev_unsupported()))
+    }
+    #[cfg(feature = "sev")]
+    /// Register a memory region of this VM holding encrypted guest memory
+    fn sev_register_memory_region(&self, _host_address: u64, _size: u64) -> Result<()> {
+        Err(HypervisorVmError::RegisterMemRegionSev(sev_unsupported()))
+    }
+    #[cfg(feature = "sev")]
+    /// Encrypt a memory region of this SEV-ES VM in place, adding it to the
+    /// launch measurement
+    fn sev_launch_update_data(&self, _host_address: u64, _size: u64) -> Result<()> {
+        Err(HypervisorVmError::LaunchUpdateDataSev(sev_unsupported()))
+    }
+    #[cfg(feature = "sev")]
+    /// Encrypt the vCPUs state and finalize the launch of this SEV-ES VM,
+    /// returning the launch measurement
+    fn sev_launch_finish(&self) -> Result<Vec<u8>> {
+        Err(HypervisorVmError::FinalizeSev(sev_unsupported()))
+    }
     /// Downcast to the underlying hypervisor VM type
     fn as_any(&self) -> &dyn Any;
 }
 
+#[cfg(feature = "sev")]
+fn sev_unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SEV-ES is not supported by this hypervisor",
+    )
+}
+
 pub trait VmOps: Send + Sync {
     fn guest_mem_write(&self, gpa: u64, buf: &[u8]) -> Result<usize>;
     fn guest_mem_read(&self, gpa: u64, buf: &mut [u8]) -> Result<usize>;
@@ -4,14 +4,17 @@
 
 use crate::GuestMemoryMmap;
 use crate::Tap;
+use crate::{MacAddr, RxFilter, MAC_ADDR_LEN};
 use libc::c_uint;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use virtio_bindings::virtio_net::{
-    VIRTIO_NET_CTRL_GUEST_OFFLOADS, VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET, VIRTIO_NET_CTRL_MQ,
+    VIRTIO_NET_CTRL_GUEST_OFFLOADS, VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET, VIRTIO_NET_CTRL_MAC,
+    VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ,
     VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_ERR, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_ECN, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6,
-    VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_OK,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
+    VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD,
+    VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN,
+    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_OK,
 };
 use virtio_queue::{Queue, QueueT};
 use vm_memory::{ByteValued, Bytes, GuestMemoryError};
@@ -37,6 +40,10 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
+// Upper bound on the data of a single control command, large enough for MAC
+// tables well beyond the number of entries actually filtered.
+const MAX_CTRL_DATA_LEN: usize = 64 * 1024;
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy, Default)]
 pub struct ControlHeader {
@@ -49,11 +56,12 @@ unsafe impl ByteValued for ControlHeader {}
 
 pub struct CtrlQueue {
     pub taps: Vec<Tap>,
+    pub rx_filter: Option<Arc<RwLock<RxFilter>>>,
 }
 
 impl CtrlQueue {
-    pub fn new(taps: Vec<Tap>) -> Self {
-        CtrlQueue { taps }
+    pub fn new(taps: Vec<Tap>, rx_filter: Option<Arc<RwLock<RxFilter>>>) -> Self {
+        CtrlQueue { taps, rx_filter }
     }
 
     pub fn process(
@@ -73,59 +81,43 @@ impl CtrlQueue {
                         .translate_gva(access_platform, ctrl_desc.len() as usize),
                 )
                 .map_err(Error::GuestMemory)?;
-            let data_desc = desc_chain.next().ok_or(Error::NoDataDescriptor)?;
-
-            let data_desc_addr = data_desc
-                .addr()
-                .translate_gva(access_platform, data_desc.len() as usize);
-
-            let status_desc = desc_chain.next().ok_or(Error::NoStatusDescriptor)?;
-
-            let ok = match u32::from(ctrl_hdr.class) {
-                VIRTIO_NET_CTRL_MQ => {
-                    let queue_pairs = desc_chain
-                        .memory()
-                        .read_obj::<u16>(data_desc_addr)
-                        .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
-                        warn!("Unsupported command: {}", ctrl_hdr.cmd);
-                        false
-                    } else if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
-                        || (queue_pairs > VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX as u16)
-                    {
-                        warn!("Number of MQ pairs out of range: {}", queue_pairs);
-                        false
-                    } else {
-                        info!("Number of MQ pairs requested: {}", queue_pairs);
-                        true
-                    }
-                }
-                VIRTIO_NET_CTRL_GUEST_OFFLOADS => {
-                    let features = desc_chain
-                        .memory()
-                        .read_obj::<u64>(data_desc_addr)
-                        .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET {
-                        warn!("Unsupported command: {}", ctrl_hdr.cmd);
-                        false
-                    } else {
-                        let mut ok = true;
-                        for tap in self.taps.iter_mut() {
-                            info!("Reprogramming tap offload with features: {}", features);
-                            tap.set_offload(virtio_features_to_tap_offload(features))
-                                .map_err(|e| {
-                                    error!("Error programming tap offload: {:?}", e);
-                                    ok = false
-                                })
-                                .ok();
-                        }
-                        ok
-                    }
+
+            // The command data can be spread over several descriptors, the
+            // last descriptor of the chain being the device writable status.
+            let mut data = Vec::new();
+            let mut len = ctrl_desc.len();
+            let mut data_too_large = false;
+            let status_desc = loop {
+                let desc = desc_chain.next().ok_or(Error::NoStatusDescriptor)?;
+                len += desc.len();
+                if desc.is_write_only() {
+                    break desc;
                 }
-                _ => {
-                    warn!("Unsupported command {:?}", ctrl_hdr);
-                    false
+
+                if data.len() + desc.len() as usize > MAX_CTRL_DATA_LEN {
+                    data_too_large = true;
+                    continue;
                 }
+                let mut buf = vec![0u8; desc.len() as usize];
+                desc_chain
+                    .memory()
+                    .read_slice(
+                        &mut buf,
+                        desc.addr()
+                            .translate_gva(access_platform, desc.len() as usize),
+                    )
+                    .map_err(Error::GuestMemory)?;
+                data.extend(buf);
+            };
+            if data.is_empty() && !data_too_large {
+                return Err(Error::NoDataDescriptor);
+            }
+
+            let ok = if data_too_large {
+                warn!("Control command data too large: {:?}", ctrl_hdr);
+                false
+            } else {
+                self.process_command(&ctrl_hdr, &data)
             };
 
             desc_chain
@@ -137,7 +129,6 @@ impl CtrlQueue {
                         .translate_gva(access_platform, status_desc.len() as usize),
                 )
                 .map_err(Error::GuestMemory)?;
-            let len = ctrl_desc.len() + data_desc.len() + status_desc.len();
 
             queue
                 .add_used(desc_chain.memory(), desc_chain.head_index(), len)
@@ -153,6 +144,156 @@ impl CtrlQueue {
 
         Ok(())
     }
+
+    fn process_command(&mut self, ctrl_hdr: &ControlHeader, data: &[u8]) -> bool {
+        match u32::from(ctrl_hdr.class) {
+            VIRTIO_NET_CTRL_RX => self.process_rx_mode(ctrl_hdr.cmd, data),
+            VIRTIO_NET_CTRL_MAC => self.process_mac(ctrl_hdr.cmd, data),
+            VIRTIO_NET_CTRL_VLAN => self.process_vlan(ctrl_hdr.cmd, data),
+            VIRTIO_NET_CTRL_MQ => {
+                let queue_pairs = match read_le16(data) {
+                    Some(queue_pairs) => queue_pairs,
+                    None => return false,
+                };
+                if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
+                    warn!("Unsupported command: {}", ctrl_hdr.cmd);
+                    false
+                } else if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
+                    || (queue_pairs > VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX as u16)
+                {
+                    warn!("Number of MQ pairs out of range: {}", queue_pairs);
+                    false
+                } else {
+                    info!("Number of MQ pairs requested: {}", queue_pairs);
+                    true
+                }
+            }
+            VIRTIO_NET_CTRL_GUEST_OFFLOADS => {
+                let features = match data.get(..8) {
+                    Some(bytes) => u64::from_le_bytes(bytes.try_into().unwrap()),
+                    None => return false,
+                };
+                if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET {
+                    warn!("Unsupported command: {}", ctrl_hdr.cmd);
+                    false
+                } else {
+                    let mut ok = true;
+                    for tap in self.taps.iter_mut() {
+                        info!("Reprogramming tap offload with features: {}", features);
+                        tap.set_offload(virtio_features_to_tap_offload(features))
+                            .map_err(|e| {
+                                error!("Error programming tap offload: {:?}", e);
+                                ok = false
+                            })
+                            .ok();
+                    }
+                    ok
+                }
+            }
+            _ => {
+                warn!("Unsupported command {:?}", ctrl_hdr);
+                false
+            }
+        }
+    }
+
+    fn process_rx_mode(&mut self, cmd: u8, data: &[u8]) -> bool {
+        let (rx_filter, enable) = match (self.rx_filter.as_ref(), data.first()) {
+            (Some(rx_filter), Some(enable)) => (rx_filter, *enable != 0),
+            _ => return false,
+        };
+        let mut rx_filter = rx_filter.write().unwrap();
+        match u32::from(cmd) {
+            VIRTIO_NET_CTRL_RX_PROMISC => {
+                info!("Setting promiscuous mode: {}", enable);
+                rx_filter.set_promisc(enable);
+                true
+            }
+            VIRTIO_NET_CTRL_RX_ALLMULTI => {
+                info!("Setting all-multicast mode: {}", enable);
+                rx_filter.set_allmulti(enable);
+                true
+            }
+            _ => {
+                warn!("Unsupported RX mode command: {}", cmd);
+                false
+            }
+        }
+    }
+
+    fn process_mac(&mut self, cmd: u8, data: &[u8]) -> bool {
+        let rx_filter = match self.rx_filter.as_ref() {
+            Some(rx_filter) => rx_filter,
+            None => return false,
+        };
+        match u32::from(cmd) {
+            VIRTIO_NET_CTRL_MAC_TABLE_SET => {
+                // Unicast table followed by the multicast one, each of them
+                // made of a 32 bits entry count and the list of addresses.
+                let (uni_macs, data) = match parse_mac_table(data) {
+                    Some(table) => table,
+                    None => return false,
+                };
+                let (multi_macs, _) = match parse_mac_table(data) {
+                    Some(table) => table,
+                    None => return false,
+                };
+                info!(
+                    "Setting MAC table with {} unicast and {} multicast addresses",
+                    uni_macs.len(),
+                    multi_macs.len()
+                );
+                rx_filter
+                    .write()
+                    .unwrap()
+                    .set_mac_table(&uni_macs, &multi_macs);
+                true
+            }
+            VIRTIO_NET_CTRL_MAC_ADDR_SET if data.len() >= MAC_ADDR_LEN => {
+                let mac = MacAddr::from_bytes_unchecked(&data[..MAC_ADDR_LEN]);
+                info!("Setting MAC address: {}", mac);
+                rx_filter.write().unwrap().set_mac(mac.get_bytes());
+                true
+            }
+            _ => {
+                warn!("Unsupported MAC command: {}", cmd);
+                false
+            }
+        }
+    }
+
+    fn process_vlan(&mut self, cmd: u8, data: &[u8]) -> bool {
+        let (rx_filter, vid) = match (self.rx_filter.as_ref(), read_le16(data)) {
+            (Some(rx_filter), Some(vid)) => (rx_filter, vid),
+            _ => return false,
+        };
+        let enable = match u32::from(cmd) {
+            VIRTIO_NET_CTRL_VLAN_ADD => true,
+            VIRTIO_NET_CTRL_VLAN_DEL => false,
+            _ => {
+                warn!("Unsupported VLAN command: {}", cmd);
+                return false;
+            }
+        };
+        if !rx_filter.write().unwrap().set_vlan(vid, enable) {
+            warn!("VLAN ID out of range: {}", vid);
+            return false;
+        }
+        true
+    }
+}
+
+fn read_le16(data: &[u8]) -> Option<u16> {
+    data.get(..2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+// Parse a MAC table of the VIRTIO_NET_CTRL_MAC_TABLE_SET command, returning
+// the addresses it contains along with the remaining data.
+fn parse_mac_table(data: &[u8]) -> Option<(Vec<&[u8]>, &[u8])> {
+    let entries = u32::from_le_bytes(data.get(..4)?.try_into().unwrap()) as usize;
+    let len = entries.checked_mul(MAC_ADDR_LEN)?;
+    let macs = data.get(4..4usize.checked_add(len)?)?;
+    Some((macs.chunks(MAC_ADDR_LEN).collect(), &data[4 + len..]))
 }
 
 pub fn virtio_features_to_tap_offload(features: u64) -> c_uint {
@@ -12,6 +12,7 @@ mod ctrl_queue;
 mod mac;
 mod open_tap;
 mod queue_pair;
+mod rx_filter;
 mod tap;
 
 use std::io::Error as IoError;
@@ -32,8 +33,9 @@ type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 
 pub use ctrl_queue::{CtrlQueue, Error as CtrlQueueError};
 pub use mac::{MacAddr, MAC_ADDR_LEN};
-pub use open_tap::{open_tap, Error as OpenTapError};
+pub use open_tap::{default_netmask, open_tap, Error as OpenTapError};
 pub use queue_pair::{NetCounters, NetQueuePair, NetQueuePairError, RxVirtio, TxVirtio};
+pub use rx_filter::{RxFilter, MAX_MAC_TABLE_ENTRIES};
 pub use tap::{Error as TapError, Tap};
 
 #[derive(Error, Debug)]
@@ -86,6 +88,17 @@ fn create_inet_socket() -> Result<net::UdpSocket> {
     Ok(unsafe { net::UdpSocket::from_raw_fd(sock) })
 }
 
+fn create_inet6_socket() -> Result<net::UdpSocket> {
+    // SAFETY: we check the return value.
+    let sock = unsafe { libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0) };
+    if sock < 0 {
+        return Err(Error::CreateSocket(IoError::last_os_error()));
+    }
+
+    // SAFETY: nothing else will use or hold onto the raw sock fd.
+    Ok(unsafe { net::UdpSocket::from_raw_fd(sock) })
+}
+
 fn create_unix_socket() -> Result<net::UdpSocket> {
     // SAFETY: we check the return value.
     let sock = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM, 0) };
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
 use super::{vnet_hdr_len, MacAddr, Tap, TapError};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::Path;
 use std::{fs, io};
 use thiserror::Error;
@@ -24,6 +24,8 @@ pub enum Error {
     TapSetIp(TapError),
     #[error("Setting tap netmask failed: {0}")]
     TapSetNetmask(TapError),
+    #[error("Tap IP address and netmask are not of the same family")]
+    TapIpFamilyMismatch,
     #[error("Setting MAC address failed: {0}")]
     TapSetMac(TapError),
     #[error("Getting MAC address failed: {0}")]
@@ -34,6 +36,8 @@ pub enum Error {
     TapSetMtu(TapError),
     #[error("Enabling tap interface failed: {0}")]
     TapEnable(TapError),
+    #[error("Adding tap interface to the bridge failed: {0}")]
+    TapAddToBridge(TapError),
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -58,12 +62,24 @@ fn check_mq_support(if_name: &Option<&str>, queue_pairs: usize) -> Result<()> {
     Ok(())
 }
 
+/// Netmask of the tap interface given an address without one: a /24 subnet
+/// for IPv4 and a /64 one for IPv6.
+pub fn default_netmask(ip_addr: IpAddr) -> IpAddr {
+    match ip_addr {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0xffff, 0xffff, 0xffff, 0xffff, 0, 0, 0, 0)),
+    }
+}
+
 /// Create a new virtio network device with the given IP address and
-/// netmask.
+/// netmask, or enslaved to the given bridge. For IPv6, the netmask is
+/// converted into the prefix length of the address, which defaults to 64.
+#[allow(clippy::too_many_arguments)]
 pub fn open_tap(
     if_name: Option<&str>,
-    ip_addr: Option<Ipv4Addr>,
-    netmask: Option<Ipv4Addr>,
+    ip_addr: Option<IpAddr>,
+    netmask: Option<IpAddr>,
+    bridge: Option<&str>,
     host_mac: &mut Option<MacAddr>,
     mtu: Option<u16>,
     num_rx_q: usize,
@@ -87,11 +103,26 @@ pub fn open_tap(
                 Some(name) => Tap::open_named(name, num_rx_q, flags).map_err(Error::TapOpen)?,
                 None => Tap::new(num_rx_q).map_err(Error::TapOpen)?,
             };
-            if let Some(ip) = ip_addr {
-                tap.set_ip_addr(ip).map_err(Error::TapSetIp)?;
-            }
-            if let Some(mask) = netmask {
-                tap.set_netmask(mask).map_err(Error::TapSetNetmask)?;
+            match (ip_addr, netmask) {
+                (Some(IpAddr::V6(ip)), mask) => {
+                    let prefix_len = match mask {
+                        Some(IpAddr::V6(mask)) => u128::from(mask).leading_ones() as u8,
+                        Some(IpAddr::V4(_)) => return Err(Error::TapIpFamilyMismatch),
+                        None => 64,
+                    };
+                    tap.set_ipv6_addr(ip, prefix_len).map_err(Error::TapSetIp)?;
+                }
+                (Some(IpAddr::V4(_)), Some(IpAddr::V6(_))) => {
+                    return Err(Error::TapIpFamilyMismatch)
+                }
+                (ip, mask) => {
+                    if let Some(IpAddr::V4(ip)) = ip {
+                        tap.set_ip_addr(ip).map_err(Error::TapSetIp)?;
+                    }
+                    if let Some(IpAddr::V4(mask)) = mask {
+                        tap.set_netmask(mask).map_err(Error::TapSetNetmask)?;
+                    }
+                }
             }
             if let Some(mac) = host_mac {
                 tap.set_mac_addr(*mac).map_err(Error::TapSetMac)?
@@ -101,6 +132,9 @@ pub fn open_tap(
             if let Some(mtu) = mtu {
                 tap.set_mtu(mtu as i32).map_err(Error::TapSetMtu)?;
             }
+            if let Some(bridge) = bridge {
+                tap.add_to_bridge(bridge).map_err(Error::TapAddToBridge)?;
+            }
             tap.enable().map_err(Error::TapEnable)?;
 
             tap.set_vnet_hdr_size(vnet_hdr_size)
@@ -3,18 +3,21 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
 use super::{register_listener, unregister_listener, vnet_hdr_len, Tap};
-use crate::GuestMemoryMmap;
+use crate::{GuestMemoryMmap, RxFilter};
 use rate_limiter::{RateLimiter, TokenType};
 use std::io;
 use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use thiserror::Error;
 use virtio_queue::{Queue, QueueOwnedT, QueueT};
 use vm_memory::{Bytes, GuestMemory};
 use vm_virtio::{AccessPlatform, Translatable};
 
+// Ethernet header, followed by the tag control information of VLAN frames.
+const FRAME_HEADER_LEN: usize = 16;
+
 #[derive(Clone)]
 pub struct TxVirtio {
     pub counter_bytes: Wrapping<u64>,
@@ -101,18 +104,21 @@ impl TxVirtio {
                         retry_write = true;
                         break;
                     }
-                    error!("net: tx: failed writing to tap: {}", e);
-                    return Err(NetQueuePairError::WriteTap(e));
-                }
-
-                if (result as usize) < vnet_hdr_len() {
-                    return Err(NetQueuePairError::InvalidVirtioNetHeader);
-                }
+                    // Drop the frame the same way a physical NIC would, and
+                    // hand the descriptor back to the guest so that the TX
+                    // queue doesn't stall on a host side failure.
+                    error!("net: tx: failed writing to tap, dropping frame: {}", e);
+                    0
+                } else {
+                    if (result as usize) < vnet_hdr_len() {
+                        return Err(NetQueuePairError::InvalidVirtioNetHeader);
+                    }
 
-                self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
-                self.counter_frames += Wrapping(1);
+                    self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
+                    self.counter_frames += Wrapping(1);
 
-                result as u32
+                    result as u32
+                }
             } else {
                 0
             };
@@ -167,6 +173,7 @@ impl RxVirtio {
         tap: &Tap,
         queue: &mut Queue,
         rate_limiter: &mut Option<RateLimiter>,
+        rx_filter: Option<&RwLock<RxFilter>>,
         access_platform: Option<&Arc<dyn AccessPlatform>>,
     ) -> Result<bool, NetQueuePairError> {
         let mut exhausted_descs = true;
@@ -248,6 +255,16 @@ impl RxVirtio {
                     return Err(NetQueuePairError::InvalidVirtioNetHeader);
                 }
 
+                if let Some(rx_filter) = rx_filter {
+                    let header = frame_header(&iovecs, result as usize);
+                    if !rx_filter.read().unwrap().accepts(&header) {
+                        // Drop the frame, the same descriptor chain being
+                        // used for the next one.
+                        queue.go_to_previous_position();
+                        continue;
+                    }
+                }
+
                 // Write num_buffers to guest memory. We simply write 1 as we
                 // never spread the frame over more than one descriptor chain.
                 desc_chain
@@ -288,6 +305,26 @@ impl RxVirtio {
     }
 }
 
+// Copy the beginning of the Ethernet frame read into the iovecs, which is
+// enough for the receive filter to look at the MAC and VLAN headers.
+fn frame_header(iovecs: &[libc::iovec], len: usize) -> Vec<u8> {
+    let len = std::cmp::min(len, vnet_hdr_len() + FRAME_HEADER_LEN);
+    let mut header = Vec::with_capacity(len);
+    for iovec in iovecs {
+        if header.len() >= len {
+            break;
+        }
+        let count = std::cmp::min(iovec.iov_len, len - header.len());
+        // SAFETY: the iovecs point to guest memory slices of at least
+        // iov_len bytes.
+        header.extend_from_slice(unsafe {
+            std::slice::from_raw_parts(iovec.iov_base as *const u8, count)
+        });
+    }
+
+    header.split_off(vnet_hdr_len())
+}
+
 #[derive(Default, Clone)]
 pub struct NetCounters {
     pub tx_bytes: Arc<AtomicU64>,
@@ -346,6 +383,7 @@ pub struct NetQueuePair {
     pub rx_desc_avail: bool,
     pub rx_rate_limiter: Option<RateLimiter>,
     pub tx_rate_limiter: Option<RateLimiter>,
+    pub rx_filter: Option<Arc<RwLock<RxFilter>>>,
     pub access_platform: Option<Arc<dyn AccessPlatform>>,
 }
 
@@ -410,6 +448,7 @@ impl NetQueuePair {
             &self.tap,
             queue,
             &mut self.rx_rate_limiter,
+            self.rx_filter.as_deref(),
             self.access_platform.as_ref(),
         )?;
         let rate_limit_reached = self
@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::MAC_ADDR_LEN;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+// Beyond this number of entries, a MAC table is considered overflowed and
+// all the addresses it would filter are accepted.
+pub const MAX_MAC_TABLE_ENTRIES: usize = 64;
+
+const MAX_VLAN: usize = 4096;
+const ETH_HLEN: usize = 14;
+const ETH_P_8021Q: u16 = 0x8100;
+const BROADCAST_ADDR: [u8; MAC_ADDR_LEN] = [0xff; MAC_ADDR_LEN];
+
+/// Receive filter of a virtio-net device, programmed by the guest through
+/// the control queue and applied to the frames read from the TAP interface.
+#[derive(Clone, Debug, Versionize)]
+pub struct RxFilter {
+    default_mac: Option<[u8; MAC_ADDR_LEN]>,
+    default_promisc: bool,
+    mac: Option<[u8; MAC_ADDR_LEN]>,
+    promisc: bool,
+    allmulti: bool,
+    uni_macs: Vec<[u8; MAC_ADDR_LEN]>,
+    uni_overflow: bool,
+    multi_macs: Vec<[u8; MAC_ADDR_LEN]>,
+    multi_overflow: bool,
+    vlan_filtering: bool,
+    vlans: Vec<u64>,
+}
+
+impl RxFilter {
+    pub fn new(mac: Option<&[u8]>, promisc: bool) -> Self {
+        let mac = mac.map(mac_from_slice);
+        RxFilter {
+            default_mac: mac,
+            default_promisc: promisc,
+            mac,
+            promisc,
+            allmulti: false,
+            uni_macs: Vec::new(),
+            uni_overflow: false,
+            multi_macs: Vec::new(),
+            multi_overflow: false,
+            vlan_filtering: false,
+            vlans: vec![0; MAX_VLAN / 64],
+        }
+    }
+
+    /// Restore the filter to its initial state, as expected when the device
+    /// is activated. A driver that can't program the RX mode receives all
+    /// multicast frames, and VLAN filtering only applies if negotiated.
+    pub fn reset(&mut self, ctrl_rx: bool, ctrl_vlan: bool) {
+        self.mac = self.default_mac;
+        self.promisc = self.default_promisc;
+        self.allmulti = !ctrl_rx;
+        self.uni_macs.clear();
+        self.uni_overflow = false;
+        self.multi_macs.clear();
+        self.multi_overflow = false;
+        self.vlan_filtering = ctrl_vlan;
+        self.vlans.iter_mut().for_each(|v| *v = 0);
+    }
+
+    pub fn set_promisc(&mut self, enable: bool) {
+        self.promisc = enable;
+    }
+
+    pub fn set_allmulti(&mut self, enable: bool) {
+        self.allmulti = enable;
+    }
+
+    pub fn set_mac(&mut self, mac: &[u8]) {
+        self.mac = Some(mac_from_slice(mac));
+    }
+
+    pub fn set_mac_table(&mut self, uni_macs: &[&[u8]], multi_macs: &[&[u8]]) {
+        self.uni_overflow = uni_macs.len() > MAX_MAC_TABLE_ENTRIES;
+        self.uni_macs = if self.uni_overflow {
+            Vec::new()
+        } else {
+            uni_macs.iter().map(|m| mac_from_slice(m)).collect()
+        };
+
+        self.multi_overflow = multi_macs.len() > MAX_MAC_TABLE_ENTRIES;
+        self.multi_macs = if self.multi_overflow {
+            Vec::new()
+        } else {
+            multi_macs.iter().map(|m| mac_from_slice(m)).collect()
+        };
+    }
+
+    /// Add or remove a VLAN ID from the filter, returning false if the ID
+    /// is out of range.
+    pub fn set_vlan(&mut self, vid: u16, enable: bool) -> bool {
+        let vid = vid as usize;
+        if vid >= MAX_VLAN {
+            return false;
+        }
+
+        if enable {
+            self.vlans[vid / 64] |= 1 << (vid % 64);
+        } else {
+            self.vlans[vid / 64] &= !(1 << (vid % 64));
+        }
+        true
+    }
+
+    /// Whether the Ethernet frame should be delivered to the guest.
+    pub fn accepts(&self, frame: &[u8]) -> bool {
+        if self.promisc {
+            return true;
+        }
+
+        if frame.len() < ETH_HLEN {
+            return false;
+        }
+
+        if self.vlan_filtering
+            && frame.len() >= ETH_HLEN + 2
+            && u16::from_be_bytes([frame[12], frame[13]]) == ETH_P_8021Q
+        {
+            let vid = (u16::from_be_bytes([frame[14], frame[15]]) & 0xfff) as usize;
+            if self.vlans[vid / 64] & (1 << (vid % 64)) == 0 {
+                return false;
+            }
+        }
+
+        let dest = &frame[..MAC_ADDR_LEN];
+        if dest[0] & 0x1 != 0 {
+            dest == BROADCAST_ADDR
+                || self.allmulti
+                || self.multi_overflow
+                || self.multi_macs.iter().any(|m| m == dest)
+        } else {
+            self.mac.map_or(false, |m| m == dest)
+                || self.uni_overflow
+                || self.uni_macs.iter().any(|m| m == dest)
+        }
+    }
+}
+
+fn mac_from_slice(mac: &[u8]) -> [u8; MAC_ADDR_LEN] {
+    let mut bytes = [0u8; MAC_ADDR_LEN];
+    bytes.copy_from_slice(&mac[..MAC_ADDR_LEN]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc];
+    const OTHER_MAC: [u8; 6] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbd];
+    const MULTICAST_MAC: [u8; 6] = [0x01, 0x00, 0x5e, 0x00, 0x00, 0x01];
+
+    fn frame(dest: &[u8], vid: Option<u16>) -> Vec<u8> {
+        let mut frame = dest.to_vec();
+        frame.extend(OTHER_MAC);
+        if let Some(vid) = vid {
+            frame.extend(ETH_P_8021Q.to_be_bytes());
+            frame.extend(vid.to_be_bytes());
+        }
+        frame.extend(0x0800u16.to_be_bytes());
+        frame.extend([0u8; 46]);
+        frame
+    }
+
+    #[test]
+    fn test_rx_filter_promisc() {
+        let mut filter = RxFilter::new(Some(&MAC), true);
+        filter.reset(true, true);
+        assert!(filter.accepts(&frame(&OTHER_MAC, None)));
+        assert!(filter.accepts(&frame(&MAC, Some(10))));
+
+        filter.set_promisc(false);
+        assert!(!filter.accepts(&frame(&OTHER_MAC, None)));
+        assert!(filter.accepts(&frame(&MAC, None)));
+        assert!(filter.accepts(&frame(&BROADCAST_ADDR, None)));
+
+        filter.reset(true, true);
+        assert!(filter.accepts(&frame(&OTHER_MAC, None)));
+    }
+
+    #[test]
+    fn test_rx_filter_mac() {
+        let mut filter = RxFilter::new(Some(&MAC), false);
+        filter.reset(true, false);
+        assert!(filter.accepts(&frame(&MAC, None)));
+        assert!(!filter.accepts(&frame(&OTHER_MAC, None)));
+        assert!(!filter.accepts(&frame(&MULTICAST_MAC, None)));
+        assert!(!filter.accepts(&MAC));
+
+        filter.set_mac_table(&[&OTHER_MAC], &[&MULTICAST_MAC]);
+        assert!(filter.accepts(&frame(&OTHER_MAC, None)));
+        assert!(filter.accepts(&frame(&MULTICAST_MAC, None)));
+
+        filter.set_mac_table(&[], &[]);
+        filter.set_allmulti(true);
+        assert!(!filter.accepts(&frame(&OTHER_MAC, None)));
+        assert!(filter.accepts(&frame(&MULTICAST_MAC, None)));
+
+        filter.set_mac(&OTHER_MAC);
+        assert!(filter.accepts(&frame(&OTHER_MAC, None)));
+        assert!(!filter.accepts(&frame(&MAC, None)));
+
+        let table = vec![&MAC[..]; MAX_MAC_TABLE_ENTRIES + 1];
+        filter.set_mac_table(&table, &[]);
+        assert!(filter.accepts(&frame(&[0x02, 0, 0, 0, 0, 1], None)));
+
+        // Without the control of the RX mode, all multicast frames are
+        // accepted.
+        filter.reset(false, false);
+        assert!(filter.accepts(&frame(&MULTICAST_MAC, None)));
+    }
+
+    #[test]
+    fn test_rx_filter_vlan() {
+        let mut filter = RxFilter::new(Some(&MAC), false);
+        filter.reset(true, true);
+        assert!(filter.accepts(&frame(&MAC, None)));
+        assert!(!filter.accepts(&frame(&MAC, Some(10))));
+
+        assert!(filter.set_vlan(10, true));
+        assert!(filter.accepts(&frame(&MAC, Some(10))));
+        assert!(!filter.accepts(&frame(&MAC, Some(11))));
+        // Priority bits are not part of the VLAN ID.
+        assert!(filter.accepts(&frame(&MAC, Some(0xe00a))));
+
+        assert!(filter.set_vlan(10, false));
+        assert!(!filter.accepts(&frame(&MAC, Some(10))));
+        assert!(!filter.set_vlan(4096, true));
+
+        filter.reset(true, false);
+        assert!(filter.accepts(&frame(&MAC, Some(11))));
+    }
+}
@@ -6,8 +6,8 @@
 // found in the THIRD-PARTY file.
 
 use super::{
-    create_inet_socket, create_sockaddr, create_unix_socket, vnet_hdr_len, Error as NetUtilError,
-    MacAddr,
+    create_inet6_socket, create_inet_socket, create_sockaddr, create_unix_socket, vnet_hdr_len,
+    Error as NetUtilError, MacAddr,
 };
 use crate::mac::MAC_ADDR_LEN;
 use std::fs::File;
@@ -15,6 +15,7 @@ use std::io::{Error as IoError, Read, Result as IoResult, Write};
 use std::net;
 use std::os::raw::*;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
 use thiserror::Error;
 use vmm_sys_util::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
 
@@ -36,6 +37,8 @@ pub enum Error {
     InvalidIfname,
     #[error("Error parsing MAC data: {0}")]
     MacParsing(IoError),
+    #[error("Bridge {0} does not exist")]
+    BridgeNotFound(String),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -245,6 +248,37 @@ impl Tap {
         unsafe { Self::ioctl_with_ref(&sock, net_gen::sockios::SIOCSIFADDR as c_ulong, &ifreq) }
     }
 
+    /// Set the host-side IPv6 address for the tap interface, along with the
+    /// prefix length of the subnet it belongs to.
+    pub fn set_ipv6_addr(&self, ip_addr: net::Ipv6Addr, prefix_len: u8) -> Result<()> {
+        // Matches the in6_ifreq structure of the kernel.
+        #[repr(C)]
+        struct In6Ifreq {
+            ifr6_addr: [u8; 16],
+            ifr6_prefixlen: u32,
+            ifr6_ifindex: c_int,
+        }
+
+        let sock = create_inet6_socket().map_err(Error::NetUtil)?;
+
+        let mut ifreq = self.get_ifreq();
+
+        // SAFETY: ioctl is safe. Called with a valid sock fd, and we check the return.
+        unsafe {
+            Self::ioctl_with_mut_ref(&sock, net_gen::sockios::SIOCGIFINDEX as c_ulong, &mut ifreq)?
+        };
+
+        let in6_ifreq = In6Ifreq {
+            ifr6_addr: ip_addr.octets(),
+            ifr6_prefixlen: prefix_len as u32,
+            // SAFETY: access a union field
+            ifr6_ifindex: unsafe { ifreq.ifr_ifru.ifru_ivalue },
+        };
+
+        // SAFETY: ioctl is safe. Called with a valid sock fd, and we check the return.
+        unsafe { Self::ioctl_with_ref(&sock, net_gen::sockios::SIOCSIFADDR as c_ulong, &in6_ifreq) }
+    }
+
     /// Set mac addr for tap interface.
     pub fn set_mac_addr(&self, addr: MacAddr) -> Result<()> {
         // Checking if the mac address already matches the desired one
@@ -366,6 +400,35 @@ impl Tap {
         unsafe { Self::ioctl_with_ref(&sock, net_gen::sockios::SIOCSIFFLAGS as c_ulong, &ifreq) }
     }
 
+    /// Enslave the tap interface to an existing bridge.
+    pub fn add_to_bridge(&self, bridge: &str) -> Result<()> {
+        let bridge_name = build_terminated_if_name(bridge)?;
+        if !Path::new(&format!("/sys/class/net/{bridge}/bridge")).exists() {
+            return Err(Error::BridgeNotFound(bridge.to_owned()));
+        }
+
+        let sock = create_unix_socket().map_err(Error::NetUtil)?;
+
+        let mut ifreq = self.get_ifreq();
+        // SAFETY: ioctl is safe. Called with a valid sock fd, and we check the return.
+        unsafe {
+            Self::ioctl_with_mut_ref(&sock, net_gen::sockios::SIOCGIFINDEX as c_ulong, &mut ifreq)?
+        };
+        // SAFETY: access a union field
+        let ifindex = unsafe { ifreq.ifr_ifru.ifru_ivalue };
+
+        let mut ifreq: net_gen::ifreq = Default::default();
+        // SAFETY: access union fields and we're sure the copy is okay.
+        unsafe {
+            let ifrn_name = ifreq.ifr_ifrn.ifrn_name.as_mut();
+            ifrn_name[..bridge_name.len()].copy_from_slice(&bridge_name);
+        }
+        ifreq.ifr_ifru.ifru_ivalue = ifindex;
+
+        // SAFETY: ioctl is safe. Called with a valid sock fd, and we check the return.
+        unsafe { Self::ioctl_with_ref(&sock, net_gen::sockios::SIOCBRADDIF as c_ulong, &ifreq) }
+    }
+
     /// Set the size of the vnet hdr.
     pub fn set_vnet_hdr_size(&self, size: c_int) -> Result<()> {
         // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -391,6 +454,12 @@ impl Tap {
         self.if_name.clone()
     }
 
+    /// File descriptor of the tap queue, to hand it over to the kernel
+    /// vhost-net datapath.
+    pub fn tap_file(&self) -> &File {
+        &self.tap_file
+    }
+
     #[cfg(fuzzing)]
     pub fn new_for_fuzzing(tap_file: File, if_name: Vec<u8>) -> Self {
         Tap { tap_file, if_name }
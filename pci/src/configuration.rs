@@ -10,6 +10,7 @@ use std::sync::{Arc, Mutex};
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_device::PciBarType;
+use vm_memory::ByteValued;
 use vm_migration::{MigratableError, Pausable, Snapshot, Snapshottable, VersionMapped};
 
 // The number of 32bit registers in the config space, 4096 bytes.
@@ -28,7 +29,7 @@ const MSIX_CAPABILITY_REGISTER_MASK: u32 = 0xc000_0000;
 const NUM_BAR_REGS: usize = 6;
 const CAPABILITY_LIST_HEAD_OFFSET: usize = 0x34;
 const FIRST_CAPABILITY_OFFSET: usize = 0x40;
-const CAPABILITY_MAX_OFFSET: usize = 192;
+const CAPABILITY_MAX_OFFSET: usize = 256;
 
 const INTERRUPT_LINE_PIN_REG: usize = 15;
 
@@ -364,6 +365,111 @@ pub trait PciCapability {
     fn id(&self) -> PciCapabilityId;
 }
 
+/// PCI Express device/port types, as reported by the PCI Express capability.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[allow(dead_code)]
+#[repr(u8)]
+pub enum PciExpressDevicePortType {
+    Endpoint = 0x0,
+    LegacyEndpoint = 0x1,
+    RootPort = 0x4,
+    UpstreamPort = 0x5,
+    DownstreamPort = 0x6,
+    PciExpressToPciBridge = 0x7,
+    PciToPciExpressBridge = 0x8,
+    RootComplexIntegratedEndpoint = 0x9,
+    RootComplexEventCollector = 0xa,
+}
+
+const PCI_EXPRESS_CAP_VERSION: u16 = 0x2;
+const PCI_EXPRESS_DEVICE_PORT_TYPE_SHIFT: u16 = 4;
+// Role-Based Error Reporting, mandatory for version 2 of the capability.
+const PCI_EXPRESS_DEV_CAP_RBER: u32 = 1 << 15;
+// Max_Read_Request_Size of 512 bytes, Relaxed Ordering and No Snoop enabled.
+const PCI_EXPRESS_DEV_CTL_DEFAULT: u16 = 0x2810;
+// Link speed of 2.5 GT/s on a x1 link.
+const PCI_EXPRESS_LINK_SPEED_2_5GT: u16 = 0x1;
+const PCI_EXPRESS_LINK_WIDTH_X1: u16 = 0x1;
+const PCI_EXPRESS_LINK_WIDTH_SHIFT: u16 = 4;
+
+/// PCI Express capability structure (version 2), letting a device be
+/// identified as a PCI Express function by the guest, which then gives
+/// access to its extended configuration space.
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Clone, Copy, Default)]
+pub struct PciExpressCap {
+    pub cap: u16,
+    pub dev_cap: u32,
+    pub dev_ctl: u16,
+    pub dev_sta: u16,
+    pub link_cap: u32,
+    pub link_ctl: u16,
+    pub link_sta: u16,
+    pub slot_cap: u32,
+    pub slot_ctl: u16,
+    pub slot_sta: u16,
+    pub root_ctl: u16,
+    pub root_cap: u16,
+    pub root_sta: u32,
+    pub dev_cap2: u32,
+    pub dev_ctl2: u16,
+    pub dev_sta2: u16,
+    pub link_cap2: u32,
+    pub link_ctl2: u16,
+    pub link_sta2: u16,
+    pub slot_cap2: u32,
+    pub slot_ctl2: u16,
+    pub slot_sta2: u16,
+}
+
+// SAFETY: All members are simple numbers and any value is valid.
+unsafe impl ByteValued for PciExpressCap {}
+
+impl PciCapability for PciExpressCap {
+    fn bytes(&self) -> &[u8] {
+        self.as_slice()
+    }
+
+    fn id(&self) -> PciCapabilityId {
+        PciCapabilityId::PciExpress
+    }
+}
+
+impl PciExpressCap {
+    pub fn new(device_port_type: PciExpressDevicePortType) -> Self {
+        let cap = PciExpressCap {
+            cap: PCI_EXPRESS_CAP_VERSION
+                | ((device_port_type as u16) << PCI_EXPRESS_DEVICE_PORT_TYPE_SHIFT),
+            dev_cap: PCI_EXPRESS_DEV_CAP_RBER,
+            dev_ctl: PCI_EXPRESS_DEV_CTL_DEFAULT,
+            ..Default::default()
+        };
+
+        // Functions integrated into the root complex aren't behind a link,
+        // hence their link registers are left reserved.
+        if matches!(
+            device_port_type,
+            PciExpressDevicePortType::RootComplexIntegratedEndpoint
+                | PciExpressDevicePortType::RootComplexEventCollector
+        ) {
+            return cap;
+        }
+
+        let link = PCI_EXPRESS_LINK_SPEED_2_5GT
+            | (PCI_EXPRESS_LINK_WIDTH_X1 << PCI_EXPRESS_LINK_WIDTH_SHIFT);
+
+        PciExpressCap {
+            link_cap: u32::from(link),
+            link_sta: link,
+            // Supported Link Speeds Vector only reports 2.5 GT/s.
+            link_cap2: u32::from(PCI_EXPRESS_LINK_SPEED_2_5GT) << 1,
+            link_ctl2: PCI_EXPRESS_LINK_SPEED_2_5GT,
+            ..cap
+        }
+    }
+}
+
 fn encode_32_bits_bar_size(bar_size: u32) -> Option<u32> {
     if bar_size > 0 {
         return Some(!(bar_size - 1));
@@ -1155,8 +1261,6 @@ impl PciBarConfiguration {
 
 #[cfg(test)]
 mod tests {
-    use vm_memory::ByteValued;
-
     use super::*;
 
     #[repr(packed)]
@@ -1226,6 +1330,44 @@ mod tests {
         assert_eq!((cap2_data >> 24) & 0xFF, 0x55); // cap2.foo
     }
 
+    #[test]
+    fn add_pci_express_capability() {
+        let mut cfg = PciConfiguration::new(
+            0x1234,
+            0x5678,
+            0x1,
+            PciClassCode::MultimediaController,
+            &PciMultimediaSubclass::AudioController,
+            None,
+            PciHeaderType::Device,
+            0xABCD,
+            0x2468,
+            None,
+            None,
+        );
+
+        let cap = PciExpressCap::new(PciExpressDevicePortType::Endpoint);
+        assert_eq!(cap.bytes().len() + 2, 0x3c);
+        let cap_offset = cfg.add_capability(&cap).unwrap();
+
+        // Capability ID, next pointer and version 2 Endpoint.
+        let cap_data = cfg.read_reg(cap_offset / 4);
+        assert_eq!(cap_data & 0xFF, 0x10);
+        assert_eq!((cap_data >> 8) & 0xFF, 0x00);
+        assert_eq!(cap_data >> 16, 0x0002);
+
+        // Link Capabilities report a x1 link at 2.5 GT/s.
+        let link_cap = cfg.read_reg(cap_offset / 4 + 3);
+        assert_eq!(link_cap, 0x11);
+
+        // Version 2 Root Complex Integrated Endpoint, without any link.
+        let cap = PciExpressCap::new(PciExpressDevicePortType::RootComplexIntegratedEndpoint);
+        let cap_offset = cfg.add_capability(&cap).unwrap();
+        let cap_data = cfg.read_reg(cap_offset / 4);
+        assert_eq!(cap_data >> 16, 0x0092);
+        assert_eq!(cfg.read_reg(cap_offset / 4 + 3), 0);
+    }
+
     #[derive(Copy, Clone)]
     enum TestPi {
         Test = 0x5a,
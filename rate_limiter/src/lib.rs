@@ -31,6 +31,16 @@
 //! on top of their `size`. This initial extra credit does not replenish and
 //! can be used for an initial burst of data.
 //!
+//! Each token bucket can also be given a `burst` allowance, letting it
+//! accumulate up to `size + burst` tokens while idle. This allows for short
+//! spikes above the configured rate, while the refill rate still bounds the
+//! long-run average.
+//!
+//! Several rate limiters can share their token buckets through a
+//! `RateLimiterGroup`, bounding the combined rate of all of them. Each limiter
+//! of a group keeps its own timer, and is blocked independently from the
+//! others when it finds the shared budget exhausted.
+//!
 //! The granularity for 'wake up' events when the rate limiter is blocked is
 //! currently hardcoded to `100 milliseconds`.
 //!
@@ -49,7 +59,7 @@ extern crate log;
 use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use vmm_sys_util::timerfd::TimerFd;
 
@@ -99,6 +109,8 @@ pub struct TokenBucket {
     size: u64,
     // Initial burst size (number of free initial tokens, that can be consumed at no cost)
     one_time_burst: u64,
+    // Extra tokens the bucket can accumulate on top of its size while idle.
+    burst: u64,
     // Complete refill time in milliseconds.
     refill_time: u64,
 
@@ -121,6 +133,21 @@ impl TokenBucket {
     ///
     /// If the `size` or the `complete refill time` are zero, then `None` is returned.
     pub fn new(size: u64, one_time_burst: u64, complete_refill_time_ms: u64) -> Option<Self> {
+        Self::new_with_burst(size, one_time_burst, 0, complete_refill_time_ms)
+    }
+
+    /// Creates a `TokenBucket` wrapped in an `Option`, with a `burst` allowance.
+    ///
+    /// On top of the behavior of `new()`, the bucket accumulates up to `burst`
+    /// extra tokens on top of `size` during idle periods, replenishing at the
+    /// same rate. This allows for brief spikes while the long-run average stays
+    /// bound by the refill rate.
+    pub fn new_with_burst(
+        size: u64,
+        one_time_burst: u64,
+        burst: u64,
+        complete_refill_time_ms: u64,
+    ) -> Option<Self> {
         // If either token bucket capacity or refill time is 0, disable limiting.
         if size == 0 || complete_refill_time_ms == 0 {
             return None;
@@ -140,6 +167,7 @@ impl TokenBucket {
         Some(TokenBucket {
             size,
             one_time_burst,
+            burst,
             refill_time: complete_refill_time_ms,
             // Start off full.
             budget: size,
@@ -180,13 +208,13 @@ impl TokenBucket {
         // fraction formula with their greatest-common-factor.
         self.budget += (time_delta * self.processed_capacity) / self.processed_refill_time;
 
-        if self.budget >= self.size {
-            self.budget = self.size;
+        if self.budget >= self.size + self.burst {
+            self.budget = self.size + self.burst;
         }
 
         if tokens > self.budget {
             // This operation requests a bandwidth higher than the bucket size
-            if tokens > self.size {
+            if tokens > self.size + self.burst {
                 error!(
                     "Consumed {} tokens from bucket of size {}",
                     tokens, self.size
@@ -215,7 +243,7 @@ impl TokenBucket {
             self.one_time_burst += tokens;
             return;
         }
-        self.budget = std::cmp::min(self.budget + tokens, self.size);
+        self.budget = std::cmp::min(self.budget + tokens, self.size + self.burst);
     }
 
     /// Returns the capacity of the token bucket.
@@ -228,6 +256,11 @@ impl TokenBucket {
         self.one_time_burst
     }
 
+    /// Returns the extra capacity the bucket can accumulate while idle.
+    pub fn burst(&self) -> u64 {
+        self.burst
+    }
+
     /// Returns the time in milliseconds required to to completely fill the bucket.
     pub fn refill_time_ms(&self) -> u64 {
         self.refill_time
@@ -273,27 +306,52 @@ pub enum BucketUpdate {
 /// implementation. These events are meant to be consumed by the user of this struct.
 /// On each such event, the user must call the `event_handler()` method.
 pub struct RateLimiter {
-    inner: Mutex<RateLimiterInner>,
+    // Token buckets, shared with the other limiters of the same group if any.
+    buckets: Arc<Mutex<TokenBuckets>>,
+    timer_fd: Mutex<TimerFd>,
 
     // Internal flag that quickly determines timer state.
     timer_active: AtomicBool,
 }
 
-struct RateLimiterInner {
+struct TokenBuckets {
     bandwidth: Option<TokenBucket>,
     ops: Option<TokenBucket>,
+}
 
-    timer_fd: TimerFd,
+/// Token buckets shared by a group of rate limiters.
+///
+/// The rate limiters created from the same group consume from the same
+/// bandwidth and ops/s budgets, so that their combined rate stays under the
+/// limits of the group. Cloning the group shares the same buckets.
+#[derive(Clone)]
+pub struct RateLimiterGroup {
+    buckets: Arc<Mutex<TokenBuckets>>,
 }
 
-impl RateLimiterInner {
-    // Arm the timer of the rate limiter with the provided `Duration` (which will fire only once).
-    fn activate_timer(&mut self, dur: Duration, flag: &AtomicBool) {
-        // Panic when failing to arm the timer (same handling in crate TimerFd::set_state())
-        self.timer_fd
-            .reset(dur, None)
-            .expect("Can't arm the timer (unexpected 'timerfd_settime' failure).");
-        flag.store(true, Ordering::Relaxed)
+impl RateLimiterGroup {
+    /// Creates a new group from the token buckets it shares.
+    ///
+    /// A `None` bucket disables the limiting for the respective token type.
+    pub fn new(
+        bytes_token_bucket: Option<TokenBucket>,
+        ops_token_bucket: Option<TokenBucket>,
+    ) -> Self {
+        RateLimiterGroup {
+            buckets: Arc::new(Mutex::new(TokenBuckets {
+                bandwidth: bytes_token_bucket,
+                ops: ops_token_bucket,
+            })),
+        }
+    }
+
+    /// Creates a new Rate Limiter consuming from the buckets of the group.
+    ///
+    /// # Errors
+    ///
+    /// If the timerfd creation fails, an error is returned.
+    pub fn new_rate_limiter(&self) -> io::Result<RateLimiter> {
+        RateLimiter::with_buckets(self.buckets.clone())
     }
 }
 
@@ -339,6 +397,27 @@ impl RateLimiter {
             ops_complete_refill_time_ms,
         );
 
+        Self::from_buckets(bytes_token_bucket, ops_token_bucket)
+    }
+
+    /// Creates a new Rate Limiter from already configured token buckets.
+    ///
+    /// A `None` bucket disables the limiter for the respective token type.
+    ///
+    /// # Errors
+    ///
+    /// If the timerfd creation fails, an error is returned.
+    pub fn from_buckets(
+        bytes_token_bucket: Option<TokenBucket>,
+        ops_token_bucket: Option<TokenBucket>,
+    ) -> io::Result<Self> {
+        Self::with_buckets(Arc::new(Mutex::new(TokenBuckets {
+            bandwidth: bytes_token_bucket,
+            ops: ops_token_bucket,
+        })))
+    }
+
+    fn with_buckets(buckets: Arc<Mutex<TokenBuckets>>) -> io::Result<Self> {
         // We'll need a timer_fd, even if our current config effectively disables rate limiting,
         // because `Self::update_buckets()` might re-enable it later, and we might be
         // seccomp-blocked from creating the timer_fd at that time.
@@ -357,15 +436,23 @@ impl RateLimiter {
         }
 
         Ok(RateLimiter {
-            inner: Mutex::new(RateLimiterInner {
-                bandwidth: bytes_token_bucket,
-                ops: ops_token_bucket,
-                timer_fd,
-            }),
+            buckets,
+            timer_fd: Mutex::new(timer_fd),
             timer_active: AtomicBool::new(false),
         })
     }
 
+    // Arm the timer of the rate limiter with the provided `Duration` (which will fire only once).
+    fn activate_timer(&self, dur: Duration) {
+        // Panic when failing to arm the timer (same handling in crate TimerFd::set_state())
+        self.timer_fd
+            .lock()
+            .unwrap()
+            .reset(dur, None)
+            .expect("Can't arm the timer (unexpected 'timerfd_settime' failure).");
+        self.timer_active.store(true, Ordering::Relaxed)
+    }
+
     /// Attempts to consume tokens and returns whether that is possible.
     ///
     /// If rate limiting is disabled on provided `token_type`, this function will always succeed.
@@ -374,7 +461,7 @@ impl RateLimiter {
         if self.is_blocked() {
             return false;
         }
-        let mut guard = self.inner.lock().unwrap();
+        let mut guard = self.buckets.lock().unwrap();
         // Identify the required token bucket.
         let token_bucket = match token_type {
             TokenType::Bytes => guard.bandwidth.as_mut(),
@@ -389,7 +476,7 @@ impl RateLimiter {
                 // make sure there is only one running timer for this limiter.
                 BucketReduction::Failure => {
                     if !self.is_blocked() {
-                        guard.activate_timer(TIMER_REFILL_DUR, &self.timer_active);
+                        self.activate_timer(TIMER_REFILL_DUR);
                     }
                     false
                 }
@@ -404,10 +491,7 @@ impl RateLimiter {
                     // order to enforce the bandwidth limit we need to prevent
                     // further calls to the rate limiter for
                     // `ratio * refill_time` milliseconds.
-                    guard.activate_timer(
-                        Duration::from_millis((ratio * refill_time as f64) as u64),
-                        &self.timer_active,
-                    );
+                    self.activate_timer(Duration::from_millis((ratio * refill_time as f64) as u64));
                     true
                 }
             }
@@ -423,7 +507,7 @@ impl RateLimiter {
     /// Can be used to *manually* add tokens to a bucket. Useful for reverting a
     /// `consume()` if needed.
     pub fn manual_replenish(&self, tokens: u64, token_type: TokenType) {
-        let mut guard = self.inner.lock().unwrap();
+        let mut guard = self.buckets.lock().unwrap();
         // Identify the required token bucket.
         let token_bucket = match token_type {
             TokenType::Bytes => guard.bandwidth.as_mut(),
@@ -451,11 +535,11 @@ impl RateLimiter {
     ///
     /// If the rate limiter is disabled or is not blocked, an error is returned.
     pub fn event_handler(&self) -> Result<(), Error> {
-        let mut guard = self.inner.lock().unwrap();
+        let mut timer_fd = self.timer_fd.lock().unwrap();
         loop {
             // Note: As we manually added the `O_NONBLOCK` flag to the FD, the following
             // `timer_fd::wait()` won't block (which is different from its default behavior.)
-            match guard.timer_fd.wait() {
+            match timer_fd.wait() {
                 Err(e) => {
                     let err: std::io::Error = e.into();
                     match err.kind() {
@@ -477,9 +561,11 @@ impl RateLimiter {
     }
 
     /// Updates the parameters of the token buckets associated with this RateLimiter.
+    ///
+    /// The update applies to all the rate limiters of the same group.
     // TODO: Please note that, right now, the buckets become full after being updated.
     pub fn update_buckets(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
-        let mut guard = self.inner.lock().unwrap();
+        let mut guard = self.buckets.lock().unwrap();
         match bytes {
             BucketUpdate::Disabled => guard.bandwidth = None,
             BucketUpdate::Update(tb) => guard.bandwidth = Some(tb),
@@ -501,8 +587,7 @@ impl AsRawFd for RateLimiter {
     /// Will return a negative value if rate limiting is disabled on both
     /// token types.
     fn as_raw_fd(&self) -> RawFd {
-        let guard = self.inner.lock().unwrap();
-        guard.timer_fd.as_raw_fd()
+        self.timer_fd.lock().unwrap().as_raw_fd()
     }
 }
 
@@ -544,6 +629,7 @@ pub(crate) mod tests {
         pub fn partial_eq(&self, other: &TokenBucket) -> bool {
             (other.capacity() == self.capacity())
                 && (other.one_time_burst() == self.one_time_burst())
+                && (other.burst() == self.burst())
                 && (other.refill_time_ms() == self.refill_time_ms())
                 && (other.budget() == self.budget())
         }
@@ -551,27 +637,27 @@ pub(crate) mod tests {
 
     impl RateLimiter {
         pub fn bandwidth(&self) -> Option<TokenBucket> {
-            let guard = self.inner.lock().unwrap();
+            let guard = self.buckets.lock().unwrap();
             guard.bandwidth.clone()
         }
 
         pub fn ops(&self) -> Option<TokenBucket> {
-            let guard = self.inner.lock().unwrap();
+            let guard = self.buckets.lock().unwrap();
             guard.ops.clone()
         }
     }
 
     impl PartialEq for RateLimiter {
         fn eq(&self, other: &RateLimiter) -> bool {
-            let self_guard = self.inner.lock().unwrap();
-            let other_guard = other.inner.lock().unwrap();
+            let self_guard = self.buckets.lock().unwrap();
+            let other_guard = other.buckets.lock().unwrap();
             self_guard.bandwidth == other_guard.bandwidth && self_guard.ops == other_guard.ops
         }
     }
 
     impl fmt::Debug for RateLimiter {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            let guard = self.inner.lock().unwrap();
+            let guard = self.buckets.lock().unwrap();
             write!(
                 f,
                 "RateLimiter {{ bandwidth: {:?}, ops: {:?} }}",
@@ -741,6 +827,41 @@ pub(crate) mod tests {
         assert!(l.consume(100, TokenType::Bytes));
     }
 
+    #[test]
+    fn test_rate_limiter_group() {
+        // group with a combined limit of 1000 bytes/s
+        let group = RateLimiterGroup::new(TokenBucket::new(1000, 0, 1000), None);
+        let l1 = group.new_rate_limiter().unwrap();
+        let l2 = group.new_rate_limiter().unwrap();
+
+        // each limiter has its own timer
+        assert_ne!(l1.as_raw_fd(), l2.as_raw_fd());
+
+        // the budget of the group is shared between both limiters
+        assert!(l1.consume(600, TokenType::Bytes));
+        assert!(!l2.consume(600, TokenType::Bytes));
+        // only the limiter which failed to consume is blocked
+        assert!(l2.is_blocked());
+        assert!(!l1.is_blocked());
+        assert!(!l1.consume(600, TokenType::Bytes));
+        assert!(l1.is_blocked());
+
+        // wait for the timer period
+        thread::sleep(Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+        assert!(l1.event_handler().is_ok());
+        assert!(l2.event_handler().is_ok());
+        assert!(!l1.is_blocked());
+        assert!(!l2.is_blocked());
+        // 100 bytes have been replenished meanwhile
+        assert!(l2.consume(500, TokenType::Bytes));
+        assert!(!l1.consume(100, TokenType::Bytes));
+
+        // updating the buckets of a limiter updates the whole group
+        let mut l3 = group.new_rate_limiter().unwrap();
+        l3.update_buckets(BucketUpdate::Disabled, BucketUpdate::None);
+        assert_eq!(l2.bandwidth(), None);
+    }
+
     #[test]
     fn test_rate_limiter_ops() {
         // rate limiter with limit of 1000 ops/s
@@ -867,6 +988,32 @@ pub(crate) mod tests {
         assert!(l.consume(100, TokenType::Bytes));
     }
 
+    #[test]
+    fn test_token_bucket_burst() {
+        // token bucket with capacity 1000, refilled at 1 token/ms, which can
+        // accumulate 500 extra tokens while idle.
+        let mut tb = TokenBucket::new_with_burst(1000, 0, 500, 1000).unwrap();
+        assert_eq!(tb.burst(), 500);
+        assert_eq!(tb.budget(), 1000);
+
+        // the burst allowance is only accumulated over time
+        assert_eq!(tb.reduce(1200), BucketReduction::Failure);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(tb.reduce(1), BucketReduction::Success);
+        assert_eq!(tb.budget(), 1499);
+        assert_eq!(tb.reduce(1499), BucketReduction::Success);
+        assert_eq!(tb.reduce(100), BucketReduction::Failure);
+
+        // the budget never goes above size + burst
+        tb.replenish(2000);
+        assert_eq!(tb.budget(), 1500);
+        assert_eq!(tb.reduce(1800), BucketReduction::OverConsumption(0.3));
+
+        let l = RateLimiter::from_buckets(Some(tb), None).unwrap();
+        assert_eq!(l.bandwidth().unwrap().burst(), 500);
+        assert!(l.ops().is_none());
+    }
+
     #[test]
     fn test_update_buckets() {
         let mut x = RateLimiter::new(1000, 2000, 1000, 10, 20, 1000).unwrap();
@@ -886,7 +1033,7 @@ pub(crate) mod tests {
         );
 
         {
-            let mut guard = x.inner.lock().unwrap();
+            let mut guard = x.buckets.lock().unwrap();
             // We have manually adjust the last_update field, because it changes when update_buckets()
             // constructs new buckets (and thus gets a different value for last_update). We do this so
             // it makes sense to test the following assertions.
@@ -3,6 +3,12 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+mod rotating_file;
+mod timestamp_writer;
+
+pub use rotating_file::RotatingFile;
+pub use timestamp_writer::TimestampWriter;
+
 use std::{
     collections::VecDeque,
     io::Write,
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+struct RotatingFileInner {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+impl RotatingFileInner {
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    // Shift the previous files by one, dropping the oldest one, before
+    // moving the current file out of the way. The current file is only
+    // renamed, so that everything written to it so far is preserved.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep > 0 {
+            for index in (1..self.keep).rev() {
+                match fs::rename(self.rotated_path(index), self.rotated_path(index + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+// Output file rotated once it would grow beyond a maximum size, keeping up
+// to `keep` previous files named after the original one, with a `.1`, `.2`,
+// ... suffix from the most recent to the oldest. Clones share the same file
+// so that rotation is coordinated across all of them.
+#[derive(Clone)]
+pub struct RotatingFile {
+    inner: Arc<Mutex<RotatingFileInner>>,
+}
+
+impl RotatingFile {
+    pub fn create(path: &Path, max_size: u64, keep: u32) -> io::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Mutex::new(RotatingFileInner {
+                path: path.to_path_buf(),
+                file: File::create(path)?,
+                size: 0,
+                max_size,
+                keep,
+            })),
+        })
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        // Rotation only happens between two writes, and never leaves an empty
+        // file behind, even if a single write exceeds the maximum size.
+        if inner.size > 0 && inner.size + buf.len() as u64 > inner.max_size {
+            inner.rotate()?;
+        }
+
+        let written = inner.file.write(buf)?;
+        inner.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().unwrap().file.flush()
+    }
+}
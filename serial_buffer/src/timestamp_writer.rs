@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Lines longer than this are written out in several chunks, each one with
+// its own timestamp, rather than growing the buffer without limit.
+const MAX_LINE_SIZE: usize = 4096;
+
+// Formats the time elapsed since the Unix epoch as an ISO-8601 UTC date and
+// time with a millisecond precision, e.g. "2023-06-15T08:30:00.250Z".
+fn format_timestamp(since_epoch: u64, millis: u32) -> String {
+    let days = (since_epoch / 86400) as i64;
+    let seconds = since_epoch % 86400;
+
+    // Conversion of the number of days into a date of the proleptic
+    // Gregorian calendar, counting in eras of 400 years from 0000-03-01.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis
+    )
+}
+
+fn now() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format_timestamp(now.as_secs(), now.subsec_millis())
+}
+
+struct TimestampWriterInner {
+    out: Box<dyn Write + Send>,
+    line: Vec<u8>,
+    timestamp: String,
+}
+
+impl TimestampWriterInner {
+    fn write_line(&mut self) -> io::Result<()> {
+        self.out.write_all(self.timestamp.as_bytes())?;
+        self.out.write_all(b" ")?;
+        self.out.write_all(&self.line)?;
+        self.line.clear();
+
+        Ok(())
+    }
+}
+
+impl Drop for TimestampWriterInner {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.write_line().and_then(|_| self.out.flush());
+        }
+    }
+}
+
+// Output prefixing each line with the host time at which its first byte was
+// received. Lines are buffered until complete, so that the output of a line
+// is never split by the timestamp of the next one. Clones share the same
+// output and pending line.
+#[derive(Clone)]
+pub struct TimestampWriter {
+    inner: Arc<Mutex<TimestampWriterInner>>,
+}
+
+impl TimestampWriter {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(TimestampWriterInner {
+                out,
+                line: Vec::new(),
+                timestamp: String::new(),
+            })),
+        }
+    }
+}
+
+impl Write for TimestampWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        for &byte in buf {
+            if inner.line.is_empty() {
+                inner.timestamp = now();
+            }
+            inner.line.push(byte);
+            if byte == b'\n' || inner.line.len() >= MAX_LINE_SIZE {
+                inner.write_line()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    // The pending line is only written once complete, flushing only applies
+    // to the lines already written.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().unwrap().out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_timestamp(951_782_400, 5), "2000-02-29T00:00:00.005Z");
+        assert_eq!(
+            format_timestamp(1_686_817_800, 250),
+            "2023-06-15T08:30:00.250Z"
+        );
+        assert_eq!(
+            format_timestamp(4_102_444_799, 999),
+            "2099-12-31T23:59:59.999Z"
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_timestamp_writer() {
+        let buffer = SharedBuffer::default();
+        let mut writer = TimestampWriter::new(Box::new(buffer.clone()));
+
+        writer.write_all(b"first line\nsecond").unwrap();
+        writer.flush().unwrap();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("Z first line"));
+
+        writer.write_all(b" line\n").unwrap();
+        drop(writer);
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with("Z second line"));
+        assert_eq!(lines[1].len(), "2023-06-15T08:30:00.250Z second line".len());
+    }
+}
@@ -37,6 +37,7 @@ enum Error {
     Restore(vmm::config::Error),
     ReadingStdin(std::io::Error),
     ReadingFile(std::io::Error),
+    InvalidConfigFile(vmm::config::Error),
 }
 
 impl fmt::Display for Error {
@@ -59,6 +60,7 @@ impl fmt::Display for Error {
             Restore(e) => write!(f, "Error parsing restore syntax: {e}"),
             ReadingStdin(e) => write!(f, "Error reading from stdin: {e}"),
             ReadingFile(e) => write!(f, "Error reading from file: {e}"),
+            InvalidConfigFile(e) => e.fmt(f),
         }
     }
 }
@@ -83,23 +85,30 @@ trait DBusApi1 {
     fn vm_add_vdpa(&self, vdpa_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_vsock(&self, vsock_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_boot(&self) -> zbus::Result<()>;
+    fn vm_config(&self) -> zbus::Result<Optional<String>>;
     fn vm_coredump(&self, vm_coredump_data: &str) -> zbus::Result<()>;
     fn vm_counters(&self) -> zbus::Result<Optional<String>>;
     fn vm_create(&self, vm_config: &str) -> zbus::Result<()>;
     fn vm_delete(&self) -> zbus::Result<()>;
     fn vm_info(&self) -> zbus::Result<String>;
+    fn vm_nmi(&self, vm_nmi_data: &str) -> zbus::Result<()>;
     fn vm_pause(&self) -> zbus::Result<()>;
     fn vm_power_button(&self) -> zbus::Result<()>;
     fn vm_reboot(&self) -> zbus::Result<()>;
     fn vm_remove_device(&self, vm_remove_device: &str) -> zbus::Result<()>;
+    fn vm_remove_disk(&self, vm_remove_disk: &str) -> zbus::Result<()>;
+    fn vm_remove_net(&self, vm_remove_net: &str) -> zbus::Result<()>;
     fn vm_resize(&self, vm_resize: &str) -> zbus::Result<()>;
     fn vm_resize_zone(&self, vm_resize_zone: &str) -> zbus::Result<()>;
     fn vm_restore(&self, restore_config: &str) -> zbus::Result<()>;
     fn vm_receive_migration(&self, receive_migration_data: &str) -> zbus::Result<()>;
+    fn vm_migration_status(&self) -> zbus::Result<Optional<String>>;
+    fn vm_migration_abort(&self) -> zbus::Result<()>;
     fn vm_send_migration(&self, receive_migration_data: &str) -> zbus::Result<()>;
     fn vm_resume(&self) -> zbus::Result<()>;
     fn vm_shutdown(&self) -> zbus::Result<()>;
     fn vm_snapshot(&self, vm_snapshot_config: &str) -> zbus::Result<()>;
+    fn vm_topology(&self) -> zbus::Result<Optional<String>>;
 }
 
 #[cfg(feature = "dbus_api")]
@@ -178,6 +187,10 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
             .map_err(Error::DBusApiClient)
     }
 
+    fn api_vm_config(&self) -> ApiResult {
+        self.print_response(self.vm_config())
+    }
+
     fn api_vm_counters(&self) -> ApiResult {
         self.print_response(self.vm_counters())
     }
@@ -196,6 +209,10 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
             .map_err(Error::DBusApiClient)
     }
 
+    fn api_vm_nmi(&self, vm_nmi_data: &str) -> ApiResult {
+        self.vm_nmi(vm_nmi_data).map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_pause(&self) -> ApiResult {
         self.vm_pause().map_err(Error::DBusApiClient)
     }
@@ -213,6 +230,16 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
             .map_err(Error::DBusApiClient)
     }
 
+    fn api_vm_remove_disk(&self, vm_remove_disk: &str) -> ApiResult {
+        self.vm_remove_disk(vm_remove_disk)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_remove_net(&self, vm_remove_net: &str) -> ApiResult {
+        self.vm_remove_net(vm_remove_net)
+            .map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_resize(&self, vm_resize: &str) -> ApiResult {
         self.vm_resize(vm_resize).map_err(Error::DBusApiClient)
     }
@@ -232,6 +259,14 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
             .map_err(Error::DBusApiClient)
     }
 
+    fn api_vm_migration_status(&self) -> ApiResult {
+        self.print_response(self.vm_migration_status())
+    }
+
+    fn api_vm_migration_abort(&self) -> ApiResult {
+        self.vm_migration_abort().map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_send_migration(&self, send_migration_data: &str) -> ApiResult {
         self.vm_send_migration(send_migration_data)
             .map_err(Error::DBusApiClient)
@@ -249,6 +284,10 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
         self.vm_snapshot(vm_snapshot_config)
             .map_err(Error::DBusApiClient)
     }
+
+    fn api_vm_topology(&self) -> ApiResult {
+        self.print_response(self.vm_topology())
+    }
 }
 
 impl<'a> TargetApi<'a> {
@@ -279,6 +318,10 @@ fn rest_api_do_command(toplevel: &TopLevel, socket: &mut UnixStream) -> ApiResul
         SubCommandEnum::PowerButton(_) => {
             simple_api_command(socket, "PUT", "power-button", None).map_err(Error::HttpApiClient)
         }
+        SubCommandEnum::Nmi(ref config) => {
+            let nmi_data = nmi_data(config.cpu);
+            simple_api_command(socket, "PUT", "nmi", Some(&nmi_data)).map_err(Error::HttpApiClient)
+        }
         SubCommandEnum::Reboot(_) => {
             simple_api_command(socket, "PUT", "reboot", None).map_err(Error::HttpApiClient)
         }
@@ -288,9 +331,15 @@ fn rest_api_do_command(toplevel: &TopLevel, socket: &mut UnixStream) -> ApiResul
         SubCommandEnum::Info(_) => {
             simple_api_command(socket, "GET", "info", None).map_err(Error::HttpApiClient)
         }
+        SubCommandEnum::Config(_) => {
+            simple_api_command(socket, "GET", "config", None).map_err(Error::HttpApiClient)
+        }
         SubCommandEnum::Counters(_) => {
             simple_api_command(socket, "GET", "counters", None).map_err(Error::HttpApiClient)
         }
+        SubCommandEnum::Topology(_) => {
+            simple_api_command(socket, "GET", "topology", None).map_err(Error::HttpApiClient)
+        }
         SubCommandEnum::Ping(_) => {
             simple_api_full_command(socket, "GET", "vmm.ping", None).map_err(Error::HttpApiClient)
         }
@@ -316,14 +365,24 @@ fn rest_api_do_command(toplevel: &TopLevel, socket: &mut UnixStream) -> ApiResul
             simple_api_command(socket, "PUT", "remove-device", Some(&remove_device_data))
                 .map_err(Error::HttpApiClient)
         }
+        SubCommandEnum::RemoveDisk(ref config) => {
+            let remove_disk_data = remove_device_config(&config.disk_id);
+            simple_api_command(socket, "PUT", "remove-disk", Some(&remove_disk_data))
+                .map_err(Error::HttpApiClient)
+        }
+        SubCommandEnum::RemoveNet(ref config) => {
+            let remove_net_data = remove_device_config(&config.net_id);
+            simple_api_command(socket, "PUT", "remove-net", Some(&remove_net_data))
+                .map_err(Error::HttpApiClient)
+        }
         SubCommandEnum::AddDisk(ref config) => {
             let disk_config = add_disk_config(&config.disk_config)?;
             simple_api_command(socket, "PUT", "add-disk", Some(&disk_config))
                 .map_err(Error::HttpApiClient)
         }
         SubCommandEnum::AddFs(ref config) => {
-            let fs_config = add_fs_config(&config.fs_config)?;
-            simple_api_command(socket, "PUT", "add-fs", Some(&fs_config))
+            let (fs_config, fds) = add_fs_config(&config.fs_config)?;
+            simple_api_command_with_fds(socket, "PUT", "add-fs", Some(&fs_config), fds)
                 .map_err(Error::HttpApiClient)
         }
         SubCommandEnum::AddPmem(ref config) => {
@@ -352,7 +411,7 @@ fn rest_api_do_command(toplevel: &TopLevel, socket: &mut UnixStream) -> ApiResul
                 .map_err(Error::HttpApiClient)
         }
         SubCommandEnum::Snapshot(ref config) => {
-            let snapshot_config = snapshot_api_config(&config.snapshot_config);
+            let snapshot_config = snapshot_api_config(config);
             simple_api_command(socket, "PUT", "snapshot", Some(&snapshot_config))
                 .map_err(Error::HttpApiClient)
         }
@@ -373,7 +432,10 @@ fn rest_api_do_command(toplevel: &TopLevel, socket: &mut UnixStream) -> ApiResul
                 .map_err(Error::HttpApiClient)
         }
         SubCommandEnum::ReceiveMigration(ref config) => {
-            let receive_migration_data = receive_migration_data(&config.receive_migration_config);
+            let receive_migration_data = receive_migration_data(
+                &config.receive_migration_config,
+                config.receive_migration_background,
+            );
             simple_api_command(
                 socket,
                 "PUT",
@@ -382,6 +444,13 @@ fn rest_api_do_command(toplevel: &TopLevel, socket: &mut UnixStream) -> ApiResul
             )
             .map_err(Error::HttpApiClient)
         }
+        SubCommandEnum::MigrationStatus(_) => {
+            simple_api_command(socket, "GET", "migration-status", None)
+                .map_err(Error::HttpApiClient)
+        }
+        SubCommandEnum::MigrationAbort(_) => {
+            simple_api_command(socket, "PUT", "migration-abort", None).map_err(Error::HttpApiClient)
+        }
         SubCommandEnum::Create(ref config) => {
             let data = create_data(&config.vm_config)?;
             simple_api_command(socket, "PUT", "create", Some(&data)).map_err(Error::HttpApiClient)
@@ -401,10 +470,16 @@ fn dbus_api_do_command(toplevel: &TopLevel, proxy: &DBusApi1ProxyBlocking<'_>) -
         SubCommandEnum::ShutdownVmm(_) => proxy.api_vmm_shutdown(),
         SubCommandEnum::Resume(_) => proxy.api_vm_resume(),
         SubCommandEnum::PowerButton(_) => proxy.api_vm_power_button(),
+        SubCommandEnum::Nmi(ref config) => {
+            let nmi_data = nmi_data(config.cpu);
+            proxy.api_vm_nmi(&nmi_data)
+        }
         SubCommandEnum::Reboot(_) => proxy.api_vm_reboot(),
         SubCommandEnum::Pause(_) => proxy.api_vm_pause(),
         SubCommandEnum::Info(_) => proxy.api_vm_info(),
+        SubCommandEnum::Config(_) => proxy.api_vm_config(),
         SubCommandEnum::Counters(_) => proxy.api_vm_counters(),
+        SubCommandEnum::Topology(_) => proxy.api_vm_topology(),
         SubCommandEnum::Ping(_) => proxy.api_vmm_ping(),
         SubCommandEnum::Shutdown(_) => proxy.api_vm_shutdown(),
         SubCommandEnum::Resize(ref config) => {
@@ -423,12 +498,20 @@ fn dbus_api_do_command(toplevel: &TopLevel, proxy: &DBusApi1ProxyBlocking<'_>) -
             let remove_device_data = remove_device_config(&config.device_config);
             proxy.api_vm_remove_device(&remove_device_data)
         }
+        SubCommandEnum::RemoveDisk(ref config) => {
+            let remove_disk_data = remove_device_config(&config.disk_id);
+            proxy.api_vm_remove_disk(&remove_disk_data)
+        }
+        SubCommandEnum::RemoveNet(ref config) => {
+            let remove_net_data = remove_device_config(&config.net_id);
+            proxy.api_vm_remove_net(&remove_net_data)
+        }
         SubCommandEnum::AddDisk(ref config) => {
             let disk_config = add_disk_config(&config.disk_config)?;
             proxy.api_vm_add_disk(&disk_config)
         }
         SubCommandEnum::AddFs(ref config) => {
-            let fs_config = add_fs_config(&config.fs_config)?;
+            let (fs_config, _fds) = add_fs_config(&config.fs_config)?;
             proxy.api_vm_add_fs(&fs_config)
         }
         SubCommandEnum::AddPmem(ref config) => {
@@ -452,7 +535,7 @@ fn dbus_api_do_command(toplevel: &TopLevel, proxy: &DBusApi1ProxyBlocking<'_>) -
             proxy.api_vm_add_vsock(&vsock_config)
         }
         SubCommandEnum::Snapshot(ref config) => {
-            let snapshot_config = snapshot_api_config(&config.snapshot_config);
+            let snapshot_config = snapshot_api_config(config);
             proxy.api_vm_snapshot(&snapshot_config)
         }
         SubCommandEnum::Restore(ref config) => {
@@ -469,9 +552,14 @@ fn dbus_api_do_command(toplevel: &TopLevel, proxy: &DBusApi1ProxyBlocking<'_>) -
             proxy.api_vm_send_migration(&send_migration_data)
         }
         SubCommandEnum::ReceiveMigration(ref config) => {
-            let receive_migration_data = receive_migration_data(&config.receive_migration_config);
+            let receive_migration_data = receive_migration_data(
+                &config.receive_migration_config,
+                config.receive_migration_background,
+            );
             proxy.api_vm_receive_migration(&receive_migration_data)
         }
+        SubCommandEnum::MigrationStatus(_) => proxy.api_vm_migration_status(),
+        SubCommandEnum::MigrationAbort(_) => proxy.api_vm_migration_abort(),
         SubCommandEnum::Create(ref config) => {
             let data = create_data(&config.vm_config)?;
             proxy.api_vm_create(&data)
@@ -483,7 +571,7 @@ fn dbus_api_do_command(toplevel: &TopLevel, proxy: &DBusApi1ProxyBlocking<'_>) -
     }
 }
 fn resize_config(
-    desired_vcpus: Option<u8>,
+    desired_vcpus: Option<u32>,
     memory: &Option<String>,
     balloon: &Option<String>,
 ) -> Result<String, Error> {
@@ -518,6 +606,12 @@ fn resize_config(
     Ok(serde_json::to_string(&resize).unwrap())
 }
 
+fn nmi_data(cpu_id: Option<u32>) -> String {
+    let nmi_data = vmm::api::VmNmiData { cpu_id };
+
+    serde_json::to_string(&nmi_data).unwrap()
+}
+
 fn resize_zone_config(id: &str, size: &str) -> Result<String, Error> {
     let resize_zone = vmm::api::VmResizeZoneData {
         id: id.to_owned(),
@@ -558,11 +652,15 @@ fn add_disk_config(config: &str) -> Result<String, Error> {
     Ok(disk_config)
 }
 
-fn add_fs_config(config: &str) -> Result<String, Error> {
-    let fs_config = vmm::config::FsConfig::parse(config).map_err(Error::AddFsConfig)?;
+fn add_fs_config(config: &str) -> Result<(String, Vec<i32>), Error> {
+    let mut fs_config = vmm::config::FsConfig::parse(config).map_err(Error::AddFsConfig)?;
+
+    // Similarly to the network case, the connected socket is sent to the
+    // server side process through a control message rather than as a value.
+    let fds = fs_config.fd.take().into_iter().collect();
     let fs_config = serde_json::to_string(&fs_config).unwrap();
 
-    Ok(fs_config)
+    Ok((fs_config, fds))
 }
 
 fn add_pmem_config(config: &str) -> Result<String, Error> {
@@ -599,9 +697,11 @@ fn add_vsock_config(config: &str) -> Result<String, Error> {
     Ok(vsock_config)
 }
 
-fn snapshot_api_config(url: &str) -> String {
+fn snapshot_api_config(config: &SnapshotSubcommand) -> String {
     let snapshot_config = vmm::api::VmSnapshotConfig {
-        destination_url: String::from(url),
+        destination_url: config.snapshot_config.clone(),
+        base_url: config.base.clone(),
+        track_changes: config.track_changes,
     };
 
     serde_json::to_string(&snapshot_config).unwrap()
@@ -622,9 +722,10 @@ fn coredump_config(destination_url: &str) -> String {
     serde_json::to_string(&coredump_config).unwrap()
 }
 
-fn receive_migration_data(url: &str) -> String {
+fn receive_migration_data(url: &str, background: bool) -> String {
     let receive_migration_data = vmm::api::VmReceiveMigrationData {
         receiver_url: url.to_owned(),
+        background,
     };
 
     serde_json::to_string(&receive_migration_data).unwrap()
@@ -647,6 +748,12 @@ fn create_data(path: &str) -> Result<String, Error> {
             .map_err(Error::ReadingStdin)?;
     } else {
         data = std::fs::read_to_string(path).map_err(Error::ReadingFile)?;
+
+        // Point at the location of an error in the file, the VMM not knowing
+        // where the configuration comes from.
+        vmm::config::VmConfig::from_json(&data).map_err(|e| {
+            Error::InvalidConfigFile(vmm::config::Error::ParseConfigFile(path.into(), e))
+        })?;
     }
 
     Ok(data)
@@ -690,11 +797,16 @@ enum SubCommandEnum {
     AddVdpa(AddVdpaSubcommand),
     AddVsock(AddVsockSubcommand),
     RemoveDevice(RemoveDeviceSubcommand),
+    RemoveDisk(RemoveDiskSubcommand),
+    RemoveNet(RemoveNetSubcommand),
     Info(InfoSubcommand),
+    Config(ConfigSubcommand),
     Counters(CountersSubcommand),
+    Topology(TopologySubcommand),
     Pause(PauseSubcommand),
     Reboot(RebootSubcommand),
     PowerButton(PowerButtonSubcommand),
+    Nmi(NmiSubcommand),
     Resume(ResumeSubcommand),
     Boot(BootSubcommand),
     Delete(DeleteSubcommand),
@@ -708,6 +820,8 @@ enum SubCommandEnum {
     Coredump(CoredumpSubcommand),
     SendMigration(SendMigrationSubcommand),
     ReceiveMigration(ReceiveMigrationSubcommand),
+    MigrationStatus(MigrationStatusSubcommand),
+    MigrationAbort(MigrationAbortSubcommand),
     Create(CreateSubcommand),
     Version(VersionSubcommand),
 }
@@ -793,16 +907,44 @@ struct RemoveDeviceSubcommand {
     device_config: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "remove-disk")]
+/// Remove block device
+struct RemoveDiskSubcommand {
+    #[argh(positional)]
+    /// disk id
+    disk_id: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "remove-net")]
+/// Remove network device
+struct RemoveNetSubcommand {
+    #[argh(positional)]
+    /// network device id
+    net_id: String,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "info")]
 /// Information on the VM
 struct InfoSubcommand {}
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "config")]
+/// Configuration of the VM, as accepted by create
+struct ConfigSubcommand {}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "counters")]
 /// Counters from the VM
 struct CountersSubcommand {}
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "topology")]
+/// Resolved device, memory and vCPU topology of the VM
+struct TopologySubcommand {}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "pause")]
 /// Pause the VM
@@ -818,6 +960,15 @@ struct RebootSubcommand {}
 /// Trigger a power button in the VM
 struct PowerButtonSubcommand {}
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "nmi")]
+/// Inject an NMI into the VM
+struct NmiSubcommand {
+    #[argh(option, long = "cpu")]
+    /// vCPU to send the NMI to, all vCPUs if not specified
+    cpu: Option<u32>,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "resume")]
 /// Resume the VM
@@ -854,7 +1005,7 @@ struct ShutdownVmmSubcommand {}
 struct ResizeSubcommand {
     #[argh(option, long = "cpus")]
     /// new VCPUs count
-    cpus: Option<u8>,
+    cpus: Option<u32>,
 
     #[argh(option, long = "memory")]
     /// new memory size in bytes (supports K/M/G suffix)"
@@ -885,6 +1036,14 @@ struct SnapshotSubcommand {
     #[argh(positional)]
     /// destination_url
     snapshot_config: String,
+
+    #[argh(option, long = "base")]
+    /// only save the changes since the snapshot at this URL
+    base: Option<String>,
+
+    #[argh(switch, long = "track-changes")]
+    /// track the changes from now on, for later snapshots to use this one as base
+    track_changes: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -922,11 +1081,25 @@ struct SendMigrationSubcommand {
 #[argh(subcommand, name = "receive-migration")]
 /// Receive a VM migration
 struct ReceiveMigrationSubcommand {
+    #[argh(switch, long = "background")]
+    /// return once listening for the source, see migration-status
+    receive_migration_background: bool,
+
     #[argh(positional)]
     /// receiver url
     receive_migration_config: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "migration-status")]
+/// Progress of the VM migration being received
+struct MigrationStatusSubcommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "migration-abort")]
+/// Abort the VM migration being received
+struct MigrationAbortSubcommand {}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "create")]
 /// Create a VM from a JSON configuration
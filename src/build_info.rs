@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Build information printed by `--version --json`, so that the version and
+//! the capabilities of the binaries deployed on a fleet of hosts can be
+//! inventoried.
+
+use serde_json::{json, Value};
+
+// Cargo features of the binary, with whether they are enabled. Every feature
+// declared in Cargo.toml must be listed, which is checked by the tests.
+const FEATURES: &[(&str, bool)] = &[
+    ("dbus_api", cfg!(feature = "dbus_api")),
+    ("dhat-heap", cfg!(feature = "dhat-heap")),
+    ("guest_debug", cfg!(feature = "guest_debug")),
+    ("https", cfg!(feature = "https")),
+    ("io_uring", cfg!(feature = "io_uring")),
+    ("kvm", cfg!(feature = "kvm")),
+    ("mshv", cfg!(feature = "mshv")),
+    ("sev", cfg!(feature = "sev")),
+    ("sev_snp", cfg!(feature = "sev_snp")),
+    ("tdx", cfg!(feature = "tdx")),
+    ("tracing", cfg!(feature = "tracing")),
+];
+
+// Devices which can be added to a VM, with whether they are supported by the
+// target architecture and the enabled features.
+const DEVICES: &[(&str, bool)] = &[
+    ("serial", true),
+    ("virtio-balloon", true),
+    ("virtio-block", true),
+    ("virtio-console", true),
+    ("virtio-iommu", true),
+    ("virtio-mem", true),
+    ("virtio-net", true),
+    ("virtio-pmem", true),
+    ("virtio-rng", true),
+    ("virtio-scsi", true),
+    ("virtio-vsock", true),
+    ("virtio-watchdog", true),
+    ("vhost-net", true),
+    ("vhost-user-blk", true),
+    ("vhost-user-fs", true),
+    ("vhost-user-net", true),
+    ("vdpa", true),
+    ("vfio", true),
+    ("vfio-user", true),
+    ("pvpanic", true),
+    ("tpm", true),
+    ("sgx-epc", cfg!(target_arch = "x86_64")),
+];
+
+fn enabled(entries: &[(&'static str, bool)]) -> Vec<&'static str> {
+    entries
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+pub fn build_info() -> Value {
+    json!({
+        "name": env!("CARGO_BIN_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "build_version": env!("BUILD_VERSION").trim(),
+        "git_commit": env!("GIT_COMMIT"),
+        "target_arch": std::env::consts::ARCH,
+        "features": enabled(FEATURES),
+        "devices": enabled(DEVICES),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FEATURES;
+
+    #[test]
+    fn test_features_match_manifest() {
+        let manifest =
+            std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        let features = manifest
+            .lines()
+            .skip_while(|line| line.trim() != "[features]")
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(name, _)| name.trim())
+            .filter(|name| !name.is_empty() && *name != "default")
+            .collect::<Vec<_>>();
+
+        assert!(!features.is_empty());
+        for feature in features {
+            assert!(
+                FEATURES.iter().any(|(name, _)| *name == feature),
+                "Feature {feature} is missing from the build information"
+            );
+        }
+    }
+}
@@ -17,6 +17,7 @@ use std::fs::File;
 use std::os::unix::io::{FromRawFd, RawFd};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use thiserror::Error;
 #[cfg(feature = "dbus_api")]
 use vmm::api::dbus::{dbus_api_graceful_shutdown, DBusApiOptions};
@@ -24,6 +25,8 @@ use vmm::config;
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::block_signal;
 
+mod build_info;
+
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
@@ -43,6 +46,12 @@ enum Error {
     StartVmmThread(#[source] vmm::Error),
     #[error("Error parsing config: {0}")]
     ParsingConfig(vmm::config::Error),
+    #[error("Error validating config: {0}")]
+    ValidatingConfig(vmm::config::ValidationError),
+    #[error("Error computing dry-run report: {0}")]
+    DryRun(#[source] vmm::dry_run::DryRunError),
+    #[error("Error serializing dry-run report: {0}")]
+    DryRunReport(#[source] serde_json::Error),
     #[error("Error creating VM: {0:?}")]
     VmCreate(vmm::api::ApiError),
     #[error("Error booting VM: {0:?}")]
@@ -148,15 +157,15 @@ fn default_rng() -> String {
 /// Launch a cloud-hypervisor VMM.
 pub struct TopLevel {
     #[argh(option, long = "cpus", default = "default_vcpus()")]
-    /// boot=<boot_vcpus>, max=<max_vcpus>, topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>, kvm_hyperv=on|off, max_phys_bits=<maximum_number_of_physical_bits>, affinity=<list_of_vcpus_with_their_associated_cpuset>, features=<list_of_features_to_enable>
+    /// boot=<boot_vcpus>, max=<max_vcpus>, topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>|host, kvm_hyperv=on|off, max_phys_bits=<maximum_number_of_physical_bits>|auto, affinity=<list_of_vcpus_with_their_associated_cpuset>, features=<list_of_features_to_enable>, tsc_freq=<tsc_frequency_hz>, idle=halt|poll, stable_id=on|off, hypervisor_cpuid=on|off, lapic_timer=tsc_deadline|periodic
     cpus: String,
 
     #[argh(option, long = "platform")]
-    /// num_pci_segments=<num_pci_segments>, iommu_segments=<list_of_segments>, serial_number=<dmi_device_serial_number>, uuid=<dmi_device_uuid>, oem_strings=<list_of_strings>
+    /// num_pci_segments=<num_pci_segments>, iommu_segments=<list_of_segments>, iommu_address_width=<bits>, serial_number=<dmi_device_serial_number>, uuid=<dmi_device_uuid>, oem_strings=<list_of_strings>, seed=<identity_seed>, acpi=on|off, transport=pci|mmio, dsdt=<aml_file>, dsdt_append=on|off
     platform: Option<String>,
 
     #[argh(option, long = "memory", default = "default_memory()")]
-    /// size=<guest_memory_size>, mergeable=on|off, shared=on|off, hugepages=on|off, hugepage_size=<hugepage_size>, hotplug_method=acpi|virtio-mem, hotplug_size=<hotpluggable_memory_size>, hotplugged_size=<hotplugged_memory_size>, prefault=on|off, thp=on|off
+    /// size=<guest_memory_size>, file=<backing_file>, mergeable=on|off, shared=on|off, hugepages=on|off, hugepage_size=<hugepage_size>, hotplug_method=acpi|virtio-mem, hotplug_size=<hotpluggable_memory_size>, hotplugged_size=<hotplugged_memory_size>, prefault=on|off, thp=on|off, prealloc_threads=<number_of_threads>, readonly_rom=<rom_address>:<rom_file_path>, numa_bind=<host_node_id>, zero_on_free=on|off, dump_on_oom=<guest_coredump_path>, backend=memfd, max_ram=<guest_memory_limit>
     memory: String,
 
     #[argh(option, long = "memory-zone")]
@@ -172,23 +181,27 @@ pub struct TopLevel {
     kernel: Option<String>,
 
     #[argh(option, long = "initramfs")]
-    /// path to initramfs image
-    initramfs: Option<String>,
+    /// path to initramfs image, repeat to concatenate several segments (e.g. early microcode first)
+    initramfs: Vec<String>,
 
     #[argh(option, long = "cmdline")]
-    /// kernel command line
+    /// kernel command line, or @<path> to read it from a file
     cmdline: Option<String>,
 
     #[argh(option, long = "disk")]
-    /// path=<disk_image_path>, readonly=on|off, direct=on|off, iommu=on|off, num_queues=<number_of_queues>, queue_size=<size_of_each_queue>, vhost_user=on|off, socket=<vhost_user_socket_path>, bw_size=<bytes>, bw_one_time_burst=<bytes>, bw_refill_time=<ms>, ops_size=<io_ops>, ops_one_time_burst=<io_ops>, ops_refill_time=<ms>, id=<device_id>, pci_segment=<segment_id>
+    /// path=<disk_image_path>|@<directory_path>, readonly=on|off, direct=on|off, cache=writeback|writethrough|none, iommu=on|off, num_queues=<number_of_queues>, queue_size=<size_of_each_queue>, vhost_user=on|off, socket=<vhost_user_socket_path>, bw_size=<bytes>, bw_one_time_burst=<bytes>, bw_burst=<bytes>, bw_refill_time=<ms>, ops_size=<io_ops>, ops_one_time_burst=<io_ops>, ops_burst=<io_ops>, ops_refill_time=<ms>, id=<device_id>, pci_segment=<segment_id>, wwn=<world_wide_name>, segment_max=<max_segments_per_request>, size_max=<max_segment_size_in_bytes>, media=disk|cdrom, aio_max_inflight=<max_inflight_io_operations>, overlay=<overlay_file_path>, overlay_discard=on|off, poll_queue=on|off, poll_queue_us=<polling_window_in_us>, sync_on_flush_interval=<ms>, request_timeout=<ms>, locking=flock|none, zoned=on|off, zone_size=<zone_size>, conventional_zones=<number_of_conventional_zones>, probe_alignment=on|off, url=<http_disk_image_url>, cache_mb=<cache_size_in_mib>, throttle_iops_rd=<read_io_ops_per_second>, throttle_iops_wr=<write_io_ops_per_second>, throttle_bps_rd=<read_bytes_per_second>, throttle_bps_wr=<write_bytes_per_second>
     disk: Vec<String>,
 
     #[argh(option, long = "net")]
-    /// tap=<if_name>, ip=<ip_addr>, mask=<net_mask>, mac=<mac_addr>, fd=<fd1,fd2...>, iommu=on|off, num_queues=<number_of_queues>, queue_size=<size_of_each_queue>, id=<device_id>, vhost_user=<vhost_user_enable>, socket=<vhost_user_socket_path>, vhost_mode=client|server, bw_size=<bytes>, bw_one_time_burst=<bytes>, bw_refill_time=<ms>, ops_size=<io_ops>, ops_one_time_burst=<io_ops>, ops_refill_time=<ms>, pci_segment=<segment_id>, offload_tso=on|off, offload_ufo=on|off, offload_csum=on|off
+    /// tap=<if_name>|@bridge:<bridge_name>, ip=<ipv4_or_ipv6_addr>, mask=<net_mask>, mac=<mac_addr>, fd=<fd1,fd2...>, iommu=on|off, num_queues=<number_of_queues>, queue_size=<size_of_each_queue>, rx_queue_size=<size_of_rx_queues>, tx_queue_size=<size_of_tx_queues>, id=<device_id>, vhost_user=<vhost_user_enable>, socket=<vhost_user_socket_path>, vhost_mode=client|server, vdpa=<vdpa_device_path>, mtu=<mtu>, bw_size=<bytes>, bw_one_time_burst=<bytes>, bw_burst=<bytes>, bw_refill_time=<ms>, ops_size=<io_ops>, ops_one_time_burst=<io_ops>, ops_burst=<io_ops>, ops_refill_time=<ms>, pci_segment=<segment_id>, offload_tso=on|off, offload_ufo=on|off, offload_csum=on|off, promisc=on|off, host_name=<guest_visible_label>, rate_limit_group=<group_id>, vhost=on|off
     net: Vec<String>,
 
+    #[argh(option, long = "rate-limit-group")]
+    /// id=<group_id>, bw_size=<bytes>, bw_one_time_burst=<bytes>, bw_burst=<bytes>, bw_refill_time=<ms>, ops_size=<io_ops>, ops_one_time_burst=<io_ops>, ops_burst=<io_ops>, ops_refill_time=<ms>
+    rate_limit_group: Vec<String>,
+
     #[argh(option, long = "rng", default = "default_rng()")]
-    /// src=<entropy_source_path>, iommu=on|off
+    /// src=<entropy_source_path>, iommu=on|off, source=egd:<egd_socket_path>|jitter, queue_size=<size_of_the_queue>
     rng: String,
 
     #[argh(option, long = "balloon")]
@@ -196,19 +209,19 @@ pub struct TopLevel {
     balloon: Option<String>,
 
     #[argh(option, long = "fs")]
-    /// tag=<tag_name>, socket=<socket_path>, num_queues=<number_of_queues>, queue_size=<size_of_each_queue>, id=<device_id>, pci_segment=<segment_id>
+    /// tag=<tag_name>, socket=<socket_path>, fd=<fd>, num_queues=<number_of_queues>, queue_size=<size_of_each_queue>, id=<device_id>, pci_segment=<segment_id>, reconnect=on|off
     fs: Vec<String>,
 
     #[argh(option, long = "pmem")]
-    /// file=<backing_file_path>, size=<persistent_memory_size>, iommu=on|off, discard_writes=on|off, id=<device_id>, pci_segment=<segment_id>
+    /// file=<backing_file_path>, size=<persistent_memory_size>, iommu=on|off, discard_writes=on|off, mem_policy=shared|private, id=<device_id>, pci_segment=<segment_id>
     pmem: Vec<String>,
 
     #[argh(option, long = "serial", default = "String::from(\"null\")")]
-    /// off|null|pty|tty|file=/path/to/a/file
+    /// off|null|pty|tty|file=/path/to/a/file, input=/path/to/a/file, input_delay=<ms>, input_pace=<ms>, rotate=<size>, keep=<n>, flow_control=none|rtscts, timestamps=on|off
     serial: String,
 
     #[argh(option, long = "console", default = "String::from(\"tty\")")]
-    /// off|null|pty|tty|file=/path/to/a/file, iommu=on|off
+    /// off|null|pty|tty|file=/path/to/a/file, iommu=on|off, input=/path/to/a/file, input_delay=<ms>, input_pace=<ms>, resize=on|off, rotate=<size>, keep=<n>, timestamps=on|off
     console: String,
 
     #[argh(option, long = "device")]
@@ -223,6 +236,10 @@ pub struct TopLevel {
     /// path=<device_path>, num_queues=<number_of_queues>, iommu=on|off, id=<device_id>, pci_segment=<segment_id>
     vdpa: Vec<String>,
 
+    #[argh(option, long = "scsi")]
+    /// disks=[<disk_image_path>,...], readonly=on|off, iommu=on|off, queue_size=<size_of_each_queue>, id=<device_id>, pci_segment=<segment_id>
+    scsi: Vec<String>,
+
     #[argh(option, long = "vsock")]
     /// cid=<context_id>, socket=<socket_path>, iommu=on|off, id=<device_id>, pci_segment=<segment_id>
     vsock: Option<String>,
@@ -239,6 +256,11 @@ pub struct TopLevel {
     /// enable virtio-watchdog
     watchdog: bool,
 
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    #[argh(option, long = "watchdog-coredump")]
+    /// path to the guest coredump taken before resetting on watchdog timeout
+    watchdog_coredump: Option<String>,
+
     #[argh(switch, short = 'v')]
     /// set the level of debugging output
     verbosity: u8,
@@ -278,6 +300,10 @@ pub struct TopLevel {
     /// seccomp configuration (true, false or log)
     seccomp: String,
 
+    #[argh(option, long = "shutdown-timeout", default = "30")]
+    /// seconds left to the guest to power off when receiving SIGTERM or SIGINT, before stopping it (0 to stop it right away)
+    shutdown_timeout: u64,
+
     #[argh(option, long = "tpm")]
     /// socket=<path/to/a/socket>
     tpm: Option<String>,
@@ -292,9 +318,17 @@ pub struct TopLevel {
     /// path=<path/to/a/file>
     gdb: Option<String>,
 
+    #[argh(switch, long = "dry-run")]
+    /// validate the configuration and print the resulting VM topology as JSON without starting the VM
+    dry_run: bool,
+
     #[argh(switch, short = 'V', long = "version")]
     /// print version information
     version: bool,
+
+    #[argh(switch, long = "json")]
+    /// print the version information as JSON, along with the enabled features and the supported devices (requires --version)
+    json: bool,
 }
 
 impl TopLevel {
@@ -310,7 +344,11 @@ impl TopLevel {
         let serial = &self.serial;
         let firmware = self.firmware.as_deref();
         let kernel = self.kernel.as_deref();
-        let initramfs = self.initramfs.as_deref();
+        let initramfs = if !self.initramfs.is_empty() {
+            Some(self.initramfs.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
         let cmdline = self.cmdline.as_deref();
 
         let disks = if !self.disk.is_empty() {
@@ -325,6 +363,12 @@ impl TopLevel {
             None
         };
 
+        let rate_limit_groups = if !self.rate_limit_group.is_empty() {
+            Some(self.rate_limit_group.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+
         let console = &self.console;
         let balloon = self.balloon.as_deref();
         let fs = if !self.fs.is_empty() {
@@ -353,6 +397,11 @@ impl TopLevel {
         } else {
             None
         };
+        let scsi = if !self.scsi.is_empty() {
+            Some(self.scsi.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
 
         let vsock = self.vsock.as_deref();
 
@@ -371,6 +420,8 @@ impl TopLevel {
             None
         };
         let watchdog = self.watchdog;
+        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+        let watchdog_coredump = self.watchdog_coredump.as_deref();
         let platform = self.platform.as_deref();
         #[cfg(feature = "guest_debug")]
         let gdb = self.gdb.is_some();
@@ -386,6 +437,7 @@ impl TopLevel {
             cmdline,
             disks,
             net,
+            rate_limit_groups,
             rng,
             balloon,
             fs,
@@ -395,12 +447,15 @@ impl TopLevel {
             devices,
             user_devices,
             vdpa,
+            scsi,
             vsock,
             pvpanic,
             #[cfg(target_arch = "x86_64")]
             sgx_epc,
             numa,
             watchdog,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            watchdog_coredump,
             #[cfg(feature = "guest_debug")]
             gdb,
             platform,
@@ -606,6 +661,7 @@ fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
         #[cfg(feature = "guest_debug")]
         vm_debug_evt.try_clone().unwrap(),
         exit_evt.try_clone().unwrap(),
+        Duration::from_secs(toplevel.shutdown_timeout),
         &seccomp_action,
         hypervisor,
     )
@@ -661,9 +717,26 @@ fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
     r.map(|_| api_socket_path)
 }
 
+fn dry_run(toplevel: &TopLevel) -> Result<(), Error> {
+    let mut vm_config =
+        config::VmConfig::parse(toplevel.to_vm_params()).map_err(Error::ParsingConfig)?;
+    let boot_id_list = vm_config.validate().map_err(Error::ValidatingConfig)?;
+
+    let report =
+        vmm::dry_run::DryRunReport::new(&vm_config, &boot_id_list).map_err(Error::DryRun)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(Error::DryRunReport)?
+    );
+
+    Ok(())
+}
+
 fn main() {
     #[cfg(all(feature = "tdx", feature = "sev_snp"))]
     compile_error!("Feature 'tdx' and 'sev_snp' are mutually exclusive.");
+    #[cfg(all(feature = "sev", any(feature = "tdx", feature = "sev_snp")))]
+    compile_error!("Feature 'sev' is mutually exclusive with 'tdx' and 'sev_snp'.");
 
     #[cfg(feature = "dhat-heap")]
     let _profiler = dhat::Profiler::new_heap();
@@ -674,11 +747,39 @@ fn main() {
 
     let toplevel: TopLevel = argh::from_env();
 
+    // argh can't express dependencies between options, hence reject --json
+    // on its own the way argh reports invalid arguments.
+    if toplevel.json && !toplevel.version {
+        eprintln!(
+            "The --json option requires --version.\nRun {} --help for more information.",
+            env!("CARGO_BIN_NAME")
+        );
+        std::process::exit(1);
+    }
+
     if toplevel.version {
-        println!("{} {}", env!("CARGO_BIN_NAME"), env!("BUILD_VERSION"));
+        if toplevel.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&build_info::build_info()).unwrap()
+            );
+        } else {
+            println!("{} {}", env!("CARGO_BIN_NAME"), env!("BUILD_VERSION"));
+        }
         return;
     }
 
+    if toplevel.dry_run {
+        let exit_code = match dry_run(&toplevel) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        std::process::exit(exit_code);
+    }
+
     let exit_code = match start_vmm(toplevel) {
         Ok(path) => {
             path.map(|s| std::fs::remove_file(s).ok());
@@ -702,8 +803,8 @@ mod unit_tests {
     use crate::TopLevel;
     use std::path::PathBuf;
     use vmm::config::{
-        ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpusConfig, MemoryConfig, PayloadConfig,
-        RngConfig, VmConfig,
+        ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpusConfig, FlowControl, MemoryConfig,
+        PayloadConfig, RngConfig, VmConfig,
     };
 
     // Taken from argh
@@ -773,13 +874,20 @@ mod unit_tests {
                 boot_vcpus: 1,
                 max_vcpus: 1,
                 topology: None,
+                host_topology: false,
                 kvm_hyperv: false,
                 max_phys_bits: 46,
+                auto_phys_bits: false,
                 affinity: None,
                 features: CpuFeatures::default(),
+                tsc_freq: None,
+                idle: None,
+                stable_id: None,
+                hypervisor_cpuid: true,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
+                file: None,
                 mergeable: false,
                 hotplug_method: HotplugMethod::Acpi,
                 hotplug_size: None,
@@ -790,6 +898,13 @@ mod unit_tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                prealloc_threads: None,
+                readonly_rom: None,
+                numa_bind: None,
+                zero_on_free: false,
+                dump_on_oom: None,
+                backend: None,
+                max_ram: None,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -797,9 +912,11 @@ mod unit_tests {
             }),
             disks: None,
             net: None,
+            rate_limit_groups: None,
             rng: RngConfig {
                 src: PathBuf::from("/dev/urandom"),
                 iommu: false,
+                source: None,
             },
             balloon: None,
             fs: None,
@@ -808,15 +925,26 @@ mod unit_tests {
                 file: None,
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             },
             console: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             },
             devices: None,
             user_devices: None,
             vdpa: None,
+            scsi: None,
             vsock: None,
             pvpanic: false,
             iommu: false,
@@ -824,6 +952,8 @@ mod unit_tests {
             sgx_epc: None,
             numa: None,
             watchdog: false,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            watchdog_coredump: None,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             platform: None,
@@ -988,6 +1118,59 @@ mod unit_tests {
         });
     }
 
+    #[test]
+    fn test_valid_vm_config_initramfs() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--initramfs",
+                    "/path/to/initramfs",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel", "initramfs": "/path/to/initramfs"}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--initramfs",
+                    "/path/to/microcode",
+                    "--initramfs",
+                    "/path/to/initramfs",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel", "initramfs": ["/path/to/microcode", "/path/to/initramfs"]}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--initramfs",
+                    "/path/to/initramfs",
+                    "--initramfs",
+                    "/path/to/microcode",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel", "initramfs": ["/path/to/microcode", "/path/to/initramfs"]}
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
     #[test]
     fn test_valid_vm_config_disks() {
         [
@@ -1673,6 +1856,48 @@ mod unit_tests {
         });
     }
 
+    #[test]
+    fn test_valid_vm_config_scsi() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--scsi",
+                    "disks=[/path/to/disk/1,/path/to/disk/2]",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "scsi": [
+                        {"disks": ["/path/to/disk/1", "/path/to/disk/2"]}
+                    ]
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--scsi",
+                    "disks=[/path/to/disk/1],readonly=on",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "scsi": [
+                        {"disks": ["/path/to/disk/1"]}
+                    ]
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
     #[test]
     fn test_valid_vm_config_vsock() {
         [
@@ -1777,4 +2002,23 @@ mod unit_tests {
             compare_vm_config_cli_vs_json(cli, openapi, *equal);
         });
     }
+
+    #[test]
+    fn test_build_info() {
+        let info = crate::build_info::build_info();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            info["features"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|f| *f == "kvm"),
+            cfg!(feature = "kvm")
+        );
+        assert!(info["devices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|d| *d == "virtio-block"));
+    }
 }
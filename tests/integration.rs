@@ -4413,6 +4413,64 @@ mod common_parallel {
         _test_api_create_boot(TargetApi::new_http_api(&guest.tmp_dir), guest)
     }
 
+    #[test]
+    // Add a disk between the creation and the boot of the VM, and check the
+    // guest finds it at first boot rather than as a hotplugged device.
+    fn test_api_http_create_add_disk_boot() {
+        let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
+        let guest = Guest::new(Box::new(focal));
+        let target_api = TargetApi::new_http_api(&guest.tmp_dir);
+
+        let mut child = GuestCommand::new(&guest)
+            .args(target_api.guest_args())
+            .capture_output()
+            .spawn()
+            .unwrap();
+
+        thread::sleep(std::time::Duration::new(1, 0));
+
+        let request_body = guest.api_create_body(
+            1,
+            direct_kernel_boot_path().to_str().unwrap(),
+            DIRECT_KERNEL_BOOT_CMDLINE,
+        );
+        let temp_config_path = guest.tmp_dir.as_path().join("config");
+        std::fs::write(&temp_config_path, request_body).unwrap();
+        assert!(target_api.remote_command("create", temp_config_path.to_str()));
+
+        let blk_file_path = guest.tmp_dir.as_path().join("blk.img");
+        fs::File::create(&blk_file_path)
+            .unwrap()
+            .set_len(16 << 20)
+            .unwrap();
+        assert!(target_api.remote_command(
+            "add-disk",
+            Some(format!("path={},id=test0", blk_file_path.to_str().unwrap()).as_str()),
+        ));
+
+        assert!(target_api.remote_command("boot", None));
+
+        let r = std::panic::catch_unwind(|| {
+            guest.wait_vm_boot(None).unwrap();
+
+            // The disk is part of the initial configuration.
+            assert_eq!(
+                guest
+                    .ssh_command("lsblk | grep vdc | grep -c 16M")
+                    .unwrap()
+                    .trim()
+                    .parse::<u32>()
+                    .unwrap_or_default(),
+                1
+            );
+        });
+
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+
+        handle_child_output(r, &output);
+    }
+
     #[test]
     fn test_virtio_iommu() {
         _test_virtio_iommu(cfg!(target_arch = "x86_64"))
@@ -6257,7 +6315,8 @@ mod common_parallel {
         use std::str::FromStr;
         let taps = net_util::open_tap(
             Some("chtap0"),
-            Some(std::net::Ipv4Addr::from_str(&guest.network.host_ip).unwrap()),
+            Some(std::net::IpAddr::from_str(&guest.network.host_ip).unwrap()),
+            None,
             None,
             &mut None,
             None,
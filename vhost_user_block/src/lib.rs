@@ -144,6 +144,7 @@ impl VhostUserBlkThread {
                             VIRTIO_BLK_S_OK
                         }
                         Err(e) => {
+                            error!("failed to execute request: {:?}", e);
                             len = 1;
                             e.status()
                         }
@@ -15,7 +15,7 @@ use option_parser::Toggle;
 use option_parser::{OptionParser, OptionParserError};
 use std::fmt;
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::process;
@@ -99,6 +99,7 @@ impl VhostUserNetThread {
                 rx_desc_avail: false,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                rx_filter: None,
                 access_platform: None,
             },
         })
@@ -120,9 +121,9 @@ pub struct VhostUserNetBackend {
 impl VhostUserNetBackend {
     #[allow(clippy::too_many_arguments)]
     fn new(
-        ip_addr: Ipv4Addr,
+        ip_addr: IpAddr,
         host_mac: MacAddr,
-        netmask: Ipv4Addr,
+        netmask: IpAddr,
         mtu: Option<u16>,
         num_queues: usize,
         queue_size: u16,
@@ -133,6 +134,7 @@ impl VhostUserNetBackend {
             ifname,
             Some(ip_addr),
             Some(netmask),
+            None,
             &mut Some(host_mac),
             mtu,
             num_queues / 2,
@@ -272,9 +274,9 @@ impl VhostUserBackendMut<VringRwLock<GuestMemoryAtomic<GuestMemoryMmap>>, Atomic
 }
 
 pub struct VhostUserNetBackendConfig {
-    pub ip: Ipv4Addr,
+    pub ip: IpAddr,
     pub host_mac: MacAddr,
-    pub mask: Ipv4Addr,
+    pub mask: IpAddr,
     pub mtu: Option<u16>,
     pub socket: String,
     pub num_queues: usize,
@@ -304,7 +306,7 @@ impl VhostUserNetBackendConfig {
         let ip = parser
             .convert("ip")
             .map_err(Error::FailedConfigParse)?
-            .unwrap_or_else(|| Ipv4Addr::new(192, 168, 100, 1));
+            .unwrap_or_else(|| IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1)));
         let host_mac = parser
             .convert("host_mac")
             .map_err(Error::FailedConfigParse)?
@@ -312,7 +314,7 @@ impl VhostUserNetBackendConfig {
         let mask = parser
             .convert("mask")
             .map_err(Error::FailedConfigParse)?
-            .unwrap_or_else(|| Ipv4Addr::new(255, 255, 255, 0));
+            .unwrap_or_else(|| net_util::default_netmask(ip));
         let mtu = parser.convert("mtu").map_err(Error::FailedConfigParse)?;
         let queue_size = parser
             .convert("queue_size")
@@ -155,6 +155,7 @@ struct BalloonEpollHandler {
     kill_evt: EventFd,
     pause_evt: EventFd,
     pbp: Option<PartiallyBalloonedPage>,
+    zero_on_free: bool,
 }
 
 impl BalloonEpollHandler {
@@ -183,14 +184,34 @@ impl BalloonEpollHandler {
         Ok(())
     }
 
+    // Overwrite the host pages backing the range before they are handed
+    // back, so that nothing the guest stored there outlives the range.
+    fn zero_memory_range(
+        memory: &GuestMemoryMmap,
+        range_base: GuestAddress,
+        range_len: usize,
+    ) -> result::Result<(), Error> {
+        let slice = memory
+            .get_slice(range_base, range_len)
+            .map_err(Error::GuestMemory)?;
+        // SAFETY: the slice was validated against the guest memory mappings
+        // and the range is owned by the balloon at this point.
+        unsafe { std::ptr::write_bytes(slice.as_ptr(), 0, slice.len()) };
+        Ok(())
+    }
+
     fn release_memory_range(
         memory: &GuestMemoryMmap,
         range_base: GuestAddress,
         range_len: usize,
+        zero_on_free: bool,
     ) -> result::Result<(), Error> {
         let region = memory.find_region(range_base).ok_or(Error::GuestMemory(
             GuestMemoryError::InvalidGuestAddress(range_base),
         ))?;
+        if zero_on_free {
+            Self::zero_memory_range(memory, range_base, range_len)?;
+        }
         if let Some(f_off) = region.file_offset() {
             let offset = range_base.0 - region.start_addr().0;
             // SAFETY: FFI call with valid arguments
@@ -215,13 +236,21 @@ impl BalloonEpollHandler {
         pbp: &mut Option<PartiallyBalloonedPage>,
         memory: &GuestMemoryMmap,
         pfn: u32,
+        zero_on_free: bool,
     ) -> result::Result<(), Error> {
         let range_base = GuestAddress((pfn as u64) << VIRTIO_BALLOON_PFN_SHIFT);
         let range_len = 1 << VIRTIO_BALLOON_PFN_SHIFT;
 
         let page_size: u64 = get_page_size();
         if page_size == 1 << VIRTIO_BALLOON_PFN_SHIFT {
-            return Self::release_memory_range(memory, range_base, range_len);
+            return Self::release_memory_range(memory, range_base, range_len, zero_on_free);
+        }
+
+        // The host page is only released once the guest has given up all of
+        // its 4K chunks, which may never happen, so scrub each chunk as it
+        // arrives.
+        if zero_on_free {
+            Self::zero_memory_range(memory, range_base, range_len)?;
         }
 
         if pbp.is_none() {
@@ -240,6 +269,7 @@ impl BalloonEpollHandler {
                 memory,
                 vm_memory::GuestAddress(pbp.as_ref().unwrap().addr),
                 page_size as usize,
+                false,
             )?;
 
             pbp.as_mut().unwrap().reset();
@@ -278,7 +308,12 @@ impl BalloonEpollHandler {
 
                 match queue_index {
                     0 => {
-                        Self::release_memory_range_4k(&mut self.pbp, desc_chain.memory(), pfn)?;
+                        Self::release_memory_range_4k(
+                            &mut self.pbp,
+                            desc_chain.memory(),
+                            pfn,
+                            self.zero_on_free,
+                        )?;
                     }
                     1 => {
                         let page_size = get_page_size() as usize;
@@ -316,7 +351,12 @@ impl BalloonEpollHandler {
             let mut descs_len = 0;
             while let Some(desc) = desc_chain.next() {
                 descs_len += desc.len();
-                Self::release_memory_range(desc_chain.memory(), desc.addr(), desc.len() as usize)?;
+                Self::release_memory_range(
+                    desc_chain.memory(),
+                    desc.addr(),
+                    desc.len() as usize,
+                    self.zero_on_free,
+                )?;
             }
 
             self.queues[queue_index]
@@ -433,15 +473,18 @@ pub struct Balloon {
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
     interrupt_cb: Option<Arc<dyn VirtioInterrupt>>,
+    zero_on_free: bool,
 }
 
 impl Balloon {
     // Create a new virtio-balloon.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         size: u64,
         deflate_on_oom: bool,
         free_page_reporting: bool,
+        zero_on_free: bool,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
         state: Option<BalloonState>,
@@ -493,6 +536,7 @@ impl Balloon {
             seccomp_action,
             exit_evt,
             interrupt_cb: None,
+            zero_on_free,
         })
     }
 
@@ -554,6 +598,10 @@ impl VirtioDevice for Balloon {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
@@ -627,6 +675,7 @@ impl VirtioDevice for Balloon {
             kill_evt,
             pause_evt,
             pbp: None,
+            zero_on_free: self.zero_on_free,
         };
 
         let paused = self.common.paused.clone();
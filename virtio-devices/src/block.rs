@@ -20,20 +20,25 @@ use crate::GuestMemoryMmap;
 use crate::VirtioInterrupt;
 use anyhow::anyhow;
 use block::{
-    async_io::AsyncIo, async_io::AsyncIoError, async_io::DiskFile, build_serial, Request,
-    RequestType, VirtioBlockConfig,
+    async_io::AsyncIo,
+    async_io::DiskFile,
+    build_serial,
+    zoned::{VirtioBlockZonedConfig, ZoneLayout, ZoneTable, VIRTIO_BLK_F_ZONED},
+    DiskTopology, Request, RequestType, VirtioBlockConfig,
 };
 use rate_limiter::{RateLimiter, TokenType};
 use seccompiler::SeccompAction;
 use std::collections::VecDeque;
+use std::fs::File;
 use std::io;
 use std::num::Wrapping;
 use std::ops::Deref;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::PathBuf;
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, convert::TryInto};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -46,6 +51,7 @@ use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::timerfd::TimerFd;
 
 const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = 0x01 << SECTOR_SHIFT;
@@ -56,6 +62,22 @@ const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 const COMPLETION_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 // New 'wake up' event from the rate limiter
 const RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// The periodic background sync is due
+const SYNC_TIMER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// New 'wake up' event from the read rate limiter
+const READ_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
+// New 'wake up' event from the write rate limiter
+const WRITE_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
+// The in-flight requests are due for a timeout check
+const REQUEST_TIMER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 7;
+
+// Number of timeout checks per request timeout period, bounding how late a
+// request is cancelled once it has timed out.
+const REQUEST_TIMER_CHECKS: u32 = 4;
+
+// Maximum time spent waiting for in-flight requests to complete once the
+// queue handler has been asked to stop.
+const DRAIN_TIMEOUT_MS: i32 = 5000;
 
 // latency scale, for reduce precision loss in calculate.
 const LATENCY_SCALE: u64 = 10000;
@@ -70,10 +92,6 @@ pub enum Error {
     RequestCompleting(block::Error),
     #[error("Missing the expected entry in the list of requests")]
     MissingEntryRequestList,
-    #[error("The asynchronous request returned with failure")]
-    AsyncRequestFailure,
-    #[error("Failed synchronizing the file: {0}")]
-    Fsync(AsyncIoError),
     #[error("Failed adding used index: {0}")]
     QueueAddUsed(virtio_queue::Error),
     #[error("Failed creating an iterator over the queue: {0}")]
@@ -130,19 +148,82 @@ struct BlockEpollHandler {
     writeback: Arc<AtomicBool>,
     counters: BlockCounters,
     queue_evt: EventFd,
-    inflight_requests: VecDeque<(u16, Request)>,
+    inflight_requests: VecDeque<(u64, Request)>,
+    max_inflight_requests: usize,
+    inflight_limit_reached: bool,
+    submitted_requests: u64,
+    request_timer: Option<(TimerFd, Duration)>,
+    timed_out_requests: Vec<(u64, Request)>,
+    needs_reset: Arc<AtomicBool>,
     rate_limiter: Option<RateLimiter>,
+    read_rate_limiter: Option<RateLimiter>,
+    write_rate_limiter: Option<RateLimiter>,
     access_platform: Option<Arc<dyn AccessPlatform>>,
     read_only: bool,
+    poll_queue: Option<Duration>,
+    sync_timer: Option<(TimerFd, Duration)>,
+    synced_write_ops: u64,
+    zones: Option<Arc<Mutex<ZoneTable>>>,
+}
+
+// Consume the tokens of a request, the bytes only being accounted for the
+// data transfers. Returns false when the rate limit is reached.
+fn consume_request_tokens(rate_limiter: &mut RateLimiter, bytes: Option<u64>) -> bool {
+    // If limiter.consume() fails it means there is no more TokenType::Ops
+    // budget and rate limiting is in effect.
+    if !rate_limiter.consume(1, TokenType::Ops) {
+        return false;
+    }
+
+    if let Some(bytes) = bytes {
+        // If limiter.consume() fails it means there is no more TokenType::Bytes
+        // budget and rate limiting is in effect.
+        if !rate_limiter.consume(bytes, TokenType::Bytes) {
+            // Revert the OPS consume().
+            rate_limiter.manual_replenish(1, TokenType::Ops);
+            return false;
+        }
+    }
+
+    true
 }
 
 impl BlockEpollHandler {
+    fn rate_limit_reached(&self) -> bool {
+        [
+            &self.rate_limiter,
+            &self.read_rate_limiter,
+            &self.write_rate_limiter,
+        ]
+        .iter()
+        .any(|r| r.as_ref().map_or(false, |r| r.is_blocked()))
+    }
+
     fn process_queue_submit(&mut self) -> Result<bool> {
+        let writeback = self.writeback.load(Ordering::Acquire);
+
+        // The driver has to reset a failed device, nothing gets processed
+        // until then.
+        if self.needs_reset.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+
         let queue = &mut self.queue;
 
         let mut used_descs = false;
 
         while let Some(mut desc_chain) = queue.pop_descriptor_chain(self.mem.memory()) {
+            // Requests which timed out still hold their slot until the
+            // backend completes them.
+            let used_slots = self.inflight_requests.len() + self.timed_out_requests.len();
+            if used_slots >= self.max_inflight_requests {
+                // Hold back the submission until enough in-flight requests
+                // complete, returning this descriptor chain to the avail ring.
+                queue.go_to_previous_position();
+                self.inflight_limit_reached = true;
+                break;
+            }
+
             let mut request = Request::parse(&mut desc_chain, self.access_platform.as_ref())
                 .map_err(Error::RequestParsing)?;
 
@@ -167,64 +248,106 @@ impl BlockEpollHandler {
                 continue;
             }
 
+            // Exercise the bandwidth rate limit only if this request is of
+            // data transfer type.
+            let bytes = if request.request_type == RequestType::In
+                || request.request_type == RequestType::Out
+            {
+                let mut bytes = Wrapping(0);
+                for (_, data_len) in &request.data_descriptors {
+                    bytes += Wrapping(*data_len as u64);
+                }
+                Some(bytes.0)
+            } else {
+                None
+            };
+
             if let Some(rate_limiter) = &mut self.rate_limiter {
-                // If limiter.consume() fails it means there is no more TokenType::Ops
-                // budget and rate limiting is in effect.
-                if !rate_limiter.consume(1, TokenType::Ops) {
+                if !consume_request_tokens(rate_limiter, bytes) {
                     // Stop processing the queue and return this descriptor chain to the
                     // avail ring, for later processing.
                     queue.go_to_previous_position();
                     break;
                 }
-                // Exercise the rate limiter only if this request is of data transfer type.
-                if request.request_type == RequestType::In
-                    || request.request_type == RequestType::Out
-                {
-                    let mut bytes = Wrapping(0);
-                    for (_, data_len) in &request.data_descriptors {
-                        bytes += Wrapping(*data_len as u64);
-                    }
+            }
 
-                    // If limiter.consume() fails it means there is no more TokenType::Bytes
-                    // budget and rate limiting is in effect.
-                    if !rate_limiter.consume(bytes.0, TokenType::Bytes) {
-                        // Revert the OPS consume().
+            // Reads and writes are also subject to their own rate limit.
+            let direction_rate_limiter = match request.request_type {
+                RequestType::In => self.read_rate_limiter.as_mut(),
+                RequestType::Out => self.write_rate_limiter.as_mut(),
+                _ => None,
+            };
+            if let Some(direction_rate_limiter) = direction_rate_limiter {
+                if !consume_request_tokens(direction_rate_limiter, bytes) {
+                    // Revert the consume() from the rate limiter of the device.
+                    if let Some(rate_limiter) = &mut self.rate_limiter {
                         rate_limiter.manual_replenish(1, TokenType::Ops);
-                        // Stop processing the queue and return this descriptor chain to the
-                        // avail ring, for later processing.
-                        queue.go_to_previous_position();
-                        break;
+                        if let Some(bytes) = bytes {
+                            rate_limiter.manual_replenish(bytes, TokenType::Bytes);
+                        }
                     }
-                };
+                    queue.go_to_previous_position();
+                    break;
+                }
             }
 
-            request.set_writeback(self.writeback.load(Ordering::Acquire));
+            request.set_writeback(writeback);
+
+            // The head of a request which timed out can be reused by the
+            // guest before the backend completes it, hence the submission
+            // sequence number tagging each request along with its head.
+            let user_data = (self.submitted_requests << 16) | desc_chain.head_index() as u64;
+            self.submitted_requests = self.submitted_requests.wrapping_add(1);
+
+            // Emulated zoned devices check the writes against the zones and
+            // complete the zone specific requests, which don't involve the
+            // disk image.
+            let zoned = match &self.zones {
+                Some(zones) => zones
+                    .lock()
+                    .unwrap()
+                    .execute(&mut request, desc_chain.memory()),
+                None => Ok(true),
+            };
 
-            if request
-                .execute_async(
+            let status = match zoned.and_then(|submit| {
+                if !submit {
+                    return Ok(false);
+                }
+                request.execute_async(
                     desc_chain.memory(),
                     self.disk_nsectors,
                     self.disk_image.as_mut(),
                     &self.serial,
-                    desc_chain.head_index() as u64,
+                    user_data,
                 )
-                .map_err(Error::RequestExecuting)?
-            {
-                self.inflight_requests
-                    .push_back((desc_chain.head_index(), request));
-            } else {
-                desc_chain
-                    .memory()
-                    .write_obj(VIRTIO_BLK_S_OK, request.status_addr)
-                    .map_err(Error::RequestStatus)?;
+            }) {
+                Ok(true) => {
+                    self.inflight_requests.push_back((user_data, request));
+                    continue;
+                }
+                Ok(false) => VIRTIO_BLK_S_OK,
+                Err(e) => {
+                    // Report the failure to the guest rather than stopping
+                    // the worker, which would leave the guest hanging.
+                    error!("Failed to execute request: {:x?}: {}", request, e);
+                    // Release any temporary buffer allocated for the request.
+                    request.complete_async().map_err(Error::RequestCompleting)?;
+                    e.status()
+                }
+            };
 
-                // If no asynchronous operation has been submitted, we can
-                // simply return the used descriptor.
-                queue
-                    .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
-                    .map_err(Error::QueueAddUsed)?;
-                used_descs = true;
-            }
+            desc_chain
+                .memory()
+                .write_obj(status, request.status_addr)
+                .map_err(Error::RequestStatus)?;
+
+            // If no asynchronous operation has been submitted, we can
+            // simply return the used descriptor.
+            queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
         }
 
         Ok(used_descs)
@@ -245,7 +368,7 @@ impl BlockEpollHandler {
     }
 
     #[inline]
-    fn find_inflight_request(&mut self, completed_head: u16) -> Result<Request> {
+    fn find_inflight_request(&mut self, completed: u64) -> Result<Request> {
         // This loop neatly handles the fast path where the completions are
         // in order (it turng into just a pop_front()) and the 1% of the time
         // (analysis during boot) where slight out of ordering has been
@@ -256,8 +379,8 @@ impl BlockEpollHandler {
         // This is a O(1) operation and is prepared for the future as it it likely
         // the next completion would be for the one that was skipped which will
         // now be the new front.
-        for (i, (head, _)) in self.inflight_requests.iter().enumerate() {
-            if head == &completed_head {
+        for (i, (user_data, _)) in self.inflight_requests.iter().enumerate() {
+            if user_data == &completed {
                 return Ok(self.inflight_requests.swap_remove_front(i).unwrap().1);
             }
         }
@@ -276,7 +399,24 @@ impl BlockEpollHandler {
         while let Some((user_data, result)) = self.disk_image.next_completed_request() {
             let desc_index = user_data as u16;
 
-            let mut request = self.find_inflight_request(desc_index)?;
+            let mut request = match self.find_inflight_request(user_data) {
+                Ok(request) => request,
+                Err(e) => {
+                    // Once cancelled, a request fails with -ECANCELED unless
+                    // the backend managed to complete it meanwhile.
+                    let i = self
+                        .timed_out_requests
+                        .iter()
+                        .position(|(u, _)| *u == user_data)
+                        .ok_or(e)?;
+                    let (_, request) = self.timed_out_requests.swap_remove(i);
+                    warn!(
+                        "Request completed after timing out: {:x?} (result {})",
+                        request, result
+                    );
+                    request
+                }
+            };
 
             request.complete_async().map_err(Error::RequestCompleting)?;
 
@@ -287,7 +427,8 @@ impl BlockEpollHandler {
             let write_max = self.counters.write_latency_max.load(Ordering::Relaxed);
             let mut read_avg = self.counters.read_latency_avg.load(Ordering::Relaxed);
             let mut write_avg = self.counters.write_latency_avg.load(Ordering::Relaxed);
-            let (status, len) = if result >= 0 {
+            let mut status = VIRTIO_BLK_S_OK;
+            let len = if result >= 0 {
                 match request.request_type {
                     RequestType::In => {
                         for (_, data_len) in &request.data_descriptors {
@@ -319,8 +460,14 @@ impl BlockEpollHandler {
                         };
                     }
                     RequestType::Out => {
-                        if !request.writeback {
-                            self.disk_image.fsync(None).map_err(Error::Fsync)?;
+                        // The guest may have turned the writeback cache off
+                        // while the write was in flight, in which case it
+                        // won't be flushing it anymore.
+                        if !request.writeback || !self.writeback.load(Ordering::Acquire) {
+                            if let Err(e) = self.disk_image.fsync(None) {
+                                error!("Failed to synchronize the file: {:x?}: {}", request, e);
+                                status = VIRTIO_BLK_S_IOERR;
+                            }
                         }
                         for (_, data_len) in &request.data_descriptors {
                             write_bytes += Wrapping(*data_len as u64);
@@ -361,14 +508,17 @@ impl BlockEpollHandler {
                     .write_latency_avg
                     .store(write_avg, Ordering::Relaxed);
 
-                (VIRTIO_BLK_S_OK, result as u32)
+                result as u32
             } else {
+                // Complete the request with an error status so that the
+                // guest sees an I/O error instead of waiting forever.
                 error!(
                     "Request failed: {:x?} {:?}",
                     request,
                     io::Error::from_raw_os_error(-result)
                 );
-                return Err(Error::AsyncRequestFailure);
+                status = VIRTIO_BLK_S_IOERR;
+                0
             };
 
             mem.write_obj(status, request.status_addr)
@@ -399,6 +549,153 @@ impl BlockEpollHandler {
         Ok(used_descs)
     }
 
+    fn process_queue_complete_and_signal(&mut self) -> result::Result<(), EpollHelperError> {
+        let needs_notification = self.process_queue_complete().map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process queue (complete): {:?}", e))
+        })?;
+
+        if needs_notification {
+            self.signal_used_queue().map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        self.resume_inflight_limited_requests()
+    }
+
+    // Resume the submissions which were held back because of the in-flight
+    // limit.
+    fn resume_inflight_limited_requests(&mut self) -> result::Result<(), EpollHelperError> {
+        let used_slots = self.inflight_requests.len() + self.timed_out_requests.len();
+        if self.inflight_limit_reached && used_slots < self.max_inflight_requests {
+            self.inflight_limit_reached = false;
+
+            if !self.rate_limit_reached() {
+                self.process_queue_submit_and_signal()?
+            }
+        }
+
+        Ok(())
+    }
+
+    // Cancel the requests the backend didn't complete in time, so that the
+    // guest gets an I/O error instead of hanging on a stuck backing store.
+    // They are only completed once the backend is done with their buffers,
+    // and the device is failed if even the cancellation doesn't go through.
+    fn process_timed_out_requests(&mut self, timeout: Duration) {
+        let mut failed = false;
+
+        let mut i = 0;
+        while i < self.inflight_requests.len() {
+            if self.inflight_requests[i].1.start.elapsed() < timeout {
+                i += 1;
+                continue;
+            }
+
+            let (user_data, request) = self.inflight_requests.remove(i).unwrap();
+            error!("Request timed out after {:?}: {:x?}", timeout, request);
+
+            if let Err(e) = self.disk_image.cancel(user_data) {
+                error!("Failed to cancel request: {:x?}: {}", request, e);
+                failed = true;
+            }
+
+            self.timed_out_requests.push((user_data, request));
+        }
+
+        if let Some((_, request)) = self
+            .timed_out_requests
+            .iter()
+            .find(|(_, request)| request.start.elapsed() >= timeout * 2)
+        {
+            error!("Request not cancelled in time: {:x?}", request);
+            failed = true;
+        }
+
+        if failed {
+            self.fail_device();
+        }
+    }
+
+    // Let the driver know the device must be reset, without handing back the
+    // descriptors of the requests the backend might still access.
+    fn fail_device(&mut self) {
+        if self.needs_reset.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        error!("Failing the device, waiting for the driver to reset it");
+        if let Err(e) = self.interrupt_cb.trigger(VirtioInterruptType::Config) {
+            error!("Failed to signal config change: {:?}", e);
+        }
+    }
+
+    // Keep on picking up requests from the avail ring for the polling window,
+    // with the guest notifications disabled. This spares the requests issued
+    // meanwhile the cost of a notification and of an epoll wakeup, at the
+    // expense of keeping the worker busy. The window isn't extended so that
+    // the pause and kill events are still serviced under a steady load.
+    fn poll_queue(&mut self, window: Duration) -> result::Result<(), EpollHelperError> {
+        let mem = self.mem.memory();
+        self.queue.disable_notification(mem.deref()).map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to disable queue notification: {:?}", e))
+        })?;
+
+        let deadline = Instant::now() + window;
+        while Instant::now() < deadline {
+            // Completions must not wait for the end of the window. Their
+            // notifier is left untouched, it is harmless once they're gone.
+            if !self.inflight_requests.is_empty() {
+                self.process_queue_complete_and_signal()?;
+            }
+
+            // Requests held back by the rate limiter or the in-flight limit
+            // are resumed from their own events.
+            if self.inflight_limit_reached || self.rate_limit_reached() {
+                break;
+            }
+
+            let avail_idx = self
+                .queue
+                .avail_idx(mem.deref(), Ordering::Acquire)
+                .map_err(EpollHelperError::QueueRingIndex)?;
+            if avail_idx.0 == self.queue.next_avail() {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            self.process_queue_submit_and_signal()?;
+        }
+
+        // Requests made available while the notifications were disabled
+        // would otherwise go unnoticed.
+        if self.queue.enable_notification(mem.deref()).map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to enable queue notification: {:?}", e))
+        })? {
+            self.process_queue_submit_and_signal()?;
+        }
+
+        Ok(())
+    }
+
+    // Flush the writes completed since the previous sync to the backing file,
+    // bounding the amount of data lost on a host crash when the guest doesn't
+    // flush by itself. The counters are shared by all the queues of the
+    // device, so that a single worker syncs on behalf of all of them.
+    fn sync_disk_image(&mut self) {
+        let write_ops = self.counters.write_ops.load(Ordering::Acquire);
+        if write_ops == self.synced_write_ops || !self.writeback.load(Ordering::Acquire) {
+            return;
+        }
+
+        if let Err(e) = self.disk_image.fsync(None) {
+            // Nothing the guest could be told about, try again next time.
+            warn!("Failed to sync the disk image in the background: {:?}", e);
+            return;
+        }
+        self.synced_write_ops = write_ops;
+    }
+
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
         self.interrupt_cb
             .trigger(VirtioInterruptType::Queue(self.queue_index))
@@ -419,8 +716,86 @@ impl BlockEpollHandler {
         if let Some(rate_limiter) = &self.rate_limiter {
             helper.add_event(rate_limiter.as_raw_fd(), RATE_LIMITER_EVENT)?;
         }
+        if let Some(rate_limiter) = &self.read_rate_limiter {
+            helper.add_event(rate_limiter.as_raw_fd(), READ_RATE_LIMITER_EVENT)?;
+        }
+        if let Some(rate_limiter) = &self.write_rate_limiter {
+            helper.add_event(rate_limiter.as_raw_fd(), WRITE_RATE_LIMITER_EVENT)?;
+        }
+        if let Some((sync_timer, interval)) = &mut self.sync_timer {
+            sync_timer
+                .reset(*interval, Some(*interval))
+                .map_err(|e| EpollHelperError::IoError(e.into()))?;
+            helper.add_event(sync_timer.as_raw_fd(), SYNC_TIMER_EVENT)?;
+        }
+        if let Some((request_timer, timeout)) = &mut self.request_timer {
+            let interval = *timeout / REQUEST_TIMER_CHECKS;
+            request_timer
+                .reset(interval, Some(interval))
+                .map_err(|e| EpollHelperError::IoError(e.into()))?;
+            helper.add_event(request_timer.as_raw_fd(), REQUEST_TIMER_EVENT)?;
+        }
         helper.run(paused, paused_sync, self)?;
 
+        self.drain_inflight_requests()
+    }
+
+    // Wait for the requests already handed to the backend to complete before
+    // the thread exits. This guarantees no IO is still pending against the
+    // backing file once the device has been reset or hot-unplugged.
+    fn drain_inflight_requests(&mut self) -> result::Result<(), EpollHelperError> {
+        if self.inflight_requests.is_empty() && self.timed_out_requests.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Waiting for {} in-flight requests to complete",
+            self.inflight_requests.len() + self.timed_out_requests.len()
+        );
+
+        let epoll_fd = epoll::create(true).map_err(EpollHelperError::CreateFd)?;
+        // SAFETY: epoll_fd is a valid fd we own
+        let epoll_file = unsafe { File::from_raw_fd(epoll_fd) };
+        epoll::ctl(
+            epoll_file.as_raw_fd(),
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            self.disk_image.notifier().as_raw_fd(),
+            epoll::Event::new(epoll::Events::EPOLLIN, COMPLETION_EVENT.into()),
+        )
+        .map_err(EpollHelperError::Ctl)?;
+
+        let mut events = [epoll::Event::new(epoll::Events::empty(), 0); 1];
+        while !self.inflight_requests.is_empty() || !self.timed_out_requests.is_empty() {
+            let num_events =
+                match epoll::wait(epoll_file.as_raw_fd(), DRAIN_TIMEOUT_MS, &mut events) {
+                    Ok(res) => res,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(EpollHelperError::Wait(e)),
+                };
+
+            if num_events == 0 {
+                warn!(
+                    "Timed out waiting for {} in-flight requests",
+                    self.inflight_requests.len() + self.timed_out_requests.len()
+                );
+                break;
+            }
+
+            // The notifier is non-blocking, spurious wakeups are harmless.
+            let _ = self.disk_image.notifier().read();
+
+            if self.process_queue_complete().map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!(
+                    "Failed to process queue (complete): {:?}",
+                    e
+                ))
+            })? {
+                self.signal_used_queue().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+                })?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -438,12 +813,13 @@ impl EpollHelperHandler for BlockEpollHandler {
                     EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
                 })?;
 
-                let rate_limit_reached =
-                    self.rate_limiter.as_ref().map_or(false, |r| r.is_blocked());
-
                 // Process the queue only when the rate limit is not reached
-                if !rate_limit_reached {
-                    self.process_queue_submit_and_signal()?
+                if !self.rate_limit_reached() {
+                    self.process_queue_submit_and_signal()?;
+
+                    if let Some(window) = self.poll_queue {
+                        self.poll_queue(window)?;
+                    }
                 }
             }
             COMPLETION_EVENT => {
@@ -451,21 +827,7 @@ impl EpollHelperHandler for BlockEpollHandler {
                     EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
                 })?;
 
-                let needs_notification = self.process_queue_complete().map_err(|e| {
-                    EpollHelperError::HandleEvent(anyhow!(
-                        "Failed to process queue (complete): {:?}",
-                        e
-                    ))
-                })?;
-
-                if needs_notification {
-                    self.signal_used_queue().map_err(|e| {
-                        EpollHelperError::HandleEvent(anyhow!(
-                            "Failed to signal used queue: {:?}",
-                            e
-                        ))
-                    })?;
-                }
+                self.process_queue_complete_and_signal()?;
             }
             RATE_LIMITER_EVENT => {
                 if let Some(rate_limiter) = &mut self.rate_limiter {
@@ -485,6 +847,62 @@ impl EpollHelperHandler for BlockEpollHandler {
                     )));
                 }
             }
+            READ_RATE_LIMITER_EVENT | WRITE_RATE_LIMITER_EVENT => {
+                let rate_limiter = if ev_type == READ_RATE_LIMITER_EVENT {
+                    &mut self.read_rate_limiter
+                } else {
+                    &mut self.write_rate_limiter
+                };
+
+                if let Some(rate_limiter) = rate_limiter {
+                    rate_limiter.event_handler().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to process rate limiter event: {:?}",
+                            e
+                        ))
+                    })?;
+
+                    self.process_queue_submit_and_signal()?
+                } else {
+                    return Err(EpollHelperError::HandleEvent(anyhow!(
+                        "Unexpected rate limiter event {} when the split rate limits are not enabled.",
+                        ev_type
+                    )));
+                }
+            }
+            SYNC_TIMER_EVENT => {
+                if let Some((sync_timer, _)) = &mut self.sync_timer {
+                    sync_timer.wait().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to get sync timer event: {:?}",
+                            e
+                        ))
+                    })?;
+
+                    self.sync_disk_image();
+                } else {
+                    return Err(EpollHelperError::HandleEvent(anyhow!(
+                        "Unexpected 'SYNC_TIMER_EVENT' when the background sync is not enabled."
+                    )));
+                }
+            }
+            REQUEST_TIMER_EVENT => {
+                if let Some((request_timer, timeout)) = &mut self.request_timer {
+                    request_timer.wait().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to get request timer event: {:?}",
+                            e
+                        ))
+                    })?;
+
+                    let timeout = *timeout;
+                    self.process_timed_out_requests(timeout);
+                } else {
+                    return Err(EpollHelperError::HandleEvent(anyhow!(
+                        "Unexpected 'REQUEST_TIMER_EVENT' when the request timeout is not enabled."
+                    )));
+                }
+            }
             _ => {
                 return Err(EpollHelperError::HandleEvent(anyhow!(
                     "Unexpected event: {}",
@@ -508,9 +926,19 @@ pub struct Block {
     counters: BlockCounters,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    read_rate_limiter_config: Option<RateLimiterConfig>,
+    write_rate_limiter_config: Option<RateLimiterConfig>,
     exit_evt: EventFd,
     read_only: bool,
+    writethrough: bool,
     serial: Vec<u8>,
+    aio_max_inflight: Option<u32>,
+    poll_queue: Option<Duration>,
+    sync_interval: Option<Duration>,
+    request_timeout: Option<Duration>,
+    needs_reset: Arc<AtomicBool>,
+    zones: Option<Arc<Mutex<ZoneTable>>>,
+    zoned_config: Option<VirtioBlockZonedConfig>,
 }
 
 #[derive(Versionize)]
@@ -532,12 +960,23 @@ impl Block {
         mut disk_image: Box<dyn DiskFile>,
         disk_path: PathBuf,
         read_only: bool,
+        writethrough: bool,
         iommu: bool,
         num_queues: usize,
         queue_size: u16,
+        seg_max: Option<u32>,
+        size_max: Option<u32>,
+        aio_max_inflight: Option<u32>,
+        poll_queue: Option<Duration>,
+        sync_interval: Option<Duration>,
+        request_timeout: Option<Duration>,
         serial: Option<String>,
+        zone_layout: Option<ZoneLayout>,
+        topology: Option<DiskTopology>,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
+        read_rate_limiter_config: Option<RateLimiterConfig>,
+        write_rate_limiter_config: Option<RateLimiterConfig>,
         exit_evt: EventFd,
         state: Option<BlockState>,
     ) -> io::Result<Self> {
@@ -580,7 +1019,8 @@ impl Block {
                     avail_features |= 1u64 << VIRTIO_BLK_F_RO;
                 }
 
-                let topology = disk_image.topology();
+                // A probed topology takes precedence over the one of the image.
+                let topology = topology.unwrap_or_else(|| disk_image.topology());
                 info!("Disk topology: {:?}", topology);
 
                 let logical_block_size = if topology.logical_block_size > 512 {
@@ -597,10 +1037,29 @@ impl Block {
                     size <<= 1;
                 }
 
-                let disk_nsectors = disk_size / SECTOR_SIZE;
+                let mut disk_nsectors = disk_size / SECTOR_SIZE;
+                if let Some(zone_layout) = zone_layout {
+                    avail_features |= 1u64 << VIRTIO_BLK_F_ZONED;
+                    let capacity = zone_layout.capacity(disk_nsectors);
+                    if capacity == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Disk is smaller than a zone",
+                        ));
+                    }
+                    if capacity != disk_nsectors {
+                        warn!(
+                            "Disk size is not a multiple of the zone size; \
+                             the last {} sectors will not be visible to the guest.",
+                            disk_nsectors - capacity
+                        );
+                    }
+                    disk_nsectors = capacity;
+                }
+
                 let mut config = VirtioBlockConfig {
                     capacity: disk_nsectors,
-                    writeback: 1,
+                    writeback: u8::from(!writethrough),
                     blk_size: topology.logical_block_size as u32,
                     physical_block_exp,
                     min_io_size: (topology.minimum_io_size / logical_block_size) as u16,
@@ -613,10 +1072,34 @@ impl Block {
                     config.num_queues = num_queues as u16;
                 }
 
+                if let Some(seg_max) = seg_max {
+                    avail_features |= 1u64 << VIRTIO_BLK_F_SEG_MAX;
+                    config.seg_max = seg_max;
+                }
+
+                if let Some(size_max) = size_max {
+                    avail_features |= 1u64 << VIRTIO_BLK_F_SIZE_MAX;
+                    config.size_max = size_max;
+                }
+
                 (disk_nsectors, avail_features, 0, config, false)
             };
 
-        let serial = serial.map(Vec::from).unwrap_or(build_serial(&disk_path));
+        let serial = serial
+            .map(|s| {
+                // The guest can't read more than VIRTIO_BLK_ID_BYTES.
+                let mut serial = Vec::from(s);
+                serial.truncate(VIRTIO_BLK_ID_BYTES as usize);
+                serial
+            })
+            .unwrap_or(build_serial(&disk_path));
+
+        // The zones are all reset whenever the device is created, including
+        // on restore, as their state isn't part of the snapshot.
+        let zones = zone_layout.map(|zone_layout| ZoneTable::new(zone_layout, disk_nsectors));
+        let zoned_config = zones
+            .as_ref()
+            .map(|zones| zones.config(std::cmp::max(config.blk_size, SECTOR_SIZE as u32)));
 
         Ok(Block {
             common: VirtioCommon {
@@ -638,9 +1121,19 @@ impl Block {
             counters: BlockCounters::default(),
             seccomp_action,
             rate_limiter_config,
+            read_rate_limiter_config,
+            write_rate_limiter_config,
             exit_evt,
             read_only,
+            writethrough,
             serial,
+            aio_max_inflight,
+            poll_queue,
+            sync_interval,
+            request_timeout,
+            needs_reset: Arc::new(AtomicBool::new(false)),
+            zones: zones.map(|zones| Arc::new(Mutex::new(zones))),
+            zoned_config,
         })
     }
 
@@ -655,8 +1148,12 @@ impl Block {
     }
 
     fn update_writeback(&mut self) {
-        // Use writeback from config if VIRTIO_BLK_F_CONFIG_WCE
-        let writeback = if self.common.feature_acked(VIRTIO_BLK_F_CONFIG_WCE.into()) {
+        // Writethrough cache mode was requested by the host, hence the guest
+        // is not allowed to enable writeback caching.
+        let writeback = if self.writethrough {
+            false
+        } else if self.common.feature_acked(VIRTIO_BLK_F_CONFIG_WCE.into()) {
+            // Use writeback from config if VIRTIO_BLK_F_CONFIG_WCE
             self.config.writeback == 1
         } else {
             // Else check if VIRTIO_BLK_F_FLUSH negotiated
@@ -671,7 +1168,25 @@ impl Block {
                 "writethrough"
             }
         );
-        self.writeback.store(writeback, Ordering::Release);
+        let cached_writes = self.writeback.swap(writeback, Ordering::AcqRel);
+
+        // The guest won't be flushing the writes completed so far once the
+        // writeback cache is turned off, hence they are synced before it can
+        // expect any further write to be on the backing file.
+        if cached_writes && !writeback {
+            self.sync_disk_image();
+        }
+    }
+
+    fn sync_disk_image(&self) {
+        match self.disk_image.new_async_io(1) {
+            Ok(mut disk_image) => {
+                if let Err(e) = disk_image.fsync(None) {
+                    error!("Failed to synchronize the file: {}", e);
+                }
+            }
+            Err(e) => error!("Failed to create new AsyncIo: {}", e),
+        }
     }
 
     #[cfg(fuzzing)]
@@ -707,8 +1222,19 @@ impl VirtioDevice for Block {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
-        self.read_config_from_slice(self.config.as_slice(), offset, data);
+        // The zoned characteristics follow the regular configuration.
+        match &self.zoned_config {
+            Some(zoned_config) => {
+                let config = [self.config.as_slice(), zoned_config.as_slice()].concat();
+                self.read_config_from_slice(&config, offset, data);
+            }
+            None => self.read_config_from_slice(self.config.as_slice(), offset, data),
+        }
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
@@ -725,7 +1251,9 @@ impl VirtioDevice for Block {
             return;
         }
 
-        self.config.writeback = data[0];
+        // Reflect the cache mode actually in use, so that the guest reading
+        // the field back notices when writeback caching can't be enabled.
+        self.config.writeback = u8::from(data[0] != 0 && !self.writethrough);
         self.update_writeback();
     }
 
@@ -750,18 +1278,50 @@ impl VirtioDevice for Block {
                 .map(RateLimiterConfig::try_into)
                 .transpose()
                 .map_err(ActivateError::CreateRateLimiter)?;
+            let read_rate_limiter: Option<RateLimiter> = self
+                .read_rate_limiter_config
+                .map(RateLimiterConfig::try_into)
+                .transpose()
+                .map_err(ActivateError::CreateRateLimiter)?;
+            let write_rate_limiter: Option<RateLimiter> = self
+                .write_rate_limiter_config
+                .map(RateLimiterConfig::try_into)
+                .transpose()
+                .map_err(ActivateError::CreateRateLimiter)?;
+
+            // The number of in-flight requests is bounded by the queue size,
+            // unless a lower limit was requested.
+            let max_inflight = self
+                .aio_max_inflight
+                .map_or(queue_size as u32, |m| m.min(queue_size as u32));
+
+            // The backing file is shared by all the queues, only the first
+            // one takes care of the periodic background sync.
+            let sync_timer = match self.sync_interval {
+                Some(interval) if i == 0 => Some((
+                    TimerFd::new().map_err(|e| ActivateError::CreateSyncTimer(e.into()))?,
+                    interval,
+                )),
+                _ => None,
+            };
+
+            let request_timer = self
+                .request_timeout
+                .map(|timeout| {
+                    TimerFd::new()
+                        .map(|timer| (timer, timeout))
+                        .map_err(|e| ActivateError::CreateRequestTimer(e.into()))
+                })
+                .transpose()?;
 
             let mut handler = BlockEpollHandler {
                 queue_index: i as u16,
                 queue,
                 mem: mem.clone(),
-                disk_image: self
-                    .disk_image
-                    .new_async_io(queue_size as u32)
-                    .map_err(|e| {
-                        error!("failed to create new AsyncIo: {}", e);
-                        ActivateError::BadActivate
-                    })?,
+                disk_image: self.disk_image.new_async_io(max_inflight).map_err(|e| {
+                    error!("failed to create new AsyncIo: {}", e);
+                    ActivateError::BadActivate
+                })?,
                 disk_nsectors: self.disk_nsectors,
                 interrupt_cb: interrupt_cb.clone(),
                 serial: self.serial.clone(),
@@ -774,9 +1334,21 @@ impl VirtioDevice for Block {
                 // This gives head room for systems with slower I/O without
                 // compromising the cost of the reallocation or memory overhead
                 inflight_requests: VecDeque::with_capacity(64),
+                max_inflight_requests: max_inflight as usize,
+                inflight_limit_reached: false,
+                submitted_requests: 0,
+                request_timer,
+                timed_out_requests: Vec::new(),
+                needs_reset: self.needs_reset.clone(),
                 rate_limiter,
+                read_rate_limiter,
+                write_rate_limiter,
                 access_platform: self.common.access_platform.clone(),
                 read_only: self.read_only,
+                poll_queue: self.poll_queue,
+                sync_timer,
+                synced_write_ops: self.counters.write_ops.load(Ordering::Acquire),
+                zones: self.zones.clone(),
             };
 
             let paused = self.common.paused.clone();
@@ -800,10 +1372,15 @@ impl VirtioDevice for Block {
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        self.needs_reset.store(false, Ordering::Release);
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
 
+    fn needs_reset(&self) -> bool {
+        self.needs_reset.load(Ordering::Acquire)
+    }
+
     fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
         let mut counters = HashMap::new();
 
@@ -872,8 +1449,257 @@ impl Snapshottable for Block {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        // The state of the emulated zones only lives in memory.
+        if self.zones.is_some() {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshotting a zoned virtio-block device is not supported"
+            )));
+        }
+
         Snapshot::new_from_versioned_state(&self.state())
     }
 }
 impl Transportable for Block {}
-impl Migratable for Block {}
+impl Migratable for Block {
+    fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        if self.zones.is_some() {
+            return Err(MigratableError::StartDirtyLog(anyhow!(
+                "Migrating a zoned virtio-block device is not supported"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use block::async_io::{AsyncIoError, AsyncIoResult};
+    use virtio_bindings::virtio_ring::{VRING_DESC_F_NEXT, VRING_DESC_F_WRITE};
+    use vm_memory::GuestAddress;
+    use vm_virtio::queue::testing::VirtQueue as GuestQ;
+
+    const HDR_ADDR: u64 = 0x2_0000;
+    const DATA_ADDR: u64 = 0x3_0000;
+    const STATUS_ADDR: u64 = 0x4_0000;
+
+    struct NoopVirtioInterrupt {}
+
+    impl VirtioInterrupt for NoopVirtioInterrupt {
+        fn trigger(&self, _int_type: VirtioInterruptType) -> std::result::Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    // Disk image completing the requests without touching their buffers,
+    // failing the operations it is told to.
+    #[derive(Default)]
+    struct FailingAsyncIo {
+        notifier: Option<EventFd>,
+        fail_submit: bool,
+        fail_completion: bool,
+        fail_fsync: bool,
+        completed: VecDeque<(u64, i32)>,
+    }
+
+    impl FailingAsyncIo {
+        fn submit(&mut self, iovecs: &[libc::iovec], user_data: u64) -> bool {
+            if self.fail_submit {
+                return false;
+            }
+            let result = if self.fail_completion {
+                -libc::EIO
+            } else {
+                iovecs.iter().map(|iovec| iovec.iov_len as i32).sum()
+            };
+            self.completed.push_back((user_data, result));
+            true
+        }
+    }
+
+    impl AsyncIo for FailingAsyncIo {
+        fn notifier(&self) -> &EventFd {
+            self.notifier.as_ref().unwrap()
+        }
+
+        fn read_vectored(
+            &mut self,
+            _offset: libc::off_t,
+            iovecs: &[libc::iovec],
+            user_data: u64,
+        ) -> AsyncIoResult<()> {
+            if !self.submit(iovecs, user_data) {
+                return Err(AsyncIoError::ReadVectored(io::Error::from_raw_os_error(
+                    libc::EIO,
+                )));
+            }
+            Ok(())
+        }
+
+        fn write_vectored(
+            &mut self,
+            _offset: libc::off_t,
+            iovecs: &[libc::iovec],
+            user_data: u64,
+        ) -> AsyncIoResult<()> {
+            if !self.submit(iovecs, user_data) {
+                return Err(AsyncIoError::WriteVectored(io::Error::from_raw_os_error(
+                    libc::EIO,
+                )));
+            }
+            Ok(())
+        }
+
+        fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()> {
+            if self.fail_fsync {
+                return Err(AsyncIoError::Fsync(io::Error::from_raw_os_error(libc::EIO)));
+            }
+            if let Some(user_data) = user_data {
+                self.completed.push_back((user_data, 0));
+            }
+            Ok(())
+        }
+
+        fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+            self.completed.pop_front()
+        }
+    }
+
+    fn create_handler(
+        mem: &GuestMemoryMmap,
+        queue: Queue,
+        disk_image: FailingAsyncIo,
+        writeback: bool,
+    ) -> BlockEpollHandler {
+        BlockEpollHandler {
+            queue_index: 0,
+            queue,
+            mem: GuestMemoryAtomic::new(mem.clone()),
+            disk_image: Box::new(disk_image),
+            disk_nsectors: 16,
+            interrupt_cb: Arc::new(NoopVirtioInterrupt {}),
+            serial: Vec::new(),
+            kill_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            pause_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            writeback: Arc::new(AtomicBool::new(writeback)),
+            counters: BlockCounters::default(),
+            queue_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            inflight_requests: VecDeque::new(),
+            max_inflight_requests: 8,
+            inflight_limit_reached: false,
+            submitted_requests: 0,
+            request_timer: None,
+            timed_out_requests: Vec::new(),
+            needs_reset: Arc::new(AtomicBool::new(false)),
+            rate_limiter: None,
+            read_rate_limiter: None,
+            write_rate_limiter: None,
+            access_platform: None,
+            read_only: false,
+            poll_queue: None,
+            sync_timer: None,
+            synced_write_ops: 0,
+            zones: None,
+        }
+    }
+
+    // Runs a single request of `request_type` on one sector of data, and
+    // returns the status reported to the guest along with the number of
+    // bytes it was told were written to its buffers.
+    fn run_request(
+        request_type: u32,
+        sector: u64,
+        disk_image: FailingAsyncIo,
+        writeback: bool,
+    ) -> (u32, u32) {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1_0000), &mem, 8);
+        let next = VRING_DESC_F_NEXT as u16;
+        let write = VRING_DESC_F_WRITE as u16;
+        let data_flags = if request_type == VIRTIO_BLK_T_IN {
+            next | write
+        } else {
+            next
+        };
+
+        mem.write_obj(request_type, GuestAddress(HDR_ADDR)).unwrap();
+        mem.write_obj(sector, GuestAddress(HDR_ADDR + 8)).unwrap();
+        mem.write_obj(0xffu8, GuestAddress(STATUS_ADDR)).unwrap();
+
+        guest_q.dtable[0].set(HDR_ADDR, 16, next, 1);
+        guest_q.dtable[1].set(DATA_ADDR, SECTOR_SIZE as u32, data_flags, 2);
+        guest_q.dtable[2].set(STATUS_ADDR, 1, write, 0);
+        guest_q.avail.ring[0].set(0);
+        guest_q.avail.idx.set(1);
+
+        let mut handler = create_handler(&mem, guest_q.create_queue(), disk_image, writeback);
+        handler.process_queue_submit().unwrap();
+        handler.process_queue_complete().unwrap();
+        assert_eq!(guest_q.used.idx.get(), 1);
+
+        let status: u8 = mem.read_obj(GuestAddress(STATUS_ADDR)).unwrap();
+        // The used element holds the descriptor index followed by the length.
+        let used_len: u32 = mem
+            .read_obj(GuestAddress(guest_q.used.ring[0].location.0 + 4))
+            .unwrap();
+        (u32::from(status), used_len)
+    }
+
+    #[test]
+    fn test_request_ok() {
+        let (status, len) = run_request(VIRTIO_BLK_T_IN, 0, FailingAsyncIo::default(), true);
+        assert_eq!(status, VIRTIO_BLK_S_OK);
+        assert_eq!(len, SECTOR_SIZE as u32);
+
+        let (status, _) = run_request(VIRTIO_BLK_T_OUT, 0, FailingAsyncIo::default(), false);
+        assert_eq!(status, VIRTIO_BLK_S_OK);
+    }
+
+    #[test]
+    fn test_request_submission_failure() {
+        let disk_image = FailingAsyncIo {
+            fail_submit: true,
+            ..Default::default()
+        };
+        let (status, len) = run_request(VIRTIO_BLK_T_OUT, 0, disk_image, true);
+        assert_eq!(status, VIRTIO_BLK_S_IOERR);
+        assert_eq!(len, 0);
+
+        // Beyond the end of the disk.
+        let (status, _) = run_request(VIRTIO_BLK_T_IN, 16, FailingAsyncIo::default(), true);
+        assert_eq!(status, VIRTIO_BLK_S_IOERR);
+
+        let (status, _) = run_request(VIRTIO_BLK_T_DISCARD, 0, FailingAsyncIo::default(), true);
+        assert_eq!(status, VIRTIO_BLK_S_UNSUPP);
+    }
+
+    #[test]
+    fn test_request_completion_failure() {
+        let disk_image = FailingAsyncIo {
+            fail_completion: true,
+            ..Default::default()
+        };
+        let (status, len) = run_request(VIRTIO_BLK_T_IN, 0, disk_image, true);
+        assert_eq!(status, VIRTIO_BLK_S_IOERR);
+        assert_eq!(len, 0);
+    }
+
+    #[test]
+    fn test_request_fsync_failure() {
+        // Writes are only synchronized with the writeback cache disabled.
+        let disk_image = FailingAsyncIo {
+            fail_fsync: true,
+            ..Default::default()
+        };
+        let (status, _) = run_request(VIRTIO_BLK_T_OUT, 0, disk_image, true);
+        assert_eq!(status, VIRTIO_BLK_S_OK);
+
+        let disk_image = FailingAsyncIo {
+            fail_fsync: true,
+            ..Default::default()
+        };
+        let (status, _) = run_request(VIRTIO_BLK_T_OUT, 0, disk_image, false);
+        assert_eq!(status, VIRTIO_BLK_S_IOERR);
+    }
+}
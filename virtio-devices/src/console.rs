@@ -14,7 +14,7 @@ use crate::VirtioInterrupt;
 use anyhow::anyhow;
 use libc::{EFD_NONBLOCK, TIOCGWINSZ};
 use seccompiler::SeccompAction;
-use serial_buffer::SerialBuffer;
+use serial_buffer::{RotatingFile, SerialBuffer, TimestampWriter};
 use std::cmp;
 use std::collections::VecDeque;
 use std::fs::File;
@@ -112,6 +112,8 @@ pub enum Endpoint {
     File(File),
     FilePair(File, File),
     PtyPair(File, File),
+    RotatingFile(RotatingFile, Option<File>),
+    Timestamped(TimestampWriter, Option<File>),
     Null,
 }
 
@@ -121,6 +123,8 @@ impl Endpoint {
             Self::File(f) => Some(f),
             Self::FilePair(f, _) => Some(f),
             Self::PtyPair(f, _) => Some(f),
+            Self::RotatingFile(_, _) => None,
+            Self::Timestamped(_, _) => None,
             Self::Null => None,
         }
     }
@@ -130,6 +134,8 @@ impl Endpoint {
             Self::File(_) => None,
             Self::FilePair(_, f) => Some(f),
             Self::PtyPair(_, f) => Some(f),
+            Self::RotatingFile(_, f) => f.as_ref(),
+            Self::Timestamped(_, f) => f.as_ref(),
             Self::Null => None,
         }
     }
@@ -149,6 +155,12 @@ impl Clone for Endpoint {
             Self::PtyPair(f_out, f_in) => {
                 Self::PtyPair(f_out.try_clone().unwrap(), f_in.try_clone().unwrap())
             }
+            Self::RotatingFile(f_out, f_in) => {
+                Self::RotatingFile(f_out.clone(), f_in.as_ref().map(|f| f.try_clone().unwrap()))
+            }
+            Self::Timestamped(f_out, f_in) => {
+                Self::Timestamped(f_out.clone(), f_in.as_ref().map(|f| f.try_clone().unwrap()))
+            }
             Self::Null => Self::Null,
         }
     }
@@ -183,6 +195,16 @@ impl ConsoleEpollHandler {
             } else {
                 (Some(Box::new(writer) as Box<dyn Write + Send>), None)
             }
+        } else if let Endpoint::RotatingFile(writer, _) = &endpoint {
+            (
+                Some(Box::new(writer.clone()) as Box<dyn Write + Send>),
+                None,
+            )
+        } else if let Endpoint::Timestamped(writer, _) = &endpoint {
+            (
+                Some(Box::new(writer.clone()) as Box<dyn Write + Send>),
+                None,
+            )
         } else {
             (None, None)
         };
@@ -731,6 +753,10 @@ impl VirtioDevice for Console {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.lock().unwrap().as_slice(), offset, data);
     }
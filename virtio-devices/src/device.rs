@@ -86,6 +86,11 @@ pub trait VirtioDevice: Send {
         let _ = value;
     }
 
+    /// The set of feature bits negotiated with the driver.
+    fn acked_features(&self) -> u64 {
+        0
+    }
+
     /// Reads this device configuration space at `offset`.
     fn read_config(&self, _offset: u64, _data: &mut [u8]) {
         warn!(
@@ -116,6 +121,12 @@ pub trait VirtioDevice: Send {
         None
     }
 
+    /// Whether the device hit an error it can't recover from, requiring the
+    /// driver to reset it.
+    fn needs_reset(&self) -> bool {
+        false
+    }
+
     /// Returns the list of shared memory regions required by the device.
     fn get_shm_regions(&self) -> Option<VirtioSharedMemoryList> {
         None
@@ -59,7 +59,6 @@ const PROBE_PROP_SIZE: u32 =
     (size_of::<VirtioIommuProbeProperty>() + size_of::<VirtioIommuProbeResvMem>()) as u32;
 
 /// Virtio IOMMU features
-#[allow(unused)]
 const VIRTIO_IOMMU_F_INPUT_RANGE: u32 = 0;
 #[allow(unused)]
 const VIRTIO_IOMMU_F_DOMAIN_RANGE: u32 = 1;
@@ -127,7 +126,6 @@ const VIRTIO_IOMMU_S_UNSUPP: u8 = 2;
 const VIRTIO_IOMMU_S_DEVERR: u8 = 3;
 #[allow(unused)]
 const VIRTIO_IOMMU_S_INVAL: u8 = 4;
-#[allow(unused)]
 const VIRTIO_IOMMU_S_RANGE: u8 = 5;
 #[allow(unused)]
 const VIRTIO_IOMMU_S_NOENT: u8 = 6;
@@ -318,6 +316,8 @@ enum Error {
     InvalidMapRequestBypassDomain,
     #[error("Invalid to map because the domain is missing")]
     InvalidMapRequestMissingDomain,
+    #[error("Invalid to map because the range is beyond the input range")]
+    InvalidMapRequestOutOfRange,
     #[error("Guest sent us invalid UNMAP request")]
     InvalidUnmapRequest,
     #[error("Invalid to unmap because the domain is in bypass mode")]
@@ -350,6 +350,7 @@ impl Request {
         mapping: &Arc<IommuMapping>,
         ext_mapping: &BTreeMap<u32, Arc<dyn ExternalDmaMapping>>,
         msi_iova_space: (u64, u64),
+        input_range_end: u64,
     ) -> result::Result<usize, Error> {
         let desc = desc_chain
             .next()
@@ -471,6 +472,11 @@ impl Request {
                     // Copy the value to use it as a proper reference.
                     let domain_id = req.domain;
 
+                    if req.virt_start > req.virt_end || req.virt_end > input_range_end {
+                        status = VIRTIO_IOMMU_S_RANGE;
+                        return Err(Error::InvalidMapRequestOutOfRange);
+                    }
+
                     if let Some(domain) = mapping.domains.read().unwrap().get(&domain_id) {
                         if domain.bypass {
                             status = VIRTIO_IOMMU_S_INVAL;
@@ -652,6 +658,7 @@ struct IommuEpollHandler {
     mapping: Arc<IommuMapping>,
     ext_mapping: Arc<Mutex<BTreeMap<u32, Arc<dyn ExternalDmaMapping>>>>,
     msi_iova_space: (u64, u64),
+    input_range_end: u64,
 }
 
 impl IommuEpollHandler {
@@ -664,6 +671,7 @@ impl IommuEpollHandler {
                 &self.mapping,
                 &self.ext_mapping.lock().unwrap(),
                 self.msi_iova_space,
+                self.input_range_end,
             )?;
 
             self.request_queue
@@ -877,6 +885,7 @@ impl Iommu {
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
         msi_iova_space: (u64, u64),
+        address_width_bits: u8,
         state: Option<IommuState>,
     ) -> io::Result<(Self, Arc<IommuMapping>)> {
         let (avail_features, acked_features, endpoints, domains, paused) =
@@ -903,6 +912,7 @@ impl Iommu {
                 )
             } else {
                 let avail_features = 1u64 << VIRTIO_F_VERSION_1
+                    | 1u64 << VIRTIO_IOMMU_F_INPUT_RANGE
                     | 1u64 << VIRTIO_IOMMU_F_MAP_UNMAP
                     | 1u64 << VIRTIO_IOMMU_F_PROBE
                     | 1u64 << VIRTIO_IOMMU_F_BYPASS_CONFIG;
@@ -910,8 +920,19 @@ impl Iommu {
                 (avail_features, 0, BTreeMap::new(), BTreeMap::new(), false)
             };
 
+        // Only the IOVAs below the address width can be mapped by the guest.
+        let input_range_end = if address_width_bits < 64 {
+            (1u64 << address_width_bits) - 1
+        } else {
+            u64::MAX
+        };
+
         let config = VirtioIommuConfig {
             page_size_mask: VIRTIO_IOMMU_PAGE_SIZE_MASK,
+            input_range: VirtioIommuRange64 {
+                start: 0,
+                end: input_range_end,
+            },
             probe_size: PROBE_PROP_SIZE,
             ..Default::default()
         };
@@ -1021,6 +1042,10 @@ impl VirtioDevice for Iommu {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
@@ -1067,6 +1092,7 @@ impl VirtioDevice for Iommu {
             mapping: self.mapping.clone(),
             ext_mapping: self.ext_mapping.clone(),
             msi_iova_space: self.msi_iova_space,
+            input_range_end: self.config.input_range.end,
         };
 
         let paused = self.common.paused.clone();
@@ -31,6 +31,7 @@ pub mod mem;
 pub mod net;
 mod pmem;
 mod rng;
+mod scsi;
 pub mod seccomp_filters;
 mod thread_helper;
 pub mod transport;
@@ -51,9 +52,10 @@ pub use self::epoll_helper::{
 };
 pub use self::iommu::{AccessPlatformMapping, Iommu, IommuMapping};
 pub use self::mem::{BlocksState, Mem, VirtioMemMappingSource, VIRTIO_MEM_ALIGN_SIZE};
-pub use self::net::{Net, NetCtrlEpollHandler};
+pub use self::net::{Net, NetCtrlEpollHandler, NetRateLimitGroup};
 pub use self::pmem::Pmem;
-pub use self::rng::Rng;
+pub use self::rng::{Rng, DEFAULT_RNG_QUEUE_SIZE};
+pub use self::scsi::{Scsi, ScsiLun, ScsiState, SCSI_MAX_LUNS};
 pub use self::vdpa::{Vdpa, VdpaDmaMapping};
 pub use self::vsock::Vsock;
 pub use self::watchdog::Watchdog;
@@ -69,6 +71,7 @@ const DEVICE_ACKNOWLEDGE: u32 = 0x01;
 const DEVICE_DRIVER: u32 = 0x02;
 const DEVICE_DRIVER_OK: u32 = 0x04;
 const DEVICE_FEATURES_OK: u32 = 0x08;
+const DEVICE_NEEDS_RESET: u32 = 0x40;
 const DEVICE_FAILED: u32 = 0x80;
 
 const VIRTIO_F_RING_INDIRECT_DESC: u32 = 28;
@@ -97,8 +100,14 @@ pub enum ActivateError {
     CreateSeccompFilter(seccompiler::Error),
     #[error("Failed to create rate limiter: {0}")]
     CreateRateLimiter(std::io::Error),
+    #[error("Failed to create the background sync timer: {0}")]
+    CreateSyncTimer(std::io::Error),
+    #[error("Failed to create the request timeout timer: {0}")]
+    CreateRequestTimer(std::io::Error),
     #[error("Failed to activate the vDPA device: {0}")]
     ActivateVdpa(vdpa::Error),
+    #[error("Failed to activate the vhost-net datapath: {0}")]
+    ActivateVhostNet(net::Error),
 }
 
 pub type ActivateResult = std::result::Result<(), ActivateError>;
@@ -115,6 +124,8 @@ pub enum Error {
     VhostUserUpdateMemory(vhost_user::Error),
     #[error("Failed to add memory region vhost-user: {0}")]
     VhostUserAddMemoryRegion(vhost_user::Error),
+    #[error("Failed to update memory vhost-net: {0}")]
+    VhostNetUpdateMemory(vhost::Error),
     #[error("Failed to set shared memory region")]
     SetShmRegionsNotSupported,
     #[error("Failed to process net queue: {0}")]
@@ -129,9 +140,22 @@ pub enum Error {
 pub struct TokenBucketConfig {
     pub size: u64,
     pub one_time_burst: Option<u64>,
+    #[serde(default)]
+    pub burst: Option<u64>,
     pub refill_time: u64,
 }
 
+impl TokenBucketConfig {
+    fn token_bucket(&self) -> Option<rate_limiter::TokenBucket> {
+        rate_limiter::TokenBucket::new_with_burst(
+            self.size,
+            self.one_time_burst.unwrap_or(0),
+            self.burst.unwrap_or(0),
+            self.refill_time,
+        )
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct RateLimiterConfig {
@@ -139,19 +163,23 @@ pub struct RateLimiterConfig {
     pub ops: Option<TokenBucketConfig>,
 }
 
+impl RateLimiterConfig {
+    /// Create the token buckets shared by a group of rate limiters.
+    pub fn new_group(&self) -> rate_limiter::RateLimiterGroup {
+        rate_limiter::RateLimiterGroup::new(
+            self.bandwidth.and_then(|bw| bw.token_bucket()),
+            self.ops.and_then(|ops| ops.token_bucket()),
+        )
+    }
+}
+
 impl TryInto<rate_limiter::RateLimiter> for RateLimiterConfig {
     type Error = io::Error;
 
     fn try_into(self) -> std::result::Result<rate_limiter::RateLimiter, Self::Error> {
-        let bw = self.bandwidth.unwrap_or_default();
-        let ops = self.ops.unwrap_or_default();
-        rate_limiter::RateLimiter::new(
-            bw.size,
-            bw.one_time_burst.unwrap_or(0),
-            bw.refill_time,
-            ops.size,
-            ops.one_time_burst.unwrap_or(0),
-            ops.refill_time,
+        rate_limiter::RateLimiter::from_buckets(
+            self.bandwidth.and_then(|bw| bw.token_bucket()),
+            self.ops.and_then(|ops| ops.token_bucket()),
         )
     }
 }
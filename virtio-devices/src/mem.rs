@@ -410,10 +410,24 @@ struct MemEpollHandler {
     kill_evt: EventFd,
     pause_evt: EventFd,
     hugepages: bool,
+    zero_on_free: bool,
     dma_mapping_handlers: Arc<Mutex<BTreeMap<VirtioMemMappingSource, Arc<dyn ExternalDmaMapping>>>>,
 }
 
 impl MemEpollHandler {
+    // Overwrite a range the guest is giving back, as discarding it does not
+    // drop the content of hugepages, nor of a backing file that is shared
+    // with another process.
+    fn zero_memory_range(&self, offset: u64, size: u64) {
+        if self.zero_on_free {
+            // SAFETY: the range has been validated against the virtio-mem
+            // region, which is mapped at host_addr.
+            unsafe {
+                std::ptr::write_bytes((self.host_addr + offset) as *mut u8, 0, size as usize)
+            };
+        }
+    }
+
     fn discard_memory_range(&self, offset: u64, size: u64) -> Result<(), Error> {
         // Use fallocate if the memory region is backed by a file.
         if let Some(fd) = self.host_fd {
@@ -478,6 +492,7 @@ impl MemEpollHandler {
         }
 
         if !plug {
+            self.zero_memory_range(offset, size);
             if let Err(e) = self.discard_memory_range(offset, size) {
                 error!("failed discarding memory range: {:?}", e);
                 return VIRTIO_MEM_RESP_ERROR;
@@ -526,6 +541,10 @@ impl MemEpollHandler {
 
     fn unplug_all(&mut self) -> u16 {
         let mut config = self.config.lock().unwrap();
+        let plugged_memory_ranges = self.blocks_state.lock().unwrap().memory_ranges(0, true);
+        for range in plugged_memory_ranges.regions() {
+            self.zero_memory_range(range.gpa, range.length);
+        }
         if let Err(e) = self.discard_memory_range(0, config.region_size) {
             error!("failed discarding memory range: {:?}", e);
             return VIRTIO_MEM_RESP_ERROR;
@@ -704,6 +723,7 @@ pub struct Mem {
     config: Arc<Mutex<VirtioMemConfig>>,
     seccomp_action: SeccompAction,
     hugepages: bool,
+    zero_on_free: bool,
     dma_mapping_handlers: Arc<Mutex<BTreeMap<VirtioMemMappingSource, Arc<dyn ExternalDmaMapping>>>>,
     blocks_state: Arc<Mutex<BlocksState>>,
     exit_evt: EventFd,
@@ -720,6 +740,7 @@ impl Mem {
         numa_node_id: Option<u16>,
         initial_size: u64,
         hugepages: bool,
+        zero_on_free: bool,
         exit_evt: EventFd,
         blocks_state: Arc<Mutex<BlocksState>>,
         state: Option<MemState>,
@@ -804,6 +825,7 @@ impl Mem {
             config: Arc::new(Mutex::new(config)),
             seccomp_action,
             hugepages,
+            zero_on_free,
             dma_mapping_handlers: Arc::new(Mutex::new(BTreeMap::new())),
             blocks_state,
             exit_evt,
@@ -923,6 +945,10 @@ impl VirtioDevice for Mem {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.lock().unwrap().as_slice(), offset, data);
     }
@@ -952,6 +978,7 @@ impl VirtioDevice for Mem {
             kill_evt,
             pause_evt,
             hugepages: self.hugepages,
+            zero_on_free: self.zero_on_free,
             dma_mapping_handlers: Arc::clone(&self.dma_mapping_handlers),
         };
 
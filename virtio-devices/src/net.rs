@@ -14,33 +14,41 @@ use super::{
 use crate::seccomp_filters::Thread;
 use crate::thread_helper::spawn_virtio_thread;
 use crate::GuestMemoryMmap;
+use crate::GuestRegionMmap;
 use crate::VirtioInterrupt;
 use anyhow::anyhow;
 use net_util::CtrlQueue;
 use net_util::{
     build_net_config_space, build_net_config_space_with_mq, open_tap,
-    virtio_features_to_tap_offload, MacAddr, NetCounters, NetQueuePair, OpenTapError, RxVirtio,
-    Tap, TapError, TxVirtio, VirtioNetConfig,
+    virtio_features_to_tap_offload, MacAddr, NetCounters, NetQueuePair, OpenTapError, RxFilter,
+    RxVirtio, Tap, TapError, TxVirtio, VirtioNetConfig,
 };
+use rate_limiter::RateLimiterGroup;
 use seccompiler::SeccompAction;
-use std::net::Ipv4Addr;
+use std::fs::OpenOptions;
+use std::net::IpAddr;
 use std::num::Wrapping;
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, RwLock};
 use std::thread;
 use std::vec::Vec;
 use std::{collections::HashMap, convert::TryInto};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
+use vhost::net::VhostNet;
+use vhost::vhost_kern::net::Net as VhostKernNet;
+use vhost::{VhostBackend, VhostUserMemoryRegionInfo, VringConfigData};
 use virtio_bindings::virtio_config::*;
 use virtio_bindings::virtio_net::*;
 use virtio_bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use virtio_queue::{Queue, QueueT};
-use vm_memory::{ByteValued, GuestAddressSpace, GuestMemoryAtomic};
+use vm_memory::{
+    Address, ByteValued, GuestAddressSpace, GuestMemory, GuestMemoryAtomic, GuestMemoryRegion,
+};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 use vm_virtio::AccessPlatform;
@@ -53,6 +61,10 @@ const CTRL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 // Following the VIRTIO specification, the MTU should be at least 1280.
 pub const MIN_MTU: u16 = 1280;
 
+// Following the VIRTIO specification, a split virtqueue can't hold more than
+// 32768 descriptors.
+pub const MAX_QUEUE_SIZE: u16 = 32768;
+
 pub struct NetCtrlEpollHandler {
     pub mem: GuestMemoryAtomic<GuestMemoryMmap>,
     pub kill_evt: EventFd,
@@ -155,6 +167,9 @@ pub const RX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
 // New 'wake up' event from the tx rate limiter
 pub const TX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
 
+// Device through which the datapath is offloaded to the host kernel.
+const VHOST_NET_PATH: &str = "/dev/vhost-net";
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to open taps: {0}")]
@@ -163,6 +178,32 @@ pub enum Error {
     TapError(TapError),
     #[error("Error calling dup() on tap fd: {0}")]
     DuplicateTapFd(std::io::Error),
+    #[error("Failed to open {VHOST_NET_PATH}: {0}")]
+    OpenVhostNet(std::io::Error),
+    #[error("Failed to create vhost-net: {0}")]
+    CreateVhostNet(vhost::Error),
+    #[error("Failed to set vhost-net owner: {0}")]
+    VhostNetSetOwner(vhost::Error),
+    #[error("Failed to get vhost-net features: {0}")]
+    VhostNetGetFeatures(vhost::Error),
+    #[error("Failed to set vhost-net features: {0}")]
+    VhostNetSetFeatures(vhost::Error),
+    #[error("Failed to set vhost-net memory table: {0}")]
+    VhostNetSetMemTable(vhost::Error),
+    #[error("Failed to set vhost-net vring size: {0}")]
+    VhostNetSetVringNum(vhost::Error),
+    #[error("Failed to set vhost-net vring address: {0}")]
+    VhostNetSetVringAddr(vhost::Error),
+    #[error("Failed to set vhost-net vring base: {0}")]
+    VhostNetSetVringBase(vhost::Error),
+    #[error("Failed to set vhost-net vring eventfd when buffer are used: {0}")]
+    VhostNetSetVringCall(vhost::Error),
+    #[error("Failed to set vhost-net vring eventfd when new descriptors are available: {0}")]
+    VhostNetSetVringKick(vhost::Error),
+    #[error("No interrupt eventfd for queue {0}")]
+    VhostNetMissingInterrupt(usize),
+    #[error("Failed to set vhost-net backend: {0}")]
+    VhostNetSetBackend(vhost::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -411,6 +452,26 @@ impl EpollHelperHandler for NetEpollHandler {
     }
 }
 
+/// Rate limits shared by several network devices.
+///
+/// The transmissions of all the devices of the group share the same token
+/// buckets, and so do their receptions, bounding the combined traffic of the
+/// group in each direction. Clones refer to the same buckets.
+#[derive(Clone)]
+pub struct NetRateLimitGroup {
+    rx: RateLimiterGroup,
+    tx: RateLimiterGroup,
+}
+
+impl NetRateLimitGroup {
+    pub fn new(config: RateLimiterConfig) -> Self {
+        NetRateLimitGroup {
+            rx: config.new_group(),
+            tx: config.new_group(),
+        }
+    }
+}
+
 pub struct Net {
     common: VirtioCommon,
     id: String,
@@ -420,7 +481,15 @@ pub struct Net {
     counters: NetCounters,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    rate_limit_group: Option<NetRateLimitGroup>,
+    rx_filter: Arc<RwLock<RxFilter>>,
+    // The restored filter must survive the activation following a restore
+    rx_filter_restored: bool,
     exit_evt: EventFd,
+    vhost: bool,
+    // One vhost-net instance per queue pair, while the device is active
+    vhost_nets: Vec<VhostKernNet<GuestMemoryAtomic<GuestMemoryMmap>>>,
+    guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
 }
 
 #[derive(Versionize)]
@@ -429,6 +498,7 @@ pub struct NetState {
     pub acked_features: u64,
     pub config: VirtioNetConfig,
     pub queue_size: Vec<u16>,
+    pub rx_filter: RxFilter,
 }
 
 impl VersionMapped for NetState {}
@@ -442,7 +512,8 @@ impl Net {
         guest_mac: Option<MacAddr>,
         iommu: bool,
         num_queues: usize,
-        queue_size: u16,
+        rx_queue_size: u16,
+        tx_queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
         exit_evt: EventFd,
@@ -450,12 +521,24 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        promisc: bool,
+        vhost: bool,
     ) -> Result<Self> {
         assert!(!taps.is_empty());
 
         let mtu = taps[0].mtu().map_err(Error::TapError)? as u16;
 
-        let (avail_features, acked_features, config, queue_sizes, paused) =
+        // Fail early if the datapath can't be offloaded, rather than when
+        // the guest activates the device.
+        if vhost {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(VHOST_NET_PATH)
+                .map_err(Error::OpenVhostNet)?;
+        }
+
+        let (avail_features, acked_features, config, queue_sizes, rx_filter, paused) =
             if let Some(state) = state {
                 info!("Restoring virtio-net {}", id);
                 (
@@ -463,6 +546,7 @@ impl Net {
                     state.acked_features,
                     state.config,
                     state.queue_size,
+                    Some(state.rx_filter),
                     true,
                 )
             } else {
@@ -494,7 +578,22 @@ impl Net {
                 }
 
                 avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ;
-                let queue_num = num_queues + 1;
+                // The RX filter is applied by the VMM datapath, which the
+                // kernel one replaces when vhost is enabled.
+                if !vhost {
+                    avail_features |= 1 << VIRTIO_NET_F_CTRL_RX
+                        | 1 << VIRTIO_NET_F_CTRL_VLAN
+                        | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR;
+                }
+
+                // Queues are laid out as RX/TX pairs, followed by the control
+                // queue which doesn't need more than the largest data queue.
+                let mut queue_sizes = Vec::with_capacity(num_queues + 1);
+                for _ in 0..num_queues / 2 {
+                    queue_sizes.push(rx_queue_size);
+                    queue_sizes.push(tx_queue_size);
+                }
+                queue_sizes.push(std::cmp::max(rx_queue_size, tx_queue_size));
 
                 let mut config = VirtioNetConfig::default();
                 if let Some(mac) = guest_mac {
@@ -514,15 +613,18 @@ impl Net {
                     );
                 }
 
-                (
-                    avail_features,
-                    0,
-                    config,
-                    vec![queue_size; queue_num],
-                    false,
-                )
+                (avail_features, 0, config, queue_sizes, None, false)
             };
 
+        let rx_filter_restored = rx_filter.is_some();
+        let rx_filter = rx_filter.unwrap_or_else(|| {
+            let mac = config.mac;
+            RxFilter::new(
+                (avail_features & (1 << VIRTIO_NET_F_MAC) != 0).then_some(&mac[..]),
+                promisc,
+            )
+        });
+
         Ok(Net {
             common: VirtioCommon {
                 device_type: VirtioDeviceType::Net as u32,
@@ -541,24 +643,32 @@ impl Net {
             counters: NetCounters::default(),
             seccomp_action,
             rate_limiter_config,
+            rate_limit_group: None,
+            rx_filter: Arc::new(RwLock::new(rx_filter)),
+            rx_filter_restored,
             exit_evt,
+            vhost,
+            vhost_nets: Vec::new(),
+            guest_memory: None,
         })
     }
 
     /// Create a new virtio network device with the given IP address and
-    /// netmask.
+    /// netmask, or enslaved to the given bridge.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         if_name: Option<&str>,
-        ip_addr: Option<Ipv4Addr>,
-        netmask: Option<Ipv4Addr>,
+        ip_addr: Option<IpAddr>,
+        netmask: Option<IpAddr>,
+        bridge: Option<&str>,
         guest_mac: Option<MacAddr>,
         host_mac: &mut Option<MacAddr>,
         mtu: Option<u16>,
         iommu: bool,
         num_queues: usize,
-        queue_size: u16,
+        rx_queue_size: u16,
+        tx_queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
         exit_evt: EventFd,
@@ -566,11 +676,14 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        promisc: bool,
+        vhost: bool,
     ) -> Result<Self> {
         let taps = open_tap(
             if_name,
             ip_addr,
             netmask,
+            bridge,
             host_mac,
             mtu,
             num_queues / 2,
@@ -584,7 +697,8 @@ impl Net {
             guest_mac,
             iommu,
             num_queues,
-            queue_size,
+            rx_queue_size,
+            tx_queue_size,
             seccomp_action,
             rate_limiter_config,
             exit_evt,
@@ -592,9 +706,22 @@ impl Net {
             offload_tso,
             offload_ufo,
             offload_csum,
+            promisc,
+            vhost,
         )
     }
 
+    /// Tap interfaces backing the device.
+    pub fn taps(&self) -> &[Tap] {
+        &self.taps
+    }
+
+    /// Limit the traffic of the device with the rate limits shared by the
+    /// group, instead of its own rate limiter configuration.
+    pub fn set_rate_limit_group(&mut self, group: NetRateLimitGroup) {
+        self.rate_limit_group = Some(group);
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn from_tap_fds(
         id: String,
@@ -602,7 +729,8 @@ impl Net {
         guest_mac: Option<MacAddr>,
         mtu: Option<u16>,
         iommu: bool,
-        queue_size: u16,
+        rx_queue_size: u16,
+        tx_queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
         exit_evt: EventFd,
@@ -610,6 +738,8 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        promisc: bool,
+        vhost: bool,
     ) -> Result<Self> {
         let mut taps: Vec<Tap> = Vec::new();
         let num_queue_pairs = fds.len();
@@ -637,7 +767,8 @@ impl Net {
             guest_mac,
             iommu,
             num_queue_pairs * 2,
-            queue_size,
+            rx_queue_size,
+            tx_queue_size,
             seccomp_action,
             rate_limiter_config,
             exit_evt,
@@ -645,6 +776,8 @@ impl Net {
             offload_tso,
             offload_ufo,
             offload_csum,
+            promisc,
+            vhost,
         )
     }
 
@@ -654,7 +787,86 @@ impl Net {
             acked_features: self.common.acked_features,
             config: self.config,
             queue_size: self.common.queue_sizes.clone(),
+            rx_filter: self.rx_filter.read().unwrap().clone(),
+        }
+    }
+
+    /// Hand the RX/TX queues over to one vhost-net instance per queue pair,
+    /// so that the host kernel moves the packets between them and the taps.
+    fn activate_vhost(
+        &mut self,
+        mem: &GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: &Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> Result<()> {
+        let regions = vhost_mem_regions(&mem.memory());
+
+        for queue_pair in queues.chunks(2) {
+            let vhost = VhostKernNet::new(mem.clone()).map_err(Error::CreateVhostNet)?;
+            vhost.set_owner().map_err(Error::VhostNetSetOwner)?;
+            // vhost-net only handles the ring features, the offloads and the
+            // header being taken care of by the tap.
+            let features = self.common.acked_features
+                & vhost.get_features().map_err(Error::VhostNetGetFeatures)?;
+            vhost
+                .set_features(features)
+                .map_err(Error::VhostNetSetFeatures)?;
+            vhost
+                .set_mem_table(&regions)
+                .map_err(Error::VhostNetSetMemTable)?;
+
+            for (vring_index, (queue_index, queue, queue_evt)) in queue_pair.iter().enumerate() {
+                vhost
+                    .set_vring_num(vring_index, queue.size())
+                    .map_err(Error::VhostNetSetVringNum)?;
+
+                let config_data = VringConfigData {
+                    queue_max_size: queue.max_size(),
+                    queue_size: queue.size(),
+                    flags: 0u32,
+                    desc_table_addr: queue.desc_table(),
+                    used_ring_addr: queue.used_ring(),
+                    avail_ring_addr: queue.avail_ring(),
+                    log_addr: None,
+                };
+                vhost
+                    .set_vring_addr(vring_index, &config_data)
+                    .map_err(Error::VhostNetSetVringAddr)?;
+                vhost
+                    .set_vring_base(vring_index, queue.next_avail())
+                    .map_err(Error::VhostNetSetVringBase)?;
+
+                let call_evt = interrupt_cb
+                    .notifier(VirtioInterruptType::Queue(*queue_index as u16))
+                    .ok_or(Error::VhostNetMissingInterrupt(*queue_index))?;
+                vhost
+                    .set_vring_call(vring_index, &call_evt)
+                    .map_err(Error::VhostNetSetVringCall)?;
+                vhost
+                    .set_vring_kick(vring_index, queue_evt)
+                    .map_err(Error::VhostNetSetVringKick)?;
+            }
+
+            self.vhost_nets.push(vhost);
         }
+
+        self.guest_memory = Some(mem.clone());
+        self.set_vhost_backends(true)
+    }
+
+    /// Attach the taps to the vhost-net instances, or detach them, which
+    /// starts or stops the processing of the queues by the host kernel.
+    fn set_vhost_backends(&self, attach: bool) -> Result<()> {
+        for (vhost, tap) in self.vhost_nets.iter().zip(self.taps.iter()) {
+            let backend = attach.then_some(tap.tap_file());
+            for vring_index in 0..2 {
+                vhost
+                    .set_backend(vring_index, backend)
+                    .map_err(Error::VhostNetSetBackend)?;
+            }
+        }
+
+        Ok(())
     }
 
     #[cfg(fuzzing)]
@@ -663,6 +875,20 @@ impl Net {
     }
 }
 
+fn vhost_mem_regions(mem: &GuestMemoryMmap) -> Vec<VhostUserMemoryRegionInfo> {
+    mem.iter()
+        .map(|region| VhostUserMemoryRegionInfo {
+            guest_phys_addr: region.start_addr().raw_value(),
+            memory_size: region.len(),
+            userspace_addr: region.as_ptr() as u64,
+            // The kernel accesses the memory through the VMM mappings, no
+            // file has to be shared with it.
+            mmap_offset: 0,
+            mmap_handle: -1,
+        })
+        .collect()
+}
+
 impl Drop for Net {
     fn drop(&mut self) {
         if let Some(kill_evt) = self.common.kill_evt.take() {
@@ -696,6 +922,10 @@ impl VirtioDevice for Net {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
@@ -710,6 +940,12 @@ impl VirtioDevice for Net {
 
         let num_queues = queues.len();
         let event_idx = self.common.feature_acked(VIRTIO_RING_F_EVENT_IDX.into());
+        if !std::mem::take(&mut self.rx_filter_restored) {
+            self.rx_filter.write().unwrap().reset(
+                self.common.feature_acked(VIRTIO_NET_F_CTRL_RX.into()),
+                self.common.feature_acked(VIRTIO_NET_F_CTRL_VLAN.into()),
+            );
+        }
         if self.common.feature_acked(VIRTIO_NET_F_CTRL_VQ.into()) && num_queues % 2 != 0 {
             let ctrl_queue_index = num_queues - 1;
             let (_, mut ctrl_queue, ctrl_queue_evt) = queues.remove(ctrl_queue_index);
@@ -721,7 +957,7 @@ impl VirtioDevice for Net {
                 mem: mem.clone(),
                 kill_evt,
                 pause_evt,
-                ctrl_q: CtrlQueue::new(self.taps.clone()),
+                ctrl_q: CtrlQueue::new(self.taps.clone(), Some(self.rx_filter.clone())),
                 queue: ctrl_queue,
                 queue_evt: ctrl_queue_evt,
                 access_platform: self.common.access_platform.clone(),
@@ -732,8 +968,10 @@ impl VirtioDevice for Net {
             let paused = self.common.paused.clone();
             // Let's update the barrier as we need 1 for each RX/TX pair +
             // 1 for the control queue + 1 for the main thread signalling
-            // the pause.
-            self.common.paused_sync = Some(Arc::new(Barrier::new(self.taps.len() + 2)));
+            // the pause. The RX/TX pairs don't have a thread when they are
+            // processed by vhost-net.
+            let queue_pair_threads = if self.vhost { 0 } else { self.taps.len() };
+            self.common.paused_sync = Some(Arc::new(Barrier::new(queue_pair_threads + 2)));
             let paused_sync = self.common.paused_sync.clone();
 
             let mut epoll_threads = Vec::new();
@@ -746,6 +984,30 @@ impl VirtioDevice for Net {
                 move || ctrl_handler.run_ctrl(paused, paused_sync.unwrap()),
             )?;
             self.ctrl_queue_epoll_thread = Some(epoll_threads.remove(0));
+        } else if self.vhost {
+            // Only the main thread signalling the pause is left.
+            self.common.paused_sync = Some(Arc::new(Barrier::new(1)));
+        }
+
+        if self.vhost {
+            #[cfg(not(fuzzing))]
+            for tap in self.taps.iter() {
+                tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
+                    .map_err(|e| {
+                        error!("Error programming tap offload: {:?}", e);
+                        ActivateError::BadActivate
+                    })?;
+            }
+
+            if let Err(e) = self.activate_vhost(&mem, &interrupt_cb, queues) {
+                // Don't keep the instances set up before the failure around.
+                self.vhost_nets.clear();
+                self.guest_memory = None;
+                return Err(ActivateError::ActivateVhostNet(e));
+            }
+
+            event!("virtio-device", "activated", "id", &self.id);
+            return Ok(());
         }
 
         let mut epoll_threads = Vec::new();
@@ -765,17 +1027,36 @@ impl VirtioDevice for Net {
 
             let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
-            let rx_rate_limiter: Option<rate_limiter::RateLimiter> = self
-                .rate_limiter_config
-                .map(RateLimiterConfig::try_into)
-                .transpose()
-                .map_err(ActivateError::CreateRateLimiter)?;
-
-            let tx_rate_limiter: Option<rate_limiter::RateLimiter> = self
-                .rate_limiter_config
-                .map(RateLimiterConfig::try_into)
-                .transpose()
-                .map_err(ActivateError::CreateRateLimiter)?;
+            let (rx_rate_limiter, tx_rate_limiter) = if let Some(group) = &self.rate_limit_group {
+                (
+                    Some(
+                        group
+                            .rx
+                            .new_rate_limiter()
+                            .map_err(ActivateError::CreateRateLimiter)?,
+                    ),
+                    Some(
+                        group
+                            .tx
+                            .new_rate_limiter()
+                            .map_err(ActivateError::CreateRateLimiter)?,
+                    ),
+                )
+            } else {
+                let rx_rate_limiter: Option<rate_limiter::RateLimiter> = self
+                    .rate_limiter_config
+                    .map(RateLimiterConfig::try_into)
+                    .transpose()
+                    .map_err(ActivateError::CreateRateLimiter)?;
+
+                let tx_rate_limiter: Option<rate_limiter::RateLimiter> = self
+                    .rate_limiter_config
+                    .map(RateLimiterConfig::try_into)
+                    .transpose()
+                    .map_err(ActivateError::CreateRateLimiter)?;
+
+                (rx_rate_limiter, tx_rate_limiter)
+            };
 
             let tap = taps.remove(0);
             #[cfg(not(fuzzing))]
@@ -800,6 +1081,7 @@ impl VirtioDevice for Net {
                     rx_desc_avail: false,
                     rx_rate_limiter,
                     tx_rate_limiter,
+                    rx_filter: Some(self.rx_filter.clone()),
                     access_platform: self.common.access_platform.clone(),
                 },
                 mem: mem.clone(),
@@ -832,6 +1114,10 @@ impl VirtioDevice for Net {
     }
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        // Closing the vhost-net instances stops the kernel datapath.
+        self.vhost_nets.clear();
+        self.guest_memory = None;
+
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
         result
@@ -863,15 +1149,35 @@ impl VirtioDevice for Net {
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
+
+    fn add_memory_region(
+        &mut self,
+        _region: &Arc<GuestRegionMmap>,
+    ) -> result::Result<(), DeviceError> {
+        if let Some(guest_memory) = &self.guest_memory {
+            let regions = vhost_mem_regions(&guest_memory.memory());
+            for vhost in self.vhost_nets.iter() {
+                vhost
+                    .set_mem_table(&regions)
+                    .map_err(DeviceError::VhostNetUpdateMemory)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Pausable for Net {
     fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.set_vhost_backends(false)
+            .map_err(|e| MigratableError::Pause(anyhow!("{}", e)))?;
         self.common.pause()
     }
 
     fn resume(&mut self) -> result::Result<(), MigratableError> {
         self.common.resume()?;
+        self.set_vhost_backends(true)
+            .map_err(|e| MigratableError::Resume(anyhow!("{}", e)))?;
 
         if let Some(ctrl_queue_epoll_thread) = &self.ctrl_queue_epoll_thread {
             ctrl_queue_epoll_thread.thread().unpark();
@@ -886,8 +1192,26 @@ impl Snapshottable for Net {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        // The position of the kernel datapath in the queues isn't retrieved.
+        if self.vhost {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshotting a virtio-net device using vhost-net is not supported"
+            )));
+        }
+
         Snapshot::new_from_versioned_state(&self.state())
     }
 }
 impl Transportable for Net {}
-impl Migratable for Net {}
+impl Migratable for Net {
+    fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        // Pages written by the kernel datapath aren't logged.
+        if self.vhost {
+            return Err(MigratableError::StartDirtyLog(anyhow!(
+                "Migrating a virtio-net device using vhost-net is not supported"
+            )));
+        }
+
+        Ok(())
+    }
+}
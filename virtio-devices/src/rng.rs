@@ -15,26 +15,62 @@ use crate::{VirtioInterrupt, VirtioInterruptType};
 use anyhow::anyhow;
 use seccompiler::SeccompAction;
 use std::fs::File;
-use std::io;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_queue::{Queue, QueueT};
-use vm_memory::{Bytes, GuestAddressSpace, GuestMemoryAtomic};
+use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 use vm_virtio::{AccessPlatform, Translatable};
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::timerfd::TimerFd;
 
-const QUEUE_SIZE: u16 = 256;
-const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+/// Default size of the virtio-rng queue.
+pub const DEFAULT_RNG_QUEUE_SIZE: u16 = 256;
+
+// Maximum amount of entropy fetched from the source at once, bounding the
+// buffer used to serve a batch of requests.
+const MAX_BATCH_LEN: usize = 1 << 20;
 
 // New descriptors are pending on the virtio queue.
 const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// The EGD socket is readable.
+const EGD_SOCKET_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// Periodic check of the EGD connection.
+const EGD_TIMER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+
+// EGD command reading entropy, blocking until the requested amount of bytes
+// is available.
+const EGD_CMD_READ_BLOCKING: u8 = 0x02;
+// Maximum amount of bytes that can be requested by a single EGD command.
+const EGD_MAX_READ_LEN: usize = 255;
+// Period at which the EGD connection is checked, either to reconnect to the
+// daemon or to time out a command it doesn't answer.
+const EGD_TIMER_PERIOD: Duration = Duration::from_secs(1);
+// Time given to the daemon to answer a command before reconnecting to it.
+const EGD_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Size of the buffer walked by the jitter entropy collector between two time
+// stamps, exceeding the L1 cache so that the access time varies.
+const JITTER_MEMORY_SIZE: usize = 64 << 10;
+// Number of memory locations updated between two time stamps.
+const JITTER_MEMORY_ACCESSES: usize = 128;
+// Number of time stamp deltas folded into each 64 bits of output, assuming
+// each of them holds at least half a bit of entropy.
+const JITTER_OVERSAMPLING: usize = 128;
+// Number of time stamps taken when creating the collector, to check that the
+// timer is fine grained enough to observe the jitter.
+const JITTER_STARTUP_SAMPLES: usize = 1024;
 
 #[derive(Error, Debug)]
 enum Error {
@@ -46,12 +82,336 @@ enum Error {
     GuestMemoryWrite(vm_memory::guest_memory::Error),
     #[error("Failed adding used index: {0}")]
     QueueAddUsed(virtio_queue::Error),
+    #[error("Failed disabling notification: {0}")]
+    QueueDisableNotification(virtio_queue::Error),
+    #[error("Failed enabling notification: {0}")]
+    QueueEnableNotification(virtio_queue::Error),
+    #[error("Failed reading from the entropy source: {0}")]
+    ReadSource(io::Error),
+}
+
+/// Entropy collected from the variations of the CPU execution time, in the
+/// spirit of the jitterentropy library, for hosts lacking any other source.
+///
+/// The time taken by a series of memory accesses is measured repeatedly, and
+/// the deltas between the time stamps are folded into a pool. The samples
+/// showing no variation are discarded, as they can't carry any entropy.
+struct JitterEntropy {
+    memory: Vec<u8>,
+    position: usize,
+    start: Instant,
+    pool: u64,
+    last_time: u64,
+    last_delta: i64,
+    last_delta2: i64,
+}
+
+impl JitterEntropy {
+    fn new() -> io::Result<Self> {
+        let mut jitter = JitterEntropy {
+            memory: vec![0u8; JITTER_MEMORY_SIZE],
+            position: 0,
+            start: Instant::now(),
+            pool: 0,
+            last_time: 0,
+            last_delta: 0,
+            last_delta2: 0,
+        };
+
+        let valid_samples = (0..JITTER_STARTUP_SAMPLES)
+            .filter(|_| jitter.sample().is_some())
+            .count();
+        if valid_samples < JITTER_STARTUP_SAMPLES / 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The timer is too coarse to collect jitter entropy",
+            ));
+        }
+
+        Ok(jitter)
+    }
+
+    fn memory_access(&mut self) {
+        for _ in 0..JITTER_MEMORY_ACCESSES {
+            // Jump over several cache lines at each access.
+            self.position = (self.position + 67) % self.memory.len();
+            let location = &mut self.memory[self.position] as *mut u8;
+            // SAFETY: the location points into the buffer. The accesses are
+            // volatile so that they are not optimized out.
+            unsafe {
+                std::ptr::write_volatile(
+                    location,
+                    std::ptr::read_volatile(location).wrapping_add(1),
+                )
+            };
+        }
+    }
+
+    fn sample(&mut self) -> Option<u64> {
+        self.memory_access();
+
+        let time = self.start.elapsed().as_nanos() as u64;
+        let delta = time.wrapping_sub(self.last_time) as i64;
+        let delta2 = delta.wrapping_sub(self.last_delta);
+        let delta3 = delta2.wrapping_sub(self.last_delta2);
+        self.last_time = time;
+        self.last_delta = delta;
+        self.last_delta2 = delta2;
+
+        (delta != 0 && delta2 != 0 && delta3 != 0).then(|| delta as u64)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut samples = 0;
+        while samples < JITTER_OVERSAMPLING {
+            if let Some(delta) = self.sample() {
+                self.pool = (self.pool.rotate_left(7) ^ delta).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                samples += 1;
+            }
+        }
+
+        // Spread the variations of the low order bits over the output.
+        let mut z = self.pool;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+// Pending guest requests, as descriptor head index, address and length.
+type EntropyRequest = (u16, GuestAddress, usize);
+
+// Entropy being received from EGD for a batch of guest requests.
+struct EgdRead {
+    requests: Vec<EntropyRequest>,
+    data: Vec<u8>,
+    // Amount of bytes received so far.
+    received: usize,
+    // Amount of bytes expected once the daemon answered the last command.
+    requested: usize,
+    // Time by which the daemon must have answered the last command.
+    deadline: Instant,
+}
+
+/// Connection to an entropy gathering daemon. The socket is non-blocking and
+/// driven by the epoll loop of the device, so that a slow or unresponsive
+/// daemon never holds the thread. The connection is re-established if it is
+/// lost or if the daemon stops answering.
+struct EgdSource {
+    path: PathBuf,
+    stream: Option<UnixStream>,
+    timer: TimerFd,
+    read: Option<EgdRead>,
+}
+
+impl EgdSource {
+    fn new(path: PathBuf, stream: Option<UnixStream>) -> io::Result<Self> {
+        if let Some(stream) = &stream {
+            stream.set_nonblocking(true)?;
+        }
+
+        Ok(EgdSource {
+            path,
+            stream,
+            timer: TimerFd::new()?,
+            read: None,
+        })
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        let stream = self.stream.as_ref().map(|s| s.try_clone()).transpose()?;
+        Self::new(self.path.clone(), stream)
+    }
+
+    fn register(&mut self, helper: &mut EpollHelper) -> result::Result<(), EpollHelperError> {
+        self.timer
+            .reset(EGD_TIMER_PERIOD, Some(EGD_TIMER_PERIOD))
+            .map_err(|e| EpollHelperError::IoError(e.into()))?;
+        helper.add_event(self.timer.as_raw_fd(), EGD_TIMER_EVENT)?;
+        if let Some(stream) = &self.stream {
+            helper.add_event(stream.as_raw_fd(), EGD_SOCKET_EVENT)?;
+        }
+
+        Ok(())
+    }
+
+    fn start_read(
+        &mut self,
+        helper: &mut EpollHelper,
+        requests: Vec<EntropyRequest>,
+        data: Vec<u8>,
+    ) {
+        self.read = Some(EgdRead {
+            requests,
+            data,
+            received: 0,
+            requested: 0,
+            deadline: Instant::now(),
+        });
+        // Without a connection, the command is sent once reconnected.
+        self.send_command(helper);
+    }
+
+    fn send_command(&mut self, helper: &mut EpollHelper) {
+        let result = match (self.stream.as_mut(), self.read.as_mut()) {
+            (Some(stream), Some(read)) => {
+                let len = std::cmp::min(EGD_MAX_READ_LEN, read.data.len() - read.received);
+                // The command is small enough to never fill the socket buffer.
+                stream
+                    .write_all(&[EGD_CMD_READ_BLOCKING, len as u8])
+                    .map(|_| {
+                        read.requested = read.received + len;
+                        read.deadline = Instant::now() + EGD_READ_TIMEOUT;
+                    })
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            self.disconnect(helper, &e);
+        }
+    }
+
+    fn disconnect(&mut self, helper: &mut EpollHelper, e: &io::Error) {
+        if let Some(stream) = self.stream.take() {
+            warn!("Lost the connection to EGD, reconnecting: {}", e);
+            if let Err(e) = helper.del_event_custom(
+                stream.as_raw_fd(),
+                EGD_SOCKET_EVENT,
+                epoll::Events::EPOLLIN,
+            ) {
+                error!("Failed removing the EGD socket from epoll: {:?}", e);
+            }
+        }
+        // The entropy received so far is kept, only the rest is requested
+        // again once reconnected.
+        if let Some(read) = self.read.as_mut() {
+            read.requested = read.received;
+        }
+    }
+
+    fn reconnect(&mut self, helper: &mut EpollHelper) -> result::Result<(), EpollHelperError> {
+        let stream = match UnixStream::connect(&self.path).and_then(|stream| {
+            stream.set_nonblocking(true)?;
+            Ok(stream)
+        }) {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Failed reconnecting to EGD: {}", e);
+                return Ok(());
+            }
+        };
+
+        helper.add_event(stream.as_raw_fd(), EGD_SOCKET_EVENT)?;
+        self.stream = Some(stream);
+        info!("Reconnected to EGD");
+        self.send_command(helper);
+
+        Ok(())
+    }
+
+    fn handle_timer(&mut self, helper: &mut EpollHelper) -> result::Result<(), EpollHelperError> {
+        self.timer
+            .wait()
+            .map_err(|e| EpollHelperError::IoError(e.into()))?;
+
+        if self.stream.is_none() {
+            return self.reconnect(helper);
+        }
+
+        let timed_out = self.read.as_ref().map_or(false, |read| {
+            read.received < read.requested && Instant::now() >= read.deadline
+        });
+        if timed_out {
+            let e = io::Error::new(io::ErrorKind::TimedOut, "No answer from the daemon");
+            self.disconnect(helper, &e);
+        }
+
+        Ok(())
+    }
+
+    // Returns the read once all the entropy it needs has been received.
+    fn receive(&mut self, helper: &mut EpollHelper) -> Option<EgdRead> {
+        loop {
+            let stream = self.stream.as_mut()?;
+            let result = match self.read.as_mut() {
+                Some(read) if read.received < read.requested => stream
+                    .read(&mut read.data[read.received..read.requested])
+                    .map(|len| {
+                        read.received += len;
+                        len
+                    }),
+                // Nothing is expected from the daemon, besides closing the
+                // connection.
+                _ => stream.read(&mut [0u8; 1]).and_then(|len| {
+                    if len > 0 {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Unexpected data from the daemon",
+                        ))
+                    } else {
+                        Ok(len)
+                    }
+                }),
+            };
+
+            match result {
+                Ok(0) => {
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed");
+                    self.disconnect(helper, &e);
+                    return None;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.disconnect(helper, &e);
+                    return None;
+                }
+            }
+        }
+
+        let (received, requested, len) = self
+            .read
+            .as_ref()
+            .map(|read| (read.received, read.requested, read.data.len()))?;
+        if received < requested {
+            None
+        } else if received < len {
+            self.send_command(helper);
+            None
+        } else {
+            self.read.take()
+        }
+    }
+}
+
+enum EntropySource {
+    File(File),
+    Egd(Box<EgdSource>),
+    Jitter(Box<JitterEntropy>),
+}
+
+impl EntropySource {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            EntropySource::File(file) => EntropySource::File(file.try_clone()?),
+            EntropySource::Egd(egd) => EntropySource::Egd(Box::new(egd.try_clone()?)),
+            // Each collector gathers its own entropy.
+            EntropySource::Jitter(_) => EntropySource::Jitter(Box::new(JitterEntropy::new()?)),
+        })
+    }
 }
 
 struct RngEpollHandler {
     mem: GuestMemoryAtomic<GuestMemoryMmap>,
     queue: Queue,
-    random_file: File,
+    source: EntropySource,
     interrupt_cb: Arc<dyn VirtioInterrupt>,
     queue_evt: EventFd,
     kill_evt: EventFd,
@@ -60,38 +420,104 @@ struct RngEpollHandler {
 }
 
 impl RngEpollHandler {
-    fn process_queue(&mut self) -> result::Result<bool, Error> {
-        let queue = &mut self.queue;
-
+    fn process_queue(&mut self, helper: &mut EpollHelper) -> result::Result<bool, Error> {
         let mut used_descs = false;
-        while let Some(mut desc_chain) = queue.pop_descriptor_chain(self.mem.memory()) {
-            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+        // The requests queued while waiting for EGD are picked up once the
+        // pending ones are completed.
+        if let EntropySource::Egd(egd) = &self.source {
+            if egd.read.is_some() {
+                return Ok(used_descs);
+            }
+        }
+
+        let mem = self.mem.memory();
+        loop {
+            // The guest doesn't need to notify the requests queued while the
+            // pending ones are being processed, as they'll be picked up here.
+            self.queue
+                .disable_notification(mem.deref())
+                .map_err(Error::QueueDisableNotification)?;
+
+            // Gather the pending requests to fetch their entropy at once.
+            let mut requests = Vec::new();
+            let mut batch_len = 0;
+            while batch_len < MAX_BATCH_LEN {
+                let mut desc_chain = match self.queue.pop_descriptor_chain(mem.deref()) {
+                    Some(desc_chain) => desc_chain,
+                    None => break,
+                };
+                let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+
+                // The descriptor must be write-only and non-zero length
+                if !(desc.is_write_only() && desc.len() > 0) {
+                    return Err(Error::InvalidDescriptor);
+                }
 
-            // The descriptor must be write-only and non-zero length
-            if !(desc.is_write_only() && desc.len() > 0) {
-                return Err(Error::InvalidDescriptor);
+                let len = desc.len() as usize;
+                let addr = desc
+                    .addr()
+                    .translate_gva(self.access_platform.as_ref(), len);
+                requests.push((desc_chain.head_index(), addr, len));
+                batch_len += len;
             }
 
-            // Fill the read with data from the random device on the host.
-            let len = desc_chain
-                .memory()
-                .read_from(
-                    desc.addr()
-                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
-                    &mut self.random_file,
-                    desc.len() as usize,
-                )
-                .map_err(Error::GuestMemoryWrite)?;
+            if !requests.is_empty() {
+                let mut data = vec![0u8; batch_len];
+                match &mut self.source {
+                    // Fill the reads with data from the random device on the host.
+                    EntropySource::File(file) => {
+                        file.read_exact(&mut data).map_err(Error::ReadSource)?
+                    }
+                    // The requests are completed once the daemon sent all
+                    // the entropy they need, rather than with data which
+                    // isn't random.
+                    EntropySource::Egd(egd) => {
+                        egd.start_read(helper, requests, data);
+                        return Ok(used_descs);
+                    }
+                    EntropySource::Jitter(jitter) => jitter.fill(&mut data),
+                }
 
-            queue
-                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
-                .map_err(Error::QueueAddUsed)?;
-            used_descs = true;
+                self.complete_requests(&requests, &data)?;
+                used_descs = true;
+
+                // Leave the notifications disabled if the batch was cut short.
+                if batch_len >= MAX_BATCH_LEN {
+                    continue;
+                }
+            }
+
+            if !self
+                .queue
+                .enable_notification(mem.deref())
+                .map_err(Error::QueueEnableNotification)?
+            {
+                break;
+            }
         }
 
         Ok(used_descs)
     }
 
+    fn complete_requests(
+        &mut self,
+        requests: &[EntropyRequest],
+        data: &[u8],
+    ) -> result::Result<(), Error> {
+        let mem = self.mem.memory();
+        let mut offset = 0;
+        for (head_index, addr, len) in requests {
+            mem.write_slice(&data[offset..offset + len], *addr)
+                .map_err(Error::GuestMemoryWrite)?;
+            self.queue
+                .add_used(mem.deref(), *head_index, *len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            offset += len;
+        }
+
+        Ok(())
+    }
+
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
         self.interrupt_cb
             .trigger(VirtioInterruptType::Queue(0))
@@ -108,6 +534,9 @@ impl RngEpollHandler {
     ) -> result::Result<(), EpollHelperError> {
         let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
         helper.add_event(self.queue_evt.as_raw_fd(), QUEUE_AVAIL_EVENT)?;
+        if let EntropySource::Egd(egd) = &mut self.source {
+            egd.register(&mut helper)?;
+        }
         helper.run(paused, paused_sync, self)?;
 
         Ok(())
@@ -117,7 +546,7 @@ impl RngEpollHandler {
 impl EpollHelperHandler for RngEpollHandler {
     fn handle_event(
         &mut self,
-        _helper: &mut EpollHelper,
+        helper: &mut EpollHelper,
         event: &epoll::Event,
     ) -> result::Result<(), EpollHelperError> {
         let ev_type = event.data as u16;
@@ -126,7 +555,7 @@ impl EpollHelperHandler for RngEpollHandler {
                 self.queue_evt.read().map_err(|e| {
                     EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
                 })?;
-                let needs_notification = self.process_queue().map_err(|e| {
+                let needs_notification = self.process_queue(helper).map_err(|e| {
                     EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
                 })?;
                 if needs_notification {
@@ -138,6 +567,36 @@ impl EpollHelperHandler for RngEpollHandler {
                     })?;
                 }
             }
+            EGD_SOCKET_EVENT => {
+                let read = match &mut self.source {
+                    EntropySource::Egd(egd) => egd.receive(helper),
+                    _ => None,
+                };
+                if let Some(read) = read {
+                    self.complete_requests(&read.requests, &read.data)
+                        .map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!(
+                                "Failed to complete requests: {:?}",
+                                e
+                            ))
+                        })?;
+                    // Pick up the requests queued in the meantime.
+                    self.process_queue(helper).map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
+                    })?;
+                    self.signal_used_queue().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to signal used queue: {:?}",
+                            e
+                        ))
+                    })?;
+                }
+            }
+            EGD_TIMER_EVENT => {
+                if let EntropySource::Egd(egd) = &mut self.source {
+                    egd.handle_timer(helper)?;
+                }
+            }
             _ => {
                 return Err(EpollHelperError::HandleEvent(anyhow!(
                     "Unexpected event: {}",
@@ -153,7 +612,7 @@ impl EpollHelperHandler for RngEpollHandler {
 pub struct Rng {
     common: VirtioCommon,
     id: String,
-    random_file: Option<File>,
+    source: Option<EntropySource>,
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
 }
@@ -172,12 +631,81 @@ impl Rng {
         id: String,
         path: &str,
         iommu: bool,
+        queue_size: u16,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
         state: Option<RngState>,
     ) -> io::Result<Rng> {
         let random_file = File::open(path)?;
 
+        Ok(Self::with_source(
+            id,
+            EntropySource::File(random_file),
+            iommu,
+            queue_size,
+            seccomp_action,
+            exit_evt,
+            state,
+        ))
+    }
+
+    /// Create a new virtio rng device that gets random data from an entropy
+    /// gathering daemon listening on the UNIX socket at `socket_path`.
+    pub fn new_egd(
+        id: String,
+        socket_path: &Path,
+        iommu: bool,
+        queue_size: u16,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<RngState>,
+    ) -> io::Result<Rng> {
+        let stream = UnixStream::connect(socket_path)?;
+        let egd = EgdSource::new(socket_path.to_path_buf(), Some(stream))?;
+
+        Ok(Self::with_source(
+            id,
+            EntropySource::Egd(Box::new(egd)),
+            iommu,
+            queue_size,
+            seccomp_action,
+            exit_evt,
+            state,
+        ))
+    }
+
+    /// Create a new virtio rng device that gets random data from the jitter
+    /// of the CPU execution time, collected by the VMM itself.
+    pub fn new_jitter(
+        id: String,
+        iommu: bool,
+        queue_size: u16,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<RngState>,
+    ) -> io::Result<Rng> {
+        let jitter = JitterEntropy::new()?;
+
+        Ok(Self::with_source(
+            id,
+            EntropySource::Jitter(Box::new(jitter)),
+            iommu,
+            queue_size,
+            seccomp_action,
+            exit_evt,
+            state,
+        ))
+    }
+
+    fn with_source(
+        id: String,
+        source: EntropySource,
+        iommu: bool,
+        queue_size: u16,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<RngState>,
+    ) -> Rng {
         let (avail_features, acked_features, paused) = if let Some(state) = state {
             info!("Restoring virtio-rng {}", id);
             (state.avail_features, state.acked_features, true)
@@ -191,10 +719,10 @@ impl Rng {
             (avail_features, 0, false)
         };
 
-        Ok(Rng {
+        Rng {
             common: VirtioCommon {
                 device_type: VirtioDeviceType::Rng as u32,
-                queue_sizes: QUEUE_SIZES.to_vec(),
+                queue_sizes: vec![queue_size],
                 paused_sync: Some(Arc::new(Barrier::new(2))),
                 avail_features,
                 acked_features,
@@ -203,10 +731,10 @@ impl Rng {
                 ..Default::default()
             },
             id,
-            random_file: Some(random_file),
+            source: Some(source),
             seccomp_action,
             exit_evt,
-        })
+        }
     }
 
     fn state(&self) -> RngState {
@@ -249,6 +777,10 @@ impl VirtioDevice for Rng {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn activate(
         &mut self,
         mem: GuestMemoryAtomic<GuestMemoryMmap>,
@@ -258,8 +790,8 @@ impl VirtioDevice for Rng {
         self.common.activate(&queues, &interrupt_cb)?;
         let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
-        if let Some(file) = self.random_file.as_ref() {
-            let random_file = file.try_clone().map_err(|e| {
+        if let Some(source) = self.source.as_ref() {
+            let source = source.try_clone().map_err(|e| {
                 error!("failed cloning rng source: {}", e);
                 ActivateError::BadActivate
             })?;
@@ -269,7 +801,7 @@ impl VirtioDevice for Rng {
             let mut handler = RngEpollHandler {
                 mem,
                 queue,
-                random_file,
+                source,
                 interrupt_cb,
                 queue_evt,
                 kill_evt,
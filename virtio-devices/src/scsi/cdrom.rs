@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Read-only MMC logical unit backed by an ISO image.
+
+use super::{
+    be16, be32, truncate, LogicalUnit, Sense, GET_CONFIGURATION, GET_EVENT_STATUS_NOTIFICATION,
+    INQUIRY, MODE_PAGE_ALL, MODE_SENSE_10, MODE_SENSE_6, PREVENT_ALLOW_MEDIUM_REMOVAL, READ_10,
+    READ_12, READ_CAPACITY_10, READ_TOC, REQUEST_SENSE, START_STOP_UNIT, TEST_UNIT_READY,
+};
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::result;
+
+const CDROM_BLOCK_SIZE: u64 = 2048;
+// Anything larger than a 80 minutes CD is reported as a DVD.
+const CDROM_MAX_BLOCKS: u64 = 360_000;
+const MMC_PROFILE_CDROM: u16 = 0x0008;
+const MMC_PROFILE_DVDROM: u16 = 0x0010;
+const MODE_PAGE_CAPABILITIES: u8 = 0x2a;
+
+pub(super) struct CdRom {
+    file: File,
+    nblocks: u64,
+}
+
+impl CdRom {
+    pub(super) fn new(mut file: File) -> io::Result<Self> {
+        let size = file.seek(SeekFrom::End(0))?;
+        Ok(CdRom {
+            file,
+            nblocks: (size + CDROM_BLOCK_SIZE - 1) / CDROM_BLOCK_SIZE,
+        })
+    }
+
+    fn inquiry(&self, cdb: &[u8]) -> result::Result<Vec<u8>, Sense> {
+        // Vital product data: only the list of supported pages is provided.
+        if cdb[1] & 0x1 != 0 {
+            if cdb[2] != 0x00 {
+                return Err(Sense::INVALID_FIELD_IN_CDB);
+            }
+            return Ok(vec![0x05, 0x00, 0x00, 0x01, 0x00]);
+        }
+
+        let mut data = vec![
+            0x05, // CD/DVD device
+            0x80, // Removable medium
+            0x05, // SPC-3
+            0x02, // Response data format
+            31,   // Additional length
+            0x00, 0x00, 0x00,
+        ];
+        data.extend(b"CLOUDHYP");
+        data.extend(b"Virtual CD-ROM  ");
+        data.extend(b"1.0 ");
+        Ok(data)
+    }
+
+    fn read(&self, lba: u64, count: u64, max_len: usize) -> result::Result<Vec<u8>, Sense> {
+        match lba.checked_add(count) {
+            Some(end) if end <= self.nblocks => {}
+            _ => return Err(Sense::LBA_OUT_OF_RANGE),
+        }
+
+        // The command fails if the guest didn't provide enough space for
+        // the whole transfer.
+        if count * CDROM_BLOCK_SIZE > max_len as u64 {
+            return Err(Sense::DATA_PHASE_ERROR);
+        }
+        let len = (count * CDROM_BLOCK_SIZE) as usize;
+        let offset = lba * CDROM_BLOCK_SIZE;
+        let mut data = vec![0u8; len];
+        let mut read = 0;
+        // The last block of an image which size isn't a multiple of the block
+        // size is padded with zeroes.
+        while read < len {
+            match self.file.read_at(&mut data[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error!("Failed reading from CD-ROM image: {}", e);
+                    return Err(Sense::UNRECOVERED_READ_ERROR);
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn mode_sense(&self, page_code: u8, ten: bool) -> result::Result<Vec<u8>, Sense> {
+        let page = page_code & 0x3f;
+        let changeable = page_code >> 6 == 1;
+        if page != MODE_PAGE_CAPABILITIES && page != MODE_PAGE_ALL {
+            return Err(Sense::INVALID_FIELD_IN_CDB);
+        }
+
+        // MM capabilities and mechanical status page, none of its values can
+        // be changed.
+        let mut capabilities = vec![0u8; 20];
+        capabilities[0] = MODE_PAGE_CAPABILITIES;
+        capabilities[1] = 18;
+        if !changeable {
+            // Reads CD-R, CD-RW and DVD-ROM media.
+            capabilities[2] = 0x0b;
+            // Tray loading mechanism, with eject and lock support.
+            capabilities[6] = 0x29;
+        }
+
+        let mut data = if ten {
+            let len = (6 + capabilities.len()) as u16;
+            let mut header = vec![0u8; 8];
+            header[0..2].copy_from_slice(&len.to_be_bytes());
+            header
+        } else {
+            vec![(3 + capabilities.len()) as u8, 0, 0, 0]
+        };
+        data.extend(capabilities);
+        Ok(data)
+    }
+
+    fn read_toc(&self, cdb: &[u8]) -> result::Result<Vec<u8>, Sense> {
+        let msf = cdb[1] & 0x2 != 0;
+        let format = cdb[2] & 0xf;
+        let start_track = cdb[6];
+
+        // A single data track, in a single session.
+        let mut data = vec![0, 0, 1, 1];
+        match format {
+            // Table of contents
+            0 => {
+                if start_track > 1 && start_track != 0xaa {
+                    return Err(Sense::INVALID_FIELD_IN_CDB);
+                }
+                if start_track <= 1 {
+                    data.extend(toc_descriptor(1, 0, msf));
+                }
+                data.extend(toc_descriptor(0xaa, self.nblocks, msf));
+            }
+            // Multi-session information
+            1 => data.extend(toc_descriptor(1, 0, msf)),
+            _ => return Err(Sense::INVALID_FIELD_IN_CDB),
+        }
+
+        let len = (data.len() - 2) as u16;
+        data[0..2].copy_from_slice(&len.to_be_bytes());
+        Ok(data)
+    }
+
+    fn configuration(&self) -> Vec<u8> {
+        let profile = if self.nblocks > CDROM_MAX_BLOCKS {
+            MMC_PROFILE_DVDROM
+        } else {
+            MMC_PROFILE_CDROM
+        };
+
+        // Feature header only, reporting the current profile.
+        let mut data = vec![0u8; 8];
+        data[0..4].copy_from_slice(&4u32.to_be_bytes());
+        data[6..8].copy_from_slice(&profile.to_be_bytes());
+        data
+    }
+}
+
+impl LogicalUnit for CdRom {
+    fn execute(
+        &self,
+        cdb: &[u8],
+        _data_out: &[u8],
+        max_len: usize,
+    ) -> result::Result<Vec<u8>, Sense> {
+        match cdb[0] {
+            TEST_UNIT_READY | START_STOP_UNIT | PREVENT_ALLOW_MEDIUM_REMOVAL => Ok(Vec::new()),
+            REQUEST_SENSE => Ok(truncate(
+                Sense::NO_SENSE.to_bytes().to_vec(),
+                cdb[4] as usize,
+                max_len,
+            )),
+            INQUIRY => Ok(truncate(
+                self.inquiry(cdb)?,
+                be16(&cdb[3..5]) as usize,
+                max_len,
+            )),
+            READ_CAPACITY_10 => {
+                let last_lba = cmp::min(self.nblocks.saturating_sub(1), u32::MAX as u64) as u32;
+                let mut data = last_lba.to_be_bytes().to_vec();
+                data.extend((CDROM_BLOCK_SIZE as u32).to_be_bytes());
+                Ok(truncate(data, 8, max_len))
+            }
+            READ_10 => {
+                let lba = be32(&cdb[2..6]) as u64;
+                let count = be16(&cdb[7..9]) as u64;
+                self.read(lba, count, max_len)
+            }
+            READ_12 => {
+                let lba = be32(&cdb[2..6]) as u64;
+                let count = be32(&cdb[6..10]) as u64;
+                self.read(lba, count, max_len)
+            }
+            MODE_SENSE_6 => Ok(truncate(
+                self.mode_sense(cdb[2], false)?,
+                cdb[4] as usize,
+                max_len,
+            )),
+            MODE_SENSE_10 => Ok(truncate(
+                self.mode_sense(cdb[2], true)?,
+                be16(&cdb[7..9]) as usize,
+                max_len,
+            )),
+            READ_TOC => Ok(truncate(
+                self.read_toc(cdb)?,
+                be16(&cdb[7..9]) as usize,
+                max_len,
+            )),
+            GET_CONFIGURATION => Ok(truncate(
+                self.configuration(),
+                be16(&cdb[7..9]) as usize,
+                max_len,
+            )),
+            GET_EVENT_STATUS_NOTIFICATION => {
+                // Only the polled operation is supported.
+                if cdb[1] & 0x1 == 0 {
+                    return Err(Sense::INVALID_FIELD_IN_CDB);
+                }
+                Ok(truncate(
+                    event_status(cdb[4]),
+                    be16(&cdb[7..9]) as usize,
+                    max_len,
+                ))
+            }
+            _ => {
+                debug!("Unsupported MMC command: {:#x}", cdb[0]);
+                Err(Sense::INVALID_OPCODE)
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn LogicalUnit>> {
+        Ok(Box::new(CdRom {
+            file: self.file.try_clone()?,
+            nblocks: self.nblocks,
+        }))
+    }
+}
+
+fn toc_descriptor(track: u8, lba: u64, msf: bool) -> [u8; 8] {
+    let addr = if msf {
+        // Addresses are offset by the 2 seconds lead-in.
+        let lba = lba + 150;
+        [
+            0,
+            (lba / (75 * 60)) as u8,
+            ((lba / 75) % 60) as u8,
+            (lba % 75) as u8,
+        ]
+    } else {
+        (lba as u32).to_be_bytes()
+    };
+
+    // Data track, with the current position as Q sub-channel.
+    [0, 0x14, track, 0, addr[0], addr[1], addr[2], addr[3]]
+}
+
+fn event_status(requested_classes: u8) -> Vec<u8> {
+    const MEDIA_CLASS: u8 = 1 << 4;
+
+    if requested_classes & MEDIA_CLASS != 0 {
+        // No media change, media present.
+        vec![0, 6, 0x04, MEDIA_CLASS, 0x00, 0x02, 0x00, 0x00]
+    } else {
+        // No event available.
+        vec![0, 2, 0x80, MEDIA_CLASS]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use vmm_sys_util::tempfile::TempFile;
+
+    // Image which size isn't a multiple of the block size.
+    fn create_cdrom() -> CdRom {
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&vec![0xa5u8; 2 * CDROM_BLOCK_SIZE as usize + 100])
+            .unwrap();
+        CdRom::new(file).unwrap()
+    }
+
+    fn cdb(bytes: &[u8]) -> Vec<u8> {
+        let mut cdb = bytes.to_vec();
+        cdb.resize(32, 0);
+        cdb
+    }
+
+    #[test]
+    fn test_inquiry() {
+        let cdrom = create_cdrom();
+
+        let data = cdrom
+            .execute(&cdb(&[INQUIRY, 0, 0, 0, 36]), &[], 512)
+            .unwrap();
+        assert_eq!(data[0], 0x05);
+        assert_eq!(data[1], 0x80);
+        assert_eq!(
+            cdrom.execute(&cdb(&[INQUIRY, 1, 0x83, 0, 255]), &[], 512),
+            Err(Sense::INVALID_FIELD_IN_CDB)
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let cdrom = create_cdrom();
+
+        let data = cdrom.execute(&cdb(&[READ_CAPACITY_10]), &[], 512).unwrap();
+        assert_eq!(be32(&data[0..4]), 2);
+        assert_eq!(be32(&data[4..8]) as u64, CDROM_BLOCK_SIZE);
+
+        // The last block is padded with zeroes.
+        let read_10 = cdb(&[READ_10, 0, 0, 0, 0, 2, 0, 0, 1]);
+        let data = cdrom.execute(&read_10, &[], 4096).unwrap();
+        assert_eq!(data.len(), CDROM_BLOCK_SIZE as usize);
+        assert!(data[..100].iter().all(|b| *b == 0xa5));
+        assert!(data[100..].iter().all(|b| *b == 0));
+        assert_eq!(
+            cdrom.execute(&read_10, &[], 512),
+            Err(Sense::DATA_PHASE_ERROR)
+        );
+
+        let read_12 = cdb(&[READ_12, 0, 0, 0, 0, 2, 0, 0, 0, 2]);
+        assert_eq!(
+            cdrom.execute(&read_12, &[], 8192),
+            Err(Sense::LBA_OUT_OF_RANGE)
+        );
+        let read_12 = cdb(&[READ_12, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            cdrom.execute(&read_12, &[], 8192),
+            Err(Sense::LBA_OUT_OF_RANGE)
+        );
+    }
+
+    #[test]
+    fn test_read_toc() {
+        let cdrom = create_cdrom();
+
+        let read_toc = cdb(&[READ_TOC, 0, 0, 0, 0, 0, 0, 0, 255]);
+        let data = cdrom.execute(&read_toc, &[], 512).unwrap();
+        assert_eq!(be16(&data[0..2]), 18);
+        // Data track, followed by the lead-out one.
+        assert_eq!(data[6], 1);
+        assert_eq!(data[14], 0xaa);
+        assert_eq!(be32(&data[16..20]), 3);
+
+        let read_toc = cdb(&[READ_TOC, 0, 0, 0, 0, 0, 2, 0, 255]);
+        assert_eq!(
+            cdrom.execute(&read_toc, &[], 512),
+            Err(Sense::INVALID_FIELD_IN_CDB)
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let cdrom = create_cdrom();
+
+        // Writes are not supported by a CD-ROM.
+        assert_eq!(
+            cdrom.execute(&cdb(&[0x2a]), &[0u8; 2048], 0),
+            Err(Sense::INVALID_OPCODE)
+        );
+        // Only polled event status notifications are supported.
+        assert_eq!(
+            cdrom.execute(&cdb(&[GET_EVENT_STATUS_NOTIFICATION]), &[], 512),
+            Err(Sense::INVALID_FIELD_IN_CDB)
+        );
+    }
+}
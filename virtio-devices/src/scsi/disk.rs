@@ -0,0 +1,503 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Direct access block logical unit backed by a raw disk image.
+
+use super::{
+    be16, be32, be64, truncate, LogicalUnit, Sense, INQUIRY, MODE_PAGE_ALL, MODE_SENSE_10,
+    MODE_SENSE_6, READ_10, READ_16, READ_CAPACITY_10, REQUEST_SENSE, SERVICE_ACTION_IN_16,
+    SYNCHRONIZE_CACHE_10, TEST_UNIT_READY, UNMAP, WRITE_10, WRITE_16,
+};
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::result;
+
+const DISK_BLOCK_SIZE: u64 = 512;
+const MODE_PAGE_CACHING: u8 = 0x08;
+const SA_READ_CAPACITY_16: u8 = 0x10;
+// VPD pages
+const VPD_SUPPORTED_PAGES: u8 = 0x00;
+const VPD_BLOCK_LIMITS: u8 = 0xb0;
+const VPD_LOGICAL_BLOCK_PROVISIONING: u8 = 0xb2;
+// Largest range a single UNMAP descriptor may cover.
+const MAX_UNMAP_BLOCKS: u32 = 0x40_0000;
+const MAX_UNMAP_DESCRIPTORS: u32 = 16;
+
+pub(super) struct Disk {
+    file: File,
+    nblocks: u64,
+    readonly: bool,
+}
+
+impl Disk {
+    pub(super) fn new(mut file: File, readonly: bool) -> io::Result<Self> {
+        let size = file.seek(SeekFrom::End(0))?;
+        Ok(Disk {
+            file,
+            nblocks: size / DISK_BLOCK_SIZE,
+            readonly,
+        })
+    }
+
+    fn check_range(&self, lba: u64, count: u64) -> result::Result<(), Sense> {
+        match lba.checked_add(count) {
+            Some(end) if end <= self.nblocks => Ok(()),
+            _ => Err(Sense::LBA_OUT_OF_RANGE),
+        }
+    }
+
+    fn inquiry(&self, cdb: &[u8]) -> result::Result<Vec<u8>, Sense> {
+        if cdb[1] & 0x1 != 0 {
+            return self.vital_product_data(cdb[2]);
+        }
+
+        let mut data = vec![
+            0x00, // Direct access block device
+            0x00, // Not removable
+            0x05, // SPC-3
+            0x02, // Response data format
+            31,   // Additional length
+            0x00, 0x00, 0x00,
+        ];
+        data.extend(b"CLOUDHYP");
+        data.extend(b"Virtual disk    ");
+        data.extend(b"1.0 ");
+        Ok(data)
+    }
+
+    fn vital_product_data(&self, page: u8) -> result::Result<Vec<u8>, Sense> {
+        let mut data = match page {
+            VPD_SUPPORTED_PAGES => vec![
+                0x00,
+                VPD_SUPPORTED_PAGES,
+                0x00,
+                0x03,
+                VPD_SUPPORTED_PAGES,
+                VPD_BLOCK_LIMITS,
+                VPD_LOGICAL_BLOCK_PROVISIONING,
+            ],
+            VPD_BLOCK_LIMITS => {
+                let mut data = vec![0u8; 64];
+                data[1] = VPD_BLOCK_LIMITS;
+                if !self.readonly {
+                    data[20..24].copy_from_slice(&MAX_UNMAP_BLOCKS.to_be_bytes());
+                    data[24..28].copy_from_slice(&MAX_UNMAP_DESCRIPTORS.to_be_bytes());
+                }
+                data
+            }
+            VPD_LOGICAL_BLOCK_PROVISIONING => {
+                let mut data = vec![0u8; 8];
+                data[1] = VPD_LOGICAL_BLOCK_PROVISIONING;
+                if !self.readonly {
+                    // UNMAP is supported.
+                    data[5] = 0x80;
+                }
+                data
+            }
+            _ => return Err(Sense::INVALID_FIELD_IN_CDB),
+        };
+
+        let len = (data.len() - 4) as u16;
+        data[2..4].copy_from_slice(&len.to_be_bytes());
+        Ok(data)
+    }
+
+    fn read_capacity_16(&self) -> Vec<u8> {
+        let mut data = vec![0u8; 32];
+        data[0..8].copy_from_slice(&self.nblocks.saturating_sub(1).to_be_bytes());
+        data[8..12].copy_from_slice(&(DISK_BLOCK_SIZE as u32).to_be_bytes());
+        if !self.readonly {
+            // Logical block provisioning management enabled.
+            data[14] = 0x80;
+        }
+        data
+    }
+
+    fn read(&self, lba: u64, count: u64, max_len: usize) -> result::Result<Vec<u8>, Sense> {
+        self.check_range(lba, count)?;
+
+        // The command fails if the guest didn't provide enough space for
+        // the whole transfer.
+        let len = count * DISK_BLOCK_SIZE;
+        if len > max_len as u64 {
+            return Err(Sense::DATA_PHASE_ERROR);
+        }
+        let mut data = vec![0u8; len as usize];
+        self.file
+            .read_exact_at(&mut data, lba * DISK_BLOCK_SIZE)
+            .map_err(|e| {
+                error!("Failed reading from SCSI disk image: {}", e);
+                Sense::UNRECOVERED_READ_ERROR
+            })?;
+
+        Ok(data)
+    }
+
+    fn write(&self, lba: u64, count: u64, data_out: &[u8]) -> result::Result<Vec<u8>, Sense> {
+        if self.readonly {
+            return Err(Sense::WRITE_PROTECTED);
+        }
+        self.check_range(lba, count)?;
+
+        // The guest must provide as much data as the command transfers.
+        let len = (count * DISK_BLOCK_SIZE) as usize;
+        if data_out.len() < len {
+            return Err(Sense::DATA_PHASE_ERROR);
+        }
+        self.file
+            .write_all_at(&data_out[..len], lba * DISK_BLOCK_SIZE)
+            .map_err(|e| {
+                error!("Failed writing to SCSI disk image: {}", e);
+                Sense::WRITE_ERROR
+            })?;
+
+        Ok(Vec::new())
+    }
+
+    fn unmap(&self, param_len: usize, data_out: &[u8]) -> result::Result<Vec<u8>, Sense> {
+        if self.readonly {
+            return Err(Sense::WRITE_PROTECTED);
+        }
+        if data_out.len() < param_len {
+            return Err(Sense::PARAMETER_LIST_LENGTH_ERROR);
+        }
+        // An empty parameter list is not an error.
+        if param_len == 0 {
+            return Ok(Vec::new());
+        }
+        if param_len < 8 {
+            return Err(Sense::PARAMETER_LIST_LENGTH_ERROR);
+        }
+        let data_out = &data_out[..param_len];
+
+        let descs_len = cmp::min(be16(&data_out[2..4]) as usize, data_out.len() - 8);
+        for desc in data_out[8..8 + descs_len].chunks_exact(16) {
+            let lba = be64(&desc[0..8]);
+            let count = be32(&desc[8..12]) as u64;
+            self.check_range(lba, count)?;
+
+            // SAFETY: FFI call with a valid fd and a range checked against
+            // the size of the image.
+            let ret = unsafe {
+                libc::fallocate64(
+                    self.file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    (lba * DISK_BLOCK_SIZE) as libc::off64_t,
+                    (count * DISK_BLOCK_SIZE) as libc::off64_t,
+                )
+            };
+            if ret != 0 {
+                error!(
+                    "Failed discarding SCSI disk image range: {}",
+                    io::Error::last_os_error()
+                );
+                return Err(Sense::WRITE_ERROR);
+            }
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn mode_sense(&self, page_code: u8, ten: bool) -> result::Result<Vec<u8>, Sense> {
+        let page = page_code & 0x3f;
+        let changeable = page_code >> 6 == 1;
+        if page != MODE_PAGE_CACHING && page != MODE_PAGE_ALL {
+            return Err(Sense::INVALID_FIELD_IN_CDB);
+        }
+
+        let mut caching = vec![0u8; 20];
+        caching[0] = MODE_PAGE_CACHING;
+        caching[1] = 18;
+        if !changeable {
+            // Write cache enabled, so that the guest flushes its writes.
+            caching[2] = 0x04;
+        }
+
+        // Reports whether the medium is write protected.
+        let device_specific = if self.readonly { 0x80 } else { 0x00 };
+        let mut data = if ten {
+            let len = (6 + caching.len()) as u16;
+            let mut header = vec![0u8; 8];
+            header[0..2].copy_from_slice(&len.to_be_bytes());
+            header[3] = device_specific;
+            header
+        } else {
+            vec![(3 + caching.len()) as u8, 0, device_specific, 0]
+        };
+        data.extend(caching);
+        Ok(data)
+    }
+}
+
+impl LogicalUnit for Disk {
+    fn execute(
+        &self,
+        cdb: &[u8],
+        data_out: &[u8],
+        max_len: usize,
+    ) -> result::Result<Vec<u8>, Sense> {
+        match cdb[0] {
+            TEST_UNIT_READY => Ok(Vec::new()),
+            REQUEST_SENSE => Ok(truncate(
+                Sense::NO_SENSE.to_bytes().to_vec(),
+                cdb[4] as usize,
+                max_len,
+            )),
+            INQUIRY => Ok(truncate(
+                self.inquiry(cdb)?,
+                be16(&cdb[3..5]) as usize,
+                max_len,
+            )),
+            READ_CAPACITY_10 => {
+                let last_lba = cmp::min(self.nblocks.saturating_sub(1), u32::MAX as u64) as u32;
+                let mut data = last_lba.to_be_bytes().to_vec();
+                data.extend((DISK_BLOCK_SIZE as u32).to_be_bytes());
+                Ok(truncate(data, 8, max_len))
+            }
+            SERVICE_ACTION_IN_16 if cdb[1] & 0x1f == SA_READ_CAPACITY_16 => Ok(truncate(
+                self.read_capacity_16(),
+                be32(&cdb[10..14]) as usize,
+                max_len,
+            )),
+            READ_10 => {
+                let lba = be32(&cdb[2..6]) as u64;
+                let count = be16(&cdb[7..9]) as u64;
+                self.read(lba, count, max_len)
+            }
+            READ_16 => {
+                let lba = be64(&cdb[2..10]);
+                let count = be32(&cdb[10..14]) as u64;
+                self.read(lba, count, max_len)
+            }
+            WRITE_10 => {
+                let lba = be32(&cdb[2..6]) as u64;
+                let count = be16(&cdb[7..9]) as u64;
+                self.write(lba, count, data_out)
+            }
+            WRITE_16 => {
+                let lba = be64(&cdb[2..10]);
+                let count = be32(&cdb[10..14]) as u64;
+                self.write(lba, count, data_out)
+            }
+            SYNCHRONIZE_CACHE_10 => {
+                self.file.sync_data().map_err(|e| {
+                    error!("Failed flushing SCSI disk image: {}", e);
+                    Sense::WRITE_ERROR
+                })?;
+                Ok(Vec::new())
+            }
+            UNMAP => self.unmap(be16(&cdb[7..9]) as usize, data_out),
+            MODE_SENSE_6 => Ok(truncate(
+                self.mode_sense(cdb[2], false)?,
+                cdb[4] as usize,
+                max_len,
+            )),
+            MODE_SENSE_10 => Ok(truncate(
+                self.mode_sense(cdb[2], true)?,
+                be16(&cdb[7..9]) as usize,
+                max_len,
+            )),
+            _ => {
+                debug!("Unsupported SBC command: {:#x}", cdb[0]);
+                Err(Sense::INVALID_OPCODE)
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn LogicalUnit>> {
+        Ok(Box::new(Disk {
+            file: self.file.try_clone()?,
+            nblocks: self.nblocks,
+            readonly: self.readonly,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempfile::TempFile;
+
+    const NBLOCKS: u64 = 8;
+
+    fn create_disk(readonly: bool) -> Disk {
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(NBLOCKS * DISK_BLOCK_SIZE).unwrap();
+        Disk::new(file, readonly).unwrap()
+    }
+
+    fn cdb(bytes: &[u8]) -> Vec<u8> {
+        let mut cdb = bytes.to_vec();
+        cdb.resize(32, 0);
+        cdb
+    }
+
+    #[test]
+    fn test_inquiry() {
+        let disk = create_disk(false);
+
+        let data = disk
+            .execute(&cdb(&[INQUIRY, 0, 0, 0, 36]), &[], 512)
+            .unwrap();
+        assert_eq!(data.len(), 36);
+        assert_eq!(data[0], 0x00);
+        assert_eq!(&data[8..16], b"CLOUDHYP");
+
+        // Truncated to the allocation length.
+        let data = disk
+            .execute(&cdb(&[INQUIRY, 0, 0, 0, 5]), &[], 512)
+            .unwrap();
+        assert_eq!(data.len(), 5);
+
+        let data = disk
+            .execute(&cdb(&[INQUIRY, 1, VPD_SUPPORTED_PAGES, 0, 255]), &[], 512)
+            .unwrap();
+        assert_eq!(&data[4..], &[0x00, 0xb0, 0xb2]);
+        assert_eq!(
+            disk.execute(&cdb(&[INQUIRY, 1, 0x80, 0, 255]), &[], 512),
+            Err(Sense::INVALID_FIELD_IN_CDB)
+        );
+    }
+
+    #[test]
+    fn test_read_capacity() {
+        let disk = create_disk(false);
+
+        let data = disk.execute(&cdb(&[READ_CAPACITY_10]), &[], 512).unwrap();
+        assert_eq!(be32(&data[0..4]) as u64, NBLOCKS - 1);
+        assert_eq!(be32(&data[4..8]) as u64, DISK_BLOCK_SIZE);
+
+        let mut read_capacity_16 = cdb(&[SERVICE_ACTION_IN_16, SA_READ_CAPACITY_16]);
+        read_capacity_16[13] = 32;
+        let data = disk.execute(&read_capacity_16, &[], 512).unwrap();
+        assert_eq!(data.len(), 32);
+        assert_eq!(be64(&data[0..8]), NBLOCKS - 1);
+        assert_eq!(data[14], 0x80);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let disk = create_disk(false);
+
+        // WRITE(10) of 2 blocks at LBA 3.
+        let data_out = vec![0xa5u8; 2 * DISK_BLOCK_SIZE as usize];
+        let write_10 = cdb(&[WRITE_10, 0, 0, 0, 0, 3, 0, 0, 2]);
+        assert_eq!(disk.execute(&write_10, &data_out, 0), Ok(Vec::new()));
+
+        // READ(16) of the same blocks.
+        let read_16 = cdb(&[READ_16, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 2]);
+        let data = disk.execute(&read_16, &[], 4096).unwrap();
+        assert_eq!(data, data_out);
+
+        // READ(10) larger than the space available in the request buffers.
+        let read_10 = cdb(&[READ_10, 0, 0, 0, 0, 2, 0, 0, 2]);
+        assert_eq!(
+            disk.execute(&read_10, &[], 512),
+            Err(Sense::DATA_PHASE_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let disk = create_disk(false);
+
+        let read_10 = cdb(&[READ_10, 0, 0, 0, 0, 7, 0, 0, 2]);
+        assert_eq!(
+            disk.execute(&read_10, &[], 4096),
+            Err(Sense::LBA_OUT_OF_RANGE)
+        );
+
+        // The range must not wrap around.
+        let mut read_16 = cdb(&[READ_16, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        read_16[13] = 2;
+        assert_eq!(
+            disk.execute(&read_16, &[], 4096),
+            Err(Sense::LBA_OUT_OF_RANGE)
+        );
+
+        let write_16 = cdb(&[WRITE_16, 0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 1]);
+        assert_eq!(
+            disk.execute(&write_16, &[0u8; 512], 0),
+            Err(Sense::LBA_OUT_OF_RANGE)
+        );
+    }
+
+    #[test]
+    fn test_short_data_out() {
+        let disk = create_disk(false);
+
+        let write_10 = cdb(&[WRITE_10, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(
+            disk.execute(&write_10, &[0xa5u8; 512], 0),
+            Err(Sense::DATA_PHASE_ERROR)
+        );
+        // Nothing was written.
+        let data = disk
+            .execute(&cdb(&[READ_10, 0, 0, 0, 0, 0, 0, 0, 1]), &[], 512)
+            .unwrap();
+        assert_eq!(data, vec![0u8; 512]);
+
+        // UNMAP parameter list length larger than the data-out buffer.
+        let unmap = cdb(&[UNMAP, 0, 0, 0, 0, 0, 0, 0, 24]);
+        assert_eq!(
+            disk.execute(&unmap, &[0u8; 8], 0),
+            Err(Sense::PARAMETER_LIST_LENGTH_ERROR)
+        );
+        // Parameter list header truncated.
+        let unmap = cdb(&[UNMAP, 0, 0, 0, 0, 0, 0, 0, 4]);
+        assert_eq!(
+            disk.execute(&unmap, &[0u8; 4], 0),
+            Err(Sense::PARAMETER_LIST_LENGTH_ERROR)
+        );
+        // No parameter list.
+        let unmap = cdb(&[UNMAP]);
+        assert_eq!(disk.execute(&unmap, &[], 0), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_readonly() {
+        let disk = create_disk(true);
+
+        let write_10 = cdb(&[WRITE_10, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            disk.execute(&write_10, &[0u8; 512], 0),
+            Err(Sense::WRITE_PROTECTED)
+        );
+        assert_eq!(
+            disk.execute(&cdb(&[UNMAP]), &[], 0),
+            Err(Sense::WRITE_PROTECTED)
+        );
+
+        // Write protection is reported in the mode parameter header.
+        let data = disk
+            .execute(
+                &cdb(&[MODE_SENSE_6, 0, MODE_PAGE_CACHING, 0, 255]),
+                &[],
+                512,
+            )
+            .unwrap();
+        assert_eq!(data[2], 0x80);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let disk = create_disk(false);
+
+        assert_eq!(
+            disk.execute(&cdb(&[0xff]), &[], 512),
+            Err(Sense::INVALID_OPCODE)
+        );
+        assert_eq!(
+            disk.execute(&cdb(&[MODE_SENSE_6, 0, 0x01, 0, 255]), &[], 512),
+            Err(Sense::INVALID_FIELD_IN_CDB)
+        );
+
+        let data = disk
+            .execute(&cdb(&[REQUEST_SENSE, 0, 0, 0, 18]), &[], 512)
+            .unwrap();
+        assert_eq!(data, Sense::NO_SENSE.to_bytes().to_vec());
+    }
+}
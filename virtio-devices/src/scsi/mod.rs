@@ -0,0 +1,1082 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Virtio SCSI host adapter with a single target, exposing each of its
+//! backing images as a logical unit. Raw disk images are presented as direct
+//! access block devices, while ISO images are presented as read-only CD-ROMs
+//! so that guests (and firmwares) looking for optical media can boot from
+//! them.
+
+mod cdrom;
+mod disk;
+
+use self::cdrom::CdRom;
+use self::disk::Disk;
+
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier};
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Descriptor, Queue, QueueT};
+use vm_memory::{ByteValued, Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::{AccessPlatform, Translatable};
+use vmm_sys_util::eventfd::EventFd;
+
+// The control queue, the event queue and a single request queue.
+const NUM_QUEUES: usize = 3;
+const CONTROL_QUEUE: usize = 0;
+const EVENT_QUEUE: usize = 1;
+const REQUEST_QUEUE: usize = 2;
+
+// New descriptors are pending on one of the virtio queues.
+const CONTROL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+const EVENT_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+const REQUEST_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+
+const VIRTIO_SCSI_CDB_SIZE: usize = 32;
+const VIRTIO_SCSI_SENSE_SIZE: usize = 96;
+const VIRTIO_SCSI_EVENT_SIZE: u32 = 16;
+
+// Response codes
+const VIRTIO_SCSI_S_OK: u8 = 0;
+const VIRTIO_SCSI_S_BAD_TARGET: u8 = 3;
+const VIRTIO_SCSI_S_FAILURE: u8 = 9;
+const VIRTIO_SCSI_S_FUNCTION_COMPLETE: u8 = 0;
+
+// Control queue request types
+const VIRTIO_SCSI_T_TMF: u32 = 0;
+const VIRTIO_SCSI_T_AN_QUERY: u32 = 1;
+const VIRTIO_SCSI_T_AN_SUBSCRIBE: u32 = 2;
+
+// SCSI status codes
+const SCSI_STATUS_GOOD: u8 = 0x00;
+const SCSI_STATUS_CHECK_CONDITION: u8 = 0x02;
+
+// SCSI, SBC and MMC commands
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const UNMAP: u8 = 0x42;
+const READ_TOC: u8 = 0x43;
+const GET_CONFIGURATION: u8 = 0x46;
+const GET_EVENT_STATUS_NOTIFICATION: u8 = 0x4a;
+const MODE_SENSE_10: u8 = 0x5a;
+const READ_16: u8 = 0x88;
+const WRITE_16: u8 = 0x8a;
+const SERVICE_ACTION_IN_16: u8 = 0x9e;
+const REPORT_LUNS: u8 = 0xa0;
+const READ_12: u8 = 0xa8;
+
+const MODE_PAGE_ALL: u8 = 0x3f;
+
+// Largest transfer a command can request, also bounding the data-out buffer
+// gathered from the guest.
+const MAX_SECTORS: u32 = 0xffff;
+const MAX_TRANSFER_LEN: usize = MAX_SECTORS as usize * 512;
+
+// Logical units are reported with the peripheral device addressing method,
+// which is limited to 256 LUNs.
+pub const SCSI_MAX_LUNS: usize = 256;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+}
+
+#[derive(Copy, Clone, Debug, Default, Versionize)]
+#[repr(C, packed)]
+pub struct VirtioScsiConfig {
+    num_queues: u32,
+    seg_max: u32,
+    max_sectors: u32,
+    cmd_per_lun: u32,
+    event_info_size: u32,
+    sense_size: u32,
+    cdb_size: u32,
+    max_channel: u16,
+    max_target: u16,
+    max_lun: u32,
+}
+
+// SAFETY: it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioScsiConfig {}
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct VirtioScsiCmdReq {
+    lun: [u8; 8],
+    tag: u64,
+    task_attr: u8,
+    prio: u8,
+    crn: u8,
+    cdb: [u8; VIRTIO_SCSI_CDB_SIZE],
+}
+
+// SAFETY: it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioScsiCmdReq {}
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct VirtioScsiCmdResp {
+    sense_len: u32,
+    resid: u32,
+    status_qualifier: u16,
+    status: u8,
+    response: u8,
+    sense: [u8; VIRTIO_SCSI_SENSE_SIZE],
+}
+
+// SAFETY: it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioScsiCmdResp {}
+
+impl Default for VirtioScsiCmdResp {
+    fn default() -> Self {
+        VirtioScsiCmdResp {
+            sense_len: 0,
+            resid: 0,
+            status_qualifier: 0,
+            status: SCSI_STATUS_GOOD,
+            response: VIRTIO_SCSI_S_OK,
+            sense: [0; VIRTIO_SCSI_SENSE_SIZE],
+        }
+    }
+}
+
+/// Sense data reported along with a CHECK CONDITION status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Sense {
+    key: u8,
+    asc: u8,
+    ascq: u8,
+}
+
+impl Sense {
+    const NO_SENSE: Sense = Sense::new(0x00, 0x00, 0x00);
+    const WRITE_ERROR: Sense = Sense::new(0x03, 0x0c, 0x00);
+    const DATA_PHASE_ERROR: Sense = Sense::new(0x0b, 0x4b, 0x00);
+    const UNRECOVERED_READ_ERROR: Sense = Sense::new(0x03, 0x11, 0x00);
+    const PARAMETER_LIST_LENGTH_ERROR: Sense = Sense::new(0x05, 0x1a, 0x00);
+    const INVALID_OPCODE: Sense = Sense::new(0x05, 0x20, 0x00);
+    const LBA_OUT_OF_RANGE: Sense = Sense::new(0x05, 0x21, 0x00);
+    const INVALID_FIELD_IN_CDB: Sense = Sense::new(0x05, 0x24, 0x00);
+    const LUN_NOT_SUPPORTED: Sense = Sense::new(0x05, 0x25, 0x00);
+    const WRITE_PROTECTED: Sense = Sense::new(0x07, 0x27, 0x00);
+
+    const fn new(key: u8, asc: u8, ascq: u8) -> Self {
+        Sense { key, asc, ascq }
+    }
+
+    // Fixed format sense data
+    fn to_bytes(self) -> [u8; 18] {
+        let mut sense = [0u8; 18];
+        sense[0] = 0x70;
+        sense[2] = self.key;
+        sense[7] = 10;
+        sense[12] = self.asc;
+        sense[13] = self.ascq;
+        sense
+    }
+}
+
+/// Backing image of a logical unit.
+pub enum ScsiLun {
+    /// Raw disk image, exposed as a direct access block device.
+    Disk { image: File, readonly: bool },
+    /// ISO image, exposed as a read-only CD-ROM.
+    Cdrom(File),
+}
+
+trait LogicalUnit: Send {
+    // Executes the command described by `cdb`, returning at most `max_len`
+    // bytes of data to transfer to the guest. `data_out` holds the data
+    // transferred from the guest.
+    fn execute(
+        &self,
+        cdb: &[u8],
+        data_out: &[u8],
+        max_len: usize,
+    ) -> result::Result<Vec<u8>, Sense>;
+
+    fn try_clone(&self) -> io::Result<Box<dyn LogicalUnit>>;
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn be64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
+
+// Limits the data returned to the guest to both the allocation length from
+// the CDB and the space available in the request buffers.
+fn truncate(mut data: Vec<u8>, alloc_len: usize, max_len: usize) -> Vec<u8> {
+    data.truncate(cmp::min(alloc_len, max_len));
+    data
+}
+
+fn report_luns(num_luns: usize) -> Vec<u8> {
+    let mut data = vec![0u8; 8];
+    data[0..4].copy_from_slice(&((num_luns * 8) as u32).to_be_bytes());
+    for lun in 0..num_luns {
+        data.extend([0, lun as u8, 0, 0, 0, 0, 0, 0]);
+    }
+    data
+}
+
+// Lists the device readable or writable buffers of a descriptor chain.
+fn translated_descs(
+    descs: &[Descriptor],
+    write_only: bool,
+    access_platform: Option<&Arc<dyn AccessPlatform>>,
+) -> Vec<(GuestAddress, usize)> {
+    descs
+        .iter()
+        .filter(|d| d.is_write_only() == write_only)
+        .map(|d| {
+            (
+                d.addr().translate_gva(access_platform, d.len() as usize),
+                d.len() as usize,
+            )
+        })
+        .collect()
+}
+
+// Gathers at most `max_len` bytes from the device readable descriptors.
+fn read_from_descs(
+    mem: &GuestMemoryMmap,
+    descs: &[(GuestAddress, usize)],
+    max_len: usize,
+) -> result::Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    for (addr, len) in descs {
+        let start = data.len();
+        let len = cmp::min(*len, max_len - start);
+        if len == 0 {
+            break;
+        }
+        data.resize(start + len, 0);
+        mem.read_slice(&mut data[start..], *addr)
+            .map_err(Error::GuestMemoryRead)?;
+    }
+
+    Ok(data)
+}
+
+// Copies `data` into the device writable descriptors, returning the number
+// of bytes written.
+fn write_to_descs(
+    mem: &GuestMemoryMmap,
+    descs: &[(GuestAddress, usize)],
+    mut data: &[u8],
+) -> result::Result<u32, Error> {
+    let mut written = 0;
+    for (addr, len) in descs {
+        if data.is_empty() {
+            break;
+        }
+        let len = cmp::min(*len, data.len());
+        mem.write_slice(&data[..len], *addr)
+            .map_err(Error::GuestMemoryWrite)?;
+        data = &data[len..];
+        written += len;
+    }
+
+    Ok(written as u32)
+}
+
+// Handles a control request, returning the number of bytes written to the
+// device writable buffers.
+fn control_request(
+    mem: &GuestMemoryMmap,
+    readable: &[(GuestAddress, usize)],
+    writable: &[(GuestAddress, usize)],
+) -> result::Result<u32, Error> {
+    let req = read_from_descs(mem, readable, size_of::<u32>())?;
+    if req.len() < size_of::<u32>() {
+        return Err(Error::DescriptorChainTooShort);
+    }
+    let req_type = u32::from_le_bytes([req[0], req[1], req[2], req[3]]);
+
+    // Requests are handled synchronously, hence there is never any task left
+    // to be managed.
+    let resp = match req_type {
+        VIRTIO_SCSI_T_TMF => vec![VIRTIO_SCSI_S_FUNCTION_COMPLETE],
+        // No asynchronous notification is supported.
+        VIRTIO_SCSI_T_AN_QUERY | VIRTIO_SCSI_T_AN_SUBSCRIBE => {
+            vec![0, 0, 0, 0, VIRTIO_SCSI_S_OK]
+        }
+        _ => vec![VIRTIO_SCSI_S_FAILURE],
+    };
+
+    write_to_descs(mem, writable, &resp)
+}
+
+// Outcome of a command which didn't complete successfully.
+#[derive(Debug, PartialEq, Eq)]
+enum ScsiResponse {
+    BadTarget,
+    CheckCondition(Sense),
+}
+
+struct ScsiEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    queues: Vec<Queue>,
+    queue_evts: Vec<EventFd>,
+    luns: Vec<Box<dyn LogicalUnit>>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+}
+
+impl ScsiEpollHandler {
+    fn process_control_queue(&mut self) -> result::Result<bool, Error> {
+        let queue = &mut self.queues[CONTROL_QUEUE];
+
+        let mut used_descs = false;
+        while let Some(mut desc_chain) = queue.pop_descriptor_chain(self.mem.memory()) {
+            let descs: Vec<Descriptor> = desc_chain.by_ref().collect();
+            let readable = translated_descs(&descs, false, self.access_platform.as_ref());
+            let writable = translated_descs(&descs, true, self.access_platform.as_ref());
+
+            // A malformed request is returned without any response, the
+            // following ones are still processed.
+            let len =
+                control_request(desc_chain.memory(), &readable, &writable).unwrap_or_else(|e| {
+                    error!("Failed to process virtio-scsi control request: {}", e);
+                    0
+                });
+            queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    fn process_request_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) =
+            self.queues[REQUEST_QUEUE].pop_descriptor_chain(self.mem.memory())
+        {
+            let descs: Vec<Descriptor> = desc_chain.by_ref().collect();
+            let readable = translated_descs(&descs, false, self.access_platform.as_ref());
+            let writable = translated_descs(&descs, true, self.access_platform.as_ref());
+
+            // A malformed request fails on its own, the following ones are
+            // still processed.
+            let len = self
+                .command_request(desc_chain.memory(), &readable, &writable)
+                .unwrap_or_else(|e| {
+                    error!("Failed to process virtio-scsi request: {}", e);
+                    let resp = VirtioScsiCmdResp {
+                        response: VIRTIO_SCSI_S_FAILURE,
+                        ..Default::default()
+                    };
+                    write_to_descs(desc_chain.memory(), &writable, resp.as_slice()).unwrap_or(0)
+                });
+            self.queues[REQUEST_QUEUE]
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Executes a command request, returning the number of bytes written to
+    // the device writable buffers.
+    fn command_request(
+        &self,
+        mem: &GuestMemoryMmap,
+        readable: &[(GuestAddress, usize)],
+        writable: &[(GuestAddress, usize)],
+    ) -> result::Result<u32, Error> {
+        // The request header is followed by the data-out buffer, if any,
+        // which isn't gathered beyond the largest transfer allowed.
+        let readable_len: usize = readable.iter().map(|(_, len)| len).sum();
+        let out = read_from_descs(
+            mem,
+            readable,
+            size_of::<VirtioScsiCmdReq>() + MAX_TRANSFER_LEN,
+        )?;
+        if out.len() < size_of::<VirtioScsiCmdReq>() {
+            return Err(Error::DescriptorChainTooShort);
+        }
+        let writable_len: usize = writable.iter().map(|(_, len)| len).sum();
+        if writable_len < size_of::<VirtioScsiCmdResp>() {
+            return Err(Error::DescriptorChainTooShort);
+        }
+        let (header, data_out) = out.split_at(size_of::<VirtioScsiCmdReq>());
+        // Unwrapping is safe since the header has the exact size of the
+        // request structure.
+        let req = *VirtioScsiCmdReq::from_slice(header).unwrap();
+        let max_len = writable_len - size_of::<VirtioScsiCmdResp>();
+
+        let result = if readable_len > out.len() {
+            Err(ScsiResponse::CheckCondition(Sense::DATA_PHASE_ERROR))
+        } else {
+            self.execute(req.lun, &req.cdb, data_out, max_len)
+        };
+
+        let mut resp = VirtioScsiCmdResp::default();
+        let data = match result {
+            Ok(data) => data,
+            Err(ScsiResponse::BadTarget) => {
+                resp.response = VIRTIO_SCSI_S_BAD_TARGET;
+                Vec::new()
+            }
+            Err(ScsiResponse::CheckCondition(sense)) => {
+                let sense = sense.to_bytes();
+                resp.status = SCSI_STATUS_CHECK_CONDITION;
+                resp.sense_len = sense.len() as u32;
+                resp.sense[..sense.len()].copy_from_slice(&sense);
+                Vec::new()
+            }
+        };
+        resp.resid = (max_len - data.len()) as u32;
+
+        let mut bytes = resp.as_slice().to_vec();
+        bytes.extend(data);
+        write_to_descs(mem, writable, &bytes)
+    }
+
+    // Dispatches the command described by `cdb` to the logical unit
+    // addressed by `lun`.
+    fn execute(
+        &self,
+        lun: [u8; 8],
+        cdb: &[u8],
+        data_out: &[u8],
+        max_len: usize,
+    ) -> result::Result<Vec<u8>, ScsiResponse> {
+        // Single target, addressed through the flat space addressing method.
+        if lun[0] != 1 || lun[1] != 0 {
+            return Err(ScsiResponse::BadTarget);
+        }
+
+        let lun_id = be16(&lun[2..4]) & 0x3fff;
+        match (self.luns.get(lun_id as usize), cdb[0]) {
+            (_, REPORT_LUNS) => Ok(truncate(
+                report_luns(self.luns.len()),
+                be32(&cdb[6..10]) as usize,
+                max_len,
+            )),
+            (Some(unit), _) => unit.execute(cdb, data_out, max_len),
+            // No device is connected to any other LUN.
+            (None, INQUIRY) if cdb[1] & 0x1 == 0 => {
+                let mut data = vec![0u8; 36];
+                data[0] = 0x7f;
+                data[4] = 31;
+                Ok(truncate(data, be16(&cdb[3..5]) as usize, max_len))
+            }
+            (None, _) => Err(Sense::LUN_NOT_SUPPORTED),
+        }
+        .map_err(ScsiResponse::CheckCondition)
+    }
+
+    fn signal_used_queue(&self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index as u16))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(
+            self.queue_evts[CONTROL_QUEUE].as_raw_fd(),
+            CONTROL_QUEUE_EVENT,
+        )?;
+        helper.add_event(self.queue_evts[EVENT_QUEUE].as_raw_fd(), EVENT_QUEUE_EVENT)?;
+        helper.add_event(
+            self.queue_evts[REQUEST_QUEUE].as_raw_fd(),
+            REQUEST_QUEUE_EVENT,
+        )?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for ScsiEpollHandler {
+    fn handle_event(
+        &mut self,
+        _helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        let queue_index = match ev_type {
+            CONTROL_QUEUE_EVENT => CONTROL_QUEUE,
+            EVENT_QUEUE_EVENT => EVENT_QUEUE,
+            REQUEST_QUEUE_EVENT => REQUEST_QUEUE,
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        };
+
+        self.queue_evts[queue_index].read().map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+        })?;
+
+        let needs_notification = match queue_index {
+            CONTROL_QUEUE => self.process_control_queue(),
+            REQUEST_QUEUE => self.process_request_queue(),
+            // Buffers from the event queue are kept around since no event
+            // is ever reported.
+            _ => Ok(false),
+        }
+        .map_err(|e| EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e)))?;
+
+        if needs_notification {
+            self.signal_used_queue(queue_index).map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Virtio SCSI device exposing a list of images as logical units.
+pub struct Scsi {
+    common: VirtioCommon,
+    id: String,
+    luns: Vec<Box<dyn LogicalUnit>>,
+    config: VirtioScsiConfig,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+#[derive(Versionize)]
+pub struct ScsiState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioScsiConfig,
+}
+
+impl VersionMapped for ScsiState {}
+
+impl Scsi {
+    /// Create a new virtio-scsi device, `luns` being attached in order
+    /// starting from LUN 0.
+    pub fn new(
+        id: String,
+        luns: Vec<ScsiLun>,
+        queue_size: u16,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<ScsiState>,
+    ) -> io::Result<Scsi> {
+        let num_luns = luns.len();
+        let luns = luns
+            .into_iter()
+            .map(|lun| -> io::Result<Box<dyn LogicalUnit>> {
+                Ok(match lun {
+                    ScsiLun::Disk { image, readonly } => Box::new(Disk::new(image, readonly)?),
+                    ScsiLun::Cdrom(image) => Box::new(CdRom::new(image)?),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (avail_features, acked_features, config, paused) = if let Some(state) = state {
+            info!("Restoring virtio-scsi {}", id);
+            (
+                state.avail_features,
+                state.acked_features,
+                state.config,
+                true,
+            )
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            let config = VirtioScsiConfig {
+                num_queues: 1,
+                // Indirect descriptors are not offered, the request header
+                // and response need their own descriptors.
+                seg_max: u32::from(queue_size).saturating_sub(2),
+                max_sectors: MAX_SECTORS,
+                cmd_per_lun: u32::from(queue_size),
+                event_info_size: VIRTIO_SCSI_EVENT_SIZE,
+                sense_size: VIRTIO_SCSI_SENSE_SIZE as u32,
+                cdb_size: VIRTIO_SCSI_CDB_SIZE as u32,
+                max_channel: 0,
+                max_target: 0,
+                max_lun: num_luns.saturating_sub(1) as u32,
+            };
+
+            (avail_features, 0, config, false)
+        };
+
+        Ok(Scsi {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Scsi as u32,
+                queue_sizes: vec![queue_size; NUM_QUEUES],
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: NUM_QUEUES as u16,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            luns,
+            config,
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> ScsiState {
+        ScsiState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: self.config,
+        }
+    }
+}
+
+impl Drop for Scsi {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Scsi {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The driver may only write back the sense and CDB sizes, which
+        // can't be changed.
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let luns = self
+            .luns
+            .iter()
+            .map(|lun| lun.try_clone())
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| {
+                error!("failed cloning SCSI image: {}", e);
+                ActivateError::BadActivate
+            })?;
+
+        let mut virtqueues = Vec::new();
+        let mut queue_evts = Vec::new();
+        for (_, queue, queue_evt) in queues {
+            virtqueues.push(queue);
+            queue_evts.push(queue_evt);
+        }
+
+        let mut handler = ScsiEpollHandler {
+            mem,
+            queues: virtqueues,
+            queue_evts,
+            luns,
+            interrupt_cb,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioScsi,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Scsi {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Scsi {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for Scsi {}
+impl Migratable for Scsi {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::VirtioInterruptType;
+    use virtio_bindings::virtio_ring::{VRING_DESC_F_NEXT, VRING_DESC_F_WRITE};
+    use vm_virtio::queue::testing::VirtQueue as GuestQ;
+    use vmm_sys_util::tempfile::TempFile;
+
+    const REQ_ADDR: u64 = 0x2_0000;
+    const RESP_ADDR: u64 = 0x3_0000;
+
+    struct NoopVirtioInterrupt {}
+
+    impl VirtioInterrupt for NoopVirtioInterrupt {
+        fn trigger(&self, _int_type: VirtioInterruptType) -> std::result::Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    // Creates a handler with a single disk, processing the requests from
+    // `request_queue`.
+    fn create_handler(mem: &GuestMemoryMmap, request_queue: Queue) -> ScsiEpollHandler {
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(8 * 512).unwrap();
+
+        ScsiEpollHandler {
+            mem: GuestMemoryAtomic::new(mem.clone()),
+            queues: vec![
+                Queue::new(8).unwrap(),
+                Queue::new(8).unwrap(),
+                request_queue,
+            ],
+            queue_evts: Vec::new(),
+            luns: vec![Box::new(Disk::new(file, false).unwrap())],
+            interrupt_cb: Arc::new(NoopVirtioInterrupt {}),
+            kill_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            pause_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            access_platform: None,
+        }
+    }
+
+    fn lun(id: u8) -> [u8; 8] {
+        [1, 0, 0, id, 0, 0, 0, 0]
+    }
+
+    fn cdb(bytes: &[u8]) -> [u8; VIRTIO_SCSI_CDB_SIZE] {
+        let mut cdb = [0u8; VIRTIO_SCSI_CDB_SIZE];
+        cdb[..bytes.len()].copy_from_slice(bytes);
+        cdb
+    }
+
+    fn response(mem: &GuestMemoryMmap, addr: u64) -> VirtioScsiCmdResp {
+        mem.read_obj(GuestAddress(addr)).unwrap()
+    }
+
+    #[test]
+    fn test_sense_to_bytes() {
+        let sense = Sense::LBA_OUT_OF_RANGE.to_bytes();
+        assert_eq!(sense.len(), 18);
+        // Current error, fixed format.
+        assert_eq!(sense[0], 0x70);
+        assert_eq!(sense[2], 0x05);
+        // Additional sense length, covering the bytes after the 8th one.
+        assert_eq!(sense[7], 10);
+        assert_eq!(sense[12], 0x21);
+        assert_eq!(sense[13], 0x00);
+
+        assert_eq!(Sense::NO_SENSE.to_bytes()[2], 0);
+    }
+
+    #[test]
+    fn test_report_luns() {
+        let data = report_luns(3);
+        assert_eq!(data.len(), 8 + 3 * 8);
+        assert_eq!(be32(&data[0..4]), 24);
+        assert_eq!(&data[8..16], &[0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&data[24..32], &[0, 2, 0, 0, 0, 0, 0, 0]);
+
+        // Truncated to the allocation length.
+        assert_eq!(truncate(report_luns(3), 16, 512).len(), 16);
+        // Truncated to the space left in the request buffers.
+        assert_eq!(truncate(report_luns(3), 512, 12).len(), 12);
+    }
+
+    #[test]
+    fn test_cdb_fields() {
+        let bytes = [0x28, 0x00, 0x12, 0x34, 0x56, 0x78, 0x00, 0x9a, 0xbc];
+        assert_eq!(be32(&bytes[2..6]), 0x1234_5678);
+        assert_eq!(be16(&bytes[7..9]), 0x9abc);
+        assert_eq!(be64(&[1, 2, 3, 4, 5, 6, 7, 8]), 0x0102_0304_0506_0708);
+    }
+
+    #[test]
+    fn test_execute_addressing() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let handler = create_handler(&mem, Queue::new(8).unwrap());
+
+        // Only a single target is exposed.
+        assert_eq!(
+            handler.execute([1, 1, 0, 0, 0, 0, 0, 0], &cdb(&[TEST_UNIT_READY]), &[], 0),
+            Err(ScsiResponse::BadTarget)
+        );
+        assert_eq!(
+            handler.execute([0, 0, 0, 0, 0, 0, 0, 0], &cdb(&[TEST_UNIT_READY]), &[], 0),
+            Err(ScsiResponse::BadTarget)
+        );
+
+        assert_eq!(
+            handler.execute(lun(0), &cdb(&[TEST_UNIT_READY]), &[], 0),
+            Ok(Vec::new())
+        );
+        assert_eq!(
+            handler.execute(lun(1), &cdb(&[TEST_UNIT_READY]), &[], 0),
+            Err(ScsiResponse::CheckCondition(Sense::LUN_NOT_SUPPORTED))
+        );
+
+        // The LUNs are listed through any of them.
+        let data = handler
+            .execute(
+                lun(5),
+                &cdb(&[REPORT_LUNS, 0, 0, 0, 0, 0, 0, 0, 1, 0]),
+                &[],
+                512,
+            )
+            .unwrap();
+        assert_eq!(data, report_luns(1));
+
+        // No device is connected to the LUNs without any disk.
+        let data = handler
+            .execute(lun(1), &cdb(&[INQUIRY, 0, 0, 0, 36]), &[], 512)
+            .unwrap();
+        assert_eq!(data.len(), 36);
+        assert_eq!(data[0], 0x7f);
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1_0000), &mem, 8);
+        let resp_len = size_of::<VirtioScsiCmdResp>() as u32;
+        let next = VRING_DESC_F_NEXT as u16;
+        let write = VRING_DESC_F_WRITE as u16;
+
+        // Request header shorter than expected.
+        guest_q.dtable[0].set(REQ_ADDR, 8, next, 1);
+        guest_q.dtable[1].set(RESP_ADDR, resp_len, write, 0);
+        // Response buffer missing.
+        let req = VirtioScsiCmdReq {
+            lun: lun(0),
+            tag: 0,
+            task_attr: 0,
+            prio: 0,
+            crn: 0,
+            cdb: cdb(&[TEST_UNIT_READY]),
+        };
+        mem.write_obj(req, GuestAddress(REQ_ADDR + 0x1000)).unwrap();
+        guest_q.dtable[2].set(
+            REQ_ADDR + 0x1000,
+            size_of::<VirtioScsiCmdReq>() as u32,
+            0,
+            0,
+        );
+        // Valid request, still processed after the malformed ones.
+        guest_q.dtable[3].set(
+            REQ_ADDR + 0x1000,
+            size_of::<VirtioScsiCmdReq>() as u32,
+            next,
+            4,
+        );
+        guest_q.dtable[4].set(RESP_ADDR + 0x1000, resp_len, write, 0);
+
+        guest_q.avail.ring[0].set(0);
+        guest_q.avail.ring[1].set(2);
+        guest_q.avail.ring[2].set(3);
+        guest_q.avail.idx.set(3);
+
+        let mut handler = create_handler(&mem, guest_q.create_queue());
+        assert!(handler.process_request_queue().unwrap());
+        assert_eq!(guest_q.used.idx.get(), 3);
+
+        let resp = response(&mem, RESP_ADDR);
+        assert_eq!(resp.response, VIRTIO_SCSI_S_FAILURE);
+
+        let resp = response(&mem, RESP_ADDR + 0x1000);
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, SCSI_STATUS_GOOD);
+    }
+
+    #[test]
+    fn test_check_condition() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1_0000), &mem, 8);
+        let resp_len = size_of::<VirtioScsiCmdResp>() as u32;
+
+        // Writing a block with only half of its data.
+        let mut data_out = VirtioScsiCmdReq {
+            lun: lun(0),
+            tag: 0,
+            task_attr: 0,
+            prio: 0,
+            crn: 0,
+            cdb: cdb(&[WRITE_10, 0, 0, 0, 0, 0, 0, 0, 1]),
+        }
+        .as_slice()
+        .to_vec();
+        data_out.extend_from_slice(&[0xa5; 256]);
+        mem.write_slice(&data_out, GuestAddress(REQ_ADDR)).unwrap();
+
+        guest_q.dtable[0].set(REQ_ADDR, data_out.len() as u32, VRING_DESC_F_NEXT as u16, 1);
+        guest_q.dtable[1].set(RESP_ADDR, resp_len, VRING_DESC_F_WRITE as u16, 0);
+        guest_q.avail.ring[0].set(0);
+        guest_q.avail.idx.set(1);
+
+        let mut handler = create_handler(&mem, guest_q.create_queue());
+        assert!(handler.process_request_queue().unwrap());
+        assert_eq!(guest_q.used.idx.get(), 1);
+
+        let resp = response(&mem, RESP_ADDR);
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, SCSI_STATUS_CHECK_CONDITION);
+        assert_eq!({ resp.sense_len }, 18);
+        assert_eq!(resp.sense[..18], Sense::DATA_PHASE_ERROR.to_bytes());
+    }
+
+    #[test]
+    fn test_oversized_data_out() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x400_0000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1_0000), &mem, 8);
+        let resp_len = size_of::<VirtioScsiCmdResp>() as u32;
+        let next = VRING_DESC_F_NEXT as u16;
+
+        // Data-out buffer larger than any transfer, which isn't gathered
+        // past the limit.
+        let req = VirtioScsiCmdReq {
+            lun: lun(0),
+            tag: 0,
+            task_attr: 0,
+            prio: 0,
+            crn: 0,
+            cdb: cdb(&[WRITE_10, 0, 0, 0, 0, 0, 0, 0, 1]),
+        };
+        mem.write_obj(req, GuestAddress(REQ_ADDR)).unwrap();
+        guest_q.dtable[0].set(REQ_ADDR, size_of::<VirtioScsiCmdReq>() as u32, next, 1);
+        guest_q.dtable[1].set(0x10_0000, u32::MAX, next, 2);
+        guest_q.dtable[2].set(RESP_ADDR, resp_len, VRING_DESC_F_WRITE as u16, 0);
+        guest_q.avail.ring[0].set(0);
+        guest_q.avail.idx.set(1);
+
+        let mut handler = create_handler(&mem, guest_q.create_queue());
+        assert!(handler.process_request_queue().unwrap());
+        assert_eq!(guest_q.used.idx.get(), 1);
+
+        let resp = response(&mem, RESP_ADDR);
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, SCSI_STATUS_CHECK_CONDITION);
+        assert_eq!(resp.sense[..18], Sense::DATA_PHASE_ERROR.to_bytes());
+    }
+}
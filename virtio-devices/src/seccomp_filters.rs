@@ -20,6 +20,7 @@ pub enum Thread {
     VirtioNetCtl,
     VirtioPmem,
     VirtioRng,
+    VirtioScsi,
     VirtioVhostBlock,
     VirtioVhostFs,
     VirtioVhostNet,
@@ -141,9 +142,36 @@ fn virtio_pmem_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_fsync, vec![])]
 }
 
+fn create_virtio_rng_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO,).unwrap()],]
+}
+
 fn virtio_rng_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
+        // The EGD daemon is reconnected to from this thread.
+        (libc::SYS_connect, vec![]),
+        (libc::SYS_ioctl, create_virtio_rng_ioctl_seccomp_rule()),
         (libc::SYS_prctl, vec![]),
+        (libc::SYS_recvfrom, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+        (
+            libc::SYS_socket,
+            or![and![
+                Cond::new(0, ArgLen::Dword, Eq, libc::AF_UNIX as u64).unwrap()
+            ]],
+        ),
+        (libc::SYS_timerfd_settime, vec![]),
+    ]
+}
+
+fn virtio_scsi_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_fallocate, vec![]),
+        (libc::SYS_fdatasync, vec![]),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_pread64, vec![]),
+        (libc::SYS_pwrite64, vec![]),
         (libc::SYS_sched_getaffinity, vec![]),
         (libc::SYS_set_robust_list, vec![]),
     ]
@@ -232,6 +260,7 @@ fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
         Thread::VirtioNetCtl => virtio_net_ctl_thread_rules(),
         Thread::VirtioPmem => virtio_pmem_thread_rules(),
         Thread::VirtioRng => virtio_rng_thread_rules(),
+        Thread::VirtioScsi => virtio_scsi_thread_rules(),
         Thread::VirtioVhostBlock => virtio_vhost_block_thread_rules(),
         Thread::VirtioVhostFs => virtio_vhost_fs_thread_rules(),
         Thread::VirtioVhostNet => virtio_vhost_net_thread_rules(),
@@ -0,0 +1,571 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::transport::{VirtioDeviceActivator, VirtioTransport};
+use crate::GuestMemoryMmap;
+use crate::{
+    VirtioDevice, VirtioInterrupt, VirtioInterruptType, DEVICE_ACKNOWLEDGE, DEVICE_DRIVER,
+    DEVICE_DRIVER_OK, DEVICE_FAILED, DEVICE_FEATURES_OK, DEVICE_INIT, DEVICE_NEEDS_RESET,
+};
+use anyhow::anyhow;
+use byteorder::{ByteOrder, LittleEndian};
+use libc::EFD_NONBLOCK;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use thiserror::Error;
+use virtio_queue::{Queue, QueueT};
+use vm_device::interrupt::InterruptSourceGroup;
+use vm_device::BusDevice;
+use vm_memory::{GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vmm_sys_util::eventfd::EventFd;
+
+/// Size of the MMIO region exposed by each virtio-mmio device.
+pub const VIRTIO_MMIO_DEVICE_SIZE: u64 = 0x1000;
+
+// "virt" in little endian.
+const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
+// Only the modern (non-legacy) transport is exposed.
+const MMIO_VERSION: u32 = 2;
+const VENDOR_ID: u32 = 0;
+
+// Register offsets, as defined by the virtio specification (4.2.2).
+const REG_MAGIC_VALUE: u64 = 0x000;
+const REG_VERSION: u64 = 0x004;
+const REG_DEVICE_ID: u64 = 0x008;
+const REG_VENDOR_ID: u64 = 0x00c;
+const REG_DEVICE_FEATURES: u64 = 0x010;
+const REG_DEVICE_FEATURES_SEL: u64 = 0x014;
+const REG_DRIVER_FEATURES: u64 = 0x020;
+const REG_DRIVER_FEATURES_SEL: u64 = 0x024;
+const REG_QUEUE_SEL: u64 = 0x030;
+const REG_QUEUE_NUM_MAX: u64 = 0x034;
+const REG_QUEUE_NUM: u64 = 0x038;
+const REG_QUEUE_READY: u64 = 0x044;
+const REG_QUEUE_NOTIFY: u64 = 0x050;
+const REG_INTERRUPT_STATUS: u64 = 0x060;
+const REG_INTERRUPT_ACK: u64 = 0x064;
+const REG_STATUS: u64 = 0x070;
+const REG_QUEUE_DESC_LOW: u64 = 0x080;
+const REG_QUEUE_DESC_HIGH: u64 = 0x084;
+const REG_QUEUE_DRIVER_LOW: u64 = 0x090;
+const REG_QUEUE_DRIVER_HIGH: u64 = 0x094;
+const REG_QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const REG_CONFIG_GENERATION: u64 = 0x0fc;
+const REG_CONFIG: u64 = 0x100;
+
+// Interrupt status bits.
+const VIRTIO_MMIO_INT_VRING: usize = 0x01;
+const VIRTIO_MMIO_INT_CONFIG: usize = 0x02;
+
+#[derive(Error, Debug)]
+pub enum VirtioMmioDeviceError {
+    #[error("Failed creating VirtioMmioDevice: {0}")]
+    CreateVirtioMmioDevice(#[source] anyhow::Error),
+}
+pub type Result<T> = std::result::Result<T, VirtioMmioDeviceError>;
+
+/// Virtio interrupt delivered through a single pin based interrupt, as
+/// expected by the virtio-mmio transport.
+struct VirtioInterruptIrq {
+    interrupt_status: Arc<AtomicUsize>,
+    interrupt: Arc<dyn InterruptSourceGroup>,
+}
+
+impl VirtioInterrupt for VirtioInterruptIrq {
+    fn trigger(&self, int_type: VirtioInterruptType) -> std::result::Result<(), std::io::Error> {
+        let status = match int_type {
+            VirtioInterruptType::Config => VIRTIO_MMIO_INT_CONFIG,
+            VirtioInterruptType::Queue(_) => VIRTIO_MMIO_INT_VRING,
+        };
+        self.interrupt_status.fetch_or(status, Ordering::AcqRel);
+        self.interrupt.trigger(0)
+    }
+}
+
+/// Implements the virtio-mmio (version 2) transport, used to expose virtio
+/// devices to guests booted without ACPI, hence without PCI enumeration.
+pub struct VirtioMmioDevice {
+    id: String,
+
+    // Virtio device reference and status
+    device: Arc<Mutex<dyn VirtioDevice>>,
+    device_activated: Arc<AtomicBool>,
+
+    // Transport registers
+    driver_status: u32,
+    device_feature_select: u32,
+    driver_feature_select: u32,
+    queue_select: u32,
+
+    // Interrupts
+    interrupt_status: Arc<AtomicUsize>,
+    virtio_interrupt: Option<Arc<dyn VirtioInterrupt>>,
+
+    // virtio queues
+    queues: Vec<Queue>,
+    queue_evts: Vec<EventFd>,
+
+    // Guest memory
+    memory: GuestMemoryAtomic<GuestMemoryMmap>,
+
+    // EventFd to signal on to request activation
+    activate_evt: EventFd,
+
+    // Pending activations
+    pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+}
+
+impl VirtioMmioDevice {
+    /// Constructs a new MMIO transport for the given virtio device.
+    pub fn new(
+        id: String,
+        memory: GuestMemoryAtomic<GuestMemoryMmap>,
+        device: Arc<Mutex<dyn VirtioDevice>>,
+        interrupt: Arc<dyn InterruptSourceGroup>,
+        activate_evt: EventFd,
+        pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+    ) -> Result<Self> {
+        let locked_device = device.lock().unwrap();
+        let mut queue_evts = Vec::new();
+        for _ in locked_device.queue_max_sizes().iter() {
+            queue_evts.push(EventFd::new(EFD_NONBLOCK).map_err(|e| {
+                VirtioMmioDeviceError::CreateVirtioMmioDevice(anyhow!(
+                    "Failed creating eventfd: {}",
+                    e
+                ))
+            })?)
+        }
+
+        let queues: Vec<Queue> = locked_device
+            .queue_max_sizes()
+            .iter()
+            .map(|&s| Queue::new(s).unwrap())
+            .collect();
+        std::mem::drop(locked_device);
+
+        let interrupt_status = Arc::new(AtomicUsize::new(0));
+        let virtio_interrupt: Arc<dyn VirtioInterrupt> = Arc::new(VirtioInterruptIrq {
+            interrupt_status: interrupt_status.clone(),
+            interrupt,
+        });
+
+        Ok(VirtioMmioDevice {
+            id,
+            device,
+            device_activated: Arc::new(AtomicBool::new(false)),
+            driver_status: DEVICE_INIT,
+            device_feature_select: 0,
+            driver_feature_select: 0,
+            queue_select: 0,
+            interrupt_status,
+            virtio_interrupt: Some(virtio_interrupt),
+            queues,
+            queue_evts,
+            memory,
+            activate_evt,
+            pending_activations,
+        })
+    }
+
+    pub fn virtio_device(&self) -> Arc<Mutex<dyn VirtioDevice>> {
+        self.device.clone()
+    }
+
+    fn is_driver_ready(&self) -> bool {
+        let ready_bits = DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_DRIVER_OK | DEVICE_FEATURES_OK;
+        self.driver_status == ready_bits && self.driver_status & DEVICE_FAILED == 0
+    }
+
+    fn needs_activation(&self) -> bool {
+        !self.device_activated.load(Ordering::SeqCst) && self.is_driver_ready()
+    }
+
+    fn with_queue<U, F>(&self, f: F) -> Option<U>
+    where
+        F: FnOnce(&Queue) -> U,
+    {
+        self.queues.get(self.queue_select as usize).map(f)
+    }
+
+    fn with_queue_mut<F: FnOnce(&mut Queue)>(&mut self, f: F) {
+        // Queues can only be configured before the device is activated.
+        if self.device_activated.load(Ordering::SeqCst) {
+            warn!("{}: Ignoring queue update on an active device", self.id);
+            return;
+        }
+        if let Some(queue) = self.queues.get_mut(self.queue_select as usize) {
+            f(queue);
+        }
+    }
+
+    fn prepare_activator(&mut self, barrier: Option<Arc<Barrier>>) -> VirtioDeviceActivator {
+        let mut queues = Vec::new();
+
+        for (queue_index, queue) in self.queues.iter().enumerate() {
+            if !queue.ready() {
+                continue;
+            }
+
+            if !queue.is_valid(self.memory.memory().deref()) {
+                error!("Queue {} is not valid", queue_index);
+            }
+
+            queues.push((
+                queue_index,
+                vm_virtio::clone_queue(queue),
+                self.queue_evts[queue_index].try_clone().unwrap(),
+            ));
+        }
+
+        VirtioDeviceActivator::new(
+            self.virtio_interrupt.take(),
+            self.memory.clone(),
+            self.device.clone(),
+            self.device_activated.clone(),
+            queues,
+            barrier,
+            self.id.clone(),
+        )
+    }
+
+    fn reset(&mut self) {
+        if self.device_activated.load(Ordering::SeqCst) {
+            let mut device = self.device.lock().unwrap();
+            if let Some(virtio_interrupt) = device.reset() {
+                // Upon reset the device returns its interrupt EventFD
+                self.virtio_interrupt = Some(virtio_interrupt);
+                self.device_activated.store(false, Ordering::SeqCst);
+            } else {
+                error!("Attempt to reset device when not implemented in underlying device");
+                self.driver_status = DEVICE_FAILED;
+                return;
+            }
+        }
+
+        // Reset queue readiness, queue sizes and selected queue as per spec
+        // for reset.
+        self.queues.iter_mut().for_each(Queue::reset);
+        self.queue_select = 0;
+        self.device_feature_select = 0;
+        self.driver_feature_select = 0;
+        self.interrupt_status.store(0, Ordering::Release);
+    }
+
+    fn read_register(&self, offset: u64) -> u32 {
+        match offset {
+            REG_MAGIC_VALUE => MMIO_MAGIC_VALUE,
+            REG_VERSION => MMIO_VERSION,
+            REG_DEVICE_ID => self.device.lock().unwrap().device_type(),
+            REG_VENDOR_ID => VENDOR_ID,
+            REG_DEVICE_FEATURES => {
+                // Only 64 bits of features (2 pages) are defined for now, so limit
+                // device_feature_select to avoid shifting by 64 or more bits.
+                if self.device_feature_select < 2 {
+                    (self.device.lock().unwrap().features() >> (self.device_feature_select * 32))
+                        as u32
+                } else {
+                    0
+                }
+            }
+            REG_QUEUE_NUM_MAX => u32::from(self.with_queue(|q| q.max_size()).unwrap_or(0)),
+            REG_QUEUE_READY => u32::from(self.with_queue(|q| q.ready()).unwrap_or(false)),
+            REG_INTERRUPT_STATUS => self.interrupt_status.load(Ordering::Acquire) as u32,
+            REG_STATUS => {
+                if self.device.lock().unwrap().needs_reset() {
+                    self.driver_status | DEVICE_NEEDS_RESET
+                } else {
+                    self.driver_status
+                }
+            }
+            REG_CONFIG_GENERATION => 0,
+            _ => {
+                warn!(
+                    "{}: Invalid virtio-mmio register read: 0x{:x}",
+                    self.id, offset
+                );
+                0
+            }
+        }
+    }
+
+    fn write_register(&mut self, offset: u64, value: u32) {
+        match offset {
+            REG_DEVICE_FEATURES_SEL => self.device_feature_select = value,
+            REG_DRIVER_FEATURES => {
+                if self.driver_feature_select < 2 {
+                    self.device
+                        .lock()
+                        .unwrap()
+                        .ack_features(u64::from(value) << (self.driver_feature_select * 32));
+                } else {
+                    warn!(
+                        "invalid ack_features (page {}, value 0x{:x})",
+                        self.driver_feature_select, value
+                    );
+                }
+            }
+            REG_DRIVER_FEATURES_SEL => self.driver_feature_select = value,
+            REG_QUEUE_SEL => self.queue_select = value,
+            REG_QUEUE_NUM => self.with_queue_mut(|q| q.set_size(value as u16)),
+            REG_QUEUE_READY => self.with_queue_mut(|q| q.set_ready(value == 1)),
+            REG_QUEUE_NOTIFY => {
+                // Handled with ioeventfds.
+                error!("{}: Unexpected write to the queue notify register", self.id);
+            }
+            REG_INTERRUPT_ACK => {
+                self.interrupt_status
+                    .fetch_and(!(value as usize), Ordering::AcqRel);
+            }
+            REG_STATUS => {
+                if value == DEVICE_INIT {
+                    self.driver_status = DEVICE_INIT;
+                    self.reset();
+                } else {
+                    self.driver_status = value;
+                }
+            }
+            REG_QUEUE_DESC_LOW => {
+                self.with_queue_mut(|q| q.set_desc_table_address(Some(value), None))
+            }
+            REG_QUEUE_DESC_HIGH => {
+                self.with_queue_mut(|q| q.set_desc_table_address(None, Some(value)))
+            }
+            REG_QUEUE_DRIVER_LOW => {
+                self.with_queue_mut(|q| q.set_avail_ring_address(Some(value), None))
+            }
+            REG_QUEUE_DRIVER_HIGH => {
+                self.with_queue_mut(|q| q.set_avail_ring_address(None, Some(value)))
+            }
+            REG_QUEUE_DEVICE_LOW => {
+                self.with_queue_mut(|q| q.set_used_ring_address(Some(value), None))
+            }
+            REG_QUEUE_DEVICE_HIGH => {
+                self.with_queue_mut(|q| q.set_used_ring_address(None, Some(value)))
+            }
+            _ => {
+                warn!(
+                    "{}: Invalid virtio-mmio register write: 0x{:x}",
+                    self.id, offset
+                );
+            }
+        }
+    }
+}
+
+impl VirtioTransport for VirtioMmioDevice {
+    fn ioeventfds(&self, base_addr: u64) -> Vec<(&EventFd, u64)> {
+        // All queues share the same notification register, the queue index
+        // being the value written by the driver.
+        self.queue_evts
+            .iter()
+            .map(|event| (event, base_addr + REG_QUEUE_NOTIFY))
+            .collect()
+    }
+}
+
+impl BusDevice for VirtioMmioDevice {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if offset >= REG_CONFIG {
+            self.device
+                .lock()
+                .unwrap()
+                .read_config(offset - REG_CONFIG, data);
+            return;
+        }
+
+        // All the transport registers are 32 bits wide.
+        if data.len() != 4 || offset % 4 != 0 {
+            warn!(
+                "{}: Invalid virtio-mmio register read: offset 0x{:x}, len {}",
+                self.id,
+                offset,
+                data.len()
+            );
+            return;
+        }
+        LittleEndian::write_u32(data, self.read_register(offset));
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if offset >= REG_CONFIG {
+            self.device
+                .lock()
+                .unwrap()
+                .write_config(offset - REG_CONFIG, data);
+            return None;
+        }
+
+        // All the transport registers are 32 bits wide.
+        if data.len() != 4 || offset % 4 != 0 {
+            warn!(
+                "{}: Invalid virtio-mmio register write: offset 0x{:x}, len {}",
+                self.id,
+                offset,
+                data.len()
+            );
+            return None;
+        }
+        self.write_register(offset, LittleEndian::read_u32(data));
+
+        // Try and activate the device if the driver status has changed
+        if self.needs_activation() {
+            let barrier = Arc::new(Barrier::new(2));
+            let activator = self.prepare_activator(Some(barrier.clone()));
+            self.pending_activations.lock().unwrap().push(activator);
+            info!(
+                "{}: Needs activation; writing to activate event fd",
+                self.id
+            );
+            self.activate_evt.write(1).ok();
+            info!("{}: Needs activation; returning barrier", self.id);
+            return Some(barrier);
+        }
+
+        None
+    }
+}
+
+impl Pausable for VirtioMmioDevice {}
+
+impl Snapshottable for VirtioMmioDevice {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Err(MigratableError::Snapshot(anyhow!(
+            "Snapshotting virtio-mmio devices is not supported"
+        )))
+    }
+}
+impl Transportable for VirtioMmioDevice {}
+impl Migratable for VirtioMmioDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ActivateError, VirtioDeviceType};
+    use vm_memory::GuestAddress;
+
+    struct DummyDevice;
+
+    impl VirtioDevice for DummyDevice {
+        fn device_type(&self) -> u32 {
+            VirtioDeviceType::Rng as u32
+        }
+
+        fn queue_max_sizes(&self) -> &[u16] {
+            &[256]
+        }
+
+        fn features(&self) -> u64 {
+            1 << crate::VIRTIO_F_VERSION_1
+        }
+
+        fn activate(
+            &mut self,
+            _mem: GuestMemoryAtomic<GuestMemoryMmap>,
+            _interrupt_evt: Arc<dyn VirtioInterrupt>,
+            _queues: Vec<(usize, Queue, EventFd)>,
+        ) -> std::result::Result<(), ActivateError> {
+            Ok(())
+        }
+    }
+
+    struct DummyInterrupt;
+
+    impl InterruptSourceGroup for DummyInterrupt {
+        fn trigger(&self, _index: u32) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn notifier(&self, _index: u32) -> Option<EventFd> {
+            None
+        }
+
+        fn update(
+            &self,
+            _index: u32,
+            _config: vm_device::interrupt::InterruptSourceConfig,
+            _masked: bool,
+            _set_gsi: bool,
+        ) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn set_gsi(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn read_reg(dev: &mut VirtioMmioDevice, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        dev.read(0, offset, &mut data);
+        LittleEndian::read_u32(&data)
+    }
+
+    fn write_reg(dev: &mut VirtioMmioDevice, offset: u64, value: u32) -> Option<Arc<Barrier>> {
+        let mut data = [0u8; 4];
+        LittleEndian::write_u32(&mut data, value);
+        dev.write(0, offset, &data)
+    }
+
+    #[test]
+    fn test_virtio_mmio_device() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let pending_activations = Arc::new(Mutex::new(Vec::new()));
+        let mut dev = VirtioMmioDevice::new(
+            "mmio0".to_string(),
+            GuestMemoryAtomic::new(mem),
+            Arc::new(Mutex::new(DummyDevice)),
+            Arc::new(DummyInterrupt),
+            EventFd::new(EFD_NONBLOCK).unwrap(),
+            pending_activations.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(read_reg(&mut dev, REG_MAGIC_VALUE), MMIO_MAGIC_VALUE);
+        assert_eq!(read_reg(&mut dev, REG_VERSION), MMIO_VERSION);
+        assert_eq!(
+            read_reg(&mut dev, REG_DEVICE_ID),
+            VirtioDeviceType::Rng as u32
+        );
+
+        write_reg(&mut dev, REG_DEVICE_FEATURES_SEL, 1);
+        assert_eq!(read_reg(&mut dev, REG_DEVICE_FEATURES), 1);
+        write_reg(&mut dev, REG_QUEUE_SEL, 0);
+        assert_eq!(read_reg(&mut dev, REG_QUEUE_NUM_MAX), 256);
+        write_reg(&mut dev, REG_QUEUE_SEL, 1);
+        assert_eq!(read_reg(&mut dev, REG_QUEUE_NUM_MAX), 0);
+
+        // Driving the status to DRIVER_OK requests the device activation.
+        write_reg(&mut dev, REG_STATUS, DEVICE_ACKNOWLEDGE | DEVICE_DRIVER);
+        write_reg(
+            &mut dev,
+            REG_STATUS,
+            DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_FEATURES_OK,
+        );
+        assert!(write_reg(
+            &mut dev,
+            REG_STATUS,
+            DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_FEATURES_OK | DEVICE_DRIVER_OK,
+        )
+        .is_some());
+        assert_eq!(pending_activations.lock().unwrap().len(), 1);
+
+        // Interrupt status is cleared through the acknowledge register.
+        dev.interrupt_status.store(
+            VIRTIO_MMIO_INT_VRING | VIRTIO_MMIO_INT_CONFIG,
+            Ordering::Release,
+        );
+        write_reg(&mut dev, REG_INTERRUPT_ACK, VIRTIO_MMIO_INT_VRING as u32);
+        assert_eq!(
+            read_reg(&mut dev, REG_INTERRUPT_STATUS),
+            VIRTIO_MMIO_INT_CONFIG as u32
+        );
+    }
+}
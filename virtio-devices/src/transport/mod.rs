@@ -2,12 +2,71 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::{ActivateResult, GuestMemoryMmap, VirtioDevice, VirtioInterrupt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use virtio_queue::Queue;
+use vm_memory::GuestMemoryAtomic;
 use vmm_sys_util::eventfd::EventFd;
+mod mmio;
 mod pci_common_config;
 mod pci_device;
+pub use mmio::{VirtioMmioDevice, VirtioMmioDeviceError, VIRTIO_MMIO_DEVICE_SIZE};
 pub use pci_common_config::{VirtioPciCommonConfig, VIRTIO_PCI_COMMON_CONFIG_ID};
-pub use pci_device::{VirtioPciDevice, VirtioPciDeviceActivator, VirtioPciDeviceError};
+pub use pci_device::{VirtioPciDevice, VirtioPciDeviceError};
 
 pub trait VirtioTransport {
     fn ioeventfds(&self, base_addr: u64) -> Vec<(&EventFd, u64)>;
 }
+
+/// Activates a virtio device on behalf of its transport, once the driver is
+/// ready. This is deferred to the VMM thread, as the device activation can't
+/// happen from the vCPU thread handling the guest access.
+pub struct VirtioDeviceActivator {
+    interrupt: Option<Arc<dyn VirtioInterrupt>>,
+    memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    device: Arc<Mutex<dyn VirtioDevice>>,
+    device_activated: Arc<AtomicBool>,
+    queues: Option<Vec<(usize, Queue, EventFd)>>,
+    barrier: Option<Arc<Barrier>>,
+    id: String,
+}
+
+impl VirtioDeviceActivator {
+    pub(crate) fn new(
+        interrupt: Option<Arc<dyn VirtioInterrupt>>,
+        memory: GuestMemoryAtomic<GuestMemoryMmap>,
+        device: Arc<Mutex<dyn VirtioDevice>>,
+        device_activated: Arc<AtomicBool>,
+        queues: Vec<(usize, Queue, EventFd)>,
+        barrier: Option<Arc<Barrier>>,
+        id: String,
+    ) -> Self {
+        VirtioDeviceActivator {
+            interrupt,
+            memory: Some(memory),
+            device,
+            device_activated,
+            queues: Some(queues),
+            barrier,
+            id,
+        }
+    }
+
+    pub fn activate(&mut self) -> ActivateResult {
+        self.device.lock().unwrap().activate(
+            self.memory.take().unwrap(),
+            self.interrupt.take().unwrap(),
+            self.queues.take().unwrap(),
+        )?;
+        self.device_activated.store(true, Ordering::SeqCst);
+
+        if let Some(barrier) = self.barrier.take() {
+            info!("{}: Waiting for barrier", self.id);
+            barrier.wait();
+            info!("{}: Barrier released", self.id);
+        }
+
+        Ok(())
+    }
+}
@@ -104,7 +104,7 @@ impl VirtioPciCommonConfig {
 
         match data.len() {
             1 => {
-                let v = self.read_common_config_byte(offset);
+                let v = self.read_common_config_byte(offset, device);
                 data[0] = v;
             }
             2 => {
@@ -143,11 +143,17 @@ impl VirtioPciCommonConfig {
         }
     }
 
-    fn read_common_config_byte(&self, offset: u64) -> u8 {
+    fn read_common_config_byte(&self, offset: u64, device: Arc<Mutex<dyn VirtioDevice>>) -> u8 {
         debug!("read_common_config_byte: offset 0x{:x}", offset);
         // The driver is only allowed to do aligned, properly sized access.
         match offset {
-            0x14 => self.driver_status,
+            0x14 => {
+                if device.lock().unwrap().needs_reset() {
+                    self.driver_status | crate::DEVICE_NEEDS_RESET as u8
+                } else {
+                    self.driver_status
+                }
+            }
             0x15 => self.config_generation,
             _ => {
                 warn!("invalid virtio config byte read: 0x{:x}", offset);
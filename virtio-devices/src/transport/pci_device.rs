@@ -6,7 +6,9 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
-use crate::transport::{VirtioPciCommonConfig, VirtioTransport, VIRTIO_PCI_COMMON_CONFIG_ID};
+use crate::transport::{
+    VirtioDeviceActivator, VirtioPciCommonConfig, VirtioTransport, VIRTIO_PCI_COMMON_CONFIG_ID,
+};
 use crate::GuestMemoryMmap;
 use crate::{
     ActivateResult, VirtioDevice, VirtioDeviceType, VirtioInterrupt, VirtioInterruptType,
@@ -18,7 +20,8 @@ use libc::EFD_NONBLOCK;
 use pci::{
     BarReprogrammingParams, MsixCap, MsixConfig, PciBarConfiguration, PciBarRegionType,
     PciCapability, PciCapabilityId, PciClassCode, PciConfiguration, PciDevice, PciDeviceError,
-    PciHeaderType, PciMassStorageSubclass, PciNetworkControllerSubclass, PciSubclass,
+    PciExpressCap, PciExpressDevicePortType, PciHeaderType, PciMassStorageSubclass,
+    PciNetworkControllerSubclass, PciSubclass,
 };
 use std::any::Any;
 use std::cmp;
@@ -282,35 +285,6 @@ pub struct VirtioPciDeviceState {
 
 impl VersionMapped for VirtioPciDeviceState {}
 
-pub struct VirtioPciDeviceActivator {
-    interrupt: Option<Arc<dyn VirtioInterrupt>>,
-    memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
-    device: Arc<Mutex<dyn VirtioDevice>>,
-    device_activated: Arc<AtomicBool>,
-    queues: Option<Vec<(usize, Queue, EventFd)>>,
-    barrier: Option<Arc<Barrier>>,
-    id: String,
-}
-
-impl VirtioPciDeviceActivator {
-    pub fn activate(&mut self) -> ActivateResult {
-        self.device.lock().unwrap().activate(
-            self.memory.take().unwrap(),
-            self.interrupt.take().unwrap(),
-            self.queues.take().unwrap(),
-        )?;
-        self.device_activated.store(true, Ordering::SeqCst);
-
-        if let Some(barrier) = self.barrier.take() {
-            info!("{}: Waiting for barrier", self.id);
-            barrier.wait();
-            info!("{}: Barrier released", self.id);
-        }
-
-        Ok(())
-    }
-}
-
 #[derive(Error, Debug)]
 pub enum VirtioPciDeviceError {
     #[error("Failed creating VirtioPciDevice: {0}")]
@@ -373,7 +347,7 @@ pub struct VirtioPciDevice {
     dma_handler: Option<Arc<dyn ExternalDmaMapping>>,
 
     // Pending activations
-    pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+    pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
 }
 
 impl VirtioPciDevice {
@@ -390,7 +364,7 @@ impl VirtioPciDevice {
         activate_evt: EventFd,
         use_64bit_bar: bool,
         dma_handler: Option<Arc<dyn ExternalDmaMapping>>,
-        pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+        pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
         snapshot: Option<Snapshot>,
     ) -> Result<Self> {
         let mut locked_device = device.lock().unwrap();
@@ -464,6 +438,10 @@ impl VirtioPciDevice {
                 PciClassCode::MassStorage,
                 &PciMassStorageSubclass::MassStorage as &dyn PciSubclass,
             ),
+            VirtioDeviceType::Scsi => (
+                PciClassCode::MassStorage,
+                &PciMassStorageSubclass::ScsiStorage as &dyn PciSubclass,
+            ),
             _ => (
                 PciClassCode::Other,
                 &PciVirtioSubclass::NonTransitionalBase as &dyn PciSubclass,
@@ -728,6 +706,15 @@ impl VirtioPciDevice {
                 .map_err(PciDeviceError::CapabilitiesSetup)?;
         }
 
+        // Expose the device as a PCI Express endpoint so that the guest
+        // can access its extended configuration space. It sits on the root
+        // bus, without any root port above it, making it integrated into
+        // the root complex.
+        let pcie_cap = PciExpressCap::new(PciExpressDevicePortType::RootComplexIntegratedEndpoint);
+        self.configuration
+            .add_capability(&pcie_cap)
+            .map_err(PciDeviceError::CapabilitiesSetup)?;
+
         self.settings_bar = settings_bar;
         Ok(())
     }
@@ -780,7 +767,7 @@ impl VirtioPciDevice {
         self.device.clone()
     }
 
-    fn prepare_activator(&mut self, barrier: Option<Arc<Barrier>>) -> VirtioPciDeviceActivator {
+    fn prepare_activator(&mut self, barrier: Option<Arc<Barrier>>) -> VirtioDeviceActivator {
         let mut queues = Vec::new();
 
         for (queue_index, queue) in self.queues.iter().enumerate() {
@@ -799,15 +786,15 @@ impl VirtioPciDevice {
             ));
         }
 
-        VirtioPciDeviceActivator {
-            interrupt: self.virtio_interrupt.take(),
-            memory: Some(self.memory.clone()),
-            device: self.device.clone(),
-            queues: Some(queues),
-            device_activated: self.device_activated.clone(),
+        VirtioDeviceActivator::new(
+            self.virtio_interrupt.take(),
+            self.memory.clone(),
+            self.device.clone(),
+            self.device_activated.clone(),
+            queues,
             barrier,
-            id: self.id.clone(),
-        }
+            self.id.clone(),
+        )
     }
 
     fn activate(&mut self) -> ActivateResult {
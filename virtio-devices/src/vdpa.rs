@@ -408,6 +408,10 @@ impl VirtioDevice for Vdpa {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         assert!(self.vhost.is_some());
         if let Err(e) = self.vhost.as_ref().unwrap().get_config(offset as u32, data) {
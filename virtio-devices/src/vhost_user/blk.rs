@@ -193,6 +193,7 @@ impl Blk {
                 acked_protocol_features,
                 socket_path: vu_cfg.socket,
                 vu_num_queues,
+                reconnect: true,
                 ..Default::default()
             },
             id,
@@ -253,6 +254,10 @@ impl VirtioDevice for Blk {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
@@ -14,7 +14,7 @@ use crate::{GuestMemoryMmap, GuestRegionMmap, MmapRegion};
 use libc::{self, c_void, off64_t, pread64, pwrite64};
 use seccompiler::SeccompAction;
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Barrier, Mutex};
@@ -307,11 +307,13 @@ pub struct Fs {
 }
 
 impl Fs {
-    /// Create a new virtio-fs device.
+    /// Create a new virtio-fs device. The backend is reached either through
+    /// the socket `path` or through `fd`, an already connected socket.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         path: &str,
+        fd: Option<RawFd>,
         tag: &str,
         req_num_queues: usize,
         queue_size: u16,
@@ -319,6 +321,7 @@ impl Fs {
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
         iommu: bool,
+        reconnect: bool,
         state: Option<State>,
     ) -> Result<Fs> {
         let mut slave_req_support = false;
@@ -327,7 +330,11 @@ impl Fs {
         let num_queues = NUM_QUEUE_OFFSET + req_num_queues;
 
         // Connect to the vhost-user socket.
-        let mut vu = VhostUserHandle::connect_vhost_user(false, path, num_queues as u64, false)?;
+        let mut vu = if let Some(fd) = fd {
+            VhostUserHandle::from_fd(fd, num_queues as u64)?
+        } else {
+            VhostUserHandle::connect_vhost_user(false, path, num_queues as u64, false)?
+        };
 
         let (
             avail_features,
@@ -432,6 +439,7 @@ impl Fs {
                 acked_protocol_features,
                 socket_path: path.to_string(),
                 vu_num_queues,
+                reconnect,
                 ..Default::default()
             },
             id,
@@ -494,6 +502,10 @@ impl VirtioDevice for Fs {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
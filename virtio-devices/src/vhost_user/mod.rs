@@ -67,6 +67,10 @@ pub enum Error {
     VhostUserOpen(VhostError),
     #[error("Connection to socket failed")]
     VhostUserConnect,
+    #[error("No socket path to (re)connect the vhost-user backend")]
+    MissingSocketPath,
+    #[error("Failed duplicating the vhost-user socket fd: {0}")]
+    DupSocketFd(io::Error),
     #[error("Get features failed: {0}")]
     VhostUserGetFeatures(VhostError),
     #[error("Get queue max number failed: {0}")]
@@ -174,6 +178,7 @@ pub struct VhostUserEpollHandler<S: VhostUserMasterReqHandler> {
     pub acked_protocol_features: u64,
     pub socket_path: String,
     pub server: bool,
+    pub reconnect: bool,
     pub slave_req_handler: Option<MasterReqHandler<S>>,
     pub inflight: Option<Inflight>,
 }
@@ -251,6 +256,8 @@ impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
         let mut vu = self.vu.lock().unwrap();
         *vu = vhost_user;
 
+        info!("Reconnected to vhost-user backend {}", self.socket_path);
+
         Ok(())
     }
 }
@@ -263,7 +270,11 @@ impl<S: VhostUserMasterReqHandler> EpollHelperHandler for VhostUserEpollHandler<
     ) -> std::result::Result<(), EpollHelperError> {
         let ev_type = event.data as u16;
         match ev_type {
-            HUP_CONNECTION_EVENT => {
+            HUP_CONNECTION_EVENT if self.reconnect => {
+                warn!(
+                    "Connection to vhost-user backend {} dropped, reconnecting",
+                    self.socket_path
+                );
                 self.reconnect(helper).map_err(|e| {
                     EpollHelperError::HandleEvent(anyhow!(
                         "failed to reconnect vhost-user backend: {:?}",
@@ -271,6 +282,16 @@ impl<S: VhostUserMasterReqHandler> EpollHelperHandler for VhostUserEpollHandler<
                     ))
                 })?;
             }
+            HUP_CONNECTION_EVENT => {
+                // The device is left without a backend, the requests of the
+                // guest won't be completed.
+                helper.del_event_custom(
+                    self.vu.lock().unwrap().socket_handle().as_raw_fd(),
+                    HUP_CONNECTION_EVENT,
+                    epoll::Events::EPOLLHUP,
+                )?;
+                error!("Connection to vhost-user backend dropped");
+            }
             SLAVE_REQ_EVENT => {
                 if let Some(slave_req_handler) = self.slave_req_handler.as_mut() {
                     slave_req_handler.handle_request().map_err(|e| {
@@ -300,6 +321,8 @@ pub struct VhostUserCommon {
     pub vu_num_queues: usize,
     pub migration_started: bool,
     pub server: bool,
+    // Reconnect to the backend when the connection drops
+    pub reconnect: bool,
 }
 
 impl VhostUserCommon {
@@ -353,6 +376,7 @@ impl VhostUserCommon {
             acked_protocol_features: self.acked_protocol_features,
             socket_path: self.socket_path.clone(),
             server: self.server,
+            reconnect: self.reconnect,
             slave_req_handler,
             inflight,
         })
@@ -221,6 +221,7 @@ impl Net {
                 socket_path: vu_cfg.socket,
                 vu_num_queues,
                 server,
+                reconnect: true,
                 ..Default::default()
             },
             config,
@@ -288,6 +289,10 @@ impl VirtioDevice for Net {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
@@ -315,7 +320,7 @@ impl VirtioDevice for Net {
                 mem: mem.clone(),
                 kill_evt,
                 pause_evt,
-                ctrl_q: CtrlQueue::new(Vec::new()),
+                ctrl_q: CtrlQueue::new(Vec::new(), None),
                 queue: ctrl_queue,
                 queue_evt: ctrl_queue_evt,
                 access_platform: None,
@@ -11,7 +11,7 @@ use std::convert::TryInto;
 use std::ffi;
 use std::fs::File;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::os::unix::net::UnixListener;
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread::sleep;
@@ -33,6 +33,10 @@ use vmm_sys_util::eventfd::EventFd;
 // Size of a dirty page for vhost-user.
 const VHOST_LOG_PAGE: u64 = 0x1000;
 
+// Bounds of the delay between two attempts at connecting the backend
+const CONNECT_RETRY_MIN_DELAY: Duration = Duration::from_millis(100);
+const CONNECT_RETRY_MAX_DELAY: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 pub struct VhostUserConfig {
     pub socket: String,
@@ -362,6 +366,12 @@ impl VhostUserHandle {
         num_queues: u64,
         unlink_socket: bool,
     ) -> Result<Self> {
+        // A backend connection handed over as a file descriptor cannot be
+        // re-established since there is no path to connect to.
+        if socket_path.is_empty() {
+            return Err(Error::MissingSocketPath);
+        }
+
         if server {
             if unlink_socket {
                 std::fs::remove_file(socket_path).map_err(Error::RemoveSocketPath)?;
@@ -383,8 +393,10 @@ impl VhostUserHandle {
             })
         } else {
             let now = Instant::now();
+            let mut retry_delay = CONNECT_RETRY_MIN_DELAY;
 
-            // Retry connecting for a full minute
+            // Retry connecting for a full minute, backing off exponentially
+            // so that a restarting backend isn't hammered.
             let err = loop {
                 let err = match Master::connect(socket_path, num_queues) {
                     Ok(m) => {
@@ -400,7 +412,8 @@ impl VhostUserHandle {
                     }
                     Err(e) => e,
                 };
-                sleep(Duration::from_millis(100));
+                sleep(retry_delay);
+                retry_delay = std::cmp::min(retry_delay * 2, CONNECT_RETRY_MAX_DELAY);
 
                 if now.elapsed().as_secs() >= 60 {
                     break err;
@@ -415,6 +428,30 @@ impl VhostUserHandle {
         }
     }
 
+    /// Create a handle from an already connected vhost-user socket. The fd
+    /// is duplicated, so the caller remains the owner of the original one.
+    pub fn from_fd(fd: RawFd, num_queues: u64) -> Result<Self> {
+        // SAFETY: FFI call, the return value is checked below.
+        let fd = unsafe { libc::dup(fd) };
+        if fd < 0 {
+            return Err(Error::DupSocketFd(std::io::Error::last_os_error()));
+        }
+
+        // SAFETY: fd is a valid file descriptor we just duplicated, hence
+        // we are its only owner.
+        let stream = unsafe { UnixStream::from_raw_fd(fd) };
+
+        Ok(VhostUserHandle {
+            vu: Master::from_stream(stream, num_queues),
+            ready: false,
+            supports_migration: false,
+            shm_log: None,
+            acked_features: 0,
+            vrings_info: None,
+            queue_indexes: Vec::new(),
+        })
+    }
+
     pub fn socket_handle(&mut self) -> &mut Master {
         &mut self.vu
     }
@@ -20,7 +20,7 @@ use std::fs::File;
 use std::io::{self, Read};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::result;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::Instant;
 use thiserror::Error;
@@ -71,6 +71,7 @@ struct WatchdogEpollHandler {
     timer: File,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
     reset_evt: EventFd,
+    triggered: Arc<AtomicBool>,
 }
 
 impl WatchdogEpollHandler {
@@ -171,6 +172,7 @@ impl EpollHelperHandler for WatchdogEpollHandler {
                     let gap = now.duration_since(*last_ping_time).as_secs();
                     if gap > WATCHDOG_TIMEOUT {
                         error!("Watchdog triggered: {} seconds since last ping", gap);
+                        self.triggered.store(true, Ordering::Release);
                         self.reset_evt.write(1).ok();
                     }
                 }
@@ -195,6 +197,7 @@ pub struct Watchdog {
     last_ping_time: Arc<Mutex<Option<Instant>>>,
     timer: File,
     exit_evt: EventFd,
+    triggered: Arc<AtomicBool>,
 }
 
 #[derive(Versionize)]
@@ -255,9 +258,16 @@ impl Watchdog {
             last_ping_time: Arc::new(Mutex::new(last_ping_time)),
             timer,
             exit_evt,
+            triggered: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Returns whether the watchdog fired since the last call, clearing the
+    /// condition at the same time.
+    pub fn take_triggered(&self) -> bool {
+        self.triggered.swap(false, Ordering::AcqRel)
+    }
+
     fn state(&self) -> WatchdogState {
         WatchdogState {
             avail_features: self.common.avail_features,
@@ -332,6 +342,10 @@ impl VirtioDevice for Watchdog {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn activate(
         &mut self,
         mem: GuestMemoryAtomic<GuestMemoryMmap>,
@@ -363,6 +377,7 @@ impl VirtioDevice for Watchdog {
             timer,
             last_ping_time: self.last_ping_time.clone(),
             reset_evt,
+            triggered: self.triggered.clone(),
         };
 
         let paused = self.common.paused.clone();
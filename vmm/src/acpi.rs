@@ -17,8 +17,11 @@ use arch::DeviceType;
 use arch::NumaNodes;
 use bitflags::bitflags;
 use pci::PciBdf;
+use std::io;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use thiserror::Error;
 use tracer::trace_scoped;
 use vm_memory::{Address, Bytes, GuestAddress, GuestMemoryRegion};
 use zerocopy::AsBytes;
@@ -39,6 +42,84 @@ pub const ACPI_APIC_GENERIC_REDISTRIBUTOR: u8 = 14;
 #[cfg(target_arch = "aarch64")]
 pub const ACPI_APIC_GENERIC_TRANSLATOR: u8 = 15;
 
+// Size of the header shared by all the system description tables
+const SDT_HEADER_SIZE: usize = 36;
+
+/// Errors associated with loading a custom DSDT
+#[derive(Debug, Error)]
+pub enum CustomDsdtError {
+    #[error("Cannot read the DSDT file: {0}")]
+    Read(#[source] io::Error),
+
+    #[error("The DSDT file is too short to hold a table header: {0} bytes")]
+    TooShort(usize),
+
+    #[error("Invalid DSDT signature: {0:?}")]
+    InvalidSignature([u8; 4]),
+
+    #[error("The DSDT length ({0} bytes) does not match the file size ({1} bytes)")]
+    InvalidLength(u32, usize),
+
+    #[error("Invalid DSDT checksum")]
+    InvalidChecksum,
+}
+
+/// A precompiled DSDT installed instead of the generated one, or whose
+/// definition block is appended to the generated one.
+pub struct CustomDsdt {
+    table: Vec<u8>,
+    append: bool,
+}
+
+impl CustomDsdt {
+    pub fn new(table: Vec<u8>, append: bool) -> Result<Self, CustomDsdtError> {
+        if table.len() < SDT_HEADER_SIZE {
+            return Err(CustomDsdtError::TooShort(table.len()));
+        }
+
+        let signature: [u8; 4] = table[0..4].try_into().unwrap();
+        if &signature != b"DSDT" {
+            return Err(CustomDsdtError::InvalidSignature(signature));
+        }
+
+        let length = u32::from_le_bytes(table[4..8].try_into().unwrap());
+        if length as usize != table.len() {
+            return Err(CustomDsdtError::InvalidLength(length, table.len()));
+        }
+
+        if table.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(CustomDsdtError::InvalidChecksum);
+        }
+
+        Ok(CustomDsdt { table, append })
+    }
+
+    pub fn from_file(path: &Path, append: bool) -> Result<Self, CustomDsdtError> {
+        let table = std::fs::read(path).map_err(CustomDsdtError::Read)?;
+        Self::new(table, append)
+    }
+
+    // The header of the custom table is kept, as its revision selects the
+    // width of the AML integers. Only the length and checksum are updated
+    // once the definition block has been appended.
+    fn to_sdt(&self) -> Sdt {
+        let header = &self.table[..SDT_HEADER_SIZE];
+        let mut dsdt = Sdt::new(
+            *b"DSDT",
+            SDT_HEADER_SIZE as u32,
+            header[8],
+            header[10..16].try_into().unwrap(),
+            header[16..24].try_into().unwrap(),
+            u32::from_le_bytes(header[24..28].try_into().unwrap()),
+        );
+        dsdt.write(28, <[u8; 4]>::try_from(&header[28..32]).unwrap());
+        dsdt.write(32, u32::from_le_bytes(header[32..36].try_into().unwrap()));
+        dsdt.append_slice(&self.table[SDT_HEADER_SIZE..]);
+
+        dsdt
+    }
+}
+
 #[allow(dead_code)]
 #[repr(packed)]
 #[derive(Default, AsBytes)]
@@ -173,8 +254,15 @@ pub fn create_dsdt_table(
     device_manager: &Arc<Mutex<DeviceManager>>,
     cpu_manager: &Arc<Mutex<CpuManager>>,
     memory_manager: &Arc<Mutex<MemoryManager>>,
+    custom_dsdt: Option<&CustomDsdt>,
 ) -> Sdt {
     trace_scoped!("create_dsdt_table");
+    if let Some(custom_dsdt) = custom_dsdt {
+        if !custom_dsdt.append {
+            return custom_dsdt.to_sdt();
+        }
+    }
+
     // DSDT
     let mut dsdt = Sdt::new(*b"DSDT", 36, 6, *b"CLOUDH", *b"CHDSDT  ", 1);
 
@@ -183,6 +271,9 @@ pub fn create_dsdt_table(
     device_manager.lock().unwrap().to_aml_bytes(&mut bytes);
     cpu_manager.lock().unwrap().to_aml_bytes(&mut bytes);
     memory_manager.lock().unwrap().to_aml_bytes(&mut bytes);
+    if let Some(custom_dsdt) = custom_dsdt {
+        bytes.extend_from_slice(&custom_dsdt.table[SDT_HEADER_SIZE..]);
+    }
     dsdt.append_slice(&bytes);
 
     dsdt
@@ -316,7 +407,7 @@ fn create_srat_table(numa_nodes: &NumaNodes) -> Sdt {
         }
 
         for cpu in &node.cpus {
-            let x2apic_id = *cpu as u32;
+            let x2apic_id = *cpu;
 
             // Flags
             // - Enabled = 1 (bit 0)
@@ -623,6 +714,7 @@ pub fn create_acpi_tables(
     memory_manager: &Arc<Mutex<MemoryManager>>,
     numa_nodes: &NumaNodes,
     tpm_enabled: bool,
+    custom_dsdt: Option<&CustomDsdt>,
 ) -> GuestAddress {
     trace_scoped!("create_acpi_tables");
 
@@ -631,7 +723,7 @@ pub fn create_acpi_tables(
     let mut tables: Vec<u64> = Vec::new();
 
     // DSDT
-    let dsdt = create_dsdt_table(device_manager, cpu_manager, memory_manager);
+    let dsdt = create_dsdt_table(device_manager, cpu_manager, memory_manager, custom_dsdt);
     let dsdt_offset = rsdp_offset.checked_add(Rsdp::len() as u64).unwrap();
     guest_mem
         .write_slice(dsdt.as_slice(), dsdt_offset)
@@ -829,12 +921,14 @@ pub fn create_acpi_tables_tdx(
     cpu_manager: &Arc<Mutex<CpuManager>>,
     memory_manager: &Arc<Mutex<MemoryManager>>,
     numa_nodes: &NumaNodes,
+    custom_dsdt: Option<&CustomDsdt>,
 ) -> Vec<Sdt> {
     // DSDT
     let mut tables = vec![create_dsdt_table(
         device_manager,
         cpu_manager,
         memory_manager,
+        custom_dsdt,
     )];
 
     // FACP aka FADT
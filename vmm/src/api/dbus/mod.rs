@@ -105,6 +105,16 @@ impl DBusApi {
         serde_json::to_string(&result).map_err(api_error)
     }
 
+    async fn vm_ping(&self) -> Result<String> {
+        let api_sender = self.clone_api_sender().await;
+        let api_notifier = self.clone_api_notifier()?;
+
+        let result = blocking::unblock(move || super::vm_ping(api_notifier, api_sender))
+            .await
+            .map_err(api_error)?;
+        serde_json::to_string(&result).map_err(api_error)
+    }
+
     async fn vmm_shutdown(&self) -> Result<()> {
         let api_sender = self.clone_api_sender().await;
         let api_notifier = self.clone_api_notifier()?;
@@ -187,10 +197,21 @@ impl DBusApi {
         ))
     }
 
+    async fn vm_config(&self) -> Result<Optional<String>> {
+        self.vm_action(VmAction::Config).await
+    }
+
     async fn vm_counters(&self) -> Result<Optional<String>> {
         self.vm_action(VmAction::Counters).await
     }
 
+    async fn vm_configure_counters(&self, vm_counters_data: String) -> Result<()> {
+        let vm_counters_data = serde_json::from_str(&vm_counters_data).map_err(api_error)?;
+        self.vm_action(VmAction::ConfigureCounters(Arc::new(vm_counters_data)))
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_create(&self, vm_config: String) -> Result<()> {
         let api_sender = self.clone_api_sender().await;
         let api_notifier = self.clone_api_notifier()?;
@@ -219,6 +240,18 @@ impl DBusApi {
         self.vm_action(VmAction::Delete).await.map(|_| ())
     }
 
+    async fn vm_dirty_rate(&self) -> Result<Optional<String>> {
+        self.vm_action(VmAction::DirtyRate).await
+    }
+
+    async fn vm_dirty_rate_start(&self) -> Result<()> {
+        self.vm_action(VmAction::DirtyRateStart).await.map(|_| ())
+    }
+
+    async fn vm_dirty_rate_stop(&self) -> Result<()> {
+        self.vm_action(VmAction::DirtyRateStop).await.map(|_| ())
+    }
+
     async fn vm_info(&self) -> Result<String> {
         let api_sender = self.clone_api_sender().await;
         let api_notifier = self.clone_api_notifier()?;
@@ -229,6 +262,13 @@ impl DBusApi {
         serde_json::to_string(&result).map_err(api_error)
     }
 
+    async fn vm_nmi(&self, vm_nmi_data: String) -> Result<()> {
+        let vm_nmi_data = serde_json::from_str(&vm_nmi_data).map_err(api_error)?;
+        self.vm_action(VmAction::Nmi(Arc::new(vm_nmi_data)))
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_pause(&self) -> Result<()> {
         self.vm_action(VmAction::Pause).await.map(|_| ())
     }
@@ -248,6 +288,20 @@ impl DBusApi {
             .map(|_| ())
     }
 
+    async fn vm_remove_disk(&self, vm_remove_disk: String) -> Result<()> {
+        let vm_remove_disk = serde_json::from_str(&vm_remove_disk).map_err(api_error)?;
+        self.vm_action(VmAction::RemoveDisk(Arc::new(vm_remove_disk)))
+            .await
+            .map(|_| ())
+    }
+
+    async fn vm_remove_net(&self, vm_remove_net: String) -> Result<()> {
+        let vm_remove_net = serde_json::from_str(&vm_remove_net).map_err(api_error)?;
+        self.vm_action(VmAction::RemoveNet(Arc::new(vm_remove_net)))
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_resize(&self, vm_resize: String) -> Result<()> {
         let vm_resize = serde_json::from_str(&vm_resize).map_err(api_error)?;
         self.vm_action(VmAction::Resize(Arc::new(vm_resize)))
@@ -277,6 +331,14 @@ impl DBusApi {
             .map(|_| ())
     }
 
+    async fn vm_migration_status(&self) -> Result<Optional<String>> {
+        self.vm_action(VmAction::MigrationStatus).await
+    }
+
+    async fn vm_migration_abort(&self) -> Result<()> {
+        self.vm_action(VmAction::MigrationAbort).await.map(|_| ())
+    }
+
     async fn vm_send_migration(&self, send_migration_data: String) -> Result<()> {
         let send_migration_data = serde_json::from_str(&send_migration_data).map_err(api_error)?;
         self.vm_action(VmAction::SendMigration(Arc::new(send_migration_data)))
@@ -299,6 +361,14 @@ impl DBusApi {
             .map(|_| ())
     }
 
+    async fn vm_topology(&self) -> Result<Optional<String>> {
+        self.vm_action(VmAction::Topology).await
+    }
+
+    async fn vm_resident_memory(&self) -> Result<Optional<String>> {
+        self.vm_action(VmAction::ResidentMemory).await
+    }
+
     // implementation of this function is provided by the `dbus_interface` macro
     #[dbus_interface(signal)]
     async fn event(ctxt: &zbus::SignalContext<'_>, event: Arc<String>) -> zbus::Result<()>;
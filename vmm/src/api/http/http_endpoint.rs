@@ -8,12 +8,14 @@ use crate::api::http::{error_response, EndpointHandler, HttpError};
 use crate::api::vm_coredump;
 use crate::api::{
     vm_add_device, vm_add_disk, vm_add_fs, vm_add_net, vm_add_pmem, vm_add_user_device,
-    vm_add_vdpa, vm_add_vsock, vm_boot, vm_counters, vm_create, vm_delete, vm_info, vm_pause,
-    vm_power_button, vm_reboot, vm_receive_migration, vm_remove_device, vm_resize, vm_resize_zone,
-    vm_restore, vm_resume, vm_send_migration, vm_shutdown, vm_snapshot, vmm_ping, vmm_shutdown,
-    ApiRequest, VmAction, VmConfig,
+    vm_add_vdpa, vm_add_vsock, vm_boot, vm_config, vm_configure_counters, vm_counters, vm_create,
+    vm_delete, vm_dirty_rate, vm_dirty_rate_start, vm_dirty_rate_stop, vm_info, vm_migration_abort,
+    vm_migration_status, vm_nmi, vm_pause, vm_ping, vm_power_button, vm_reboot,
+    vm_receive_migration, vm_remove_device, vm_remove_disk, vm_remove_net, vm_resident_memory,
+    vm_resize, vm_resize_zone, vm_restore, vm_resume, vm_send_migration, vm_shutdown, vm_snapshot,
+    vm_topology, vmm_ping, vmm_shutdown, ApiRequest, VmAction, VmConfig,
 };
-use crate::config::NetConfig;
+use crate::config::{FsConfig, NetConfig};
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
 use std::fs::File;
 use std::os::unix::io::IntoRawFd;
@@ -52,6 +54,15 @@ impl EndpointHandler for VmCreate {
                             }
                         }
 
+                        if let Some(ref mut fses) = vm_config.fs {
+                            if fses.iter().any(|fs| fs.fd.is_some()) {
+                                warn!("Ignoring FDs sent via the HTTP request body");
+                            }
+                            for fs in fses {
+                                fs.fd = None;
+                            }
+                        }
+
                         // Call vm_create()
                         match vm_create(api_notifier, api_sender, Arc::new(Mutex::new(vm_config)))
                             .map_err(HttpError::ApiError)
@@ -102,11 +113,19 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
-                AddFs(_) => vm_add_fs(
-                    api_notifier,
-                    api_sender,
-                    Arc::new(serde_json::from_slice(body.raw())?),
-                ),
+                AddFs(_) => {
+                    let mut fs_cfg: FsConfig = serde_json::from_slice(body.raw())?;
+                    if fs_cfg.fd.is_some() {
+                        warn!("Ignoring FDs sent via the HTTP request body");
+                        fs_cfg.fd = None;
+                    }
+                    // Update filesystem config with the optional connected
+                    // socket that might have been sent through control message.
+                    if !files.is_empty() {
+                        fs_cfg.fd = Some(files.remove(0).into_raw_fd());
+                    }
+                    vm_add_fs(api_notifier, api_sender, Arc::new(fs_cfg))
+                }
                 AddPmem(_) => vm_add_pmem(
                     api_notifier,
                     api_sender,
@@ -146,6 +165,16 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                RemoveDisk(_) => vm_remove_disk(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                RemoveNet(_) => vm_remove_net(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
                 Resize(_) => vm_resize(
                     api_notifier,
                     api_sender,
@@ -182,6 +211,16 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                Nmi(_) => vm_nmi(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                Counters => vm_configure_counters(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
 
                 _ => return Err(HttpError::BadRequest),
             }
@@ -194,6 +233,11 @@ impl EndpointHandler for VmActionHandler {
                 Pause => vm_pause(api_notifier, api_sender),
                 Resume => vm_resume(api_notifier, api_sender),
                 PowerButton => vm_power_button(api_notifier, api_sender),
+                Nmi(_) => vm_nmi(api_notifier, api_sender, Arc::default()),
+                DirtyRateStart => vm_dirty_rate_start(api_notifier, api_sender),
+                DirtyRateStop => vm_dirty_rate_stop(api_notifier, api_sender),
+                MigrationAbort => vm_migration_abort(api_notifier, api_sender),
+                Counters => vm_configure_counters(api_notifier, api_sender, Arc::default()),
                 _ => return Err(HttpError::BadRequest),
             }
         }
@@ -209,6 +253,15 @@ impl EndpointHandler for VmActionHandler {
         use VmAction::*;
         match self.action {
             Counters => vm_counters(api_notifier, api_sender).map_err(HttpError::ApiError),
+            DirtyRate => vm_dirty_rate(api_notifier, api_sender).map_err(HttpError::ApiError),
+            Topology => vm_topology(api_notifier, api_sender).map_err(HttpError::ApiError),
+            ResidentMemory => {
+                vm_resident_memory(api_notifier, api_sender).map_err(HttpError::ApiError)
+            }
+            Config => vm_config(api_notifier, api_sender).map_err(HttpError::ApiError),
+            MigrationStatus => {
+                vm_migration_status(api_notifier, api_sender).map_err(HttpError::ApiError)
+            }
             _ => Err(HttpError::BadRequest),
         }
     }
@@ -240,6 +293,33 @@ impl EndpointHandler for VmInfo {
     }
 }
 
+// /api/v1/vm.ping handler
+pub struct VmPing {}
+
+impl EndpointHandler for VmPing {
+    fn handle_request(
+        &self,
+        req: &Request,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+    ) -> Response {
+        match req.method() {
+            Method::Get => match vm_ping(api_notifier, api_sender).map_err(HttpError::ApiError) {
+                Ok(pong) => {
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    let pong_serialized = serde_json::to_string(&pong).unwrap();
+
+                    response.set_body(Body::new(pong_serialized));
+                    response
+                }
+                Err(e) => error_response(e, StatusCode::InternalServerError),
+            },
+
+            _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
+        }
+    }
+}
+
 // /api/v1/vmm.info handler
 pub struct VmmPing {}
 
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use self::http_endpoint::{VmActionHandler, VmCreate, VmInfo, VmmPing, VmmShutdown};
+use self::http_endpoint::{VmActionHandler, VmCreate, VmInfo, VmPing, VmmPing, VmmShutdown};
 use crate::api::{ApiError, ApiRequest, VmAction};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::{Error as VmmError, Result};
@@ -177,6 +177,10 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.boot"),
         Box::new(VmActionHandler::new(VmAction::Boot)),
     );
+    r.routes.insert(
+        endpoint!("/vm.config"),
+        Box::new(VmActionHandler::new(VmAction::Config)),
+    );
     r.routes.insert(
         endpoint!("/vm.counters"),
         Box::new(VmActionHandler::new(VmAction::Counters)),
@@ -187,15 +191,40 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.delete"),
         Box::new(VmActionHandler::new(VmAction::Delete)),
     );
+    r.routes.insert(
+        endpoint!("/vm.dirty-rate"),
+        Box::new(VmActionHandler::new(VmAction::DirtyRate)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.dirty-rate-start"),
+        Box::new(VmActionHandler::new(VmAction::DirtyRateStart)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.dirty-rate-stop"),
+        Box::new(VmActionHandler::new(VmAction::DirtyRateStop)),
+    );
     r.routes.insert(endpoint!("/vm.info"), Box::new(VmInfo {}));
     r.routes.insert(
         endpoint!("/vm.pause"),
         Box::new(VmActionHandler::new(VmAction::Pause)),
     );
+    r.routes.insert(endpoint!("/vm.ping"), Box::new(VmPing {}));
     r.routes.insert(
         endpoint!("/vm.power-button"),
         Box::new(VmActionHandler::new(VmAction::PowerButton)),
     );
+    r.routes.insert(
+        endpoint!("/vm.migration-abort"),
+        Box::new(VmActionHandler::new(VmAction::MigrationAbort)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.migration-status"),
+        Box::new(VmActionHandler::new(VmAction::MigrationStatus)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.nmi"),
+        Box::new(VmActionHandler::new(VmAction::Nmi(Arc::default()))),
+    );
     r.routes.insert(
         endpoint!("/vm.reboot"),
         Box::new(VmActionHandler::new(VmAction::Reboot)),
@@ -210,6 +239,14 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.remove-device"),
         Box::new(VmActionHandler::new(VmAction::RemoveDevice(Arc::default()))),
     );
+    r.routes.insert(
+        endpoint!("/vm.remove-disk"),
+        Box::new(VmActionHandler::new(VmAction::RemoveDisk(Arc::default()))),
+    );
+    r.routes.insert(
+        endpoint!("/vm.remove-net"),
+        Box::new(VmActionHandler::new(VmAction::RemoveNet(Arc::default()))),
+    );
     r.routes.insert(
         endpoint!("/vm.resize"),
         Box::new(VmActionHandler::new(VmAction::Resize(Arc::default()))),
@@ -240,6 +277,14 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.snapshot"),
         Box::new(VmActionHandler::new(VmAction::Snapshot(Arc::default()))),
     );
+    r.routes.insert(
+        endpoint!("/vm.resident-memory"),
+        Box::new(VmActionHandler::new(VmAction::ResidentMemory)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.topology"),
+        Box::new(VmActionHandler::new(VmAction::Topology)),
+    );
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
     r.routes.insert(
         endpoint!("/vm.coredump"),
@@ -44,10 +44,12 @@ use crate::config::{
 use crate::device_tree::DeviceTree;
 use crate::vm::{Error as VmError, VmState};
 use micro_http::Body;
+use net_util::MacAddr;
 use serde::{Deserialize, Serialize};
 use std::io;
-use std::sync::mpsc::{channel, RecvError, SendError, Sender};
+use std::sync::mpsc::{channel, RecvError, RecvTimeoutError, SendError, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use vm_migration::MigratableError;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -66,6 +68,9 @@ pub enum ApiError {
     /// API response receive error
     ResponseRecv(RecvError),
 
+    /// The VMM API server did not answer in time
+    ResponseTimeout(RecvTimeoutError),
+
     /// The VM could not boot.
     VmBoot(VmError),
 
@@ -132,6 +137,12 @@ pub enum ApiError {
     /// The disk could not be added to the VM.
     VmAddDisk(VmError),
 
+    /// The disk could not be removed from the VM.
+    VmRemoveDisk(VmError),
+
+    /// The network device could not be removed from the VM.
+    VmRemoveNet(VmError),
+
     /// The fs could not be added to the VM.
     VmAddFs(VmError),
 
@@ -153,17 +164,62 @@ pub enum ApiError {
     /// Error starting migration sender
     VmSendMigration(MigratableError),
 
+    /// The status of the migration being received could not be retrieved.
+    VmMigrationStatus(MigratableError),
+
+    /// The migration being received could not be aborted.
+    VmMigrationAbort(MigratableError),
+
     /// Error triggering power button
     VmPowerButton(VmError),
+
+    /// Error injecting NMI
+    VmNmi(VmError),
+
+    /// The VM dirty rate could not be estimated.
+    VmDirtyRate(VmError),
+
+    /// The VM counters could not be configured.
+    VmConfigureCounters(VmError),
+
+    /// The VM topology could not be retrieved.
+    VmTopology(VmError),
+
+    /// The VM configuration could not be retrieved.
+    VmConfig(VmError),
+
+    /// The resident memory could not be retrieved.
+    VmResidentMemory(VmError),
 }
 pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmResidentMemory {
+    /// Memory of the VMM resident on the host in bytes, hugetlb pages included
+    pub resident: u64,
+    /// Part of the resident memory made of hugetlb pages, in bytes
+    pub resident_hugetlb: u64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct NetInterfaceInfo {
+    /// Identifier of the virtio-net device
+    pub id: String,
+    /// Name of the tap interface on the host
+    pub tap: String,
+    pub mac: Option<MacAddr>,
+    pub host_mac: Option<MacAddr>,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct VmInfo {
     pub config: Arc<Mutex<VmConfig>>,
     pub state: VmState,
     pub memory_actual_size: u64,
     pub device_tree: Option<Arc<Mutex<DeviceTree>>>,
+    pub serial_number: Option<String>,
+    #[serde(default)]
+    pub net_interfaces: Vec<NetInterfaceInfo>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -173,9 +229,21 @@ pub struct VmmPingResponse {
     pub pid: i64,
 }
 
+#[derive(Clone, Deserialize, Serialize)]
+pub struct VmPingResponse {
+    pub build_version: String,
+    pub version: String,
+    /// None when no VM has been created yet
+    pub state: Option<VmState>,
+    /// Seconds elapsed since the VMM started
+    pub vmm_uptime: u64,
+    /// Seconds elapsed since the VM was booted or restored
+    pub vm_uptime: Option<u64>,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmResizeData {
-    pub desired_vcpus: Option<u8>,
+    pub desired_vcpus: Option<u32>,
     pub desired_ram: Option<u64>,
     pub desired_balloon: Option<u64>,
 }
@@ -191,10 +259,48 @@ pub struct VmRemoveDeviceData {
     pub id: String,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmNmiData {
+    /// The vCPU the NMI is sent to, or all vCPUs if not specified
+    #[serde(default)]
+    pub cpu_id: Option<u32>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct VmCountersData {
+    /// Restart all the counters from zero
+    #[serde(default = "default_vmcountersdata_reset")]
+    pub reset: bool,
+    /// Interval in seconds between two samples of the counters sent to the
+    /// event monitor, 0 to stop sampling, unchanged if not specified
+    #[serde(default)]
+    pub sampling_interval_secs: Option<u64>,
+}
+
+fn default_vmcountersdata_reset() -> bool {
+    true
+}
+
+impl Default for VmCountersData {
+    fn default() -> Self {
+        VmCountersData {
+            reset: default_vmcountersdata_reset(),
+            sampling_interval_secs: None,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmSnapshotConfig {
     /// The snapshot destination URL
     pub destination_url: String,
+    /// Only save the changes since the snapshot at this URL
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Track the changes from now on, for differential snapshots to be
+    /// taken against this one
+    #[serde(default)]
+    pub track_changes: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -207,6 +313,10 @@ pub struct VmCoredumpData {
 pub struct VmReceiveMigrationData {
     /// URL for the reception of migration state
     pub receiver_url: String,
+    /// Return as soon as the source can connect, rather than once the
+    /// migration is over, leaving vm.migration-status to report its progress
+    #[serde(default)]
+    pub background: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -218,6 +328,20 @@ pub struct VmSendMigrationData {
     pub local: bool,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmDirtyRate {
+    /// Duration of the sampling window in milliseconds
+    pub sample_duration_ms: u64,
+    /// Number of guest RAM regions sampled
+    pub sampled_regions: u32,
+    /// Amount of guest memory dirtied during the sampling window
+    pub dirty_bytes: u64,
+    /// Number of 4KiB pages dirtied per second
+    pub dirty_pages_per_sec: u64,
+    /// Dirty rate in MiB per second
+    pub dirty_rate_mib_per_sec: f64,
+}
+
 pub enum ApiResponsePayload {
     /// No data is sent on the channel.
     Empty,
@@ -228,6 +352,9 @@ pub enum ApiResponsePayload {
     /// Vmm ping response
     VmmPing(VmmPingResponse),
 
+    /// Vm ping response
+    VmPing(VmPingResponse),
+
     /// Vm action response
     VmAction(Option<Vec<u8>>),
 }
@@ -260,6 +387,9 @@ pub enum ApiRequest {
     /// Request the VMM API server status
     VmmPing(Sender<ApiResponse>),
 
+    /// Request the VMM and VM liveness status
+    VmPing(Sender<ApiResponse>),
+
     /// Pause a VM.
     VmPause(Sender<ApiResponse>),
 
@@ -269,6 +399,9 @@ pub enum ApiRequest {
     /// Get counters for a VM.
     VmCounters(Sender<ApiResponse>),
 
+    /// Reset the counters of a VM and configure their sampling.
+    VmConfigureCounters(Arc<VmCountersData>, Sender<ApiResponse>),
+
     /// Shut the previously booted virtual machine down.
     /// If the VM was not previously booted or created, the VMM API server
     /// will send a VmShutdown error back.
@@ -302,6 +435,12 @@ pub enum ApiRequest {
     /// Add a disk to the VM.
     VmAddDisk(Arc<DiskConfig>, Sender<ApiResponse>),
 
+    /// Remove a disk from the VM.
+    VmRemoveDisk(Arc<VmRemoveDeviceData>, Sender<ApiResponse>),
+
+    /// Remove a network device from the VM.
+    VmRemoveNet(Arc<VmRemoveDeviceData>, Sender<ApiResponse>),
+
     /// Add a fs to the VM.
     VmAddFs(Arc<FsConfig>, Sender<ApiResponse>),
 
@@ -333,8 +472,35 @@ pub enum ApiRequest {
     /// Outgoing migration
     VmSendMigration(Arc<VmSendMigrationData>, Sender<ApiResponse>),
 
+    /// Get the progress of the incoming migration
+    VmMigrationStatus(Sender<ApiResponse>),
+
+    /// Abort the incoming migration
+    VmMigrationAbort(Sender<ApiResponse>),
+
     // Trigger power button
     VmPowerButton(Sender<ApiResponse>),
+
+    /// Inject an NMI into the VM
+    VmNmi(Arc<VmNmiData>, Sender<ApiResponse>),
+
+    /// Start sampling the guest memory dirty rate
+    VmDirtyRateStart(Sender<ApiResponse>),
+
+    /// Stop sampling the guest memory dirty rate
+    VmDirtyRateStop(Sender<ApiResponse>),
+
+    /// Get the guest memory dirty rate since the sampling started
+    VmDirtyRate(Sender<ApiResponse>),
+
+    /// Get the resolved device, memory and vCPU topology of the VM
+    VmTopology(Sender<ApiResponse>),
+
+    /// Get the memory of the VMM resident on the host
+    VmResidentMemory(Sender<ApiResponse>),
+
+    /// Get the configuration of the VM
+    VmConfig(Sender<ApiResponse>),
 }
 
 pub fn vm_create(
@@ -380,6 +546,9 @@ pub enum VmAction {
     /// Return VM counters
     Counters,
 
+    /// Reset VM counters and configure their sampling
+    ConfigureCounters(Arc<VmCountersData>),
+
     /// Add VFIO device
     AddDevice(Arc<DeviceConfig>),
 
@@ -407,6 +576,12 @@ pub enum VmAction {
     /// Remove VFIO device
     RemoveDevice(Arc<VmRemoveDeviceData>),
 
+    /// Remove disk
+    RemoveDisk(Arc<VmRemoveDeviceData>),
+
+    /// Remove network device
+    RemoveNet(Arc<VmRemoveDeviceData>),
+
     /// Resize VM
     Resize(Arc<VmResizeData>),
 
@@ -429,8 +604,35 @@ pub enum VmAction {
     /// Outgoing migration
     SendMigration(Arc<VmSendMigrationData>),
 
+    /// Return the incoming migration progress
+    MigrationStatus,
+
+    /// Abort the incoming migration
+    MigrationAbort,
+
     /// Power Button for clean shutdown
     PowerButton,
+
+    /// Inject an NMI
+    Nmi(Arc<VmNmiData>),
+
+    /// Start sampling the dirty rate
+    DirtyRateStart,
+
+    /// Stop sampling the dirty rate
+    DirtyRateStop,
+
+    /// Return the dirty rate
+    DirtyRate,
+
+    /// Return the VM topology
+    Topology,
+
+    /// Return the resident memory
+    ResidentMemory,
+
+    /// Return the VM configuration
+    Config,
 }
 
 fn vm_action(
@@ -449,6 +651,7 @@ fn vm_action(
         Pause => ApiRequest::VmPause(response_sender),
         Resume => ApiRequest::VmResume(response_sender),
         Counters => ApiRequest::VmCounters(response_sender),
+        ConfigureCounters(v) => ApiRequest::VmConfigureCounters(v, response_sender),
         AddDevice(v) => ApiRequest::VmAddDevice(v, response_sender),
         AddDisk(v) => ApiRequest::VmAddDisk(v, response_sender),
         AddFs(v) => ApiRequest::VmAddFs(v, response_sender),
@@ -458,6 +661,8 @@ fn vm_action(
         AddVsock(v) => ApiRequest::VmAddVsock(v, response_sender),
         AddUserDevice(v) => ApiRequest::VmAddUserDevice(v, response_sender),
         RemoveDevice(v) => ApiRequest::VmRemoveDevice(v, response_sender),
+        RemoveDisk(v) => ApiRequest::VmRemoveDisk(v, response_sender),
+        RemoveNet(v) => ApiRequest::VmRemoveNet(v, response_sender),
         Resize(v) => ApiRequest::VmResize(v, response_sender),
         ResizeZone(v) => ApiRequest::VmResizeZone(v, response_sender),
         Restore(v) => ApiRequest::VmRestore(v, response_sender),
@@ -466,7 +671,16 @@ fn vm_action(
         Coredump(v) => ApiRequest::VmCoredump(v, response_sender),
         ReceiveMigration(v) => ApiRequest::VmReceiveMigration(v, response_sender),
         SendMigration(v) => ApiRequest::VmSendMigration(v, response_sender),
+        MigrationStatus => ApiRequest::VmMigrationStatus(response_sender),
+        MigrationAbort => ApiRequest::VmMigrationAbort(response_sender),
         PowerButton => ApiRequest::VmPowerButton(response_sender),
+        Nmi(v) => ApiRequest::VmNmi(v, response_sender),
+        DirtyRateStart => ApiRequest::VmDirtyRateStart(response_sender),
+        DirtyRateStop => ApiRequest::VmDirtyRateStop(response_sender),
+        DirtyRate => ApiRequest::VmDirtyRate(response_sender),
+        Topology => ApiRequest::VmTopology(response_sender),
+        ResidentMemory => ApiRequest::VmResidentMemory(response_sender),
+        Config => ApiRequest::VmConfig(response_sender),
     };
 
     // Send the VM request.
@@ -510,6 +724,14 @@ pub fn vm_counters(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResul
     vm_action(api_evt, api_sender, VmAction::Counters)
 }
 
+pub fn vm_configure_counters(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmCountersData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::ConfigureCounters(data))
+}
+
 pub fn vm_power_button(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -517,6 +739,47 @@ pub fn vm_power_button(
     vm_action(api_evt, api_sender, VmAction::PowerButton)
 }
 
+pub fn vm_nmi(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmNmiData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Nmi(data))
+}
+
+pub fn vm_dirty_rate_start(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::DirtyRateStart)
+}
+
+pub fn vm_dirty_rate_stop(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::DirtyRateStop)
+}
+
+pub fn vm_dirty_rate(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::DirtyRate)
+}
+
+pub fn vm_topology(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Topology)
+}
+
+pub fn vm_resident_memory(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::ResidentMemory)
+}
+
+pub fn vm_config(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Config)
+}
+
 pub fn vm_receive_migration(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -525,6 +788,20 @@ pub fn vm_receive_migration(
     vm_action(api_evt, api_sender, VmAction::ReceiveMigration(data))
 }
 
+pub fn vm_migration_status(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::MigrationStatus)
+}
+
+pub fn vm_migration_abort(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::MigrationAbort)
+}
+
 pub fn vm_send_migration(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -591,6 +868,29 @@ pub fn vmm_ping(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<V
     }
 }
 
+/// How long a liveness check waits for the VMM before reporting it as hung.
+const VM_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn vm_ping(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<VmPingResponse> {
+    let (response_sender, response_receiver) = channel();
+
+    api_sender
+        .send(ApiRequest::VmPing(response_sender))
+        .map_err(ApiError::RequestSend)?;
+    api_evt.write(1).map_err(ApiError::EventFdWrite)?;
+
+    // Unlike other requests, don't wait forever so that a wedged VMM
+    // control loop is reported rather than blocking the caller.
+    let vm_pong = response_receiver
+        .recv_timeout(VM_PING_TIMEOUT)
+        .map_err(ApiError::ResponseTimeout)??;
+
+    match vm_pong {
+        ApiResponsePayload::VmPing(pong) => Ok(pong),
+        _ => Err(ApiError::ResponsePayloadType),
+    }
+}
+
 pub fn vmm_shutdown(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<()> {
     let (response_sender, response_receiver) = channel();
 
@@ -653,6 +953,22 @@ pub fn vm_add_disk(
     vm_action(api_evt, api_sender, VmAction::AddDisk(data))
 }
 
+pub fn vm_remove_disk(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmRemoveDeviceData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::RemoveDisk(data))
+}
+
+pub fn vm_remove_net(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmRemoveDeviceData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::RemoveNet(data))
+}
+
 pub fn vm_add_fs(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -4,6 +4,8 @@
 //
 
 pub use crate::vm_config::*;
+#[cfg(target_arch = "x86_64")]
+use arch::KvmPvFeature;
 use option_parser::{
     ByteSized, IntegerList, OptionParser, OptionParserError, StringList, Toggle, Tuple,
 };
@@ -11,13 +13,21 @@ use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
 use std::convert::From;
 use std::fmt;
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::str::FromStr;
 use thiserror::Error;
 use virtio_devices::{RateLimiterConfig, TokenBucketConfig};
 
 const MAX_NUM_PCI_SEGMENTS: u16 = 96;
+// Narrowest IOMMU input range still holding a 4 KiB page.
+const MIN_IOMMU_ADDRESS_WIDTH_BITS: u8 = 12;
+// Longest label exposed to the guest for a network device.
+const MAX_NET_HOST_NAME_LEN: usize = 64;
+// Smallest virtio-scsi queue holding a request header, a response and a data
+// segment.
+const MIN_SCSI_QUEUE_SIZE: u16 = 4;
 
 /// Errors associated with VM configuration parameters.
 #[derive(Debug, Error)]
@@ -50,6 +60,8 @@ pub enum Error {
     ParseNetwork(OptionParserError),
     /// Error parsing RNG options
     ParseRng(OptionParserError),
+    /// Both src and source were specified for the RNG
+    ParseRngConflictingSources,
     /// Error parsing balloon options
     ParseBalloon(OptionParserError),
     /// Error parsing filesystem parameters
@@ -97,10 +109,24 @@ pub enum Error {
     ParseVdpa(OptionParserError),
     /// Missing path for vDPA device
     ParseVdpaPathMissing,
+    /// Failed parsing virtio-scsi device
+    ParseScsi(OptionParserError),
+    /// Missing disks for virtio-scsi device
+    ParseScsiDisksMissing,
+    /// Failed parsing rate limit group parameters
+    ParseRateLimitGroup(OptionParserError),
+    /// Missing 'id' from rate limit group
+    ParseRateLimitGroupIdMissing,
     /// Failed parsing TPM device
     ParseTpm(OptionParserError),
     /// Missing path for TPM device
     ParseTpmPathMissing,
+    /// Failed reading the kernel command line from a file
+    ReadCmdlineFile(PathBuf, std::io::Error),
+    /// Failed reading a VM configuration file
+    ReadConfigFile(PathBuf, std::io::Error),
+    /// Malformed VM configuration file
+    ParseConfigFile(PathBuf, ConfigError),
 }
 
 #[derive(Debug, PartialEq, Eq, Error)]
@@ -111,10 +137,22 @@ pub enum ValidationError {
     KernelMissing,
     /// Missing file value for console
     ConsoleFileMissing,
+    /// Input from a file requires the file or tty mode
+    ConsoleInputInvalidMode,
+    /// Output rotation requires the file mode
+    ConsoleRotateInvalidMode,
+    /// Output timestamps require the file mode
+    ConsoleTimestampsInvalidMode,
+    /// Hardware flow control is only supported by the x86_64 serial port
+    FlowControlUnsupported,
+    /// Terminal resizing is only supported by the virtio console
+    SerialResizeUnsupported,
     /// Max is less than boot
     CpusMaxLowerThanBoot,
     /// Both socket and path specified
     DiskSocketAndPath,
+    /// Both URL and path or socket specified
+    DiskUrlAndPath,
     /// Using vhost user requires shared memory
     VhostUserRequiresSharedMemory,
     /// No socket provided for vhost_use
@@ -130,6 +168,26 @@ pub enum ValidationError {
     #[cfg(target_arch = "aarch64")]
     /// Dies per package must be 1
     CpuTopologyDiesPerPackage,
+    /// TSC frequency can't be expressed in kHz
+    InvalidTscFrequency(u64),
+    #[cfg(target_arch = "aarch64")]
+    /// Setting the TSC frequency is only supported on x86_64
+    TscFrequencyUnsupported,
+    #[cfg(target_arch = "x86_64")]
+    /// Stable CPU identity can't be encoded in CPUID
+    InvalidCpuStableId(&'static str),
+    #[cfg(target_arch = "aarch64")]
+    /// Stable CPU identity is only supported on x86_64
+    CpuStableIdUnsupported,
+    #[cfg(target_arch = "x86_64")]
+    /// Hiding the hypervisor CPUID leaves conflicts with a feature relying on them
+    HiddenHypervisorCpuid(&'static str),
+    #[cfg(target_arch = "x86_64")]
+    /// More than 255 vCPUs need the KVM CPUID leaves hidden by kvm_hyperv
+    KvmHypervTooManyVcpus,
+    #[cfg(target_arch = "aarch64")]
+    /// Hiding the hypervisor CPUID leaves is only supported on x86_64
+    HiddenHypervisorCpuidUnsupported,
     /// Virtio needs a min of 2 queues
     VnetQueueLowerThan2,
     /// The input queue number for virtio_net must match the number of input fds
@@ -138,16 +196,30 @@ pub enum ValidationError {
     VnetReservedFd,
     /// Hardware checksum offload is disabled.
     NoHardwareChecksumOffload,
+    /// Filtered mode is only supported by TAP backed virtio-net devices
+    PromiscNotSupported,
     /// Hugepages not turned on
     HugePageSizeWithoutHugePages,
     /// Huge page size is not power of 2
     InvalidHugePageSize(u64),
+    /// Number of threads used to prefault memory can't be 0
+    InvalidPreallocThreads,
+    /// Read-only ROM address is not page aligned
+    InvalidReadonlyRomAddress(u64),
+    /// NUMA binding of the guest memory doesn't apply to memory zones
+    NumaBindWithMemoryZones,
     /// CPU Hotplug is not permitted with TDX
     #[cfg(feature = "tdx")]
     TdxNoCpuHotplug,
     /// Missing firmware for TDX
     #[cfg(feature = "tdx")]
     TdxFirmwareMissing,
+    /// Missing firmware for SEV-ES
+    #[cfg(feature = "sev")]
+    SevEsFirmwareMissing,
+    /// Feature not supported with SEV-ES
+    #[cfg(feature = "sev")]
+    SevEsUnsupported(&'static str),
     /// Insufficient vCPUs for queues
     TooManyQueues,
     /// Need shared memory for vfio-user
@@ -158,6 +230,8 @@ pub enum ValidationError {
     InvalidNumPciSegments(u16),
     /// Invalid PCI segment id
     InvalidPciSegment(u16),
+    /// Invalid IOMMU address width
+    InvalidIommuAddressWidthBits(u8),
     /// Balloon too big
     BalloonLargerThanRam(u64, u64),
     /// On a IOMMU segment but not behind IOMMU
@@ -174,6 +248,140 @@ pub enum ValidationError {
     DuplicateDevicePath(String),
     /// Provided MTU is lower than what the VIRTIO specification expects
     InvalidMtu(u16),
+    /// Tap IP address and netmask are not of the same family
+    NetIpMaskMismatch,
+    /// Tap netmask is not made of contiguous leading ones
+    InvalidNetmask(IpAddr),
+    /// Discarding writes requires a private persistent memory mapping
+    PmemDiscardWritesRequiresPrivate,
+    /// Queue size must be a power of two no larger than the VIRTIO maximum
+    InvalidQueueSize(u16),
+    /// Per direction queue sizes are not supported by vhost-user devices
+    AsymmetricQueueSizeVhostUser,
+    /// A vDPA backed network device cannot use a TAP, fds or vhost-user backend
+    VdpaNetConflict,
+    /// Per direction queue sizes are not supported by vDPA devices
+    AsymmetricQueueSizeVdpa,
+    /// Feature not available with a vDPA backed network device
+    VdpaNetUnsupported(&'static str),
+    /// Invalid bridge to enslave the tap interface to
+    InvalidNetBridge(String),
+    /// Bridging the tap interface is not supported by vhost-user devices
+    NetBridgeVhostUser,
+    /// Invalid network device label
+    InvalidNetHostName(String),
+    /// Rate limit group defined more than once
+    DuplicateRateLimitGroup(String),
+    /// Rate limit group without any limit
+    RateLimitGroupWithoutLimits(String),
+    /// Network device referencing an undefined rate limit group
+    UnknownRateLimitGroup(String),
+    /// A network device in a rate limit group can't have its own rate limits
+    RateLimitGroupAndRateLimiter,
+    /// Rate limit groups are not supported by vhost-user and vDPA devices
+    RateLimitGroupUnsupported,
+    /// The kernel vhost-net datapath needs a TAP backend
+    VhostNetConflict,
+    /// Feature not available with the kernel vhost-net datapath
+    VhostNetUnsupported(&'static str),
+    /// Invalid World Wide Name
+    InvalidWwn(String),
+    /// Both serial and WWN specified for a disk
+    DiskSerialAndWwn,
+    /// Maximum number of segments per request doesn't fit in the queue
+    InvalidSegmentMax(u32, u16),
+    /// Maximum size of a segment can't be 0
+    InvalidSizeMax,
+    /// Segment limits are not supported by vhost-user devices
+    SegmentLimitsVhostUser,
+    /// Probing the IO alignment is not supported by vhost-user devices
+    ProbeAlignmentVhostUser,
+    /// Split read and write rate limits are not supported by vhost-user devices
+    SplitRateLimitsVhostUser,
+    /// Maximum number of in-flight IO operations can't be 0
+    InvalidAioMaxInflight,
+    /// In-flight IO limit is not supported by vhost-user devices
+    AioMaxInflightVhostUser,
+    /// CD-ROM media must be read-only
+    CdromNotReadonly,
+    /// CD-ROM media is not supported by vhost-user devices
+    CdromVhostUser,
+    /// Disks built from a directory must be read-only
+    DiskDirectoryNotReadonly,
+    /// Disks built from a directory are not supported by vhost-user devices
+    DiskDirectoryVhostUser,
+    /// Direct IO bypasses the host page cache
+    DiskDirectCache,
+    /// Host caching is managed by the vhost-user backend
+    DiskCacheVhostUser,
+    /// Option only meaningful for writable disks
+    ReadonlyDiskWriteOption(&'static str),
+    /// Overlays are not supported by vhost-user devices
+    OverlayVhostUser,
+    /// Overlays are only supported on writable disk images
+    OverlayUnsupportedDisk,
+    /// Overlays are accessed through the host page cache
+    OverlayDirect,
+    /// Discarding the overlay requires one
+    OverlayDiscardWithoutOverlay,
+    /// Remote disk images can only be fetched over HTTP, or HTTPS with the
+    /// https feature
+    InvalidDiskUrl(String),
+    /// Remote disk images must be read-only
+    DiskUrlNotReadonly,
+    /// Option not supported by remote disk images
+    DiskUrlUnsupportedOption(&'static str),
+    /// The block cache is only used by remote disk images
+    DiskCacheSizeWithoutUrl,
+    /// Queue polling window can't be 0
+    InvalidPollQueueDuration,
+    /// Queue polling window requires queue polling
+    PollQueueDurationWithoutPollQueue,
+    /// Queue polling is not supported by vhost-user devices
+    PollQueueVhostUser,
+    /// Background sync is not supported by vhost-user devices
+    SyncIntervalVhostUser,
+    /// Request timeout is not supported by vhost-user devices
+    RequestTimeoutVhostUser,
+    /// Request timeout requires a disk backend able to cancel requests
+    RequestTimeoutUnsupported(&'static str),
+    /// Zoned devices are only emulated on top of writable disk images
+    ZonedUnsupportedDisk,
+    /// Zone size must be a power of two between 4 KiB and 1 TiB
+    InvalidZoneSize(u64),
+    /// Zone layout requires a zoned device
+    ZoneLayoutWithoutZoned,
+    /// A virtio-scsi controller must have between 1 and 256 disks
+    InvalidScsiDiskCount(usize),
+    /// A virtio-scsi queue size must be a power of two leaving room for data segments
+    InvalidScsiQueueSize(u16),
+    /// Watchdog coredump requested without the watchdog device
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    WatchdogCoredumpWithoutWatchdog,
+    /// Dumping the guest on host memory pressure requires guest debug support
+    DumpOnOomUnsupported,
+    /// Memory backend given along with a backing file
+    MemoryBackendWithFile,
+    /// Memory backing file given along with hotpluggable memory
+    MemoryFileWithHotplug,
+    /// Memory backing file given along with memory zones
+    MemoryFileWithZones,
+    /// NUMA binding of memory backed by a shared file
+    MemoryFileSharedWithNumaBind,
+    /// Guest RAM at boot above the max_ram limit
+    MemoryAboveMaxRam(u64, u64),
+    /// Feature not available when ACPI is disabled
+    AcpiDisabledUnsupported(&'static str),
+    /// Feature not available with the virtio-mmio transport
+    MmioTransportUnsupported(&'static str),
+    /// The state of the VM can't be saved for a snapshot or a migration
+    NotMigratable(&'static str),
+    /// A virtio-fs device needs either a socket path or a connected fd
+    FsSocketMissing,
+    /// A virtio-fs device cannot use both a socket path and a connected fd
+    FsSocketAndFd,
+    /// The virtio-fs connected fd uses a reserved fd number
+    FsReservedFd,
 }
 
 type ValidationResult<T> = std::result::Result<T, ValidationError>;
@@ -185,8 +393,33 @@ impl fmt::Display for ValidationError {
             DoubleTtyMode => write!(f, "Console mode tty specified for both serial and console"),
             KernelMissing => write!(f, "No kernel specified"),
             ConsoleFileMissing => write!(f, "Path missing when using file console mode"),
+            ConsoleInputInvalidMode => {
+                write!(f, "Console input is only supported with file or tty mode")
+            }
+            ConsoleRotateInvalidMode => {
+                write!(
+                    f,
+                    "Console output rotation is only supported with file mode"
+                )
+            }
+            ConsoleTimestampsInvalidMode => {
+                write!(
+                    f,
+                    "Console output timestamps are only supported with file mode"
+                )
+            }
+            FlowControlUnsupported => {
+                write!(
+                    f,
+                    "Flow control is only supported by the serial port on x86_64"
+                )
+            }
+            SerialResizeUnsupported => {
+                write!(f, "\"resize\" is only supported by the virtio console")
+            }
             CpusMaxLowerThanBoot => write!(f, "Max CPUs lower than boot CPUs"),
             DiskSocketAndPath => write!(f, "Disk path and vhost socket both provided"),
+            DiskUrlAndPath => write!(f, "Disk URL and path or vhost socket both provided"),
             VhostUserRequiresSharedMemory => {
                 write!(
                     f,
@@ -203,6 +436,39 @@ impl fmt::Display for ValidationError {
             ),
             #[cfg(target_arch = "aarch64")]
             CpuTopologyDiesPerPackage => write!(f, "Dies per package must be 1"),
+            InvalidTscFrequency(freq) => {
+                write!(f, "TSC frequency {freq} Hz is out of range")
+            }
+            #[cfg(target_arch = "aarch64")]
+            TscFrequencyUnsupported => {
+                write!(f, "Setting the TSC frequency is only supported on x86_64")
+            }
+            #[cfg(target_arch = "x86_64")]
+            InvalidCpuStableId(reason) => {
+                write!(f, "Invalid stable CPU identity: {reason}")
+            }
+            #[cfg(target_arch = "aarch64")]
+            CpuStableIdUnsupported => {
+                write!(f, "Stable CPU identity is only supported on x86_64")
+            }
+            #[cfg(target_arch = "x86_64")]
+            HiddenHypervisorCpuid(feature) => {
+                write!(
+                    f,
+                    "Hiding the hypervisor CPUID leaves is incompatible with {feature}"
+                )
+            }
+            #[cfg(target_arch = "x86_64")]
+            KvmHypervTooManyVcpus => {
+                write!(f, "kvm_hyperv is incompatible with more than 255 vCPUs")
+            }
+            #[cfg(target_arch = "aarch64")]
+            HiddenHypervisorCpuidUnsupported => {
+                write!(
+                    f,
+                    "Hiding the hypervisor CPUID leaves is only supported on x86_64"
+                )
+            }
             VnetQueueLowerThan2 => write!(f, "Number of queues to virtio_net less than 2"),
             VnetQueueFdMismatch => write!(
                 f,
@@ -213,12 +479,28 @@ impl fmt::Display for ValidationError {
                 f,
                 "\"offload_tso\" and \"offload_ufo\" depend on \"offload_tso\""
             ),
+            PromiscNotSupported => write!(
+                f,
+                "\"promisc=off\" is not supported with vhost-user or vDPA"
+            ),
             HugePageSizeWithoutHugePages => {
                 write!(f, "Huge page size specified but huge pages not enabled")
             }
             InvalidHugePageSize(s) => {
                 write!(f, "Huge page size is not power of 2: {s}")
             }
+            InvalidPreallocThreads => {
+                write!(f, "Number of threads used to prefault memory can't be 0")
+            }
+            InvalidReadonlyRomAddress(address) => {
+                write!(f, "Read-only ROM address {address:#x} is not page aligned")
+            }
+            NumaBindWithMemoryZones => {
+                write!(
+                    f,
+                    "\"numa_bind\" can't be used with memory zones, use \"host_numa_node\" instead"
+                )
+            }
             #[cfg(feature = "tdx")]
             TdxNoCpuHotplug => {
                 write!(f, "CPU hotplug is not permitted with TDX")
@@ -227,6 +509,14 @@ impl fmt::Display for ValidationError {
             TdxFirmwareMissing => {
                 write!(f, "No TDX firmware specified")
             }
+            #[cfg(feature = "sev")]
+            SevEsFirmwareMissing => {
+                write!(f, "No SEV-ES firmware specified")
+            }
+            #[cfg(feature = "sev")]
+            SevEsUnsupported(feature) => {
+                write!(f, "Using {feature} is not possible with SEV-ES")
+            }
             TooManyQueues => {
                 write!(f, "Number of vCPUs is insufficient for number of queues")
             }
@@ -251,6 +541,12 @@ impl fmt::Display for ValidationError {
             InvalidPciSegment(pci_segment) => {
                 write!(f, "Invalid PCI segment id: {pci_segment}")
             }
+            InvalidIommuAddressWidthBits(bits) => {
+                write!(
+                    f,
+                    "IOMMU address width ({bits} bits) not in range of {MIN_IOMMU_ADDRESS_WIDTH_BITS} to {DEFAULT_IOMMU_ADDRESS_WIDTH_BITS}"
+                )
+            }
             BalloonLargerThanRam(balloon_size, ram_size) => {
                 write!(
                     f,
@@ -285,6 +581,317 @@ impl fmt::Display for ValidationError {
                     "Provided MTU {mtu} is lower than 1280 (expected by VIRTIO specification)"
                 )
             }
+            NetIpMaskMismatch => {
+                write!(f, "Tap IP address and netmask must both be IPv4 or IPv6")
+            }
+            InvalidNetmask(mask) => write!(f, "Invalid tap netmask: {mask}"),
+            PmemDiscardWritesRequiresPrivate => {
+                write!(
+                    f,
+                    "Persistent memory with \"discard_writes\" enabled requires \"mem_policy=private\""
+                )
+            }
+            &InvalidQueueSize(size) => {
+                write!(
+                    f,
+                    "Queue size {size} is not a power of two between 1 and {}",
+                    virtio_devices::net::MAX_QUEUE_SIZE
+                )
+            }
+            AsymmetricQueueSizeVhostUser => {
+                write!(
+                    f,
+                    "\"rx_queue_size\" and \"tx_queue_size\" are not supported with vhost-user"
+                )
+            }
+            VdpaNetConflict => {
+                write!(
+                    f,
+                    "\"vdpa\" is mutually exclusive with \"tap\", \"fd\" and \"vhost_user\""
+                )
+            }
+            AsymmetricQueueSizeVdpa => {
+                write!(
+                    f,
+                    "\"rx_queue_size\" and \"tx_queue_size\" are not supported with vDPA"
+                )
+            }
+            VdpaNetUnsupported(feature) => {
+                write!(f, "Using {feature} is not supported with \"vdpa\"")
+            }
+            InvalidNetBridge(tap) => {
+                write!(
+                    f,
+                    "Invalid \"tap={tap}\", expecting \"@bridge:<name>\" with a name of at most 15 characters"
+                )
+            }
+            NetBridgeVhostUser => {
+                write!(f, "\"tap=@bridge:<name>\" is not supported with vhost-user")
+            }
+            InvalidNetHostName(name) => {
+                write!(
+                    f,
+                    "Invalid \"host_name={name}\", expecting at most {MAX_NET_HOST_NAME_LEN} printable ASCII characters"
+                )
+            }
+            InvalidWwn(wwn) => {
+                write!(
+                    f,
+                    "Invalid WWN {wwn}, expecting a 64-bit NAA identifier (16 hexadecimal digits)"
+                )
+            }
+            DiskSerialAndWwn => {
+                write!(f, "Disk serial and WWN are mutually exclusive")
+            }
+            InvalidSegmentMax(segment_max, queue_size) => {
+                write!(
+                    f,
+                    "\"segment_max\" {segment_max} must be between 1 and the queue size ({queue_size}) minus 2"
+                )
+            }
+            InvalidSizeMax => {
+                write!(f, "\"size_max\" must be greater than 0")
+            }
+            SegmentLimitsVhostUser => {
+                write!(
+                    f,
+                    "\"segment_max\" and \"size_max\" are not supported with vhost-user"
+                )
+            }
+            ProbeAlignmentVhostUser => {
+                write!(f, "\"probe_alignment\" is not supported with vhost-user")
+            }
+            SplitRateLimitsVhostUser => {
+                write!(
+                    f,
+                    "Split read and write rate limits are not supported with vhost-user"
+                )
+            }
+            InvalidAioMaxInflight => {
+                write!(f, "\"aio_max_inflight\" must be greater than 0")
+            }
+            AioMaxInflightVhostUser => {
+                write!(f, "\"aio_max_inflight\" is not supported with vhost-user")
+            }
+            CdromNotReadonly => {
+                write!(f, "\"media=cdrom\" requires \"readonly=on\"")
+            }
+            CdromVhostUser => {
+                write!(f, "\"media=cdrom\" is not supported with vhost-user")
+            }
+            DiskDirectoryNotReadonly => {
+                write!(f, "A disk built from a directory requires \"readonly=on\"")
+            }
+            DiskDirectoryVhostUser => {
+                write!(
+                    f,
+                    "A disk built from a directory is not supported with vhost-user"
+                )
+            }
+            DiskDirectCache => {
+                write!(
+                    f,
+                    "\"direct=on\" implies \"cache=none\" and can't be used with another cache mode"
+                )
+            }
+            DiskCacheVhostUser => {
+                write!(
+                    f,
+                    "\"direct\" and \"cache\" are not supported with vhost-user"
+                )
+            }
+            ReadonlyDiskWriteOption(option) => {
+                write!(f, "\"{option}\" is not supported on read-only disks")
+            }
+            OverlayVhostUser => {
+                write!(f, "\"overlay\" is not supported with vhost-user")
+            }
+            OverlayUnsupportedDisk => {
+                write!(
+                    f,
+                    "\"overlay\" is not supported on read-only disks, CD-ROMs or directories"
+                )
+            }
+            OverlayDirect => {
+                write!(
+                    f,
+                    "\"overlay\" is not supported with \"direct=on\" or \"cache=none\""
+                )
+            }
+            OverlayDiscardWithoutOverlay => {
+                write!(f, "\"overlay_discard\" requires \"overlay\"")
+            }
+            InvalidDiskUrl(url) => {
+                if cfg!(feature = "https") {
+                    write!(
+                        f,
+                        "Invalid disk URL {url}, only \"http://\" and \"https://\" URLs are supported"
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Invalid disk URL {url}, only \"http://\" URLs are supported without the \"https\" feature"
+                    )
+                }
+            }
+            DiskUrlNotReadonly => {
+                write!(f, "\"url\" requires \"readonly=on\"")
+            }
+            DiskUrlUnsupportedOption(option) => {
+                write!(f, "\"{option}\" is not supported with \"url\"")
+            }
+            DiskCacheSizeWithoutUrl => {
+                write!(f, "\"cache_mb\" requires \"url\"")
+            }
+            InvalidPollQueueDuration => {
+                write!(f, "\"poll_queue_us\" must be greater than 0")
+            }
+            PollQueueDurationWithoutPollQueue => {
+                write!(f, "\"poll_queue_us\" requires \"poll_queue=on\"")
+            }
+            PollQueueVhostUser => {
+                write!(f, "\"poll_queue\" is not supported with vhost-user")
+            }
+            SyncIntervalVhostUser => {
+                write!(
+                    f,
+                    "\"sync_on_flush_interval\" is not supported with vhost-user"
+                )
+            }
+            RequestTimeoutVhostUser => {
+                write!(f, "\"request_timeout\" is not supported with vhost-user")
+            }
+            RequestTimeoutUnsupported(o) => {
+                write!(
+                    f,
+                    "\"request_timeout\" is not supported with \"{o}\", the synchronous backend can't cancel requests"
+                )
+            }
+            ZonedUnsupportedDisk => {
+                write!(
+                    f,
+                    "\"zoned\" is not supported with vhost-user, read-only disks or CD-ROMs"
+                )
+            }
+            InvalidZoneSize(zone_size) => {
+                write!(
+                    f,
+                    "\"zone_size\" must be a power of two between 4 KiB and 1 TiB: {zone_size}"
+                )
+            }
+            ZoneLayoutWithoutZoned => {
+                write!(
+                    f,
+                    "\"zone_size\" and \"conventional_zones\" require \"zoned=on\""
+                )
+            }
+            InvalidScsiDiskCount(count) => {
+                write!(
+                    f,
+                    "A virtio-scsi controller must have between 1 and {} disks, got {count}",
+                    virtio_devices::SCSI_MAX_LUNS
+                )
+            }
+            InvalidScsiQueueSize(size) => {
+                write!(
+                    f,
+                    "Queue size {size} is not a power of two between {} and {}",
+                    MIN_SCSI_QUEUE_SIZE,
+                    virtio_devices::net::MAX_QUEUE_SIZE
+                )
+            }
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            WatchdogCoredumpWithoutWatchdog => {
+                write!(f, "Watchdog coredump requires the watchdog to be enabled")
+            }
+            DumpOnOomUnsupported => {
+                write!(
+                    f,
+                    "\"dump_on_oom\" requires the \"guest_debug\" feature on x86_64"
+                )
+            }
+            MemoryBackendWithFile => {
+                write!(
+                    f,
+                    "The memory backend can't be combined with a backing file"
+                )
+            }
+            MemoryFileWithHotplug => {
+                write!(
+                    f,
+                    "The memory backing file can't be combined with \"hotplug_size\""
+                )
+            }
+            MemoryFileWithZones => {
+                write!(
+                    f,
+                    "Memory zones must be given their own backing file rather than a global one"
+                )
+            }
+            MemoryFileSharedWithNumaBind => {
+                write!(
+                    f,
+                    "\"numa_bind\" can't be used with memory backed by a shared file"
+                )
+            }
+            MemoryAboveMaxRam(size, max_ram) => {
+                write!(
+                    f,
+                    "The guest RAM at boot ({size} bytes) is above \"max_ram\" ({max_ram} bytes)"
+                )
+            }
+            AcpiDisabledUnsupported(feature) => {
+                write!(f, "Using {feature} requires ACPI to be enabled")
+            }
+            MmioTransportUnsupported(feature) => {
+                write!(f, "Using {feature} requires the virtio-pci transport")
+            }
+            NotMigratable(feature) => {
+                write!(f, "Snapshot and migration are not supported with {feature}")
+            }
+            FsSocketMissing => {
+                write!(f, "Filesystem requires either a socket path or a socket fd")
+            }
+            FsSocketAndFd => {
+                write!(
+                    f,
+                    "Filesystem socket path and socket fd are mutually exclusive"
+                )
+            }
+            FsReservedFd => write!(f, "Filesystem socket fd is a reserved fd number (<= 2)"),
+            DuplicateRateLimitGroup(s) => {
+                write!(f, "Rate limit group \"{s}\" is defined more than once")
+            }
+            RateLimitGroupWithoutLimits(s) => {
+                write!(
+                    f,
+                    "Rate limit group \"{s}\" must limit the bandwidth or the operations"
+                )
+            }
+            UnknownRateLimitGroup(s) => {
+                write!(f, "Network device uses undefined rate limit group \"{s}\"")
+            }
+            RateLimitGroupAndRateLimiter => {
+                write!(
+                    f,
+                    "Network device can't combine a rate limit group with its own rate limits"
+                )
+            }
+            RateLimitGroupUnsupported => {
+                write!(
+                    f,
+                    "Rate limit groups are not supported by vhost-user and vDPA devices"
+                )
+            }
+            VhostNetConflict => {
+                write!(
+                    f,
+                    "\"vhost=on\" is mutually exclusive with \"vhost_user\" and \"vdpa\""
+                )
+            }
+            VhostNetUnsupported(feature) => {
+                write!(f, "Using {feature} is not supported with \"vhost=on\"")
+            }
         }
     }
 }
@@ -302,7 +909,7 @@ impl fmt::Display for Error {
             ParseDevice(o) => write!(f, "Error parsing --device: {o}"),
             ParseDevicePathMissing => write!(f, "Error parsing --device: path missing"),
             ParseFileSystem(o) => write!(f, "Error parsing --fs: {o}"),
-            ParseFsSockMissing => write!(f, "Error parsing --fs: socket missing"),
+            ParseFsSockMissing => write!(f, "Error parsing --fs: socket or fd missing"),
             ParseFsTagMissing => write!(f, "Error parsing --fs: tag missing"),
             ParsePersistentMemory(o) => write!(f, "Error parsing --pmem: {o}"),
             ParsePmemFileMissing => write!(f, "Error parsing --pmem: file missing"),
@@ -315,6 +922,12 @@ impl fmt::Display for Error {
             ParseNetwork(o) => write!(f, "Error parsing --net: {o}"),
             ParseDisk(o) => write!(f, "Error parsing --disk: {o}"),
             ParseRng(o) => write!(f, "Error parsing --rng: {o}"),
+            ParseRngConflictingSources => {
+                write!(
+                    f,
+                    "Error parsing --rng: src and source are mutually exclusive"
+                )
+            }
             ParseBalloon(o) => write!(f, "Error parsing --balloon: {o}"),
             ParseRestore(o) => write!(f, "Error parsing --restore: {o}"),
             #[cfg(target_arch = "x86_64")]
@@ -339,8 +952,70 @@ impl fmt::Display for Error {
             ParsePlatform(o) => write!(f, "Error parsing --platform: {o}"),
             ParseVdpa(o) => write!(f, "Error parsing --vdpa: {o}"),
             ParseVdpaPathMissing => write!(f, "Error parsing --vdpa: path missing"),
+            ParseScsi(o) => write!(f, "Error parsing --scsi: {o}"),
+            ParseScsiDisksMissing => write!(f, "Error parsing --scsi: disks missing"),
+            ParseRateLimitGroup(o) => write!(f, "Error parsing --rate-limit-group: {o}"),
+            ParseRateLimitGroupIdMissing => {
+                write!(f, "Error parsing --rate-limit-group: id missing")
+            }
             ParseTpm(o) => write!(f, "Error parsing --tpm: {o}"),
             ParseTpmPathMissing => write!(f, "Error parsing --tpm: path missing"),
+            ReadCmdlineFile(p, e) => {
+                write!(f, "Error reading --cmdline from {}: {e}", p.display())
+            }
+            ReadConfigFile(p, e) => {
+                write!(f, "Error reading configuration from {}: {e}", p.display())
+            }
+            ParseConfigFile(p, e) => {
+                write!(
+                    f,
+                    "Error parsing configuration at {}:{}:{}: {}",
+                    p.display(),
+                    e.line,
+                    e.column,
+                    e.message
+                )
+            }
+        }
+    }
+}
+
+/// Error found while deserializing a VM configuration, along with its
+/// location in the serialized configuration.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error, starting at 1, or 0 when the line ended early
+    pub column: usize,
+    /// Description of the error, without its location
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        let (line, column) = (e.line(), e.column());
+        // serde_json appends the location to its messages.
+        let message = e.to_string();
+        let message = message
+            .strip_suffix(&format!(" at line {line} column {column}"))
+            .unwrap_or(&message)
+            .to_string();
+
+        ConfigError {
+            line,
+            column,
+            message,
         }
     }
 }
@@ -355,16 +1030,30 @@ pub fn add_to_config<T>(items: &mut Option<Vec<T>>, item: T) {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Parse the kernel command line. When prefixed with '@', the remainder is
+/// the path of a file the command line is read from.
+pub fn parse_cmdline(cmdline: &str) -> Result<String> {
+    if let Some(path) = cmdline.strip_prefix('@') {
+        let path = PathBuf::from(path);
+        let cmdline =
+            std::fs::read_to_string(&path).map_err(|e| Error::ReadCmdlineFile(path, e))?;
+        Ok(cmdline.trim_end_matches('\n').to_string())
+    } else {
+        Ok(cmdline.to_string())
+    }
+}
+
 pub struct VmParams<'a> {
     pub cpus: &'a str,
     pub memory: &'a str,
     pub memory_zones: Option<Vec<&'a str>>,
     pub firmware: Option<&'a str>,
     pub kernel: Option<&'a str>,
-    pub initramfs: Option<&'a str>,
+    pub initramfs: Option<Vec<&'a str>>,
     pub cmdline: Option<&'a str>,
     pub disks: Option<Vec<&'a str>>,
     pub net: Option<Vec<&'a str>>,
+    pub rate_limit_groups: Option<Vec<&'a str>>,
     pub rng: &'a str,
     pub balloon: Option<&'a str>,
     pub fs: Option<Vec<&'a str>>,
@@ -374,12 +1063,15 @@ pub struct VmParams<'a> {
     pub devices: Option<Vec<&'a str>>,
     pub user_devices: Option<Vec<&'a str>>,
     pub vdpa: Option<Vec<&'a str>>,
+    pub scsi: Option<Vec<&'a str>>,
     pub vsock: Option<&'a str>,
     pub pvpanic: bool,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc: Option<Vec<&'a str>>,
     pub numa: Option<Vec<&'a str>>,
     pub watchdog: bool,
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    pub watchdog_coredump: Option<&'a str>,
     #[cfg(feature = "guest_debug")]
     pub gdb: bool,
     pub platform: Option<&'a str>,
@@ -403,20 +1095,136 @@ impl FromStr for HotplugMethod {
     }
 }
 
-pub enum CpuTopologyParseError {
+#[derive(Debug)]
+pub enum ParseMemoryBackendError {
     InvalidValue(String),
 }
 
-impl FromStr for CpuTopology {
-    type Err = CpuTopologyParseError;
+impl FromStr for MemoryBackend {
+    type Err = ParseMemoryBackendError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split(':').collect();
-
-        if parts.len() != 4 {
-            return Err(Self::Err::InvalidValue(s.to_owned()));
+        match s.to_lowercase().as_str() {
+            "memfd" => Ok(MemoryBackend::Memfd),
+            _ => Err(ParseMemoryBackendError::InvalidValue(s.to_owned())),
         }
-
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseCpuIdleError {
+    InvalidValue(String),
+}
+
+impl FromStr for CpuIdle {
+    type Err = ParseCpuIdleError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "halt" => Ok(CpuIdle::Halt),
+            "poll" => Ok(CpuIdle::Poll),
+            _ => Err(ParseCpuIdleError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseLapicTimerError {
+    InvalidValue(String),
+}
+
+impl FromStr for LapicTimer {
+    type Err = ParseLapicTimerError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tsc_deadline" => Ok(LapicTimer::TscDeadline),
+            "periodic" => Ok(LapicTimer::Periodic),
+            _ => Err(ParseLapicTimerError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseVirtioTransportTypeError {
+    InvalidValue(String),
+}
+
+impl FromStr for VirtioTransportType {
+    type Err = ParseVirtioTransportTypeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pci" => Ok(VirtioTransportType::Pci),
+            "mmio" => Ok(VirtioTransportType::Mmio),
+            _ => Err(ParseVirtioTransportTypeError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseRngSourceError {
+    InvalidValue(String),
+}
+
+impl FromStr for RngSource {
+    type Err = ParseRngSourceError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("egd", socket)) if !socket.is_empty() => Ok(RngSource::Egd(socket.into())),
+            None if s == "jitter" => Ok(RngSource::Jitter),
+            _ => Err(ParseRngSourceError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseReadonlyRomError {
+    InvalidValue(String),
+}
+
+impl FromStr for ReadonlyRomConfig {
+    type Err = ParseReadonlyRomError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (address, file) = s
+            .split_once(':')
+            .ok_or_else(|| ParseReadonlyRomError::InvalidValue(s.to_owned()))?;
+        let address = if let Some(hex) = address
+            .strip_prefix("0x")
+            .or_else(|| address.strip_prefix("0X"))
+        {
+            u64::from_str_radix(hex, 16)
+        } else {
+            address.parse()
+        }
+        .map_err(|_| ParseReadonlyRomError::InvalidValue(s.to_owned()))?;
+        if file.is_empty() {
+            return Err(ParseReadonlyRomError::InvalidValue(s.to_owned()));
+        }
+
+        Ok(ReadonlyRomConfig {
+            address,
+            file: PathBuf::from(file),
+        })
+    }
+}
+
+pub enum CpuTopologyParseError {
+    InvalidValue(String),
+}
+
+impl FromStr for CpuTopology {
+    type Err = CpuTopologyParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+
+        if parts.len() != 4 {
+            return Err(Self::Err::InvalidValue(s.to_owned()));
+        }
+
         let t = CpuTopology {
             threads_per_core: parts[0]
                 .parse()
@@ -446,29 +1254,44 @@ impl CpusConfig {
             .add("kvm_hyperv")
             .add("max_phys_bits")
             .add("affinity")
-            .add("features");
+            .add("features")
+            .add("tsc_freq")
+            .add("idle")
+            .add("stable_id")
+            .add("hypervisor_cpuid")
+            .add("lapic_timer");
         parser.parse(cpus).map_err(Error::ParseCpus)?;
 
-        let boot_vcpus: u8 = parser
+        let boot_vcpus: u32 = parser
             .convert("boot")
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_VCPUS);
-        let max_vcpus: u8 = parser
+        let max_vcpus: u32 = parser
             .convert("max")
             .map_err(Error::ParseCpus)?
             .unwrap_or(boot_vcpus);
-        let topology = parser.convert("topology").map_err(Error::ParseCpus)?;
+        let host_topology = parser.get("topology").as_deref() == Some("host");
+        let topology = if host_topology {
+            None
+        } else {
+            parser.convert("topology").map_err(Error::ParseCpus)?
+        };
         let kvm_hyperv = parser
             .convert::<Toggle>("kvm_hyperv")
             .map_err(Error::ParseCpus)?
             .unwrap_or(Toggle(false))
             .0;
-        let max_phys_bits = parser
-            .convert::<u8>("max_phys_bits")
-            .map_err(Error::ParseCpus)?
-            .unwrap_or(DEFAULT_MAX_PHYS_BITS);
+        let auto_phys_bits = parser.get("max_phys_bits").as_deref() == Some("auto");
+        let max_phys_bits = if auto_phys_bits {
+            DEFAULT_MAX_PHYS_BITS
+        } else {
+            parser
+                .convert::<u8>("max_phys_bits")
+                .map_err(Error::ParseCpus)?
+                .unwrap_or(DEFAULT_MAX_PHYS_BITS)
+        };
         let affinity = parser
-            .convert::<Tuple<u8, Vec<u8>>>("affinity")
+            .convert::<Tuple<u32, Vec<u8>>>("affinity")
             .map_err(Error::ParseCpus)?
             .map(|v| {
                 v.0.iter()
@@ -496,35 +1319,108 @@ impl CpusConfig {
                     features.amx = true;
                     Ok(())
                 }
-                _ => Err(Error::InvalidCpuFeatures(s)),
+                // KVM PV features are toggled with <feature>=on|off
+                #[cfg(target_arch = "x86_64")]
+                f if f.contains('=') => {
+                    let (name, value) = f.split_once('=').unwrap();
+                    match (name.parse::<KvmPvFeature>(), value.parse::<Toggle>()) {
+                        (Ok(feature), Ok(toggle)) => {
+                            features.kvm_pv_features.insert(feature, toggle.0);
+                            Ok(())
+                        }
+                        _ => Err(Error::InvalidCpuFeatures(s.clone())),
+                    }
+                }
+                _ => Err(Error::InvalidCpuFeatures(s.clone())),
             }?;
         }
+        let tsc_freq = parser
+            .convert::<u64>("tsc_freq")
+            .map_err(Error::ParseCpus)?;
+        let idle = parser
+            .convert::<CpuIdle>("idle")
+            .map_err(Error::ParseCpus)?;
+        let stable_id = parser
+            .convert::<Toggle>("stable_id")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(false))
+            .0
+            .then(CpuStableId::default);
+        let hypervisor_cpuid = parser
+            .convert::<Toggle>("hypervisor_cpuid")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(true))
+            .0;
+        let lapic_timer = parser
+            .convert::<LapicTimer>("lapic_timer")
+            .map_err(Error::ParseCpus)?;
 
         Ok(CpusConfig {
             boot_vcpus,
             max_vcpus,
             topology,
+            host_topology,
             kvm_hyperv,
             max_phys_bits,
+            auto_phys_bits,
             affinity,
             features,
+            tsc_freq,
+            idle,
+            stable_id,
+            hypervisor_cpuid,
+            lapic_timer,
         })
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+impl CpuStableId {
+    pub fn validate(&self) -> ValidationResult<()> {
+        // Families up to 0xf are encoded directly, the next ones through the
+        // 8 bits extended family field.
+        if self.family == 0 || self.family > 0xf + 0xff {
+            return Err(ValidationError::InvalidCpuStableId("family out of range"));
+        }
+        // Only families 0x6 and 0xf can use the extended model field.
+        if self.model > 0xf && self.family != 0x6 && self.family < 0xf {
+            return Err(ValidationError::InvalidCpuStableId("model out of range"));
+        }
+        if self.stepping > 0xf {
+            return Err(ValidationError::InvalidCpuStableId("stepping out of range"));
+        }
+        // 48 bytes of CPUID, including the terminating NUL character.
+        if !self.brand.is_ascii() || self.brand.len() > 47 {
+            return Err(ValidationError::InvalidCpuStableId(
+                "brand string must be at most 47 ASCII characters",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 impl PlatformConfig {
     pub fn parse(platform: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
         parser
             .add("num_pci_segments")
             .add("iommu_segments")
+            .add("iommu_address_width")
             .add("serial_number")
             .add("uuid")
-            .add("oem_strings");
+            .add("oem_strings")
+            .add("seed")
+            .add("acpi")
+            .add("transport")
+            .add("dsdt")
+            .add("dsdt_append");
         #[cfg(feature = "tdx")]
         parser.add("tdx");
         #[cfg(feature = "sev_snp")]
         parser.add("sev_snp");
+        #[cfg(feature = "sev")]
+        parser.add("sev_es");
         parser.parse(platform).map_err(Error::ParsePlatform)?;
 
         let num_pci_segments: u16 = parser
@@ -535,6 +1431,10 @@ impl PlatformConfig {
             .convert::<IntegerList>("iommu_segments")
             .map_err(Error::ParsePlatform)?
             .map(|v| v.0.iter().map(|e| *e as u16).collect());
+        let iommu_address_width_bits: u8 = parser
+            .convert("iommu_address_width")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(DEFAULT_IOMMU_ADDRESS_WIDTH_BITS);
         let serial_number = parser
             .convert("serial_number")
             .map_err(Error::ParsePlatform)?;
@@ -543,6 +1443,22 @@ impl PlatformConfig {
             .convert::<StringList>("oem_strings")
             .map_err(Error::ParsePlatform)?
             .map(|v| v.0);
+        let seed = parser.get("seed");
+        let acpi = parser
+            .convert::<Toggle>("acpi")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(true))
+            .0;
+        let transport = parser
+            .convert::<VirtioTransportType>("transport")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or_default();
+        let dsdt = parser.get("dsdt").map(PathBuf::from);
+        let dsdt_append = parser
+            .convert::<Toggle>("dsdt_append")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
         #[cfg(feature = "tdx")]
         let tdx = parser
             .convert::<Toggle>("tdx")
@@ -555,16 +1471,30 @@ impl PlatformConfig {
             .map_err(Error::ParsePlatform)?
             .unwrap_or(Toggle(false))
             .0;
+        #[cfg(feature = "sev")]
+        let sev_es = parser
+            .convert::<Toggle>("sev_es")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
         Ok(PlatformConfig {
             num_pci_segments,
             iommu_segments,
+            iommu_address_width_bits,
             serial_number,
             uuid,
             oem_strings,
+            seed,
+            acpi,
+            transport,
+            dsdt,
+            dsdt_append,
             #[cfg(feature = "tdx")]
             tdx,
             #[cfg(feature = "sev_snp")]
             sev_snp,
+            #[cfg(feature = "sev")]
+            sev_es,
         })
     }
 
@@ -583,6 +1513,14 @@ impl PlatformConfig {
             }
         }
 
+        if self.iommu_address_width_bits < MIN_IOMMU_ADDRESS_WIDTH_BITS
+            || self.iommu_address_width_bits > DEFAULT_IOMMU_ADDRESS_WIDTH_BITS
+        {
+            return Err(ValidationError::InvalidIommuAddressWidthBits(
+                self.iommu_address_width_bits,
+            ));
+        }
+
         Ok(())
     }
 }
@@ -601,7 +1539,14 @@ impl MemoryConfig {
             .add("hugepages")
             .add("hugepage_size")
             .add("prefault")
-            .add("thp");
+            .add("thp")
+            .add("prealloc_threads")
+            .add("readonly_rom")
+            .add("numa_bind")
+            .add("zero_on_free")
+            .add("dump_on_oom")
+            .add("backend")
+            .add("max_ram");
         parser.parse(memory).map_err(Error::ParseMemory)?;
 
         let size = parser
@@ -609,6 +1554,7 @@ impl MemoryConfig {
             .map_err(Error::ParseMemory)?
             .unwrap_or(ByteSized(DEFAULT_MEMORY_MB << 20))
             .0;
+        let file = parser.get("file").map(PathBuf::from);
         let mergeable = parser
             .convert::<Toggle>("mergeable")
             .map_err(Error::ParseMemory)?
@@ -650,6 +1596,28 @@ impl MemoryConfig {
             .map_err(Error::ParseMemory)?
             .unwrap_or(Toggle(true))
             .0;
+        let prealloc_threads = parser
+            .convert::<usize>("prealloc_threads")
+            .map_err(Error::ParseMemory)?;
+        let readonly_rom = parser
+            .convert::<ReadonlyRomConfig>("readonly_rom")
+            .map_err(Error::ParseMemory)?;
+        let numa_bind = parser
+            .convert::<u32>("numa_bind")
+            .map_err(Error::ParseMemory)?;
+        let zero_on_free = parser
+            .convert::<Toggle>("zero_on_free")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let dump_on_oom = parser.get("dump_on_oom").map(PathBuf::from);
+        let backend = parser
+            .convert::<MemoryBackend>("backend")
+            .map_err(Error::ParseMemory)?;
+        let max_ram = parser
+            .convert::<ByteSized>("max_ram")
+            .map_err(Error::ParseMemory)?
+            .map(|v| v.0);
 
         let zones: Option<Vec<MemoryZoneConfig>> = if let Some(memory_zones) = &memory_zones {
             let mut zones = Vec::new();
@@ -727,6 +1695,7 @@ impl MemoryConfig {
 
         Ok(MemoryConfig {
             size,
+            file,
             mergeable,
             hotplug_method,
             hotplug_size,
@@ -737,6 +1706,13 @@ impl MemoryConfig {
             prefault,
             zones,
             thp,
+            prealloc_threads,
+            readonly_rom,
+            numa_bind,
+            zero_on_free,
+            dump_on_oom,
+            backend,
+            max_ram,
         })
     }
 
@@ -759,6 +1735,25 @@ impl MemoryConfig {
     }
 }
 
+// The split read and write limits are given per second, a limit of 0 being
+// the same as no limit.
+fn split_rate_limiter_config(ops: Option<u64>, bytes: Option<u64>) -> Option<RateLimiterConfig> {
+    let token_bucket = |size| TokenBucketConfig {
+        size,
+        one_time_burst: None,
+        burst: None,
+        refill_time: 1000,
+    };
+    let ops = ops.filter(|v| *v != 0).map(token_bucket);
+    let bandwidth = bytes.filter(|v| *v != 0).map(token_bucket);
+
+    if ops.is_some() || bandwidth.is_some() {
+        Some(RateLimiterConfig { bandwidth, ops })
+    } else {
+        None
+    }
+}
+
 impl DiskConfig {
     pub fn parse(disk: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -773,14 +1768,39 @@ impl DiskConfig {
             .add("socket")
             .add("bw_size")
             .add("bw_one_time_burst")
+            .add("bw_burst")
             .add("bw_refill_time")
             .add("ops_size")
             .add("ops_one_time_burst")
+            .add("ops_burst")
             .add("ops_refill_time")
             .add("id")
             .add("_disable_io_uring")
             .add("pci_segment")
-            .add("serial");
+            .add("serial")
+            .add("wwn")
+            .add("cache")
+            .add("segment_max")
+            .add("size_max")
+            .add("media")
+            .add("aio_max_inflight")
+            .add("overlay")
+            .add("overlay_discard")
+            .add("poll_queue")
+            .add("poll_queue_us")
+            .add("sync_on_flush_interval")
+            .add("request_timeout")
+            .add("locking")
+            .add("zoned")
+            .add("zone_size")
+            .add("conventional_zones")
+            .add("probe_alignment")
+            .add("url")
+            .add("cache_mb")
+            .add("throttle_iops_rd")
+            .add("throttle_iops_wr")
+            .add("throttle_bps_rd")
+            .add("throttle_bps_wr");
         parser.parse(disk).map_err(Error::ParseDisk)?;
 
         let path = parser.get("path").map(PathBuf::from);
@@ -831,6 +1851,7 @@ impl DiskConfig {
             .convert("bw_one_time_burst")
             .map_err(Error::ParseDisk)?
             .unwrap_or_default();
+        let bw_burst = parser.convert("bw_burst").map_err(Error::ParseDisk)?;
         let bw_refill_time = parser
             .convert("bw_refill_time")
             .map_err(Error::ParseDisk)?
@@ -843,15 +1864,100 @@ impl DiskConfig {
             .convert("ops_one_time_burst")
             .map_err(Error::ParseDisk)?
             .unwrap_or_default();
+        let ops_burst = parser.convert("ops_burst").map_err(Error::ParseDisk)?;
         let ops_refill_time = parser
             .convert("ops_refill_time")
             .map_err(Error::ParseDisk)?
             .unwrap_or_default();
         let serial = parser.get("serial");
+        let wwn = parser.get("wwn");
+        let cache = parser
+            .convert("cache")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or_default();
+        let segment_max = parser.convert("segment_max").map_err(Error::ParseDisk)?;
+        let size_max = parser.convert("size_max").map_err(Error::ParseDisk)?;
+        let media = parser
+            .convert("media")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or_default();
+        let aio_max_inflight = parser
+            .convert("aio_max_inflight")
+            .map_err(Error::ParseDisk)?;
+        let overlay = parser.get("overlay").map(PathBuf::from);
+        let overlay_discard = parser
+            .convert::<Toggle>("overlay_discard")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let poll_queue = parser
+            .convert::<Toggle>("poll_queue")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let poll_queue_us = parser.convert("poll_queue_us").map_err(Error::ParseDisk)?;
+        let sync_on_flush_interval = parser
+            .convert("sync_on_flush_interval")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or_default();
+        let request_timeout = parser
+            .convert("request_timeout")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or_default();
+        let locking = parser
+            .convert("locking")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or_default();
+        let zoned = parser
+            .convert::<Toggle>("zoned")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let zone_size = parser
+            .convert::<ByteSized>("zone_size")
+            .map_err(Error::ParseDisk)?
+            .map(|v| v.0);
+        let conventional_zones = parser
+            .convert("conventional_zones")
+            .map_err(Error::ParseDisk)?;
+        let probe_alignment = parser
+            .convert::<Toggle>("probe_alignment")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let url = parser.get("url");
+        let cache_mb = parser.convert("cache_mb").map_err(Error::ParseDisk)?;
+        let throttle_iops_rd = parser
+            .convert("throttle_iops_rd")
+            .map_err(Error::ParseDisk)?;
+        let throttle_iops_wr = parser
+            .convert("throttle_iops_wr")
+            .map_err(Error::ParseDisk)?;
+        let throttle_bps_rd = parser
+            .convert::<ByteSized>("throttle_bps_rd")
+            .map_err(Error::ParseDisk)?
+            .map(|v| v.0);
+        let throttle_bps_wr = parser
+            .convert::<ByteSized>("throttle_bps_wr")
+            .map_err(Error::ParseDisk)?
+            .map(|v| v.0);
+
+        // Only an explicit cache mode can be told apart from the default one,
+        // hence these checks being done here rather than in validate().
+        if parser.is_set("cache") {
+            if vhost_user {
+                return Err(Error::Validation(ValidationError::DiskCacheVhostUser));
+            }
+            if direct && cache != CacheMode::None {
+                return Err(Error::Validation(ValidationError::DiskDirectCache));
+            }
+        }
+
         let bw_tb_config = if bw_size != 0 && bw_refill_time != 0 {
             Some(TokenBucketConfig {
                 size: bw_size,
                 one_time_burst: Some(bw_one_time_burst),
+                burst: bw_burst,
                 refill_time: bw_refill_time,
             })
         } else {
@@ -861,6 +1967,7 @@ impl DiskConfig {
             Some(TokenBucketConfig {
                 size: ops_size,
                 one_time_burst: Some(ops_one_time_burst),
+                burst: ops_burst,
                 refill_time: ops_refill_time,
             })
         } else {
@@ -874,6 +1981,9 @@ impl DiskConfig {
         } else {
             None
         };
+        let read_rate_limiter_config = split_rate_limiter_config(throttle_iops_rd, throttle_bps_rd);
+        let write_rate_limiter_config =
+            split_rate_limiter_config(throttle_iops_wr, throttle_bps_wr);
 
         Ok(DiskConfig {
             path,
@@ -885,10 +1995,31 @@ impl DiskConfig {
             vhost_user,
             vhost_socket,
             rate_limiter_config,
+            read_rate_limiter_config,
+            write_rate_limiter_config,
             id,
             disable_io_uring,
             pci_segment,
             serial,
+            wwn,
+            cache,
+            segment_max,
+            size_max,
+            media,
+            aio_max_inflight,
+            overlay,
+            overlay_discard,
+            poll_queue,
+            poll_queue_us,
+            sync_on_flush_interval,
+            request_timeout,
+            locking,
+            zoned,
+            zone_size,
+            conventional_zones,
+            probe_alignment,
+            url,
+            cache_mb,
         })
     }
 
@@ -901,6 +2032,177 @@ impl DiskConfig {
             return Err(ValidationError::IommuNotSupported);
         }
 
+        if let Some(wwn) = self.wwn.as_ref() {
+            if !is_valid_wwn(wwn) {
+                return Err(ValidationError::InvalidWwn(wwn.clone()));
+            }
+        }
+
+        if self.serial.is_some() && self.wwn.is_some() {
+            return Err(ValidationError::DiskSerialAndWwn);
+        }
+
+        if self.vhost_user && (self.segment_max.is_some() || self.size_max.is_some()) {
+            return Err(ValidationError::SegmentLimitsVhostUser);
+        }
+
+        if self.vhost_user && self.probe_alignment {
+            return Err(ValidationError::ProbeAlignmentVhostUser);
+        }
+
+        if self.vhost_user
+            && (self.read_rate_limiter_config.is_some() || self.write_rate_limiter_config.is_some())
+        {
+            return Err(ValidationError::SplitRateLimitsVhostUser);
+        }
+
+        // As indirect descriptors are not offered, a request must fit in the
+        // queue, including its header and status descriptors.
+        if let Some(segment_max) = self.segment_max {
+            if segment_max == 0 || segment_max > u32::from(self.queue_size).saturating_sub(2) {
+                return Err(ValidationError::InvalidSegmentMax(
+                    segment_max,
+                    self.queue_size,
+                ));
+            }
+        }
+
+        if self.size_max == Some(0) {
+            return Err(ValidationError::InvalidSizeMax);
+        }
+
+        if let Some(aio_max_inflight) = self.aio_max_inflight {
+            if aio_max_inflight == 0 {
+                return Err(ValidationError::InvalidAioMaxInflight);
+            }
+            if self.vhost_user {
+                return Err(ValidationError::AioMaxInflightVhostUser);
+            }
+        }
+
+        // "direct=on" is the same as "cache=none". The default writeback mode
+        // can't be told apart from an explicit one, see parse().
+        if self.vhost_user && (self.direct || self.cache != CacheMode::Writeback) {
+            return Err(ValidationError::DiskCacheVhostUser);
+        }
+        if self.direct && self.cache == CacheMode::Writethrough {
+            return Err(ValidationError::DiskDirectCache);
+        }
+
+        if self.readonly {
+            if self.cache == CacheMode::Writethrough {
+                return Err(ValidationError::ReadonlyDiskWriteOption(
+                    "cache=writethrough",
+                ));
+            }
+            if self.sync_on_flush_interval != 0 {
+                return Err(ValidationError::ReadonlyDiskWriteOption(
+                    "sync_on_flush_interval",
+                ));
+            }
+        }
+
+        if self.media == DiskMedia::Cdrom {
+            if self.vhost_user {
+                return Err(ValidationError::CdromVhostUser);
+            }
+            if !self.readonly {
+                return Err(ValidationError::CdromNotReadonly);
+            }
+        }
+
+        if self.directory().is_some() {
+            if self.vhost_user {
+                return Err(ValidationError::DiskDirectoryVhostUser);
+            }
+            if !self.readonly {
+                return Err(ValidationError::DiskDirectoryNotReadonly);
+            }
+        }
+
+        if self.overlay.is_some() {
+            if self.vhost_user {
+                return Err(ValidationError::OverlayVhostUser);
+            }
+            if self.readonly || self.media == DiskMedia::Cdrom || self.directory().is_some() {
+                return Err(ValidationError::OverlayUnsupportedDisk);
+            }
+            if self.direct || self.cache == CacheMode::None {
+                return Err(ValidationError::OverlayDirect);
+            }
+        } else if self.overlay_discard {
+            return Err(ValidationError::OverlayDiscardWithoutOverlay);
+        }
+
+        if let Some(url) = self.url.as_ref() {
+            if !url.starts_with("http://")
+                && !(cfg!(feature = "https") && url.starts_with("https://"))
+            {
+                return Err(ValidationError::InvalidDiskUrl(url.clone()));
+            }
+            if !self.readonly {
+                return Err(ValidationError::DiskUrlNotReadonly);
+            }
+            let unsupported = [
+                (self.vhost_user, "vhost_user"),
+                (self.direct, "direct"),
+                (self.cache == CacheMode::None, "cache=none"),
+                (self.media == DiskMedia::Cdrom, "media=cdrom"),
+                (self.probe_alignment, "probe_alignment"),
+            ];
+            if let Some((_, option)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(ValidationError::DiskUrlUnsupportedOption(option));
+            }
+        } else if self.cache_mb.is_some() {
+            return Err(ValidationError::DiskCacheSizeWithoutUrl);
+        }
+
+        if self.poll_queue_us == Some(0) {
+            return Err(ValidationError::InvalidPollQueueDuration);
+        }
+
+        if self.poll_queue {
+            if self.vhost_user {
+                return Err(ValidationError::PollQueueVhostUser);
+            }
+        } else if self.poll_queue_us.is_some() {
+            return Err(ValidationError::PollQueueDurationWithoutPollQueue);
+        }
+
+        if self.vhost_user && self.sync_on_flush_interval != 0 {
+            return Err(ValidationError::SyncIntervalVhostUser);
+        }
+
+        if self.vhost_user && self.request_timeout != 0 {
+            return Err(ValidationError::RequestTimeoutVhostUser);
+        }
+
+        // Only the io_uring backends can give up on a request, the others
+        // block the queue worker until it completes.
+        if self.request_timeout != 0 {
+            let unsupported = [
+                (self.url.is_some(), "url"),
+                (self.overlay.is_some(), "overlay"),
+                (self.disable_io_uring, "_disable_io_uring"),
+            ];
+            if let Some((_, option)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(ValidationError::RequestTimeoutUnsupported(option));
+            }
+        }
+
+        if self.zoned {
+            if self.vhost_user || self.readonly || self.media == DiskMedia::Cdrom {
+                return Err(ValidationError::ZonedUnsupportedDisk);
+            }
+            if let Some(zone_size) = self.zone_size {
+                if !zone_size.is_power_of_two() || !(4 << 10..=1 << 40).contains(&zone_size) {
+                    return Err(ValidationError::InvalidZoneSize(zone_size));
+                }
+            }
+        } else if self.zone_size.is_some() || self.conventional_zones.is_some() {
+            return Err(ValidationError::ZoneLayoutWithoutZoned);
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -917,52 +2219,162 @@ impl DiskConfig {
     }
 }
 
+// A WWN is accepted as a 64-bit NAA identifier (16 hexadecimal digits,
+// optionally prefixed with "0x"). Only the IEEE extended (2), locally assigned
+// (3) and IEEE registered (5) formats are 64-bit wide, which is also what fits
+// in the 20 bytes of the virtio-blk device id.
+fn is_valid_wwn(wwn: &str) -> bool {
+    let digits = wwn
+        .strip_prefix("0x")
+        .or_else(|| wwn.strip_prefix("0X"))
+        .unwrap_or(wwn);
+
+    digits.len() == 16
+        && digits.chars().all(|c| c.is_ascii_hexdigit())
+        && matches!(digits.chars().next(), Some('2' | '3' | '5'))
+}
+
 #[derive(Debug)]
-pub enum ParseVhostModeError {
+pub enum ParseCacheModeError {
     InvalidValue(String),
 }
 
-impl FromStr for VhostMode {
-    type Err = ParseVhostModeError;
+impl FromStr for CacheMode {
+    type Err = ParseCacheModeError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "client" => Ok(VhostMode::Client),
-            "server" => Ok(VhostMode::Server),
-            _ => Err(ParseVhostModeError::InvalidValue(s.to_owned())),
+            "writeback" => Ok(CacheMode::Writeback),
+            "writethrough" => Ok(CacheMode::Writethrough),
+            "none" => Ok(CacheMode::None),
+            _ => Err(ParseCacheModeError::InvalidValue(s.to_owned())),
         }
     }
 }
 
-impl NetConfig {
-    pub fn parse(net: &str) -> Result<Self> {
-        let mut parser = OptionParser::new();
+#[derive(Debug)]
+pub enum ParseFlowControlError {
+    InvalidValue(String),
+}
 
-        parser
-            .add("tap")
-            .add("ip")
-            .add("mask")
-            .add("mac")
-            .add("host_mac")
-            .add("offload_tso")
-            .add("offload_ufo")
-            .add("offload_csum")
-            .add("mtu")
-            .add("iommu")
-            .add("queue_size")
-            .add("num_queues")
-            .add("vhost_user")
+impl FromStr for FlowControl {
+    type Err = ParseFlowControlError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(FlowControl::None),
+            "rtscts" => Ok(FlowControl::RtsCts),
+            _ => Err(ParseFlowControlError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseDiskLockingError {
+    InvalidValue(String),
+}
+
+impl FromStr for DiskLocking {
+    type Err = ParseDiskLockingError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "flock" => Ok(DiskLocking::Flock),
+            "none" => Ok(DiskLocking::None),
+            _ => Err(ParseDiskLockingError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseDiskMediaError {
+    InvalidValue(String),
+}
+
+impl FromStr for DiskMedia {
+    type Err = ParseDiskMediaError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "disk" => Ok(DiskMedia::Disk),
+            "cdrom" => Ok(DiskMedia::Cdrom),
+            _ => Err(ParseDiskMediaError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParsePmemMemPolicyError {
+    InvalidValue(String),
+}
+
+impl FromStr for PmemMemPolicy {
+    type Err = ParsePmemMemPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "shared" => Ok(PmemMemPolicy::Shared),
+            "private" => Ok(PmemMemPolicy::Private),
+            _ => Err(ParsePmemMemPolicyError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseVhostModeError {
+    InvalidValue(String),
+}
+
+impl FromStr for VhostMode {
+    type Err = ParseVhostModeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "client" => Ok(VhostMode::Client),
+            "server" => Ok(VhostMode::Server),
+            _ => Err(ParseVhostModeError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+impl NetConfig {
+    pub fn parse(net: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+
+        parser
+            .add("tap")
+            .add("ip")
+            .add("mask")
+            .add("mac")
+            .add("host_mac")
+            .add("offload_tso")
+            .add("offload_ufo")
+            .add("offload_csum")
+            .add("promisc")
+            .add("mtu")
+            .add("iommu")
+            .add("queue_size")
+            .add("rx_queue_size")
+            .add("tx_queue_size")
+            .add("num_queues")
+            .add("vhost_user")
             .add("socket")
             .add("vhost_mode")
+            .add("vdpa")
             .add("id")
             .add("fd")
             .add("bw_size")
             .add("bw_one_time_burst")
+            .add("bw_burst")
             .add("bw_refill_time")
             .add("ops_size")
             .add("ops_one_time_burst")
+            .add("ops_burst")
             .add("ops_refill_time")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("host_name")
+            .add("rate_limit_group")
+            .add("vhost");
         parser.parse(net).map_err(Error::ParseNetwork)?;
 
         let tap = parser.get("tap");
@@ -970,14 +2382,8 @@ impl NetConfig {
             .convert("ip")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_else(default_netconfig_ip);
-        let mask = parser
-            .convert("mask")
-            .map_err(Error::ParseNetwork)?
-            .unwrap_or_else(default_netconfig_mask);
-        let mac = parser
-            .convert("mac")
-            .map_err(Error::ParseNetwork)?
-            .unwrap_or_else(default_netconfig_mac);
+        let mask = parser.convert("mask").map_err(Error::ParseNetwork)?;
+        let mac = parser.convert("mac").map_err(Error::ParseNetwork)?;
         let host_mac = parser.convert("host_mac").map_err(Error::ParseNetwork)?;
         let offload_tso = parser
             .convert::<Toggle>("offload_tso")
@@ -994,6 +2400,11 @@ impl NetConfig {
             .map_err(Error::ParseNetwork)?
             .unwrap_or(Toggle(true))
             .0;
+        let promisc = parser
+            .convert::<Toggle>("promisc")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(true))
+            .0;
         let mtu = parser.convert("mtu").map_err(Error::ParseNetwork)?;
         let iommu = parser
             .convert::<Toggle>("iommu")
@@ -1004,6 +2415,12 @@ impl NetConfig {
             .convert("queue_size")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_else(default_netconfig_queue_size);
+        let rx_queue_size = parser
+            .convert("rx_queue_size")
+            .map_err(Error::ParseNetwork)?;
+        let tx_queue_size = parser
+            .convert("tx_queue_size")
+            .map_err(Error::ParseNetwork)?;
         let num_queues = parser
             .convert("num_queues")
             .map_err(Error::ParseNetwork)?
@@ -1018,6 +2435,7 @@ impl NetConfig {
             .convert("vhost_mode")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
+        let vdpa = parser.get("vdpa").map(PathBuf::from);
         let id = parser.get("id");
         let fds = parser
             .convert::<IntegerList>("fd")
@@ -1027,6 +2445,13 @@ impl NetConfig {
             .convert("pci_segment")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
+        let host_name = parser.get("host_name");
+        let rate_limit_group = parser.get("rate_limit_group");
+        let vhost = parser
+            .convert::<Toggle>("vhost")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
         let bw_size = parser
             .convert("bw_size")
             .map_err(Error::ParseNetwork)?
@@ -1035,6 +2460,7 @@ impl NetConfig {
             .convert("bw_one_time_burst")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
+        let bw_burst = parser.convert("bw_burst").map_err(Error::ParseNetwork)?;
         let bw_refill_time = parser
             .convert("bw_refill_time")
             .map_err(Error::ParseNetwork)?
@@ -1047,6 +2473,7 @@ impl NetConfig {
             .convert("ops_one_time_burst")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
+        let ops_burst = parser.convert("ops_burst").map_err(Error::ParseNetwork)?;
         let ops_refill_time = parser
             .convert("ops_refill_time")
             .map_err(Error::ParseNetwork)?
@@ -1055,6 +2482,7 @@ impl NetConfig {
             Some(TokenBucketConfig {
                 size: bw_size,
                 one_time_burst: Some(bw_one_time_burst),
+                burst: bw_burst,
                 refill_time: bw_refill_time,
             })
         } else {
@@ -1064,6 +2492,7 @@ impl NetConfig {
             Some(TokenBucketConfig {
                 size: ops_size,
                 one_time_burst: Some(ops_one_time_burst),
+                burst: ops_burst,
                 refill_time: ops_refill_time,
             })
         } else {
@@ -1088,9 +2517,12 @@ impl NetConfig {
             iommu,
             num_queues,
             queue_size,
+            rx_queue_size,
+            tx_queue_size,
             vhost_user,
             vhost_socket,
             vhost_mode,
+            vdpa,
             id,
             fds,
             rate_limiter_config,
@@ -1098,6 +2530,10 @@ impl NetConfig {
             offload_tso,
             offload_ufo,
             offload_csum,
+            promisc,
+            host_name,
+            rate_limit_group,
+            vhost,
         };
         Ok(config)
     }
@@ -1145,10 +2581,207 @@ impl NetConfig {
             }
         }
 
+        let mask = match (self.ip, self.netmask()) {
+            (IpAddr::V4(_), IpAddr::V4(mask)) => u128::from(u32::from(mask)) << 96,
+            (IpAddr::V6(_), IpAddr::V6(mask)) => u128::from(mask),
+            _ => return Err(ValidationError::NetIpMaskMismatch),
+        };
+        if mask.leading_ones() != mask.count_ones() {
+            return Err(ValidationError::InvalidNetmask(self.netmask()));
+        }
+
         if !self.offload_csum && (self.offload_tso || self.offload_ufo) {
             return Err(ValidationError::NoHardwareChecksumOffload);
         }
 
+        if !self.promisc && (self.vhost_user || self.vdpa.is_some()) {
+            return Err(ValidationError::PromiscNotSupported);
+        }
+
+        for size in [self.rx_queue_size, self.tx_queue_size]
+            .into_iter()
+            .flatten()
+        {
+            if !size.is_power_of_two() || size > virtio_devices::net::MAX_QUEUE_SIZE {
+                return Err(ValidationError::InvalidQueueSize(size));
+            }
+        }
+
+        if self.vhost_user && (self.rx_queue_size.is_some() || self.tx_queue_size.is_some()) {
+            return Err(ValidationError::AsymmetricQueueSizeVhostUser);
+        }
+
+        if self.vdpa.is_some() {
+            if self.vhost_user || self.tap.is_some() || self.fds.is_some() {
+                return Err(ValidationError::VdpaNetConflict);
+            }
+
+            if self.rx_queue_size.is_some() || self.tx_queue_size.is_some() {
+                return Err(ValidationError::AsymmetricQueueSizeVdpa);
+            }
+
+            // The MAC address, MTU, queue size and offloads are those exposed
+            // by the hardware, there is no host side TAP interface, and the
+            // datapath bypasses the VMM rate limiters.
+            if self.mac.is_some() {
+                return Err(ValidationError::VdpaNetUnsupported("\"mac\""));
+            }
+            if self.host_mac.is_some() {
+                return Err(ValidationError::VdpaNetUnsupported("\"host_mac\""));
+            }
+            if self.queue_size != default_netconfig_queue_size() {
+                return Err(ValidationError::VdpaNetUnsupported("\"queue_size\""));
+            }
+            if self.mtu.is_some() {
+                return Err(ValidationError::VdpaNetUnsupported("\"mtu\""));
+            }
+            if self.rate_limiter_config.is_some() {
+                return Err(ValidationError::VdpaNetUnsupported("rate limiting"));
+            }
+            if !self.offload_tso || !self.offload_ufo || !self.offload_csum {
+                return Err(ValidationError::VdpaNetUnsupported("offload settings"));
+            }
+        }
+
+        if self.vhost {
+            if self.vhost_user || self.vdpa.is_some() {
+                return Err(ValidationError::VhostNetConflict);
+            }
+            if self.iommu {
+                return Err(ValidationError::VhostNetUnsupported("\"iommu=on\""));
+            }
+            if self.rate_limiter_config.is_some() || self.rate_limit_group.is_some() {
+                return Err(ValidationError::VhostNetUnsupported("rate limiting"));
+            }
+            if !self.promisc {
+                return Err(ValidationError::VhostNetUnsupported("\"promisc=off\""));
+            }
+        }
+
+        if let Some(tap) = self.tap.as_ref().filter(|tap| tap.starts_with('@')) {
+            match self.bridge() {
+                Some(bridge) if !bridge.is_empty() && bridge.len() < 16 => {}
+                _ => return Err(ValidationError::InvalidNetBridge(tap.clone())),
+            }
+            if self.vhost_user {
+                return Err(ValidationError::NetBridgeVhostUser);
+            }
+        }
+
+        if let Some(host_name) = self.host_name.as_ref() {
+            if host_name.is_empty()
+                || host_name.len() > MAX_NET_HOST_NAME_LEN
+                || !host_name.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+            {
+                return Err(ValidationError::InvalidNetHostName(host_name.clone()));
+            }
+        }
+
+        if let Some(group) = self.rate_limit_group.as_ref() {
+            if self.vhost_user || self.vdpa.is_some() {
+                return Err(ValidationError::RateLimitGroupUnsupported);
+            }
+            if self.rate_limiter_config.is_some() {
+                return Err(ValidationError::RateLimitGroupAndRateLimiter);
+            }
+            if !vm_config
+                .rate_limit_groups
+                .iter()
+                .flatten()
+                .any(|g| &g.id == group)
+            {
+                return Err(ValidationError::UnknownRateLimitGroup(group.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RateLimitGroupConfig {
+    pub fn parse(rate_limit_group: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("id")
+            .add("bw_size")
+            .add("bw_one_time_burst")
+            .add("bw_burst")
+            .add("bw_refill_time")
+            .add("ops_size")
+            .add("ops_one_time_burst")
+            .add("ops_burst")
+            .add("ops_refill_time");
+        parser
+            .parse(rate_limit_group)
+            .map_err(Error::ParseRateLimitGroup)?;
+
+        let id = parser
+            .get("id")
+            .ok_or(Error::ParseRateLimitGroupIdMissing)?;
+        let bw_size = parser
+            .convert("bw_size")
+            .map_err(Error::ParseRateLimitGroup)?
+            .unwrap_or_default();
+        let bw_one_time_burst = parser
+            .convert("bw_one_time_burst")
+            .map_err(Error::ParseRateLimitGroup)?
+            .unwrap_or_default();
+        let bw_burst = parser
+            .convert("bw_burst")
+            .map_err(Error::ParseRateLimitGroup)?;
+        let bw_refill_time = parser
+            .convert("bw_refill_time")
+            .map_err(Error::ParseRateLimitGroup)?
+            .unwrap_or_default();
+        let ops_size = parser
+            .convert("ops_size")
+            .map_err(Error::ParseRateLimitGroup)?
+            .unwrap_or_default();
+        let ops_one_time_burst = parser
+            .convert("ops_one_time_burst")
+            .map_err(Error::ParseRateLimitGroup)?
+            .unwrap_or_default();
+        let ops_burst = parser
+            .convert("ops_burst")
+            .map_err(Error::ParseRateLimitGroup)?;
+        let ops_refill_time = parser
+            .convert("ops_refill_time")
+            .map_err(Error::ParseRateLimitGroup)?
+            .unwrap_or_default();
+        let bandwidth = if bw_size != 0 && bw_refill_time != 0 {
+            Some(TokenBucketConfig {
+                size: bw_size,
+                one_time_burst: Some(bw_one_time_burst),
+                burst: bw_burst,
+                refill_time: bw_refill_time,
+            })
+        } else {
+            None
+        };
+        let ops = if ops_size != 0 && ops_refill_time != 0 {
+            Some(TokenBucketConfig {
+                size: ops_size,
+                one_time_burst: Some(ops_one_time_burst),
+                burst: ops_burst,
+                refill_time: ops_refill_time,
+            })
+        } else {
+            None
+        };
+
+        Ok(RateLimitGroupConfig {
+            id,
+            rate_limiter_config: RateLimiterConfig { bandwidth, ops },
+        })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.rate_limiter_config.bandwidth.is_none() && self.rate_limiter_config.ops.is_none() {
+            return Err(ValidationError::RateLimitGroupWithoutLimits(
+                self.id.clone(),
+            ));
+        }
+
         Ok(())
     }
 }
@@ -1156,9 +2789,17 @@ impl NetConfig {
 impl RngConfig {
     pub fn parse(rng: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("src").add("iommu");
+        parser
+            .add("src")
+            .add("iommu")
+            .add("source")
+            .add("queue_size");
         parser.parse(rng).map_err(Error::ParseRng)?;
 
+        if parser.is_set("src") && parser.is_set("source") {
+            return Err(Error::ParseRngConflictingSources);
+        }
+
         let src = PathBuf::from(
             parser
                 .get("src")
@@ -1169,8 +2810,21 @@ impl RngConfig {
             .map_err(Error::ParseRng)?
             .unwrap_or(Toggle(false))
             .0;
+        let source = parser
+            .convert::<RngSource>("source")
+            .map_err(Error::ParseRng)?;
+
+        let queue_size = parser
+            .convert("queue_size")
+            .map_err(Error::ParseRng)?
+            .unwrap_or_else(default_rngconfig_queue_size);
 
-        Ok(RngConfig { src, iommu })
+        Ok(RngConfig {
+            src,
+            iommu,
+            source,
+            queue_size,
+        })
     }
 }
 
@@ -1216,12 +2870,21 @@ impl FsConfig {
             .add("queue_size")
             .add("num_queues")
             .add("socket")
+            .add("fd")
             .add("id")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("reconnect");
         parser.parse(fs).map_err(Error::ParseFileSystem)?;
 
         let tag = parser.get("tag").ok_or(Error::ParseFsTagMissing)?;
-        let socket = PathBuf::from(parser.get("socket").ok_or(Error::ParseFsSockMissing)?);
+        let fd = parser
+            .convert::<i32>("fd")
+            .map_err(Error::ParseFileSystem)?;
+        let socket = match parser.get("socket") {
+            Some(socket) => PathBuf::from(socket),
+            None if fd.is_some() => PathBuf::new(),
+            None => return Err(Error::ParseFsSockMissing),
+        };
 
         let queue_size = parser
             .convert("queue_size")
@@ -1239,13 +2902,21 @@ impl FsConfig {
             .map_err(Error::ParseFileSystem)?
             .unwrap_or_default();
 
+        let reconnect = parser
+            .convert::<Toggle>("reconnect")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or(Toggle(default_fsconfig_reconnect()))
+            .0;
+
         Ok(FsConfig {
             tag,
             socket,
+            fd,
             num_queues,
             queue_size,
             id,
             pci_segment,
+            reconnect,
         })
     }
 
@@ -1254,6 +2925,13 @@ impl FsConfig {
             return Err(ValidationError::TooManyQueues);
         }
 
+        match (self.socket.as_os_str().is_empty(), self.fd) {
+            (true, None) => return Err(ValidationError::FsSocketMissing),
+            (false, Some(_)) => return Err(ValidationError::FsSocketAndFd),
+            (true, Some(fd)) if fd <= 2 => return Err(ValidationError::FsReservedFd),
+            _ => {}
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1281,7 +2959,8 @@ impl PmemConfig {
             .add("iommu")
             .add("discard_writes")
             .add("id")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("mem_policy");
         parser.parse(pmem).map_err(Error::ParsePersistentMemory)?;
 
         let file = PathBuf::from(parser.get("file").ok_or(Error::ParsePmemFileMissing)?);
@@ -1304,6 +2983,9 @@ impl PmemConfig {
             .convert("pci_segment")
             .map_err(Error::ParsePersistentMemory)?
             .unwrap_or_default();
+        let mem_policy = parser
+            .convert("mem_policy")
+            .map_err(Error::ParsePersistentMemory)?;
 
         Ok(PmemConfig {
             file,
@@ -1312,10 +2994,15 @@ impl PmemConfig {
             discard_writes,
             id,
             pci_segment,
+            mem_policy,
         })
     }
 
     pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.discard_writes && self.mem_policy == Some(PmemMemPolicy::Shared) {
+            return Err(ValidationError::PmemDiscardWritesRequiresPrivate);
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1334,6 +3021,16 @@ impl PmemConfig {
 
 impl ConsoleConfig {
     pub fn parse(console: &str) -> Result<Self> {
+        Self::parse_device(console, true)
+    }
+
+    /// Same as `parse`, without the options only supported by the virtio
+    /// console.
+    pub fn parse_serial(serial: &str) -> Result<Self> {
+        Self::parse_device(serial, false)
+    }
+
+    fn parse_device(console: &str, virtio: bool) -> Result<Self> {
         let mut parser = OptionParser::new();
         parser
             .add_valueless("off")
@@ -1341,7 +3038,17 @@ impl ConsoleConfig {
             .add_valueless("tty")
             .add_valueless("null")
             .add("file")
-            .add("iommu");
+            .add("iommu")
+            .add("input")
+            .add("input_delay")
+            .add("input_pace")
+            .add("rotate")
+            .add("keep")
+            .add("flow_control")
+            .add("timestamps");
+        if virtio {
+            parser.add("resize");
+        }
         parser.parse(console).map_err(Error::ParseConsole)?;
 
         let mut file: Option<PathBuf> = default_consoleconfig_file();
@@ -1368,8 +3075,63 @@ impl ConsoleConfig {
             .map_err(Error::ParseConsole)?
             .unwrap_or(Toggle(false))
             .0;
+        let input = if let Some(path) = parser.get("input") {
+            let delay = parser
+                .convert("input_delay")
+                .map_err(Error::ParseConsole)?
+                .unwrap_or_default();
+            let pace = parser
+                .convert("input_pace")
+                .map_err(Error::ParseConsole)?
+                .unwrap_or_default();
+            Some(ConsoleInputConfig {
+                path: PathBuf::from(path),
+                delay,
+                pace,
+            })
+        } else {
+            None
+        };
+
+        let resize = parser
+            .convert::<Toggle>("resize")
+            .map_err(Error::ParseConsole)?
+            .unwrap_or(Toggle(default_consoleconfig_resize()))
+            .0;
+
+        let rotate = if let Some(size) = parser
+            .convert::<ByteSized>("rotate")
+            .map_err(Error::ParseConsole)?
+        {
+            let keep = parser
+                .convert("keep")
+                .map_err(Error::ParseConsole)?
+                .unwrap_or_else(default_consolerotateconfig_keep);
+            Some(ConsoleRotateConfig { size: size.0, keep })
+        } else {
+            None
+        };
+
+        let flow_control = parser
+            .convert("flow_control")
+            .map_err(Error::ParseConsole)?
+            .unwrap_or_default();
+        let timestamps = parser
+            .convert::<Toggle>("timestamps")
+            .map_err(Error::ParseConsole)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        Ok(Self { file, mode, iommu })
+        Ok(Self {
+            file,
+            mode,
+            iommu,
+            input,
+            resize,
+            rotate,
+            flow_control,
+            timestamps,
+        })
     }
 }
 
@@ -1517,28 +3279,106 @@ impl VdpaConfig {
     }
 }
 
-impl VsockConfig {
-    pub fn parse(vsock: &str) -> Result<Self> {
+impl ScsiConfig {
+    pub fn parse(scsi: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
         parser
-            .add("socket")
-            .add("cid")
+            .add("disks")
+            .add("readonly")
             .add("iommu")
+            .add("queue_size")
             .add("id")
             .add("pci_segment");
-        parser.parse(vsock).map_err(Error::ParseVsock)?;
-
-        let socket = parser
-            .get("socket")
+        parser.parse(scsi).map_err(Error::ParseScsi)?;
+
+        let disks = parser
+            .convert::<StringList>("disks")
+            .map_err(Error::ParseScsi)?
+            .ok_or(Error::ParseScsiDisksMissing)?
+            .0
+            .into_iter()
             .map(PathBuf::from)
-            .ok_or(Error::ParseVsockSockMissing)?;
+            .collect();
+        let readonly = parser
+            .convert::<Toggle>("readonly")
+            .map_err(Error::ParseScsi)?
+            .unwrap_or(Toggle(false))
+            .0;
         let iommu = parser
             .convert::<Toggle>("iommu")
-            .map_err(Error::ParseVsock)?
+            .map_err(Error::ParseScsi)?
             .unwrap_or(Toggle(false))
             .0;
-        let cid = parser
-            .convert("cid")
+        let queue_size = parser
+            .convert("queue_size")
+            .map_err(Error::ParseScsi)?
+            .unwrap_or_else(default_scsiconfig_queue_size);
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseScsi)?
+            .unwrap_or_default();
+
+        Ok(ScsiConfig {
+            disks,
+            readonly,
+            iommu,
+            queue_size,
+            id,
+            pci_segment,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.disks.is_empty() || self.disks.len() > virtio_devices::SCSI_MAX_LUNS {
+            return Err(ValidationError::InvalidScsiDiskCount(self.disks.len()));
+        }
+
+        if !self.queue_size.is_power_of_two()
+            || self.queue_size < MIN_SCSI_QUEUE_SIZE
+            || self.queue_size > virtio_devices::net::MAX_QUEUE_SIZE
+        {
+            return Err(ValidationError::InvalidScsiQueueSize(self.queue_size));
+        }
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl VsockConfig {
+    pub fn parse(vsock: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("cid")
+            .add("iommu")
+            .add("id")
+            .add("pci_segment");
+        parser.parse(vsock).map_err(Error::ParseVsock)?;
+
+        let socket = parser
+            .get("socket")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseVsockSockMissing)?;
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseVsock)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let cid = parser
+            .convert("cid")
             .map_err(Error::ParseVsock)?
             .ok_or(Error::ParseVsockCidMissing)?;
         let id = parser.get("id");
@@ -1614,7 +3454,7 @@ impl NumaConfig {
         let cpus = parser
             .convert::<IntegerList>("cpus")
             .map_err(Error::ParseNuma)?
-            .map(|v| v.0.iter().map(|e| *e as u8).collect());
+            .map(|v| v.0.iter().map(|e| *e as u32).collect());
         let distances = parser
             .convert::<Tuple<u64, u64>>("distances")
             .map_err(Error::ParseNuma)?
@@ -1709,13 +3549,14 @@ impl VmConfig {
     }
 
     pub fn backed_by_shared_memory(&self) -> bool {
-        if self.memory.shared || self.memory.hugepages {
+        let memfd = self.memory.backend == Some(MemoryBackend::Memfd);
+        if self.memory.shared || self.memory.hugepages || (memfd && self.memory.size != 0) {
             return true;
         }
 
         if self.memory.size == 0 {
             for zone in self.memory.zones.as_ref().unwrap() {
-                if !zone.shared && !zone.hugepages {
+                if !zone.shared && !zone.hugepages && !(memfd && zone.file.is_none()) {
                     return false;
                 }
             }
@@ -1747,6 +3588,28 @@ impl VmConfig {
             }
         }
 
+        // The vCPUs state and the guest memory are encrypted when the VM is
+        // launched, which rules out adding any of them later on.
+        #[cfg(feature = "sev")]
+        if self.is_sev_es_enabled() {
+            if self.payload.as_ref().unwrap().firmware.is_none() {
+                return Err(ValidationError::SevEsFirmwareMissing);
+            }
+            if self.cpus.max_vcpus != self.cpus.boot_vcpus {
+                return Err(ValidationError::SevEsUnsupported("CPU hotplug"));
+            }
+            if self.memory.hotplug_size.is_some()
+                || self
+                    .memory
+                    .zones
+                    .as_ref()
+                    .map(|zones| zones.iter().any(|zone| zone.hotplug_size.is_some()))
+                    .unwrap_or(false)
+            {
+                return Err(ValidationError::SevEsUnsupported("memory hotplug"));
+            }
+        }
+
         if self.console.mode == ConsoleOutputMode::Tty && self.serial.mode == ConsoleOutputMode::Tty
         {
             return Err(ValidationError::DoubleTtyMode);
@@ -1760,6 +3623,38 @@ impl VmConfig {
             return Err(ValidationError::ConsoleFileMissing);
         }
 
+        for console in [&self.console, &self.serial] {
+            if console.input.is_some()
+                && !matches!(
+                    console.mode,
+                    ConsoleOutputMode::File | ConsoleOutputMode::Tty
+                )
+            {
+                return Err(ValidationError::ConsoleInputInvalidMode);
+            }
+
+            if console.rotate.is_some() && console.mode != ConsoleOutputMode::File {
+                return Err(ValidationError::ConsoleRotateInvalidMode);
+            }
+
+            if console.timestamps && console.mode != ConsoleOutputMode::File {
+                return Err(ValidationError::ConsoleTimestampsInvalidMode);
+            }
+        }
+
+        if self.console.flow_control != FlowControl::None {
+            return Err(ValidationError::FlowControlUnsupported);
+        }
+
+        if !self.serial.resize {
+            return Err(ValidationError::SerialResizeUnsupported);
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        if self.serial.flow_control != FlowControl::None {
+            return Err(ValidationError::FlowControlUnsupported);
+        }
+
         if self.cpus.max_vcpus < self.cpus.boot_vcpus {
             return Err(ValidationError::CpusMaxLowerThanBoot);
         }
@@ -1769,6 +3664,9 @@ impl VmConfig {
                 if disk.vhost_socket.as_ref().and(disk.path.as_ref()).is_some() {
                     return Err(ValidationError::DiskSocketAndPath);
                 }
+                if disk.url.is_some() && (disk.path.is_some() || disk.vhost_socket.is_some()) {
+                    return Err(ValidationError::DiskUrlAndPath);
+                }
                 if disk.vhost_user && !self.backed_by_shared_memory() {
                     return Err(ValidationError::VhostUserRequiresSharedMemory);
                 }
@@ -1782,6 +3680,26 @@ impl VmConfig {
             }
         }
 
+        if let Some(scsi_devices) = &self.scsi {
+            for scsi in scsi_devices {
+                scsi.validate(self)?;
+                self.iommu |= scsi.iommu;
+
+                Self::validate_identifier(&mut id_list, &scsi.id)?;
+            }
+        }
+
+        if let Some(rate_limit_groups) = &self.rate_limit_groups {
+            let mut group_ids = BTreeSet::new();
+            for group in rate_limit_groups {
+                group.validate()?;
+
+                if !group_ids.insert(group.id.as_str()) {
+                    return Err(ValidationError::DuplicateRateLimitGroup(group.id.clone()));
+                }
+            }
+        }
+
         if let Some(nets) = &self.net {
             for net in nets {
                 if net.vhost_user && !self.backed_by_shared_memory() {
@@ -1815,6 +3733,11 @@ impl VmConfig {
         }
 
         self.iommu |= self.rng.iommu;
+        if !self.rng.queue_size.is_power_of_two()
+            || self.rng.queue_size > virtio_devices::net::MAX_QUEUE_SIZE
+        {
+            return Err(ValidationError::InvalidQueueSize(self.rng.queue_size));
+        }
         self.iommu |= self.console.iommu;
 
         if let Some(t) = &self.cpus.topology {
@@ -1834,12 +3757,63 @@ impl VmConfig {
                 return Err(ValidationError::CpuTopologyDiesPerPackage);
             }
 
-            let total = t.threads_per_core * t.cores_per_die * t.dies_per_package * t.packages;
+            let total = u32::from(t.threads_per_core)
+                * u32::from(t.cores_per_die)
+                * u32::from(t.dies_per_package)
+                * u32::from(t.packages);
             if total != self.cpus.max_vcpus {
                 return Err(ValidationError::CpuTopologyCount);
             }
         }
 
+        // KVM takes the TSC frequency in kHz, as a 32 bits value.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(tsc_freq) = self.cpus.tsc_freq {
+            if tsc_freq / 1000 == 0 || tsc_freq / 1000 > u64::from(u32::MAX) {
+                return Err(ValidationError::InvalidTscFrequency(tsc_freq));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        if self.cpus.tsc_freq.is_some() {
+            return Err(ValidationError::TscFrequencyUnsupported);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(stable_id) = &self.cpus.stable_id {
+            stable_id.validate()?;
+        }
+        #[cfg(target_arch = "aarch64")]
+        if self.cpus.stable_id.is_some() {
+            return Err(ValidationError::CpuStableIdUnsupported);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if !self.cpus.hypervisor_cpuid {
+            if self.cpus.kvm_hyperv {
+                return Err(ValidationError::HiddenHypervisorCpuid("kvm_hyperv"));
+            }
+            if self.cpus.features.kvm_pv_features.values().any(|e| *e) {
+                return Err(ValidationError::HiddenHypervisorCpuid(
+                    "enabling KVM PV features",
+                ));
+            }
+            if self.cpus.max_vcpus > 255 {
+                return Err(ValidationError::HiddenHypervisorCpuid(
+                    "more than 255 vCPUs",
+                ));
+            }
+        }
+        // APIC ids above 255 are advertised to the guest through the KVM
+        // extended destination ID feature.
+        #[cfg(target_arch = "x86_64")]
+        if self.cpus.kvm_hyperv && self.cpus.max_vcpus > 255 {
+            return Err(ValidationError::KvmHypervTooManyVcpus);
+        }
+        #[cfg(target_arch = "aarch64")]
+        if !self.cpus.hypervisor_cpuid {
+            return Err(ValidationError::HiddenHypervisorCpuidUnsupported);
+        }
+
         if let Some(hugepage_size) = &self.memory.hugepage_size {
             if !self.memory.hugepages {
                 return Err(ValidationError::HugePageSizeWithoutHugePages);
@@ -1849,6 +3823,20 @@ impl VmConfig {
             }
         }
 
+        if self.memory.prealloc_threads == Some(0) {
+            return Err(ValidationError::InvalidPreallocThreads);
+        }
+
+        if let Some(rom) = &self.memory.readonly_rom {
+            if rom.address & (arch::PAGE_SIZE as u64 - 1) != 0 {
+                return Err(ValidationError::InvalidReadonlyRomAddress(rom.address));
+            }
+        }
+
+        if self.memory.numa_bind.is_some() && self.memory.size == 0 {
+            return Err(ValidationError::NumaBindWithMemoryZones);
+        }
+
         if let Some(user_devices) = &self.user_devices {
             if !user_devices.is_empty() && !self.backed_by_shared_memory() {
                 return Err(ValidationError::UserDevicesRequireSharedMemory);
@@ -1943,6 +3931,40 @@ impl VmConfig {
             }
         }
 
+        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+        if self.watchdog_coredump.is_some() && !self.watchdog {
+            return Err(ValidationError::WatchdogCoredumpWithoutWatchdog);
+        }
+
+        #[cfg(not(all(target_arch = "x86_64", feature = "guest_debug")))]
+        if self.memory.dump_on_oom.is_some() {
+            return Err(ValidationError::DumpOnOomUnsupported);
+        }
+
+        if self.memory.file.is_some() {
+            if self.memory.backend.is_some() {
+                return Err(ValidationError::MemoryBackendWithFile);
+            }
+            if self.memory.size == 0 {
+                return Err(ValidationError::MemoryFileWithZones);
+            }
+            // Hotplugged memory isn't backed by the file, and would be mapped
+            // from it when restoring the VM.
+            if self.memory.hotplug_size.is_some() {
+                return Err(ValidationError::MemoryFileWithHotplug);
+            }
+            if self.memory.shared && self.memory.numa_bind.is_some() {
+                return Err(ValidationError::MemoryFileSharedWithNumaBind);
+            }
+        }
+
+        if let Some(max_ram) = self.memory.max_ram {
+            let size = self.memory.total_size();
+            if size > max_ram {
+                return Err(ValidationError::MemoryAboveMaxRam(size, max_ram));
+            }
+        }
+
         self.platform.as_ref().map(|p| p.validate()).transpose()?;
         self.iommu |= self
             .platform
@@ -1950,9 +3972,133 @@ impl VmConfig {
             .map(|p| p.iommu_segments.is_some())
             .unwrap_or_default();
 
+        if self.virtio_transport() == VirtioTransportType::Mmio {
+            self.validate_virtio_mmio()?;
+        }
+
+        if !self.is_acpi_enabled() {
+            self.validate_acpi_disabled()?;
+        }
+
         Ok(id_list)
     }
 
+    // The virtio-mmio devices are only described to the guest kernel, and
+    // they can't be placed behind the virtual IOMMU.
+    fn validate_virtio_mmio(&self) -> ValidationResult<()> {
+        // At this point we know payload isn't None.
+        if self.payload.as_ref().unwrap().firmware.is_some() {
+            return Err(ValidationError::MmioTransportUnsupported("a firmware"));
+        }
+
+        if self.iommu {
+            return Err(ValidationError::MmioTransportUnsupported("a virtual IOMMU"));
+        }
+
+        if self.vdpa.as_ref().map(|v| !v.is_empty()).unwrap_or(false) {
+            return Err(ValidationError::MmioTransportUnsupported("vDPA devices"));
+        }
+
+        if self
+            .net
+            .as_ref()
+            .map(|net| net.iter().any(|n| n.host_name.is_some()))
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::MmioTransportUnsupported(
+                "network device labels",
+            ));
+        }
+
+        // The queues can't notify the guest through an eventfd of their own.
+        if self
+            .net
+            .as_ref()
+            .map(|net| net.iter().any(|n| n.vhost))
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::MmioTransportUnsupported("vhost-net"));
+        }
+
+        Ok(())
+    }
+
+    // Without ACPI, devices are described to the guest through the kernel
+    // command line or the device tree, which leaves out any feature relying
+    // on ACPI for discovery or notification.
+    fn validate_acpi_disabled(&self) -> ValidationResult<()> {
+        #[cfg(feature = "tdx")]
+        if self.is_tdx_enabled() {
+            return Err(ValidationError::AcpiDisabledUnsupported("TDX"));
+        }
+
+        if self.cpus.max_vcpus != self.cpus.boot_vcpus {
+            return Err(ValidationError::AcpiDisabledUnsupported("CPU hotplug"));
+        }
+
+        if self.memory.hotplug_size.is_some()
+            || self
+                .memory
+                .zones
+                .as_ref()
+                .map(|zones| zones.iter().any(|zone| zone.hotplug_size.is_some()))
+                .unwrap_or(false)
+        {
+            return Err(ValidationError::AcpiDisabledUnsupported("memory hotplug"));
+        }
+
+        if self.tpm.is_some() {
+            return Err(ValidationError::AcpiDisabledUnsupported("a TPM device"));
+        }
+
+        if self
+            .platform
+            .as_ref()
+            .map(|p| p.num_pci_segments > 1)
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::AcpiDisabledUnsupported(
+                "multiple PCI segments",
+            ));
+        }
+
+        if self
+            .net
+            .as_ref()
+            .map(|net| net.iter().any(|n| n.host_name.is_some()))
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::AcpiDisabledUnsupported(
+                "network device labels",
+            ));
+        }
+
+        if self
+            .platform
+            .as_ref()
+            .map(|p| p.dsdt.is_some())
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::AcpiDisabledUnsupported("a custom DSDT"));
+        }
+
+        Ok(())
+    }
+
+    /// Deserialize a VM configuration from JSON, as written by the
+    /// `vm.config` API or along with a snapshot.
+    pub fn from_json(json: &str) -> result::Result<Self, ConfigError> {
+        serde_json::from_str(json).map_err(ConfigError::from)
+    }
+
+    /// Read a VM configuration from a JSON file, reporting the line and the
+    /// column of the error when it is malformed.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| Error::ReadConfigFile(path.to_path_buf(), e))?;
+        Self::from_json(&json).map_err(|e| Error::ParseConfigFile(path.to_path_buf(), e))
+    }
+
     pub fn parse(vm_params: VmParams) -> Result<Self> {
         let mut disks: Option<Vec<DiskConfig>> = None;
         if let Some(disk_list) = &vm_params.disks {
@@ -1974,6 +4120,16 @@ impl VmConfig {
             net = Some(net_config_list);
         }
 
+        let mut rate_limit_groups: Option<Vec<RateLimitGroupConfig>> = None;
+        if let Some(rate_limit_group_list) = &vm_params.rate_limit_groups {
+            let mut rate_limit_group_config_list = Vec::new();
+            for item in rate_limit_group_list.iter() {
+                let rate_limit_group_config = RateLimitGroupConfig::parse(item)?;
+                rate_limit_group_config_list.push(rate_limit_group_config);
+            }
+            rate_limit_groups = Some(rate_limit_group_config_list);
+        }
+
         let rng = RngConfig::parse(vm_params.rng)?;
 
         let mut balloon: Option<BalloonConfig> = None;
@@ -2001,7 +4157,7 @@ impl VmConfig {
         }
 
         let console = ConsoleConfig::parse(vm_params.console)?;
-        let serial = ConsoleConfig::parse(vm_params.serial)?;
+        let serial = ConsoleConfig::parse_serial(vm_params.serial)?;
 
         let mut devices: Option<Vec<DeviceConfig>> = None;
         if let Some(device_list) = &vm_params.devices {
@@ -2033,6 +4189,16 @@ impl VmConfig {
             vdpa = Some(vdpa_config_list);
         }
 
+        let mut scsi: Option<Vec<ScsiConfig>> = None;
+        if let Some(scsi_list) = &vm_params.scsi {
+            let mut scsi_config_list = Vec::new();
+            for item in scsi_list.iter() {
+                let scsi_config = ScsiConfig::parse(item)?;
+                scsi_config_list.push(scsi_config);
+            }
+            scsi = Some(scsi_config_list);
+        }
+
         let mut vsock: Option<VsockConfig> = None;
         if let Some(vs) = &vm_params.vsock {
             let vsock_config = VsockConfig::parse(vs)?;
@@ -2068,8 +4234,11 @@ impl VmConfig {
         let payload = if vm_params.kernel.is_some() || vm_params.firmware.is_some() {
             Some(PayloadConfig {
                 kernel: vm_params.kernel.map(PathBuf::from),
-                initramfs: vm_params.initramfs.map(PathBuf::from),
-                cmdline: vm_params.cmdline.map(|s| s.to_string()),
+                initramfs: vm_params
+                    .initramfs
+                    .map(|paths| paths.into_iter().map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                cmdline: vm_params.cmdline.map(parse_cmdline).transpose()?,
                 firmware: vm_params.firmware.map(PathBuf::from),
             })
         } else {
@@ -2093,6 +4262,7 @@ impl VmConfig {
             payload,
             disks,
             net,
+            rate_limit_groups,
             rng,
             balloon,
             fs,
@@ -2102,6 +4272,7 @@ impl VmConfig {
             devices,
             user_devices,
             vdpa,
+            scsi,
             vsock,
             pvpanic: vm_params.pvpanic,
             iommu: false, // updated in VmConfig::validate()
@@ -2109,6 +4280,8 @@ impl VmConfig {
             sgx_epc,
             numa,
             watchdog: vm_params.watchdog,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            watchdog_coredump: vm_params.watchdog_coredump.map(PathBuf::from),
             #[cfg(feature = "guest_debug")]
             gdb,
             platform,
@@ -2119,6 +4292,11 @@ impl VmConfig {
         Ok(config)
     }
 
+    /// Returns a builder for constructing a `VmConfig` programmatically.
+    pub fn builder() -> VmConfigBuilder {
+        VmConfigBuilder::new()
+    }
+
     pub fn remove_device(&mut self, id: &str) -> bool {
         let mut removed = false;
 
@@ -2171,6 +4349,13 @@ impl VmConfig {
             removed |= vdpa.len() != len;
         }
 
+        // Remove if virtio-scsi device
+        if let Some(scsi) = self.scsi.as_mut() {
+            let len = scsi.len();
+            scsi.retain(|dev| dev.id.as_ref().map(|id| id.as_ref()) != Some(id));
+            removed |= scsi.len() != len;
+        }
+
         // Remove if vsock device
         if let Some(vsock) = self.vsock.as_ref() {
             if vsock.id.as_ref().map(|id| id.as_ref()) == Some(id) {
@@ -2202,49 +4387,372 @@ impl VmConfig {
         self.platform.as_ref().map(|p| p.tdx).unwrap_or(false)
     }
 
+    pub fn is_acpi_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.acpi).unwrap_or(true)
+    }
+
+    /// Returns the transport used by the virtio devices. Without ACPI, the
+    /// virtio-pci devices can't be described to the guest, hence the
+    /// virtio-mmio transport is always used.
+    pub fn virtio_transport(&self) -> VirtioTransportType {
+        if !self.is_acpi_enabled() {
+            return VirtioTransportType::Mmio;
+        }
+        self.platform
+            .as_ref()
+            .map(|p| p.transport)
+            .unwrap_or_default()
+    }
+
+    /// Checks the state of the VM can be saved, before a snapshot or a
+    /// migration gets started.
+    pub fn validate_migratable(&self) -> ValidationResult<()> {
+        // The virtio-mmio transport doesn't save the state of its devices.
+        if self.virtio_transport() == VirtioTransportType::Mmio {
+            return Err(ValidationError::NotMigratable("the virtio-mmio transport"));
+        }
+
+        // The overlay is gone once unlinked, there's nothing to restore from.
+        if self
+            .disks
+            .as_ref()
+            .map(|disks| disks.iter().any(|d| d.overlay_discard))
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::NotMigratable("overlay_discard"));
+        }
+
+        // The state of the emulated zones only lives in memory.
+        if self
+            .disks
+            .as_ref()
+            .map(|disks| disks.iter().any(|d| d.zoned))
+            .unwrap_or(false)
+        {
+            return Err(ValidationError::NotMigratable("zoned disks"));
+        }
+
+        Ok(())
+    }
+
     #[cfg(feature = "sev_snp")]
     pub fn is_sev_snp_enabled(&self) -> bool {
         self.platform.as_ref().map(|p| p.sev_snp).unwrap_or(false)
     }
+
+    #[cfg(feature = "sev")]
+    pub fn is_sev_es_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.sev_es).unwrap_or(false)
+    }
 }
 
-impl Clone for VmConfig {
-    fn clone(&self) -> Self {
-        VmConfig {
-            cpus: self.cpus.clone(),
-            memory: self.memory.clone(),
-            payload: self.payload.clone(),
-            disks: self.disks.clone(),
-            net: self.net.clone(),
-            rng: self.rng.clone(),
-            balloon: self.balloon.clone(),
-            fs: self.fs.clone(),
-            pmem: self.pmem.clone(),
-            serial: self.serial.clone(),
-            console: self.console.clone(),
-            devices: self.devices.clone(),
-            user_devices: self.user_devices.clone(),
-            vdpa: self.vdpa.clone(),
-            vsock: self.vsock.clone(),
-            #[cfg(target_arch = "x86_64")]
-            sgx_epc: self.sgx_epc.clone(),
-            numa: self.numa.clone(),
-            platform: self.platform.clone(),
-            tpm: self.tpm.clone(),
-            preserved_fds: self
-                .preserved_fds
-                .as_ref()
-                // SAFETY: FFI call with valid FDs
-                .map(|fds| fds.iter().map(|fd| unsafe { libc::dup(*fd) }).collect()),
-            ..*self
-        }
+/// Builder for constructing a `VmConfig` out of the typed configurations,
+/// as an alternative to parsing the command line syntax.
+///
+/// The values not explicitly set are the same defaults as the ones applied
+/// by `VmConfig::parse()`, and the configuration is validated by `build()`.
+///
+/// # Examples
+///
+/// Booting a VM from a kernel and a disk image, through the API of a VMM
+/// thread started from the same process:
+///
+/// ```no_run
+/// # use seccompiler::SeccompAction;
+/// # use std::sync::mpsc::channel;
+/// # use std::sync::{Arc, Mutex};
+/// # use std::time::Duration;
+/// # use vmm_sys_util::eventfd::{EventFd, EFD_NONBLOCK};
+/// use vmm::config::{DiskConfig, VmConfig};
+///
+/// let config = VmConfig::builder()
+///     .cpus(2)
+///     .memory_mb(1024)
+///     .kernel("/path/to/vmlinux")
+///     .cmdline("console=hvc0 root=/dev/vda1 rw")
+///     .disk(DiskConfig {
+///         path: Some("/path/to/disk.raw".into()),
+///         ..Default::default()
+///     })
+///     .build()
+///     .unwrap();
+///
+/// let (api_sender, api_receiver) = channel();
+/// let api_evt = EventFd::new(EFD_NONBLOCK).unwrap();
+/// let exit_evt = EventFd::new(EFD_NONBLOCK).unwrap();
+/// let vmm = vmm::start_vmm_thread(
+///     vmm::VmmVersionInfo::new("example", "example"),
+///     &None,
+///     None,
+///     #[cfg(feature = "dbus_api")]
+///     None,
+///     api_evt.try_clone().unwrap(),
+///     api_sender.clone(),
+///     api_receiver,
+///     #[cfg(feature = "guest_debug")]
+///     None,
+///     #[cfg(feature = "guest_debug")]
+///     EventFd::new(EFD_NONBLOCK).unwrap(),
+///     #[cfg(feature = "guest_debug")]
+///     EventFd::new(EFD_NONBLOCK).unwrap(),
+///     exit_evt,
+///     Duration::from_secs(30),
+///     &SeccompAction::Trap,
+///     hypervisor::new().unwrap(),
+/// )
+/// .unwrap();
+///
+/// vmm::api::vm_create(
+///     api_evt.try_clone().unwrap(),
+///     api_sender.clone(),
+///     Arc::new(Mutex::new(config)),
+/// )
+/// .unwrap();
+/// vmm::api::vm_boot(api_evt, api_sender).unwrap();
+///
+/// // Wait for the VM to shut down.
+/// vmm.thread_handle.join().unwrap().unwrap();
+/// ```
+pub struct VmConfigBuilder {
+    config: VmConfig,
+}
+
+impl Default for VmConfigBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl Drop for VmConfig {
-    fn drop(&mut self) {
-        if let Some(mut fds) = self.preserved_fds.take() {
-            for fd in fds.drain(..) {
+impl VmConfigBuilder {
+    pub fn new() -> Self {
+        VmConfigBuilder {
+            config: VmConfig {
+                cpus: CpusConfig::default(),
+                memory: MemoryConfig::default(),
+                payload: None,
+                disks: None,
+                net: None,
+                rate_limit_groups: None,
+                rng: RngConfig::default(),
+                balloon: None,
+                fs: None,
+                pmem: None,
+                serial: default_serial(),
+                console: default_console(),
+                devices: None,
+                user_devices: None,
+                vdpa: None,
+                scsi: None,
+                vsock: None,
+                pvpanic: false,
+                iommu: false,
+                #[cfg(target_arch = "x86_64")]
+                sgx_epc: None,
+                numa: None,
+                watchdog: false,
+                #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+                watchdog_coredump: None,
+                #[cfg(feature = "guest_debug")]
+                gdb: false,
+                platform: None,
+                tpm: None,
+                preserved_fds: None,
+            },
+        }
+    }
+
+    /// Number of vCPUs, both at boot time and at most.
+    pub fn cpus(mut self, vcpus: u32) -> Self {
+        self.config.cpus.boot_vcpus = vcpus;
+        self.config.cpus.max_vcpus = vcpus;
+        self
+    }
+
+    pub fn cpus_config(mut self, cpus: CpusConfig) -> Self {
+        self.config.cpus = cpus;
+        self
+    }
+
+    /// Size of the guest RAM in MiB.
+    pub fn memory_mb(mut self, size: u64) -> Self {
+        self.config.memory.size = size << 20;
+        self
+    }
+
+    pub fn memory_config(mut self, memory: MemoryConfig) -> Self {
+        self.config.memory = memory;
+        self
+    }
+
+    fn payload(&mut self) -> &mut PayloadConfig {
+        self.config.payload.get_or_insert_with(Default::default)
+    }
+
+    pub fn kernel<P: Into<PathBuf>>(mut self, kernel: P) -> Self {
+        self.payload().kernel = Some(kernel.into());
+        self
+    }
+
+    pub fn firmware<P: Into<PathBuf>>(mut self, firmware: P) -> Self {
+        self.payload().firmware = Some(firmware.into());
+        self
+    }
+
+    pub fn cmdline<S: Into<String>>(mut self, cmdline: S) -> Self {
+        self.payload().cmdline = Some(cmdline.into());
+        self
+    }
+
+    pub fn initramfs<P: Into<PathBuf>>(mut self, initramfs: P) -> Self {
+        self.payload().initramfs.push(initramfs.into());
+        self
+    }
+
+    pub fn disk(mut self, disk: DiskConfig) -> Self {
+        self.config.disks.get_or_insert_with(Vec::new).push(disk);
+        self
+    }
+
+    pub fn net(mut self, net: NetConfig) -> Self {
+        self.config.net.get_or_insert_with(Vec::new).push(net);
+        self
+    }
+
+    pub fn fs(mut self, fs: FsConfig) -> Self {
+        self.config.fs.get_or_insert_with(Vec::new).push(fs);
+        self
+    }
+
+    pub fn pmem(mut self, pmem: PmemConfig) -> Self {
+        self.config.pmem.get_or_insert_with(Vec::new).push(pmem);
+        self
+    }
+
+    pub fn device(mut self, device: DeviceConfig) -> Self {
+        self.config
+            .devices
+            .get_or_insert_with(Vec::new)
+            .push(device);
+        self
+    }
+
+    pub fn user_device(mut self, user_device: UserDeviceConfig) -> Self {
+        self.config
+            .user_devices
+            .get_or_insert_with(Vec::new)
+            .push(user_device);
+        self
+    }
+
+    pub fn vdpa(mut self, vdpa: VdpaConfig) -> Self {
+        self.config.vdpa.get_or_insert_with(Vec::new).push(vdpa);
+        self
+    }
+
+    pub fn scsi(mut self, scsi: ScsiConfig) -> Self {
+        self.config.scsi.get_or_insert_with(Vec::new).push(scsi);
+        self
+    }
+
+    pub fn numa(mut self, numa: NumaConfig) -> Self {
+        self.config.numa.get_or_insert_with(Vec::new).push(numa);
+        self
+    }
+
+    pub fn rng(mut self, rng: RngConfig) -> Self {
+        self.config.rng = rng;
+        self
+    }
+
+    pub fn balloon(mut self, balloon: BalloonConfig) -> Self {
+        self.config.balloon = Some(balloon);
+        self
+    }
+
+    pub fn vsock(mut self, vsock: VsockConfig) -> Self {
+        self.config.vsock = Some(vsock);
+        self
+    }
+
+    pub fn serial(mut self, serial: ConsoleConfig) -> Self {
+        self.config.serial = serial;
+        self
+    }
+
+    pub fn console(mut self, console: ConsoleConfig) -> Self {
+        self.config.console = console;
+        self
+    }
+
+    pub fn platform(mut self, platform: PlatformConfig) -> Self {
+        self.config.platform = Some(platform);
+        self
+    }
+
+    pub fn tpm(mut self, tpm: TpmConfig) -> Self {
+        self.config.tpm = Some(tpm);
+        self
+    }
+
+    pub fn pvpanic(mut self, pvpanic: bool) -> Self {
+        self.config.pvpanic = pvpanic;
+        self
+    }
+
+    pub fn watchdog(mut self, watchdog: bool) -> Self {
+        self.config.watchdog = watchdog;
+        self
+    }
+
+    /// Validates the configuration, which also enables the virtio-iommu if
+    /// any of the devices needs it.
+    pub fn build(mut self) -> ValidationResult<VmConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+impl Clone for VmConfig {
+    fn clone(&self) -> Self {
+        VmConfig {
+            cpus: self.cpus.clone(),
+            memory: self.memory.clone(),
+            payload: self.payload.clone(),
+            disks: self.disks.clone(),
+            net: self.net.clone(),
+            rate_limit_groups: self.rate_limit_groups.clone(),
+            rng: self.rng.clone(),
+            balloon: self.balloon.clone(),
+            fs: self.fs.clone(),
+            pmem: self.pmem.clone(),
+            serial: self.serial.clone(),
+            console: self.console.clone(),
+            devices: self.devices.clone(),
+            user_devices: self.user_devices.clone(),
+            vdpa: self.vdpa.clone(),
+            scsi: self.scsi.clone(),
+            vsock: self.vsock.clone(),
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc: self.sgx_epc.clone(),
+            numa: self.numa.clone(),
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            watchdog_coredump: self.watchdog_coredump.clone(),
+            platform: self.platform.clone(),
+            tpm: self.tpm.clone(),
+            preserved_fds: self
+                .preserved_fds
+                .as_ref()
+                // SAFETY: FFI call with valid FDs
+                .map(|fds| fds.iter().map(|fd| unsafe { libc::dup(*fd) }).collect()),
+            ..*self
+        }
+    }
+}
+
+impl Drop for VmConfig {
+    fn drop(&mut self) {
+        if let Some(mut fds) = self.preserved_fds.take() {
+            for fd in fds.drain(..) {
                 // SAFETY: FFI call with valid FDs
                 unsafe { libc::close(fd) };
             }
@@ -2258,6 +4766,8 @@ mod tests {
     use net_util::MacAddr;
     use std::fs::File;
     use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::time::Duration;
 
     #[test]
     fn test_cpu_parsing() -> Result<()> {
@@ -2279,6 +4789,14 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            CpusConfig::parse("boot=300")?,
+            CpusConfig {
+                boot_vcpus: 300,
+                max_vcpus: 300,
+                ..Default::default()
+            }
+        );
         assert_eq!(
             CpusConfig::parse("boot=8,topology=2:2:1:2")?,
             CpusConfig {
@@ -2296,6 +4814,15 @@ mod tests {
 
         assert!(CpusConfig::parse("boot=8,topology=2:2:1").is_err());
         assert!(CpusConfig::parse("boot=8,topology=2:2:1:x").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=8,topology=host")?,
+            CpusConfig {
+                boot_vcpus: 8,
+                max_vcpus: 8,
+                host_topology: true,
+                ..Default::default()
+            }
+        );
         assert_eq!(
             CpusConfig::parse("boot=1,kvm_hyperv=on")?,
             CpusConfig {
@@ -2323,6 +4850,89 @@ mod tests {
                 ..Default::default()
             },
         );
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert_eq!(
+                CpusConfig::parse("boot=1,features=[kvm_pv_eoi=off,steal_time=on]")?,
+                CpusConfig {
+                    boot_vcpus: 1,
+                    max_vcpus: 1,
+                    features: CpuFeatures {
+                        kvm_pv_features: std::collections::BTreeMap::from([
+                            (KvmPvFeature::KvmPvEoi, false),
+                            (KvmPvFeature::StealTime, true),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+            assert!(CpusConfig::parse("boot=1,features=steal_time=maybe").is_err());
+            assert!(CpusConfig::parse("boot=1,features=unknown_pv=off").is_err());
+        }
+        assert_eq!(
+            CpusConfig::parse("boot=1,tsc_freq=2500000000")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                tsc_freq: Some(2_500_000_000),
+                ..Default::default()
+            },
+        );
+        assert!(CpusConfig::parse("boot=1,tsc_freq=2.5GHz").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=1,idle=poll")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                idle: Some(CpuIdle::Poll),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,idle=halt")?.idle,
+            Some(CpuIdle::Halt)
+        );
+        assert!(CpusConfig::parse("boot=1,idle=mwait").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=1,stable_id=on")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                stable_id: Some(CpuStableId::default()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(CpusConfig::parse("boot=1,stable_id=off")?.stable_id, None);
+        assert!(CpusConfig::parse("boot=1")?.hypervisor_cpuid);
+        assert!(!CpusConfig::parse("boot=1,hypervisor_cpuid=off")?.hypervisor_cpuid);
+        assert_eq!(
+            CpusConfig::parse("boot=1,lapic_timer=periodic")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                lapic_timer: Some(LapicTimer::Periodic),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,lapic_timer=tsc_deadline")?.lapic_timer,
+            Some(LapicTimer::TscDeadline)
+        );
+        assert!(CpusConfig::parse("boot=1,lapic_timer=oneshot").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=1,max_phys_bits=40")?.max_phys_bits,
+            40
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,max_phys_bits=auto")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                auto_phys_bits: true,
+                ..Default::default()
+            },
+        );
 
         Ok(())
     }
@@ -2350,6 +4960,15 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,file=/dev/shm/guest_ram,shared=on", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                file: Some(PathBuf::from("/dev/shm/guest_ram")),
+                shared: true,
+                ..Default::default()
+            }
+        );
         assert_eq!(
             MemoryConfig::parse("size=1G,mergeable=off", None)?,
             MemoryConfig {
@@ -2379,6 +4998,15 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=virtio-mem,hotplug_size=4G,max_ram=2G", None)?,
+            MemoryConfig {
+                hotplug_size: Some(4 << 30),
+                hotplug_method: HotplugMethod::VirtioMem,
+                max_ram: Some(2 << 30),
+                ..Default::default()
+            }
+        );
         assert_eq!(
             MemoryConfig::parse("hugepages=on,size=1G,hugepage_size=2M", None)?,
             MemoryConfig {
@@ -2388,6 +5016,61 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,prefault=on,prealloc_threads=4", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                prefault: true,
+                prealloc_threads: Some(4),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,readonly_rom=0xd0000000:/path/to/ipxe.rom", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                readonly_rom: Some(ReadonlyRomConfig {
+                    address: 0xd000_0000,
+                    file: PathBuf::from("/path/to/ipxe.rom"),
+                }),
+                ..Default::default()
+            }
+        );
+        assert!(MemoryConfig::parse("size=1G,readonly_rom=/path/to/ipxe.rom", None).is_err());
+        assert!(MemoryConfig::parse("size=1G,readonly_rom=0xd0000000:", None).is_err());
+        assert_eq!(
+            MemoryConfig::parse("size=1G,numa_bind=1", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                numa_bind: Some(1),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,zero_on_free=on", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                zero_on_free: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,dump_on_oom=/tmp/guest.core", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                dump_on_oom: Some(PathBuf::from("/tmp/guest.core")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,backend=memfd", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                backend: Some(MemoryBackend::Memfd),
+                ..Default::default()
+            }
+        );
+        assert!(MemoryConfig::parse("size=1G,backend=anonymous", None).is_err());
         Ok(())
     }
 
@@ -2473,80 +5156,421 @@ mod tests {
                 ..Default::default()
             }
         );
-        Ok(())
-    }
-
-    #[test]
-    fn test_net_parsing() -> Result<()> {
-        // mac address is random
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+            DiskConfig::parse("path=/path/to_file,wwn=0x5000c500a1b2c3d4")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                wwn: Some(String::from("0x5000c500a1b2c3d4")),
                 ..Default::default()
             }
         );
-
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,id=mynet0")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                id: Some("mynet0".to_owned()),
+            DiskConfig::parse("path=/path/to_file,segment_max=126,size_max=65536")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                segment_max: Some(126),
+                size_max: Some(65536),
                 ..Default::default()
             }
         );
-
         assert_eq!(
-            NetConfig::parse(
-                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,ip=192.168.100.1,mask=255.255.255.128"
-            )?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                tap: Some("tap0".to_owned()),
-                ip: "192.168.100.1".parse().unwrap(),
-                mask: "255.255.255.128".parse().unwrap(),
+            DiskConfig::parse("path=/path/to_file,cache=writethrough")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                cache: CacheMode::Writethrough,
                 ..Default::default()
             }
         );
-
         assert_eq!(
-            NetConfig::parse(
-                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,vhost_user=true,socket=/tmp/sock"
-            )?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                vhost_user: true,
-                vhost_socket: Some("/tmp/sock".to_owned()),
+            DiskConfig::parse("path=/path/to_file,cache=none")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                cache: CacheMode::None,
                 ..Default::default()
             }
         );
-
+        assert!(DiskConfig::parse("path=/path/to_file,cache=unsafe").is_err());
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,queue_size=1024,iommu=on")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                num_queues: 4,
-                queue_size: 1024,
-                iommu: true,
+            DiskConfig::parse("path=/path/to_file,direct=on,cache=none")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                direct: true,
+                cache: CacheMode::None,
                 ..Default::default()
             }
         );
-
+        assert!(matches!(
+            DiskConfig::parse("path=/path/to_file,direct=on,cache=writeback"),
+            Err(Error::Validation(ValidationError::DiskDirectCache))
+        ));
+        assert!(matches!(
+            DiskConfig::parse("vhost_user=on,socket=/tmp/sock,cache=writeback"),
+            Err(Error::Validation(ValidationError::DiskCacheVhostUser))
+        ));
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,fd=[3,7],num_queues=4")?,
+            DiskConfig::parse("path=/path/to_file.iso,readonly=on,media=cdrom")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file.iso")),
+                readonly: true,
+                media: DiskMedia::Cdrom,
+                ..Default::default()
+            }
+        );
+        let disk = DiskConfig::parse("path=@/path/to/dir,readonly=on")?;
+        assert_eq!(disk.directory(), Some(Path::new("/path/to/dir")));
+        assert_eq!(DiskConfig::parse("path=/path/to_file")?.directory(), None);
+        assert!(DiskConfig::parse("path=/path/to_file,media=floppy").is_err());
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,overlay=/path/to_overlay,overlay_discard=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                overlay: Some(PathBuf::from("/path/to_overlay")),
+                overlay_discard: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,poll_queue=on,poll_queue_us=100")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                poll_queue: true,
+                poll_queue_us: Some(100),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,poll_queue=on")?.poll_queue_window(),
+            Some(Duration::from_micros(DEFAULT_DISK_POLL_QUEUE_US))
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,sync_on_flush_interval=1000")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                sync_on_flush_interval: 1000,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,locking=none")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                locking: DiskLocking::None,
+                ..Default::default()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,locking=fcntl").is_err());
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,probe_alignment=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                probe_alignment: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("url=http://example.com/disk.raw,readonly=on,cache_mb=256")?,
+            DiskConfig {
+                url: Some("http://example.com/disk.raw".to_owned()),
+                readonly: true,
+                cache_mb: Some(256),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,zoned=on,zone_size=64M,conventional_zones=4")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                zoned: true,
+                zone_size: Some(64 << 20),
+                conventional_zones: Some(4),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,sync_on_flush_interval=1000")?.sync_interval(),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,sync_on_flush_interval=0")?.sync_interval(),
+            None
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,request_timeout=30000")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                request_timeout: 30000,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,request_timeout=30000")?
+                .request_timeout_duration(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file")?.request_timeout_duration(),
+            None
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,aio_max_inflight=16")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                aio_max_inflight: Some(16),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse(
+                "path=/path/to_file,bw_size=1000,bw_burst=500,bw_refill_time=100,ops_size=10,ops_refill_time=100"
+            )?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                rate_limiter_config: Some(RateLimiterConfig {
+                    bandwidth: Some(TokenBucketConfig {
+                        size: 1000,
+                        one_time_burst: Some(0),
+                        burst: Some(500),
+                        refill_time: 100,
+                    }),
+                    ops: Some(TokenBucketConfig {
+                        size: 10,
+                        one_time_burst: Some(0),
+                        burst: None,
+                        refill_time: 100,
+                    }),
+                }),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse(
+                "path=/path/to_file,throttle_iops_wr=100,throttle_bps_wr=1M,throttle_iops_rd=0"
+            )?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                write_rate_limiter_config: Some(RateLimiterConfig {
+                    bandwidth: Some(TokenBucketConfig {
+                        size: 1 << 20,
+                        one_time_burst: None,
+                        burst: None,
+                        refill_time: 1000,
+                    }),
+                    ops: Some(TokenBucketConfig {
+                        size: 100,
+                        one_time_burst: None,
+                        burst: None,
+                        refill_time: 1000,
+                    }),
+                }),
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_parsing() -> Result<()> {
+        // mac address is random
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,id=mynet0")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                id: Some("mynet0".to_owned()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,ip=192.168.100.1,mask=255.255.255.128"
+            )?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                tap: Some("tap0".to_owned()),
+                ip: "192.168.100.1".parse().unwrap(),
+                mask: Some("255.255.255.128".parse().unwrap()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            NetConfig::parse("tap=tap0,ip=fd00::1,mask=ffff:ffff:ffff:ffff::")?,
+            NetConfig {
+                tap: Some("tap0".to_owned()),
+                ip: "fd00::1".parse().unwrap(),
+                mask: Some("ffff:ffff:ffff:ffff::".parse().unwrap()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            NetConfig::parse("tap=tap0,ip=fd00::1")?.netmask(),
+            "ffff:ffff:ffff:ffff::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            NetConfig::parse("tap=tap0,ip=192.168.1.1")?.netmask(),
+            "255.255.255.0".parse::<IpAddr>().unwrap()
+        );
+        // Same defaults through the REST API.
+        assert_eq!(
+            serde_json::from_str::<NetConfig>(r#"{"ip": "fd00::1"}"#)
+                .unwrap()
+                .netmask(),
+            "ffff:ffff:ffff:ffff::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(NetConfig::parse("tap=@bridge:br0")?.bridge(), Some("br0"));
+        assert_eq!(NetConfig::parse("tap=tap0")?.bridge(), None);
+
+        assert_eq!(
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,vhost_user=true,socket=/tmp/sock"
+            )?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                vhost_user: true,
+                vhost_socket: Some("/tmp/sock".to_owned()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,queue_size=1024,iommu=on")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                num_queues: 4,
+                queue_size: 1024,
+                iommu: true,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,rx_queue_size=1024,tx_queue_size=128")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                rx_queue_size: Some(1024),
+                tx_queue_size: Some(128),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("vdpa=/dev/vhost-vdpa-0,num_queues=4")?,
+            NetConfig {
+                vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+                num_queues: 4,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,mtu=9000")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                mtu: Some(9000),
+                ..Default::default()
+            }
+        );
+        // The MTU is bounded by the 16 bits field of the config space.
+        assert!(NetConfig::parse("mac=de:ad:be:ef:12:34,mtu=65536").is_err());
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,promisc=off")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                promisc: false,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_name=lan0")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                host_name: Some("lan0".to_owned()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,fd=[3,7],num_queues=4")?,
             NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
                 fds: Some(vec![3, 7]),
                 num_queues: 4,
                 ..Default::default()
             }
         );
 
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,rate_limit_group=tenant0")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                rate_limit_group: Some("tenant0".to_owned()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,tap=tap0,vhost=on")?,
+            NetConfig {
+                mac: Some(MacAddr::parse_str("de:ad:be:ef:12:34").unwrap()),
+                tap: Some("tap0".to_owned()),
+                vhost: true,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rate_limit_group() -> Result<()> {
+        assert!(RateLimitGroupConfig::parse("").is_err());
+        assert!(RateLimitGroupConfig::parse("bw_size=1000,bw_refill_time=100").is_err());
+        assert_eq!(
+            RateLimitGroupConfig::parse("id=tenant0,bw_size=1000,bw_refill_time=100")?,
+            RateLimitGroupConfig {
+                id: "tenant0".to_owned(),
+                rate_limiter_config: RateLimiterConfig {
+                    bandwidth: Some(TokenBucketConfig {
+                        size: 1000,
+                        one_time_burst: Some(0),
+                        burst: None,
+                        refill_time: 100,
+                    }),
+                    ops: None,
+                },
+            }
+        );
+        assert_eq!(
+            RateLimitGroupConfig::parse(
+                "id=tenant1,ops_size=10,ops_one_time_burst=5,ops_burst=2,ops_refill_time=1000"
+            )?,
+            RateLimitGroupConfig {
+                id: "tenant1".to_owned(),
+                rate_limiter_config: RateLimiterConfig {
+                    bandwidth: None,
+                    ops: Some(TokenBucketConfig {
+                        size: 10,
+                        one_time_burst: Some(5),
+                        burst: Some(2),
+                        refill_time: 1000,
+                    }),
+                },
+            }
+        );
         Ok(())
     }
 
@@ -2565,6 +5589,7 @@ mod tests {
             RngConfig {
                 src: PathBuf::from("/dev/random"),
                 iommu: true,
+                ..Default::default()
             }
         );
         assert_eq!(
@@ -2574,12 +5599,36 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            RngConfig::parse("source=egd:/tmp/egd.sock")?,
+            RngConfig {
+                source: Some(RngSource::Egd(PathBuf::from("/tmp/egd.sock"))),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            RngConfig::parse("queue_size=1024")?,
+            RngConfig {
+                queue_size: 1024,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            RngConfig::parse("source=jitter")?,
+            RngConfig {
+                source: Some(RngSource::Jitter),
+                ..Default::default()
+            }
+        );
+        assert!(RngConfig::parse("source=/tmp/egd.sock").is_err());
+        assert!(RngConfig::parse("source=egd:").is_err());
+        assert!(RngConfig::parse("src=/dev/random,source=egd:/tmp/egd.sock").is_err());
         Ok(())
     }
 
     #[test]
     fn test_parse_fs() -> Result<()> {
-        // "tag" and "socket" must be supplied
+        // "tag" and either "socket" or "fd" must be supplied
         assert!(FsConfig::parse("").is_err());
         assert!(FsConfig::parse("tag=mytag").is_err());
         assert!(FsConfig::parse("socket=/tmp/sock").is_err());
@@ -2609,6 +5658,24 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,fd=3")?,
+            FsConfig {
+                fd: Some(3),
+                tag: "mytag".to_owned(),
+                ..Default::default()
+            }
+        );
+        assert!(FsConfig::parse("tag=mytag,fd=foo").is_err());
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock,reconnect=off")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                reconnect: false,
+                ..Default::default()
+            }
+        );
 
         Ok(())
     }
@@ -2645,6 +5712,25 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,size=128M,mem_policy=shared")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                size: Some(128 << 20),
+                mem_policy: Some(PmemMemPolicy::Shared),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,size=128M,mem_policy=private")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                size: Some(128 << 20),
+                mem_policy: Some(PmemMemPolicy::Private),
+                ..Default::default()
+            }
+        );
+        assert!(PmemConfig::parse("file=/tmp/pmem,mem_policy=cow").is_err());
 
         Ok(())
     }
@@ -2659,6 +5745,11 @@ mod tests {
                 mode: ConsoleOutputMode::Off,
                 iommu: false,
                 file: None,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
         assert_eq!(
@@ -2667,6 +5758,11 @@ mod tests {
                 mode: ConsoleOutputMode::Pty,
                 iommu: false,
                 file: None,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
         assert_eq!(
@@ -2675,6 +5771,11 @@ mod tests {
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
                 file: None,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
         assert_eq!(
@@ -2683,6 +5784,11 @@ mod tests {
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
                 file: None,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
         assert_eq!(
@@ -2690,7 +5796,12 @@ mod tests {
             ConsoleConfig {
                 mode: ConsoleOutputMode::File,
                 iommu: false,
-                file: Some(PathBuf::from("/tmp/console"))
+                file: Some(PathBuf::from("/tmp/console")),
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
         assert_eq!(
@@ -2699,16 +5810,87 @@ mod tests {
                 mode: ConsoleOutputMode::Null,
                 iommu: true,
                 file: None,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
+        assert_eq!(
+            ConsoleConfig::parse("tty,input=/tmp/script,input_delay=5000,input_pace=500")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Tty,
+                iommu: false,
+                file: None,
+                input: Some(ConsoleInputConfig {
+                    path: PathBuf::from("/tmp/script"),
+                    delay: 5000,
+                    pace: 500,
+                }),
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("tty,resize=off")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Tty,
+                iommu: false,
+                file: None,
+                input: None,
+                resize: false,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
+            }
+        );
+        assert!(ConsoleConfig::parse_serial("tty,resize=off").is_err());
         assert_eq!(
             ConsoleConfig::parse("file=/tmp/console,iommu=on")?,
             ConsoleConfig {
                 mode: ConsoleOutputMode::File,
                 iommu: true,
-                file: Some(PathBuf::from("/tmp/console"))
+                file: Some(PathBuf::from("/tmp/console")),
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("file=/tmp/console,rotate=1M,keep=3")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::File,
+                iommu: false,
+                file: Some(PathBuf::from("/tmp/console")),
+                input: None,
+                resize: true,
+                rotate: Some(ConsoleRotateConfig {
+                    size: 1 << 20,
+                    keep: 3,
+                }),
+                flow_control: FlowControl::None,
+                timestamps: false,
             }
         );
+        assert_eq!(
+            ConsoleConfig::parse("file=/tmp/console,rotate=4096")?.rotate,
+            Some(ConsoleRotateConfig {
+                size: 4096,
+                keep: 1,
+            })
+        );
+        assert_eq!(
+            ConsoleConfig::parse("pty,flow_control=rtscts")?.flow_control,
+            FlowControl::RtsCts
+        );
+        assert!(ConsoleConfig::parse("pty,flow_control=xonxoff").is_err());
+        assert!(ConsoleConfig::parse("file=/tmp/console,timestamps=on")?.timestamps);
+        assert!(!ConsoleConfig::parse("file=/tmp/console")?.timestamps);
         Ok(())
     }
 
@@ -2774,6 +5956,41 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_scsi_parsing() -> Result<()> {
+        // disks are required
+        assert!(ScsiConfig::parse("").is_err());
+        assert_eq!(
+            ScsiConfig::parse("disks=[/path/to/disk0]")?,
+            ScsiConfig {
+                disks: vec![PathBuf::from("/path/to/disk0")],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            ScsiConfig::parse(
+                "disks=[/path/to/disk0,/path/to/disk1],readonly=on,queue_size=256,id=my_scsi"
+            )?,
+            ScsiConfig {
+                disks: vec![
+                    PathBuf::from("/path/to/disk0"),
+                    PathBuf::from("/path/to/disk1")
+                ],
+                readonly: true,
+                queue_size: 256,
+                id: Some("my_scsi".to_owned()),
+                ..Default::default()
+            }
+        );
+        // Block device options not supported by the SCSI disks are rejected.
+        assert!(ScsiConfig::parse("disks=[/path/to/disk0],bw_size=1000").is_err());
+        assert!(serde_json::from_str::<ScsiConfig>(
+            r#"{"disks": ["/path/to/disk0"], "rate_limiter_config": {}}"#
+        )
+        .is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_tpm_parsing() -> Result<()> {
         // path is required
@@ -2787,6 +6004,132 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_platform_parsing() -> Result<()> {
+        assert_eq!(PlatformConfig::parse("")?, PlatformConfig::default());
+        assert!(PlatformConfig::parse("")?.acpi);
+        assert_eq!(
+            PlatformConfig::parse("acpi=off")?,
+            PlatformConfig {
+                acpi: false,
+                ..Default::default()
+            }
+        );
+        assert!(PlatformConfig::parse("acpi=foo").is_err());
+        assert_eq!(
+            PlatformConfig::parse("transport=mmio")?,
+            PlatformConfig {
+                transport: VirtioTransportType::Mmio,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("transport=pci")?.transport,
+            VirtioTransportType::Pci
+        );
+        assert!(PlatformConfig::parse("transport=virtio").is_err());
+        assert_eq!(
+            PlatformConfig::parse("seed=ci")?,
+            PlatformConfig {
+                seed: Some("ci".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("iommu_address_width=32")?,
+            PlatformConfig {
+                iommu_address_width_bits: 32,
+                ..Default::default()
+            }
+        );
+        assert!(PlatformConfig::parse("iommu_address_width=foo").is_err());
+        assert_eq!(
+            PlatformConfig::parse("dsdt=/path/to/dsdt.aml")?,
+            PlatformConfig {
+                dsdt: Some(PathBuf::from("/path/to/dsdt.aml")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("dsdt=/path/to/dsdt.aml,dsdt_append=on")?,
+            PlatformConfig {
+                dsdt: Some(PathBuf::from("/path/to/dsdt.aml")),
+                dsdt_append: true,
+                ..Default::default()
+            }
+        );
+        assert!(PlatformConfig::parse("dsdt_append=foo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmdline_parsing() -> Result<()> {
+        assert_eq!(
+            parse_cmdline("console=ttyS0 root=/dev/vda1")?,
+            "console=ttyS0 root=/dev/vda1"
+        );
+
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(file.as_path(), "console=ttyS0 root=/dev/vda1\n").unwrap();
+        assert_eq!(
+            parse_cmdline(&format!("@{}", file.as_path().display()))?,
+            "console=ttyS0 root=/dev/vda1"
+        );
+
+        assert!(parse_cmdline("@/path/does/not/exist").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_from_file() {
+        let config = VmConfig::builder()
+            .cpus(2)
+            .kernel("/path/to/kernel")
+            .build()
+            .unwrap();
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(
+            file.as_path(),
+            serde_json::to_string_pretty(&config).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(VmConfig::from_file(file.as_path()).unwrap(), config);
+
+        std::fs::write(
+            file.as_path(),
+            "{\n  \"cpus\": {\n    \"boot_vcpus\": \"two\"\n  }\n}\n",
+        )
+        .unwrap();
+        match VmConfig::from_file(file.as_path()) {
+            Err(Error::ParseConfigFile(path, e)) => {
+                assert_eq!(path, file.as_path());
+                assert_eq!(
+                    e,
+                    ConfigError {
+                        line: 3,
+                        column: 23,
+                        message: "invalid type: string \"two\", expected u32".to_owned(),
+                    }
+                );
+            }
+            r => panic!("Unexpected result: {r:?}"),
+        }
+
+        assert_eq!(
+            VmConfig::from_json("{\n  \"cpus\": {\n").err(),
+            Some(ConfigError {
+                line: 3,
+                column: 0,
+                message: "EOF while parsing an object".to_owned(),
+            })
+        );
+
+        assert!(matches!(
+            VmConfig::from_file(Path::new("/path/does/not/exist")),
+            Err(Error::ReadConfigFile(_, _))
+        ));
+    }
+
     #[test]
     fn test_vsock_parsing() -> Result<()> {
         // socket and cid is required
@@ -2814,6 +6157,57 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_config_builder() {
+        let config = VmConfig::builder()
+            .cpus(2)
+            .memory_mb(512)
+            .kernel("/path/to/kernel")
+            .cmdline("console=hvc0")
+            .disk(DiskConfig {
+                path: Some(PathBuf::from("/path/to/disk")),
+                ..Default::default()
+            })
+            .disk(DiskConfig {
+                path: Some(PathBuf::from("/path/to/disk2")),
+                iommu: true,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        assert_eq!(config.cpus.boot_vcpus, 2);
+        assert_eq!(config.cpus.max_vcpus, 2);
+        assert_eq!(config.memory.size, 512 << 20);
+        assert_eq!(
+            config.payload,
+            Some(PayloadConfig {
+                kernel: Some(PathBuf::from("/path/to/kernel")),
+                cmdline: Some("console=hvc0".to_owned()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.disks.as_ref().unwrap().len(), 2);
+        assert_eq!(config.console, default_console());
+        assert!(config.iommu);
+
+        assert_eq!(
+            VmConfig::builder().build().err(),
+            Some(ValidationError::KernelMissing)
+        );
+        assert_eq!(
+            VmConfig::builder()
+                .cpus_config(CpusConfig {
+                    boot_vcpus: 2,
+                    max_vcpus: 1,
+                    ..Default::default()
+                })
+                .kernel("/path/to/kernel")
+                .build()
+                .err(),
+            Some(ValidationError::CpusMaxLowerThanBoot)
+        );
+    }
+
     #[test]
     fn test_config_validation() {
         let mut valid_config = VmConfig {
@@ -2824,6 +6218,7 @@ mod tests {
             },
             memory: MemoryConfig {
                 size: 536_870_912,
+                file: None,
                 mergeable: false,
                 hotplug_method: HotplugMethod::Acpi,
                 hotplug_size: None,
@@ -2834,6 +6229,13 @@ mod tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                prealloc_threads: None,
+                readonly_rom: None,
+                numa_bind: None,
+                zero_on_free: false,
+                dump_on_oom: None,
+                backend: None,
+                max_ram: None,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -2841,9 +6243,11 @@ mod tests {
             }),
             disks: None,
             net: None,
+            rate_limit_groups: None,
             rng: RngConfig {
                 src: PathBuf::from("/dev/urandom"),
                 iommu: false,
+                source: None,
             },
             balloon: None,
             fs: None,
@@ -2852,15 +6256,26 @@ mod tests {
                 file: None,
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             },
             console: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             },
             devices: None,
             user_devices: None,
             vdpa: None,
+            scsi: None,
             vsock: None,
             pvpanic: false,
             iommu: false,
@@ -2868,6 +6283,8 @@ mod tests {
             sgx_epc: None,
             numa: None,
             watchdog: false,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            watchdog_coredump: None,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             platform: None,
@@ -2878,129 +6295,1020 @@ mod tests {
         assert!(valid_config.validate().is_ok());
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.serial.mode = ConsoleOutputMode::Tty;
-        invalid_config.console.mode = ConsoleOutputMode::Tty;
+        invalid_config.serial.mode = ConsoleOutputMode::Tty;
+        invalid_config.console.mode = ConsoleOutputMode::Tty;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DoubleTtyMode)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.console.flow_control = FlowControl::RtsCts;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::FlowControlUnsupported)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.resize = false;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::SerialResizeUnsupported)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.payload = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::KernelMissing)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.mode = ConsoleOutputMode::File;
+        invalid_config.serial.file = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleFileMissing)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.input = Some(ConsoleInputConfig {
+            path: PathBuf::from("/tmp/script"),
+            delay: 0,
+            pace: 0,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleInputInvalidMode)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.console.rotate = Some(ConsoleRotateConfig {
+            size: 1 << 20,
+            keep: 1,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleRotateInvalidMode)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.timestamps = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleTimestampsInvalidMode)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.backend = Some(MemoryBackend::Memfd);
+        invalid_config.memory.file = Some(PathBuf::from("/dev/shm/guest_ram"));
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryBackendWithFile)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.file = Some(PathBuf::from("/dev/shm/guest_ram"));
+        invalid_config.memory.hotplug_size = Some(1 << 30);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryFileWithHotplug)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.file = Some(PathBuf::from("/dev/shm/guest_ram"));
+        invalid_config.memory.size = 0;
+        invalid_config.memory.zones = Some(vec![MemoryZoneConfig {
+            id: "mem0".to_owned(),
+            size: 536_870_912,
+            file: None,
+            shared: false,
+            hugepages: false,
+            hugepage_size: None,
+            host_numa_node: None,
+            hotplug_size: None,
+            hotplugged_size: None,
+            prefault: false,
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryFileWithZones)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.file = Some(PathBuf::from("/dev/shm/guest_ram"));
+        invalid_config.memory.shared = true;
+        invalid_config.memory.numa_bind = Some(0);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryFileSharedWithNumaBind)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.max_ram = Some(still_valid_config.memory.size);
+        assert!(still_valid_config.validate().is_ok());
+        let mut invalid_config = still_valid_config;
+        invalid_config.memory.hotplug_method = HotplugMethod::VirtioMem;
+        invalid_config.memory.hotplug_size = Some(1 << 30);
+        invalid_config.memory.hotplugged_size = Some(1 << 30);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryAboveMaxRam(
+                invalid_config.memory.size + (1 << 30),
+                invalid_config.memory.size
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = 16;
+        invalid_config.cpus.boot_vcpus = 32;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CpusMaxLowerThanBoot)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = 16;
+        invalid_config.cpus.boot_vcpus = 16;
+        invalid_config.cpus.topology = Some(CpuTopology {
+            threads_per_core: 2,
+            cores_per_die: 8,
+            dies_per_package: 1,
+            packages: 2,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CpuTopologyCount)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            path: Some(PathBuf::from("/path/to/image")),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskSocketAndPath)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            wwn: Some("0x6000c500a1b2c3d4".to_owned()),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidWwn("0x6000c500a1b2c3d4".to_owned()))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            serial: Some("disk0".to_owned()),
+            wwn: Some("5000c500a1b2c3d4".to_owned()),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskSerialAndWwn)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            segment_max: Some(127),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidSegmentMax(127, 128))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            size_max: Some(65536),
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::SegmentLimitsVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            probe_alignment: true,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ProbeAlignmentVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            write_rate_limiter_config: Some(RateLimiterConfig::default()),
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::SplitRateLimitsVhostUser)
+        );
+
+        let url_disk = DiskConfig {
+            url: Some("http://example.com/disk.raw".to_owned()),
+            readonly: true,
+            cache_mb: Some(16),
+            ..Default::default()
+        };
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![url_disk.clone()]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut https_config = valid_config.clone();
+        https_config.disks = Some(vec![DiskConfig {
+            url: Some("https://example.com/disk.raw".to_owned()),
+            ..url_disk.clone()
+        }]);
+        if cfg!(feature = "https") {
+            assert!(https_config.validate().is_ok());
+        } else {
+            assert_eq!(
+                https_config.validate(),
+                Err(ValidationError::InvalidDiskUrl(
+                    "https://example.com/disk.raw".to_owned()
+                ))
+            );
+        }
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            url: Some("ftp://example.com/disk.raw".to_owned()),
+            ..url_disk.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidDiskUrl(
+                "ftp://example.com/disk.raw".to_owned()
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            ..url_disk.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskUrlAndPath)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            readonly: false,
+            ..url_disk.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskUrlNotReadonly)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            direct: true,
+            ..url_disk
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskUrlUnsupportedOption("direct"))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            cache_mb: Some(16),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskCacheSizeWithoutUrl)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            aio_max_inflight: Some(0),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidAioMaxInflight)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            aio_max_inflight: Some(16),
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::AioMaxInflightVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.scsi = Some(vec![ScsiConfig::default()]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidScsiDiskCount(0))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            disks: vec![PathBuf::from("/path/to/disk")],
+            queue_size: 2,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidScsiQueueSize(2))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            disks: vec![PathBuf::from("/path/to/disk")],
+            queue_size: 100,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidScsiQueueSize(100))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image.iso")),
+            media: DiskMedia::Cdrom,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CdromNotReadonly)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            readonly: true,
+            media: DiskMedia::Cdrom,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CdromVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("@/path/to/dir")),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskDirectoryNotReadonly)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            readonly: true,
+            overlay: Some(PathBuf::from("/path/to/overlay")),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::OverlayUnsupportedDisk)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            overlay_discard: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::OverlayDiscardWithoutOverlay)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            poll_queue: true,
+            poll_queue_us: Some(0),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPollQueueDuration)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            poll_queue_us: Some(100),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PollQueueDurationWithoutPollQueue)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            poll_queue: true,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PollQueueVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            sync_on_flush_interval: 1000,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::SyncIntervalVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            request_timeout: 30000,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::RequestTimeoutVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            overlay: Some(PathBuf::from("/path/to/overlay")),
+            request_timeout: 30000,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::RequestTimeoutUnsupported("overlay"))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            disable_io_uring: true,
+            request_timeout: 30000,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::RequestTimeoutUnsupported(
+                "_disable_io_uring"
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            direct: true,
+            cache: CacheMode::Writethrough,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskDirectCache)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            direct: true,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskCacheVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            readonly: true,
+            cache: CacheMode::Writethrough,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ReadonlyDiskWriteOption(
+                "cache=writethrough"
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            readonly: true,
+            sync_on_flush_interval: 1000,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ReadonlyDiskWriteOption(
+                "sync_on_flush_interval"
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            readonly: true,
+            zoned: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ZonedUnsupportedDisk)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            zoned: true,
+            zone_size: Some(3 << 20),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidZoneSize(3 << 20))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            conventional_zones: Some(2),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ZoneLayoutWithoutZoned)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            zoned: true,
+            zone_size: Some(64 << 20),
+            conventional_zones: Some(2),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("@/path/to/dir")),
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            readonly: true,
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskDirectoryVhostUser)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("@/path/to/dir")),
+            readonly: true,
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            wwn: Some("5000C500A1B2C3D4".to_owned()),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserMissingSocket)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserRequiresSharedMemory)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            ..Default::default()
+        }]);
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+        still_valid_config.memory.shared = false;
+        still_valid_config.memory.backend = Some(MemoryBackend::Memfd);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost_user: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserRequiresSharedMemory)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            ..Default::default()
+        }]);
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            fds: Some(vec![0]),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VnetReservedFd)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            offload_csum: false,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::NoHardwareChecksumOffload)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            mtu: Some(576),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidMtu(576))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            ip: "fd00::1".parse().unwrap(),
+            mask: Some("255.255.255.0".parse().unwrap()),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::NetIpMaskMismatch)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            ip: "fd00::1".parse().unwrap(),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            mask: Some("255.0.255.0".parse().unwrap()),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidNetmask(
+                "255.0.255.0".parse().unwrap()
+            ))
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            ip: "fd00::1".parse().unwrap(),
+            mask: Some("ffff:ffff:ffff:ffff::".parse().unwrap()),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            promisc: false,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PromiscNotSupported)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            rx_queue_size: Some(1000),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidQueueSize(1000))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            tx_queue_size: Some(128),
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::AsymmetricQueueSizeVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            tap: Some("tap0".to_owned()),
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::DoubleTtyMode)
+            Err(ValidationError::VdpaNetConflict)
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.payload = None;
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            mac: Some(MacAddr::parse_str("12:34:56:78:90:ab").unwrap()),
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::KernelMissing)
+            Err(ValidationError::VdpaNetUnsupported("\"mac\""))
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.serial.mode = ConsoleOutputMode::File;
-        invalid_config.serial.file = None;
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            mtu: Some(9000),
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::ConsoleFileMissing)
+            Err(ValidationError::VdpaNetUnsupported("\"mtu\""))
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.cpus.max_vcpus = 16;
-        invalid_config.cpus.boot_vcpus = 32;
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            host_mac: Some(MacAddr::parse_str("12:34:56:78:90:ab").unwrap()),
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::CpusMaxLowerThanBoot)
+            Err(ValidationError::VdpaNetUnsupported("\"host_mac\""))
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.cpus.max_vcpus = 16;
-        invalid_config.cpus.boot_vcpus = 16;
-        invalid_config.cpus.topology = Some(CpuTopology {
-            threads_per_core: 2,
-            cores_per_die: 8,
-            dies_per_package: 1,
-            packages: 2,
-        });
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            queue_size: 1024,
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::CpuTopologyCount)
+            Err(ValidationError::VdpaNetUnsupported("\"queue_size\""))
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.disks = Some(vec![DiskConfig {
-            vhost_socket: Some("/path/to/sock".to_owned()),
-            path: Some(PathBuf::from("/path/to/image")),
+        invalid_config.net = Some(vec![NetConfig {
+            vdpa: Some(PathBuf::from("/dev/vhost-vdpa-0")),
+            offload_tso: false,
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::DiskSocketAndPath)
+            Err(ValidationError::VdpaNetUnsupported("offload settings"))
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            rx_queue_size: Some(4096),
+            tx_queue_size: Some(64),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            tap: Some("@bridge:br0".to_owned()),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
         let mut invalid_config = valid_config.clone();
-        invalid_config.memory.shared = true;
-        invalid_config.disks = Some(vec![DiskConfig {
-            vhost_user: true,
+        invalid_config.net = Some(vec![NetConfig {
+            tap: Some("@br0".to_owned()),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserMissingSocket)
+            Err(ValidationError::InvalidNetBridge("@br0".to_owned()))
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            host_name: Some("Onboard LAN 1".to_owned()),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
         let mut invalid_config = valid_config.clone();
-        invalid_config.disks = Some(vec![DiskConfig {
-            vhost_user: true,
-            vhost_socket: Some("/path/to/sock".to_owned()),
+        invalid_config.net = Some(vec![NetConfig {
+            host_name: Some(String::new()),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserRequiresSharedMemory)
+            Err(ValidationError::InvalidNetHostName(String::new()))
         );
 
+        let rate_limit_group = RateLimitGroupConfig {
+            id: "tenant0".to_owned(),
+            rate_limiter_config: RateLimiterConfig {
+                bandwidth: Some(TokenBucketConfig {
+                    size: 1000,
+                    one_time_burst: None,
+                    burst: None,
+                    refill_time: 100,
+                }),
+                ops: None,
+            },
+        };
         let mut still_valid_config = valid_config.clone();
-        still_valid_config.disks = Some(vec![DiskConfig {
-            vhost_user: true,
-            vhost_socket: Some("/path/to/sock".to_owned()),
+        still_valid_config.rate_limit_groups = Some(vec![rate_limit_group.clone()]);
+        still_valid_config.net = Some(vec![
+            NetConfig {
+                rate_limit_group: Some("tenant0".to_owned()),
+                id: Some("net0".to_owned()),
+                ..Default::default()
+            },
+            NetConfig {
+                rate_limit_group: Some("tenant0".to_owned()),
+                id: Some("net1".to_owned()),
+                ..Default::default()
+            },
+        ]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            rate_limit_group: Some("tenant0".to_owned()),
             ..Default::default()
         }]);
-        still_valid_config.memory.shared = true;
-        assert!(still_valid_config.validate().is_ok());
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::UnknownRateLimitGroup("tenant0".to_owned()))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.rate_limit_groups = Some(vec![rate_limit_group.clone()]);
+        invalid_config.net = Some(vec![NetConfig {
+            rate_limit_group: Some("tenant0".to_owned()),
+            rate_limiter_config: Some(rate_limit_group.rate_limiter_config),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::RateLimitGroupAndRateLimiter)
+        );
 
         let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.rate_limit_groups = Some(vec![rate_limit_group.clone()]);
         invalid_config.net = Some(vec![NetConfig {
+            rate_limit_group: Some("tenant0".to_owned()),
             vhost_user: true,
+            vhost_socket: Some("/tmp/sock".to_owned()),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserRequiresSharedMemory)
+            Err(ValidationError::RateLimitGroupUnsupported)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.rate_limit_groups =
+            Some(vec![rate_limit_group.clone(), rate_limit_group.clone()]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DuplicateRateLimitGroup(
+                "tenant0".to_owned()
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.rate_limit_groups = Some(vec![RateLimitGroupConfig {
+            id: "tenant0".to_owned(),
+            rate_limiter_config: RateLimiterConfig::default(),
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::RateLimitGroupWithoutLimits(
+                "tenant0".to_owned()
+            ))
         );
 
         let mut still_valid_config = valid_config.clone();
         still_valid_config.net = Some(vec![NetConfig {
-            vhost_user: true,
-            vhost_socket: Some("/path/to/sock".to_owned()),
+            vhost: true,
             ..Default::default()
         }]);
-        still_valid_config.memory.shared = true;
         assert!(still_valid_config.validate().is_ok());
 
         let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
         invalid_config.net = Some(vec![NetConfig {
-            fds: Some(vec![0]),
+            vhost: true,
+            vhost_user: true,
+            vhost_socket: Some("/tmp/sock".to_owned()),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VnetReservedFd)
+            Err(ValidationError::VhostNetConflict)
         );
 
         let mut invalid_config = valid_config.clone();
         invalid_config.net = Some(vec![NetConfig {
-            offload_csum: false,
+            vhost: true,
+            rate_limiter_config: Some(rate_limit_group.rate_limiter_config),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::NoHardwareChecksumOffload)
+            Err(ValidationError::VhostNetUnsupported("rate limiting"))
         );
 
         let mut invalid_config = valid_config.clone();
@@ -3016,6 +7324,47 @@ mod tests {
         still_valid_config.memory.shared = true;
         assert!(still_valid_config.validate().is_ok());
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.fs = Some(vec![FsConfig {
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::FsSocketMissing)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.fs = Some(vec![FsConfig {
+            socket: PathBuf::from("/tmp/sock"),
+            fd: Some(3),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::FsSocketAndFd)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.fs = Some(vec![FsConfig {
+            fd: Some(2),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::FsReservedFd)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.shared = true;
+        still_valid_config.fs = Some(vec![FsConfig {
+            fd: Some(3),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.memory.hugepages = true;
         assert!(still_valid_config.validate().is_ok());
@@ -3041,6 +7390,116 @@ mod tests {
             Err(ValidationError::InvalidHugePageSize(3 << 20))
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.prealloc_threads = Some(0);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPreallocThreads)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.readonly_rom = Some(ReadonlyRomConfig {
+            address: 0xd000_0800,
+            file: PathBuf::from("/path/to/ipxe.rom"),
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidReadonlyRomAddress(0xd000_0800))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.size = 0;
+        invalid_config.memory.numa_bind = Some(0);
+        invalid_config.memory.zones = Some(vec![MemoryZoneConfig {
+            id: "mem0".to_owned(),
+            size: 536_870_912,
+            file: None,
+            shared: false,
+            hugepages: false,
+            hugepage_size: None,
+            host_numa_node: None,
+            hotplug_size: None,
+            hotplugged_size: None,
+            prefault: false,
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::NumaBindWithMemoryZones)
+        );
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.tsc_freq = Some(999);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidTscFrequency(999))
+            );
+
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.tsc_freq = Some(2_500_000_000);
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.stable_id = Some(CpuStableId {
+                family: 5,
+                model: 0x55,
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidCpuStableId("model out of range"))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.stable_id = Some(CpuStableId {
+                brand: "x".repeat(48),
+                ..Default::default()
+            });
+            assert!(invalid_config.validate().is_err());
+
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.stable_id = Some(CpuStableId {
+                family: 6,
+                model: 0x55,
+                stepping: 7,
+                brand: "Stable vCPU".to_owned(),
+            });
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.hypervisor_cpuid = false;
+            invalid_config.cpus.kvm_hyperv = true;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::HiddenHypervisorCpuid("kvm_hyperv"))
+            );
+
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.hypervisor_cpuid = false;
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.boot_vcpus = 256;
+            invalid_config.cpus.max_vcpus = 256;
+            invalid_config.cpus.hypervisor_cpuid = false;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::HiddenHypervisorCpuid(
+                    "more than 255 vCPUs"
+                ))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.boot_vcpus = 256;
+            invalid_config.cpus.max_vcpus = 256;
+            invalid_config.cpus.kvm_hyperv = true;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::KvmHypervTooManyVcpus)
+            );
+        }
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.platform = Some(PlatformConfig {
             num_pci_segments: MAX_NUM_PCI_SEGMENTS,
@@ -3068,6 +7527,25 @@ mod tests {
         });
         assert!(still_valid_config.validate().is_ok());
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            iommu_address_width_bits: 32,
+            ..Default::default()
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        for bits in [0, MIN_IOMMU_ADDRESS_WIDTH_BITS - 1, 65] {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                iommu_address_width_bits: bits,
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidIommuAddressWidthBits(bits))
+            );
+        }
+
         let mut invalid_config = valid_config.clone();
         invalid_config.platform = Some(PlatformConfig {
             num_pci_segments: MAX_NUM_PCI_SEGMENTS,
@@ -3263,6 +7741,7 @@ mod tests {
             ..Default::default()
         });
         invalid_config.fs = Some(vec![FsConfig {
+            socket: PathBuf::from("/tmp/sock"),
             pci_segment: 1,
             ..Default::default()
         }]);
@@ -3297,6 +7776,118 @@ mod tests {
         ]);
         assert!(invalid_config.validate().is_err());
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.pmem = Some(vec![PmemConfig {
+            file: PathBuf::from("/tmp/pmem"),
+            discard_writes: true,
+            mem_policy: Some(PmemMemPolicy::Shared),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PmemDiscardWritesRequiresPrivate)
+        );
+
+        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.watchdog_coredump = Some(PathBuf::from("/tmp/core"));
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::WatchdogCoredumpWithoutWatchdog)
+            );
+        }
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            acpi: false,
+            ..Default::default()
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.cpus.max_vcpus = 2;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::AcpiDisabledUnsupported("CPU hotplug"))
+        );
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.memory.hotplug_size = Some(1 << 30);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::AcpiDisabledUnsupported("memory hotplug"))
+        );
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            acpi: false,
+            dsdt: Some(PathBuf::from("/path/to/dsdt.aml")),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::AcpiDisabledUnsupported("a custom DSDT"))
+        );
+
+        // Disabling ACPI implies the virtio-mmio transport.
+        let mut invalid_config = still_valid_config;
+        invalid_config.rng.iommu = true;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MmioTransportUnsupported("a virtual IOMMU"))
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            transport: VirtioTransportType::Mmio,
+            ..Default::default()
+        });
+        still_valid_config.cpus.max_vcpus = 2;
+        assert!(still_valid_config.validate().is_ok());
+        assert_eq!(
+            still_valid_config.virtio_transport(),
+            VirtioTransportType::Mmio
+        );
+        assert_eq!(
+            still_valid_config.validate_migratable(),
+            Err(ValidationError::NotMigratable("the virtio-mmio transport"))
+        );
+        assert!(valid_config.validate_migratable().is_ok());
+
+        let mut not_migratable_config = valid_config.clone();
+        not_migratable_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            overlay: Some(PathBuf::from("/path/to/overlay")),
+            overlay_discard: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            not_migratable_config.validate_migratable(),
+            Err(ValidationError::NotMigratable("overlay_discard"))
+        );
+
+        let mut not_migratable_config = valid_config.clone();
+        not_migratable_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            zoned: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            not_migratable_config.validate_migratable(),
+            Err(ValidationError::NotMigratable("zoned disks"))
+        );
+
+        let mut invalid_config = still_valid_config;
+        invalid_config.payload = Some(PayloadConfig {
+            firmware: Some(PathBuf::from("/path/to/firmware")),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MmioTransportUnsupported("a firmware"))
+        );
+
         let mut still_valid_config = valid_config;
         // SAFETY: Safe as the file was just opened
         let fd1 = unsafe { libc::dup(File::open("/dev/null").unwrap().as_raw_fd()) };
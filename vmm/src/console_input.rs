@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::config::ConsoleInputConfig;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::FromRawFd;
+use std::thread;
+use std::time::Duration;
+
+/// Streams the content of the input file, line by line, through a pipe so
+/// that it can be consumed by the serial or the virtio-console device the
+/// same way input from a TTY is.
+///
+/// The read end of the pipe is returned along with the write end. The latter
+/// must be kept open as long as the device is in use, otherwise the device
+/// would observe a hangup once the whole file has been sent.
+pub fn start_console_input(config: &ConsoleInputConfig) -> io::Result<(File, File)> {
+    let input = File::open(&config.path)?;
+
+    let mut fds = [-1; 2];
+    // SAFETY: FFI call with valid arguments
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both file descriptors are valid and owned solely by us
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    let mut out = writer.try_clone()?;
+    let delay = Duration::from_millis(config.delay);
+    let pace = Duration::from_millis(config.pace);
+    thread::Builder::new()
+        .name("console-input".to_string())
+        .spawn(move || {
+            thread::sleep(delay);
+
+            let mut input = BufReader::new(input);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match input.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        if let Err(e) = out.write_all(&line) {
+                            warn!("Error writing console input: {}", e);
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Error reading console input file: {}", e);
+                        break;
+                    }
+                }
+                thread::sleep(pace);
+            }
+        })?;
+
+    Ok((reader, writer))
+}
@@ -11,7 +11,9 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
-use crate::config::CpusConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::config::LapicTimer;
+use crate::config::{CpuIdle, CpusConfig};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CpuElf64Writable, CpuSegment, CpuState as DumpCpusState, DumpState, Elf64Writable,
@@ -61,14 +63,17 @@ use libc::{c_void, siginfo_t};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use linux_loader::elf::Elf64_Nhdr;
 use seccompiler::{apply_filter, SeccompAction};
-use std::collections::BTreeMap;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::io::Write;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::os::unix::thread::JoinHandleExt;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
 use std::{cmp, io, result, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
@@ -107,6 +112,25 @@ macro_rules! extract_bits_64_without_offset {
 
 pub const CPU_MANAGER_ACPI_SIZE: usize = 0xc;
 
+// How long a halted vCPU busy-polls for a wakeup event with "idle=poll",
+// well above the usual host default so that short idle periods never block.
+const IDLE_POLL_NS: u32 = 1_000_000;
+
+thread_local! {
+    // Time spent by the current vCPU thread handling guest exits in
+    // userspace since the last call to take_exit_handling_time().
+    static EXIT_HANDLING_TIME: Cell<Duration> = Cell::new(Duration::ZERO);
+}
+
+/// Accounts time spent handling a guest exit from the current vCPU thread.
+pub(crate) fn account_exit_handling_time(duration: Duration) {
+    EXIT_HANDLING_TIME.with(|time| time.set(time.get() + duration));
+}
+
+fn take_exit_handling_time() -> Duration {
+    EXIT_HANDLING_TIME.with(|time| time.replace(Duration::ZERO))
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Error creating vCPU: {0}")]
@@ -127,6 +151,12 @@ pub enum Error {
     #[error("Still pending removed vcpu")]
     VcpuPendingRemovedVcpu,
 
+    #[error("Cannot remove vCPUs below the {0} the VM booted with")]
+    RemoveBootVcpus(u32),
+
+    #[error("vCPU {0} cannot be removed as it is pinned to host CPUs")]
+    RemovePinnedVcpu(u32),
+
     #[cfg(target_arch = "aarch64")]
     #[error("Error fetching preferred target: {0}")]
     VcpuArmPreferredTarget(#[source] hypervisor::HypervisorVmError),
@@ -179,8 +209,35 @@ pub enum Error {
     #[error("Error setting up AMX: {0}")]
     AmxEnable(#[source] anyhow::Error),
 
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error enabling x2APIC API: {0}")]
+    EnableX2ApicApi(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Extended MSI destination ID not supported by the host, needed above 255 vCPUs")]
+    MsiExtDestIdUnsupported,
+
+    #[error("Error setting the vCPU idle policy: {0}")]
+    SetHaltPoll(#[source] hypervisor::HypervisorVmError),
+
     #[error("Maximum number of vCPUs exceeds host limit")]
     MaximumVcpusExceeded,
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("vCPU {0} is not present")]
+    VcpuNotPresent(u32),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error setting the TSC frequency: {0}")]
+    SetTscFrequency(#[source] hypervisor::HypervisorCpuError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error restoring the TSC offset: {0}")]
+    RestoreTscOffset(#[source] hypervisor::HypervisorCpuError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Host can't scale the TSC to {0} Hz")]
+    UnsupportedTscFrequency(u64),
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -311,7 +368,7 @@ macro_rules! round_up {
 pub struct Vcpu {
     // The hypervisor abstracted CPU.
     vcpu: Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     #[cfg(target_arch = "aarch64")]
     mpidr: u64,
     saved_state: Option<CpuState>,
@@ -326,7 +383,7 @@ impl Vcpu {
     /// * `vm` - The virtual machine this vcpu will get attached to.
     /// * `vm_ops` - Optional object for exit handling.
     pub fn new(
-        id: u8,
+        id: u32,
         vm: &Arc<dyn hypervisor::Vm>,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> Result<Self> {
@@ -371,6 +428,20 @@ impl Vcpu {
         Ok(())
     }
 
+    /// Pins the frequency of the guest TSC, in Hz.
+    ///
+    /// KVM silently ignores the request when the host doesn't support TSC
+    /// scaling, hence the frequency is read back to make sure it was applied.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_tsc_frequency(&self, freq: u64) -> Result<()> {
+        let khz = (freq / 1000) as u32;
+        self.vcpu.set_tsc_khz(khz).map_err(Error::SetTscFrequency)?;
+        match self.vcpu.tsc_khz().map_err(Error::SetTscFrequency)? {
+            Some(v) if v == khz => Ok(()),
+            _ => Err(Error::UnsupportedTscFrequency(freq)),
+        }
+    }
+
     /// Gets the MPIDR register value.
     #[cfg(target_arch = "aarch64")]
     pub fn get_mpidr(&self) -> u64 {
@@ -437,6 +508,27 @@ impl Snapshottable for Vcpu {
     }
 }
 
+// Checks that going down from `present_vcpus` to `desired_vcpus` keeps the
+// vCPUs the guest booted with, which it may rely on, and the vCPUs pinned to
+// host CPUs, so that their placement isn't silently dropped.
+fn check_vcpus_removal(
+    boot_vcpus: u32,
+    desired_vcpus: u32,
+    present_vcpus: u32,
+    affinity: &BTreeMap<u32, Vec<u8>>,
+) -> Result<()> {
+    if desired_vcpus < boot_vcpus {
+        return Err(Error::RemoveBootVcpus(boot_vcpus));
+    }
+    if let Some(cpu_id) =
+        (desired_vcpus..present_vcpus).find(|cpu_id| affinity.contains_key(cpu_id))
+    {
+        return Err(Error::RemovePinnedVcpu(cpu_id));
+    }
+
+    Ok(())
+}
+
 pub struct CpuManager {
     hypervisor_type: HypervisorType,
     config: CpusConfig,
@@ -454,14 +546,14 @@ pub struct CpuManager {
     #[cfg(feature = "guest_debug")]
     vm_debug_evt: EventFd,
     vcpu_states: Vec<VcpuState>,
-    selected_cpu: u8,
+    selected_cpu: u32,
     vcpus: Vec<Arc<Mutex<Vcpu>>>,
     seccomp_action: SeccompAction,
     vm_ops: Arc<dyn VmOps>,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     acpi_address: Option<GuestAddress>,
-    proximity_domain_per_cpu: BTreeMap<u8, u32>,
-    affinity: BTreeMap<u8, Vec<u8>>,
+    proximity_domain_per_cpu: BTreeMap<u32, u32>,
+    affinity: BTreeMap<u32, Vec<u8>>,
     dynamic: bool,
 }
 
@@ -480,11 +572,13 @@ impl BusDevice for CpuManager {
 
         match offset {
             CPU_SELECTION_OFFSET => {
-                data[0] = self.selected_cpu;
+                let bytes = self.selected_cpu.to_le_bytes();
+                let len = data.len().min(bytes.len());
+                data[..len].copy_from_slice(&bytes[..len]);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.max_vcpus() {
-                    let state = &self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &self.vcpu_states[self.selected_cpu as usize];
                     if state.active() {
                         data[0] |= 1 << CPU_ENABLE_FLAG;
                     }
@@ -510,11 +604,14 @@ impl BusDevice for CpuManager {
     fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
         match offset {
             CPU_SELECTION_OFFSET => {
-                self.selected_cpu = data[0];
+                let mut bytes = [0u8; 4];
+                let len = data.len().min(bytes.len());
+                bytes[..len].copy_from_slice(&data[..len]);
+                self.selected_cpu = u32::from_le_bytes(bytes);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.max_vcpus() {
-                    let state = &mut self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &mut self.vcpu_states[self.selected_cpu as usize];
                     // The ACPI code writes back a 1 to acknowledge the insertion
                     if (data[0] & (1 << CPU_INSERTING_FLAG) == 1 << CPU_INSERTING_FLAG)
                         && state.inserting
@@ -548,6 +645,40 @@ impl BusDevice for CpuManager {
     }
 }
 
+#[derive(Default)]
+struct VcpuRunStats {
+    // Time spent in the hypervisor running the guest, in nanoseconds.
+    run_time: AtomicU64,
+    // Time spent handling guest exits in userspace, in nanoseconds.
+    exit_handling_time: AtomicU64,
+    exits: AtomicU64,
+}
+
+impl VcpuRunStats {
+    fn account(&self, total: Duration, exit_handling: Duration) {
+        let run = total.saturating_sub(exit_handling);
+        self.run_time
+            .fetch_add(run.as_nanos() as u64, Ordering::Relaxed);
+        self.exit_handling_time
+            .fetch_add(exit_handling.as_nanos() as u64, Ordering::Relaxed);
+        self.exits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counters(&self) -> HashMap<&'static str, Wrapping<u64>> {
+        let mut counters = HashMap::new();
+        counters.insert(
+            "run_time_ns",
+            Wrapping(self.run_time.load(Ordering::Relaxed)),
+        );
+        counters.insert(
+            "exit_handling_time_ns",
+            Wrapping(self.exit_handling_time.load(Ordering::Relaxed)),
+        );
+        counters.insert("exits", Wrapping(self.exits.load(Ordering::Relaxed)));
+        counters
+    }
+}
+
 #[derive(Default)]
 struct VcpuState {
     inserting: bool,
@@ -557,6 +688,9 @@ struct VcpuState {
     kill: Arc<AtomicBool>,
     vcpu_run_interrupted: Arc<AtomicBool>,
     paused: Arc<AtomicBool>,
+    #[cfg(target_arch = "x86_64")]
+    nmi_pending: Arc<AtomicBool>,
+    run_stats: Arc<VcpuRunStats>,
 }
 
 impl VcpuState {
@@ -582,6 +716,22 @@ impl VcpuState {
         }
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn inject_nmi(&self) {
+        if let Some(handle) = self.handle.as_ref() {
+            self.nmi_pending.store(true, Ordering::SeqCst);
+            // The NMI is queued by the vCPU thread itself before entering the
+            // guest again, which is why the thread is kicked out of the guest.
+            // A paused vCPU queues it once resumed.
+            if !self.vcpu_run_interrupted.load(Ordering::SeqCst) {
+                // SAFETY: FFI call with correct arguments
+                unsafe {
+                    libc::pthread_kill(handle.as_pthread_t() as _, SIGRTMIN());
+                }
+            }
+        }
+    }
+
     fn join_thread(&mut self) -> Result<()> {
         if let Some(handle) = self.handle.take() {
             handle.join().map_err(Error::ThreadCleanup)?
@@ -612,12 +762,12 @@ impl CpuManager {
         #[cfg(feature = "tdx")] tdx_enabled: bool,
         numa_nodes: &NumaNodes,
     ) -> Result<Arc<Mutex<CpuManager>>> {
-        if u32::from(config.max_vcpus) > hypervisor.get_max_vcpus() {
+        if config.max_vcpus > hypervisor.get_max_vcpus() {
             return Err(Error::MaximumVcpusExceeded);
         }
 
-        let mut vcpu_states = Vec::with_capacity(usize::from(config.max_vcpus));
-        vcpu_states.resize_with(usize::from(config.max_vcpus), VcpuState::default);
+        let mut vcpu_states = Vec::with_capacity(config.max_vcpus as usize);
+        vcpu_states.resize_with(config.max_vcpus as usize, VcpuState::default);
         let hypervisor_type = hypervisor.hypervisor_type();
 
         #[cfg(target_arch = "x86_64")]
@@ -652,7 +802,21 @@ impl CpuManager {
             }
         }
 
-        let proximity_domain_per_cpu: BTreeMap<u8, u32> = {
+        // APIC ids above 254 can only be addressed through x2APIC
+        #[cfg(target_arch = "x86_64")]
+        if config.max_vcpus > 255 {
+            vm.enable_x2apic_api().map_err(Error::EnableX2ApicApi)?;
+        }
+
+        if let Some(idle) = config.idle {
+            let poll_ns = match idle {
+                CpuIdle::Halt => 0,
+                CpuIdle::Poll => IDLE_POLL_NS,
+            };
+            vm.set_halt_poll(poll_ns).map_err(Error::SetHaltPoll)?;
+        }
+
+        let proximity_domain_per_cpu: BTreeMap<u32, u32> = {
             let mut cpu_list = Vec::new();
             for (proximity_domain, numa_node) in numa_nodes.iter() {
                 for cpu in numa_node.cpus.iter() {
@@ -693,7 +857,7 @@ impl CpuManager {
             #[cfg(feature = "guest_debug")]
             vm_debug_evt,
             selected_cpu: 0,
-            vcpus: Vec::with_capacity(usize::from(config.max_vcpus)),
+            vcpus: Vec::with_capacity(config.max_vcpus as usize),
             seccomp_action,
             vm_ops,
             acpi_address: None,
@@ -725,7 +889,13 @@ impl CpuManager {
                 }
                 None
             },
-            |t| Some((t.threads_per_core, t.cores_per_die, t.dies_per_package)),
+            |t| {
+                Some((
+                    u32::from(t.threads_per_core),
+                    u32::from(t.cores_per_die),
+                    u32::from(t.dies_per_package),
+                ))
+            },
         );
 
         self.cpuid = {
@@ -736,16 +906,42 @@ impl CpuManager {
                 sgx_epc_sections,
                 phys_bits,
                 self.config.kvm_hyperv,
+                &self.config.features.kvm_pv_features,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
             )
             .map_err(Error::CommonCpuId)?
         };
 
+        if let Some(stable_id) = &self.config.stable_id {
+            arch::set_cpu_identity(
+                &mut self.cpuid,
+                stable_id.family,
+                stable_id.model,
+                stable_id.stepping,
+                &stable_id.brand,
+            );
+        }
+
+        if !self.config.hypervisor_cpuid {
+            arch::hide_hypervisor(&mut self.cpuid);
+        }
+
+        if self.config.lapic_timer == Some(LapicTimer::Periodic) {
+            arch::hide_tsc_deadline_timer(&mut self.cpuid);
+        }
+
+        // Interrupts can only be routed to APIC ids above 255 if the guest
+        // encodes the upper bits of the destination in the MSI address, which
+        // KVM only decodes once the x2APIC API has been enabled.
+        if !arch::set_msi_ext_dest_id(&mut self.cpuid, self.config.max_vcpus > 255) {
+            return Err(Error::MsiExtDestIdUnsupported);
+        }
+
         Ok(())
     }
 
-    fn create_vcpu(&mut self, cpu_id: u8, snapshot: Option<Snapshot>) -> Result<Arc<Mutex<Vcpu>>> {
+    fn create_vcpu(&mut self, cpu_id: u32, snapshot: Option<Snapshot>) -> Result<Arc<Mutex<Vcpu>>> {
         info!("Creating vCPU: cpu_id = {}", cpu_id);
 
         let mut vcpu = Vcpu::new(cpu_id, &self.vm, Some(self.vm_ops.clone()))?;
@@ -783,6 +979,13 @@ impl CpuManager {
         #[cfg(target_arch = "x86_64")]
         assert!(!self.cpuid.is_empty());
 
+        // The TSC frequency must be set first so that the CPUID leaf
+        // advertising it reflects the requested value.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(tsc_freq) = self.config.tsc_freq {
+            vcpu.set_tsc_frequency(tsc_freq)?;
+        }
+
         #[cfg(target_arch = "x86_64")]
         vcpu.configure(boot_setup, self.cpuid.clone(), self.config.kvm_hyperv)?;
 
@@ -795,7 +998,7 @@ impl CpuManager {
     /// Only create new vCPUs if there aren't any inactive ones to reuse
     fn create_vcpus(
         &mut self,
-        desired_vcpus: u8,
+        desired_vcpus: u32,
         snapshot: Option<Snapshot>,
     ) -> Result<Vec<Arc<Mutex<Vcpu>>>> {
         let mut vcpus: Vec<Arc<Mutex<Vcpu>>> = vec![];
@@ -812,7 +1015,7 @@ impl CpuManager {
         }
 
         // Only create vCPUs in excess of all the allocated vCPUs.
-        for cpu_id in self.vcpus.len() as u8..desired_vcpus {
+        for cpu_id in self.vcpus.len() as u32..desired_vcpus {
             vcpus.push(self.create_vcpu(
                 cpu_id,
                 // TODO: The special format of the CPU id can be removed once
@@ -821,9 +1024,60 @@ impl CpuManager {
             )?);
         }
 
+        #[cfg(target_arch = "x86_64")]
+        if snapshot.is_some() {
+            Self::restore_tsc_offsets(&vcpus)?;
+        }
+
         Ok(vcpus)
     }
 
+    // Each vCPU resumes from the TSC value saved in the snapshot, but these
+    // values are read and written back one vCPU after the other, leaving the
+    // vCPUs slightly out of sync. When the snapshot carries the offsets
+    // between the host and the guest TSCs, the differences between them are
+    // applied on top of the offset of the first vCPU, so that all of them
+    // keep the exact relationship they had when the snapshot was taken.
+    #[cfg(target_arch = "x86_64")]
+    fn restore_tsc_offsets(vcpus: &[Arc<Mutex<Vcpu>>]) -> Result<()> {
+        let saved_offsets = match vcpus
+            .iter()
+            .map(|vcpu| {
+                vcpu.lock()
+                    .unwrap()
+                    .saved_state
+                    .as_ref()
+                    .and_then(CpuState::tsc_offset)
+            })
+            .collect::<Option<Vec<u64>>>()
+        {
+            Some(offsets) if !offsets.is_empty() => offsets,
+            _ => return Ok(()),
+        };
+
+        let base_offset = match vcpus[0]
+            .lock()
+            .unwrap()
+            .vcpu
+            .tsc_offset()
+            .map_err(Error::RestoreTscOffset)?
+        {
+            Some(offset) => offset,
+            None => return Ok(()),
+        };
+
+        for (vcpu, saved_offset) in vcpus.iter().zip(saved_offsets.iter()).skip(1) {
+            let offset = base_offset.wrapping_add(saved_offset.wrapping_sub(saved_offsets[0]));
+            vcpu.lock()
+                .unwrap()
+                .vcpu
+                .set_tsc_offset(offset)
+                .map_err(Error::RestoreTscOffset)?;
+        }
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "aarch64")]
     pub fn init_pmu(&self, irq: u32) -> Result<bool> {
         for cpu in self.vcpus.iter() {
@@ -850,7 +1104,7 @@ impl CpuManager {
     fn start_vcpu(
         &mut self,
         vcpu: Arc<Mutex<Vcpu>>,
-        vcpu_id: u8,
+        vcpu_id: u32,
         vcpu_thread_barrier: Arc<Barrier>,
         inserting: bool,
     ) -> Result<()> {
@@ -864,12 +1118,15 @@ impl CpuManager {
         let vcpu_kill_signalled = self.vcpus_kill_signalled.clone();
         let vcpu_pause_signalled = self.vcpus_pause_signalled.clone();
 
-        let vcpu_kill = self.vcpu_states[usize::from(vcpu_id)].kill.clone();
-        let vcpu_run_interrupted = self.vcpu_states[usize::from(vcpu_id)]
+        let vcpu_kill = self.vcpu_states[vcpu_id as usize].kill.clone();
+        let vcpu_run_interrupted = self.vcpu_states[vcpu_id as usize]
             .vcpu_run_interrupted
             .clone();
         let panic_vcpu_run_interrupted = vcpu_run_interrupted.clone();
-        let vcpu_paused = self.vcpu_states[usize::from(vcpu_id)].paused.clone();
+        let vcpu_paused = self.vcpu_states[vcpu_id as usize].paused.clone();
+        #[cfg(target_arch = "x86_64")]
+        let vcpu_nmi_pending = self.vcpu_states[vcpu_id as usize].nmi_pending.clone();
+        let vcpu_run_stats = self.vcpu_states[vcpu_id as usize].run_stats.clone();
 
         // Prepare the CPU set the current vCPU is expected to run onto.
         let cpuset = self.affinity.get(&vcpu_id).map(|host_cpus| {
@@ -996,8 +1253,20 @@ impl CpuManager {
                             let mut vcpu = vcpu.lock().unwrap();
                             #[cfg(not(feature = "tdx"))]
                             let vcpu = vcpu.lock().unwrap();
+
+                            #[cfg(target_arch = "x86_64")]
+                            if vcpu_nmi_pending.swap(false, Ordering::SeqCst) {
+                                if let Err(e) = vcpu.vcpu.nmi() {
+                                    error!("Failed injecting NMI into vCPU {}: {}", vcpu_id, e);
+                                }
+                            }
+
+                            let run_start = Instant::now();
+                            let run_result = vcpu.run();
+                            vcpu_run_stats.account(run_start.elapsed(), take_exit_handling_time());
+
                             // vcpu.run() returns false on a triple-fault so trigger a reset
-                            match vcpu.run() {
+                            match run_result {
                                 Ok(run) => match run {
                                     #[cfg(feature = "kvm")]
                                     VmExit::Debug => {
@@ -1094,8 +1363,8 @@ impl CpuManager {
 
         // On hot plug calls into this function entry_point is None. It is for
         // those hotplug CPU additions that we need to set the inserting flag.
-        self.vcpu_states[usize::from(vcpu_id)].handle = handle;
-        self.vcpu_states[usize::from(vcpu_id)].inserting = inserting;
+        self.vcpu_states[vcpu_id as usize].handle = handle;
+        self.vcpu_states[vcpu_id as usize].inserting = inserting;
 
         Ok(())
     }
@@ -1103,7 +1372,7 @@ impl CpuManager {
     /// Start up as many vCPUs threads as needed to reach `desired_vcpus`
     fn activate_vcpus(
         &mut self,
-        desired_vcpus: u8,
+        desired_vcpus: u32,
         inserting: bool,
         paused: Option<bool>,
     ) -> Result<()> {
@@ -1138,11 +1407,11 @@ impl CpuManager {
         Ok(())
     }
 
-    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u8) {
+    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u32) {
         // Mark vCPUs for removal, actual removal happens on ejection
         for cpu_id in desired_vcpus..self.present_vcpus() {
-            self.vcpu_states[usize::from(cpu_id)].removing = true;
-            self.vcpu_states[usize::from(cpu_id)]
+            self.vcpu_states[cpu_id as usize].removing = true;
+            self.vcpu_states[cpu_id as usize]
                 .pending_removal
                 .store(true, Ordering::SeqCst);
         }
@@ -1157,9 +1426,9 @@ impl CpuManager {
         false
     }
 
-    fn remove_vcpu(&mut self, cpu_id: u8) -> Result<()> {
+    fn remove_vcpu(&mut self, cpu_id: u32) -> Result<()> {
         info!("Removing vCPU: cpu_id = {}", cpu_id);
-        let state = &mut self.vcpu_states[usize::from(cpu_id)];
+        let state = &mut self.vcpu_states[cpu_id as usize];
         state.kill.store(true, Ordering::SeqCst);
         state.signal_thread();
         state.join_thread()?;
@@ -1187,7 +1456,7 @@ impl CpuManager {
     }
 
     pub fn start_restored_vcpus(&mut self) -> Result<()> {
-        self.activate_vcpus(self.vcpus.len() as u8, false, Some(true))
+        self.activate_vcpus(self.vcpus.len() as u32, false, Some(true))
             .map_err(|e| {
                 Error::StartRestoreVcpu(anyhow!("Failed to start restored vCPUs: {:#?}", e))
             })?;
@@ -1195,7 +1464,7 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn resize(&mut self, desired_vcpus: u8) -> Result<bool> {
+    pub fn resize(&mut self, desired_vcpus: u32) -> Result<bool> {
         if desired_vcpus.cmp(&self.present_vcpus()) == cmp::Ordering::Equal {
             return Ok(false);
         }
@@ -1218,6 +1487,12 @@ impl CpuManager {
                 Ok(true)
             }
             cmp::Ordering::Less => {
+                check_vcpus_removal(
+                    self.config.boot_vcpus,
+                    desired_vcpus,
+                    self.present_vcpus(),
+                    &self.affinity,
+                )?;
                 self.mark_vcpus_for_removal(desired_vcpus);
                 Ok(true)
             }
@@ -1225,6 +1500,37 @@ impl CpuManager {
         }
     }
 
+    /// Returns the run statistics of each active vCPU, keyed by vCPU id: the
+    /// time spent running the guest and the time spent handling exits from
+    /// userspace.
+    pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
+        self.vcpu_states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.active())
+            .map(|(id, state)| (id.to_string(), state.run_stats.counters()))
+            .collect()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn nmi(&self, cpu_id: Option<u32>) -> Result<()> {
+        match cpu_id {
+            Some(cpu_id) => self
+                .vcpu_states
+                .get(cpu_id as usize)
+                .filter(|state| state.active())
+                .ok_or(Error::VcpuNotPresent(cpu_id))?
+                .inject_nmi(),
+            None => {
+                for state in self.vcpu_states.iter().filter(|state| state.active()) {
+                    state.inject_nmi();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn shutdown(&mut self) -> Result<()> {
         // Tell the vCPUs to stop themselves next time they go through the loop
         self.vcpus_kill_signalled.store(true, Ordering::SeqCst);
@@ -1264,11 +1570,11 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn boot_vcpus(&self) -> u8 {
+    pub fn boot_vcpus(&self) -> u32 {
         self.config.boot_vcpus
     }
 
-    pub fn max_vcpus(&self) -> u8 {
+    pub fn max_vcpus(&self) -> u32 {
         self.config.max_vcpus
     }
 
@@ -1278,10 +1584,10 @@ impl CpuManager {
         self.cpuid.clone()
     }
 
-    fn present_vcpus(&self) -> u8 {
+    pub fn present_vcpus(&self) -> u32 {
         self.vcpu_states
             .iter()
-            .fold(0, |acc, state| acc + state.active() as u8)
+            .fold(0, |acc, state| acc + state.active() as u32)
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -1322,8 +1628,8 @@ impl CpuManager {
                 let lapic = LocalX2Apic {
                     r#type: acpi::ACPI_X2APIC_PROCESSOR,
                     length: 16,
-                    processor_id: cpu.into(),
-                    apic_id: cpu.into(),
+                    processor_id: cpu,
+                    apic_id: cpu,
                     flags: if cpu < self.config.boot_vcpus {
                         1 << MADT_CPU_ENABLE_FLAG
                     } else {
@@ -1445,8 +1751,10 @@ impl CpuManager {
         // If topology is not specified, the default setting is:
         // 1 package, multiple cores, 1 thread per core
         // This is also the behavior when PPTT is missing.
-        let (threads_per_core, cores_per_package, packages) =
-            self.get_vcpu_topology().unwrap_or((1, self.max_vcpus(), 1));
+        let (threads_per_core, cores_per_package, packages) = self
+            .get_vcpu_topology()
+            .map(|(t, c, p)| (u32::from(t), u32::from(c), u32::from(p)))
+            .unwrap_or((1, self.max_vcpus(), 1));
 
         let mut pptt = Sdt::new(*b"PPTT", 36, 2, *b"CLOUDH", *b"CHPPTT  ", 1);
 
@@ -1459,7 +1767,7 @@ impl CpuManager {
                     reserved: 0,
                     flags: 0x2,
                     parent: 0,
-                    acpi_processor_id: cluster_idx as u32,
+                    acpi_processor_id: cluster_idx,
                     num_private_resources: 0,
                 };
                 pptt.append(cluster_hierarchy_node);
@@ -1474,7 +1782,7 @@ impl CpuManager {
                             reserved: 0,
                             flags: 0x2,
                             parent: cluster_offset as u32,
-                            acpi_processor_id: core_idx as u32,
+                            acpi_processor_id: core_idx,
                             num_private_resources: 0,
                         };
                         pptt.append(core_hierarchy_node);
@@ -1515,8 +1823,8 @@ impl CpuManager {
     }
 
     #[cfg(feature = "guest_debug")]
-    fn get_regs(&self, cpu_id: u8) -> Result<StandardRegisters> {
-        self.vcpus[usize::from(cpu_id)]
+    fn get_regs(&self, cpu_id: u32) -> Result<StandardRegisters> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1525,8 +1833,8 @@ impl CpuManager {
     }
 
     #[cfg(feature = "guest_debug")]
-    fn set_regs(&self, cpu_id: u8, regs: &StandardRegisters) -> Result<()> {
-        self.vcpus[usize::from(cpu_id)]
+    fn set_regs(&self, cpu_id: u32, regs: &StandardRegisters) -> Result<()> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1535,8 +1843,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn get_sregs(&self, cpu_id: u8) -> Result<SpecialRegisters> {
-        self.vcpus[usize::from(cpu_id)]
+    fn get_sregs(&self, cpu_id: u32) -> Result<SpecialRegisters> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1545,8 +1853,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn set_sregs(&self, cpu_id: u8, sregs: &SpecialRegisters) -> Result<()> {
-        self.vcpus[usize::from(cpu_id)]
+    fn set_sregs(&self, cpu_id: u32, sregs: &SpecialRegisters) -> Result<()> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1558,10 +1866,10 @@ impl CpuManager {
     fn translate_gva(
         &self,
         _guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
-        cpu_id: u8,
+        cpu_id: u32,
         gva: u64,
     ) -> Result<u64> {
-        let (gpa, _) = self.vcpus[usize::from(cpu_id)]
+        let (gpa, _) = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1590,22 +1898,22 @@ impl CpuManager {
     fn translate_gva(
         &self,
         guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
-        cpu_id: u8,
+        cpu_id: u32,
         gva: u64,
     ) -> Result<u64> {
-        let tcr_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let tcr_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
             .get_sys_reg(regs::TCR_EL1)
             .map_err(|e| Error::TranslateVirtualAddress(e.into()))?;
-        let ttbr1_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let ttbr1_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
             .get_sys_reg(regs::TTBR1_EL1)
             .map_err(|e| Error::TranslateVirtualAddress(e.into()))?;
-        let id_aa64mmfr0_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let id_aa64mmfr0_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1753,7 +2061,7 @@ impl CpuManager {
 }
 
 struct Cpu {
-    cpu_id: u8,
+    cpu_id: u32,
     proximity_domain: u32,
     dynamic: bool,
 }
@@ -1770,8 +2078,8 @@ impl Cpu {
         let lapic = LocalX2Apic {
             r#type: crate::acpi::ACPI_X2APIC_PROCESSOR,
             length: 16,
-            processor_id: self.cpu_id.into(),
-            apic_id: self.cpu_id.into(),
+            processor_id: self.cpu_id,
+            apic_id: self.cpu_id,
             flags: 1 << MADT_CPU_ENABLE_FLAG,
             _reserved: 0,
         };
@@ -1872,7 +2180,7 @@ impl Aml for Cpu {
 }
 
 struct CpuNotify {
-    cpu_id: u8,
+    cpu_id: u32,
 }
 
 impl Aml for CpuNotify {
@@ -1887,7 +2195,7 @@ impl Aml for CpuNotify {
 }
 
 struct CpuMethods {
-    max_vcpus: u8,
+    max_vcpus: u32,
     dynamic: bool,
 }
 
@@ -1925,7 +2233,7 @@ impl Aml for CpuMethods {
 
             let mut cpu_notifies_refs: Vec<&dyn Aml> = Vec::new();
             for cpu_id in 0..self.max_vcpus {
-                cpu_notifies_refs.push(&cpu_notifies[usize::from(cpu_id)]);
+                cpu_notifies_refs.push(&cpu_notifies[cpu_id as usize]);
             }
 
             aml::Method::new("CTFY".into(), 2, true, cpu_notifies_refs).to_aml_bytes(sink);
@@ -2109,8 +2417,9 @@ impl Pausable for CpuManager {
         for vcpu in self.vcpus.iter() {
             let mut vcpu = vcpu.lock().unwrap();
             vcpu.pause()?;
+            // Without the hypervisor leaves the guest doesn't use kvmclock
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-            if !self.config.kvm_hyperv {
+            if !self.config.kvm_hyperv && self.config.hypervisor_cpuid {
                 vcpu.vcpu.notify_guest_clock_paused().map_err(|e| {
                     MigratableError::Pause(anyhow!(
                         "Could not notify guest it has been paused {:?}",
@@ -2204,7 +2513,7 @@ impl Debuggable for CpuManager {
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError> {
         // General registers: RAX, RBX, RCX, RDX, RSI, RDI, RBP, RSP, r8-r15
         let gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let regs = [
             gregs.rax, gregs.rbx, gregs.rcx, gregs.rdx, gregs.rsi, gregs.rdi, gregs.rbp, gregs.rsp,
@@ -2218,7 +2527,7 @@ impl Debuggable for CpuManager {
 
         // Segment registers: CS, SS, DS, ES, FS, GS
         let sregs = self
-            .get_sregs(cpu_id as u8)
+            .get_sregs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let segments = X86SegmentRegs {
             cs: sregs.cs.selector as u32,
@@ -2243,7 +2552,7 @@ impl Debuggable for CpuManager {
     #[cfg(target_arch = "aarch64")]
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError> {
         let gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         Ok(CoreRegs {
             x: gregs.regs.regs,
@@ -2260,7 +2569,7 @@ impl Debuggable for CpuManager {
         regs: &CoreRegs,
     ) -> std::result::Result<(), DebuggableError> {
         let orig_gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let gregs = StandardRegisters {
             rax: regs.regs[0],
@@ -2284,13 +2593,13 @@ impl Debuggable for CpuManager {
             rflags: (orig_gregs.rflags & !(u32::MAX as u64)) | (regs.eflags as u64),
         };
 
-        self.set_regs(cpu_id as u8, &gregs)
+        self.set_regs(cpu_id as u32, &gregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         // Segment registers: CS, SS, DS, ES, FS, GS
         // Since GDB care only selectors, we call get_sregs() first.
         let mut sregs = self
-            .get_sregs(cpu_id as u8)
+            .get_sregs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         sregs.cs.selector = regs.segments.cs as u16;
         sregs.ss.selector = regs.segments.ss as u16;
@@ -2299,7 +2608,7 @@ impl Debuggable for CpuManager {
         sregs.fs.selector = regs.segments.fs as u16;
         sregs.gs.selector = regs.segments.gs as u16;
 
-        self.set_sregs(cpu_id as u8, &sregs)
+        self.set_sregs(cpu_id as u32, &sregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         // TODO: Add other registers
@@ -2314,14 +2623,14 @@ impl Debuggable for CpuManager {
         regs: &CoreRegs,
     ) -> std::result::Result<(), DebuggableError> {
         let mut gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
 
         gregs.regs.regs = regs.x;
         gregs.regs.sp = regs.sp;
         gregs.regs.pc = regs.pc;
 
-        self.set_regs(cpu_id as u8, &gregs)
+        self.set_regs(cpu_id as u32, &gregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         Ok(())
@@ -2339,7 +2648,7 @@ impl Debuggable for CpuManager {
 
         while total_read < len as u64 {
             let gaddr = vaddr.0 + total_read;
-            let paddr = match self.translate_gva(guest_memory, cpu_id as u8, gaddr) {
+            let paddr = match self.translate_gva(guest_memory, cpu_id as u32, gaddr) {
                 Ok(paddr) => paddr,
                 Err(_) if gaddr == u64::MIN => gaddr, // Silently return GVA as GPA if GVA == 0.
                 Err(e) => return Err(DebuggableError::TranslateGva(e)),
@@ -2369,7 +2678,7 @@ impl Debuggable for CpuManager {
 
         while total_written < data.len() as u64 {
             let gaddr = vaddr.0 + total_written;
-            let paddr = match self.translate_gva(guest_memory, cpu_id as u8, gaddr) {
+            let paddr = match self.translate_gva(guest_memory, cpu_id as u32, gaddr) {
                 Ok(paddr) => paddr,
                 Err(_) if gaddr == u64::MIN => gaddr, // Silently return GVA as GPA if GVA == 0.
                 Err(e) => return Err(DebuggableError::TranslateGva(e)),
@@ -2434,7 +2743,7 @@ impl CpuElf64Writable for CpuManager {
             pos += descsz - size_of::<X86_64UserRegs>() - size_of::<u64>();
 
             let orig_rax: u64 = 0;
-            let gregs = self.vcpus[usize::from(vcpu_id)]
+            let gregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2449,7 +2758,7 @@ impl CpuElf64Writable for CpuManager {
                 gregs.r9, gregs.r8, gregs.rax, gregs.rcx, gregs.rdx, gregs.rsi, gregs.rdi, orig_rax,
             ];
 
-            let sregs = self.vcpus[usize::from(vcpu_id)]
+            let sregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2523,7 +2832,7 @@ impl CpuElf64Writable for CpuManager {
 
             pos += round_up!(COREDUMP_NAME_SIZE as usize, 4);
 
-            let gregs = self.vcpus[usize::from(vcpu_id)]
+            let gregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2540,7 +2849,7 @@ impl CpuElf64Writable for CpuManager {
                 gregs.r15,
             ];
 
-            let sregs = self.vcpus[usize::from(vcpu_id)]
+            let sregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2609,9 +2918,34 @@ impl CpuElf64Writable for CpuManager {
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 #[cfg(test)]
 mod tests {
+    use super::{check_vcpus_removal, Error};
     use arch::x86_64::interrupts::*;
     use arch::x86_64::regs::*;
     use hypervisor::arch::x86::{FpuState, LapicState, StandardRegisters};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_check_vcpus_removal() {
+        let mut affinity = BTreeMap::new();
+        assert!(check_vcpus_removal(2, 2, 4, &affinity).is_ok());
+        assert!(check_vcpus_removal(1, 1, 4, &affinity).is_ok());
+        assert!(matches!(
+            check_vcpus_removal(2, 1, 4, &affinity),
+            Err(Error::RemoveBootVcpus(2))
+        ));
+        assert!(matches!(
+            check_vcpus_removal(1, 0, 4, &affinity),
+            Err(Error::RemoveBootVcpus(1))
+        ));
+
+        affinity.insert(3, vec![0]);
+        assert!(matches!(
+            check_vcpus_removal(2, 2, 4, &affinity),
+            Err(Error::RemovePinnedVcpu(3))
+        ));
+        // The pinned vCPU isn't part of the removed ones.
+        assert!(check_vcpus_removal(2, 2, 3, &affinity).is_ok());
+    }
 
     #[test]
     fn test_setlint() {
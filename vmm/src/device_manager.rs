@@ -9,12 +9,18 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+#[cfg(target_arch = "x86_64")]
+use crate::config::FlowControl;
 use crate::config::{
-    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, UserDeviceConfig,
-    VdpaConfig, VhostMode, VmConfig, VsockConfig,
+    CacheMode, ConsoleInputConfig, ConsoleOutputMode, DeviceConfig, DiskConfig, DiskLocking,
+    DiskMedia, FsConfig, NetConfig, PmemConfig, RngSource, ScsiConfig, UserDeviceConfig,
+    VdpaConfig, VhostMode, VirtioTransportType, VmConfig, VsockConfig,
+    DEFAULT_IOMMU_ADDRESS_WIDTH_BITS,
 };
+use crate::console_input::start_console_input;
 use crate::cpu::{CpuManager, CPU_MANAGER_ACPI_SIZE};
 use crate::device_tree::{DeviceNode, DeviceTree};
+use crate::identity::{default_net_mac, platform_disk_serial, IdentitySeed};
 use crate::interrupt::LegacyUserspaceInterruptManager;
 use crate::interrupt::MsiInterruptManager;
 use crate::memory_manager::{Error as MemoryManagerError, MemoryManager, MEMORY_MANAGER_ACPI_SIZE};
@@ -36,8 +42,9 @@ use arch::NumaNodes;
 use arch::{DeviceType, MmioDeviceInfo};
 use block::{
     async_io::DiskFile, block_io_uring_is_supported, detect_image_type,
-    fixed_vhd_sync::FixedVhdDiskSync, qcow, qcow_sync::QcowDiskSync, raw_sync::RawFileDiskSync,
-    vhdx, vhdx_sync::VhdxDiskSync, ImageType,
+    fixed_vhd_sync::FixedVhdDiskSync, http, http_sync::HttpDiskSync, overlay,
+    overlay_sync::OverlayDiskSync, qcow, qcow_sync::QcowDiskSync, raw_sync::RawFileDiskSync, vhdx,
+    vhdx_sync::VhdxDiskSync, DiskTopology, ImageType,
 };
 #[cfg(feature = "io_uring")]
 use block::{fixed_vhd_async::FixedVhdDiskAsync, raw_async::RawFileDisk};
@@ -57,32 +64,36 @@ use libc::{
     cfmakeraw, isatty, tcgetattr, tcsetattr, termios, MAP_NORESERVE, MAP_PRIVATE, MAP_SHARED,
     O_TMPFILE, PROT_READ, PROT_WRITE, TCSANOW,
 };
+use net_util::Tap;
 use pci::{
     DeviceRelocation, PciBarRegionType, PciBdf, PciDevice, VfioPciDevice, VfioUserDmaMapping,
     VfioUserPciDevice, VfioUserPciDeviceError,
 };
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap};
+use serial_buffer::{RotatingFile, TimestampWriter};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{read_link, File, OpenOptions};
 use std::io::{self, stdout, Seek, SeekFrom};
 use std::mem::zeroed;
 use std::num::Wrapping;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tracer::trace_scoped;
 use vfio_ioctls::{VfioContainer, VfioDevice, VfioDeviceFd};
 use virtio_devices::transport::VirtioTransport;
-use virtio_devices::transport::{VirtioPciDevice, VirtioPciDeviceActivator};
+use virtio_devices::transport::{
+    VirtioDeviceActivator, VirtioMmioDevice, VirtioPciDevice, VIRTIO_MMIO_DEVICE_SIZE,
+};
 use virtio_devices::vhost_user::VhostUserConfig;
 use virtio_devices::{
     AccessPlatformMapping, ActivateError, VdpaDmaMapping, VirtioMemMappingSource,
 };
-use virtio_devices::{Endpoint, IommuMapping};
+use virtio_devices::{Endpoint, IommuMapping, NetRateLimitGroup};
 use vm_allocator::{AddressAllocator, SystemAllocator};
 use vm_device::dma_mapping::vfio::VfioDmaMapping;
 use vm_device::dma_mapping::ExternalDmaMapping;
@@ -112,11 +123,11 @@ const IOAPIC_DEVICE_NAME: &str = "__ioapic";
 const SERIAL_DEVICE_NAME: &str = "__serial";
 #[cfg(target_arch = "aarch64")]
 const GPIO_DEVICE_NAME: &str = "__gpio";
-const RNG_DEVICE_NAME: &str = "__rng";
-const IOMMU_DEVICE_NAME: &str = "__iommu";
-const BALLOON_DEVICE_NAME: &str = "__balloon";
-const CONSOLE_DEVICE_NAME: &str = "__console";
-const PVPANIC_DEVICE_NAME: &str = "__pvpanic";
+pub(crate) const RNG_DEVICE_NAME: &str = "__rng";
+pub(crate) const IOMMU_DEVICE_NAME: &str = "__iommu";
+pub(crate) const BALLOON_DEVICE_NAME: &str = "__balloon";
+pub(crate) const CONSOLE_DEVICE_NAME: &str = "__console";
+pub(crate) const PVPANIC_DEVICE_NAME: &str = "__pvpanic";
 
 // Devices that the user may name and for which we generate
 // identifiers if the user doesn't give one
@@ -124,12 +135,14 @@ const DISK_DEVICE_NAME_PREFIX: &str = "_disk";
 const FS_DEVICE_NAME_PREFIX: &str = "_fs";
 const NET_DEVICE_NAME_PREFIX: &str = "_net";
 const PMEM_DEVICE_NAME_PREFIX: &str = "_pmem";
+const SCSI_DEVICE_NAME_PREFIX: &str = "_scsi";
 const VDPA_DEVICE_NAME_PREFIX: &str = "_vdpa";
 const VSOCK_DEVICE_NAME_PREFIX: &str = "_vsock";
-const WATCHDOG_DEVICE_NAME: &str = "__watchdog";
+pub(crate) const WATCHDOG_DEVICE_NAME: &str = "__watchdog";
 const VFIO_DEVICE_NAME_PREFIX: &str = "_vfio";
 const VFIO_USER_DEVICE_NAME_PREFIX: &str = "_vfio_user";
 const VIRTIO_PCI_DEVICE_NAME_PREFIX: &str = "_virtio-pci";
+const VIRTIO_MMIO_DEVICE_NAME_PREFIX: &str = "_virtio-mmio";
 
 /// Errors associated with device manager
 #[derive(Debug)]
@@ -146,15 +159,24 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-blk device
     CreateVirtioBlock(io::Error),
 
+    /// Cannot probe the IO alignment of the disk
+    ProbeDiskAlignment(io::Error),
+
     /// Cannot create virtio-net device
     CreateVirtioNet(virtio_devices::net::Error),
 
+    /// Undefined rate limit group
+    UnknownRateLimitGroup(String),
+
     /// Cannot create virtio-console device
     CreateVirtioConsole(io::Error),
 
     /// Cannot create virtio-rng device
     CreateVirtioRng(io::Error),
 
+    /// Cannot create virtio-scsi device
+    CreateVirtioScsi(io::Error),
+
     /// Cannot create virtio-fs device
     CreateVirtioFs(virtio_devices::vhost_user::Error),
 
@@ -179,6 +201,9 @@ pub enum DeviceManagerError {
     /// Failed to convert Path to &str for the vDPA device.
     CreateVdpaConvertPath,
 
+    /// The vDPA device isn't of the expected virtio device type.
+    VdpaDeviceTypeMismatch(VirtioDeviceType, u32),
+
     /// Failed to convert Path to &str for the virtio-vsock device.
     CreateVsockConvertPath,
 
@@ -251,6 +276,12 @@ pub enum DeviceManagerError {
     /// Error creating console output file
     ConsoleOutputFileOpen(io::Error),
 
+    /// Error setting up serial input from a file
+    SerialInputStart(io::Error),
+
+    /// Error setting up console input from a file
+    ConsoleInputStart(io::Error),
+
     /// Error creating serial pty
     SerialPtyOpen(io::Error),
 
@@ -362,6 +393,9 @@ pub enum DeviceManagerError {
     /// No disk path was specified when one was expected
     NoDiskPath,
 
+    /// Cannot build a disk image from a directory
+    CreateDirectoryDiskImage(block::ext2::Error),
+
     /// Failed to update guest memory for virtio device.
     UpdateMemoryForVirtioDevice(virtio_devices::Error),
 
@@ -426,6 +460,33 @@ pub enum DeviceManagerError {
     /// Failed to create FixedVhdxDiskSync
     CreateFixedVhdxDiskSync(vhdx::VhdxError),
 
+    /// Failed to create OverlayDiskSync
+    CreateOverlayDiskSync(overlay::OverlayError),
+
+    /// Failed to create HttpDiskSync
+    CreateHttpDiskSync(http::HttpDiskError),
+
+    /// Cannot open the overlay of a disk
+    OpenDiskOverlay(io::Error),
+
+    /// Cannot remove the overlay of a disk to be discarded
+    RemoveDiskOverlay(io::Error),
+
+    /// Overlays can only be stacked on raw disk images
+    OverlayNotRaw,
+
+    /// Request timeout is only supported by the io_uring disk backends
+    RequestTimeoutUnsupported,
+
+    /// Disk image is locked by another process
+    DiskImageInUse(PathBuf),
+
+    /// Disks attached to a virtio-scsi controller must be raw images
+    ScsiDiskNotRaw(PathBuf),
+
+    /// Cannot lock the disk image
+    LockDiskImage(io::Error),
+
     /// Failed to add DMA mapping handler to virtio-mem device.
     AddDmaMappingHandlerVirtioMem(virtio_devices::mem::Error),
 
@@ -476,9 +537,90 @@ pub enum DeviceManagerError {
 
     /// Cannot create a PvPanic device
     PvPanicCreate(devices::pvpanic::PvPanicError),
+
+    /// Cannot create a virtio-mmio device
+    VirtioMmioDevice(virtio_devices::transport::VirtioMmioDeviceError),
+
+    /// Cannot hotplug or unplug devices when ACPI is disabled
+    HotplugWithoutAcpi,
+
+    /// Cannot hotplug or unplug virtio devices with the virtio-mmio transport
+    HotplugWithMmioTransport,
 }
 pub type DeviceManagerResult<T> = result::Result<T, DeviceManagerError>;
 
+/// Generates a name made of `prefix` and of the device counter
+/// `device_id_cnt`, skipping the names for which `in_use` returns true.
+pub(crate) fn next_device_name(
+    device_id_cnt: &mut Wrapping<usize>,
+    prefix: &str,
+    in_use: impl Fn(&str) -> bool,
+) -> DeviceManagerResult<String> {
+    let start_id = *device_id_cnt;
+    loop {
+        // Generate the temporary name.
+        let name = format!("{}{}", prefix, device_id_cnt);
+        // Increment the counter.
+        *device_id_cnt += Wrapping(1);
+        // Check if the name is already in use.
+        if !in_use(&name) {
+            return Ok(name);
+        }
+
+        if *device_id_cnt == start_id {
+            // We went through a full loop and there's nothing else we can
+            // do.
+            break;
+        }
+    }
+    Err(DeviceManagerError::NoAvailableDeviceName)
+}
+
+/// Names the devices of `config` which don't have an identifier yet, in the
+/// order the DeviceManager creates them, with `next_name` generating the
+/// name for a given prefix.
+pub(crate) fn name_devices(
+    config: &mut VmConfig,
+    mut next_name: impl FnMut(&str) -> DeviceManagerResult<String>,
+) -> DeviceManagerResult<()> {
+    let mut name = |id: &mut Option<String>, prefix: &str| -> DeviceManagerResult<()> {
+        if id.is_none() {
+            *id = Some(next_name(prefix)?);
+        }
+        Ok(())
+    };
+
+    for disk in config.disks.iter_mut().flatten() {
+        name(&mut disk.id, DISK_DEVICE_NAME_PREFIX)?;
+    }
+    for net in config.net.iter_mut().flatten() {
+        name(&mut net.id, NET_DEVICE_NAME_PREFIX)?;
+    }
+    for fs in config.fs.iter_mut().flatten() {
+        name(&mut fs.id, FS_DEVICE_NAME_PREFIX)?;
+    }
+    for pmem in config.pmem.iter_mut().flatten() {
+        name(&mut pmem.id, PMEM_DEVICE_NAME_PREFIX)?;
+    }
+    if let Some(vsock) = config.vsock.as_mut() {
+        name(&mut vsock.id, VSOCK_DEVICE_NAME_PREFIX)?;
+    }
+    for vdpa in config.vdpa.iter_mut().flatten() {
+        name(&mut vdpa.id, VDPA_DEVICE_NAME_PREFIX)?;
+    }
+    for scsi in config.scsi.iter_mut().flatten() {
+        name(&mut scsi.id, SCSI_DEVICE_NAME_PREFIX)?;
+    }
+    for device in config.devices.iter_mut().flatten() {
+        name(&mut device.id, VFIO_DEVICE_NAME_PREFIX)?;
+    }
+    for device in config.user_devices.iter_mut().flatten() {
+        name(&mut device.id, VFIO_USER_DEVICE_NAME_PREFIX)?;
+    }
+
+    Ok(())
+}
+
 const DEVICE_MANAGER_ACPI_SIZE: usize = 0x10;
 
 const TIOCSPTLCK: libc::c_int = 0x4004_5431;
@@ -530,6 +672,79 @@ pub fn create_pty() -> io::Result<(File, File, PathBuf)> {
     Ok((main, unsafe { File::from_raw_fd(sub_fd) }, path))
 }
 
+// Terminal used by the serial port and the console in tty mode. Its mode is
+// looked up and changed through the standard input when it is a terminal, as
+// the mode matters most to the keys typed, and through the standard output
+// otherwise.
+fn terminal_fd() -> RawFd {
+    // SAFETY: FFI call. Trivially safe.
+    if unsafe { isatty(libc::STDIN_FILENO) } == 1 {
+        libc::STDIN_FILENO
+    } else {
+        libc::STDOUT_FILENO
+    }
+}
+
+/// Restores the mode the terminal of the VMM had before being put in raw mode.
+pub fn restore_terminal_mode(termios: &termios) {
+    // SAFETY: FFI call, the syscall only reads the extent of termios.
+    let _ = unsafe { tcsetattr(terminal_fd(), TCSANOW, termios) };
+}
+
+// The image is built in an unnamed temporary file, which goes away as soon
+// as the disk is closed.
+fn create_directory_disk_image(dir: &Path) -> DeviceManagerResult<File> {
+    let image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .open(std::env::temp_dir())
+        .map_err(DeviceManagerError::Disk)?;
+    block::ext2::create_image(dir, &image).map_err(DeviceManagerError::CreateDirectoryDiskImage)?;
+
+    Ok(image)
+}
+
+// Takes an advisory lock on a disk image for as long as the file stays open.
+// The lock is shared when the image is only read, so that several VMs can
+// boot from the same read-only image, but never along with a VM writing to it.
+fn lock_disk_image(file: &File, path: &Path, exclusive: bool) -> DeviceManagerResult<()> {
+    let operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    // SAFETY: FFI call with a valid fd
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::WouldBlock {
+            error!("Disk image {:?} is in use by another process", path);
+            return Err(DeviceManagerError::DiskImageInUse(path.to_path_buf()));
+        }
+        return Err(DeviceManagerError::LockDiskImage(e));
+    }
+
+    Ok(())
+}
+
+// Opens and locks a disk image attached to a virtio-scsi controller, which
+// only exposes raw images.
+fn open_scsi_disk_image(path: &Path, readonly: bool) -> DeviceManagerResult<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(!readonly)
+        .open(path)
+        .map_err(DeviceManagerError::Disk)?;
+    lock_disk_image(&file, path, !readonly)?;
+
+    let image_type = detect_image_type(&mut file).map_err(DeviceManagerError::DetectImageType)?;
+    if !matches!(image_type, ImageType::Raw) {
+        return Err(DeviceManagerError::ScsiDiskNotRaw(path.to_path_buf()));
+    }
+
+    Ok(file)
+}
+
 #[derive(Default)]
 pub struct Console {
     console_resizer: Option<Arc<virtio_devices::ConsoleResizer>>,
@@ -807,6 +1022,31 @@ struct MetaVirtioDevice {
     dma_handler: Option<Arc<dyn ExternalDmaMapping>>,
 }
 
+/// Host resources backing the devices, which outlive the guest facing
+/// devices so that they can be reused when the VM is rebooted.
+#[derive(Default)]
+pub struct DeviceBackends {
+    // Tap interfaces of the virtio-net devices, indexed by device id.
+    taps: HashMap<String, Vec<Tap>>,
+    // Backing files of the virtio-block devices, indexed by device id.
+    disk_files: HashMap<String, File>,
+    // Copy-on-write overlays of the virtio-block devices, indexed by device
+    // id. Discarded overlays are only reachable from here once unlinked.
+    disk_overlays: HashMap<String, File>,
+    // Backing files of the virtio-scsi devices, indexed by device id, in
+    // LUN order.
+    scsi_disk_files: HashMap<String, Vec<File>>,
+}
+
+impl DeviceBackends {
+    fn remove(&mut self, id: &str) {
+        self.taps.remove(id);
+        self.disk_files.remove(id);
+        self.disk_overlays.remove(id);
+        self.scsi_disk_files.remove(id);
+    }
+}
+
 #[derive(Default)]
 pub struct AcpiPlatformAddresses {
     pub pm_timer_address: Option<GenericAddress>,
@@ -837,6 +1077,9 @@ pub struct DeviceManager {
     // pty foreground status,
     console_resize_pipe: Option<Arc<File>>,
 
+    // Write ends of the pipes feeding serial and console input from a file
+    console_input_pipes: Vec<File>,
+
     // To restore on exit.
     original_termios_opt: Arc<Mutex<Option<termios>>>,
 
@@ -846,8 +1089,8 @@ pub struct DeviceManager {
     #[cfg(target_arch = "aarch64")]
     interrupt_controller: Option<Arc<Mutex<gic::Gic>>>,
 
-    // Things to be added to the commandline (e.g. aarch64 early console)
-    #[cfg(target_arch = "aarch64")]
+    // Things to be added to the commandline (e.g. aarch64 early console or
+    // virtio-mmio devices on x86_64)
     cmdline_additions: Vec<String>,
 
     // ACPI GED notification device
@@ -940,6 +1183,9 @@ pub struct DeviceManager {
     // pvpanic device
     pvpanic_device: Option<Arc<Mutex<devices::PvPanicDevice>>>,
 
+    // Possible handle to the virtio-watchdog device
+    watchdog: Option<Arc<Mutex<virtio_devices::Watchdog>>>,
+
     // Flag to force setting the iommu on virtio devices
     force_iommu: bool,
 
@@ -949,16 +1195,30 @@ pub struct DeviceManager {
     // List of unique identifiers provided at boot through the configuration.
     boot_id_list: BTreeSet<String>,
 
+    // Guest visible labels of the devices not yet placed on a PCI slot,
+    // indexed by device identifier.
+    pending_device_labels: HashMap<String, String>,
+
     // Start time of the VM
     timestamp: Instant,
 
     // Pending activations
-    pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+    pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
 
     // Addresses for ACPI platform devices e.g. ACPI PM timer, sleep/reset registers
     acpi_platform_addresses: AcpiPlatformAddresses,
 
     snapshot: Option<Snapshot>,
+
+    // Host resources backing the devices created by this DeviceManager
+    backends: DeviceBackends,
+
+    // Host resources left by a previous DeviceManager, to be reused by the
+    // devices with the same identifier
+    reusable_backends: DeviceBackends,
+
+    // Rate limits shared by the network devices, indexed by group identifier
+    rate_limit_groups: HashMap<String, NetRateLimitGroup>,
 }
 
 impl DeviceManager {
@@ -994,6 +1254,20 @@ impl DeviceManager {
             (Arc::new(Mutex::new(DeviceTree::new())), Wrapping(0))
         };
 
+        let rate_limit_groups = config
+            .lock()
+            .unwrap()
+            .rate_limit_groups
+            .iter()
+            .flatten()
+            .map(|group| {
+                (
+                    group.id.clone(),
+                    NetRateLimitGroup::new(group.rate_limiter_config),
+                )
+            })
+            .collect();
+
         let num_pci_segments =
             if let Some(platform_config) = config.lock().unwrap().platform.as_ref() {
                 platform_config.num_pci_segments
@@ -1093,7 +1367,6 @@ impl DeviceManager {
             address_manager: Arc::clone(&address_manager),
             console: Arc::new(Console::default()),
             interrupt_controller: None,
-            #[cfg(target_arch = "aarch64")]
             cmdline_additions: Vec::new(),
             ged_notification_device: None,
             config,
@@ -1127,18 +1400,24 @@ impl DeviceManager {
             serial_manager: None,
             console_pty: None,
             console_resize_pipe: None,
+            console_input_pipes: Vec::new(),
             original_termios_opt: Arc::new(Mutex::new(None)),
             virtio_mem_devices: Vec::new(),
             #[cfg(target_arch = "aarch64")]
             gpio_device: None,
             pvpanic_device: None,
+            watchdog: None,
             force_iommu,
             io_uring_supported: None,
             boot_id_list,
+            pending_device_labels: HashMap::new(),
             timestamp,
             pending_activations: Arc::new(Mutex::new(Vec::default())),
             acpi_platform_addresses: AcpiPlatformAddresses::default(),
             snapshot,
+            backends: DeviceBackends::default(),
+            reusable_backends: DeviceBackends::default(),
+            rate_limit_groups,
         };
 
         let device_manager = Arc::new(Mutex::new(device_manager));
@@ -1177,9 +1456,18 @@ impl DeviceManager {
         console_pty: Option<PtyPair>,
         console_resize_pipe: Option<File>,
         original_termios_opt: Arc<Mutex<Option<termios>>>,
+        device_backends: Option<DeviceBackends>,
     ) -> DeviceManagerResult<()> {
         trace_scoped!("create_devices");
 
+        self.reusable_backends = device_backends.unwrap_or_default();
+
+        // Name the devices up front, as the dry run does.
+        let config = self.config.clone();
+        name_devices(&mut config.lock().unwrap(), |prefix| {
+            self.next_device_name(prefix)
+        })?;
+
         let mut virtio_devices: Vec<MetaVirtioDevice> = Vec::new();
 
         let interrupt_controller = self.add_interrupt_controller()?;
@@ -1251,7 +1539,18 @@ impl DeviceManager {
 
         virtio_devices.append(&mut self.make_virtio_devices()?);
 
-        self.add_pci_devices(virtio_devices.clone())?;
+        // With the virtio-mmio transport, only the non virtio devices are
+        // added to the PCI buses.
+        let transport = self.config.lock().unwrap().virtio_transport();
+        match transport {
+            VirtioTransportType::Pci => self.add_pci_devices(virtio_devices.clone())?,
+            VirtioTransportType::Mmio => {
+                for handle in virtio_devices.iter() {
+                    self.add_virtio_mmio_device(handle.virtio_device.clone(), handle.id.clone())?;
+                }
+                self.add_pci_devices(Vec::new())?;
+            }
+        }
 
         self.virtio_devices = virtio_devices;
 
@@ -1289,6 +1588,17 @@ impl DeviceManager {
         &self.id_to_dev_info
     }
 
+    #[cfg(target_arch = "x86_64")]
+    /// Gets the legacy IRQs of the virtio-mmio devices, which are level
+    /// triggered.
+    pub fn virtio_mmio_irqs(&self) -> Vec<u32> {
+        self.id_to_dev_info
+            .iter()
+            .filter(|((device_type, _), _)| matches!(device_type, DeviceType::Virtio(_)))
+            .map(|(_, info)| info.irq)
+            .collect()
+    }
+
     #[allow(unused_variables)]
     fn add_pci_devices(
         &mut self,
@@ -1297,6 +1607,14 @@ impl DeviceManager {
         let iommu_id = String::from(IOMMU_DEVICE_NAME);
 
         let iommu_device = if self.config.lock().unwrap().iommu {
+            let address_width_bits = self
+                .config
+                .lock()
+                .unwrap()
+                .platform
+                .as_ref()
+                .map(|pc| pc.iommu_address_width_bits)
+                .unwrap_or(DEFAULT_IOMMU_ADDRESS_WIDTH_BITS);
             let (device, mapping) = virtio_devices::Iommu::new(
                 iommu_id.clone(),
                 self.seccomp_action.clone(),
@@ -1304,6 +1622,7 @@ impl DeviceManager {
                     .try_clone()
                     .map_err(DeviceManagerError::EventFd)?,
                 self.get_msi_iova_space(),
+                address_width_bits,
                 versioned_state_from_id(self.snapshot.as_ref(), iommu_id.as_str())
                     .map_err(DeviceManagerError::RestoreGetState)?,
             )
@@ -1872,14 +2191,15 @@ impl DeviceManager {
         Ok(serial)
     }
 
+    // Modifies the mode of a terminal, returning its previous mode. Nothing is
+    // done when the file descriptor doesn't refer to a terminal.
     fn modify_mode<F: FnOnce(&mut termios)>(
-        &mut self,
         fd: RawFd,
         f: F,
-    ) -> vmm_sys_util::errno::Result<()> {
+    ) -> vmm_sys_util::errno::Result<Option<termios>> {
         // SAFETY: safe because we check the return value of isatty.
         if unsafe { isatty(fd) } != 1 {
-            return Ok(());
+            return Ok(None);
         }
 
         // SAFETY: The following pair are safe because termios gets totally overwritten by tcgetattr
@@ -1890,10 +2210,7 @@ impl DeviceManager {
         if ret < 0 {
             return vmm_sys_util::errno::errno_result();
         }
-        let mut original_termios_opt = self.original_termios_opt.lock().unwrap();
-        if original_termios_opt.is_none() {
-            *original_termios_opt = Some(termios);
-        }
+        let original_termios = termios;
         f(&mut termios);
         // SAFETY: Safe because the syscall will only read the extent of termios and we check
         // the return result.
@@ -1902,12 +2219,27 @@ impl DeviceManager {
             return vmm_sys_util::errno::errno_result();
         }
 
-        Ok(())
+        Ok(Some(original_termios))
     }
 
     fn set_raw_mode(&mut self, f: &dyn AsRawFd) -> vmm_sys_util::errno::Result<()> {
         // SAFETY: FFI call. Variable t is guaranteed to be a valid termios from modify_mode.
-        self.modify_mode(f.as_raw_fd(), |t| unsafe { cfmakeraw(t) })
+        Self::modify_mode(f.as_raw_fd(), |t| unsafe { cfmakeraw(t) }).map(|_| ())
+    }
+
+    // Puts the terminal of the VMM in raw mode, so that the keys are forwarded
+    // to the guest as soon as typed rather than line by line, and without
+    // being echoed or turned into signals. The original mode is kept to be
+    // restored once the VMM exits.
+    fn set_terminal_raw_mode(&mut self) -> vmm_sys_util::errno::Result<()> {
+        // SAFETY: FFI call. Variable t is guaranteed to be a valid termios from modify_mode.
+        let termios = Self::modify_mode(terminal_fd(), |t| unsafe { cfmakeraw(t) })?;
+        let mut original_termios_opt = self.original_termios_opt.lock().unwrap();
+        if original_termios_opt.is_none() {
+            *original_termios_opt = termios;
+        }
+
+        Ok(())
     }
 
     fn listen_for_sigwinch_on_tty(&mut self, pty_sub: File) -> std::io::Result<()> {
@@ -1931,11 +2263,41 @@ impl DeviceManager {
         resize_pipe: Option<File>,
     ) -> DeviceManagerResult<Option<Arc<virtio_devices::ConsoleResizer>>> {
         let console_config = self.config.lock().unwrap().console.clone();
+        let console_input = self
+            .start_console_input(&console_config.input)
+            .map_err(DeviceManagerError::ConsoleInputStart)?;
         let endpoint = match console_config.mode {
+            ConsoleOutputMode::File if console_config.timestamps => {
+                let file = console_config.file.as_ref().unwrap();
+                let writer: Box<dyn io::Write + Send> = if let Some(rotate) = &console_config.rotate
+                {
+                    Box::new(
+                        RotatingFile::create(file, rotate.size, rotate.keep)
+                            .map_err(DeviceManagerError::ConsoleOutputFileOpen)?,
+                    )
+                } else {
+                    Box::new(File::create(file).map_err(DeviceManagerError::ConsoleOutputFileOpen)?)
+                };
+                Endpoint::Timestamped(TimestampWriter::new(writer), console_input)
+            }
+            ConsoleOutputMode::File if console_config.rotate.is_some() => {
+                let rotate = console_config.rotate.as_ref().unwrap();
+                let file = RotatingFile::create(
+                    console_config.file.as_ref().unwrap(),
+                    rotate.size,
+                    rotate.keep,
+                )
+                .map_err(DeviceManagerError::ConsoleOutputFileOpen)?;
+                Endpoint::RotatingFile(file, console_input)
+            }
             ConsoleOutputMode::File => {
                 let file = File::create(console_config.file.as_ref().unwrap())
                     .map_err(DeviceManagerError::ConsoleOutputFileOpen)?;
-                Endpoint::File(file)
+                if let Some(input) = console_input {
+                    Endpoint::FilePair(file, input)
+                } else {
+                    Endpoint::File(file)
+                }
             }
             ConsoleOutputMode::Pty => {
                 if let Some(pty) = console_pty {
@@ -1952,7 +2314,9 @@ impl DeviceManager {
                     self.config.lock().unwrap().console.file = Some(path.clone());
                     let file = main.try_clone().unwrap();
                     assert!(resize_pipe.is_none());
-                    self.listen_for_sigwinch_on_tty(sub).unwrap();
+                    if console_config.resize {
+                        self.listen_for_sigwinch_on_tty(sub).unwrap();
+                    }
                     self.console_pty = Some(Arc::new(Mutex::new(PtyPair { main, path })));
                     Endpoint::PtyPair(file.try_clone().unwrap(), file)
                 }
@@ -1969,18 +2333,22 @@ impl DeviceManager {
                 // SAFETY: stdout is valid and owned solely by us.
                 let stdout = unsafe { File::from_raw_fd(stdout) };
 
-                // Make sure stdout is in raw mode, if it's a terminal.
-                let _ = self.set_raw_mode(&stdout);
+                // Make sure the terminal is in raw mode, if there is one.
+                let _ = self.set_terminal_raw_mode();
 
                 // SAFETY: FFI call. Trivially safe.
-                if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+                if console_config.resize && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
                     self.listen_for_sigwinch_on_tty(stdout.try_clone().unwrap())
                         .unwrap();
                 }
 
                 // If an interactive TTY then we can accept input
                 // SAFETY: FFI call. Trivially safe.
-                if unsafe { libc::isatty(libc::STDIN_FILENO) == 1 } {
+                let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) == 1 };
+                if let Some(input) = console_input {
+                    // Input from a file takes precedence over the TTY input
+                    Endpoint::FilePair(stdout, input)
+                } else if interactive {
                     // SAFETY: FFI call to dup. Trivially safe.
                     let stdin = unsafe { libc::dup(libc::STDIN_FILENO) };
                     if stdin == -1 {
@@ -2034,13 +2402,27 @@ impl DeviceManager {
             .insert(id.clone(), device_node!(id, virtio_console_device));
 
         // Only provide a resizer (for SIGWINCH handling) if the console is attached to the TTY
-        Ok(if matches!(console_config.mode, ConsoleOutputMode::Tty) {
+        let tty = matches!(console_config.mode, ConsoleOutputMode::Tty);
+        Ok(if tty && console_config.resize {
             Some(console_resizer)
         } else {
             None
         })
     }
 
+    fn start_console_input(
+        &mut self,
+        input_config: &Option<ConsoleInputConfig>,
+    ) -> io::Result<Option<File>> {
+        if let Some(input_config) = input_config {
+            let (reader, writer) = start_console_input(input_config)?;
+            self.console_input_pipes.push(writer);
+            Ok(Some(reader))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn add_console_device(
         &mut self,
         interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
@@ -2050,11 +2432,27 @@ impl DeviceManager {
         console_resize_pipe: Option<File>,
     ) -> DeviceManagerResult<Arc<Console>> {
         let serial_config = self.config.lock().unwrap().serial.clone();
+        let serial_input = self
+            .start_console_input(&serial_config.input)
+            .map_err(DeviceManagerError::SerialInputStart)?;
         let serial_writer: Option<Box<dyn io::Write + Send>> = match serial_config.mode {
-            ConsoleOutputMode::File => Some(Box::new(
-                File::create(serial_config.file.as_ref().unwrap())
-                    .map_err(DeviceManagerError::SerialOutputFileOpen)?,
-            )),
+            ConsoleOutputMode::File => {
+                let file = serial_config.file.as_ref().unwrap();
+                let writer: Box<dyn io::Write + Send> = if let Some(rotate) = &serial_config.rotate
+                {
+                    Box::new(
+                        RotatingFile::create(file, rotate.size, rotate.keep)
+                            .map_err(DeviceManagerError::SerialOutputFileOpen)?,
+                    )
+                } else {
+                    Box::new(File::create(file).map_err(DeviceManagerError::SerialOutputFileOpen)?)
+                };
+                if serial_config.timestamps {
+                    Some(Box::new(TimestampWriter::new(writer)))
+                } else {
+                    Some(writer)
+                }
+            }
             ConsoleOutputMode::Pty => {
                 if let Some(pty) = serial_pty {
                     self.config.lock().unwrap().serial.file = Some(pty.path.clone());
@@ -2070,19 +2468,27 @@ impl DeviceManager {
                 None
             }
             ConsoleOutputMode::Tty => {
-                let out = stdout();
-                let _ = self.set_raw_mode(&out);
-                Some(Box::new(out))
+                let _ = self.set_terminal_raw_mode();
+                Some(Box::new(stdout()))
             }
             ConsoleOutputMode::Off | ConsoleOutputMode::Null => None,
         };
         if serial_config.mode != ConsoleOutputMode::Off {
             let serial = self.add_serial_device(interrupt_manager, serial_writer)?;
+            #[cfg(target_arch = "x86_64")]
+            serial
+                .lock()
+                .unwrap()
+                .set_flow_control(serial_config.flow_control == FlowControl::RtsCts);
             self.serial_manager = match serial_config.mode {
-                ConsoleOutputMode::Pty | ConsoleOutputMode::Tty => {
-                    let serial_manager =
-                        SerialManager::new(serial, self.serial_pty.clone(), serial_config.mode)
-                            .map_err(DeviceManagerError::CreateSerialManager)?;
+                ConsoleOutputMode::Pty | ConsoleOutputMode::Tty | ConsoleOutputMode::File => {
+                    let serial_manager = SerialManager::new(
+                        serial,
+                        self.serial_pty.clone(),
+                        serial_config.mode,
+                        serial_input,
+                    )
+                    .map_err(DeviceManagerError::CreateSerialManager)?;
                     if let Some(mut serial_manager) = serial_manager {
                         serial_manager
                             .start_thread(
@@ -2157,6 +2563,9 @@ impl DeviceManager {
         // Add vDPA devices if required
         devices.append(&mut self.make_vdpa_devices()?);
 
+        // Add virtio-scsi if required
+        devices.append(&mut self.make_virtio_scsi_devices()?);
+
         Ok(devices)
     }
 
@@ -2185,6 +2594,30 @@ impl DeviceManager {
 
         info!("Creating virtio-block device: {:?}", disk_cfg);
 
+        // Disks report their explicit serial or their WWN, which validation
+        // keeps mutually exclusive. Without either, they inherit the platform
+        // serial number, suffixed with the disk id so that each one stays
+        // unique, or else a serial derived from the platform seed rather than
+        // from the backing file on the host.
+        let serial = disk_cfg
+            .serial
+            .clone()
+            .or_else(|| disk_cfg.wwn_id())
+            .or_else(|| {
+                let config = self.config.lock().unwrap();
+                let platform = config.platform.as_ref()?;
+                platform
+                    .serial_number
+                    .as_ref()
+                    .map(|serial_number| platform_disk_serial(serial_number, &id))
+                    .or_else(|| {
+                        platform
+                            .seed
+                            .as_deref()
+                            .map(|seed| IdentitySeed::new(seed).disk_serial(&format!("disk:{id}")))
+                    })
+            });
+
         let snapshot = snapshot_from_id(self.snapshot.as_ref(), id.as_str());
 
         let (virtio_device, migratable_device) = if disk_cfg.vhost_user {
@@ -2219,86 +2652,219 @@ impl DeviceManager {
                 Arc::clone(&vhost_user_block) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
                 vhost_user_block as Arc<Mutex<dyn Migratable>>,
             )
-        } else {
-            let mut options = OpenOptions::new();
-            options.read(true);
-            options.write(!disk_cfg.readonly);
-            if disk_cfg.direct {
-                options.custom_flags(libc::O_DIRECT);
-            }
-            // Open block device path
-            let mut file: File = options
+        } else if disk_cfg.media == DiskMedia::Cdrom {
+            // CD-ROM images are exposed as the only logical unit of a
+            // virtio-scsi adapter, as this is what guests and firmwares
+            // expect to find optical media behind.
+            let file = OpenOptions::new()
+                .read(true)
                 .open(
                     disk_cfg
                         .path
                         .as_ref()
-                        .ok_or(DeviceManagerError::NoDiskPath)?
-                        .clone(),
+                        .ok_or(DeviceManagerError::NoDiskPath)?,
                 )
                 .map_err(DeviceManagerError::Disk)?;
-            let image_type =
-                detect_image_type(&mut file).map_err(DeviceManagerError::DetectImageType)?;
-
-            let image = match image_type {
-                ImageType::FixedVhd => {
-                    // Use asynchronous backend relying on io_uring if the
-                    // syscalls are supported.
-                    if cfg!(feature = "io_uring")
-                        && !disk_cfg.disable_io_uring
-                        && self.io_uring_is_supported()
-                    {
-                        info!("Using asynchronous fixed VHD disk file (io_uring)");
 
-                        #[cfg(not(feature = "io_uring"))]
-                        unreachable!("Checked in if statement above");
-                        #[cfg(feature = "io_uring")]
-                        {
-                            Box::new(
-                                FixedVhdDiskAsync::new(file)
-                                    .map_err(DeviceManagerError::CreateFixedVhdDiskAsync)?,
-                            ) as Box<dyn DiskFile>
+            let virtio_scsi = Arc::new(Mutex::new(
+                virtio_devices::Scsi::new(
+                    id.clone(),
+                    vec![virtio_devices::ScsiLun::Cdrom(file)],
+                    disk_cfg.queue_size,
+                    self.force_iommu | disk_cfg.iommu,
+                    self.seccomp_action.clone(),
+                    self.exit_evt
+                        .try_clone()
+                        .map_err(DeviceManagerError::EventFd)?,
+                    snapshot
+                        .map(|s| s.to_versioned_state())
+                        .transpose()
+                        .map_err(DeviceManagerError::RestoreGetState)?,
+                )
+                .map_err(DeviceManagerError::CreateVirtioScsi)?,
+            ));
+
+            (
+                Arc::clone(&virtio_scsi) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                virtio_scsi as Arc<Mutex<dyn Migratable>>,
+            )
+        } else {
+            // Bypassing the host page cache is what cache=none means.
+            let direct = disk_cfg.direct || disk_cfg.cache == CacheMode::None;
+            let mut options = OpenOptions::new();
+            options.read(true);
+            options.write(disk_cfg.writes_image());
+            if direct {
+                options.custom_flags(libc::O_DIRECT);
+            }
+            let (image, topology) = match disk_cfg.url.as_ref() {
+                // Remote images are only read, on demand, there is no local
+                // file to lock, reuse or probe.
+                Some(url) => {
+                    info!("Using synchronous HTTP disk image {}", url);
+                    let cache_size = disk_cfg
+                        .cache_mb
+                        .map_or(http::DEFAULT_HTTP_CACHE_SIZE, |cache_mb| cache_mb << 20);
+                    let image = Box::new(
+                        HttpDiskSync::new(url, cache_size)
+                            .map_err(DeviceManagerError::CreateHttpDiskSync)?,
+                    ) as Box<dyn DiskFile>;
+                    (image, None)
+                }
+                None => {
+                    let mut file: File = match self.reusable_backends.disk_files.remove(&id) {
+                        Some(mut file) => {
+                            info!("Reusing backing file for virtio-block device: {}", id);
+                            // The previous device may have moved the file offset.
+                            file.seek(SeekFrom::Start(0))
+                                .map_err(DeviceManagerError::Disk)?;
+                            file
                         }
-                    } else {
-                        info!("Using synchronous fixed VHD disk file");
-                        Box::new(
-                            FixedVhdDiskSync::new(file)
-                                .map_err(DeviceManagerError::CreateFixedVhdDiskSync)?,
-                        ) as Box<dyn DiskFile>
+                        None => match disk_cfg.directory() {
+                            Some(dir) => create_directory_disk_image(dir)?,
+                            // Open block device path
+                            None => {
+                                let path = disk_cfg
+                                    .path
+                                    .as_ref()
+                                    .ok_or(DeviceManagerError::NoDiskPath)?;
+                                let file = options.open(path).map_err(DeviceManagerError::Disk)?;
+                                if disk_cfg.locking == DiskLocking::Flock {
+                                    lock_disk_image(&file, path, disk_cfg.writes_image())?;
+                                }
+                                file
+                            }
+                        },
+                    };
+                    self.backends.disk_files.insert(
+                        id.clone(),
+                        file.try_clone().map_err(DeviceManagerError::Disk)?,
+                    );
+                    let image_type = detect_image_type(&mut file)
+                        .map_err(DeviceManagerError::DetectImageType)?;
+
+                    if disk_cfg.overlay.is_some() && !matches!(image_type, ImageType::Raw) {
+                        return Err(DeviceManagerError::OverlayNotRaw);
                     }
-                }
-                ImageType::Raw => {
-                    // Use asynchronous backend relying on io_uring if the
-                    // syscalls are supported.
-                    if cfg!(feature = "io_uring")
-                        && !disk_cfg.disable_io_uring
-                        && self.io_uring_is_supported()
+
+                    // The synchronous backends block the queue worker, their
+                    // requests can't be cancelled.
+                    if disk_cfg.request_timeout != 0
+                        && !(matches!(image_type, ImageType::Raw | ImageType::FixedVhd)
+                            && cfg!(feature = "io_uring")
+                            && self.io_uring_is_supported())
                     {
-                        info!("Using asynchronous RAW disk file (io_uring)");
+                        return Err(DeviceManagerError::RequestTimeoutUnsupported);
+                    }
 
-                        #[cfg(not(feature = "io_uring"))]
-                        unreachable!("Checked in if statement above");
-                        #[cfg(feature = "io_uring")]
-                        {
-                            Box::new(RawFileDisk::new(file)) as Box<dyn DiskFile>
-                        }
+                    let topology = if disk_cfg.probe_alignment {
+                        let topology = DiskTopology::probe_alignment(&file)
+                            .map_err(DeviceManagerError::ProbeDiskAlignment)?;
+                        info!("Probed disk alignment for {}: {:?}", id, topology);
+                        Some(topology)
                     } else {
-                        info!("Using synchronous RAW disk file");
-                        Box::new(RawFileDiskSync::new(file)) as Box<dyn DiskFile>
-                    }
-                }
-                ImageType::Qcow2 => {
-                    info!("Using synchronous QCOW disk file");
-                    Box::new(
-                        QcowDiskSync::new(file, disk_cfg.direct)
-                            .map_err(DeviceManagerError::CreateQcowDiskSync)?,
-                    ) as Box<dyn DiskFile>
-                }
-                ImageType::Vhdx => {
-                    info!("Using synchronous VHDX disk file");
-                    Box::new(
-                        VhdxDiskSync::new(file)
-                            .map_err(DeviceManagerError::CreateFixedVhdxDiskSync)?,
-                    ) as Box<dyn DiskFile>
+                        None
+                    };
+
+                    let image = match image_type {
+                        ImageType::Raw if disk_cfg.overlay.is_some() => {
+                            let overlay_file =
+                                match self.reusable_backends.disk_overlays.remove(&id) {
+                                    Some(overlay_file) => overlay_file,
+                                    None => {
+                                        let overlay_path = disk_cfg.overlay.as_ref().unwrap();
+                                        let overlay_file = OpenOptions::new()
+                                            .read(true)
+                                            .write(true)
+                                            .create(true)
+                                            .open(overlay_path)
+                                            .map_err(DeviceManagerError::OpenDiskOverlay)?;
+                                        if disk_cfg.locking == DiskLocking::Flock {
+                                            lock_disk_image(&overlay_file, overlay_path, true)?;
+                                        }
+                                        // Once unlinked, the overlay goes away with the
+                                        // last file descriptor, whenever the VM stops.
+                                        if disk_cfg.overlay_discard {
+                                            std::fs::remove_file(overlay_path)
+                                                .map_err(DeviceManagerError::RemoveDiskOverlay)?;
+                                        }
+                                        overlay_file
+                                    }
+                                };
+                            self.backends.disk_overlays.insert(
+                                id.clone(),
+                                overlay_file.try_clone().map_err(DeviceManagerError::Disk)?,
+                            );
+
+                            info!("Using synchronous RAW disk file with a copy-on-write overlay");
+                            Box::new(
+                                OverlayDiskSync::new(file, overlay_file)
+                                    .map_err(DeviceManagerError::CreateOverlayDiskSync)?,
+                            ) as Box<dyn DiskFile>
+                        }
+                        ImageType::FixedVhd => {
+                            // Use asynchronous backend relying on io_uring if the
+                            // syscalls are supported.
+                            if cfg!(feature = "io_uring")
+                                && !disk_cfg.disable_io_uring
+                                && self.io_uring_is_supported()
+                            {
+                                info!("Using asynchronous fixed VHD disk file (io_uring)");
+
+                                #[cfg(not(feature = "io_uring"))]
+                                unreachable!("Checked in if statement above");
+                                #[cfg(feature = "io_uring")]
+                                {
+                                    Box::new(
+                                        FixedVhdDiskAsync::new(file)
+                                            .map_err(DeviceManagerError::CreateFixedVhdDiskAsync)?,
+                                    ) as Box<dyn DiskFile>
+                                }
+                            } else {
+                                info!("Using synchronous fixed VHD disk file");
+                                Box::new(
+                                    FixedVhdDiskSync::new(file)
+                                        .map_err(DeviceManagerError::CreateFixedVhdDiskSync)?,
+                                ) as Box<dyn DiskFile>
+                            }
+                        }
+                        ImageType::Raw => {
+                            // Use asynchronous backend relying on io_uring if the
+                            // syscalls are supported.
+                            if cfg!(feature = "io_uring")
+                                && !disk_cfg.disable_io_uring
+                                && self.io_uring_is_supported()
+                            {
+                                info!("Using asynchronous RAW disk file (io_uring)");
+
+                                #[cfg(not(feature = "io_uring"))]
+                                unreachable!("Checked in if statement above");
+                                #[cfg(feature = "io_uring")]
+                                {
+                                    Box::new(RawFileDisk::new(file)) as Box<dyn DiskFile>
+                                }
+                            } else {
+                                info!("Using synchronous RAW disk file");
+                                Box::new(RawFileDiskSync::new(file)) as Box<dyn DiskFile>
+                            }
+                        }
+                        ImageType::Qcow2 => {
+                            info!("Using synchronous QCOW disk file");
+                            Box::new(
+                                QcowDiskSync::new(file, direct)
+                                    .map_err(DeviceManagerError::CreateQcowDiskSync)?,
+                            ) as Box<dyn DiskFile>
+                        }
+                        ImageType::Vhdx => {
+                            info!("Using synchronous VHDX disk file");
+                            Box::new(
+                                VhdxDiskSync::new(file)
+                                    .map_err(DeviceManagerError::CreateFixedVhdxDiskSync)?,
+                            ) as Box<dyn DiskFile>
+                        }
+                    };
+
+                    (image, topology)
                 }
             };
 
@@ -2308,16 +2874,27 @@ impl DeviceManager {
                     image,
                     disk_cfg
                         .path
-                        .as_ref()
-                        .ok_or(DeviceManagerError::NoDiskPath)?
-                        .clone(),
+                        .clone()
+                        .or_else(|| disk_cfg.url.as_ref().map(PathBuf::from))
+                        .ok_or(DeviceManagerError::NoDiskPath)?,
                     disk_cfg.readonly,
+                    disk_cfg.cache == CacheMode::Writethrough,
                     self.force_iommu | disk_cfg.iommu,
                     disk_cfg.num_queues,
                     disk_cfg.queue_size,
-                    disk_cfg.serial.clone(),
+                    disk_cfg.segment_max,
+                    disk_cfg.size_max,
+                    disk_cfg.aio_max_inflight,
+                    disk_cfg.poll_queue_window(),
+                    disk_cfg.sync_interval(),
+                    disk_cfg.request_timeout_duration(),
+                    serial,
+                    disk_cfg.zone_layout(),
+                    topology,
                     self.seccomp_action.clone(),
                     disk_cfg.rate_limiter_config,
+                    disk_cfg.read_rate_limiter_config,
+                    disk_cfg.write_rate_limiter_config,
                     self.exit_evt
                         .try_clone()
                         .map_err(DeviceManagerError::EventFd)?,
@@ -2379,6 +2956,35 @@ impl DeviceManager {
         };
         info!("Creating virtio-net device: {:?}", net_cfg);
 
+        if let Some(host_name) = &net_cfg.host_name {
+            self.pending_device_labels
+                .insert(id.clone(), host_name.clone());
+        }
+
+        // The vDPA device exposes the virtio-net device implemented by the
+        // hardware, hence it's handled as any other vDPA device.
+        if let Some(vdpa_path) = net_cfg.vdpa.clone() {
+            return self.create_vdpa_device(
+                id,
+                &vdpa_path,
+                net_cfg.num_queues,
+                net_cfg.iommu,
+                net_cfg.pci_segment,
+                Some(VirtioDeviceType::Net),
+            );
+        }
+
+        // The guest MAC address is resolved once, and kept in the
+        // configuration so that it doesn't change across reboots.
+        let mac = match net_cfg.mac {
+            Some(mac) => mac,
+            None => {
+                let mac = default_net_mac(self.config.lock().unwrap().platform.as_ref(), &id);
+                net_cfg.mac = Some(mac);
+                mac
+            }
+        };
+
         let snapshot = snapshot_from_id(self.snapshot.as_ref(), id.as_str());
 
         let (virtio_device, migratable_device) = if net_cfg.vhost_user {
@@ -2395,7 +3001,7 @@ impl DeviceManager {
             let vhost_user_net = Arc::new(Mutex::new(
                 match virtio_devices::vhost_user::Net::new(
                     id.clone(),
-                    net_cfg.mac,
+                    mac,
                     net_cfg.mtu,
                     vu_cfg,
                     server,
@@ -2429,19 +3035,82 @@ impl DeviceManager {
                 .transpose()
                 .map_err(DeviceManagerError::RestoreGetState)?;
 
-            let virtio_net = if let Some(ref tap_if_name) = net_cfg.tap {
+            // Taps provided through file descriptors already survive reboots.
+            let reusable_taps = if net_cfg.fds.is_none() {
+                self.reusable_backends.taps.remove(&id)
+            } else {
+                None
+            };
+
+            let virtio_net = if let Some(taps) = reusable_taps {
+                info!("Reusing tap interfaces for virtio-net device: {}", id);
+                Arc::new(Mutex::new(
+                    virtio_devices::Net::new_with_tap(
+                        id.clone(),
+                        taps,
+                        Some(mac),
+                        self.force_iommu | net_cfg.iommu,
+                        net_cfg.num_queues,
+                        net_cfg.rx_queue_size(),
+                        net_cfg.tx_queue_size(),
+                        self.seccomp_action.clone(),
+                        net_cfg.rate_limiter_config,
+                        self.exit_evt
+                            .try_clone()
+                            .map_err(DeviceManagerError::EventFd)?,
+                        state,
+                        net_cfg.offload_tso,
+                        net_cfg.offload_ufo,
+                        net_cfg.offload_csum,
+                        net_cfg.promisc,
+                        net_cfg.vhost,
+                    )
+                    .map_err(DeviceManagerError::CreateVirtioNet)?,
+                ))
+            } else if let Some(bridge) = net_cfg.bridge().map(str::to_owned) {
+                Arc::new(Mutex::new(
+                    virtio_devices::Net::new(
+                        id.clone(),
+                        None,
+                        None,
+                        None,
+                        Some(&bridge),
+                        Some(mac),
+                        &mut net_cfg.host_mac,
+                        net_cfg.mtu,
+                        self.force_iommu | net_cfg.iommu,
+                        net_cfg.num_queues,
+                        net_cfg.rx_queue_size(),
+                        net_cfg.tx_queue_size(),
+                        self.seccomp_action.clone(),
+                        net_cfg.rate_limiter_config,
+                        self.exit_evt
+                            .try_clone()
+                            .map_err(DeviceManagerError::EventFd)?,
+                        state,
+                        net_cfg.offload_tso,
+                        net_cfg.offload_ufo,
+                        net_cfg.offload_csum,
+                        net_cfg.promisc,
+                        net_cfg.vhost,
+                    )
+                    .map_err(DeviceManagerError::CreateVirtioNet)?,
+                ))
+            } else if let Some(ref tap_if_name) = net_cfg.tap {
                 Arc::new(Mutex::new(
                     virtio_devices::Net::new(
                         id.clone(),
                         Some(tap_if_name),
                         None,
                         None,
-                        Some(net_cfg.mac),
+                        None,
+                        Some(mac),
                         &mut net_cfg.host_mac,
                         net_cfg.mtu,
                         self.force_iommu | net_cfg.iommu,
                         net_cfg.num_queues,
-                        net_cfg.queue_size,
+                        net_cfg.rx_queue_size(),
+                        net_cfg.tx_queue_size(),
                         self.seccomp_action.clone(),
                         net_cfg.rate_limiter_config,
                         self.exit_evt
@@ -2451,6 +3120,8 @@ impl DeviceManager {
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
                         net_cfg.offload_csum,
+                        net_cfg.promisc,
+                        net_cfg.vhost,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
@@ -2458,10 +3129,11 @@ impl DeviceManager {
                 let net = virtio_devices::Net::from_tap_fds(
                     id.clone(),
                     fds,
-                    Some(net_cfg.mac),
+                    Some(mac),
                     net_cfg.mtu,
                     self.force_iommu | net_cfg.iommu,
-                    net_cfg.queue_size,
+                    net_cfg.rx_queue_size(),
+                    net_cfg.tx_queue_size(),
                     self.seccomp_action.clone(),
                     net_cfg.rate_limiter_config,
                     self.exit_evt
@@ -2471,6 +3143,8 @@ impl DeviceManager {
                     net_cfg.offload_tso,
                     net_cfg.offload_ufo,
                     net_cfg.offload_csum,
+                    net_cfg.promisc,
+                    net_cfg.vhost,
                 )
                 .map_err(DeviceManagerError::CreateVirtioNet)?;
 
@@ -2486,13 +3160,15 @@ impl DeviceManager {
                         id.clone(),
                         None,
                         Some(net_cfg.ip),
-                        Some(net_cfg.mask),
-                        Some(net_cfg.mac),
+                        Some(net_cfg.netmask()),
+                        None,
+                        Some(mac),
                         &mut net_cfg.host_mac,
                         net_cfg.mtu,
                         self.force_iommu | net_cfg.iommu,
                         net_cfg.num_queues,
-                        net_cfg.queue_size,
+                        net_cfg.rx_queue_size(),
+                        net_cfg.tx_queue_size(),
                         self.seccomp_action.clone(),
                         net_cfg.rate_limiter_config,
                         self.exit_evt
@@ -2502,11 +3178,30 @@ impl DeviceManager {
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
                         net_cfg.offload_csum,
+                        net_cfg.promisc,
+                        net_cfg.vhost,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
             };
 
+            if let Some(group_id) = &net_cfg.rate_limit_group {
+                let group = self
+                    .rate_limit_groups
+                    .get(group_id)
+                    .ok_or_else(|| DeviceManagerError::UnknownRateLimitGroup(group_id.clone()))?;
+                virtio_net
+                    .lock()
+                    .unwrap()
+                    .set_rate_limit_group(group.clone());
+            }
+
+            if net_cfg.fds.is_none() {
+                self.backends
+                    .taps
+                    .insert(id.clone(), virtio_net.lock().unwrap().taps().to_vec());
+            }
+
             (
                 Arc::clone(&virtio_net) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
                 virtio_net as Arc<Mutex<dyn Migratable>>,
@@ -2553,20 +3248,42 @@ impl DeviceManager {
             info!("Creating virtio-rng device: {:?}", rng_config);
             let id = String::from(RNG_DEVICE_NAME);
 
-            let virtio_rng_device = Arc::new(Mutex::new(
-                virtio_devices::Rng::new(
+            let exit_evt = self
+                .exit_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?;
+            let state = versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                .map_err(DeviceManagerError::RestoreGetState)?;
+            let virtio_rng_device = match &rng_config.source {
+                Some(RngSource::Egd(socket)) => virtio_devices::Rng::new_egd(
+                    id.clone(),
+                    socket,
+                    self.force_iommu | rng_config.iommu,
+                    rng_config.queue_size,
+                    self.seccomp_action.clone(),
+                    exit_evt,
+                    state,
+                ),
+                Some(RngSource::Jitter) => virtio_devices::Rng::new_jitter(
+                    id.clone(),
+                    self.force_iommu | rng_config.iommu,
+                    rng_config.queue_size,
+                    self.seccomp_action.clone(),
+                    exit_evt,
+                    state,
+                ),
+                None => virtio_devices::Rng::new(
                     id.clone(),
                     rng_path,
                     self.force_iommu | rng_config.iommu,
+                    rng_config.queue_size,
                     self.seccomp_action.clone(),
-                    self.exit_evt
-                        .try_clone()
-                        .map_err(DeviceManagerError::EventFd)?,
-                    versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
-                        .map_err(DeviceManagerError::RestoreGetState)?,
-                )
-                .map_err(DeviceManagerError::CreateVirtioRng)?,
-            ));
+                    exit_evt,
+                    state,
+                ),
+            }
+            .map_err(DeviceManagerError::CreateVirtioRng)?;
+            let virtio_rng_device = Arc::new(Mutex::new(virtio_rng_device));
             devices.push(MetaVirtioDevice {
                 virtio_device: Arc::clone(&virtio_rng_device)
                     as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
@@ -2609,6 +3326,7 @@ impl DeviceManager {
                 virtio_devices::vhost_user::Fs::new(
                     id.clone(),
                     fs_socket,
+                    fs_cfg.fd,
                     &fs_cfg.tag,
                     fs_cfg.num_queues,
                     fs_cfg.queue_size,
@@ -2618,12 +3336,23 @@ impl DeviceManager {
                         .try_clone()
                         .map_err(DeviceManagerError::EventFd)?,
                     self.force_iommu,
+                    // There is no path to connect to again when the
+                    // connection was handed over as a file descriptor.
+                    fs_cfg.reconnect && fs_cfg.fd.is_none(),
                     versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
                         .map_err(DeviceManagerError::RestoreGetState)?,
                 )
                 .map_err(DeviceManagerError::CreateVirtioFs)?,
             ));
 
+            if let Some(fd) = fs_cfg.fd {
+                // SAFETY: 'fd' is valid because the backend connection was
+                // established successfully through it
+                unsafe {
+                    self.config.lock().unwrap().add_preserved_fds(vec![fd]);
+                }
+            }
+
             // Update the device tree with the migratable device.
             node.migratable = Some(Arc::clone(&virtio_fs_device) as Arc<Mutex<dyn Migratable>>);
             self.device_tree.lock().unwrap().insert(id.clone(), node);
@@ -2766,7 +3495,7 @@ impl DeviceManager {
             region_size as usize,
             PROT_READ | PROT_WRITE,
             MAP_NORESERVE
-                | if pmem_cfg.discard_writes {
+                | if pmem_cfg.private_mapping() {
                     MAP_PRIVATE
                 } else {
                     MAP_SHARED
@@ -2913,6 +3642,7 @@ impl DeviceManager {
     fn make_virtio_mem_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
+        let zero_on_free = self.config.lock().unwrap().memory.zero_on_free;
         let mm = self.memory_manager.clone();
         let mut mm = mm.lock().unwrap();
         for (memory_zone_id, memory_zone) in mm.memory_zones_mut().iter_mut() {
@@ -2930,6 +3660,7 @@ impl DeviceManager {
                         node_id,
                         virtio_mem_zone.hotplugged_size(),
                         virtio_mem_zone.hugepages(),
+                        zero_on_free,
                         self.exit_evt
                             .try_clone()
                             .map_err(DeviceManagerError::EventFd)?,
@@ -2972,7 +3703,8 @@ impl DeviceManager {
     fn make_virtio_balloon_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
-        if let Some(balloon_config) = &self.config.lock().unwrap().balloon {
+        let config = self.config.lock().unwrap();
+        if let Some(balloon_config) = &config.balloon {
             let id = String::from(BALLOON_DEVICE_NAME);
             info!("Creating virtio-balloon device: id = {}", id);
 
@@ -2982,6 +3714,7 @@ impl DeviceManager {
                     balloon_config.size,
                     balloon_config.deflate_on_oom,
                     balloon_config.free_page_reporting,
+                    config.memory.zero_on_free,
                     self.seccomp_action.clone(),
                     self.exit_evt
                         .try_clone()
@@ -3035,6 +3768,7 @@ impl DeviceManager {
             )
             .map_err(DeviceManagerError::CreateVirtioWatchdog)?,
         ));
+        self.watchdog = Some(virtio_watchdog_device.clone());
         devices.push(MetaVirtioDevice {
             virtio_device: Arc::clone(&virtio_watchdog_device)
                 as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
@@ -3066,22 +3800,50 @@ impl DeviceManager {
 
         info!("Creating vDPA device: {:?}", vdpa_cfg);
 
-        let device_path = vdpa_cfg
-            .path
+        self.create_vdpa_device(
+            id,
+            &vdpa_cfg.path,
+            vdpa_cfg.num_queues,
+            vdpa_cfg.iommu,
+            vdpa_cfg.pci_segment,
+            None,
+        )
+    }
+
+    fn create_vdpa_device(
+        &mut self,
+        id: String,
+        path: &Path,
+        num_queues: usize,
+        iommu: bool,
+        pci_segment: u16,
+        expected_type: Option<VirtioDeviceType>,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let device_path = path
             .to_str()
             .ok_or(DeviceManagerError::CreateVdpaConvertPath)?;
 
-        let vdpa_device = Arc::new(Mutex::new(
-            virtio_devices::Vdpa::new(
-                id.clone(),
-                device_path,
-                self.memory_manager.lock().unwrap().guest_memory(),
-                vdpa_cfg.num_queues as u16,
-                versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
-                    .map_err(DeviceManagerError::RestoreGetState)?,
-            )
-            .map_err(DeviceManagerError::CreateVdpa)?,
-        ));
+        let vdpa = virtio_devices::Vdpa::new(
+            id.clone(),
+            device_path,
+            self.memory_manager.lock().unwrap().guest_memory(),
+            num_queues as u16,
+            versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                .map_err(DeviceManagerError::RestoreGetState)?,
+        )
+        .map_err(DeviceManagerError::CreateVdpa)?;
+
+        if let Some(expected_type) = expected_type {
+            let device_type = virtio_devices::VirtioDevice::device_type(&vdpa);
+            if device_type != expected_type as u32 {
+                return Err(DeviceManagerError::VdpaDeviceTypeMismatch(
+                    expected_type,
+                    device_type,
+                ));
+            }
+        }
+
+        let vdpa_device = Arc::new(Mutex::new(vdpa));
 
         // Create the DMA handler that is required by the vDPA device
         let vdpa_mapping = Arc::new(VdpaDmaMapping::new(
@@ -3096,9 +3858,9 @@ impl DeviceManager {
 
         Ok(MetaVirtioDevice {
             virtio_device: vdpa_device as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
-            iommu: vdpa_cfg.iommu,
+            iommu,
             id,
-            pci_segment: vdpa_cfg.pci_segment,
+            pci_segment,
             dma_handler: Some(vdpa_mapping),
         })
     }
@@ -3117,27 +3879,102 @@ impl DeviceManager {
         Ok(devices)
     }
 
-    fn next_device_name(&mut self, prefix: &str) -> DeviceManagerResult<String> {
-        let start_id = self.device_id_cnt;
-        loop {
-            // Generate the temporary name.
-            let name = format!("{}{}", prefix, self.device_id_cnt);
-            // Increment the counter.
-            self.device_id_cnt += Wrapping(1);
-            // Check if the name is already in use.
-            if !self.boot_id_list.contains(&name)
-                && !self.device_tree.lock().unwrap().contains_key(&name)
-            {
-                return Ok(name);
+    fn make_virtio_scsi_device(
+        &mut self,
+        scsi_cfg: &mut ScsiConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &scsi_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(SCSI_DEVICE_NAME_PREFIX)?;
+            scsi_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!("Creating virtio-scsi device: {:?}", scsi_cfg);
+
+        // The backing files are reused across reboots, for their locks to
+        // be kept.
+        let images = match self.reusable_backends.scsi_disk_files.remove(&id) {
+            Some(images) if images.len() == scsi_cfg.disks.len() => {
+                info!("Reusing backing files for virtio-scsi device: {}", id);
+                images
             }
+            _ => scsi_cfg
+                .disks
+                .iter()
+                .map(|path| open_scsi_disk_image(path, scsi_cfg.readonly))
+                .collect::<DeviceManagerResult<Vec<File>>>()?,
+        };
+        self.backends.scsi_disk_files.insert(
+            id.clone(),
+            images
+                .iter()
+                .map(|image| image.try_clone())
+                .collect::<io::Result<Vec<File>>>()
+                .map_err(DeviceManagerError::Disk)?,
+        );
 
-            if self.device_id_cnt == start_id {
-                // We went through a full loop and there's nothing else we can
-                // do.
-                break;
+        let luns = images
+            .into_iter()
+            .map(|image| virtio_devices::ScsiLun::Disk {
+                image,
+                readonly: scsi_cfg.readonly,
+            })
+            .collect();
+
+        let virtio_scsi = Arc::new(Mutex::new(
+            virtio_devices::Scsi::new(
+                id.clone(),
+                luns,
+                scsi_cfg.queue_size,
+                self.force_iommu | scsi_cfg.iommu,
+                self.seccomp_action.clone(),
+                self.exit_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                    .map_err(DeviceManagerError::RestoreGetState)?,
+            )
+            .map_err(DeviceManagerError::CreateVirtioScsi)?,
+        ));
+
+        // Fill the device tree with a new node. In case of restore, we
+        // know there is nothing to do, so we can simply override the
+        // existing entry.
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, virtio_scsi));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: virtio_scsi as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: scsi_cfg.iommu,
+            id,
+            pci_segment: scsi_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_scsi_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+        let mut scsi_devices = self.config.lock().unwrap().scsi.clone();
+        if let Some(scsi_list_cfg) = &mut scsi_devices {
+            for scsi_cfg in scsi_list_cfg.iter_mut() {
+                devices.push(self.make_virtio_scsi_device(scsi_cfg)?);
             }
         }
-        Err(DeviceManagerError::NoAvailableDeviceName)
+        self.config.lock().unwrap().scsi = scsi_devices;
+
+        Ok(devices)
+    }
+
+    fn next_device_name(&mut self, prefix: &str) -> DeviceManagerResult<String> {
+        let device_tree = self.device_tree.clone();
+        let device_tree = device_tree.lock().unwrap();
+        next_device_name(&mut self.device_id_cnt, prefix, |name| {
+            self.boot_id_list.contains(name) || device_tree.contains_key(name)
+        })
     }
 
     fn add_passthrough_device(
@@ -3534,6 +4371,13 @@ impl DeviceManager {
         let (pci_segment_id, pci_device_bdf, resources) =
             self.pci_resources(&id, pci_segment_id)?;
 
+        // Expose the label of the device through the ACPI description of
+        // the PCI slot it has been placed on.
+        if let Some(label) = self.pending_device_labels.remove(&virtio_device_id) {
+            self.pci_segments[pci_segment_id as usize].pci_device_labels
+                [pci_device_bdf.device() as usize] = Some(label);
+        }
+
         // Update the existing virtio node by setting the parent.
         if let Some(node) = self.device_tree.lock().unwrap().get_mut(&virtio_device_id) {
             node.parent = Some(id.clone());
@@ -3653,6 +4497,126 @@ impl DeviceManager {
         Ok(pci_device_bdf)
     }
 
+    fn add_virtio_mmio_device(
+        &mut self,
+        virtio_device: Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+        virtio_device_id: String,
+    ) -> DeviceManagerResult<()> {
+        let id = format!("{VIRTIO_MMIO_DEVICE_NAME_PREFIX}-{virtio_device_id}");
+
+        info!("Creating virtio-mmio device {}", id);
+
+        // Add the new virtio-mmio node to the device tree.
+        let mut node = device_node!(id);
+        node.children = vec![virtio_device_id.clone()];
+
+        // Update the existing virtio node by setting the parent.
+        if let Some(node) = self.device_tree.lock().unwrap().get_mut(&virtio_device_id) {
+            node.parent = Some(id.clone());
+        } else {
+            return Err(DeviceManagerError::MissingNode);
+        }
+
+        let irq = self
+            .address_manager
+            .allocator
+            .lock()
+            .unwrap()
+            .allocate_irq()
+            .ok_or(DeviceManagerError::AllocateIrq)?;
+
+        let interrupt_group = self
+            .legacy_interrupt_manager
+            .as_ref()
+            .unwrap()
+            .create_group(LegacyIrqGroupConfig {
+                irq: irq as InterruptIndex,
+            })
+            .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+        let addr = self
+            .address_manager
+            .allocator
+            .lock()
+            .unwrap()
+            .allocate_platform_mmio_addresses(None, VIRTIO_MMIO_DEVICE_SIZE, None)
+            .ok_or(DeviceManagerError::AllocateMmioAddress)?;
+
+        let device_type = virtio_device.lock().unwrap().device_type();
+        let memory = self.memory_manager.lock().unwrap().guest_memory();
+        let virtio_mmio_device = Arc::new(Mutex::new(
+            VirtioMmioDevice::new(
+                id.clone(),
+                memory,
+                virtio_device,
+                interrupt_group,
+                self.activate_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                self.pending_activations.clone(),
+            )
+            .map_err(DeviceManagerError::VirtioMmioDevice)?,
+        ));
+
+        self.address_manager
+            .mmio_bus
+            .insert(virtio_mmio_device.clone(), addr.0, VIRTIO_MMIO_DEVICE_SIZE)
+            .map_err(DeviceManagerError::BusError)?;
+        self.bus_devices
+            .push(Arc::clone(&virtio_mmio_device) as Arc<Mutex<dyn BusDevice>>);
+
+        // All queues are notified through the same register, the value
+        // written being the queue index.
+        for (queue_index, (event, notify_addr)) in virtio_mmio_device
+            .lock()
+            .unwrap()
+            .ioeventfds(addr.0)
+            .into_iter()
+            .enumerate()
+        {
+            let io_addr = IoEventAddress::Mmio(notify_addr);
+            self.address_manager
+                .vm
+                .register_ioevent(
+                    event,
+                    &io_addr,
+                    Some(hypervisor::DataMatch::DataMatch32(queue_index as u32)),
+                )
+                .map_err(|e| DeviceManagerError::RegisterIoevent(e.into()))?;
+        }
+
+        self.id_to_dev_info.insert(
+            (DeviceType::Virtio(device_type), virtio_device_id),
+            MmioDeviceInfo {
+                addr: addr.0,
+                len: VIRTIO_MMIO_DEVICE_SIZE,
+                irq,
+            },
+        );
+
+        // The x86_64 guest learns about the device through the kernel command
+        // line, as there's no device tree.
+        #[cfg(target_arch = "x86_64")]
+        self.cmdline_additions.push(format!(
+            "virtio_mmio.device={}K@0x{:x}:{}",
+            VIRTIO_MMIO_DEVICE_SIZE >> 10,
+            addr.0,
+            irq
+        ));
+
+        node.resources = vec![
+            Resource::MmioAddressRange {
+                base: addr.0,
+                size: VIRTIO_MMIO_DEVICE_SIZE,
+            },
+            Resource::LegacyIrq(irq),
+        ];
+        node.migratable = Some(Arc::clone(&virtio_mmio_device) as Arc<Mutex<dyn Migratable>>);
+        self.device_tree.lock().unwrap().insert(id, node);
+
+        Ok(())
+    }
+
     fn add_pvpanic_device(
         &mut self,
     ) -> DeviceManagerResult<Option<Arc<Mutex<devices::PvPanicDevice>>>> {
@@ -3749,7 +4713,75 @@ impl DeviceManager {
         &self.console
     }
 
-    #[cfg(target_arch = "aarch64")]
+    /// Names of the tap interfaces created or opened for the virtio-net
+    /// devices, indexed by device id.
+    pub fn tap_names(&self) -> BTreeMap<String, String> {
+        self.backends
+            .taps
+            .iter()
+            .filter_map(|(id, taps)| {
+                let name = taps.first()?.get_if_name();
+                let name = String::from_utf8_lossy(&name);
+                Some((id.clone(), name.trim_end_matches('\0').to_owned()))
+            })
+            .collect()
+    }
+
+    /// Releases the locks held on the disk images, for the destination of a
+    /// migration to open them before this VM stops.
+    pub fn unlock_disk_images(&self) {
+        for file in self
+            .backends
+            .disk_files
+            .values()
+            .chain(self.backends.disk_overlays.values())
+            .chain(self.backends.scsi_disk_files.values().flatten())
+        {
+            // SAFETY: FFI call with a valid fd
+            unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+        }
+    }
+
+    /// Takes the locks on the disk images back, after a failed migration.
+    pub fn lock_disk_images(&self) -> DeviceManagerResult<()> {
+        let config = self.config.lock().unwrap();
+        for disk_cfg in config.disks.iter().flatten() {
+            if disk_cfg.locking != DiskLocking::Flock || disk_cfg.directory().is_some() {
+                continue;
+            }
+            let id = match disk_cfg.id.as_ref() {
+                Some(id) => id,
+                None => continue,
+            };
+            if let (Some(file), Some(path)) =
+                (self.backends.disk_files.get(id), disk_cfg.path.as_ref())
+            {
+                lock_disk_image(file, path, disk_cfg.writes_image())?;
+            }
+            if let (Some(file), Some(path)) = (
+                self.backends.disk_overlays.get(id),
+                disk_cfg.overlay.as_ref(),
+            ) {
+                lock_disk_image(file, path, true)?;
+            }
+        }
+        for scsi_cfg in config.scsi.iter().flatten() {
+            let files = match scsi_cfg
+                .id
+                .as_ref()
+                .and_then(|id| self.backends.scsi_disk_files.get(id))
+            {
+                Some(files) => files,
+                None => continue,
+            };
+            for (file, path) in files.iter().zip(scsi_cfg.disks.iter()) {
+                lock_disk_image(file, path, !scsi_cfg.readonly)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn cmdline_additions(&self) -> &[String] {
         self.cmdline_additions.as_slice()
     }
@@ -3833,6 +4865,7 @@ impl DeviceManager {
         &mut self,
         device_cfg: &mut DeviceConfig,
     ) -> DeviceManagerResult<PciDeviceInfo> {
+        self.validate_hotplug()?;
         self.validate_identifier(&device_cfg.id)?;
 
         if device_cfg.iommu && !self.is_iommu_segment(device_cfg.pci_segment) {
@@ -3854,6 +4887,7 @@ impl DeviceManager {
         &mut self,
         device_cfg: &mut UserDeviceConfig,
     ) -> DeviceManagerResult<PciDeviceInfo> {
+        self.validate_hotplug()?;
         self.validate_identifier(&device_cfg.id)?;
 
         let (bdf, device_name) = self.add_vfio_user_device(device_cfg)?;
@@ -3867,7 +4901,18 @@ impl DeviceManager {
         })
     }
 
+    // Devices hotplug relies on the ACPI GED device to notify the guest.
+    fn validate_hotplug(&self) -> DeviceManagerResult<()> {
+        if !self.config.lock().unwrap().is_acpi_enabled() {
+            return Err(DeviceManagerError::HotplugWithoutAcpi);
+        }
+
+        Ok(())
+    }
+
     pub fn remove_device(&mut self, id: String) -> DeviceManagerResult<()> {
+        self.validate_hotplug()?;
+
         // The node can be directly a PCI node in case the 'id' refers to a
         // VFIO device or a virtio-pci one.
         // In case the 'id' refers to a virtio device, we must find the PCI
@@ -3884,6 +4929,9 @@ impl DeviceManager {
                 .parent
                 .as_ref()
                 .ok_or(DeviceManagerError::MissingNode)?;
+            if parent.starts_with(VIRTIO_MMIO_DEVICE_NAME_PREFIX) {
+                return Err(DeviceManagerError::HotplugWithMmioTransport);
+            }
             device_tree
                 .get(parent)
                 .ok_or(DeviceManagerError::MissingNode)?
@@ -3941,6 +4989,7 @@ impl DeviceManager {
             .unwrap()
             .put_device_id(device_id as usize)
             .map_err(DeviceManagerError::PutPciDeviceId)?;
+        self.pci_segments[pci_segment_id as usize].pci_device_labels[device_id as usize] = None;
 
         // Remove the device from the device tree along with its children.
         let mut device_tree = self.device_tree.lock().unwrap();
@@ -4103,6 +5152,10 @@ impl DeviceManager {
                 .retain(|handler| !Arc::ptr_eq(&handler.virtio_device, &virtio_device));
         }
 
+        // The host resources must not be reused by a device created later on
+        // with the same identifier.
+        self.backends.remove(&id);
+
         event!(
             "vm",
             "device-removed",
@@ -4123,6 +5176,11 @@ impl DeviceManager {
         &mut self,
         handle: MetaVirtioDevice,
     ) -> DeviceManagerResult<PciDeviceInfo> {
+        self.validate_hotplug()?;
+        if self.config.lock().unwrap().virtio_transport() == VirtioTransportType::Mmio {
+            return Err(DeviceManagerError::HotplugWithMmioTransport);
+        }
+
         // Add the virtio device to the device manager list. This is important
         // as the list is used to notify virtio devices about memory updates
         // for instance.
@@ -4260,10 +5318,33 @@ impl DeviceManager {
         0
     }
 
+    pub fn watchdog_triggered(&self) -> bool {
+        if let Some(watchdog) = &self.watchdog {
+            return watchdog.lock().unwrap().take_triggered();
+        }
+
+        false
+    }
+
     pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
         self.device_tree.clone()
     }
 
+    pub fn take_device_backends(&mut self) -> DeviceBackends {
+        std::mem::take(&mut self.backends)
+    }
+
+    // Record the features currently negotiated by each virtio device in its
+    // device tree node, so that they are reported through vm.info.
+    pub fn update_virtio_features(&self) {
+        let mut device_tree = self.device_tree.lock().unwrap();
+        for handle in &self.virtio_devices {
+            if let Some(node) = device_tree.get_mut(&handle.id) {
+                node.virtio_features = Some(handle.virtio_device.lock().unwrap().acked_features());
+            }
+        }
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn notify_power_button(&self) -> DeviceManagerResult<()> {
         self.ged_notification_device
@@ -4743,8 +5824,7 @@ impl Drop for DeviceManager {
         }
 
         if let Some(termios) = *self.original_termios_opt.lock().unwrap() {
-            // SAFETY: FFI call
-            let _ = unsafe { tcsetattr(stdout().lock().as_raw_fd(), TCSANOW, &termios) };
+            restore_terminal_mode(&termios);
         }
     }
 }
@@ -21,6 +21,8 @@ pub struct DeviceNode {
     pub pci_bdf: Option<PciBdf>,
     #[serde(skip)]
     pub pci_device_handle: Option<PciDeviceHandle>,
+    #[serde(default)]
+    pub virtio_features: Option<u64>,
 }
 
 impl DeviceNode {
@@ -33,6 +35,7 @@ impl DeviceNode {
             migratable,
             pci_bdf: None,
             pci_device_handle: None,
+            virtio_features: None,
         }
     }
 }
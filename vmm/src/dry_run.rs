@@ -0,0 +1,526 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Computes the topology of a VM from its configuration only, without
+//! relying on the hypervisor. This is used by `--dry-run` to report how the
+//! VM would look like if it was booted. The same layout is reported for a
+//! running VM by `GET /vm.topology`, resolved from the actual devices and
+//! memory regions.
+
+use crate::config::{ConsoleOutputMode, CpuTopology, DiskMedia, VirtioTransportType, VmConfig};
+use crate::device_manager::{
+    name_devices, next_device_name, DeviceManagerError, BALLOON_DEVICE_NAME, CONSOLE_DEVICE_NAME,
+    IOMMU_DEVICE_NAME, PVPANIC_DEVICE_NAME, RNG_DEVICE_NAME, WATCHDOG_DEVICE_NAME,
+};
+use crate::device_tree::DeviceTree;
+use crate::memory_manager::{Error as MemoryManagerError, MemoryManager};
+use arch::RegionType;
+use pci::{DeviceRelocation, PciBarRegionType, PciBdf, PciBus, PciDevice, PciRoot, PciRootError};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::num::Wrapping;
+use std::sync::Arc;
+use thiserror::Error;
+use vm_device::Resource;
+
+#[derive(Debug, Error)]
+pub enum DryRunError {
+    #[error("Error laying out the guest memory: {0:?}")]
+    MemoryLayout(MemoryManagerError),
+    #[error("Error naming the devices: {0:?}")]
+    DeviceNaming(DeviceManagerError),
+    #[error("Error allocating a PCI slot: {0:?}")]
+    PciSlot(PciRootError),
+}
+
+#[derive(Serialize)]
+pub struct MemoryRange {
+    pub start: u64,
+    pub size: u64,
+    pub r#type: RegionType,
+}
+
+#[derive(Serialize)]
+pub struct DeviceTopology {
+    pub id: String,
+    pub device_type: &'static str,
+    /// Transport of the virtio devices, the others being PCI devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<VirtioTransportType>,
+    /// PCI address, unless the device is a virtio-mmio device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bdf: Option<PciBdf>,
+    pub iommu: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_queues: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_size: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rx_queue_size: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_queue_size: Option<u16>,
+}
+
+#[derive(Serialize)]
+pub struct DryRunReport {
+    pub config: VmConfig,
+    pub memory_map: Vec<MemoryRange>,
+    pub devices: Vec<DeviceTopology>,
+}
+
+impl DryRunReport {
+    /// Builds the report out of a validated `VmConfig`. `boot_id_list` is the
+    /// list of identifiers returned by `VmConfig::validate()`. The devices
+    /// are named and the guest RAM laid out by the same code as when booting
+    /// the VM, and the reported config carries the resolved identifiers.
+    pub fn new(config: &VmConfig, boot_id_list: &BTreeSet<String>) -> Result<Self, DryRunError> {
+        let mut config = config.clone();
+        let mut device_id_cnt = Wrapping(0);
+        name_devices(&mut config, |prefix| {
+            next_device_name(&mut device_id_cnt, prefix, |name| {
+                boot_id_list.contains(name)
+            })
+        })
+        .map_err(DryRunError::DeviceNaming)?;
+
+        let (boot_ram, virtio_mem) =
+            MemoryManager::boot_memory_layout(&config.memory).map_err(DryRunError::MemoryLayout)?;
+        let virtio_mem_zones: Vec<String> = virtio_mem.iter().map(|r| r.0.clone()).collect();
+        let ram_regions = boot_ram
+            .into_iter()
+            .chain(virtio_mem)
+            .map(|(_, start, size)| (start.0, size));
+
+        Ok(DryRunReport {
+            memory_map: memory_map(ram_regions),
+            devices: DeviceTopologies::new(&config).devices(&config, &virtio_mem_zones)?,
+            config,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct CpusTopology {
+    pub boot_vcpus: u32,
+    pub present_vcpus: u32,
+    pub max_vcpus: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topology: Option<CpuTopology>,
+    pub max_phys_bits: u8,
+}
+
+#[derive(Serialize)]
+pub struct DeviceResources {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bdf: Option<PciBdf>,
+    pub resources: Vec<Resource>,
+}
+
+#[derive(Serialize)]
+pub struct VmTopology {
+    pub cpus: CpusTopology,
+    pub memory_map: Vec<MemoryRange>,
+    pub devices: Vec<DeviceResources>,
+}
+
+impl VmTopology {
+    /// Builds the topology of a running VM. `ram_regions` are the start and
+    /// size of the guest RAM regions actually mapped, including the
+    /// hotplugged ones, while the rest of the memory map is defined by the
+    /// architecture. Devices are sorted by PCI address, followed by the
+    /// devices not attached to a PCI bus sorted by identifier.
+    pub fn new(
+        config: &VmConfig,
+        present_vcpus: u32,
+        ram_regions: impl Iterator<Item = (u64, u64)>,
+        device_tree: &DeviceTree,
+    ) -> Self {
+        let mut devices: Vec<DeviceResources> = device_tree
+            .iter()
+            .map(|(id, node)| DeviceResources {
+                id: id.clone(),
+                parent: node.parent.clone(),
+                bdf: node.pci_bdf,
+                resources: node.resources.clone(),
+            })
+            .collect();
+        devices.sort_by(|a, b| match (a.bdf, b.bdf) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap(),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.id.cmp(&b.id),
+        });
+
+        VmTopology {
+            cpus: CpusTopology {
+                boot_vcpus: config.cpus.boot_vcpus,
+                present_vcpus,
+                max_vcpus: config.cpus.max_vcpus,
+                topology: config.cpus.topology.clone(),
+                max_phys_bits: config.cpus.max_phys_bits,
+            },
+            memory_map: memory_map(ram_regions),
+            devices,
+        }
+    }
+}
+
+// The memory map defined by the architecture, with the guest RAM regions
+// given as start and size.
+fn memory_map(ram_regions: impl Iterator<Item = (u64, u64)>) -> Vec<MemoryRange> {
+    let mut memory_map: Vec<MemoryRange> = arch::arch_memory_regions()
+        .into_iter()
+        .filter(|(_, _, r#type)| *r#type != RegionType::Ram)
+        .map(|(start, size, r#type)| MemoryRange {
+            start: start.0,
+            size: size as u64,
+            r#type,
+        })
+        .chain(ram_regions.map(|(start, size)| MemoryRange {
+            start,
+            size,
+            r#type: RegionType::Ram,
+        }))
+        .collect();
+    memory_map.sort_by_key(|r| r.start);
+
+    memory_map
+}
+
+// No BAR is ever moved as no device is actually added to the PCI buses.
+struct NoDeviceRelocation;
+
+impl DeviceRelocation for NoDeviceRelocation {
+    fn move_bar(
+        &self,
+        _old_base: u64,
+        _new_base: u64,
+        _len: u64,
+        _pci_dev: &mut dyn PciDevice,
+        _region_type: PciBarRegionType,
+    ) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+// Lists the devices in the order the DeviceManager creates them, allocating
+// their PCI slot from the same PCI bus implementation.
+struct DeviceTopologies {
+    transport: VirtioTransportType,
+    pci_buses: BTreeMap<u16, PciBus>,
+    devices: Vec<DeviceTopology>,
+}
+
+impl DeviceTopologies {
+    fn new(config: &VmConfig) -> Self {
+        DeviceTopologies {
+            transport: config.virtio_transport(),
+            pci_buses: BTreeMap::new(),
+            devices: Vec::new(),
+        }
+    }
+
+    fn add(
+        &mut self,
+        id: &Option<String>,
+        device_type: &'static str,
+        pci_segment: u16,
+        iommu: bool,
+        virtio: bool,
+    ) -> Result<&mut DeviceTopology, DryRunError> {
+        let transport = virtio.then_some(self.transport);
+        let bdf = if transport == Some(VirtioTransportType::Mmio) {
+            None
+        } else {
+            let pci_bus = self
+                .pci_buses
+                .entry(pci_segment)
+                .or_insert_with(|| PciBus::new(PciRoot::new(None), Arc::new(NoDeviceRelocation)));
+            let device = pci_bus.next_device_id().map_err(DryRunError::PciSlot)?;
+            Some(PciBdf::new(pci_segment, 0, device as u8, 0))
+        };
+
+        self.devices.push(DeviceTopology {
+            // All the devices are named by now
+            id: id.clone().unwrap(),
+            device_type,
+            transport,
+            bdf,
+            iommu,
+            num_queues: None,
+            queue_size: None,
+            rx_queue_size: None,
+            tx_queue_size: None,
+        });
+        Ok(self.devices.last_mut().unwrap())
+    }
+
+    fn add_virtio(
+        &mut self,
+        id: &Option<String>,
+        device_type: &'static str,
+        pci_segment: u16,
+        iommu: bool,
+    ) -> Result<&mut DeviceTopology, DryRunError> {
+        self.add(id, device_type, pci_segment, iommu, true)
+    }
+
+    fn devices(
+        mut self,
+        config: &VmConfig,
+        virtio_mem_zones: &[String],
+    ) -> Result<Vec<DeviceTopology>, DryRunError> {
+        if config.console.mode != ConsoleOutputMode::Off {
+            self.add_virtio(
+                &Some(CONSOLE_DEVICE_NAME.to_owned()),
+                "virtio-console",
+                0,
+                config.console.iommu,
+            )?;
+        }
+
+        for disk in config.disks.iter().flatten() {
+            let device_type = if disk.vhost_user {
+                "vhost-user-block"
+            } else if disk.media == DiskMedia::Cdrom {
+                "virtio-scsi"
+            } else {
+                "virtio-block"
+            };
+            let device = self.add_virtio(&disk.id, device_type, disk.pci_segment, disk.iommu)?;
+            if disk.media == DiskMedia::Disk {
+                device.num_queues = Some(disk.num_queues);
+                device.queue_size = Some(disk.queue_size);
+            }
+        }
+
+        for net in config.net.iter().flatten() {
+            let device_type = if net.vdpa.is_some() {
+                "vdpa"
+            } else if net.vhost_user {
+                "vhost-user-net"
+            } else {
+                "virtio-net"
+            };
+            let device = self.add_virtio(&net.id, device_type, net.pci_segment, net.iommu)?;
+            device.num_queues = Some(net.num_queues);
+            device.queue_size = Some(net.queue_size);
+            device.rx_queue_size = net.rx_queue_size;
+            device.tx_queue_size = net.tx_queue_size;
+        }
+
+        self.add_virtio(
+            &Some(RNG_DEVICE_NAME.to_owned()),
+            "virtio-rng",
+            0,
+            config.rng.iommu,
+        )?;
+
+        for fs in config.fs.iter().flatten() {
+            let device = self.add_virtio(&fs.id, "vhost-user-fs", fs.pci_segment, false)?;
+            device.num_queues = Some(fs.num_queues);
+            device.queue_size = Some(fs.queue_size);
+        }
+
+        for pmem in config.pmem.iter().flatten() {
+            self.add_virtio(&pmem.id, "virtio-pmem", pmem.pci_segment, pmem.iommu)?;
+        }
+
+        if let Some(vsock) = config.vsock.as_ref() {
+            self.add_virtio(&vsock.id, "virtio-vsock", vsock.pci_segment, vsock.iommu)?;
+        }
+
+        for zone in virtio_mem_zones {
+            self.add_virtio(&Some(zone.clone()), "virtio-mem", 0, false)?;
+        }
+
+        if config.balloon.is_some() {
+            self.add_virtio(
+                &Some(BALLOON_DEVICE_NAME.to_owned()),
+                "virtio-balloon",
+                0,
+                false,
+            )?;
+        }
+
+        if config.watchdog {
+            self.add_virtio(
+                &Some(WATCHDOG_DEVICE_NAME.to_owned()),
+                "virtio-watchdog",
+                0,
+                false,
+            )?;
+        }
+
+        for vdpa in config.vdpa.iter().flatten() {
+            let device = self.add_virtio(&vdpa.id, "vdpa", vdpa.pci_segment, vdpa.iommu)?;
+            device.num_queues = Some(vdpa.num_queues);
+        }
+
+        for scsi in config.scsi.iter().flatten() {
+            let device = self.add_virtio(&scsi.id, "virtio-scsi", scsi.pci_segment, scsi.iommu)?;
+            device.queue_size = Some(scsi.queue_size);
+        }
+
+        for device in config.devices.iter().flatten() {
+            self.add(&device.id, "vfio", device.pci_segment, device.iommu, false)?;
+        }
+
+        for device in config.user_devices.iter().flatten() {
+            self.add(&device.id, "vfio-user", device.pci_segment, false, false)?;
+        }
+
+        if config.iommu {
+            self.add_virtio(
+                &Some(IOMMU_DEVICE_NAME.to_owned()),
+                "virtio-iommu",
+                0,
+                false,
+            )?;
+        }
+
+        if config.pvpanic {
+            self.add(
+                &Some(PVPANIC_DEVICE_NAME.to_owned()),
+                "pvpanic",
+                0,
+                false,
+                false,
+            )?;
+        }
+
+        Ok(self.devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DiskConfig, NetConfig, PlatformConfig};
+    use crate::device_tree::DeviceNode;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_dry_run_devices() {
+        let mut config: VmConfig =
+            serde_json::from_str(r#"{ "payload": {"kernel": "/path/to/kernel"} }"#).unwrap();
+        config.disks = Some(vec![
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to/disk0")),
+                ..Default::default()
+            },
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to/disk1")),
+                id: Some("root".to_owned()),
+                ..Default::default()
+            },
+        ]);
+        config.net = Some(vec![NetConfig {
+            rx_queue_size: Some(1024),
+            ..Default::default()
+        }]);
+
+        let report = DryRunReport::new(&config, &BTreeSet::new()).unwrap();
+        let devices: Vec<(&str, String)> = report
+            .devices
+            .iter()
+            .map(|d| (d.id.as_str(), d.bdf.unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            devices,
+            vec![
+                ("__console", "0000:00:01.0".to_owned()),
+                ("_disk0", "0000:00:02.0".to_owned()),
+                ("root", "0000:00:03.0".to_owned()),
+                ("_net1", "0000:00:04.0".to_owned()),
+                ("__rng", "0000:00:05.0".to_owned()),
+            ]
+        );
+        assert_eq!(report.devices[3].rx_queue_size, Some(1024));
+
+        let ram: u64 = report
+            .memory_map
+            .iter()
+            .filter(|r| r.r#type == RegionType::Ram)
+            .map(|r| r.size)
+            .sum();
+        assert_eq!(ram, config.memory.size);
+        assert_eq!(
+            report.config.disks.as_ref().unwrap()[0].id.as_deref(),
+            Some("_disk0")
+        );
+    }
+
+    #[test]
+    fn test_dry_run_virtio_mmio() {
+        let mut config: VmConfig =
+            serde_json::from_str(r#"{ "payload": {"kernel": "/path/to/kernel"} }"#).unwrap();
+        config.platform = Some(PlatformConfig {
+            acpi: false,
+            ..Default::default()
+        });
+        config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/disk0")),
+            ..Default::default()
+        }]);
+        config.pvpanic = true;
+
+        let report = DryRunReport::new(&config, &BTreeSet::new()).unwrap();
+        let devices: Vec<(&str, Option<VirtioTransportType>, Option<String>)> = report
+            .devices
+            .iter()
+            .map(|d| (d.id.as_str(), d.transport, d.bdf.map(|bdf| bdf.to_string())))
+            .collect();
+        assert_eq!(
+            devices,
+            vec![
+                ("__console", Some(VirtioTransportType::Mmio), None),
+                ("_disk0", Some(VirtioTransportType::Mmio), None),
+                ("__rng", Some(VirtioTransportType::Mmio), None),
+                ("__pvpanic", None, Some("0000:00:01.0".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vm_topology() {
+        let config: VmConfig =
+            serde_json::from_str(r#"{ "payload": {"kernel": "/path/to/kernel"} }"#).unwrap();
+
+        let mut device_tree = DeviceTree::new();
+        for (id, bdf) in [
+            ("_disk0", Some(2)),
+            ("__serial", None),
+            ("__console", Some(1)),
+        ] {
+            let mut node = DeviceNode::new(id.to_owned(), None);
+            node.pci_bdf = bdf.map(|device| PciBdf::new(0, 0, device, 0));
+            node.resources.push(Resource::LegacyIrq(5));
+            device_tree.insert(id.to_owned(), node);
+        }
+
+        let ram_size = config.memory.size + (1 << 30);
+        let topology = VmTopology::new(&config, 1, [(0, ram_size)].into_iter(), &device_tree);
+        assert_eq!(topology.cpus.present_vcpus, 1);
+        assert_eq!(topology.cpus.max_vcpus, config.cpus.max_vcpus);
+
+        let ids: Vec<&str> = topology.devices.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["__console", "_disk0", "__serial"]);
+
+        let ram: Vec<(u64, u64)> = topology
+            .memory_map
+            .iter()
+            .filter(|r| r.r#type == RegionType::Ram)
+            .map(|r| (r.start, r.size))
+            .collect();
+        assert_eq!(ram, vec![(0, ram_size)]);
+        assert!(topology
+            .memory_map
+            .windows(2)
+            .all(|w| w[0].start <= w[1].start));
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::config::{CpuTopology, MemoryZoneConfig, NumaConfig, NumaDistance, VmConfig};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SYSFS_CPU_PATH: &str = "/sys/devices/system/cpu";
+const SYSFS_NODE_PATH: &str = "/sys/devices/system/node";
+
+// The guest RAM is split across the NUMA nodes in multiples of this size,
+// unless huge pages of another size are used.
+const NUMA_ZONE_ALIGNMENT: u64 = 2 << 20;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Parses a list of CPUs or NUMA nodes as found in sysfs, e.g. "0-3,8,10-11".
+fn parse_id_list(list: &str) -> Option<Vec<u32>> {
+    let mut ids = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => ids.extend(start.parse::<u32>().ok()?..=end.parse().ok()?),
+            None => ids.push(range.parse().ok()?),
+        }
+    }
+
+    Some(ids)
+}
+
+fn read_id_list(path: &Path) -> io::Result<Vec<u32>> {
+    let list = fs::read_to_string(path)?;
+    parse_id_list(&list).ok_or_else(|| invalid_data(format!("Invalid list in {path:?}: {list}")))
+}
+
+/// Layout of the host CPUs and NUMA nodes, as found in sysfs.
+pub struct HostTopology {
+    threads_per_core: u32,
+    cores_per_die: u32,
+    dies_per_package: u32,
+    // NUMA node holding each package, in the order of the package ids.
+    package_nodes: Vec<u32>,
+    // Distance from each NUMA node to each other one.
+    distances: BTreeMap<(u32, u32), u8>,
+}
+
+impl HostTopology {
+    pub fn new() -> io::Result<Self> {
+        let cpu_path = Path::new(SYSFS_CPU_PATH);
+        let cpus = read_id_list(&cpu_path.join("online"))?;
+        let first = cpus
+            .first()
+            .ok_or_else(|| invalid_data("No online host CPU".to_owned()))?;
+
+        // All the cores and packages are assumed to be alike, hence the first
+        // CPU being representative of all of them.
+        let topology_path = cpu_path.join(format!("cpu{first}/topology"));
+        let threads = read_id_list(&topology_path.join("thread_siblings_list"))?.len() as u32;
+        let package_cpus = read_id_list(&topology_path.join("core_siblings_list"))?.len() as u32;
+        // Dies are only reported by Linux 5.2 and later.
+        let die_cpus = match read_id_list(&topology_path.join("die_cpus_list")) {
+            Ok(die_cpus) => die_cpus.len() as u32,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => package_cpus,
+            Err(e) => return Err(e),
+        };
+        if threads == 0 || die_cpus % threads != 0 || die_cpus == 0 || package_cpus % die_cpus != 0
+        {
+            return Err(invalid_data(format!(
+                "Inconsistent host CPU topology: {threads} threads per core, \
+                {die_cpus} CPUs per die, {package_cpus} CPUs per package"
+            )));
+        }
+
+        let node_path = Path::new(SYSFS_NODE_PATH);
+        // Kernels built without NUMA support don't expose any node.
+        let nodes = match read_id_list(&node_path.join("online")) {
+            Ok(nodes) => nodes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut cpu_nodes = BTreeMap::new();
+        let mut distances = BTreeMap::new();
+        for node in nodes.iter() {
+            for cpu in read_id_list(&node_path.join(format!("node{node}/cpulist")))? {
+                cpu_nodes.insert(cpu, *node);
+            }
+
+            // The distances are listed in the order of the online nodes.
+            let path = node_path.join(format!("node{node}/distance"));
+            let line = fs::read_to_string(&path)?;
+            for (destination, distance) in nodes.iter().zip(line.split_whitespace()) {
+                let distance = distance
+                    .parse()
+                    .map_err(|_| invalid_data(format!("Invalid distance in {path:?}: {line}")))?;
+                distances.insert((*node, *destination), distance);
+            }
+        }
+
+        let mut package_nodes = BTreeMap::new();
+        for cpu in cpus.iter() {
+            let path = cpu_path.join(format!("cpu{cpu}/topology/physical_package_id"));
+            let package: u32 = fs::read_to_string(&path)?
+                .trim()
+                .parse()
+                .map_err(|_| invalid_data(format!("Invalid package id in {path:?}")))?;
+            package_nodes
+                .entry(package)
+                .or_insert_with(|| cpu_nodes.get(cpu).copied().unwrap_or_default());
+        }
+
+        Ok(HostTopology {
+            threads_per_core: threads,
+            cores_per_die: die_cpus / threads,
+            dies_per_package: package_cpus / die_cpus,
+            package_nodes: package_nodes.into_values().collect(),
+            distances,
+        })
+    }
+
+    /// Topology shaped like the host one holding the given number of vCPUs.
+    /// The guest cores have as many threads as the host ones, unless the
+    /// number of vCPUs isn't a multiple of it, and are spread across as few
+    /// packages as needed, each of them being at most as large as a host one.
+    pub fn guest_topology(&self, vcpus: u32) -> Option<CpuTopology> {
+        let threads_per_core = if vcpus % self.threads_per_core == 0 {
+            self.threads_per_core
+        } else {
+            1
+        };
+        let cores = vcpus / threads_per_core;
+
+        let host_cores_per_package = self.cores_per_die * self.dies_per_package;
+        let mut packages = (cores + host_cores_per_package - 1) / host_cores_per_package;
+        while cores % packages != 0 {
+            packages += 1;
+        }
+        let cores_per_package = cores / packages;
+
+        // Dies are not supported on AArch64.
+        let dies_per_package =
+            if cfg!(target_arch = "aarch64") || cores_per_package % self.dies_per_package != 0 {
+                1
+            } else {
+                self.dies_per_package
+            };
+
+        Some(CpuTopology {
+            threads_per_core: u8::try_from(threads_per_core).ok()?,
+            cores_per_die: u8::try_from(cores_per_package / dies_per_package).ok()?,
+            dies_per_package: u8::try_from(dies_per_package).ok()?,
+            packages: u8::try_from(packages).ok()?,
+        })
+    }
+
+    // Host NUMA node of each guest NUMA node along with its vCPUs, the guest
+    // package n being mirrored from the host package n modulo the number of
+    // host packages.
+    fn guest_numa_nodes(&self, topology: &CpuTopology) -> Vec<(u32, Vec<u32>)> {
+        let cpus_per_package = u32::from(topology.threads_per_core)
+            * u32::from(topology.cores_per_die)
+            * u32::from(topology.dies_per_package);
+        let mut nodes: Vec<(u32, Vec<u32>)> = Vec::new();
+        for package in 0..u32::from(topology.packages) {
+            let host_node = self.package_nodes[package as usize % self.package_nodes.len()];
+            let cpus = package * cpus_per_package..(package + 1) * cpus_per_package;
+            match nodes.iter_mut().find(|(node, _)| *node == host_node) {
+                Some((_, node_cpus)) => node_cpus.extend(cpus),
+                None => nodes.push((host_node, cpus.collect())),
+            }
+        }
+
+        nodes
+    }
+
+    /// Replaces the `topology=host` request of the configuration with a
+    /// topology mirroring the host one, along with NUMA nodes mirroring the
+    /// host nodes the guest packages are mapped onto. The NUMA nodes are only
+    /// generated when the guest spans several of them, none was configured,
+    /// and the guest RAM is a single block which can be split into memory
+    /// zones, each of them being bound to the host node it mirrors.
+    pub fn apply(&self, config: &mut VmConfig) -> io::Result<()> {
+        let topology = self.guest_topology(config.cpus.max_vcpus).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "No topology mirroring the host one holds {} vCPUs",
+                    config.cpus.max_vcpus
+                ),
+            )
+        })?;
+        let nodes = self.guest_numa_nodes(&topology);
+        config.cpus.topology = Some(topology);
+        config.cpus.host_topology = false;
+
+        let memory = &config.memory;
+        let alignment = memory.hugepage_size.unwrap_or(NUMA_ZONE_ALIGNMENT);
+        let zone_size = memory.size / nodes.len() as u64 / alignment * alignment;
+        if nodes.len() < 2
+            || config.numa.is_some()
+            || memory.zones.is_some()
+            || memory.file.is_some()
+            || memory.hotplug_size.is_some()
+            || memory.numa_bind.is_some()
+            || zone_size == 0
+        {
+            return Ok(());
+        }
+
+        let mut zones = Vec::new();
+        let mut numa = Vec::new();
+        for (guest_node, (host_node, cpus)) in nodes.iter().enumerate() {
+            let id = format!("numa{guest_node}");
+            zones.push(MemoryZoneConfig {
+                id: id.clone(),
+                // The remainder of the split goes to the first node.
+                size: if guest_node == 0 {
+                    memory.size - zone_size * (nodes.len() as u64 - 1)
+                } else {
+                    zone_size
+                },
+                file: None,
+                shared: memory.shared,
+                hugepages: memory.hugepages,
+                hugepage_size: memory.hugepage_size,
+                host_numa_node: Some(*host_node),
+                hotplug_size: None,
+                hotplugged_size: None,
+                prefault: memory.prefault,
+            });
+
+            let distances = nodes
+                .iter()
+                .enumerate()
+                .filter(|(destination, _)| *destination != guest_node)
+                .filter_map(|(destination, (other_node, _))| {
+                    self.distances
+                        .get(&(*host_node, *other_node))
+                        .map(|distance| NumaDistance {
+                            destination: destination as u32,
+                            distance: *distance,
+                        })
+                })
+                .collect::<Vec<_>>();
+            numa.push(NumaConfig {
+                guest_numa_id: guest_node as u32,
+                cpus: Some(cpus.clone()),
+                distances: (!distances.is_empty()).then_some(distances),
+                memory_zones: Some(vec![id]),
+                #[cfg(target_arch = "x86_64")]
+                sgx_epc_sections: None,
+            });
+        }
+
+        config.memory.size = 0;
+        config.memory.zones = Some(zones);
+        config.numa = Some(numa);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CpusConfig;
+
+    #[test]
+    fn test_parse_id_list() {
+        assert_eq!(parse_id_list("0\n"), Some(vec![0]));
+        assert_eq!(
+            parse_id_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_id_list("\n"), Some(vec![]));
+        assert_eq!(parse_id_list("0-x"), None);
+    }
+
+    fn host_topology() -> HostTopology {
+        // Two packages of two dies of 8 cores with 2 threads each, on two
+        // NUMA nodes.
+        HostTopology {
+            threads_per_core: 2,
+            cores_per_die: 8,
+            dies_per_package: 2,
+            package_nodes: vec![0, 1],
+            distances: [((0, 0), 10), ((0, 1), 21), ((1, 0), 21), ((1, 1), 10)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_guest_topology() {
+        let host = host_topology();
+        let topology = |t, c, d, p| CpuTopology {
+            threads_per_core: t,
+            cores_per_die: c,
+            dies_per_package: d,
+            packages: p,
+        };
+
+        assert_eq!(host.guest_topology(8), Some(topology(2, 2, 2, 1)));
+        assert_eq!(host.guest_topology(32), Some(topology(2, 8, 2, 1)));
+        assert_eq!(host.guest_topology(64), Some(topology(2, 8, 2, 2)));
+        assert_eq!(host.guest_topology(3), Some(topology(1, 3, 1, 1)));
+        assert_eq!(host.guest_topology(40), Some(topology(2, 5, 2, 2)));
+    }
+
+    #[test]
+    fn test_apply_host_topology() {
+        let host = host_topology();
+        let mut config = VmConfig::builder()
+            .cpus_config(CpusConfig {
+                boot_vcpus: 64,
+                max_vcpus: 64,
+                host_topology: true,
+                ..Default::default()
+            })
+            .memory_mb(3072)
+            .kernel("/path/to/kernel")
+            .build()
+            .unwrap();
+        host.apply(&mut config).unwrap();
+        config.validate().unwrap();
+
+        assert!(!config.cpus.host_topology);
+        assert_eq!(config.cpus.topology.as_ref().unwrap().packages, 2);
+        assert_eq!(config.memory.size, 0);
+        let zones = config.memory.zones.as_ref().unwrap();
+        assert_eq!(zones[0].size + zones[1].size, 3 << 30);
+        assert_eq!(zones[1].size, 1536 << 20);
+        assert_eq!(zones[1].host_numa_node, Some(1));
+        let numa = config.numa.as_ref().unwrap();
+        assert_eq!(numa[0].cpus, Some((0..32).collect()));
+        assert_eq!(numa[1].cpus, Some((32..64).collect()));
+        assert_eq!(numa[1].memory_zones, Some(vec!["numa1".to_owned()]));
+        assert_eq!(
+            numa[1].distances,
+            Some(vec![NumaDistance {
+                destination: 0,
+                distance: 21
+            }])
+        );
+
+        // A single package doesn't need any NUMA node.
+        let mut config = VmConfig::builder()
+            .cpus_config(CpusConfig {
+                boot_vcpus: 4,
+                max_vcpus: 4,
+                host_topology: true,
+                ..Default::default()
+            })
+            .kernel("/path/to/kernel")
+            .build()
+            .unwrap();
+        host.apply(&mut config).unwrap();
+        assert!(config.numa.is_none());
+        assert!(config.memory.zones.is_none());
+    }
+}
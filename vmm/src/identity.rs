@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::config::PlatformConfig;
+use net_util::MacAddr;
+
+// Longest serial a guest can read from a virtio-blk device.
+const DISK_SERIAL_LEN: usize = 20;
+
+// FNV-1a parameters for 64-bit hashes.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(state: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(state, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Derives the guest visible identifiers which would otherwise be random or
+/// host specific (MAC addresses, disk serials, system UUID) out of the seed
+/// given through `--platform seed=<value>`.
+///
+/// Each identifier is generated from the seed and a label naming what it is
+/// for, so that the same seed and VM configuration always produce the same
+/// identity, independently of the host and of the order values are derived.
+/// The generator is not cryptographically secure.
+pub struct IdentitySeed {
+    seed: u64,
+}
+
+impl IdentitySeed {
+    pub fn new(seed: &str) -> Self {
+        IdentitySeed {
+            seed: fnv1a(FNV_OFFSET_BASIS, seed.as_bytes()),
+        }
+    }
+
+    fn fill_bytes(&self, label: &str, bytes: &mut [u8]) {
+        let mut state = fnv1a(self.seed, label.as_bytes());
+        for chunk in bytes.chunks_mut(8) {
+            let value = splitmix64(&mut state).to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+
+    /// Locally administered MAC address, matching [`MacAddr::local_random`].
+    pub fn mac_addr(&self, label: &str) -> MacAddr {
+        let mut bytes = [0u8; 6];
+        self.fill_bytes(label, &mut bytes);
+        bytes[0] = 0x2e;
+        MacAddr::from_bytes_unchecked(&bytes)
+    }
+
+    /// Hexadecimal string filling the 20 bytes of a virtio-blk serial.
+    pub fn disk_serial(&self, label: &str) -> String {
+        let mut bytes = [0u8; DISK_SERIAL_LEN / 2];
+        self.fill_bytes(label, &mut bytes);
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Version 4 UUID, formatted as expected by `--platform uuid=`.
+    pub fn uuid(&self, label: &str) -> String {
+        let mut bytes = [0u8; 16];
+        self.fill_bytes(label, &mut bytes);
+        uuid::Builder::from_random_bytes(bytes)
+            .into_uuid()
+            .hyphenated()
+            .to_string()
+    }
+}
+
+/// Serial of the disk `id` inheriting the platform serial number, which it is
+/// suffixed with so that each disk stays unique. When the guest can't read it
+/// whole, the platform part is shortened to a hash of it so that the suffix
+/// is kept, unless the id is too long itself, in which case the whole serial
+/// is hashed.
+pub fn platform_disk_serial(serial_number: &str, id: &str) -> String {
+    let serial = format!("{serial_number}-{id}");
+    if serial.len() <= DISK_SERIAL_LEN {
+        return serial;
+    }
+
+    let hash = IdentitySeed::new(serial_number);
+    match DISK_SERIAL_LEN.checked_sub(id.len() + 1) {
+        Some(len) if len > 0 => {
+            format!("{}-{id}", &hash.disk_serial("serial_number")[..len])
+        }
+        _ => hash.disk_serial(&format!("disk:{id}")),
+    }
+}
+
+/// Guest MAC address of the network device `id` configured without one,
+/// derived from the platform seed if any and random otherwise.
+pub fn default_net_mac(platform: Option<&PlatformConfig>, id: &str) -> MacAddr {
+    platform
+        .and_then(|p| p.seed.as_deref())
+        .map_or_else(MacAddr::local_random, |seed| {
+            IdentitySeed::new(seed).mac_addr(&format!("net:{id}"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NetConfig;
+
+    #[test]
+    fn test_identity_seed() {
+        let seed = IdentitySeed::new("ci");
+        assert_eq!(
+            seed.mac_addr("net0"),
+            IdentitySeed::new("ci").mac_addr("net0")
+        );
+        assert_ne!(seed.mac_addr("net0"), seed.mac_addr("net1"));
+        assert_ne!(
+            seed.mac_addr("net0"),
+            IdentitySeed::new("cd").mac_addr("net0")
+        );
+        assert_eq!(seed.mac_addr("net0").get_bytes()[0], 0x2e);
+
+        let serial = seed.disk_serial("_disk0");
+        assert_eq!(serial.len(), 20);
+        assert_eq!(serial, IdentitySeed::new("ci").disk_serial("_disk0"));
+
+        let uuid = uuid::Uuid::parse_str(&seed.uuid("system")).unwrap();
+        assert_eq!(uuid.get_version_num(), 4);
+        assert_eq!(seed.uuid("system"), IdentitySeed::new("ci").uuid("system"));
+    }
+
+    #[test]
+    fn test_platform_disk_serial() {
+        assert_eq!(platform_disk_serial("ABC123", "_disk0"), "ABC123-_disk0");
+
+        // The platform part is shortened, the disk id is kept.
+        let serial = platform_disk_serial("0123456789ABCDEF", "_disk0");
+        assert_eq!(serial.len(), 20);
+        assert!(serial.ends_with("-_disk0"));
+        assert_ne!(serial, platform_disk_serial("0123456789ABCDEG", "_disk0"));
+        assert_ne!(serial, platform_disk_serial("0123456789ABCDEF", "_disk1"));
+        assert_eq!(serial, platform_disk_serial("0123456789ABCDEF", "_disk0"));
+
+        // The disk id doesn't fit, the whole serial is hashed.
+        let serial = platform_disk_serial("ABC123", "a-very-long-disk-identifier");
+        assert_eq!(serial.len(), 20);
+        assert_ne!(
+            serial,
+            platform_disk_serial("ABC123", "a-very-long-disk-identifieR")
+        );
+    }
+
+    #[test]
+    fn test_default_net_mac() {
+        // Configurations as received through vm.create and vm.add-net.
+        let platform: PlatformConfig = serde_json::from_str(r#"{"seed": "ci"}"#).unwrap();
+        let net: NetConfig = serde_json::from_str(r#"{"id": "net0"}"#).unwrap();
+        assert_eq!(net.mac, None);
+        assert_eq!(
+            default_net_mac(Some(&platform), "net0"),
+            default_net_mac(Some(&platform), "net0")
+        );
+        assert_eq!(
+            default_net_mac(Some(&platform), "net0"),
+            IdentitySeed::new("ci").mac_addr("net:net0")
+        );
+        assert_ne!(
+            default_net_mac(Some(&platform), "net0"),
+            default_net_mac(Some(&platform), "net1")
+        );
+
+        // Without any seed, the address is random.
+        let platform: PlatformConfig = serde_json::from_str("{}").unwrap();
+        assert_ne!(
+            default_net_mac(Some(&platform), "net0"),
+            IdentitySeed::new("ci").mac_addr("net:net0")
+        );
+        assert_eq!(default_net_mac(None, "net0").get_bytes()[0] & 0x3, 0x2);
+
+        let net: NetConfig = serde_json::from_str(r#"{"mac": "12:34:56:78:9a:bc"}"#).unwrap();
+        assert_eq!(
+            net.mac,
+            Some(MacAddr::parse_str("12:34:56:78:9a:bc").unwrap())
+        );
+    }
+}
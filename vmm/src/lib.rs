@@ -9,25 +9,28 @@ extern crate event_monitor;
 extern crate log;
 
 use crate::api::{
-    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmInfo, VmReceiveMigrationData,
-    VmSendMigrationData, VmmPingResponse,
+    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, NetInterfaceInfo, VmInfo,
+    VmPingResponse, VmReceiveMigrationData, VmSendMigrationData, VmSnapshotConfig, VmmPingResponse,
 };
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig,
-    UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, DeviceConfig, DiskConfig, FsConfig, MemoryConfig, NetConfig, PmemConfig,
+    RestoreConfig, UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
 };
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::GuestDebuggable;
-use crate::memory_manager::MemoryManager;
+use crate::device_manager::restore_terminal_mode;
+use crate::memory_manager::{Error as MemoryManagerError, MemoryManager};
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 use crate::migration::get_vm_snapshot;
-use crate::migration::{recv_vm_config, recv_vm_state};
+use crate::migration::{
+    recv_vm_config, recv_vm_state, MigrationMemoryReader, MigrationPhase, MigrationStatus,
+};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::vm::{Error as VmError, Vm, VmState};
 use anyhow::anyhow;
 #[cfg(feature = "dbus_api")]
 use api::dbus::{DBusApiOptions, DBusApiShutdownChannels};
-use libc::{tcsetattr, termios, EFD_NONBLOCK, SIGINT, SIGTERM, TCSANOW};
+use libc::{termios, EFD_NONBLOCK, SIGINT, SIGTERM};
 use memory_manager::MemoryManagerSnapshotData;
 use pci::PciBdf;
 use seccompiler::{apply_filter, SeccompAction};
@@ -37,7 +40,7 @@ use signal_hook::iterator::{Handle, Signals};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
-use std::io::{stdout, Read, Write};
+use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
@@ -46,7 +49,7 @@ use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::mpsc::{Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{result, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
@@ -56,20 +59,27 @@ use vm_migration::{MigratableError, Pausable, Snapshot, Snapshottable, Transport
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::unblock_signal;
 use vmm_sys_util::sock_ctrl_msg::ScmSocket;
+use vmm_sys_util::timerfd::TimerFd;
 
 mod acpi;
 pub mod api;
 mod clone3;
 pub mod config;
+mod console_input;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 mod coredump;
 pub mod cpu;
 pub mod device_manager;
 pub mod device_tree;
+pub mod dry_run;
 #[cfg(feature = "guest_debug")]
 mod gdb;
+mod host_topology;
+mod identity;
 pub mod interrupt;
 pub mod memory_manager;
+#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+mod memory_pressure;
 pub mod migration;
 mod pci_segment;
 pub mod seccomp_filters;
@@ -200,6 +210,10 @@ pub enum EpollDispatch {
     Api = 2,
     ActivateVirtioDevices = 3,
     Debug = 4,
+    MigrationReceive = 5,
+    Shutdown = 6,
+    ShutdownTimeout = 7,
+    MemoryPressure = 8,
     Unknown,
 }
 
@@ -212,6 +226,10 @@ impl From<u64> for EpollDispatch {
             2 => Api,
             3 => ActivateVirtioDevices,
             4 => Debug,
+            5 => MigrationReceive,
+            6 => Shutdown,
+            7 => ShutdownTimeout,
+            8 => MemoryPressure,
             _ => Unknown,
         }
     }
@@ -293,6 +311,17 @@ impl Serialize for PciDeviceInfo {
     }
 }
 
+// A VM which isn't booted yet is resized through its configuration, which must
+// stay within max_ram as well.
+fn check_max_ram(memory: &MemoryConfig) -> result::Result<(), VmError> {
+    match memory.max_ram {
+        Some(max_ram) if memory.total_size() > max_ram => Err(VmError::MemoryManager(
+            MemoryManagerError::MaxRamExceeded(memory.total_size(), max_ram),
+        )),
+        _ => Ok(()),
+    }
+}
+
 pub fn start_event_monitor_thread(
     mut monitor: event_monitor::Monitor,
     seccomp_action: &SeccompAction,
@@ -356,6 +385,7 @@ pub fn start_vmm_thread(
     #[cfg(feature = "guest_debug")] debug_event: EventFd,
     #[cfg(feature = "guest_debug")] vm_debug_event: EventFd,
     exit_event: EventFd,
+    shutdown_timeout: Duration,
     seccomp_action: &SeccompAction,
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
 ) -> Result<VmmThreadHandle> {
@@ -396,9 +426,11 @@ pub fn start_vmm_thread(
                     vmm_seccomp_action,
                     hypervisor,
                     exit_event,
+                    shutdown_timeout,
                 )?;
 
                 vmm.setup_signal_handler()?;
+                vmm.setup_panic_hook();
 
                 vmm.control_loop(
                     Rc::new(api_receiver),
@@ -497,6 +529,28 @@ pub struct VmmThreadHandle {
     pub dbus_shutdown_chs: Option<DBusApiShutdownChannels>,
 }
 
+// Migration being received from a dedicated thread
+struct MigrationReceiver {
+    thread: thread::JoinHandle<result::Result<ReceivedMigration, MigratableError>>,
+    abort_evt: EventFd,
+    connection: Arc<Mutex<Option<UnixStream>>>,
+}
+
+// What the migration receiver thread hands over to the VMM thread
+enum ReceivedMigration {
+    // The VM state, for the VM to be restored
+    State {
+        socket: UnixStream,
+        state: Vec<u8>,
+        vm_config: Arc<Mutex<VmConfig>>,
+        memory_manager: Arc<Mutex<MemoryManager>>,
+    },
+    // The source completed the migration, for the VM to be resumed
+    Complete {
+        socket: UnixStream,
+    },
+}
+
 pub struct Vmm {
     epoll: EpollContext,
     exit_evt: EventFd,
@@ -515,15 +569,30 @@ pub struct Vmm {
     signals: Option<Handle>,
     threads: Vec<thread::JoinHandle<()>>,
     original_termios_opt: Arc<Mutex<Option<termios>>>,
+    migration_receive_evt: EventFd,
+    migration_receiver: Option<MigrationReceiver>,
+    migration_status: Option<Arc<Mutex<MigrationStatus>>>,
+    // Answered once the migration being received is over
+    migration_response: Option<Sender<ApiResponse>>,
+    shutdown_evt: EventFd,
+    shutdown_timeout: Duration,
+    shutdown_timer: Option<TimerFd>,
+    start_time: Instant,
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    memory_pressure: Option<memory_pressure::MemoryPressureMonitor>,
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    memory_pressure_dumped: bool,
 }
 
 impl Vmm {
     pub const HANDLED_SIGNALS: [i32; 2] = [SIGTERM, SIGINT];
 
+    // Termination requests are only forwarded to the control loop from here,
+    // as signal_hook already defers them out of the signal handler itself.
     fn signal_handler(
         mut signals: Signals,
         original_termios_opt: Arc<Mutex<Option<termios>>>,
-        exit_evt: &EventFd,
+        shutdown_evt: &EventFd,
     ) {
         for sig in &Self::HANDLED_SIGNALS {
             unblock_signal(*sig).unwrap();
@@ -532,14 +601,11 @@ impl Vmm {
         for signal in signals.forever() {
             match signal {
                 SIGTERM | SIGINT => {
-                    if exit_evt.write(1).is_err() {
+                    if shutdown_evt.write(1).is_err() {
                         // Resetting the terminal is usually done as the VMM exits
                         if let Ok(lock) = original_termios_opt.lock() {
                             if let Some(termios) = *lock {
-                                // SAFETY: FFI call
-                                let _ = unsafe {
-                                    tcsetattr(stdout().lock().as_raw_fd(), TCSANOW, &termios)
-                                };
+                                restore_terminal_mode(&termios);
                             }
                         } else {
                             warn!("Failed to lock original termios");
@@ -559,6 +625,7 @@ impl Vmm {
             Ok(signals) => {
                 self.signals = Some(signals.handle());
                 let exit_evt = self.exit_evt.try_clone().map_err(Error::EventFdClone)?;
+                let shutdown_evt = self.shutdown_evt.try_clone().map_err(Error::EventFdClone)?;
                 let original_termios_opt = Arc::clone(&self.original_termios_opt);
 
                 let signal_handler_seccomp_filter = get_seccomp_filter(
@@ -581,7 +648,7 @@ impl Vmm {
                                 }
                             }
                             std::panic::catch_unwind(AssertUnwindSafe(|| {
-                                Vmm::signal_handler(signals, original_termios_opt, &exit_evt);
+                                Vmm::signal_handler(signals, original_termios_opt, &shutdown_evt);
                             }))
                             .map_err(|_| {
                                 error!("vmm signal_handler thread panicked");
@@ -597,6 +664,31 @@ impl Vmm {
         Ok(())
     }
 
+    // A panic of the VMM thread can bring the whole process down without the
+    // devices being dropped, hence the terminal being restored before
+    // reporting it. Only the VMM thread is allowed to reset the terminal by
+    // its seccomp filter, while the panics of the vCPU and device threads are
+    // caught and turned into an exit of the VMM, which restores it.
+    fn setup_panic_hook(&self) {
+        let original_termios_opt = Arc::clone(&self.original_termios_opt);
+        let vmm_thread = thread::current().id();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if thread::current().id() != vmm_thread {
+                default_hook(info);
+                return;
+            }
+
+            // The lock may be held by the panicking thread.
+            if let Ok(lock) = original_termios_opt.try_lock() {
+                if let Some(termios) = *lock {
+                    restore_terminal_mode(&termios);
+                }
+            }
+            default_hook(info);
+        }));
+    }
+
     fn new(
         vmm_version: VmmVersionInfo,
         api_evt: EventFd,
@@ -605,10 +697,13 @@ impl Vmm {
         seccomp_action: SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         exit_evt: EventFd,
+        shutdown_timeout: Duration,
     ) -> Result<Self> {
         let mut epoll = EpollContext::new().map_err(Error::Epoll)?;
         let reset_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let activate_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let migration_receive_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let shutdown_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
 
         epoll
             .add_event(&exit_evt, EpollDispatch::Exit)
@@ -626,6 +721,14 @@ impl Vmm {
             .add_event(&api_evt, EpollDispatch::Api)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&migration_receive_evt, EpollDispatch::MigrationReceive)
+            .map_err(Error::Epoll)?;
+
+        epoll
+            .add_event(&shutdown_evt, EpollDispatch::Shutdown)
+            .map_err(Error::Epoll)?;
+
         #[cfg(feature = "guest_debug")]
         epoll
             .add_event(&debug_evt, EpollDispatch::Debug)
@@ -649,6 +752,18 @@ impl Vmm {
             signals: None,
             threads: vec![],
             original_termios_opt: Arc::new(Mutex::new(None)),
+            migration_receive_evt,
+            migration_receiver: None,
+            migration_status: None,
+            migration_response: None,
+            shutdown_evt,
+            shutdown_timeout,
+            shutdown_timer: None,
+            start_time: Instant::now(),
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            memory_pressure: None,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            memory_pressure_dumped: false,
         })
     }
 
@@ -699,6 +814,7 @@ impl Vmm {
                         None,
                         None,
                         None,
+                        None,
                         Arc::clone(&self.original_termios_opt),
                         None,
                         None,
@@ -709,6 +825,9 @@ impl Vmm {
                 }
             }
 
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            self.vm_watch_memory_pressure()?;
+
             // Now we can boot the VM.
             if let Some(ref mut vm) = self.vm {
                 vm.boot()
@@ -736,14 +855,48 @@ impl Vmm {
         }
     }
 
-    fn vm_snapshot(&mut self, destination_url: &str) -> result::Result<(), VmError> {
+    fn vm_snapshot(&mut self, snapshot_cfg: &VmSnapshotConfig) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
+            let destination_url = snapshot_cfg.destination_url.as_str();
+
+            if let Some(vm_config) = self.vm_config.as_ref() {
+                vm_config
+                    .lock()
+                    .unwrap()
+                    .validate_migratable()
+                    .map_err(|e| VmError::Snapshot(MigratableError::Snapshot(anyhow!("{}", e))))?;
+            }
+
+            if snapshot_cfg.track_changes && vm.dirty_rate_started() {
+                return Err(VmError::Snapshot(MigratableError::Snapshot(anyhow!(
+                    "Can't track the changes while the dirty rate is sampled"
+                ))));
+            }
+
+            if let Some(base_url) = &snapshot_cfg.base_url {
+                if snapshot_cfg.track_changes {
+                    return Err(VmError::Snapshot(MigratableError::Snapshot(anyhow!(
+                        "A differential snapshot can't be used as a base"
+                    ))));
+                }
+
+                vm.prepare_differential_snapshot(base_url)
+                    .map_err(VmError::Snapshot)?;
+            }
+
             vm.snapshot()
                 .map_err(VmError::Snapshot)
                 .and_then(|snapshot| {
                     vm.send(&snapshot, destination_url)
                         .map_err(VmError::SnapshotSend)
-                })
+                })?;
+
+            if snapshot_cfg.track_changes {
+                vm.track_snapshot_base(destination_url)
+                    .map_err(VmError::Snapshot)?;
+            }
+
+            Ok(())
         } else {
             Err(VmError::VmNotRunning)
         }
@@ -769,11 +922,14 @@ impl Vmm {
         let vm_snapshot = get_vm_snapshot(&snapshot).map_err(VmError::Restore)?;
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(&vm_config, &vm_snapshot.common_cpuid)
+        Self::vm_check_cpuid_compatibility(&self.hypervisor, &vm_config, &vm_snapshot.common_cpuid)
             .map_err(VmError::Restore)?;
 
         self.vm_config = Some(Arc::clone(&vm_config));
 
+        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+        self.vm_watch_memory_pressure()?;
+
         let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
         #[cfg(feature = "guest_debug")]
@@ -798,6 +954,7 @@ impl Vmm {
             None,
             None,
             None,
+            None,
             Arc::clone(&self.original_termios_opt),
             Some(snapshot),
             Some(source_url),
@@ -822,6 +979,100 @@ impl Vmm {
         }
     }
 
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    fn vm_watchdog_coredump(&mut self) {
+        let destination = if let Some(ref vm_config) = self.vm_config {
+            vm_config.lock().unwrap().watchdog_coredump.clone()
+        } else {
+            None
+        };
+
+        if let (Some(destination), Some(vm)) = (destination, self.vm.as_mut()) {
+            if !vm.watchdog_triggered() {
+                return;
+            }
+
+            // The reset is about to happen anyway, hence a failure to dump
+            // the guest must not prevent it.
+            info!("Capturing guest coredump to {:?}", destination);
+            if let Err(e) = vm.coredump(&format!("file://{}", destination.display())) {
+                error!(
+                    "Failed capturing guest coredump on watchdog timeout: {:?}",
+                    e
+                );
+            }
+        }
+    }
+
+    // Starts watching the memory pressure of the cgroup the VMM runs in when
+    // the guest must be dumped before the host runs out of memory. The
+    // monitor is kept for the lifetime of the VMM, but a single dump is
+    // captured for each VM booted or restored.
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    fn vm_watch_memory_pressure(&mut self) -> result::Result<(), VmError> {
+        let enabled = self
+            .vm_config
+            .as_ref()
+            .map(|vm_config| vm_config.lock().unwrap().memory.dump_on_oom.is_some())
+            .unwrap_or(false);
+        if !enabled {
+            return Ok(());
+        }
+
+        self.memory_pressure_dumped = false;
+        if self.memory_pressure.is_none() {
+            let monitor = memory_pressure::MemoryPressureMonitor::new()
+                .map_err(VmError::MemoryPressureMonitor)?;
+            self.epoll
+                .add_event(&monitor, EpollDispatch::MemoryPressure)
+                .map_err(VmError::MemoryPressureMonitor)?;
+            self.memory_pressure = Some(monitor);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    fn vm_memory_pressure_coredump(&mut self) {
+        let reported = match self.memory_pressure.as_mut().map(|m| m.pressure_reported()) {
+            Some(Ok(reported)) => reported,
+            Some(Err(e)) => {
+                error!("Failed reading the memory pressure events: {}", e);
+                false
+            }
+            None => false,
+        };
+        if !reported || self.memory_pressure_dumped {
+            return;
+        }
+
+        let destination = if let Some(ref vm_config) = self.vm_config {
+            vm_config.lock().unwrap().memory.dump_on_oom.clone()
+        } else {
+            None
+        };
+
+        if let (Some(destination), Some(vm)) = (destination, self.vm.as_mut()) {
+            if !matches!(vm.get_state(), Ok(VmState::Running) | Ok(VmState::Paused)) {
+                return;
+            }
+
+            // The OOM killer might strike at any point from now on, hence
+            // the dump is attempted only once, whatever its outcome.
+            self.memory_pressure_dumped = true;
+            warn!(
+                "Host memory pressure reported, capturing guest coredump to {:?}",
+                destination
+            );
+            if let Err(e) = vm.coredump(&format!("file://{}", destination.display())) {
+                error!(
+                    "Failed capturing guest coredump on memory pressure: {:?}",
+                    e
+                );
+            }
+        }
+    }
+
     fn vm_shutdown(&mut self) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm.take() {
             vm.shutdown()
@@ -832,7 +1083,7 @@ impl Vmm {
 
     fn vm_reboot(&mut self) -> result::Result<(), VmError> {
         // First we stop the current VM
-        let (config, serial_pty, console_pty, console_resize_pipe) =
+        let (config, serial_pty, console_pty, console_resize_pipe, device_backends) =
             if let Some(mut vm) = self.vm.take() {
                 let config = vm.get_config();
                 let serial_pty = vm.serial_pty();
@@ -841,8 +1092,17 @@ impl Vmm {
                     .console_resize_pipe()
                     .as_ref()
                     .map(|pipe| pipe.try_clone().unwrap());
+                // Keep the host resources backing the devices, so that the
+                // new VM doesn't have to open or create them again.
+                let device_backends = vm.take_device_backends();
                 vm.shutdown()?;
-                (config, serial_pty, console_pty, console_resize_pipe)
+                (
+                    config,
+                    serial_pty,
+                    console_pty,
+                    console_resize_pipe,
+                    device_backends,
+                )
             } else {
                 return Err(VmError::VmNotCreated);
             };
@@ -879,6 +1139,7 @@ impl Vmm {
             serial_pty,
             console_pty,
             console_resize_pipe,
+            Some(device_backends),
             Arc::clone(&self.original_termios_opt),
             None,
             None,
@@ -908,13 +1169,43 @@ impl Vmm {
                     memory_actual_size -= vm.balloon_size();
                 }
 
-                let device_tree = self.vm.as_ref().map(|vm| vm.device_tree());
+                let device_tree = self.vm.as_ref().map(|vm| {
+                    vm.update_virtio_features();
+                    vm.device_tree()
+                });
+
+                let serial_number = config
+                    .lock()
+                    .unwrap()
+                    .platform
+                    .as_ref()
+                    .and_then(|p| p.serial_number.clone());
+
+                let net_configs = config.lock().unwrap().net.clone().unwrap_or_default();
+                let net_interfaces = self
+                    .vm
+                    .as_ref()
+                    .map(|vm| vm.tap_names())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(id, tap)| {
+                        let net = net_configs.iter().find(|n| n.id.as_ref() == Some(&id));
+                        NetInterfaceInfo {
+                            mac: net.and_then(|n| n.mac),
+                            host_mac: net.and_then(|n| n.host_mac),
+                            id,
+                            tap,
+                        }
+                    })
+                    .collect();
 
                 Ok(VmInfo {
                     config,
                     state,
                     memory_actual_size,
                     device_tree,
+                    serial_number,
+                    net_interfaces,
                 })
             }
             None => Err(VmError::VmNotCreated),
@@ -934,6 +1225,32 @@ impl Vmm {
         }
     }
 
+    fn vm_ping(&self) -> VmPingResponse {
+        let VmmVersionInfo {
+            build_version,
+            version,
+        } = self.version.clone();
+
+        // Only rely on the VMM side state, the guest itself is never
+        // involved so that a wedged guest doesn't prevent from answering.
+        let state = match &self.vm {
+            Some(vm) => vm.get_state().ok(),
+            None => self.vm_config.as_ref().map(|_| VmState::Created),
+        };
+
+        VmPingResponse {
+            build_version,
+            version,
+            state,
+            vmm_uptime: self.start_time.elapsed().as_secs(),
+            vm_uptime: self
+                .vm
+                .as_ref()
+                .and_then(|vm| vm.uptime())
+                .map(|uptime| uptime.as_secs()),
+        }
+    }
+
     fn vm_delete(&mut self) -> result::Result<(), VmError> {
         if self.vm_config.is_none() {
             return Ok(());
@@ -952,6 +1269,11 @@ impl Vmm {
     }
 
     fn vmm_shutdown(&mut self) -> result::Result<(), VmError> {
+        if self.migration_receiver.is_some() {
+            if let Err(e) = self.vm_migration_abort() {
+                warn!("Error aborting the migration being received: {:?}", e);
+            }
+        }
         self.vm_delete()?;
         event!("vmm", "shutdown");
         Ok(())
@@ -959,7 +1281,7 @@ impl Vmm {
 
     fn vm_resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_ram: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> result::Result<(), VmError> {
@@ -974,6 +1296,11 @@ impl Vmm {
             }
         } else {
             let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            if let Some(desired_ram) = desired_ram {
+                let mut memory = config.memory.clone();
+                memory.size = desired_ram;
+                check_max_ram(&memory)?;
+            }
             if let Some(desired_vcpus) = desired_vcpus {
                 config.cpus.boot_vcpus = desired_vcpus;
             }
@@ -1003,17 +1330,15 @@ impl Vmm {
             // Update VmConfig by setting the new desired ram.
             let memory_config = &mut self.vm_config.as_ref().unwrap().lock().unwrap().memory;
 
-            if let Some(zones) = &mut memory_config.zones {
-                for zone in zones.iter_mut() {
-                    if zone.id == id {
-                        zone.size = desired_ram;
-                        return Ok(());
-                    }
-                }
+            let mut memory = memory_config.clone();
+            if let Some(zone) = memory.zones.iter_mut().flatten().find(|zone| zone.id == id) {
+                zone.size = desired_ram;
+                check_max_ram(&memory)?;
+                *memory_config = memory;
+                return Ok(());
             }
 
-            error!("Could not find the memory zone {} for the resize", id);
-            Err(VmError::ResizeZone)
+            Err(VmError::UnknownMemoryZone(id))
         }
     }
 
@@ -1095,6 +1420,43 @@ impl Vmm {
         }
     }
 
+    fn vm_remove_disk(&mut self, id: String) -> result::Result<(), VmError> {
+        let config = self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        // Only accept ids referring to a disk, so that a typo can't unplug
+        // some other device.
+        let is_disk = config
+            .lock()
+            .unwrap()
+            .disks
+            .as_ref()
+            .map_or(false, |disks| {
+                disks
+                    .iter()
+                    .any(|disk| disk.id.as_deref() == Some(id.as_str()))
+            });
+        if !is_disk {
+            return Err(VmError::NoDiskToRemove(id));
+        }
+
+        self.vm_remove_device(id)
+    }
+
+    fn vm_remove_net(&mut self, id: String) -> result::Result<(), VmError> {
+        let config = self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        // Only accept ids referring to a network device, so that a typo can't
+        // unplug some other device.
+        let is_net = config.lock().unwrap().net.as_ref().map_or(false, |net| {
+            net.iter().any(|net| net.id.as_deref() == Some(id.as_str()))
+        });
+        if !is_net {
+            return Err(VmError::NoNetToRemove(id));
+        }
+
+        self.vm_remove_device(id)
+    }
+
     fn vm_add_disk(&mut self, disk_cfg: DiskConfig) -> result::Result<Option<Vec<u8>>, VmError> {
         self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
 
@@ -1270,6 +1632,18 @@ impl Vmm {
         }
     }
 
+    fn vm_configure_counters(
+        &mut self,
+        reset: bool,
+        sampling_interval_secs: Option<u64>,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.configure_counters(reset, sampling_interval_secs)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_power_button(&mut self) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
             vm.power_button()
@@ -1278,12 +1652,120 @@ impl Vmm {
         }
     }
 
+    // Asks the guest to power off when the VMM is told to terminate, leaving
+    // it `shutdown_timeout` to do so. Returns whether the VMM should rather
+    // stop right away, which is the case when there is no running guest to
+    // wait for, or when it has already been asked once.
+    fn vmm_graceful_shutdown(&mut self) -> bool {
+        if self.shutdown_timer.is_some() || self.shutdown_timeout.is_zero() {
+            return true;
+        }
+
+        match self.vm.as_ref().map(|vm| vm.get_state()) {
+            Some(Ok(VmState::Running)) => {}
+            _ => return true,
+        }
+
+        if let Err(e) = self.vm_power_button() {
+            error!("Failed to press the power button: {:?}", e);
+            return true;
+        }
+
+        let timer = TimerFd::new().and_then(|mut timer| {
+            timer.reset(self.shutdown_timeout, None)?;
+            self.epoll
+                .add_event(&timer, EpollDispatch::ShutdownTimeout)?;
+            Ok(timer)
+        });
+        match timer {
+            Ok(timer) => {
+                info!(
+                    "Waiting up to {:?} for the guest to shut down",
+                    self.shutdown_timeout
+                );
+                self.shutdown_timer = Some(timer);
+                false
+            }
+            Err(e) => {
+                error!("Failed to arm the shutdown timer: {}", e);
+                true
+            }
+        }
+    }
+
+    fn vm_nmi(&mut self, cpu_id: Option<u32>) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.nmi(cpu_id)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_dirty_rate_start(&mut self) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.start_dirty_rate()
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_dirty_rate_stop(&mut self) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.stop_dirty_rate()
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_dirty_rate(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref mut vm) = self.vm {
+            let dirty_rate = vm.dirty_rate()?;
+            serde_json::to_vec(&dirty_rate)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_resident_memory(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.resident_memory()?)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_topology(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.topology())
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    // The configuration is the one the VM would be booted from, including the
+    // devices added or removed since its creation, serialized the same way as
+    // the one given to vm.create.
+    fn vm_config(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        match &self.vm_config {
+            Some(config) => serde_json::to_vec(&*config.lock().unwrap())
+                .map(Some)
+                .map_err(VmError::SerializeJson),
+            None => Err(VmError::VmNotCreated),
+        }
+    }
+
     fn vm_receive_config<T>(
-        &mut self,
         req: &Request,
         socket: &mut T,
+        hypervisor: &Arc<dyn hypervisor::Hypervisor>,
         existing_memory_files: Option<HashMap<u32, File>>,
-    ) -> std::result::Result<Arc<Mutex<MemoryManager>>, MigratableError>
+    ) -> std::result::Result<(Arc<Mutex<VmConfig>>, Arc<Mutex<MemoryManager>>), MigratableError>
     where
         T: Read + Write,
     {
@@ -1300,16 +1782,16 @@ impl Vmm {
             })?;
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(
+        Self::vm_check_cpuid_compatibility(
+            hypervisor,
             &vm_migration_config.vm_config,
             &vm_migration_config.common_cpuid,
         )?;
 
         let config = vm_migration_config.vm_config.clone();
-        self.vm_config = Some(vm_migration_config.vm_config);
 
         let vm = Vm::create_hypervisor_vm(
-            &self.hypervisor,
+            hypervisor,
             #[cfg(feature = "tdx")]
             false,
             #[cfg(feature = "sev_snp")]
@@ -1322,8 +1804,7 @@ impl Vmm {
             ))
         })?;
 
-        let phys_bits =
-            vm::physical_bits(&self.hypervisor, config.lock().unwrap().cpus.max_phys_bits);
+        let phys_bits = vm::physical_bits(hypervisor, config.lock().unwrap().cpus.max_phys_bits);
 
         let memory_manager = MemoryManager::new(
             vm,
@@ -1346,25 +1827,19 @@ impl Vmm {
 
         Response::ok().write_to(socket)?;
 
-        Ok(memory_manager)
+        Ok((config, memory_manager))
     }
 
     fn vm_receive_state<T>(
         &mut self,
-        req: &Request,
+        data: &[u8],
         socket: &mut T,
         mm: Arc<Mutex<MemoryManager>>,
     ) -> std::result::Result<(), MigratableError>
     where
         T: Read + Write,
     {
-        // Read in state data
-        let mut data: Vec<u8> = Vec::new();
-        data.resize_with(req.length() as usize, Default::default);
-        socket
-            .read_exact(&mut data)
-            .map_err(MigratableError::MigrateSocket)?;
-        let snapshot: Snapshot = serde_json::from_slice(&data).map_err(|e| {
+        let snapshot: Snapshot = serde_json::from_slice(data).map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error deserialising snapshot: {}", e))
         })?;
 
@@ -1399,6 +1874,7 @@ impl Vmm {
             None,
             None,
             None,
+            None,
             Arc::clone(&self.original_termios_opt),
             Some(snapshot),
         )
@@ -1419,10 +1895,10 @@ impl Vmm {
     }
 
     fn vm_receive_memory<T>(
-        &mut self,
         req: &Request,
         socket: &mut T,
         memory_manager: &mut MemoryManager,
+        status: &Mutex<MigrationStatus>,
     ) -> std::result::Result<(), MigratableError>
     where
         T: Read + Write,
@@ -1430,9 +1906,17 @@ impl Vmm {
         // Read table
         let table = MemoryRangeTable::read_from(socket, req.length())?;
 
+        {
+            let mut status = status.lock().unwrap();
+            status.phase = MigrationPhase::Memory;
+            status.memory_iterations += 1;
+            status.bytes_remaining = table.regions().iter().map(|r| r.length).sum();
+        }
+
         // And then read the memory itself
+        let mut reader = MigrationMemoryReader::new(socket, status);
         memory_manager
-            .receive_memory_regions(&table, socket)
+            .receive_memory_regions(&table, &mut reader)
             .map_err(|e| {
                 Response::error().write_to(socket).ok();
                 e
@@ -1449,30 +1933,75 @@ impl Vmm {
             .map(|s| s.into())
     }
 
-    fn vm_receive_migration(
-        &mut self,
-        receive_data_migration: VmReceiveMigrationData,
-    ) -> result::Result<(), MigratableError> {
-        info!(
-            "Receiving migration: receiver_url = {}",
-            receive_data_migration.receiver_url
-        );
+    // Wait for the file descriptor to be readable, unless the migration gets
+    // aborted first.
+    fn wait_migration_fd(fd: RawFd, abort_evt: &EventFd) -> result::Result<(), MigratableError> {
+        let mut fds = [
+            libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: abort_evt.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
 
-        let path = Self::socket_url_to_path(&receive_data_migration.receiver_url)?;
-        let listener = UnixListener::bind(&path).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error binding to UNIX socket: {}", e))
-        })?;
-        let (mut socket, _addr) = listener.accept().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error accepting on UNIX socket: {}", e))
-        })?;
+        loop {
+            // SAFETY: FFI call with valid arguments
+            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if ret >= 0 {
+                break;
+            }
+
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(MigratableError::MigrateSocket(e));
+            }
+        }
+
+        if fds[1].revents & libc::POLLIN != 0 {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Migration aborted"
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Runs from the migration receiver thread, until the source sends the
+    // VM state, which is restored from the VMM thread.
+    fn receive_migration(
+        listener: UnixListener,
+        path: PathBuf,
+        hypervisor: Arc<dyn hypervisor::Hypervisor>,
+        status: &Mutex<MigrationStatus>,
+        abort_evt: &EventFd,
+        connection: &Mutex<Option<UnixStream>>,
+    ) -> result::Result<ReceivedMigration, MigratableError> {
+        let accepted = Self::wait_migration_fd(listener.as_raw_fd(), abort_evt).and_then(|_| {
+            listener.accept().map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error accepting on UNIX socket: {}", e))
+            })
+        });
         std::fs::remove_file(&path).map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error unlinking UNIX socket: {}", e))
         })?;
+        let (mut socket, _addr) = accepted?;
+
+        // Keep a handle onto the connection so that an abort can interrupt a
+        // transfer in progress.
+        *connection.lock().unwrap() =
+            Some(socket.try_clone().map_err(MigratableError::MigrateSocket)?);
+        status.lock().unwrap().phase = MigrationPhase::Connected;
 
         let mut started = false;
-        let mut memory_manager: Option<Arc<Mutex<MemoryManager>>> = None;
+        let mut config = None;
         let mut existing_memory_files = None;
         loop {
+            Self::wait_migration_fd(socket.as_raw_fd(), abort_evt)?;
             let req = Request::read_from(&mut socket)?;
             match req.command() {
                 Command::Invalid => info!("Invalid Command Received"),
@@ -1490,9 +2019,10 @@ impl Vmm {
                         Response::error().write_to(&mut socket)?;
                         continue;
                     }
-                    memory_manager = Some(self.vm_receive_config(
+                    config = Some(Self::vm_receive_config(
                         &req,
                         &mut socket,
+                        &hypervisor,
                         existing_memory_files.take(),
                     )?);
                 }
@@ -1504,8 +2034,18 @@ impl Vmm {
                         Response::error().write_to(&mut socket)?;
                         continue;
                     }
-                    if let Some(mm) = memory_manager.take() {
-                        self.vm_receive_state(&req, &mut socket, mm)?;
+                    if let Some((vm_config, memory_manager)) = config.take() {
+                        // Read in state data
+                        let mut state = vec![0u8; req.length() as usize];
+                        socket
+                            .read_exact(&mut state)
+                            .map_err(MigratableError::MigrateSocket)?;
+                        return Ok(ReceivedMigration::State {
+                            socket,
+                            state,
+                            vm_config,
+                            memory_manager,
+                        });
                     } else {
                         warn!("Configuration not sent yet");
                         Response::error().write_to(&mut socket)?;
@@ -1519,8 +2059,13 @@ impl Vmm {
                         Response::error().write_to(&mut socket)?;
                         continue;
                     }
-                    if let Some(mm) = memory_manager.as_ref() {
-                        self.vm_receive_memory(&req, &mut socket, &mut mm.lock().unwrap())?;
+                    if let Some((_, mm)) = config.as_ref() {
+                        Self::vm_receive_memory(
+                            &req,
+                            &mut socket,
+                            &mut mm.lock().unwrap(),
+                            status,
+                        )?;
                     } else {
                         warn!("Configuration not sent yet");
                         Response::error().write_to(&mut socket)?;
@@ -1556,24 +2101,304 @@ impl Vmm {
                 }
                 Command::Complete => {
                     info!("Complete Command Received");
+                    warn!("VM not created yet");
+                    Response::error().write_to(&mut socket)?;
+                    return Err(MigratableError::MigrateReceive(anyhow!(
+                        "Migration completed before the VM state was received"
+                    )));
+                }
+                Command::Abandon => {
+                    info!("Abandon Command Received");
+                    Response::ok().write_to(&mut socket).ok();
+                    return Err(MigratableError::MigrateReceive(anyhow!(
+                        "Migration abandoned by the source"
+                    )));
+                }
+            }
+        }
+    }
+
+    // Runs from the migration receiver thread once the VM is restored, until
+    // the source completes the migration.
+    fn receive_migration_complete(
+        mut socket: UnixStream,
+        abort_evt: &EventFd,
+    ) -> result::Result<ReceivedMigration, MigratableError> {
+        Self::wait_migration_fd(socket.as_raw_fd(), abort_evt)?;
+        let req = Request::read_from(&mut socket)?;
+        match req.command() {
+            Command::Complete => {
+                info!("Complete Command Received");
+                Ok(ReceivedMigration::Complete { socket })
+            }
+            Command::Abandon => {
+                info!("Abandon Command Received");
+                Response::ok().write_to(&mut socket).ok();
+                Err(MigratableError::MigrateReceive(anyhow!(
+                    "Migration abandoned by the source"
+                )))
+            }
+            _ => {
+                warn!("Unexpected command after the VM state");
+                Response::error().write_to(&mut socket).ok();
+                Err(MigratableError::MigrateReceive(anyhow!(
+                    "Unexpected command after the VM state"
+                )))
+            }
+        }
+    }
+
+    // Restores the VM from the state sent by the source.
+    fn vm_receive_migration_state(
+        &mut self,
+        socket: &mut UnixStream,
+        state: &[u8],
+        vm_config: Arc<Mutex<VmConfig>>,
+        memory_manager: Arc<Mutex<MemoryManager>>,
+        status: &Mutex<MigrationStatus>,
+    ) -> result::Result<(), MigratableError> {
+        if self.vm_config.is_some() {
+            Response::error().write_to(socket).ok();
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "A VM was created while receiving the migration"
+            )));
+        }
+
+        status.lock().unwrap().phase = MigrationPhase::State;
+        self.vm_config = Some(vm_config);
+        self.vm_receive_state(state, socket, memory_manager)
+    }
+
+    // Runs the given part of the migration from the migration receiver
+    // thread, which notifies the VMM thread once it is done.
+    fn spawn_migration_receiver<F>(
+        &mut self,
+        connection: Arc<Mutex<Option<UnixStream>>>,
+        receive: F,
+    ) -> result::Result<(), MigratableError>
+    where
+        F: FnOnce(&EventFd) -> result::Result<ReceivedMigration, MigratableError> + Send + 'static,
+    {
+        let abort_evt = EventFd::new(EFD_NONBLOCK).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error creating abort EventFd: {}", e))
+        })?;
+        let thread_abort_evt = abort_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning abort EventFd: {}", e))
+        })?;
+        let done_evt = self.migration_receive_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning migration EventFd: {}", e))
+        })?;
+
+        let thread = thread::Builder::new()
+            .name("migration-receiver".to_string())
+            .spawn(move || {
+                let result = receive(&thread_abort_evt);
+                // Let the VMM thread collect the result
+                done_evt.write(1).ok();
+                result
+            })
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error spawning migration thread: {}", e))
+            })?;
+
+        self.migration_receiver = Some(MigrationReceiver {
+            thread,
+            abort_evt,
+            connection,
+        });
+
+        Ok(())
+    }
+
+    fn vm_receive_migration(
+        &mut self,
+        receive_data_migration: VmReceiveMigrationData,
+    ) -> result::Result<(), MigratableError> {
+        info!(
+            "Receiving migration: receiver_url = {}",
+            receive_data_migration.receiver_url
+        );
+
+        if self.migration_receiver.is_some() {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "A migration is already being received"
+            )));
+        }
+        if self.vm_config.is_some() {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "A VM has already been created"
+            )));
+        }
+
+        let path = Self::socket_url_to_path(&receive_data_migration.receiver_url)?;
+        let listener = UnixListener::bind(&path).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error binding to UNIX socket: {}", e))
+        })?;
+
+        let status = Arc::new(Mutex::new(MigrationStatus::default()));
+        let connection = Arc::new(Mutex::new(None));
+        let hypervisor = self.hypervisor.clone();
+        let thread_status = status.clone();
+        let thread_connection = connection.clone();
+        self.spawn_migration_receiver(connection, move |abort_evt| {
+            Self::receive_migration(
+                listener,
+                path,
+                hypervisor,
+                &thread_status,
+                abort_evt,
+                &thread_connection,
+            )
+        })?;
+        self.migration_status = Some(status);
+
+        Ok(())
+    }
+
+    // Called once the migration receiver thread is done with its part of the
+    // migration, to restore or resume the VM, or to report the failure.
+    fn vm_receive_migration_done(&mut self) {
+        let receiver = match self.migration_receiver.take() {
+            Some(receiver) if receiver.thread.is_finished() => receiver,
+            receiver => {
+                // Stale notification from an aborted migration
+                self.migration_receiver = receiver;
+                return;
+            }
+        };
+        let status = match self.migration_status.clone() {
+            Some(status) => status,
+            None => return,
+        };
+        let MigrationReceiver {
+            thread, connection, ..
+        } = receiver;
+
+        let result = thread
+            .join()
+            .map_err(|_| {
+                MigratableError::MigrateReceive(anyhow!("Migration receiver thread panicked"))
+            })
+            .and_then(|result| result)
+            .and_then(|received| match received {
+                ReceivedMigration::State {
+                    mut socket,
+                    state,
+                    vm_config,
+                    memory_manager,
+                } => {
+                    self.vm_receive_migration_state(
+                        &mut socket,
+                        &state,
+                        vm_config,
+                        memory_manager,
+                        &status,
+                    )?;
+                    // Wait for the source to complete the migration without
+                    // holding the VMM thread.
+                    self.spawn_migration_receiver(connection, move |abort_evt| {
+                        Self::receive_migration_complete(socket, abort_evt)
+                    })?;
+                    Ok(false)
+                }
+                ReceivedMigration::Complete { mut socket } => {
                     if let Some(ref mut vm) = self.vm.as_mut() {
                         vm.resume()?;
-                        Response::ok().write_to(&mut socket)?;
-                    } else {
-                        warn!("VM not created yet");
-                        Response::error().write_to(&mut socket)?;
                     }
-                    break;
+                    Response::ok().write_to(&mut socket)?;
+                    Ok(true)
                 }
-                Command::Abandon => {
-                    info!("Abandon Command Received");
+            });
+
+        let result = match result {
+            Ok(false) => return,
+            Ok(true) => {
+                info!("Migration received");
+                status.lock().unwrap().phase = MigrationPhase::Completed;
+                Ok(())
+            }
+            Err(e) => {
+                error!("Migration failed: {:?}", e);
+                let mut status = status.lock().unwrap();
+                // Don't leave a partially restored VM behind
+                if status.phase == MigrationPhase::State {
                     self.vm = None;
                     self.vm_config = None;
-                    Response::ok().write_to(&mut socket).ok();
-                    break;
                 }
+                status.phase = MigrationPhase::Failed;
+                status.error = Some(format!("{e}"));
+                Err(e)
+            }
+        };
+
+        self.vm_receive_migration_response(result);
+    }
+
+    // Answers the receive-migration request waiting for the end of the
+    // migration, unless it was sent for the migration to run in the
+    // background.
+    fn vm_receive_migration_response(&mut self, result: result::Result<(), MigratableError>) {
+        if let Some(sender) = self.migration_response.take() {
+            let response = result
+                .map_err(ApiError::VmReceiveMigration)
+                .map(|_| ApiResponsePayload::Empty);
+            if sender.send(response).is_err() {
+                warn!("Failed answering the receive-migration request");
             }
         }
+    }
+
+    fn vm_migration_status(&self) -> result::Result<Option<Vec<u8>>, MigratableError> {
+        if let Some(status) = self.migration_status.as_ref() {
+            serde_json::to_vec(&*status.lock().unwrap())
+                .map(Some)
+                .map_err(|e| {
+                    MigratableError::MigrateReceive(anyhow!(
+                        "Error serialising migration status: {}",
+                        e
+                    ))
+                })
+        } else {
+            Err(MigratableError::MigrateReceive(anyhow!(
+                "No migration has been received"
+            )))
+        }
+    }
+
+    fn vm_migration_abort(&mut self) -> result::Result<(), MigratableError> {
+        let receiver = self.migration_receiver.take().ok_or_else(|| {
+            MigratableError::MigrateReceive(anyhow!("No migration is being received"))
+        })?;
+
+        info!("Aborting the migration being received");
+        receiver.abort_evt.write(1).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error writing abort EventFd: {}", e))
+        })?;
+        // Interrupt a transfer in progress
+        if let Some(connection) = receiver.connection.lock().unwrap().as_ref() {
+            connection.shutdown(std::net::Shutdown::Both).ok();
+        }
+
+        // The thread owns everything received so far, which is released
+        // once it returns.
+        receiver.thread.join().map_err(|_| {
+            MigratableError::MigrateReceive(anyhow!("Migration receiver thread panicked"))
+        })?;
+
+        if let Some(status) = self.migration_status.as_ref() {
+            let mut status = status.lock().unwrap();
+            // Release the VM restored from the state as well
+            if status.phase == MigrationPhase::State {
+                self.vm = None;
+                self.vm_config = None;
+            }
+            status.phase = MigrationPhase::Aborted;
+            status.bytes_remaining = 0;
+        }
+
+        self.vm_receive_migration_response(Err(MigratableError::MigrateReceive(anyhow!(
+            "Migration aborted"
+        ))));
 
         Ok(())
     }
@@ -1641,12 +2466,20 @@ impl Vmm {
         let common_cpuid = {
             let phys_bits =
                 vm::physical_bits(&hypervisor, vm_config.lock().unwrap().cpus.max_phys_bits);
+            let kvm_pv_features = vm_config
+                .lock()
+                .unwrap()
+                .cpus
+                .features
+                .kvm_pv_features
+                .clone();
             arch::generate_common_cpuid(
                 &hypervisor,
                 None,
                 None,
                 phys_bits,
                 vm_config.lock().unwrap().cpus.kvm_hyperv,
+                &kvm_pv_features,
                 #[cfg(feature = "tdx")]
                 vm_config.lock().unwrap().is_tdx_enabled(),
             )
@@ -1726,6 +2559,10 @@ impl Vmm {
             // Stop logging dirty pages
             vm.stop_dirty_log()?;
         }
+        // The destination opens the disk images when receiving the state,
+        // while this VM still has them open.
+        vm.unlock_disk_images();
+
         // Capture snapshot and send it
         let vm_snapshot = vm.snapshot()?;
         let snapshot_data = serde_json::to_vec(&vm_snapshot).unwrap();
@@ -1783,7 +2620,21 @@ impl Vmm {
             )));
         }
 
+        if let Some(vm_config) = self.vm_config.as_ref() {
+            vm_config
+                .lock()
+                .unwrap()
+                .validate_migratable()
+                .map_err(|e| MigratableError::MigrateSend(anyhow!("{}", e)))?;
+        }
+
         if let Some(vm) = self.vm.as_mut() {
+            if vm.dirty_rate_started() {
+                return Err(MigratableError::MigrateSend(anyhow!(
+                    "Can't migrate while the dirty rate is sampled"
+                )));
+            }
+
             Self::send_migration(
                 vm,
                 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
@@ -1793,6 +2644,12 @@ impl Vmm {
             .map_err(|migration_err| {
                 error!("Migration failed: {:?}", migration_err);
 
+                // Take the locks back first, as this VM keeps on using the
+                // disk images whatever happens next.
+                if let Err(e) = vm.lock_disk_images() {
+                    warn!("Failed to lock the disk images back: {:?}", e);
+                }
+
                 // Stop logging dirty pages
                 if let Err(e) = vm.stop_dirty_log() {
                     return e;
@@ -1821,7 +2678,7 @@ impl Vmm {
 
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     fn vm_check_cpuid_compatibility(
-        &self,
+        hypervisor: &Arc<dyn hypervisor::Hypervisor>,
         src_vm_config: &Arc<Mutex<VmConfig>>,
         src_vm_cpuid: &[hypervisor::arch::x86::CpuIdEntry],
     ) -> result::Result<(), MigratableError> {
@@ -1830,13 +2687,14 @@ impl Vmm {
         let dest_cpuid = &{
             let vm_config = &src_vm_config.lock().unwrap();
 
-            let phys_bits = vm::physical_bits(&self.hypervisor, vm_config.cpus.max_phys_bits);
+            let phys_bits = vm::physical_bits(hypervisor, vm_config.cpus.max_phys_bits);
             arch::generate_common_cpuid(
-                &self.hypervisor.clone(),
+                hypervisor,
                 None,
                 None,
                 phys_bits,
                 vm_config.cpus.kvm_hyperv,
+                &vm_config.cpus.features.kvm_pv_features,
                 #[cfg(feature = "tdx")]
                 vm_config.is_tdx_enabled(),
             )
@@ -1899,8 +2757,40 @@ impl Vmm {
                         info!("VM reset event");
                         // Consume the event.
                         self.reset_evt.read().map_err(Error::EventFdRead)?;
+                        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+                        self.vm_watchdog_coredump();
                         self.vm_reboot().map_err(Error::VmReboot)?;
                     }
+                    EpollDispatch::Shutdown => {
+                        info!("VMM shutdown event");
+                        // Consume the event.
+                        self.shutdown_evt.read().map_err(Error::EventFdRead)?;
+                        if self.vmm_graceful_shutdown() {
+                            self.vmm_shutdown().map_err(Error::VmmShutdown)?;
+
+                            break 'outer;
+                        }
+                    }
+                    EpollDispatch::ShutdownTimeout => {
+                        warn!(
+                            "Guest did not shut down within {:?}, stopping it",
+                            self.shutdown_timeout
+                        );
+                        self.vmm_shutdown().map_err(Error::VmmShutdown)?;
+
+                        break 'outer;
+                    }
+                    EpollDispatch::MemoryPressure => {
+                        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+                        self.vm_memory_pressure_coredump();
+                    }
+                    EpollDispatch::MigrationReceive => {
+                        // Consume the event.
+                        self.migration_receive_evt
+                            .read()
+                            .map_err(Error::EventFdRead)?;
+                        self.vm_receive_migration_done();
+                    }
                     EpollDispatch::ActivateVirtioDevices => {
                         if let Some(ref vm) = self.vm {
                             let count = self.activate_evt.read().map_err(Error::EventFdRead)?;
@@ -1973,6 +2863,15 @@ impl Vmm {
 
                                     sender.send(Ok(response)).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmPing(sender) => {
+                                    let response = ApiResponsePayload::VmPing(self.vm_ping());
+
+                                    // The requester may have given up waiting,
+                                    // which must not take the VMM down.
+                                    if sender.send(Ok(response)).is_err() {
+                                        warn!("Liveness check answered after its timeout");
+                                    }
+                                }
                                 ApiRequest::VmPause(sender) => {
                                     let response = self
                                         .vm_pause()
@@ -1991,7 +2890,7 @@ impl Vmm {
                                 }
                                 ApiRequest::VmSnapshot(snapshot_data, sender) => {
                                     let response = self
-                                        .vm_snapshot(&snapshot_data.destination_url)
+                                        .vm_snapshot(&snapshot_data)
                                         .map_err(ApiError::VmSnapshot)
                                         .map(|_| ApiResponsePayload::Empty);
 
@@ -2066,6 +2965,20 @@ impl Vmm {
                                         .map(|_| ApiResponsePayload::Empty);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmRemoveDisk(remove_disk_data, sender) => {
+                                    let response = self
+                                        .vm_remove_disk(remove_disk_data.id.clone())
+                                        .map_err(ApiError::VmRemoveDisk)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmRemoveNet(remove_net_data, sender) => {
+                                    let response = self
+                                        .vm_remove_net(remove_net_data.id.clone())
+                                        .map_err(ApiError::VmRemoveNet)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmAddDisk(add_disk_data, sender) => {
                                     let response = self
                                         .vm_add_disk(add_disk_data.as_ref().clone())
@@ -2115,13 +3028,43 @@ impl Vmm {
                                         .map(ApiResponsePayload::VmAction);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmConfigureCounters(counters_data, sender) => {
+                                    let response = self
+                                        .vm_configure_counters(
+                                            counters_data.reset,
+                                            counters_data.sampling_interval_secs,
+                                        )
+                                        .map_err(ApiError::VmConfigureCounters)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmReceiveMigration(receive_migration_data, sender) => {
+                                    let background = receive_migration_data.background;
                                     let response = self
                                         .vm_receive_migration(
                                             receive_migration_data.as_ref().clone(),
                                         )
                                         .map_err(ApiError::VmReceiveMigration)
                                         .map(|_| ApiResponsePayload::Empty);
+                                    if response.is_ok() && !background {
+                                        // Answered once the migration is over
+                                        self.migration_response = Some(sender);
+                                    } else {
+                                        sender.send(response).map_err(Error::ApiResponseSend)?;
+                                    }
+                                }
+                                ApiRequest::VmMigrationStatus(sender) => {
+                                    let response = self
+                                        .vm_migration_status()
+                                        .map_err(ApiError::VmMigrationStatus)
+                                        .map(ApiResponsePayload::VmAction);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmMigrationAbort(sender) => {
+                                    let response = self
+                                        .vm_migration_abort()
+                                        .map_err(ApiError::VmMigrationAbort)
+                                        .map(|_| ApiResponsePayload::Empty);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
                                 ApiRequest::VmSendMigration(send_migration_data, sender) => {
@@ -2139,6 +3082,62 @@ impl Vmm {
 
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmNmi(nmi_data, sender) => {
+                                    let response = self
+                                        .vm_nmi(nmi_data.cpu_id)
+                                        .map_err(ApiError::VmNmi)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmDirtyRateStart(sender) => {
+                                    let response = self
+                                        .vm_dirty_rate_start()
+                                        .map_err(ApiError::VmDirtyRate)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmDirtyRateStop(sender) => {
+                                    let response = self
+                                        .vm_dirty_rate_stop()
+                                        .map_err(ApiError::VmDirtyRate)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmDirtyRate(sender) => {
+                                    let response = self
+                                        .vm_dirty_rate()
+                                        .map_err(ApiError::VmDirtyRate)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmTopology(sender) => {
+                                    let response = self
+                                        .vm_topology()
+                                        .map_err(ApiError::VmTopology)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmResidentMemory(sender) => {
+                                    let response = self
+                                        .vm_resident_memory()
+                                        .map_err(ApiError::VmResidentMemory)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmConfig(sender) => {
+                                    let response = self
+                                        .vm_config()
+                                        .map_err(ApiError::VmConfig)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                             }
                         }
                     }
@@ -2190,8 +3189,8 @@ const DEVICE_MANAGER_SNAPSHOT_ID: &str = "device-manager";
 mod unit_tests {
     use super::*;
     use config::{
-        ConsoleConfig, ConsoleOutputMode, CpusConfig, HotplugMethod, MemoryConfig, PayloadConfig,
-        RngConfig, VmConfig,
+        ConsoleConfig, ConsoleOutputMode, CpusConfig, FlowControl, HotplugMethod, MemoryConfig,
+        PayloadConfig, RngConfig, VmConfig,
     };
 
     fn create_dummy_vmm() -> Vmm {
@@ -2205,6 +3204,7 @@ mod unit_tests {
             SeccompAction::Allow,
             hypervisor::new().unwrap(),
             EventFd::new(EFD_NONBLOCK).unwrap(),
+            Duration::ZERO,
         )
         .unwrap()
     }
@@ -2215,13 +3215,20 @@ mod unit_tests {
                 boot_vcpus: 1,
                 max_vcpus: 1,
                 topology: None,
+                host_topology: false,
                 kvm_hyperv: false,
                 max_phys_bits: 46,
+                auto_phys_bits: false,
                 affinity: None,
                 features: config::CpuFeatures::default(),
+                tsc_freq: None,
+                idle: None,
+                stable_id: None,
+                hypervisor_cpuid: true,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
+                file: None,
                 mergeable: false,
                 hotplug_method: HotplugMethod::Acpi,
                 hotplug_size: None,
@@ -2232,6 +3239,13 @@ mod unit_tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                prealloc_threads: None,
+                readonly_rom: None,
+                numa_bind: None,
+                zero_on_free: false,
+                dump_on_oom: None,
+                backend: None,
+                max_ram: None,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -2239,9 +3253,12 @@ mod unit_tests {
             }),
             disks: None,
             net: None,
+            rate_limit_groups: None,
             rng: RngConfig {
                 src: PathBuf::from("/dev/urandom"),
                 iommu: false,
+                source: None,
+                queue_size: 256,
             },
             balloon: None,
             fs: None,
@@ -2250,15 +3267,26 @@ mod unit_tests {
                 file: None,
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             },
             console: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
+                input: None,
+                resize: true,
+                rotate: None,
+                flow_control: FlowControl::None,
+                timestamps: false,
             },
             devices: None,
             user_devices: None,
             vdpa: None,
+            scsi: None,
             vsock: None,
             pvpanic: false,
             iommu: false,
@@ -2266,6 +3294,8 @@ mod unit_tests {
             sgx_epc: None,
             numa: None,
             watchdog: false,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            watchdog_coredump: None,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             platform: None,
@@ -2286,6 +3316,17 @@ mod unit_tests {
         ));
     }
 
+    #[test]
+    fn test_vmm_vm_config() {
+        let mut vmm = create_dummy_vmm();
+
+        assert!(matches!(vmm.vm_config(), Err(VmError::VmNotCreated)));
+
+        let _ = vmm.vm_create(create_dummy_vm_config());
+        let config: VmConfig = serde_json::from_slice(&vmm.vm_config().unwrap().unwrap()).unwrap();
+        assert_eq!(config, *vmm.vm_config.as_ref().unwrap().lock().unwrap());
+    }
+
     #[test]
     fn test_vmm_vm_cold_add_device() {
         let mut vmm = create_dummy_vmm();
@@ -2431,6 +3472,37 @@ mod unit_tests {
         );
     }
 
+    #[test]
+    fn test_vmm_vm_cold_remove_disk() {
+        let mut vmm = create_dummy_vmm();
+
+        assert!(matches!(
+            vmm.vm_remove_disk("disk0".to_string()),
+            Err(VmError::VmNotCreated)
+        ));
+
+        let _ = vmm.vm_create(create_dummy_vm_config());
+        let disk_config = DiskConfig::parse("path=/path/to_file,id=disk0").unwrap();
+        assert!(vmm.vm_add_disk(disk_config).is_ok());
+
+        assert!(matches!(
+            vmm.vm_remove_disk("net0".to_string()),
+            Err(VmError::NoDiskToRemove(_))
+        ));
+
+        assert!(vmm.vm_remove_disk("disk0".to_string()).is_ok());
+        assert!(vmm
+            .vm_config
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .disks
+            .clone()
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn test_vmm_vm_cold_add_fs() {
         let mut vmm = create_dummy_vmm();
@@ -2571,6 +3643,37 @@ mod unit_tests {
         );
     }
 
+    #[test]
+    fn test_vmm_vm_cold_remove_net() {
+        let mut vmm = create_dummy_vmm();
+
+        assert!(matches!(
+            vmm.vm_remove_net("net0".to_string()),
+            Err(VmError::VmNotCreated)
+        ));
+
+        let _ = vmm.vm_create(create_dummy_vm_config());
+        let net_config = NetConfig::parse("tap=chtap0,id=net0").unwrap();
+        assert!(vmm.vm_add_net(net_config).is_ok());
+
+        assert!(matches!(
+            vmm.vm_remove_net("disk0".to_string()),
+            Err(VmError::NoNetToRemove(_))
+        ));
+
+        assert!(vmm.vm_remove_net("net0".to_string()).is_ok());
+        assert!(vmm
+            .vm_config
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .net
+            .clone()
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn test_vmm_vm_cold_add_vdpa() {
         let mut vmm = create_dummy_vmm();
@@ -4,12 +4,16 @@
 //
 #[cfg(target_arch = "x86_64")]
 use crate::config::SgxEpcConfig;
-use crate::config::{HotplugMethod, MemoryConfig, MemoryZoneConfig};
+use crate::config::{
+    HotplugMethod, MemoryBackend, MemoryConfig, MemoryZoneConfig, ReadonlyRomConfig,
+};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CoredumpMemoryRegion, CoredumpMemoryRegions, DumpState, GuestDebuggableError,
 };
-use crate::migration::url_to_path;
+use crate::migration::{
+    recv_snapshot_base, recv_vm_state, url_to_path, SnapshotBase, SNAPSHOT_BASE_FILE,
+};
 use crate::MEMORY_MANAGER_SNAPSHOT_ID;
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use acpi_tables::{aml, Aml};
@@ -33,9 +37,10 @@ use std::fs::{File, OpenOptions};
 use std::io::{self, Read};
 use std::ops::{BitAnd, Deref, Not, Sub};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
 use tracer::trace_scoped;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -57,10 +62,44 @@ use vm_migration::{
 
 pub const MEMORY_MANAGER_ACPI_SIZE: usize = 0x18;
 
+// Returns the resident set size of the process along with the size of the
+// hugetlb pages it maps, which the former doesn't account for, out of the
+// content of "/proc/self/smaps_rollup".
+fn parse_smaps_rollup(smaps_rollup: &str) -> (u64, u64) {
+    let mut rss = 0;
+    let mut hugetlb = 0;
+    for line in smaps_rollup.lines() {
+        let mut fields = line.split_whitespace();
+        let (field, kb) = match (
+            fields.next(),
+            fields.next().and_then(|v| v.parse::<u64>().ok()),
+        ) {
+            (Some(field), Some(kb)) => (field, kb),
+            _ => continue,
+        };
+        match field {
+            "Rss:" => rss += kb << 10,
+            "Shared_Hugetlb:" | "Private_Hugetlb:" => hugetlb += kb << 10,
+            _ => {}
+        }
+    }
+    (rss, hugetlb)
+}
+
+/// Returns the memory of the process resident on the host, hugetlb pages
+/// included, along with the part of it made of hugetlb pages.
+pub fn resident_memory() -> io::Result<(u64, u64)> {
+    let (rss, hugetlb) = parse_smaps_rollup(&std::fs::read_to_string("/proc/self/smaps_rollup")?);
+    Ok((rss + hugetlb, hugetlb))
+}
+
 const DEFAULT_MEMORY_ZONE: &str = "mem0";
 
 const SNAPSHOT_FILENAME: &str = "memory-ranges";
 
+// Granularity of the dirty pages logging
+const DIRTY_PAGE_SIZE: u64 = 4096;
+
 #[cfg(target_arch = "x86_64")]
 const X86_64_IRQ_BASE: u32 = 5;
 
@@ -74,6 +113,9 @@ const MPOL_BIND: u32 = 2;
 const MPOL_MF_STRICT: u32 = 1;
 const MPOL_MF_MOVE: u32 = 1 << 1;
 
+// Populate the page tables writable without touching the memory (Linux 5.14)
+const MADV_POPULATE_WRITE: libc::c_int = 23;
+
 // Reserve 1 MiB for platform MMIO devices (e.g. ACPI control devices)
 const PLATFORM_DEVICE_AREA_SIZE: u64 = 1 << 20;
 
@@ -148,6 +190,90 @@ struct GuestRamMapping {
     file_offset: u64,
 }
 
+// Base snapshot of the differential snapshots, along with the guest pages
+// written since it was taken.
+struct TrackedBase {
+    url: String,
+    guest_ram_mappings: Vec<GuestRamMapping>,
+    // One bitmap of 4 KiB pages for each guest RAM mapping.
+    dirty_bitmaps: Vec<Vec<u64>>,
+}
+
+impl TrackedBase {
+    fn new(url: &str, guest_ram_mappings: &[GuestRamMapping]) -> Self {
+        let dirty_bitmaps = guest_ram_mappings
+            .iter()
+            .map(|r| vec![0; ((r.size / DIRTY_PAGE_SIZE) as usize + 63) / 64])
+            .collect();
+
+        TrackedBase {
+            url: url.to_string(),
+            guest_ram_mappings: guest_ram_mappings.to_vec(),
+            dirty_bitmaps,
+        }
+    }
+
+    fn same_layout(&self, guest_ram_mappings: &[GuestRamMapping]) -> bool {
+        self.guest_ram_mappings.len() == guest_ram_mappings.len()
+            && self
+                .guest_ram_mappings
+                .iter()
+                .zip(guest_ram_mappings.iter())
+                .all(|(a, b)| a.gpa == b.gpa && a.size == b.size)
+    }
+
+    fn mark_dirty(&mut self, table: &MemoryRangeTable) {
+        for range in table.regions() {
+            for (mapping, bitmap) in self
+                .guest_ram_mappings
+                .iter()
+                .zip(self.dirty_bitmaps.iter_mut())
+            {
+                let start = std::cmp::max(range.gpa, mapping.gpa);
+                let end = std::cmp::min(range.gpa + range.length, mapping.gpa + mapping.size);
+                if start >= end {
+                    continue;
+                }
+
+                let first_page = (start - mapping.gpa) / DIRTY_PAGE_SIZE;
+                let last_page = (end - 1 - mapping.gpa) / DIRTY_PAGE_SIZE;
+                for page in first_page..=last_page {
+                    bitmap[(page / 64) as usize] |= 1 << (page % 64);
+                }
+            }
+        }
+    }
+
+    // Ranges written since the base, restricted to the ones saved in a
+    // snapshot.
+    fn dirty_ranges(&self, snapshot_ranges: &MemoryRangeTable) -> MemoryRangeTable {
+        let mut table = MemoryRangeTable::default();
+
+        for (mapping, bitmap) in self
+            .guest_ram_mappings
+            .iter()
+            .zip(self.dirty_bitmaps.iter())
+        {
+            let dirty_table =
+                MemoryRangeTable::from_bitmap(bitmap.clone(), mapping.gpa, DIRTY_PAGE_SIZE);
+            for dirty in dirty_table.regions() {
+                for range in snapshot_ranges.regions() {
+                    let start = std::cmp::max(dirty.gpa, range.gpa);
+                    let end = std::cmp::min(dirty.gpa + dirty.length, range.gpa + range.length);
+                    if start < end {
+                        table.push(MemoryRange {
+                            gpa: start,
+                            length: end - start,
+                        });
+                    }
+                }
+            }
+        }
+
+        table
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Versionize)]
 struct ArchMemRegion {
     base: u64,
@@ -172,14 +298,24 @@ pub struct MemoryManager {
     current_ram: u64,
     next_hotplug_slot: usize,
     shared: bool,
+    sealed: bool,
     hugepages: bool,
     hugepage_size: Option<u64>,
     prefault: bool,
+    prealloc_threads: usize,
     thp: bool,
+    numa_bind: Option<u32>,
+    max_ram: Option<u64>,
     #[cfg(target_arch = "x86_64")]
     sgx_epc_region: Option<SgxEpcRegion>,
     user_provided_zones: bool,
     snapshot_memory_ranges: MemoryRangeTable,
+    // Base snapshot tracked for the differential snapshots.
+    tracked_base: Option<TrackedBase>,
+    // Base of the next snapshot to be taken, if differential.
+    next_snapshot_base: Option<String>,
+    // Base of the last snapshot taken, if differential.
+    snapshot_base: Option<String>,
     memory_zones: MemoryZones,
     log_dirty: bool, // Enable dirty logging for created RAM regions
     arch_mem_regions: Vec<ArchMemRegion>,
@@ -194,6 +330,8 @@ pub struct MemoryManager {
     pub acpi_address: Option<GuestAddress>,
     #[cfg(target_arch = "aarch64")]
     uefi_flash: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    // Keep the read-only ROM mapping alive as long as the guest can access it.
+    readonly_rom: Option<MmapRegion>,
 }
 
 #[derive(Debug)]
@@ -204,6 +342,9 @@ pub enum Error {
     /// Failed to set shared file length.
     SharedFileSetLen(io::Error),
 
+    /// Failed to seal the memfd backing the guest RAM.
+    SharedFileSeal(io::Error),
+
     /// Mmap backed guest memory error
     GuestMemory(MmapError),
 
@@ -274,6 +415,15 @@ pub enum Error {
     #[cfg(target_arch = "x86_64")]
     NewMmapRegion(vm_memory::mmap::MmapRegionError),
 
+    /// Failed opening the read-only ROM file
+    ReadonlyRomOpen(io::Error),
+
+    /// Failed mapping the read-only ROM file
+    ReadonlyRomMmap(MmapRegionError),
+
+    /// Failed allocating the read-only ROM guest address range
+    ReadonlyRomRangeAllocation,
+
     /// No memory zones found.
     MissingMemoryZones,
 
@@ -291,6 +441,9 @@ pub enum Error {
     /// Failed applying NUMA memory policy.
     ApplyNumaPolicy(io::Error),
 
+    /// Host NUMA node the guest memory should be bound to doesn't exist.
+    InvalidHostNumaNode(u32),
+
     /// Memory zone identifier is not unique.
     DuplicateZoneId,
 
@@ -312,6 +465,9 @@ pub enum Error {
     /// Resizing the memory zone failed.
     ResizeZone,
 
+    /// Requested guest RAM above the max_ram limit.
+    MaxRamExceeded(u64, u64),
+
     /// Guest address overflow
     GuestAddressOverFlow,
 
@@ -359,6 +515,49 @@ fn mmio_address_space_size(phys_bits: u8) -> u64 {
     (1 << phys_bits) - (1 << 16)
 }
 
+// Room left in each PCI segment for the BARs of the emulated devices, on top
+// of the 64-bit BARs of the passthrough devices.
+const DEVICE_AREA_HEADROOM: u64 = 1 << 30;
+
+/// Returns an upper bound of the guest physical address the RAM can reach,
+/// including the hotpluggable memory and the alignment of its regions.
+pub fn ram_area_end(config: &MemoryConfig) -> u64 {
+    let zones = config.zones.as_deref().unwrap_or_default();
+    let ram_size = config.size + zones.iter().map(|zone| zone.size).sum::<u64>();
+    let hotplug_size = config
+        .hotplug_size
+        .iter()
+        .chain(zones.iter().filter_map(|zone| zone.hotplug_size.as_ref()))
+        .map(|size| size + virtio_devices::VIRTIO_MEM_ALIGN_SIZE)
+        .sum::<u64>();
+
+    layout::RAM_64BIT_START.0 + ram_size + hotplug_size
+}
+
+/// Returns the number of physical address bits needed to place the device
+/// area after the RAM ending at `ram_end`, large enough for the given 64-bit
+/// BARs, each one listed along with the PCI segment it belongs to.
+pub fn required_physical_bits(ram_end: u64, num_pci_segments: u16, bars: &[(u16, u64)]) -> u8 {
+    // The device area is split equally between the PCI segments, hence the
+    // segment holding the largest BARs sets the size of all of them.
+    let mut segment_sizes = vec![0u64; num_pci_segments.max(1) as usize];
+    for (segment, size) in bars {
+        if let Some(segment_size) = segment_sizes.get_mut(*segment as usize) {
+            *segment_size += size;
+        }
+    }
+    // Each segment starts on a 4GiB boundary, and must leave enough room for
+    // the natural alignment of the largest BAR.
+    let largest_bar = bars.iter().map(|(_, size)| *size).max().unwrap_or(0);
+    let segment_size = segment_sizes.iter().max().unwrap() + largest_bar + DEVICE_AREA_HEADROOM;
+    let segment_size = ((segment_size + (4 << 30) - 1) >> 32) << 32;
+
+    let end =
+        ram_end + segment_size * segment_sizes.len() as u64 + PLATFORM_DEVICE_AREA_SIZE + (1 << 16);
+
+    (u64::BITS - (end - 1).leading_zeros()) as u8
+}
+
 // The `statfs` function can get information of hugetlbfs, and the hugepage size is in the
 // `f_bsize` field.
 //
@@ -385,17 +584,27 @@ fn statfs_get_bsize(path: &str) -> Result<u64, Error> {
 }
 
 fn memory_zone_get_align_size(zone: &MemoryZoneConfig) -> Result<u64, Error> {
+    memory_get_page_size(zone.file.as_ref(), zone.hugepages, zone.hugepage_size)
+}
+
+// Size of the pages backing some memory, given its backing file and hugepages
+// settings.
+fn memory_get_page_size(
+    file: Option<&PathBuf>,
+    hugepages: bool,
+    hugepage_size: Option<u64>,
+) -> Result<u64, Error> {
     // SAFETY: FFI call. Trivially safe.
     let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 };
 
     // There is no backend file and the `hugepages` is disabled, just use system page size.
-    if zone.file.is_none() && !zone.hugepages {
+    if file.is_none() && !hugepages {
         return Ok(page_size);
     }
 
     // The `hugepages` is enabled and the `hugepage_size` is specified, just use it directly.
-    if zone.hugepages && zone.hugepage_size.is_some() {
-        return Ok(zone.hugepage_size.unwrap());
+    if let (true, Some(hugepage_size)) = (hugepages, hugepage_size) {
+        return Ok(hugepage_size);
     }
 
     // There are two scenarios here:
@@ -405,7 +614,7 @@ fn memory_zone_get_align_size(zone: &MemoryZoneConfig) -> Result<u64, Error> {
     //     Call `statfs` for the file and get its `f_bsize`.  If the value is larger than the page
     //     size of normal page, just use the `f_bsize` because the file is in a hugetlbfs.  If the
     //     value is less than or equal to the page size, just use the page size.
-    let path = zone.file.as_ref().map_or(Ok("/dev/hugepages"), |pathbuf| {
+    let path = file.map_or(Ok("/dev/hugepages"), |pathbuf| {
         pathbuf.to_str().ok_or(Error::InvalidMemoryParameters)
     })?;
 
@@ -508,43 +717,38 @@ impl BusDevice for MemoryManager {
 }
 
 impl MemoryManager {
-    /// Creates all memory regions based on the available RAM ranges defined
-    /// by `ram_regions`, and based on the description of the memory zones.
-    /// In practice, this function can perform multiple memory mappings of the
-    /// same backing file if there's a hole in the address space between two
-    /// RAM ranges.
+    /// Lays out the memory zones onto the available RAM ranges defined by
+    /// `ram_regions`, returning the regions of each memory zone reached as
+    /// (offset in the zone, guest address, size) tuples.
+    /// In practice, the same memory zone can be split in multiple regions if
+    /// there's a hole in the address space between two RAM ranges.
     /// One example might be ram_regions containing 2 regions (0-3G and 4G-6G)
     /// and zones containing two zones (size 1G and size 4G).
-    /// This function will create 3 resulting memory regions:
+    /// This function will lay out 3 resulting memory regions:
     /// - First one mapping entirely the first memory zone on 0-1G range
     /// - Second one mapping partially the second memory zone on 1G-3G range
     /// - Third one mapping partially the second memory zone on 4G-6G range
     /// Also, all memory regions are page-size aligned (e.g. their sizes must
     /// be multiple of page-size), which may leave an additional hole in the
     /// address space when hugepage is used.
-    fn create_memory_regions_from_zones(
+    #[allow(clippy::type_complexity)]
+    fn layout_memory_zones<'a>(
         ram_regions: &[(GuestAddress, usize)],
-        zones: &[MemoryZoneConfig],
-        prefault: Option<bool>,
-        thp: bool,
-    ) -> Result<(Vec<Arc<GuestRegionMmap>>, MemoryZones), Error> {
+        zones: &'a [MemoryZoneConfig],
+    ) -> Result<Vec<(&'a MemoryZoneConfig, Vec<(u64, GuestAddress, u64)>)>, Error> {
         let mut zone_iter = zones.iter();
-        let mut mem_regions = Vec::new();
+        let mut layout = Vec::new();
         let mut zone = zone_iter.next().ok_or(Error::MissingMemoryZones)?;
         let mut zone_align_size = memory_zone_get_align_size(zone)?;
         let mut zone_offset = 0u64;
-        let mut memory_zones = HashMap::new();
+        let mut zone_regions = Vec::new();
 
         if !is_aligned(zone.size, zone_align_size) {
             return Err(Error::MisalignedMemorySize);
         }
 
-        // Add zone id to the list of memory zones.
-        memory_zones.insert(zone.id.clone(), MemoryZone::default());
-
         for ram_region in ram_regions.iter() {
             let mut ram_region_offset = 0;
-            let mut exit = false;
 
             loop {
                 let mut ram_region_consumed = false;
@@ -578,6 +782,65 @@ impl MemoryManager {
                     ram_region_available_size
                 };
 
+                zone_regions.push((file_offset, region_start, region_size));
+
+                if pull_next_zone {
+                    layout.push((zone, std::mem::take(&mut zone_regions)));
+
+                    // Get the next zone and reset the offset.
+                    zone_offset = 0;
+                    if let Some(z) = zone_iter.next() {
+                        zone = z;
+                    } else {
+                        return Ok(layout);
+                    }
+                    zone_align_size = memory_zone_get_align_size(zone)?;
+                    if !is_aligned(zone.size, zone_align_size) {
+                        return Err(Error::MisalignedMemorySize);
+                    }
+
+                    // Check if zone id already exist. In case it does, throw
+                    // an error as we need unique identifiers.
+                    if layout.iter().any(|(z, _)| z.id == zone.id) {
+                        error!(
+                            "Memory zone identifier '{}' found more than once. \
+                            It must be unique",
+                            zone.id,
+                        );
+                        return Err(Error::DuplicateZoneId);
+                    }
+                }
+
+                if ram_region_consumed {
+                    break;
+                }
+            }
+        }
+
+        // The last zone reached doesn't fit in the RAM ranges
+        layout.push((zone, zone_regions));
+
+        Ok(layout)
+    }
+
+    /// Creates all memory regions based on the available RAM ranges defined
+    /// by `ram_regions`, and based on the description of the memory zones,
+    /// as laid out by layout_memory_zones().
+    fn create_memory_regions_from_zones(
+        ram_regions: &[(GuestAddress, usize)],
+        zones: &[MemoryZoneConfig],
+        prefault: Option<bool>,
+        thp: bool,
+        sealed: bool,
+        prealloc_threads: usize,
+    ) -> Result<(Vec<Arc<GuestRegionMmap>>, MemoryZones), Error> {
+        let mut mem_regions = Vec::new();
+        let mut memory_zones = HashMap::new();
+
+        for (zone, regions) in Self::layout_memory_zones(ram_regions, zones)? {
+            let mut memory_zone = MemoryZone::default();
+
+            for (file_offset, region_start, region_size) in regions {
                 info!(
                     "create ram region for zone {}, region_start: {:#x}, region_size: {:#x}",
                     zone.id,
@@ -596,55 +859,114 @@ impl MemoryManager {
                     zone.host_numa_node,
                     None,
                     thp,
+                    sealed,
+                    prealloc_threads,
                 )?;
 
                 // Add region to the list of regions associated with the
                 // current memory zone.
-                if let Some(memory_zone) = memory_zones.get_mut(&zone.id) {
-                    memory_zone.regions.push(region.clone());
-                }
-
+                memory_zone.regions.push(region.clone());
                 mem_regions.push(region);
+            }
 
-                if pull_next_zone {
-                    // Get the next zone and reset the offset.
-                    zone_offset = 0;
-                    if let Some(z) = zone_iter.next() {
-                        zone = z;
-                    } else {
-                        exit = true;
-                        break;
-                    }
-                    zone_align_size = memory_zone_get_align_size(zone)?;
-                    if !is_aligned(zone.size, zone_align_size) {
-                        return Err(Error::MisalignedMemorySize);
-                    }
+            memory_zones.insert(zone.id.clone(), memory_zone);
+        }
 
-                    // Check if zone id already exist. In case it does, throw
-                    // an error as we need unique identifiers. Otherwise, add
-                    // the new zone id to the list of memory zones.
-                    if memory_zones.contains_key(&zone.id) {
-                        error!(
-                            "Memory zone identifier '{}' found more than once. \
-                            It must be unique",
-                            zone.id,
-                        );
-                        return Err(Error::DuplicateZoneId);
-                    }
-                    memory_zones.insert(zone.id.clone(), MemoryZone::default());
+        Ok((mem_regions, memory_zones))
+    }
+
+    // Places the hotpluggable memory of the zones after the boot RAM, from
+    // `start_of_device_area`, returning where the virtio-mem region of each
+    // zone goes along with the actual start of the device area.
+    #[allow(clippy::type_complexity)]
+    fn layout_hotplug_memory<'a>(
+        config: &MemoryConfig,
+        zones: &'a [MemoryZoneConfig],
+        mut start_of_device_area: GuestAddress,
+    ) -> Result<(Vec<(&'a MemoryZoneConfig, GuestAddress)>, GuestAddress), Error> {
+        let user_provided_zones = config.size == 0;
+        let mut virtio_mem_regions = Vec::new();
+
+        for zone in zones.iter() {
+            if let Some(hotplug_size) = zone.hotplug_size {
+                if hotplug_size == 0 {
+                    error!("'hotplug_size' can't be 0");
+                    return Err(Error::InvalidHotplugSize);
                 }
 
-                if ram_region_consumed {
-                    break;
+                if !user_provided_zones && config.hotplug_method == HotplugMethod::Acpi {
+                    start_of_device_area = start_of_device_area
+                        .checked_add(hotplug_size)
+                        .ok_or(Error::GuestAddressOverFlow)?;
+                } else {
+                    // Alignment must be "natural" i.e. same as size of block
+                    let start_addr = GuestAddress(
+                        (start_of_device_area.0 + virtio_devices::VIRTIO_MEM_ALIGN_SIZE - 1)
+                            / virtio_devices::VIRTIO_MEM_ALIGN_SIZE
+                            * virtio_devices::VIRTIO_MEM_ALIGN_SIZE,
+                    );
+                    virtio_mem_regions.push((zone, start_addr));
+
+                    start_of_device_area = start_addr
+                        .checked_add(hotplug_size)
+                        .ok_or(Error::GuestAddressOverFlow)?;
                 }
             }
+        }
 
-            if exit {
-                break;
+        Ok((virtio_mem_regions, start_of_device_area))
+    }
+
+    /// Lays out the guest RAM of a VM booted from `config` the same way
+    /// new() does, but without allocating it. Returns the memory zone,
+    /// guest address and size of each boot RAM region, followed by the
+    /// regions virtio-mem devices hotplug memory into.
+    #[allow(clippy::type_complexity)]
+    pub fn boot_memory_layout(
+        config: &MemoryConfig,
+    ) -> Result<
+        (
+            Vec<(String, GuestAddress, u64)>,
+            Vec<(String, GuestAddress, u64)>,
+        ),
+        Error,
+    > {
+        let (_, zones, allow_mem_hotplug) = Self::validate_memory_config(config, config.size == 0)?;
+        let ram_regions: Vec<(GuestAddress, usize)> = arch::arch_memory_regions()
+            .iter()
+            .filter(|r| r.2 == RegionType::Ram)
+            .map(|r| (r.0, r.1))
+            .collect();
+
+        let mut layout = Vec::new();
+        for (zone, regions) in Self::layout_memory_zones(&ram_regions, &zones)? {
+            for (_, start, size) in regions {
+                layout.push((zone.id.clone(), start, size));
             }
         }
+        if zones
+            .iter()
+            .any(|zone| !layout.iter().any(|(id, _, _)| *id == zone.id))
+        {
+            return Err(Error::MissingZoneIdentifier);
+        }
 
-        Ok((mem_regions, memory_zones))
+        let last_addr = layout
+            .iter()
+            .map(|(_, start, size)| start.unchecked_add(size - 1))
+            .max()
+            .ok_or(Error::MissingMemoryZones)?;
+        let (virtio_mem_regions, _) = Self::layout_hotplug_memory(
+            config,
+            &zones,
+            Self::start_addr(last_addr, allow_mem_hotplug)?,
+        )?;
+        let virtio_mem_layout = virtio_mem_regions
+            .into_iter()
+            .map(|(zone, start)| (zone.id.clone(), start, zone.hotplug_size.unwrap()))
+            .collect();
+
+        Ok((layout, virtio_mem_layout))
     }
 
     // Restore both GuestMemory regions along with MemoryZone zones.
@@ -654,6 +976,8 @@ impl MemoryManager {
         prefault: Option<bool>,
         mut existing_memory_files: HashMap<u32, File>,
         thp: bool,
+        sealed: bool,
+        prealloc_threads: usize,
     ) -> Result<(Vec<Arc<GuestRegionMmap>>, MemoryZones), Error> {
         let mut memory_regions = Vec::new();
         let mut memory_zones = HashMap::new();
@@ -677,6 +1001,8 @@ impl MemoryManager {
                         zone_config.host_numa_node,
                         existing_memory_files.remove(&guest_ram_mapping.slot),
                         thp,
+                        sealed,
+                        prealloc_threads,
                     )?;
                     memory_regions.push(Arc::clone(&region));
                     if let Some(memory_zone) = memory_zones.get_mut(&guest_ram_mapping.zone_id) {
@@ -790,16 +1116,23 @@ impl MemoryManager {
                 }
             }
 
+            if let Some(node) = config.numa_bind {
+                if !Path::new(&format!("/sys/devices/system/node/node{node}")).exists() {
+                    error!("Host NUMA node {} doesn't exist", node);
+                    return Err(Error::InvalidHostNumaNode(node));
+                }
+            }
+
             // Create a single zone from the global memory config. This lets
             // us reuse the codepath for user defined memory zones.
             let zones = vec![MemoryZoneConfig {
                 id: String::from(DEFAULT_MEMORY_ZONE),
                 size: config.size,
-                file: None,
+                file: config.file.clone(),
                 shared: config.shared,
                 hugepages: config.hugepages,
                 hugepage_size: config.hugepage_size,
-                host_numa_node: None,
+                host_numa_node: config.numa_bind,
                 hotplug_size: config.hotplug_size,
                 hotplugged_size: config.hotplugged_size,
                 prefault: config.prefault,
@@ -995,6 +1328,10 @@ impl MemoryManager {
         let (ram_size, zones, allow_mem_hotplug) =
             Self::validate_memory_config(config, user_provided_zones)?;
 
+        // Unless specified, let the kernel prefault the memory through
+        // MAP_POPULATE.
+        let prealloc_threads = config.prealloc_threads.unwrap_or(1);
+
         let (
             start_of_device_area,
             boot_ram,
@@ -1014,6 +1351,8 @@ impl MemoryManager {
                 prefault,
                 existing_memory_files.unwrap_or_default(),
                 config.thp,
+                config.backend == Some(MemoryBackend::Memfd),
+                prealloc_threads,
             )?;
             let guest_memory =
                 GuestMemoryMmap::from_arc_regions(regions).map_err(Error::GuestMemory)?;
@@ -1050,78 +1389,65 @@ impl MemoryManager {
                 })
                 .collect();
 
-            let (mem_regions, mut memory_zones) =
-                Self::create_memory_regions_from_zones(&ram_regions, &zones, prefault, config.thp)?;
+            let (mem_regions, mut memory_zones) = Self::create_memory_regions_from_zones(
+                &ram_regions,
+                &zones,
+                prefault,
+                config.thp,
+                config.backend == Some(MemoryBackend::Memfd),
+                prealloc_threads,
+            )?;
 
             let mut guest_memory =
                 GuestMemoryMmap::from_arc_regions(mem_regions).map_err(Error::GuestMemory)?;
 
             let boot_guest_memory = guest_memory.clone();
 
-            let mut start_of_device_area =
+            let start_of_boot_device_area =
                 MemoryManager::start_addr(guest_memory.last_addr(), allow_mem_hotplug)?;
 
             // Update list of memory zones for resize.
-            for zone in zones.iter() {
-                if let Some(memory_zone) = memory_zones.get_mut(&zone.id) {
-                    if let Some(hotplug_size) = zone.hotplug_size {
-                        if hotplug_size == 0 {
-                            error!("'hotplug_size' can't be 0");
-                            return Err(Error::InvalidHotplugSize);
-                        }
-
-                        if !user_provided_zones && config.hotplug_method == HotplugMethod::Acpi {
-                            start_of_device_area = start_of_device_area
-                                .checked_add(hotplug_size)
-                                .ok_or(Error::GuestAddressOverFlow)?;
-                        } else {
-                            // Alignment must be "natural" i.e. same as size of block
-                            let start_addr = GuestAddress(
-                                (start_of_device_area.0 + virtio_devices::VIRTIO_MEM_ALIGN_SIZE
-                                    - 1)
-                                    / virtio_devices::VIRTIO_MEM_ALIGN_SIZE
-                                    * virtio_devices::VIRTIO_MEM_ALIGN_SIZE,
-                            );
-
-                            // When `prefault` is set by vm_restore, memory manager
-                            // will create ram region with `prefault` option in
-                            // restore config rather than same option in zone
-                            let region = MemoryManager::create_ram_region(
-                                &None,
-                                0,
-                                start_addr,
-                                hotplug_size as usize,
-                                prefault.unwrap_or(zone.prefault),
-                                zone.shared,
-                                zone.hugepages,
-                                zone.hugepage_size,
-                                zone.host_numa_node,
-                                None,
-                                config.thp,
-                            )?;
-
-                            guest_memory = guest_memory
-                                .insert_region(Arc::clone(&region))
-                                .map_err(Error::GuestMemory)?;
-
-                            let hotplugged_size = zone.hotplugged_size.unwrap_or(0);
-                            let region_size = region.len();
-                            memory_zone.virtio_mem_zone = Some(VirtioMemZone {
-                                region,
-                                virtio_device: None,
-                                hotplugged_size,
-                                hugepages: zone.hugepages,
-                                blocks_state: Arc::new(Mutex::new(BlocksState::new(region_size))),
-                            });
+            if zones
+                .iter()
+                .any(|zone| !memory_zones.contains_key(&zone.id))
+            {
+                return Err(Error::MissingZoneIdentifier);
+            }
+            let (virtio_mem_regions, start_of_device_area) =
+                Self::layout_hotplug_memory(config, &zones, start_of_boot_device_area)?;
+            for (zone, start_addr) in virtio_mem_regions {
+                // When `prefault` is set by vm_restore, memory manager
+                // will create ram region with `prefault` option in
+                // restore config rather than same option in zone
+                let region = MemoryManager::create_ram_region(
+                    &None,
+                    0,
+                    start_addr,
+                    zone.hotplug_size.unwrap() as usize,
+                    prefault.unwrap_or(zone.prefault),
+                    zone.shared,
+                    zone.hugepages,
+                    zone.hugepage_size,
+                    zone.host_numa_node,
+                    None,
+                    config.thp,
+                    config.backend == Some(MemoryBackend::Memfd),
+                    prealloc_threads,
+                )?;
 
-                            start_of_device_area = start_addr
-                                .checked_add(hotplug_size)
-                                .ok_or(Error::GuestAddressOverFlow)?;
-                        }
-                    }
-                } else {
-                    return Err(Error::MissingZoneIdentifier);
-                }
+                guest_memory = guest_memory
+                    .insert_region(Arc::clone(&region))
+                    .map_err(Error::GuestMemory)?;
+
+                let hotplugged_size = zone.hotplugged_size.unwrap_or(0);
+                let region_size = region.len();
+                memory_zones.get_mut(&zone.id).unwrap().virtio_mem_zone = Some(VirtioMemZone {
+                    region,
+                    virtio_device: None,
+                    hotplugged_size,
+                    hugepages: zone.hugepages,
+                    blocks_state: Arc::new(Mutex::new(BlocksState::new(region_size))),
+                });
             }
 
             let mut hotplug_slots = Vec::with_capacity(HOTPLUG_COUNT);
@@ -1210,13 +1536,18 @@ impl MemoryManager {
             current_ram,
             next_hotplug_slot,
             shared: config.shared,
+            sealed: config.backend == Some(MemoryBackend::Memfd),
             hugepages: config.hugepages,
             hugepage_size: config.hugepage_size,
             prefault: config.prefault,
+            prealloc_threads,
             #[cfg(target_arch = "x86_64")]
             sgx_epc_region: None,
             user_provided_zones,
             snapshot_memory_ranges: MemoryRangeTable::default(),
+            tracked_base: None,
+            next_snapshot_base: None,
+            snapshot_base: None,
             memory_zones,
             guest_ram_mappings: Vec::new(),
             acpi_address,
@@ -1227,6 +1558,9 @@ impl MemoryManager {
             #[cfg(target_arch = "aarch64")]
             uefi_flash: None,
             thp: config.thp,
+            numa_bind: config.numa_bind,
+            max_ram: config.max_ram,
+            readonly_rom: None,
         };
 
         #[cfg(target_arch = "aarch64")]
@@ -1246,6 +1580,10 @@ impl MemoryManager {
             memory_manager.setup_sgx(sgx_epc_config)?;
         }
 
+        if let Some(readonly_rom) = &config.readonly_rom {
+            memory_manager.add_readonly_rom(readonly_rom)?;
+        }
+
         Ok(Arc::new(Mutex::new(memory_manager)))
     }
 
@@ -1277,6 +1615,41 @@ impl MemoryManager {
                 None,
             )?;
 
+            if let Some(snapshot_base) = recv_snapshot_base(source_url).map_err(Error::Restore)? {
+                // A differential snapshot only holds the pages written since
+                // its base was taken, the base content is restored first.
+                let base_url = snapshot_base.base_url.as_str();
+                if recv_snapshot_base(base_url)
+                    .map_err(Error::Restore)?
+                    .is_some()
+                {
+                    return Err(Error::Restore(MigratableError::Restore(anyhow!(
+                        "Base snapshot {} is itself differential",
+                        base_url
+                    ))));
+                }
+
+                let base_mem_snapshot: MemoryManagerSnapshotData = recv_vm_state(base_url)
+                    .map_err(Error::Restore)?
+                    .snapshots
+                    .get(MEMORY_MANAGER_SNAPSHOT_ID)
+                    .ok_or_else(|| {
+                        Error::Restore(MigratableError::Restore(anyhow!(
+                            "Missing memory manager snapshot in base snapshot {}",
+                            base_url
+                        )))
+                    })?
+                    .to_versioned_state()
+                    .map_err(Error::Restore)?;
+
+                let mut base_memory_file_path = url_to_path(base_url).map_err(Error::Restore)?;
+                base_memory_file_path.push(String::from(SNAPSHOT_FILENAME));
+
+                mm.lock()
+                    .unwrap()
+                    .fill_saved_regions(base_memory_file_path, base_mem_snapshot.memory_ranges)?;
+            }
+
             mm.lock()
                 .unwrap()
                 .fill_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?;
@@ -1330,10 +1703,12 @@ impl MemoryManager {
         size: usize,
         hugepages: bool,
         hugepage_size: Option<u64>,
+        sealed: bool,
     ) -> Result<FileOffset, Error> {
         let fd = Self::memfd_create(
             &ffi::CString::new("ch_ram").unwrap(),
             libc::MFD_CLOEXEC
+                | if sealed { libc::MFD_ALLOW_SEALING } else { 0 }
                 | if hugepages {
                     libc::MFD_HUGETLB
                         | if let Some(hugepage_size) = hugepage_size {
@@ -1362,6 +1737,23 @@ impl MemoryManager {
         let f = unsafe { File::from_raw_fd(fd) };
         f.set_len(size as u64).map_err(Error::SharedFileSetLen)?;
 
+        // Prevent the size of the memory from changing underneath the VMM,
+        // which would otherwise raise SIGBUS when accessing a truncated
+        // mapping. Punching holes is still allowed for discarding memory.
+        if sealed {
+            // SAFETY: FFI call with a valid file descriptor
+            let ret = unsafe {
+                libc::fcntl(
+                    fd,
+                    libc::F_ADD_SEALS,
+                    libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL,
+                )
+            };
+            if ret < 0 {
+                return Err(Error::SharedFileSeal(io::Error::last_os_error()));
+            }
+        }
+
         Ok(FileOffset::new(f, 0))
     }
 
@@ -1392,6 +1784,8 @@ impl MemoryManager {
         host_numa_node: Option<u32>,
         existing_memory_file: Option<File>,
         thp: bool,
+        sealed: bool,
+        prealloc_threads: usize,
     ) -> Result<Arc<GuestRegionMmap>, Error> {
         let mut mmap_flags = libc::MAP_NORESERVE;
 
@@ -1408,18 +1802,27 @@ impl MemoryManager {
                 mmap_flags |= libc::MAP_PRIVATE;
             }
             Some(Self::open_backing_file(backing_file, file_offset)?)
-        } else if shared || hugepages {
+        } else if shared || hugepages || sealed {
             // For hugepages we must also MAP_SHARED otherwise we will trigger #4805
             // because the MAP_PRIVATE will trigger CoW against the backing file with
             // the VFIO pinning
             mmap_flags |= libc::MAP_SHARED;
-            Some(Self::create_anonymous_file(size, hugepages, hugepage_size)?)
+            Some(Self::create_anonymous_file(
+                size,
+                hugepages,
+                hugepage_size,
+                sealed,
+            )?)
         } else {
             mmap_flags |= libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
             None
         };
 
-        if prefault {
+        // With a single thread, let the kernel populate the whole mapping.
+        // Otherwise the pages are touched in parallel once the mapping has
+        // been created and the NUMA policy applied.
+        let parallel_prefault = prefault && prealloc_threads > 1;
+        if prefault && !parallel_prefault {
             mmap_flags |= libc::MAP_POPULATE;
         }
 
@@ -1476,9 +1879,68 @@ impl MemoryManager {
                 .map_err(Error::ApplyNumaPolicy)?;
         }
 
+        if parallel_prefault {
+            // The region must be faulted in with the size of the pages backing
+            // it, touching every hugepage only once.
+            let page_size = memory_get_page_size(backing_file.as_ref(), hugepages, hugepage_size)?;
+            Self::prefault_region(&region, page_size as usize, prealloc_threads);
+        }
+
         Ok(Arc::new(region))
     }
 
+    // Fault in every page of the region by splitting it into contiguous
+    // chunks, each of them being populated by a dedicated thread. The memory
+    // content is left untouched, as a shared backing file can still be
+    // written by another VMM, e.g. the source of a local migration.
+    fn prefault_region(region: &GuestRegionMmap, page_size: usize, threads: usize) {
+        let num_pages = region.len() as usize / page_size;
+        let threads = std::cmp::min(threads, num_pages);
+        if threads == 0 {
+            return;
+        }
+        let pages_per_thread = (num_pages + threads - 1) / threads;
+        let start = region.as_ptr() as usize;
+        let file_backed = region.file_offset().is_some();
+
+        thread::scope(|s| {
+            for i in 0..threads {
+                let first = i * pages_per_thread;
+                let last = std::cmp::min(first + pages_per_thread, num_pages);
+                s.spawn(move || {
+                    // SAFETY: FFI call with a range which is part of the
+                    // mapping owned by the region, which outlives the scoped
+                    // threads.
+                    let ret = unsafe {
+                        libc::madvise(
+                            (start + first * page_size) as *mut libc::c_void,
+                            (last - first) * page_size,
+                            MADV_POPULATE_WRITE,
+                        )
+                    };
+                    if ret == 0 {
+                        return;
+                    }
+
+                    // Older kernels don't support MADV_POPULATE_WRITE. A read
+                    // is enough to allocate the pages of a backing file, and
+                    // nobody else can write to private anonymous memory.
+                    for page in first..last {
+                        let addr = (start + page * page_size) as *mut u8;
+                        // SAFETY: the address is part of the mapping owned by
+                        // the region, which outlives the scoped threads.
+                        unsafe {
+                            let value = addr.read_volatile();
+                            if !file_backed {
+                                addr.write_volatile(value);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+
     // Update the GuestMemoryMmap with the new range
     fn add_region(&mut self, region: Arc<GuestRegionMmap>) -> Result<(), Error> {
         let guest_memory = self
@@ -1531,9 +1993,11 @@ impl MemoryManager {
             self.shared,
             self.hugepages,
             self.hugepage_size,
-            None,
+            self.numa_bind,
             None,
             self.thp,
+            self.sealed,
+            self.prealloc_threads,
         )?;
 
         // Map it into the guest
@@ -1759,7 +2223,45 @@ impl MemoryManager {
         Ok(())
     }
 
+    // Guest RAM plugged so far, whatever the way it was plugged.
+    fn plugged_ram(&self) -> u64 {
+        let virtio_mem_size: u64 = self
+            .memory_zones
+            .values()
+            .filter_map(|zone| zone.virtio_mem_zone.as_ref())
+            .map(|virtio_mem_zone| virtio_mem_zone.hotplugged_size)
+            .sum();
+        // With virtio-mem, the current RAM already accounts for the memory
+        // plugged in the default zone.
+        match self.hotplug_method {
+            HotplugMethod::Acpi => self.current_ram + virtio_mem_size,
+            HotplugMethod::VirtioMem => self.boot_ram + virtio_mem_size,
+        }
+    }
+
+    fn check_max_ram(&self, ram_size: u64) -> Result<(), Error> {
+        match self.max_ram {
+            Some(max_ram) if ram_size > max_ram => {
+                error!(
+                    "Requested {} bytes of guest RAM, above the {} bytes max_ram limit",
+                    ram_size, max_ram
+                );
+                Err(Error::MaxRamExceeded(ram_size, max_ram))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn virtio_mem_resize(&mut self, id: &str, size: u64) -> Result<(), Error> {
+        if let Some(hotplugged_size) = self
+            .memory_zones
+            .get(id)
+            .and_then(|zone| zone.virtio_mem_zone.as_ref())
+            .map(|virtio_mem_zone| virtio_mem_zone.hotplugged_size)
+        {
+            self.check_max_ram(self.plugged_ram() - hotplugged_size + size)?;
+        }
+
         if let Some(memory_zone) = self.memory_zones.get_mut(id) {
             if let Some(virtio_mem_zone) = &mut memory_zone.virtio_mem_zone {
                 if let Some(virtio_mem_device) = virtio_mem_zone.virtio_device.as_ref() {
@@ -1815,6 +2317,7 @@ impl MemoryManager {
                         return Ok(region);
                     }
 
+                    self.check_max_ram(desired_ram)?;
                     region =
                         Some(self.hotplug_ram_region((desired_ram - self.current_ram) as usize)?);
                     self.current_ram = desired_ram;
@@ -1939,6 +2442,53 @@ impl MemoryManager {
         Ok(())
     }
 
+    fn add_readonly_rom(&mut self, rom: &ReadonlyRomConfig) -> Result<(), Error> {
+        let file = File::open(&rom.file).map_err(Error::ReadonlyRomOpen)?;
+        let file_size = file.metadata().map_err(Error::ReadonlyRomOpen)?.len();
+
+        // The ROM content is never written back to the file, and any write
+        // from the guest is rejected by the hypervisor since the memory slot
+        // is registered as read-only.
+        let region = MmapRegion::build(
+            Some(FileOffset::new(file, 0)),
+            file_size as usize,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE | libc::MAP_NORESERVE,
+        )
+        .map_err(Error::ReadonlyRomMmap)?;
+
+        // The mapping is implicitly extended to a page boundary, the end of
+        // the last page reading as zeroes.
+        let page_size = arch::PAGE_SIZE as u64;
+        let size = ((file_size + page_size - 1) / page_size) * page_size;
+
+        // Reserving the range from the 32 bits device area ensures it can't
+        // overlap with RAM, nor with any MMIO range given to a device.
+        self.allocator
+            .lock()
+            .unwrap()
+            .allocate_mmio_hole_addresses(Some(GuestAddress(rom.address)), size, None)
+            .ok_or(Error::ReadonlyRomRangeAllocation)?;
+
+        info!(
+            "Adding read-only ROM {:?}: 0x{:x} (0x{:x})",
+            rom.file, rom.address, size
+        );
+
+        self.create_userspace_mapping(
+            rom.address,
+            size,
+            region.as_ptr() as u64,
+            false,
+            true,
+            false,
+        )?;
+
+        self.readonly_rom = Some(region);
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn sgx_epc_region(&self) -> &Option<SgxEpcRegion> {
         &self.sgx_epc_region
@@ -2006,6 +2556,50 @@ impl MemoryManager {
         Ok(table)
     }
 
+    /// Track the guest pages written from now on, for differential
+    /// snapshots against the snapshot at `base_url`. The dirty pages
+    /// logging must have been started right before.
+    pub fn track_snapshot_base(&mut self, base_url: &str) {
+        self.tracked_base = Some(TrackedBase::new(base_url, &self.guest_ram_mappings));
+    }
+
+    /// Whether the guest pages written are tracked for differential
+    /// snapshots, whatever the base snapshot.
+    pub fn tracks_snapshot_changes(&self) -> bool {
+        self.tracked_base.is_some()
+    }
+
+    pub fn tracks_snapshot_base(&self, base_url: &str) -> bool {
+        self.tracked_base
+            .as_ref()
+            .map(|b| b.url == base_url)
+            .unwrap_or(false)
+    }
+
+    /// Make the next snapshot a differential one against the tracked base,
+    /// given the pages logged as dirty since the previous call.
+    pub fn prepare_differential_snapshot(
+        &mut self,
+        dirty: &MemoryRangeTable,
+    ) -> std::result::Result<(), MigratableError> {
+        let tracked_base = self.tracked_base.as_mut().ok_or_else(|| {
+            MigratableError::Snapshot(anyhow!("No base snapshot is being tracked"))
+        })?;
+
+        tracked_base.mark_dirty(dirty);
+
+        if !tracked_base.same_layout(&self.guest_ram_mappings) {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Guest memory layout changed since base snapshot {}",
+                tracked_base.url
+            )));
+        }
+
+        self.next_snapshot_base = Some(tracked_base.url.clone());
+
+        Ok(())
+    }
+
     pub fn snapshot_data(&self) -> MemoryManagerSnapshotData {
         MemoryManagerSnapshotData {
             memory_ranges: self.snapshot_memory_ranges.clone(),
@@ -2550,7 +3144,16 @@ impl Snapshottable for MemoryManager {
     }
 
     fn snapshot(&mut self) -> result::Result<Snapshot, MigratableError> {
-        let memory_ranges = self.memory_range_table(true)?;
+        self.snapshot_base = self.next_snapshot_base.take();
+
+        let mut memory_ranges = self.memory_range_table(true)?;
+        if self.snapshot_base.is_some() {
+            // A differential snapshot only saves the pages written since
+            // its base was taken.
+            if let Some(tracked_base) = &self.tracked_base {
+                memory_ranges = tracked_base.dirty_ranges(&memory_ranges);
+            }
+        }
 
         // Store locally this list of ranges as it will be used through the
         // Transportable::send() implementation. The point is to avoid the
@@ -2574,6 +3177,25 @@ impl Transportable for MemoryManager {
         _snapshot: &Snapshot,
         destination_url: &str,
     ) -> result::Result<(), MigratableError> {
+        if let Some(base_url) = &self.snapshot_base {
+            let mut snapshot_base_path = url_to_path(destination_url)?;
+            snapshot_base_path.push(SNAPSHOT_BASE_FILE);
+
+            let snapshot_base_file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(snapshot_base_path)
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            serde_json::to_writer(
+                snapshot_base_file,
+                &SnapshotBase {
+                    base_url: base_url.clone(),
+                },
+            )
+            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+        }
+
         if self.snapshot_memory_ranges.is_empty() {
             return Ok(());
         }
@@ -2627,6 +3249,12 @@ impl Migratable for MemoryManager {
             MigratableError::MigrateSend(anyhow!("Error starting VM dirty log {}", e))
         })?;
 
+        // Whoever consumes the dirty log from now on, it won't be the
+        // differential snapshots.
+        if let Some(tracked_base) = self.tracked_base.take() {
+            info!("Stopped tracking base snapshot {}", tracked_base.url);
+        }
+
         for r in self.guest_memory.memory().iter() {
             r.bitmap().reset();
         }
@@ -2639,6 +3267,10 @@ impl Migratable for MemoryManager {
             MigratableError::MigrateSend(anyhow!("Error stopping VM dirty log {}", e))
         })?;
 
+        if let Some(tracked_base) = self.tracked_base.take() {
+            info!("Stopped tracking base snapshot {}", tracked_base.url);
+        }
+
         Ok(())
     }
 
@@ -2671,7 +3303,7 @@ impl Migratable for MemoryManager {
                 .map(|(x, y)| x | y)
                 .collect();
 
-            let sub_table = MemoryRangeTable::from_bitmap(dirty_bitmap, r.gpa, 4096);
+            let sub_table = MemoryRangeTable::from_bitmap(dirty_bitmap, r.gpa, DIRTY_PAGE_SIZE);
 
             if sub_table.regions().is_empty() {
                 info!("Dirty Memory Range Table is empty");
@@ -2687,3 +3319,105 @@ impl Migratable for MemoryManager {
         Ok(table)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_smaps_rollup() {
+        let smaps_rollup = "\
+55d0a0000000-7ffd00021000 ---p 00000000 00:00 0 [rollup]
+Rss:              204812 kB
+Pss:              204806 kB
+Shared_Hugetlb:   524288 kB
+Private_Hugetlb:    2048 kB
+Swap:                  0 kB
+";
+        assert_eq!(
+            parse_smaps_rollup(smaps_rollup),
+            (204812 << 10, (524288 + 2048) << 10)
+        );
+        assert_eq!(parse_smaps_rollup(""), (0, 0));
+    }
+
+    #[test]
+    fn test_required_physical_bits() {
+        let ram_end = ram_area_end(&MemoryConfig {
+            size: 1 << 30,
+            ..Default::default()
+        });
+        assert_eq!(ram_end, 5 << 30);
+        assert_eq!(required_physical_bits(ram_end, 1, &[]), 34);
+        // A 64GiB BAR along with its alignment
+        assert_eq!(required_physical_bits(ram_end, 1, &[(0, 64 << 30)]), 38);
+        // Every PCI segment is as large as the one holding the BAR
+        assert_eq!(required_physical_bits(ram_end, 2, &[(1, 64 << 30)]), 39);
+        assert_eq!(
+            required_physical_bits(ram_end, 1, &[(0, 64 << 30), (0, 64 << 30)]),
+            38
+        );
+        assert_eq!(
+            required_physical_bits(ram_end, 1, &[(0, 128 << 30), (0, 64 << 30)]),
+            39
+        );
+
+        let ram_end = ram_area_end(&MemoryConfig {
+            size: 1 << 30,
+            hotplug_size: Some(7 << 30),
+            ..Default::default()
+        });
+        assert_eq!(ram_end, (12 << 30) + (128 << 20));
+    }
+
+    #[test]
+    fn test_tracked_base() {
+        let mapping = |gpa, size| GuestRamMapping {
+            slot: 0,
+            gpa,
+            size,
+            zone_id: String::new(),
+            virtio_mem: false,
+            file_offset: 0,
+        };
+        let mappings = vec![mapping(0, 1 << 20), mapping(1 << 30, 1 << 20)];
+        let mut tracked_base = TrackedBase::new("file:///base", &mappings);
+        assert!(tracked_base.same_layout(&mappings));
+        assert!(!tracked_base.same_layout(&mappings[..1]));
+
+        let mut dirty = MemoryRangeTable::default();
+        dirty.push(MemoryRange {
+            gpa: 0x1800,
+            length: 0x1000,
+        });
+        dirty.push(MemoryRange {
+            gpa: (1 << 30) + 0x10000,
+            length: 0x1000,
+        });
+        tracked_base.mark_dirty(&dirty);
+
+        // Pages written later add up to the earlier ones
+        let mut dirty = MemoryRangeTable::default();
+        dirty.push(MemoryRange {
+            gpa: 0x3000,
+            length: 0x1000,
+        });
+        tracked_base.mark_dirty(&dirty);
+
+        // Only the ranges saved by snapshots are kept
+        let mut snapshot_ranges = MemoryRangeTable::default();
+        snapshot_ranges.push(MemoryRange {
+            gpa: 0,
+            length: 1 << 20,
+        });
+        let ranges = tracked_base.dirty_ranges(&snapshot_ranges);
+        assert_eq!(
+            ranges
+                .regions()
+                .iter()
+                .map(|r| (r.gpa, r.length))
+                .collect::<Vec<_>>(),
+            vec![(0x1000, 0x3000)]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+// Counters of the cgroup v2 "memory.events" file signalling that the memory
+// usage hit one of the limits of the cgroup.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct MemoryEvents {
+    high: u64,
+    max: u64,
+    oom: u64,
+}
+
+impl MemoryEvents {
+    fn parse(content: &str) -> Self {
+        let mut events = MemoryEvents::default();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let (name, value) = match (fields.next(), fields.next()) {
+                (Some(name), Some(value)) => (name, value.parse().unwrap_or_default()),
+                _ => continue,
+            };
+            match name {
+                "high" => events.high = value,
+                "max" => events.max = value,
+                "oom" => events.oom = value,
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn read(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path).map(|content| Self::parse(&content))
+    }
+}
+
+// Returns the path of the cgroup v2 the process belongs to, relative to the
+// cgroup2 mount point, out of the content of "/proc/self/cgroup".
+fn unified_cgroup_path(proc_cgroup: &str) -> Option<&str> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.trim_start_matches('/'))
+}
+
+// Returns the mount point of the cgroup2 hierarchy out of the content of
+// "/proc/self/mounts".
+fn cgroup2_mount_point(proc_mounts: &str) -> Option<&str> {
+    proc_mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.len() > 2 && fields[2] == "cgroup2").then(|| fields[1])
+    })
+}
+
+/// Watches the "memory.events" file of the cgroup v2 the VMM runs in, which
+/// the kernel updates whenever the memory usage of the cgroup reaches its
+/// `memory.high` or `memory.max` limit, before resorting to the OOM killer.
+///
+/// The monitor is meant to be registered with an epoll instance, and
+/// [`MemoryPressureMonitor::pressure_reported`] called whenever it becomes
+/// readable.
+pub struct MemoryPressureMonitor {
+    inotify: File,
+    events_path: PathBuf,
+    events: MemoryEvents,
+}
+
+impl MemoryPressureMonitor {
+    pub fn new() -> io::Result<Self> {
+        let proc_cgroup = fs::read_to_string("/proc/self/cgroup")?;
+        let proc_mounts = fs::read_to_string("/proc/self/mounts")?;
+        let events_path = match (
+            cgroup2_mount_point(&proc_mounts),
+            unified_cgroup_path(&proc_cgroup),
+        ) {
+            (Some(mount_point), Some(path)) => {
+                Path::new(mount_point).join(path).join("memory.events")
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The VMM is not running in a cgroup v2 hierarchy",
+                ))
+            }
+        };
+        let events = MemoryEvents::read(&events_path)?;
+
+        // SAFETY: FFI call with valid arguments
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid file descriptor owned solely by us
+        let inotify = unsafe { File::from_raw_fd(fd) };
+
+        let path = CString::new(events_path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: FFI call with a valid file descriptor and path
+        if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), libc::IN_MODIFY) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        info!("Monitoring memory pressure through {:?}", events_path);
+
+        Ok(MemoryPressureMonitor {
+            inotify,
+            events_path,
+            events,
+        })
+    }
+
+    /// Consumes the pending notifications, returning whether the cgroup hit
+    /// one of its memory limits since the previous call.
+    pub fn pressure_reported(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.inotify.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let events = MemoryEvents::read(&self.events_path)?;
+        let reported = events.high > self.events.high
+            || events.max > self.events.max
+            || events.oom > self.events.oom;
+        self.events = events;
+
+        Ok(reported)
+    }
+}
+
+impl AsRawFd for MemoryPressureMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_events_parse() {
+        assert_eq!(
+            MemoryEvents::parse("low 0\nhigh 12\nmax 3\noom 1\noom_kill 0\noom_group_kill 0\n"),
+            MemoryEvents {
+                high: 12,
+                max: 3,
+                oom: 1
+            }
+        );
+        assert_eq!(MemoryEvents::parse(""), MemoryEvents::default());
+    }
+
+    #[test]
+    fn test_cgroup_paths() {
+        assert_eq!(
+            unified_cgroup_path("0::/system.slice/vm.service\n"),
+            Some("system.slice/vm.service")
+        );
+        assert_eq!(unified_cgroup_path("4:memory:/vm\n"), None);
+        assert_eq!(
+            cgroup2_mount_point(
+                "proc /proc proc rw 0 0\ncgroup2 /sys/fs/cgroup cgroup2 rw,nosuid 0 0\n"
+            ),
+            Some("/sys/fs/cgroup")
+        );
+        assert_eq!(cgroup2_mount_point("proc /proc proc rw 0 0\n"), None);
+    }
+}
@@ -6,13 +6,24 @@
 use crate::coredump::GuestDebuggableError;
 use crate::{config::VmConfig, vm::VmSnapshot};
 use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, Read};
 use std::path::PathBuf;
+use std::sync::Mutex;
 use vm_migration::{MigratableError, Snapshot};
 
 pub const SNAPSHOT_STATE_FILE: &str = "state.json";
 pub const SNAPSHOT_CONFIG_FILE: &str = "config.json";
+pub const SNAPSHOT_BASE_FILE: &str = "base.json";
+
+/// Manifest of a differential snapshot, which only holds the guest pages
+/// written since its base snapshot was taken.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SnapshotBase {
+    /// The base snapshot URL
+    pub base_url: String,
+}
 
 pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
     let path: PathBuf = url
@@ -48,11 +59,7 @@ pub fn recv_vm_config(source_url: &str) -> std::result::Result<VmConfig, Migrata
 
     vm_config_path.push(SNAPSHOT_CONFIG_FILE);
 
-    // Try opening the snapshot file
-    let vm_config_file =
-        File::open(vm_config_path).map_err(|e| MigratableError::MigrateSend(e.into()))?;
-    let vm_config_reader = BufReader::new(vm_config_file);
-    serde_json::from_reader(vm_config_reader).map_err(|e| MigratableError::MigrateReceive(e.into()))
+    VmConfig::from_file(&vm_config_path).map_err(|e| MigratableError::MigrateReceive(e.into()))
 }
 
 pub fn recv_vm_state(source_url: &str) -> std::result::Result<Snapshot, MigratableError> {
@@ -67,6 +74,26 @@ pub fn recv_vm_state(source_url: &str) -> std::result::Result<Snapshot, Migratab
     serde_json::from_reader(vm_state_reader).map_err(|e| MigratableError::MigrateReceive(e.into()))
 }
 
+pub fn recv_snapshot_base(
+    source_url: &str,
+) -> std::result::Result<Option<SnapshotBase>, MigratableError> {
+    let mut snapshot_base_path = url_to_path(source_url)?;
+
+    snapshot_base_path.push(SNAPSHOT_BASE_FILE);
+
+    // Full snapshots come without any manifest
+    if !snapshot_base_path.exists() {
+        return Ok(None);
+    }
+
+    let snapshot_base_file =
+        File::open(snapshot_base_path).map_err(|e| MigratableError::MigrateSend(e.into()))?;
+    let snapshot_base_reader = BufReader::new(snapshot_base_file);
+    serde_json::from_reader(snapshot_base_reader)
+        .map(Some)
+        .map_err(|e| MigratableError::MigrateReceive(e.into()))
+}
+
 pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, MigratableError> {
     if let Some(snapshot_data) = snapshot.snapshot_data.as_ref() {
         return snapshot_data.to_state();
@@ -76,3 +103,89 @@ pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, M
         "Could not find VM config snapshot section"
     )))
 }
+
+/// Phase of a migration being received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MigrationPhase {
+    /// Waiting for the source to connect
+    #[default]
+    Listening,
+    /// The source is connected, the migration is starting
+    Connected,
+    /// Receiving the guest memory
+    Memory,
+    /// Restoring the VM from its state
+    State,
+    /// The VM has been received and is running
+    Completed,
+    /// The migration failed, see the error
+    Failed,
+    /// The migration was aborted through the API
+    Aborted,
+}
+
+/// Progress of a migration being received.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationStatus {
+    pub phase: MigrationPhase,
+    /// Guest memory received so far, in bytes
+    pub bytes_received: u64,
+    /// Number of memory tables received, the first one covering the whole
+    /// guest memory and the following ones the pages dirtied meanwhile
+    pub memory_iterations: u64,
+    /// Guest memory still expected for the table being received, in bytes
+    pub bytes_remaining: u64,
+    /// Reason of the failure
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Reader accounting the guest memory read from the migration socket in
+/// the status of the migration.
+pub struct MigrationMemoryReader<'a, R> {
+    inner: &'a mut R,
+    status: &'a Mutex<MigrationStatus>,
+}
+
+impl<'a, R: Read> MigrationMemoryReader<'a, R> {
+    pub fn new(inner: &'a mut R, status: &'a Mutex<MigrationStatus>) -> Self {
+        MigrationMemoryReader { inner, status }
+    }
+}
+
+impl<'a, R: Read> Read for MigrationMemoryReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+
+        let mut status = self.status.lock().unwrap();
+        status.bytes_received += count as u64;
+        status.bytes_remaining = status.bytes_remaining.saturating_sub(count as u64);
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_memory_reader() {
+        let status = Mutex::new(MigrationStatus {
+            phase: MigrationPhase::Memory,
+            bytes_remaining: 16,
+            ..Default::default()
+        });
+        let mut source: &[u8] = &[0xau8; 12];
+        let mut reader = MigrationMemoryReader::new(&mut source, &status);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 8);
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        let status = status.lock().unwrap();
+        assert_eq!(status.bytes_received, 12);
+        assert_eq!(status.bytes_remaining, 4);
+    }
+}
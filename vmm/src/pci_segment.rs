@@ -35,6 +35,8 @@ pub(crate) struct PciSegment {
     pub(crate) pci_devices_down: u32,
     // List of allocated IRQs for each PCI slot.
     pub(crate) pci_irq_slots: [u8; 32],
+    // Labels exposed to the guest for each PCI slot.
+    pub(crate) pci_device_labels: [Option<String>; 32],
 
     // Device memory covered by this segment
     pub(crate) start_of_device_area: u64,
@@ -85,6 +87,7 @@ impl PciSegment {
             start_of_device_area,
             end_of_device_area,
             pci_irq_slots: *pci_irq_slots,
+            pci_device_labels: Default::default(),
         };
 
         info!(
@@ -166,28 +169,93 @@ impl PciSegment {
     }
 }
 
-struct PciDevSlot {
+struct PciDevSlot<'a> {
+    segment_id: u16,
     device_id: u8,
+    label: Option<&'a str>,
 }
 
-impl Aml for PciDevSlot {
+impl<'a> Aml for PciDevSlot<'a> {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
         let sun = self.device_id;
         let adr: u32 = (self.device_id as u32) << 16;
-        aml::Device::new(
-            format!("S{:03}", self.device_id).as_str().into(),
+        let sun = aml::Name::new("_SUN".into(), &sun);
+        let adr = aml::Name::new("_ADR".into(), &adr);
+        let ej0 = aml::Method::new(
+            "_EJ0".into(),
+            1,
+            true,
+            vec![&aml::MethodCall::new(
+                "\\_SB_.PHPR.PCEJ".into(),
+                vec![&aml::Path::new("_SUN"), &aml::Path::new("_SEG")],
+            )],
+        );
+        let dsm = self.label.map(|label| PciDevSlotDsmMethod {
+            acpi_index: self.segment_id as u32 * 32 + self.device_id as u32 + 1,
+            label,
+        });
+
+        let mut children: Vec<&dyn Aml> = vec![&sun, &adr, &ej0];
+        if let Some(dsm) = &dsm {
+            children.push(dsm);
+        }
+
+        aml::Device::new(format!("S{:03}", self.device_id).as_str().into(), children)
+            .to_aml_bytes(sink)
+    }
+}
+
+struct PciDevSlotDsmMethod<'a> {
+    acpi_index: u32,
+    label: &'a str,
+}
+
+impl<'a> Aml for PciDevSlotDsmMethod<'a> {
+    fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
+        // Refer to PCI Firmware spec v3.3 Ch 4.6.7
+        // _DSM for Naming a PCI or PCI Express Device Under Operating Systems,
+        // the following is the implementation in ASL.
+        /*
+        Method (_DSM, 4, NotSerialized)  // _DSM: Device-Specific Method
+        {
+              If ((Arg0 == ToUUID ("e5c937d0-3553-4d7a-9117-ea4d19c3434d") /* Device Labeling Interface */))
+              {
+                  If ((Arg2 == Zero))
+                  {
+                      Return (Buffer (One) { 0x81 })
+                  }
+                  If ((Arg2 == 0x07))
+                  {
+                      Return (Package (0x02) { <acpi_index>, "<label>" })
+                  }
+              }
+
+              Return (Buffer (One) { 0x00 })
+        }
+         */
+        let uuid_buf = uuid_to_aml_buffer("E5C937D0-3553-4D7A-9117-EA4D19C3434D");
+        aml::Method::new(
+            "_DSM".into(),
+            4,
+            false,
             vec![
-                &aml::Name::new("_SUN".into(), &sun),
-                &aml::Name::new("_ADR".into(), &adr),
-                &aml::Method::new(
-                    "_EJ0".into(),
-                    1,
-                    true,
-                    vec![&aml::MethodCall::new(
-                        "\\_SB_.PHPR.PCEJ".into(),
-                        vec![&aml::Path::new("_SUN"), &aml::Path::new("_SEG")],
-                    )],
+                &aml::If::new(
+                    &aml::Equal::new(&aml::Arg(0), &aml::BufferData::new(uuid_buf)),
+                    vec![
+                        &aml::If::new(
+                            &aml::Equal::new(&aml::Arg(2), &aml::ZERO),
+                            vec![&aml::Return::new(&aml::BufferData::new(vec![0x81]))],
+                        ),
+                        &aml::If::new(
+                            &aml::Equal::new(&aml::Arg(2), &0x07u8),
+                            vec![&aml::Return::new(&aml::Package::new(vec![
+                                &self.acpi_index,
+                                &self.label,
+                            ]))],
+                        ),
+                    ],
                 ),
+                &aml::Return::new(&aml::BufferData::new(vec![0])),
             ],
         )
         .to_aml_bytes(sink)
@@ -248,6 +316,24 @@ impl Aml for PciDevSlotMethods {
     }
 }
 
+/*
+ * As per ACPI v6.3 Ch 19.6.142, the UUID is required to be in mixed endian:
+ * Among the fields of a UUID:
+ *   {d1 (8 digits)} - {d2 (4 digits)} - {d3 (4 digits)} - {d4 (16 digits)}
+ * d1 ~ d3 need to be little endian, d4 be big endian.
+ * See https://en.wikipedia.org/wiki/Universally_unique_identifier#Encoding .
+ */
+fn uuid_to_aml_buffer(uuid: &str) -> Vec<u8> {
+    let uuid = Uuid::parse_str(uuid).unwrap();
+    let (uuid_d1, uuid_d2, uuid_d3, uuid_d4) = uuid.as_fields();
+    let mut uuid_buf = vec![];
+    uuid_buf.extend(uuid_d1.to_le_bytes());
+    uuid_buf.extend(uuid_d2.to_le_bytes());
+    uuid_buf.extend(uuid_d3.to_le_bytes());
+    uuid_buf.extend(uuid_d4);
+    uuid_buf
+}
+
 struct PciDsmMethod {}
 
 impl Aml for PciDsmMethod {
@@ -272,20 +358,7 @@ impl Aml for PciDsmMethod {
               Return (Buffer (One) { 0x00 })
         }
          */
-        /*
-         * As per ACPI v6.3 Ch 19.6.142, the UUID is required to be in mixed endian:
-         * Among the fields of a UUID:
-         *   {d1 (8 digits)} - {d2 (4 digits)} - {d3 (4 digits)} - {d4 (16 digits)}
-         * d1 ~ d3 need to be little endian, d4 be big endian.
-         * See https://en.wikipedia.org/wiki/Universally_unique_identifier#Encoding .
-         */
-        let uuid = Uuid::parse_str("E5C937D0-3553-4D7A-9117-EA4D19C3434D").unwrap();
-        let (uuid_d1, uuid_d2, uuid_d3, uuid_d4) = uuid.as_fields();
-        let mut uuid_buf = vec![];
-        uuid_buf.extend(uuid_d1.to_le_bytes());
-        uuid_buf.extend(uuid_d2.to_le_bytes());
-        uuid_buf.extend(uuid_d3.to_le_bytes());
-        uuid_buf.extend(uuid_d4);
+        let uuid_buf = uuid_to_aml_buffer("E5C937D0-3553-4D7A-9117-EA4D19C3434D");
         aml::Method::new(
             "_DSM".into(),
             4,
@@ -311,6 +384,81 @@ impl Aml for PciDsmMethod {
     }
 }
 
+// Control of the PCI Express features granted to the OS through _OSC:
+// PME, AER and PCI Express Capability Structure. Native hotplug and SHPC
+// are kept by the firmware since hotplug is handled through ACPI.
+const PCI_OSC_GRANTED_CONTROLS: u8 = 0x1c;
+
+struct PciOscMethod {}
+
+impl Aml for PciOscMethod {
+    fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
+        // Refer to ACPI spec v6.3 Ch 6.2.11 and PCI Firmware spec v3.3 Ch 4.5
+        // _OSC (Operating System Capabilities), the following is the
+        // implementation in ASL.
+        /*
+        Method (_OSC, 4, NotSerialized)  // _OSC: Operating System Capabilities
+        {
+              CreateDWordField (Arg3, Zero, CDW1)
+              If ((Arg0 == ToUUID ("33db4d5b-1ff7-401c-9657-7441c03dd766") /* PCI Host Bridge Device */))
+              {
+                  CreateDWordField (Arg3, 0x08, CDW3)
+                  If ((Arg1 != One))
+                  {
+                      CDW1 |= 0x08
+                  }
+
+                  Local0 = CDW3
+                  CDW3 &= 0x1C
+                  If ((Local0 != CDW3))
+                  {
+                      CDW1 |= 0x10
+                  }
+
+                  Return (Arg3)
+              }
+
+              CDW1 |= 0x04
+              Return (Arg3)
+        }
+         */
+        let uuid_buf = uuid_to_aml_buffer("33DB4D5B-1FF7-401C-9657-7441C03DD766");
+        let cdw1 = aml::Path::new("CDW1");
+        let cdw3 = aml::Path::new("CDW3");
+        aml::Method::new(
+            "_OSC".into(),
+            4,
+            false,
+            vec![
+                &aml::CreateDWordField::new(&cdw1, &aml::Arg(3), &aml::ZERO),
+                &aml::If::new(
+                    &aml::Equal::new(&aml::Arg(0), &aml::BufferData::new(uuid_buf)),
+                    vec![
+                        &aml::CreateDWordField::new(&cdw3, &aml::Arg(3), &8u8),
+                        // Unrecognized revision
+                        &aml::If::new(
+                            &aml::NotEqual::new(&aml::Arg(1), &aml::ONE),
+                            vec![&aml::Or::new(&cdw1, &cdw1, &0x08u8)],
+                        ),
+                        &aml::Store::new(&aml::Local(0), &cdw3),
+                        &aml::And::new(&cdw3, &cdw3, &PCI_OSC_GRANTED_CONTROLS),
+                        // Capabilities masked
+                        &aml::If::new(
+                            &aml::NotEqual::new(&aml::Local(0), &cdw3),
+                            vec![&aml::Or::new(&cdw1, &cdw1, &0x10u8)],
+                        ),
+                        &aml::Return::new(&aml::Arg(3)),
+                    ],
+                ),
+                // Unrecognized UUID
+                &aml::Or::new(&cdw1, &cdw1, &0x04u8),
+                &aml::Return::new(&aml::Arg(3)),
+            ],
+        )
+        .to_aml_bytes(sink)
+    }
+}
+
 impl Aml for PciSegment {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
         let mut pci_dsdt_inner_data: Vec<&dyn Aml> = Vec::new();
@@ -340,6 +488,9 @@ impl Aml for PciSegment {
         let pci_dsm = PciDsmMethod {};
         pci_dsdt_inner_data.push(&pci_dsm);
 
+        let pci_osc = PciOscMethod {};
+        pci_dsdt_inner_data.push(&pci_osc);
+
         let crs = if self.id == 0 {
             aml::Name::new(
                 "_CRS".into(),
@@ -392,7 +543,11 @@ impl Aml for PciSegment {
 
         let mut pci_devices = Vec::new();
         for device_id in 0..32 {
-            let pci_device = PciDevSlot { device_id };
+            let pci_device = PciDevSlot {
+                segment_id: self.id,
+                device_id,
+                label: self.pci_device_labels[device_id as usize].as_deref(),
+            };
             pci_devices.push(pci_device);
         }
         for pci_device in pci_devices.iter() {
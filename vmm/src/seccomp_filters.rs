@@ -96,6 +96,7 @@ const VFIO_DEVICE_IOEVENTFD: u64 = 0x3b74;
 const VHOST_GET_FEATURES: u64 = 0x8008af00;
 const VHOST_SET_FEATURES: u64 = 0x4008af00;
 const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008af03;
 const VHOST_SET_VRING_NUM: u64 = 0x4008af10;
 const VHOST_SET_VRING_ADDR: u64 = 0x4028af11;
 const VHOST_SET_VRING_BASE: u64 = 0x4008af12;
@@ -103,6 +104,7 @@ const VHOST_SET_VRING_KICK: u64 = 0x4008af20;
 const VHOST_SET_VRING_CALL: u64 = 0x4008af21;
 const VHOST_SET_BACKEND_FEATURES: u64 = 0x4008af25;
 const VHOST_GET_BACKEND_FEATURES: u64 = 0x8008af26;
+const VHOST_NET_SET_BACKEND: u64 = 0x4008af30;
 const VHOST_VDPA_GET_DEVICE_ID: u64 = 0x8004af70;
 const VHOST_VDPA_GET_STATUS: u64 = 0x8001af71;
 const VHOST_VDPA_SET_STATUS: u64 = 0x4001af72;
@@ -146,6 +148,7 @@ mod kvm {
     pub const KVM_CREATE_DEVICE: u64 = 0xc00c_aee0;
     pub const KVM_GET_REG_LIST: u64 = 0xc008_aeb0;
     pub const KVM_MEMORY_ENCRYPT_OP: u64 = 0xc008_aeba;
+    pub const KVM_MEMORY_ENCRYPT_REG_REGION: u64 = 0x8010_aebb;
 }
 
 #[cfg(feature = "kvm")]
@@ -237,6 +240,12 @@ fn create_vmm_ioctl_seccomp_rule_common_kvm() -> Result<Vec<SeccompRule>, Backen
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IRQFD)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_RUN)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_MEMORY_ENCRYPT_OP)?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            Eq,
+            KVM_MEMORY_ENCRYPT_REG_REGION
+        )?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_DEVICE_ATTR,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_HAS_DEVICE_ATTR,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_GSI_ROUTING)?],
@@ -313,6 +322,7 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_GET_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_OWNER)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_MEM_TABLE)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_NUM)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_ADDR)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_BASE)?],
@@ -320,6 +330,7 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_CALL)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_BACKEND_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_GET_BACKEND_FEATURES)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, VHOST_NET_SET_BACKEND)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_DEVICE_ID)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_STATUS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_SET_STATUS)?],
@@ -532,6 +543,7 @@ fn vmm_thread_rules(
         (libc::SYS_fallocate, vec![]),
         (libc::SYS_fcntl, vec![]),
         (libc::SYS_fdatasync, vec![]),
+        (libc::SYS_flock, vec![]),
         (libc::SYS_fstat, vec![]),
         (libc::SYS_fsync, vec![]),
         (libc::SYS_ftruncate, vec![]),
@@ -549,6 +561,8 @@ fn vmm_thread_rules(
         (libc::SYS_gettid, vec![]),
         (libc::SYS_gettimeofday, vec![]),
         (libc::SYS_getuid, vec![]),
+        (libc::SYS_inotify_add_watch, vec![]),
+        (libc::SYS_inotify_init1, vec![]),
         (
             libc::SYS_ioctl,
             create_vmm_ioctl_seccomp_rule(hypervisor_type)?,
@@ -602,6 +616,7 @@ fn vmm_thread_rules(
         (libc::SYS_rt_sigreturn, vec![]),
         (libc::SYS_sched_getaffinity, vec![]),
         (libc::SYS_sched_setaffinity, vec![]),
+        (libc::SYS_sendmmsg, vec![]),
         (libc::SYS_sendmsg, vec![]),
         (libc::SYS_sendto, vec![]),
         (libc::SYS_set_robust_list, vec![]),
@@ -614,6 +629,7 @@ fn vmm_thread_rules(
             or![
                 and![Cond::new(0, ArgLen::Dword, Eq, libc::AF_UNIX as u64)?],
                 and![Cond::new(0, ArgLen::Dword, Eq, libc::AF_INET as u64)?],
+                and![Cond::new(0, ArgLen::Dword, Eq, libc::AF_INET6 as u64)?],
             ],
         ),
         (libc::SYS_socketpair, vec![]),
@@ -641,7 +657,11 @@ fn vmm_thread_rules(
 
 #[cfg(feature = "kvm")]
 fn create_vcpu_ioctl_seccomp_rule_kvm() -> Result<Vec<SeccompRule>, BackendError> {
-    Ok(or![
+    #[cfg(target_arch = "x86_64")]
+    const KVM_NMI: u64 = 0xae9a;
+
+    #[allow(unused_mut)]
+    let mut rules = or![
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_CHECK_EXTENSION,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IOEVENTFD)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IRQFD,)?],
@@ -649,7 +669,12 @@ fn create_vcpu_ioctl_seccomp_rule_kvm() -> Result<Vec<SeccompRule>, BackendError
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_GSI_ROUTING,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_USER_MEMORY_REGION,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_RUN,)?],
-    ])
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    rules.extend(or![and![Cond::new(1, ArgLen::Dword, Eq, KVM_NMI)?]]);
+
+    Ok(rules)
 }
 
 #[cfg(feature = "mshv")]
@@ -39,6 +39,11 @@ pub enum Error {
     #[error("Error queuing input to the serial device: {0}")]
     QueueInput(#[source] vmm_sys_util::errno::Error),
 
+    /// Cannot update the modem status of the serial device.
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error updating the serial device's modem status: {0}")]
+    ModemStatus(#[source] vmm_sys_util::errno::Error),
+
     /// Cannot flush output on the serial buffer.
     #[error("Error flushing serial device's output buffer: {0}")]
     FlushOutput(#[source] io::Error),
@@ -94,43 +99,50 @@ impl SerialManager {
         #[cfg(target_arch = "aarch64")] serial: Arc<Mutex<Pl011>>,
         pty_pair: Option<Arc<Mutex<PtyPair>>>,
         mode: ConsoleOutputMode,
+        input: Option<File>,
     ) -> Result<Option<Self>> {
-        let in_file = match mode {
-            ConsoleOutputMode::Pty => {
-                if let Some(pty_pair) = pty_pair {
-                    pty_pair
-                        .lock()
-                        .unwrap()
-                        .main
-                        .try_clone()
-                        .map_err(Error::FileClone)?
-                } else {
-                    return Ok(None);
-                }
-            }
-            ConsoleOutputMode::Tty => {
-                // If running on an interactive TTY then accept input
-                // SAFETY: trivially safe
-                if unsafe { libc::isatty(libc::STDIN_FILENO) == 1 } {
-                    // SAFETY: STDIN_FILENO is a valid fd
-                    let stdin_clone = unsafe { File::from_raw_fd(libc::dup(libc::STDIN_FILENO)) };
-                    // SAFETY: FFI calls with correct arguments
-                    let ret = unsafe {
-                        let mut flags = libc::fcntl(stdin_clone.as_raw_fd(), libc::F_GETFL);
-                        flags |= libc::O_NONBLOCK;
-                        libc::fcntl(stdin_clone.as_raw_fd(), libc::F_SETFL, flags)
-                    };
-
-                    if ret < 0 {
-                        return Err(Error::SetNonBlocking(std::io::Error::last_os_error()));
+        let in_file = if let Some(input) = input {
+            // Input from a file takes precedence over the TTY input
+            input
+        } else {
+            match mode {
+                ConsoleOutputMode::Pty => {
+                    if let Some(pty_pair) = pty_pair {
+                        pty_pair
+                            .lock()
+                            .unwrap()
+                            .main
+                            .try_clone()
+                            .map_err(Error::FileClone)?
+                    } else {
+                        return Ok(None);
                     }
+                }
+                ConsoleOutputMode::Tty => {
+                    // If running on an interactive TTY then accept input
+                    // SAFETY: trivially safe
+                    if unsafe { libc::isatty(libc::STDIN_FILENO) == 1 } {
+                        // SAFETY: STDIN_FILENO is a valid fd
+                        let stdin_clone =
+                            unsafe { File::from_raw_fd(libc::dup(libc::STDIN_FILENO)) };
+                        // SAFETY: FFI calls with correct arguments
+                        let ret = unsafe {
+                            let mut flags = libc::fcntl(stdin_clone.as_raw_fd(), libc::F_GETFL);
+                            flags |= libc::O_NONBLOCK;
+                            libc::fcntl(stdin_clone.as_raw_fd(), libc::F_SETFL, flags)
+                        };
+
+                        if ret < 0 {
+                            return Err(Error::SetNonBlocking(std::io::Error::last_os_error()));
+                        }
 
-                    stdin_clone
-                } else {
-                    return Ok(None);
+                        stdin_clone
+                    } else {
+                        return Ok(None);
+                    }
                 }
+                _ => return Ok(None),
             }
-            _ => return Ok(None),
         };
 
         let epoll_fd = epoll::create(true).map_err(Error::Epoll)?;
@@ -159,6 +171,15 @@ impl SerialManager {
             let writer = in_file.try_clone().map_err(Error::FileClone)?;
             let buffer = SerialBuffer::new(Box::new(writer), write_out);
             serial.as_ref().lock().unwrap().set_out(Box::new(buffer));
+
+            // Until someone connects to the PTY, the output is buffered and
+            // the guest is told it should hold off sending.
+            #[cfg(target_arch = "x86_64")]
+            serial
+                .lock()
+                .unwrap()
+                .set_out_ready(false)
+                .map_err(Error::ModemStatus)?;
         }
 
         // Use 'File' to enforce closing on 'epoll_fd'
@@ -196,6 +217,12 @@ impl SerialManager {
                 .unwrap()
                 .flush_output()
                 .map_err(Error::FlushOutput)?;
+            #[cfg(target_arch = "x86_64")]
+            serial
+                .lock()
+                .unwrap()
+                .set_out_ready(true)
+                .map_err(Error::ModemStatus)?;
         }
 
         Ok(())
@@ -287,6 +314,12 @@ impl SerialManager {
                                     if event.events & libc::EPOLLHUP as u32 != 0 {
                                         if let Some(pty_write_out) = &pty_write_out {
                                             pty_write_out.store(false, Ordering::Release);
+                                            #[cfg(target_arch = "x86_64")]
+                                            serial
+                                                .lock()
+                                                .unwrap()
+                                                .set_out_ready(false)
+                                                .map_err(Error::ModemStatus)?;
                                         }
                                         // It's really important to sleep here as this will prevent
                                         // the current thread from consuming 100% of the CPU cycles
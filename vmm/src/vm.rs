@@ -11,9 +11,10 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+use crate::api::{VmDirtyRate, VmResidentMemory};
 use crate::config::{
     add_to_config, DeviceConfig, DiskConfig, FsConfig, HotplugMethod, NetConfig, PmemConfig,
-    UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig,
+    UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig, DEFAULT_MAX_PHYS_BITS,
 };
 use crate::config::{NumaConfig, PayloadConfig};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
@@ -21,12 +22,16 @@ use crate::coredump::{
     CpuElf64Writable, DumpState, Elf64Writable, GuestDebuggable, GuestDebuggableError, NoteDescType,
 };
 use crate::cpu;
-use crate::device_manager::{DeviceManager, DeviceManagerError, PtyPair};
+use crate::device_manager::{DeviceBackends, DeviceManager, DeviceManagerError, PtyPair};
 use crate::device_tree::DeviceTree;
+use crate::dry_run::VmTopology;
 #[cfg(feature = "guest_debug")]
 use crate::gdb::{Debuggable, DebuggableError, GdbRequestPayload, GdbResponsePayload};
+use crate::host_topology::HostTopology;
+#[cfg(target_arch = "x86_64")]
+use crate::identity::IdentitySeed;
 use crate::memory_manager::{
-    Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData,
+    self, Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData,
 };
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 use crate::migration::get_vm_snapshot;
@@ -64,8 +69,10 @@ use linux_loader::loader::elf::PvhBootCapability::PvhEntryPresent;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::Error::InvalidImageMagicNumber;
 use linux_loader::loader::KernelLoader;
+use once_cell::sync::OnceCell;
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::cmp;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
@@ -79,15 +86,18 @@ use std::mem::size_of;
 use std::num::Wrapping;
 use std::ops::Deref;
 use std::os::unix::net::UnixStream;
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use std::{result, str, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
-use vm_device::Bus;
+use vm_device::{Bus, Resource};
 #[cfg(feature = "tdx")]
 use vm_memory::{Address, ByteValued, GuestMemory, GuestMemoryRegion};
 use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+#[cfg(all(feature = "sev", not(feature = "tdx")))]
+use vm_memory::{GuestMemory, GuestMemoryRegion};
 use vm_migration::protocol::{Request, Response, Status};
 use vm_migration::{
     protocol::MemoryRangeTable, snapshot_from_id, Migratable, MigratableError, Pausable, Snapshot,
@@ -118,6 +128,9 @@ pub enum Error {
     #[error("Cannot load the kernel command line in memory: {0}")]
     LoadCmdLine(#[source] linux_loader::loader::Error),
 
+    #[error("Cannot load the custom DSDT: {0}")]
+    LoadCustomDsdt(#[source] crate::acpi::CustomDsdtError),
+
     #[error("Cannot modify the kernel command line: {0}")]
     CmdLineInsertStr(#[source] linux_loader::cmdline::Error),
 
@@ -140,6 +153,12 @@ pub enum Error {
     #[error("No device with id {0:?} to remove")]
     NoDeviceToRemove(String),
 
+    #[error("No disk with id {0:?} to remove")]
+    NoDiskToRemove(String),
+
+    #[error("No network device with id {0:?} to remove")]
+    NoNetToRemove(String),
+
     #[error("Cannot spawn a signal handler thread: {0}")]
     SignalHandlerSpawn(#[source] io::Error),
 
@@ -221,8 +240,19 @@ pub enum Error {
     #[error("Cannot apply seccomp filter: {0}")]
     ApplySeccompFilter(#[source] seccompiler::Error),
 
-    #[error("Failed resizing a memory zone")]
-    ResizeZone,
+    #[error("Unknown memory zone: {0}")]
+    UnknownMemoryZone(String),
+
+    #[error("Memory zone {0} can't be resized as it has no hotpluggable memory")]
+    MemoryZoneNotResizable(String),
+
+    #[error("Invalid size {size} for memory zone {id}, expected between {min} and {max}")]
+    InvalidMemoryZoneSize {
+        id: String,
+        size: u64,
+        min: u64,
+        max: u64,
+    },
 
     #[error("Cannot activate virtio devices: {0:?}")]
     ActivateVirtioDevices(DeviceManagerError),
@@ -230,6 +260,13 @@ pub enum Error {
     #[error("Error triggering power button: {0:?}")]
     PowerButton(DeviceManagerError),
 
+    #[error("Error injecting NMI: {0}")]
+    Nmi(#[source] cpu::Error),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("NMI injection is not supported on this architecture")]
+    NmiUnsupported,
+
     #[error("Kernel lacks PVH header")]
     KernelMissingPvhHeader,
 
@@ -285,6 +322,22 @@ pub enum Error {
     #[error("Invalid TDX payload type")]
     InvalidPayloadType,
 
+    #[cfg(feature = "sev")]
+    #[error("Error enabling SEV-ES VM: {0}")]
+    InitializeSevEsVm(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev")]
+    #[error("Error encrypting SEV-ES memory region: {0}")]
+    InitializeSevEsMemoryRegion(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev")]
+    #[error("Error finalizing SEV-ES VM: {0}")]
+    FinalizeSevEs(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev")]
+    #[error("Measured SEV-ES range 0x{0:x}-0x{1:x} isn't part of a single memory region")]
+    InvalidSevEsMeasuredRange(u64, u64),
+
     #[cfg(feature = "guest_debug")]
     #[error("Error debugging VM: {0:?}")]
     Debug(DebuggableError),
@@ -301,6 +354,37 @@ pub enum Error {
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
     #[error("Error coredumping VM: {0:?}")]
     Coredump(GuestDebuggableError),
+
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    #[error("Failed to monitor the host memory pressure: {0}")]
+    MemoryPressureMonitor(#[source] io::Error),
+
+    #[error("Dirty rate sampling is already in progress")]
+    DirtyRateAlreadyStarted,
+
+    #[error("Dirty rate sampling has not been started")]
+    DirtyRateNotStarted,
+
+    #[error("Dirty pages are already tracked for a migration or a differential snapshot")]
+    DirtyRateTrackingInUse,
+
+    #[error("Error sampling the dirty rate: {0}")]
+    DirtyRate(#[source] MigratableError),
+
+    #[error("Error reading the resident memory: {0}")]
+    ResidentMemory(#[source] io::Error),
+
+    #[error("Error spawning the counters sampling thread: {0}")]
+    CountersSamplerSpawn(#[source] io::Error),
+
+    #[error("Cannot read the resources of device {0:?}: {1}")]
+    DeviceResources(PathBuf, #[source] io::Error),
+
+    #[error("The VM requires {0} physical address bits while the host supports {1}")]
+    PhysicalBitsExceeded(u8, u8),
+
+    #[error("Cannot mirror the host CPU topology: {0}")]
+    HostTopology(#[source] io::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -351,11 +435,57 @@ impl VmState {
     }
 }
 
+// Exits taking longer than this to be handled are reported.
+const SLOW_EXIT_THRESHOLD: Duration = Duration::from_millis(50);
+
 struct VmOpsHandler {
     memory: GuestMemoryAtomic<GuestMemoryMmap>,
     #[cfg(target_arch = "x86_64")]
     io_bus: Arc<Bus>,
     mmio_bus: Arc<Bus>,
+    // Only used to name the device behind a slow exit, which is why it can
+    // be set once the device manager has been created.
+    device_tree: OnceCell<Arc<Mutex<DeviceTree>>>,
+}
+
+impl VmOpsHandler {
+    fn account_exit(&self, start: Instant, pio: bool, addr: u64) {
+        let elapsed = start.elapsed();
+        cpu::account_exit_handling_time(elapsed);
+
+        if elapsed >= SLOW_EXIT_THRESHOLD {
+            let space = if pio { "PIO" } else { "MMIO" };
+            warn!(
+                "Slow {} exit: access to 0x{:x} handled by {} in {:?}",
+                space,
+                addr,
+                self.device_at(pio, addr)
+                    .unwrap_or_else(|| String::from("unknown device")),
+                elapsed
+            );
+        }
+    }
+
+    fn device_at(&self, pio: bool, addr: u64) -> Option<String> {
+        // Don't wait for the device tree as the vCPU could be prevented from
+        // being paused.
+        let device_tree = self.device_tree.get()?.try_lock().ok()?;
+        device_tree
+            .iter()
+            .find(|(_, node)| {
+                node.resources.iter().any(|resource| match resource {
+                    Resource::PioAddressRange { base, size } => {
+                        pio && (*base as u64..*base as u64 + *size as u64).contains(&addr)
+                    }
+                    Resource::MmioAddressRange { base, size }
+                    | Resource::PciBar { base, size, .. } => {
+                        !pio && (*base..*base + *size).contains(&addr)
+                    }
+                    _ => false,
+                })
+            })
+            .map(|(id, _)| id.clone())
+    }
 }
 
 impl VmOps for VmOpsHandler {
@@ -374,13 +504,16 @@ impl VmOps for VmOpsHandler {
     }
 
     fn mmio_read(&self, gpa: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
+        let start = Instant::now();
         if let Err(vm_device::BusError::MissingAddressRange) = self.mmio_bus.read(gpa, data) {
             info!("Guest MMIO read to unregistered address 0x{:x}", gpa);
         }
+        self.account_exit(start, false, gpa);
         Ok(())
     }
 
     fn mmio_write(&self, gpa: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        let start = Instant::now();
         match self.mmio_bus.write(gpa, data) {
             Err(vm_device::BusError::MissingAddressRange) => {
                 info!("Guest MMIO write to unregistered address 0x{:x}", gpa);
@@ -392,19 +525,23 @@ impl VmOps for VmOpsHandler {
             }
             _ => {}
         };
+        self.account_exit(start, false, gpa);
         Ok(())
     }
 
     #[cfg(target_arch = "x86_64")]
     fn pio_read(&self, port: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
+        let start = Instant::now();
         if let Err(vm_device::BusError::MissingAddressRange) = self.io_bus.read(port, data) {
             info!("Guest PIO read to unregistered address 0x{:x}", port);
         }
+        self.account_exit(start, true, port);
         Ok(())
     }
 
     #[cfg(target_arch = "x86_64")]
     fn pio_write(&self, port: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        let start = Instant::now();
         match self.io_bus.write(port, data) {
             Err(vm_device::BusError::MissingAddressRange) => {
                 info!("Guest PIO write to unregistered address 0x{:x}", port);
@@ -416,20 +553,60 @@ impl VmOps for VmOpsHandler {
             }
             _ => {}
         };
+        self.account_exit(start, true, port);
         Ok(())
     }
 }
 
+// Guest policy of SEV-ES VMs: debugging is disallowed (NODBG) and the
+// SEV-ES state encryption is required (ES).
+#[cfg(feature = "sev")]
+const SEV_ES_POLICY: u32 = 0x1 | 0x4;
+
+#[cfg(feature = "sev")]
+const SEV_PAGE_SIZE: u64 = 4096;
+
 pub fn physical_bits(hypervisor: &Arc<dyn hypervisor::Hypervisor>, max_phys_bits: u8) -> u8 {
     let host_phys_bits = get_host_cpu_phys_bits(hypervisor);
 
     cmp::min(host_phys_bits, max_phys_bits)
 }
 
+// Flags of the sysfs "resource" file of a PCI device identifying the BARs
+// placed in the 64-bit memory space.
+const IORESOURCE_MEM: u64 = 0x200;
+const IORESOURCE_MEM_64: u64 = 0x0010_0000;
+
+// Returns the sizes of the 64-bit memory BARs of a PCI device out of the
+// content of its sysfs "resource" file, which lists the 6 standard BARs first
+// as "<start> <end> <flags>".
+fn bar64_sizes(resource: &str) -> Vec<u64> {
+    resource
+        .lines()
+        .take(6)
+        .filter_map(|line| {
+            let fields: Vec<u64> = line
+                .split_whitespace()
+                .filter_map(|field| u64::from_str_radix(field.trim_start_matches("0x"), 16).ok())
+                .collect();
+            match fields[..] {
+                [start, end, flags]
+                    if flags & IORESOURCE_MEM != 0
+                        && flags & IORESOURCE_MEM_64 != 0
+                        && end > start =>
+                {
+                    Some(end - start + 1)
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
 pub struct Vm {
     #[cfg(feature = "tdx")]
     kernel: Option<File>,
-    initramfs: Option<File>,
+    initramfs: Vec<File>,
     threads: Vec<thread::JoinHandle<()>>,
     device_manager: Arc<Mutex<DeviceManager>>,
     config: Arc<Mutex<VmConfig>>,
@@ -445,7 +622,115 @@ pub struct Vm {
     #[cfg_attr(any(not(feature = "kvm"), target_arch = "aarch64"), allow(dead_code))]
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
     stop_on_boot: bool,
-    load_payload_handle: Option<thread::JoinHandle<Result<EntryPoint>>>,
+    load_payload_handle: Option<thread::JoinHandle<Result<(EntryPoint, Duration)>>>,
+    dirty_rate_start: Option<Instant>,
+    dirty_rate_bytes: u64,
+    counters_baseline: VmCounters,
+    counters_sampler: Option<CountersSampler>,
+    start_time: Option<Instant>,
+}
+
+pub type Counters = HashMap<String, HashMap<&'static str, Wrapping<u64>>>;
+
+// Counters of the devices, keyed by device id, along with the ones of the
+// vCPUs, nested under their own key so that they can't collide with a
+// device id.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct VmCounters {
+    #[serde(flatten)]
+    pub devices: Counters,
+    pub vcpus: Counters,
+}
+
+impl VmCounters {
+    fn delta(&self, previous: &VmCounters) -> VmCounters {
+        VmCounters {
+            devices: counters_delta(&self.devices, &previous.devices),
+            vcpus: counters_delta(&self.vcpus, &previous.vcpus),
+        }
+    }
+}
+
+// Reports the completion of a stage of the boot sequence along with the time
+// spent in it, so that slow boots can be narrowed down to a stage.
+fn boot_stage_event(stage: &str, duration: Duration) {
+    event!("vm", stage, "duration_ms", duration.as_millis().to_string());
+}
+
+// Increments of the counters since a previous snapshot. A counter lower than
+// its previous value belongs to a device which has been replaced since then,
+// hence it is reported as is.
+fn counters_delta(current: &Counters, previous: &Counters) -> Counters {
+    current
+        .iter()
+        .map(|(id, counters)| {
+            let previous = previous.get(id);
+            let delta = counters
+                .iter()
+                .map(|(name, value)| {
+                    let base = previous
+                        .and_then(|p| p.get(name))
+                        .copied()
+                        .unwrap_or_default();
+                    (*name, if *value >= base { value - base } else { *value })
+                })
+                .collect();
+            (id.clone(), delta)
+        })
+        .collect()
+}
+
+// Thread sending the increments of the counters to the event monitor at a
+// regular interval, until the sending end of its channel is dropped.
+struct CountersSampler {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CountersSampler {
+    fn start(
+        interval: Duration,
+        device_manager: Arc<Mutex<DeviceManager>>,
+        cpu_manager: Arc<Mutex<cpu::CpuManager>>,
+    ) -> Result<Self> {
+        let (stop, stop_receiver) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("counters_sampler".into())
+            .spawn(move || {
+                let mut previous = Vm::read_counters(&device_manager, &cpu_manager);
+                while let Err(mpsc::RecvTimeoutError::Timeout) =
+                    stop_receiver.recv_timeout(interval)
+                {
+                    let current = Vm::read_counters(&device_manager, &cpu_manager);
+                    let delta = current.delta(&previous);
+                    let properties: HashMap<Cow<str>, Cow<str>> = delta
+                        .devices
+                        .into_iter()
+                        .chain(
+                            delta
+                                .vcpus
+                                .into_iter()
+                                .map(|(id, counters)| (format!("vcpus.{id}"), counters)),
+                        )
+                        .flat_map(|(id, counters)| {
+                            counters.into_iter().map(move |(name, value)| {
+                                (format!("{id}.{name}").into(), value.0.to_string().into())
+                            })
+                        })
+                        .collect();
+                    event_monitor::event_log("vm", "counters", Some(&properties));
+                    previous = current;
+                }
+            })
+            .map_err(Error::CountersSamplerSpawn)?;
+
+        Ok(CountersSampler { stop, handle })
+    }
+
+    fn stop(self) -> Result<()> {
+        drop(self.stop);
+        self.handle.join().map_err(Error::ThreadCleanup)
+    }
 }
 
 impl Vm {
@@ -466,6 +751,7 @@ impl Vm {
         serial_pty: Option<PtyPair>,
         console_pty: Option<PtyPair>,
         console_resize_pipe: Option<File>,
+        device_backends: Option<DeviceBackends>,
         original_termios: Arc<Mutex<Option<termios>>>,
         snapshot: Option<Snapshot>,
     ) -> Result<Self> {
@@ -495,6 +781,10 @@ impl Vm {
         let force_iommu = tdx_enabled;
         #[cfg(not(feature = "tdx"))]
         let force_iommu = false;
+        // The encrypted guest memory can't be accessed by the devices, which
+        // must go through the bounce buffers set up by the guest IOMMU layer.
+        #[cfg(feature = "sev")]
+        let force_iommu = force_iommu || config.lock().unwrap().is_sev_es_enabled();
 
         #[cfg(feature = "guest_debug")]
         let stop_on_boot = config.lock().unwrap().gdb;
@@ -506,12 +796,14 @@ impl Vm {
         let io_bus = Arc::new(Bus::new());
         let mmio_bus = Arc::new(Bus::new());
 
-        let vm_ops: Arc<dyn VmOps> = Arc::new(VmOpsHandler {
+        let vm_ops_handler = Arc::new(VmOpsHandler {
             memory,
             #[cfg(target_arch = "x86_64")]
             io_bus: io_bus.clone(),
             mmio_bus: mmio_bus.clone(),
+            device_tree: OnceCell::new(),
         });
+        let vm_ops: Arc<dyn VmOps> = vm_ops_handler.clone();
 
         let cpus_config = { &config.lock().unwrap().cpus.clone() };
         let cpu_manager = cpu::CpuManager::new(
@@ -547,11 +839,18 @@ impl Vm {
         #[cfg(feature = "tdx")]
         if tdx_enabled {
             let cpuid = cpu_manager.lock().unwrap().common_cpuid();
-            let max_vcpus = cpu_manager.lock().unwrap().max_vcpus() as u32;
+            let max_vcpus = cpu_manager.lock().unwrap().max_vcpus();
             vm.tdx_init(&cpuid, max_vcpus)
                 .map_err(Error::InitializeTdxVm)?;
         }
 
+        // Likewise, SEV-ES must be enabled before the vCPUs are created
+        #[cfg(feature = "sev")]
+        if config.lock().unwrap().is_sev_es_enabled() {
+            vm.sev_es_init(SEV_ES_POLICY)
+                .map_err(Error::InitializeSevEsVm)?;
+        }
+
         cpu_manager
             .lock()
             .unwrap()
@@ -585,6 +884,11 @@ impl Vm {
         )
         .map_err(Error::DeviceManager)?;
 
+        let _ = vm_ops_handler
+            .device_tree
+            .set(device_manager.lock().unwrap().device_tree());
+
+        let start = Instant::now();
         device_manager
             .lock()
             .unwrap()
@@ -593,8 +897,12 @@ impl Vm {
                 console_pty,
                 console_resize_pipe,
                 original_termios,
+                device_backends,
             )
             .map_err(Error::DeviceManager)?;
+        if snapshot.is_none() {
+            boot_stage_event("devices-ready", start.elapsed());
+        }
 
         #[cfg(feature = "tdx")]
         let kernel = config
@@ -612,9 +920,13 @@ impl Vm {
             .unwrap()
             .payload
             .as_ref()
-            .map(|p| p.initramfs.as_ref().map(File::open))
-            .unwrap_or_default()
-            .transpose()
+            .map(|p| {
+                p.initramfs
+                    .iter()
+                    .map(File::open)
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .unwrap_or_else(|| Ok(Vec::new()))
             .map_err(Error::InitramfsFile)?;
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
@@ -648,6 +960,11 @@ impl Vm {
             hypervisor,
             stop_on_boot,
             load_payload_handle,
+            dirty_rate_start: None,
+            dirty_rate_bytes: 0,
+            counters_baseline: VmCounters::default(),
+            counters_sampler: None,
+            start_time: None,
         })
     }
 
@@ -743,6 +1060,7 @@ impl Vm {
         serial_pty: Option<PtyPair>,
         console_pty: Option<PtyPair>,
         console_resize_pipe: Option<File>,
+        device_backends: Option<DeviceBackends>,
         original_termios: Arc<Mutex<Option<termios>>>,
         snapshot: Option<Snapshot>,
         source_url: Option<&str>,
@@ -774,6 +1092,27 @@ impl Vm {
             sev_snp_enabled,
         )?;
 
+        // The number of physical bits derived when the VM was first created
+        // is kept in the configuration of the snapshot.
+        if snapshot.is_none() && vm_config.lock().unwrap().cpus.auto_phys_bits {
+            let max_phys_bits = Self::auto_physical_bits(&hypervisor, &vm_config.lock().unwrap())?;
+            info!("Using {} physical address bits", max_phys_bits);
+            vm_config.lock().unwrap().cpus.max_phys_bits = max_phys_bits;
+        }
+
+        // The topology mirroring the host one replaces the request in the
+        // configuration, so that it is kept across reboots, snapshots and
+        // migrations, along with the memory zones of its NUMA nodes.
+        if snapshot.is_none() && vm_config.lock().unwrap().cpus.host_topology {
+            HostTopology::new()
+                .and_then(|host| host.apply(&mut vm_config.lock().unwrap()))
+                .map_err(Error::HostTopology)?;
+            info!(
+                "Mirroring the host topology: {:?}",
+                vm_config.lock().unwrap().cpus.topology
+            );
+        }
+
         let phys_bits = physical_bits(&hypervisor, vm_config.lock().unwrap().cpus.max_phys_bits);
 
         let memory_manager = if let Some(snapshot) =
@@ -792,7 +1131,8 @@ impl Vm {
             #[cfg(target_arch = "x86_64")]
             let sgx_epc_config = vm_config.lock().unwrap().sgx_epc.clone();
 
-            MemoryManager::new(
+            let start = Instant::now();
+            let memory_manager = MemoryManager::new(
                 vm.clone(),
                 &vm_config.lock().unwrap().memory.clone(),
                 None,
@@ -804,7 +1144,10 @@ impl Vm {
                 #[cfg(target_arch = "x86_64")]
                 sgx_epc_config,
             )
-            .map_err(Error::MemoryManager)?
+            .map_err(Error::MemoryManager)?;
+            boot_stage_event("memory-ready", start.elapsed());
+
+            memory_manager
         };
 
         Vm::new_from_memory_manager(
@@ -822,11 +1165,54 @@ impl Vm {
             serial_pty,
             console_pty,
             console_resize_pipe,
+            device_backends,
             original_termios,
             snapshot,
         )
     }
 
+    // Derives the number of physical address bits from the amount of memory
+    // and the 64-bit BARs of the passthrough devices, so that the 64-bit PCI
+    // MMIO space is large enough to hold them.
+    fn auto_physical_bits(
+        hypervisor: &Arc<dyn hypervisor::Hypervisor>,
+        config: &VmConfig,
+    ) -> Result<u8> {
+        let mut bars = Vec::new();
+        for device in config.devices.iter().flatten() {
+            let path = device.path.join("resource");
+            let resource =
+                std::fs::read_to_string(&path).map_err(|e| Error::DeviceResources(path, e))?;
+            bars.extend(
+                bar64_sizes(&resource)
+                    .into_iter()
+                    .map(|size| (device.pci_segment, size)),
+            );
+        }
+
+        let num_pci_segments = config
+            .platform
+            .as_ref()
+            .map_or(1, |platform| platform.num_pci_segments);
+        let required_phys_bits = memory_manager::required_physical_bits(
+            memory_manager::ram_area_end(&config.memory),
+            num_pci_segments,
+            &bars,
+        );
+        let host_phys_bits = get_host_cpu_phys_bits(hypervisor);
+        if required_phys_bits > host_phys_bits {
+            return Err(Error::PhysicalBitsExceeded(
+                required_phys_bits,
+                host_phys_bits,
+            ));
+        }
+
+        Ok(cmp::max(
+            cmp::min(host_phys_bits, DEFAULT_MAX_PHYS_BITS),
+            required_phys_bits,
+        ))
+    }
+
     pub fn create_hypervisor_vm(
         hypervisor: &Arc<dyn hypervisor::Hypervisor>,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
@@ -867,24 +1253,38 @@ impl Vm {
         Ok(vm)
     }
 
+    // The initramfs segments are concatenated in order, which is how the
+    // kernel expects an early microcode archive to precede the initrd.
     fn load_initramfs(&mut self, guest_mem: &GuestMemoryMmap) -> Result<arch::InitramfsConfig> {
-        let mut initramfs = self.initramfs.as_ref().unwrap();
-        let size: usize = initramfs
-            .seek(SeekFrom::End(0))
-            .map_err(|_| Error::InitramfsLoad)?
-            .try_into()
-            .unwrap();
-        initramfs.rewind().map_err(|_| Error::InitramfsLoad)?;
+        let mut sizes = Vec::with_capacity(self.initramfs.len());
+        for mut initramfs in self.initramfs.iter() {
+            let size: usize = initramfs
+                .seek(SeekFrom::End(0))
+                .map_err(|_| Error::InitramfsLoad)?
+                .try_into()
+                .unwrap();
+            initramfs.rewind().map_err(|_| Error::InitramfsLoad)?;
+            sizes.push(size);
+        }
+        let size = sizes.iter().sum();
 
         let address =
             arch::initramfs_load_addr(guest_mem, size).map_err(|_| Error::InitramfsLoad)?;
         let address = GuestAddress(address);
 
-        guest_mem
-            .read_from(address, &mut initramfs, size)
-            .map_err(|_| Error::InitramfsLoad)?;
+        let mut segment_address = address;
+        for (mut initramfs, segment_size) in self.initramfs.iter().zip(sizes) {
+            guest_mem
+                .read_from(segment_address, &mut initramfs, segment_size)
+                .map_err(|_| Error::InitramfsLoad)?;
+            segment_address = segment_address.unchecked_add(segment_size as u64);
+        }
 
-        info!("Initramfs loaded: address = 0x{:x}", address.0);
+        info!(
+            "Initramfs loaded: address = 0x{:x}, segments = {}",
+            address.0,
+            self.initramfs.len()
+        );
         Ok(arch::InitramfsConfig { address, size })
     }
 
@@ -977,10 +1377,17 @@ impl Vm {
                 .map_err(Error::LoadCmdLine)?;
         }
 
+        let load_range = (
+            entry_addr.kernel_load,
+            entry_addr.kernel_end - entry_addr.kernel_load.0,
+        );
         if let PvhEntryPresent(entry_addr) = entry_addr.pvh_boot_cap {
             // Use the PVH kernel entry point to boot the guest
             info!("Kernel loaded: entry_addr = 0x{:x}", entry_addr.0);
-            Ok(EntryPoint { entry_addr })
+            Ok(EntryPoint {
+                entry_addr,
+                load_range,
+            })
         } else {
             Err(Error::KernelMissingPvhHeader)
         }
@@ -992,17 +1399,12 @@ impl Vm {
         memory_manager: Arc<Mutex<MemoryManager>>,
     ) -> Result<EntryPoint> {
         trace_scoped!("load_payload");
-        match (
-            &payload.firmware,
-            &payload.kernel,
-            &payload.initramfs,
-            &payload.cmdline,
-        ) {
-            (Some(firmware), None, None, None) => {
+        match (&payload.firmware, &payload.kernel, &payload.cmdline) {
+            (Some(firmware), None, None) if payload.initramfs.is_empty() => {
                 let firmware = File::open(firmware).map_err(Error::FirmwareFile)?;
                 Self::load_kernel(firmware, None, memory_manager)
             }
-            (None, Some(kernel), _, _) => {
+            (None, Some(kernel), _) => {
                 let kernel = File::open(kernel).map_err(Error::KernelFile)?;
                 let cmdline = Self::generate_cmdline(payload)?;
                 Self::load_kernel(kernel, Some(cmdline), memory_manager)
@@ -1032,7 +1434,7 @@ impl Vm {
     fn load_payload_async(
         memory_manager: &Arc<Mutex<MemoryManager>>,
         config: &Arc<Mutex<VmConfig>>,
-    ) -> Result<Option<thread::JoinHandle<Result<EntryPoint>>>> {
+    ) -> Result<Option<thread::JoinHandle<Result<(EntryPoint, Duration)>>>> {
         // Kernel with TDX is loaded in a different manner
         #[cfg(feature = "tdx")]
         if config.lock().unwrap().is_tdx_enabled() {
@@ -1050,25 +1452,49 @@ impl Vm {
 
                 std::thread::Builder::new()
                     .name("payload_loader".into())
-                    .spawn(move || Self::load_payload(&payload, memory_manager))
+                    .spawn(move || {
+                        let start = Instant::now();
+                        Self::load_payload(&payload, memory_manager)
+                            .map(|entry_point| (entry_point, start.elapsed()))
+                    })
                     .map_err(Error::KernelLoadThreadSpawn)
             })
             .transpose()
     }
 
     #[cfg(target_arch = "x86_64")]
-    fn configure_system(&mut self, rsdp_addr: GuestAddress) -> Result<()> {
+    fn configure_system(&mut self, rsdp_addr: Option<GuestAddress>) -> Result<()> {
         trace_scoped!("configure_system");
         info!("Configuring system");
         let mem = self.memory_manager.lock().unwrap().boot_guest_memory();
 
-        let initramfs_config = match self.initramfs {
-            Some(_) => Some(self.load_initramfs(&mem)?),
-            None => None,
+        // The command line is loaded along with the kernel, before any device
+        // is created. Load it again if some devices need to be described
+        // through it, which is the case of virtio-mmio devices when booting
+        // without ACPI.
+        let cmdline_additions = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .cmdline_additions()
+            .to_vec();
+        if !cmdline_additions.is_empty() {
+            let mut cmdline =
+                Self::generate_cmdline(self.config.lock().unwrap().payload.as_ref().unwrap())?;
+            for entry in cmdline_additions.iter() {
+                cmdline.insert_str(entry).map_err(Error::CmdLineInsertStr)?;
+            }
+            linux_loader::loader::load_cmdline(&mem, arch::layout::CMDLINE_START, &cmdline)
+                .map_err(Error::LoadCmdLine)?;
+        }
+
+        let initramfs_config = if self.initramfs.is_empty() {
+            None
+        } else {
+            Some(self.load_initramfs(&mem)?)
         };
 
         let boot_vcpus = self.cpu_manager.lock().unwrap().boot_vcpus();
-        let rsdp_addr = Some(rsdp_addr);
         let sgx_epc_region = self
             .memory_manager
             .lock()
@@ -1085,13 +1511,13 @@ impl Vm {
             .as_ref()
             .and_then(|p| p.serial_number.clone());
 
-        let uuid = self
-            .config
-            .lock()
-            .unwrap()
-            .platform
-            .as_ref()
-            .and_then(|p| p.uuid.clone());
+        let uuid = self.config.lock().unwrap().platform.as_ref().and_then(|p| {
+            p.uuid.clone().or_else(|| {
+                p.seed
+                    .as_deref()
+                    .map(|seed| IdentitySeed::new(seed).uuid("system"))
+            })
+        });
 
         let oem_strings = self
             .config
@@ -1105,6 +1531,8 @@ impl Vm {
             .as_deref()
             .map(|strings| strings.iter().map(|s| s.as_ref()).collect::<Vec<&str>>());
 
+        let virtio_mmio_irqs = self.device_manager.lock().unwrap().virtio_mmio_irqs();
+
         arch::configure_system(
             &mem,
             arch::layout::CMDLINE_START,
@@ -1115,13 +1543,14 @@ impl Vm {
             serial_number.as_deref(),
             uuid.as_deref(),
             oem_strings.as_deref(),
+            &virtio_mmio_irqs,
         )
         .map_err(Error::ConfigureSystem)?;
         Ok(())
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn configure_system(&mut self, _rsdp_addr: GuestAddress) -> Result<()> {
+    fn configure_system(&mut self, _rsdp_addr: Option<GuestAddress>) -> Result<()> {
         let cmdline = Self::generate_cmdline(
             self.config.lock().unwrap().payload.as_ref().unwrap(),
             &self.device_manager,
@@ -1130,9 +1559,10 @@ impl Vm {
         let vcpu_topology = self.cpu_manager.lock().unwrap().get_vcpu_topology();
         let mem = self.memory_manager.lock().unwrap().boot_guest_memory();
         let mut pci_space_info: Vec<PciSpaceInfo> = Vec::new();
-        let initramfs_config = match self.initramfs {
-            Some(_) => Some(self.load_initramfs(&mem)?),
-            None => None,
+        let initramfs_config = if self.initramfs.is_empty() {
+            None
+        } else {
+            Some(self.load_initramfs(&mem)?)
         };
 
         let device_info = &self
@@ -1225,6 +1655,10 @@ impl Vm {
 
         state.valid_transition(new_state)?;
 
+        if let Some(sampler) = self.counters_sampler.take() {
+            sampler.stop()?;
+        }
+
         // Wake up the DeviceManager threads so they will get terminated cleanly
         self.device_manager
             .lock()
@@ -1251,7 +1685,7 @@ impl Vm {
 
     pub fn resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_memory: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> Result<()> {
@@ -1340,37 +1774,41 @@ impl Vm {
     pub fn resize_zone(&mut self, id: String, desired_memory: u64) -> Result<()> {
         let memory_config = &mut self.config.lock().unwrap().memory;
 
-        if let Some(zones) = &mut memory_config.zones {
-            for zone in zones.iter_mut() {
-                if zone.id == id {
-                    if desired_memory >= zone.size {
-                        let hotplugged_size = desired_memory - zone.size;
-                        self.memory_manager
-                            .lock()
-                            .unwrap()
-                            .resize_zone(&id, desired_memory - zone.size)
-                            .map_err(Error::MemoryManager)?;
-                        // We update the memory zone config regardless of the
-                        // actual 'resize-zone' operation result (happened or
-                        // not), so that if the VM reboots it will be running
-                        // with the last configured memory zone size.
-                        zone.hotplugged_size = Some(hotplugged_size);
-
-                        return Ok(());
-                    } else {
-                        error!(
-                            "Invalid to ask less ({}) than boot RAM ({}) for \
-                            this memory zone",
-                            desired_memory, zone.size,
-                        );
-                        return Err(Error::ResizeZone);
-                    }
-                }
-            }
+        let zone = memory_config
+            .zones
+            .iter_mut()
+            .flatten()
+            .find(|zone| zone.id == id)
+            .ok_or_else(|| Error::UnknownMemoryZone(id.clone()))?;
+
+        // Only the hotpluggable memory of the zone, backed by its virtio-mem
+        // device, can be plugged or unplugged. Its boot memory stays in place.
+        let hotplug_size = zone
+            .hotplug_size
+            .ok_or_else(|| Error::MemoryZoneNotResizable(id.clone()))?;
+        let max_size = zone.size + hotplug_size;
+        if desired_memory < zone.size || desired_memory > max_size {
+            return Err(Error::InvalidMemoryZoneSize {
+                id,
+                size: desired_memory,
+                min: zone.size,
+                max: max_size,
+            });
         }
 
-        error!("Could not find the memory zone {} for the resize", id);
-        Err(Error::ResizeZone)
+        let hotplugged_size = desired_memory - zone.size;
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .resize_zone(&id, hotplugged_size)
+            .map_err(Error::MemoryManager)?;
+        // We update the memory zone config regardless of the actual
+        // 'resize-zone' operation result (happened or not), so that if the
+        // VM reboots it will be running with the last configured memory zone
+        // size.
+        zone.hotplugged_size = Some(hotplugged_size);
+
+        Ok(())
     }
 
     pub fn add_device(&mut self, mut device_cfg: DeviceConfig) -> Result<PciDeviceInfo> {
@@ -1584,8 +2022,146 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn counters(&self) -> Result<HashMap<String, HashMap<&'static str, Wrapping<u64>>>> {
-        Ok(self.device_manager.lock().unwrap().counters())
+    fn read_counters(
+        device_manager: &Mutex<DeviceManager>,
+        cpu_manager: &Mutex<cpu::CpuManager>,
+    ) -> VmCounters {
+        VmCounters {
+            devices: device_manager.lock().unwrap().counters(),
+            vcpus: cpu_manager.lock().unwrap().counters(),
+        }
+    }
+
+    pub fn counters(&self) -> Result<VmCounters> {
+        let counters = Self::read_counters(&self.device_manager, &self.cpu_manager);
+        Ok(counters.delta(&self.counters_baseline))
+    }
+
+    /// Restarts the counters from zero if `reset` is set, and starts sending
+    /// their increments to the event monitor every `sampling_interval_secs`
+    /// seconds if provided, 0 stopping the sampling. The baseline the
+    /// counters are reported against is only updated from the VMM thread,
+    /// along with the API requests reading them, so that a reader never
+    /// observes a partially reset set of counters.
+    pub fn configure_counters(
+        &mut self,
+        reset: bool,
+        sampling_interval_secs: Option<u64>,
+    ) -> Result<()> {
+        if reset {
+            self.counters_baseline = Self::read_counters(&self.device_manager, &self.cpu_manager);
+        }
+
+        if let Some(interval) = sampling_interval_secs {
+            if let Some(sampler) = self.counters_sampler.take() {
+                sampler.stop()?;
+            }
+
+            if interval > 0 {
+                self.counters_sampler = Some(CountersSampler::start(
+                    Duration::from_secs(interval),
+                    self.device_manager.clone(),
+                    self.cpu_manager.clone(),
+                )?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts sampling the guest memory dirty rate, relying on the same
+    /// dirty pages tracking as live migration and differential snapshots,
+    /// hence it can't be started while any of them is tracking.
+    pub fn start_dirty_rate(&mut self) -> Result<()> {
+        if self.dirty_rate_start.is_some() {
+            return Err(Error::DirtyRateAlreadyStarted);
+        }
+        if self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .tracks_snapshot_changes()
+        {
+            return Err(Error::DirtyRateTrackingInUse);
+        }
+
+        self.start_dirty_log().map_err(Error::DirtyRate)?;
+        // Forget about the pages dirtied before the sampling started.
+        if let Err(e) = self.dirty_log() {
+            let _ = self.stop_dirty_log();
+            return Err(Error::DirtyRate(e));
+        }
+        self.dirty_rate_start = Some(Instant::now());
+        self.dirty_rate_bytes = 0;
+
+        Ok(())
+    }
+
+    /// Stops sampling the guest memory dirty rate.
+    pub fn stop_dirty_rate(&mut self) -> Result<()> {
+        if self.dirty_rate_start.take().is_none() {
+            return Err(Error::DirtyRateNotStarted);
+        }
+
+        self.stop_dirty_log().map_err(Error::DirtyRate)
+    }
+
+    /// Whether the guest memory dirty rate is being sampled, in which case
+    /// the dirty pages can't be tracked for anything else.
+    pub fn dirty_rate_started(&self) -> bool {
+        self.dirty_rate_start.is_some()
+    }
+
+    /// Estimates the guest memory dirty rate from the pages dirtied since
+    /// `start_dirty_rate()` was called. The estimate covers the whole
+    /// sampling window, which keeps going until `stop_dirty_rate()`.
+    pub fn dirty_rate(&mut self) -> Result<VmDirtyRate> {
+        let start = self.dirty_rate_start.ok_or(Error::DirtyRateNotStarted)?;
+
+        // Reading the dirty log clears it, hence the pages dirtied since the
+        // previous estimate add up to the ones accounted so far.
+        let table = self.dirty_log().map_err(Error::DirtyRate)?;
+        let elapsed = start.elapsed();
+        self.dirty_rate_bytes += table.regions().iter().map(|r| r.length).sum::<u64>();
+
+        let dirty_bytes = self.dirty_rate_bytes;
+        let secs = elapsed.as_secs_f64();
+        let (dirty_pages_per_sec, dirty_rate_mib_per_sec) = if secs > 0.0 {
+            (
+                ((dirty_bytes >> 12) as f64 / secs) as u64,
+                (dirty_bytes as f64 / (1 << 20) as f64) / secs,
+            )
+        } else {
+            (0, 0.0)
+        };
+
+        Ok(VmDirtyRate {
+            sample_duration_ms: elapsed.as_millis() as u64,
+            sampled_regions: self.memory_manager.lock().unwrap().num_guest_ram_mappings(),
+            dirty_bytes,
+            dirty_pages_per_sec,
+            dirty_rate_mib_per_sec,
+        })
+    }
+
+    pub fn topology(&self) -> VmTopology {
+        let guest_memory = self.memory_manager.lock().unwrap().guest_memory().memory();
+        let ram_regions = guest_memory
+            .iter()
+            .map(|r| (r.start_addr().raw_value(), r.len()));
+
+        VmTopology::new(
+            &self.config.lock().unwrap(),
+            self.cpu_manager.lock().unwrap().present_vcpus(),
+            ram_regions,
+            &self
+                .device_manager
+                .lock()
+                .unwrap()
+                .device_tree()
+                .lock()
+                .unwrap(),
+        )
     }
 
     #[cfg(feature = "tdx")]
@@ -1843,11 +2419,13 @@ impl Vm {
 
         // Loop over the ACPI tables and copy them to the HOB.
 
+        let custom_dsdt = self.custom_dsdt()?;
         for acpi_table in crate::acpi::create_acpi_tables_tdx(
             &self.device_manager,
             &self.cpu_manager,
             &self.memory_manager,
             &self.numa_nodes,
+            custom_dsdt.as_ref(),
         ) {
             hob.add_acpi_table(&mem, acpi_table.as_slice())
                 .map_err(Error::PopulateHob)?;
@@ -1884,15 +2462,72 @@ impl Vm {
         Ok(())
     }
 
+    // Encrypts the firmware along with the first MiB of memory, holding the
+    // boot information and the ACPI tables, and the state of the configured
+    // vCPUs, all of them being part of the launch measurement. The rest of
+    // the guest memory is only registered, the guest accepting it by itself.
+    #[cfg(feature = "sev")]
+    fn launch_sev_es(&mut self, entry_point: Option<EntryPoint>) -> Result<()> {
+        let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
+        let mem = guest_memory.memory();
+
+        for region in mem.iter() {
+            self.vm
+                .sev_register_memory_region(region.as_ptr() as u64, region.len())
+                .map_err(Error::InitializeSevEsMemoryRegion)?;
+        }
+
+        let mut measured_ranges = vec![(
+            arch::layout::LOW_RAM_START.0,
+            arch::layout::HIGH_RAM_START.0,
+        )];
+        if let Some(entry_point) = entry_point {
+            // The memory is encrypted by whole pages.
+            let (start, size) = entry_point.load_range;
+            measured_ranges.push((
+                start.0 & !(SEV_PAGE_SIZE - 1),
+                (start.0 + size + SEV_PAGE_SIZE - 1) & !(SEV_PAGE_SIZE - 1),
+            ));
+        }
+
+        for (start, end) in measured_ranges {
+            let region = mem
+                .find_region(GuestAddress(start))
+                .filter(|region| end <= region.start_addr().0 + region.len())
+                .ok_or(Error::InvalidSevEsMeasuredRange(start, end))?;
+            let host_address = region.as_ptr() as u64 + (start - region.start_addr().0);
+            self.vm
+                .sev_launch_update_data(host_address, end - start)
+                .map_err(Error::InitializeSevEsMemoryRegion)?;
+        }
+
+        let measurement: String = self
+            .vm
+            .sev_launch_finish()
+            .map_err(Error::FinalizeSevEs)?
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        info!("SEV-ES launch measurement: {}", measurement);
+        event!("vm", "sev-es-launched", "measurement", measurement);
+
+        Ok(())
+    }
+
     // Creates ACPI tables
     // In case of TDX being used, this is a no-op since the tables will be
-    // created and passed when populating the HOB.
+    // created and passed when populating the HOB. This is a no-op as well
+    // when ACPI has been disabled through the platform configuration.
 
-    fn create_acpi_tables(&self) -> Option<GuestAddress> {
+    fn create_acpi_tables(&self) -> Result<Option<GuestAddress>> {
         #[cfg(feature = "tdx")]
         if self.config.lock().unwrap().is_tdx_enabled() {
-            return None;
+            return Ok(None);
+        }
+        if !self.config.lock().unwrap().is_acpi_enabled() {
+            return Ok(None);
         }
+        let custom_dsdt = self.custom_dsdt()?;
         let mem = self.memory_manager.lock().unwrap().guest_memory().memory();
         let tpm_enabled = self.config.lock().unwrap().tpm.is_some();
         let rsdp_addr = crate::acpi::create_acpi_tables(
@@ -1902,10 +2537,30 @@ impl Vm {
             &self.memory_manager,
             &self.numa_nodes,
             tpm_enabled,
+            custom_dsdt.as_ref(),
         );
         info!("Created ACPI tables: rsdp_addr = 0x{:x}", rsdp_addr.0);
 
-        Some(rsdp_addr)
+        Ok(Some(rsdp_addr))
+    }
+
+    // The custom DSDT is read each time the tables are created, so that an
+    // updated table is picked up when the VM reboots.
+    fn custom_dsdt(&self) -> Result<Option<crate::acpi::CustomDsdt>> {
+        let config = self.config.lock().unwrap();
+        let platform = match config.platform.as_ref() {
+            Some(platform) => platform,
+            None => return Ok(None),
+        };
+
+        platform
+            .dsdt
+            .as_ref()
+            .map(|path| {
+                crate::acpi::CustomDsdt::from_file(path, platform.dsdt_append)
+                    .map_err(Error::LoadCustomDsdt)
+            })
+            .transpose()
     }
 
     fn entry_point(&mut self) -> Result<Option<EntryPoint>> {
@@ -1913,7 +2568,12 @@ impl Vm {
 
         self.load_payload_handle
             .take()
-            .map(|handle| handle.join().map_err(Error::KernelLoadThreadJoin)?)
+            .map(|handle| {
+                let (entry_point, duration) =
+                    handle.join().map_err(Error::KernelLoadThreadJoin)??;
+                boot_stage_event("kernel-loaded", duration);
+                Ok(entry_point)
+            })
             .transpose()
     }
 
@@ -1935,11 +2595,12 @@ impl Vm {
 
         // Do earlier to parallelise with loading kernel
         #[cfg(target_arch = "x86_64")]
-        let rsdp_addr = self.create_acpi_tables();
+        let rsdp_addr = self.create_acpi_tables()?;
 
         // Load kernel synchronously or if asynchronous then wait for load to
         // finish.
         let entry_point = self.entry_point()?;
+        let start = Instant::now();
 
         #[cfg(feature = "tdx")]
         let tdx_enabled = self.config.lock().unwrap().is_tdx_enabled();
@@ -1975,15 +2636,11 @@ impl Vm {
         // On aarch64 the ACPI tables depend on the vCPU mpidr which is only
         // available after they are configured
         #[cfg(target_arch = "aarch64")]
-        let rsdp_addr = self.create_acpi_tables();
+        let rsdp_addr = self.create_acpi_tables()?;
 
         // Configure shared state based on loaded kernel
         entry_point
-            .map(|_| {
-                // Safe to unwrap rsdp_addr as we know it can't be None when
-                // the entry_point is Some.
-                self.configure_system(rsdp_addr.unwrap())
-            })
+            .map(|_| self.configure_system(rsdp_addr))
             .transpose()?;
 
         #[cfg(target_arch = "x86_64")]
@@ -2015,14 +2672,23 @@ impl Vm {
             self.vm.tdx_finalize().map_err(Error::FinalizeTdx)?;
         }
 
+        // The vCPUs state can't be changed anymore once the launch is
+        // complete, hence it must happen once they are fully configured.
+        #[cfg(feature = "sev")]
+        if self.config.lock().unwrap().is_sev_es_enabled() {
+            self.launch_sev_es(entry_point)?;
+        }
+
         self.cpu_manager
             .lock()
             .unwrap()
             .start_boot_vcpus(new_state == VmState::BreakPoint)
             .map_err(Error::CpuManager)?;
+        boot_stage_event("vcpus-started", start.elapsed());
 
         let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
         *state = new_state;
+        self.start_time = Some(Instant::now());
         event!("vm", "booted");
         Ok(())
     }
@@ -2049,10 +2715,17 @@ impl Vm {
             .start_restored_vcpus()
             .map_err(Error::CpuManager)?;
 
+        self.start_time = Some(Instant::now());
         event!("vm", "restored");
         Ok(())
     }
 
+    /// Time elapsed since the vCPUs were first started, either by booting or
+    /// by restoring the VM.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.start_time.map(|t| t.elapsed())
+    }
+
     /// Gets a thread-safe reference counted pointer to the VM configuration.
     pub fn get_config(&self) -> Arc<Mutex<VmConfig>> {
         Arc::clone(&self.config)
@@ -2066,11 +2739,66 @@ impl Vm {
             .map(|state| *state)
     }
 
+    /// Track the changes made to the guest memory from now on, so that
+    /// differential snapshots can be taken against the snapshot at
+    /// `base_url`.
+    pub fn track_snapshot_base(
+        &mut self,
+        base_url: &str,
+    ) -> std::result::Result<(), MigratableError> {
+        self.start_dirty_log()?;
+        // Forget about the pages written before the base was taken.
+        self.dirty_log()?;
+
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .track_snapshot_base(base_url);
+
+        Ok(())
+    }
+
+    /// Make the next snapshot a differential one, only holding the guest
+    /// pages written since the tracked snapshot at `base_url` was taken.
+    pub fn prepare_differential_snapshot(
+        &mut self,
+        base_url: &str,
+    ) -> std::result::Result<(), MigratableError> {
+        if self.get_state().unwrap() != VmState::Paused {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Trying to snapshot while VM is running"
+            )));
+        }
+
+        if !self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .tracks_snapshot_base(base_url)
+        {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Changes since snapshot {} are not being tracked",
+                base_url
+            )));
+        }
+
+        let dirty = self.dirty_log()?;
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .prepare_differential_snapshot(&dirty)
+    }
+
     /// Gets the actual size of the balloon.
     pub fn balloon_size(&self) -> u64 {
         self.device_manager.lock().unwrap().balloon_size()
     }
 
+    /// Checks whether the last reset request came from the watchdog.
+    pub fn watchdog_triggered(&self) -> bool {
+        self.device_manager.lock().unwrap().watchdog_triggered()
+    }
+
     pub fn send_memory_fds(
         &mut self,
         socket: &mut UnixStream,
@@ -2160,6 +2888,14 @@ impl Vm {
         self.device_manager.lock().unwrap().device_tree()
     }
 
+    pub fn take_device_backends(&self) -> DeviceBackends {
+        self.device_manager.lock().unwrap().take_device_backends()
+    }
+
+    pub fn update_virtio_features(&self) {
+        self.device_manager.lock().unwrap().update_virtio_features()
+    }
+
     pub fn activate_virtio_devices(&self) -> Result<()> {
         self.device_manager
             .lock()
@@ -2187,10 +2923,54 @@ impl Vm {
             .map_err(Error::PowerButton)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    pub fn nmi(&self, cpu_id: Option<u32>) -> Result<()> {
+        if *self.state.read().unwrap() != VmState::Running {
+            return Err(Error::VmNotRunning);
+        }
+
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .nmi(cpu_id)
+            .map_err(Error::Nmi)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn nmi(&self, _cpu_id: Option<u32>) -> Result<()> {
+        Err(Error::NmiUnsupported)
+    }
+
     pub fn memory_manager_data(&self) -> MemoryManagerSnapshotData {
         self.memory_manager.lock().unwrap().snapshot_data()
     }
 
+    pub fn resident_memory(&self) -> Result<VmResidentMemory> {
+        let (resident, resident_hugetlb) =
+            memory_manager::resident_memory().map_err(Error::ResidentMemory)?;
+
+        Ok(VmResidentMemory {
+            resident,
+            resident_hugetlb,
+        })
+    }
+
+    pub fn tap_names(&self) -> BTreeMap<String, String> {
+        self.device_manager.lock().unwrap().tap_names()
+    }
+
+    pub fn unlock_disk_images(&self) {
+        self.device_manager.lock().unwrap().unlock_disk_images()
+    }
+
+    pub fn lock_disk_images(&self) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .lock_disk_images()
+            .map_err(Error::DeviceManager)
+    }
+
     #[cfg(feature = "guest_debug")]
     pub fn debug_request(
         &mut self,
@@ -2245,7 +3025,7 @@ impl Vm {
         &mut self,
         destination_url: &str,
     ) -> std::result::Result<DumpState, GuestDebuggableError> {
-        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus as u32;
+        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus;
         let elf_note_size = self.get_note_size(NoteDescType::ElfAndVmm, nr_cpus) as isize;
         let mut elf_phdr_num = 1;
         let elf_sh_info = 0;
@@ -2386,6 +3166,13 @@ impl Snapshottable for Vm {
             }
         }
 
+        #[cfg(feature = "sev")]
+        if self.config.lock().unwrap().is_sev_es_enabled() {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshot not possible with SEV-ES VM"
+            )));
+        }
+
         let current_state = self.get_state().unwrap();
         if current_state != VmState::Paused {
             return Err(MigratableError::Snapshot(anyhow!(
@@ -2399,12 +3186,21 @@ impl Snapshottable for Vm {
                 &self.hypervisor,
                 self.config.lock().unwrap().cpus.max_phys_bits,
             );
+            let kvm_pv_features = self
+                .config
+                .lock()
+                .unwrap()
+                .cpus
+                .features
+                .kvm_pv_features
+                .clone();
             arch::generate_common_cpuid(
                 &self.hypervisor,
                 None,
                 None,
                 phys_bits,
                 self.config.lock().unwrap().cpus.kvm_hyperv,
+                &kvm_pv_features,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
             )
@@ -2641,6 +3437,13 @@ impl GuestDebuggable for Vm {
             }
         }
 
+        #[cfg(feature = "sev")]
+        if self.config.lock().unwrap().is_sev_es_enabled() {
+            return Err(GuestDebuggableError::Coredump(anyhow!(
+                "Coredump not possible with SEV-ES VM"
+            )));
+        }
+
         match self.get_state().unwrap() {
             VmState::Running => {
                 self.pause().map_err(GuestDebuggableError::Pause)?;
@@ -2752,6 +3555,72 @@ mod tests {
         test_vm_state_transitions(VmState::Paused);
     }
 
+    #[test]
+    fn test_bar64_sizes() {
+        // 64-bit prefetchable BAR of 32GiB, 32-bit BAR of 16MiB, I/O BAR and
+        // the expansion ROM which isn't a BAR.
+        let resource = "\
+0x0000382000000000 0x00003827ffffffff 0x000000000014220c
+0x0000000000000000 0x0000000000000000 0x0000000000000000
+0x00000000c2000000 0x00000000c2ffffff 0x0000000000040200
+0x0000000000000000 0x0000000000000000 0x0000000000000000
+0x0000000000003000 0x000000000000307f 0x0000000000040101
+0x0000000000000000 0x0000000000000000 0x0000000000000000
+0x0000383000000000 0x00003837ffffffff 0x000000000014220c
+";
+        assert_eq!(bar64_sizes(resource), vec![32 << 30]);
+        assert!(bar64_sizes("").is_empty());
+    }
+
+    #[test]
+    fn test_counters_delta() {
+        let counters = |values: &[(&str, &'static str, u64)]| {
+            let mut counters = Counters::new();
+            for (id, name, value) in values {
+                counters
+                    .entry(id.to_string())
+                    .or_default()
+                    .insert(*name, Wrapping(*value));
+            }
+            counters
+        };
+
+        let previous = counters(&[("_net0", "rx_bytes", 100), ("_net0", "tx_bytes", 50)]);
+        let current = counters(&[
+            ("_net0", "rx_bytes", 150),
+            ("_net0", "tx_bytes", 20),
+            ("_disk0", "read_ops", 7),
+        ]);
+        assert_eq!(
+            counters_delta(&current, &previous),
+            counters(&[
+                ("_net0", "rx_bytes", 50),
+                ("_net0", "tx_bytes", 20),
+                ("_disk0", "read_ops", 7),
+            ])
+        );
+        assert_eq!(
+            counters_delta(&current, &current),
+            counters(&[
+                ("_net0", "rx_bytes", 0),
+                ("_net0", "tx_bytes", 0),
+                ("_disk0", "read_ops", 0),
+            ])
+        );
+
+        let vm_counters = VmCounters {
+            devices: counters(&[("vcpus0", "rx_bytes", 1)]),
+            vcpus: counters(&[("0", "exits", 2)]),
+        };
+        assert_eq!(
+            serde_json::to_value(&vm_counters).unwrap(),
+            serde_json::json!({
+                "vcpus0": { "rx_bytes": 1 },
+                "vcpus": { "0": { "exits": 2 } },
+            })
+        );
+    }
+
     #[cfg(feature = "tdx")]
     #[test]
     fn test_hob_memory_resources() {
@@ -2,14 +2,26 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 //
+#[cfg(target_arch = "x86_64")]
+use arch::KvmPvFeature;
+use block::{zoned::ZoneLayout, SECTOR_SIZE};
 use net_util::MacAddr;
-use serde::{Deserialize, Serialize};
-use std::{net::Ipv4Addr, path::PathBuf};
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(target_arch = "x86_64")]
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
 use virtio_devices::RateLimiterConfig;
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CpuAffinity {
-    pub vcpu: u8,
+    pub vcpu: u32,
     pub host_cpus: Vec<u8>,
 }
 
@@ -18,6 +30,9 @@ pub struct CpuFeatures {
     #[cfg(target_arch = "x86_64")]
     #[serde(default)]
     pub amx: bool,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub kvm_pv_features: BTreeMap<KvmPvFeature, bool>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -37,23 +52,83 @@ pub fn default_cpuconfig_max_phys_bits() -> u8 {
     DEFAULT_MAX_PHYS_BITS
 }
 
+pub fn default_cpuconfig_hypervisor_cpuid() -> bool {
+    true
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CpusConfig {
-    pub boot_vcpus: u8,
-    pub max_vcpus: u8,
+    pub boot_vcpus: u32,
+    pub max_vcpus: u32,
     #[serde(default)]
     pub topology: Option<CpuTopology>,
     #[serde(default)]
+    pub host_topology: bool,
+    #[serde(default)]
     pub kvm_hyperv: bool,
     #[serde(default = "default_cpuconfig_max_phys_bits")]
     pub max_phys_bits: u8,
     #[serde(default)]
+    pub auto_phys_bits: bool,
+    #[serde(default)]
     pub affinity: Option<Vec<CpuAffinity>>,
     #[serde(default)]
     pub features: CpuFeatures,
+    #[serde(default)]
+    pub tsc_freq: Option<u64>,
+    #[serde(default)]
+    pub idle: Option<CpuIdle>,
+    #[serde(default)]
+    pub stable_id: Option<CpuStableId>,
+    #[serde(default = "default_cpuconfig_hypervisor_cpuid")]
+    pub hypervisor_cpuid: bool,
+    #[serde(default)]
+    pub lapic_timer: Option<LapicTimer>,
 }
 
-pub const DEFAULT_VCPUS: u8 = 1;
+/// CPU identity exposed to the guest instead of the host one, so that it
+/// doesn't change when the VM is migrated to a different host CPU.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CpuStableId {
+    pub family: u16,
+    pub model: u8,
+    pub stepping: u8,
+    pub brand: String,
+}
+
+impl Default for CpuStableId {
+    // Signature of a Skylake server, the oldest CPU still common among cloud
+    // hosts, rather than a legacy family 15 one guests would handle as a
+    // Pentium 4.
+    fn default() -> Self {
+        CpuStableId {
+            family: 6,
+            model: 0x55,
+            stepping: 4,
+            brand: "Cloud Hypervisor Virtual CPU".to_owned(),
+        }
+    }
+}
+
+/// What a vCPU does when the guest halts it, waiting for an interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CpuIdle {
+    /// Block the vCPU thread right away
+    Halt,
+    /// Busy-poll for a wakeup event before blocking the vCPU thread
+    Poll,
+}
+
+/// Timer modes of the local APIC advertised to the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LapicTimer {
+    /// TSC-deadline mode, along with the one-shot and periodic modes
+    TscDeadline,
+    /// One-shot and periodic modes only, hiding the TSC-deadline mode
+    Periodic,
+}
+
+pub const DEFAULT_VCPUS: u32 = 1;
 
 impl Default for CpusConfig {
     fn default() -> Self {
@@ -61,10 +136,17 @@ impl Default for CpusConfig {
             boot_vcpus: DEFAULT_VCPUS,
             max_vcpus: DEFAULT_VCPUS,
             topology: None,
+            host_topology: false,
             kvm_hyperv: false,
             max_phys_bits: DEFAULT_MAX_PHYS_BITS,
+            auto_phys_bits: false,
             affinity: None,
             features: CpuFeatures::default(),
+            tsc_freq: None,
+            idle: None,
+            stable_id: None,
+            hypervisor_cpuid: true,
+            lapic_timer: None,
         }
     }
 }
@@ -74,24 +156,58 @@ pub fn default_platformconfig_num_pci_segments() -> u16 {
     DEFAULT_NUM_PCI_SEGMENTS
 }
 
+pub const DEFAULT_IOMMU_ADDRESS_WIDTH_BITS: u8 = 64;
+pub fn default_platformconfig_iommu_address_width_bits() -> u8 {
+    DEFAULT_IOMMU_ADDRESS_WIDTH_BITS
+}
+
+pub fn default_platformconfig_acpi() -> bool {
+    true
+}
+
+/// Transport layer exposing the virtio devices to the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum VirtioTransportType {
+    /// Each virtio device is a PCI device
+    #[default]
+    Pci,
+    /// Each virtio device is a memory mapped platform device
+    Mmio,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PlatformConfig {
     #[serde(default = "default_platformconfig_num_pci_segments")]
     pub num_pci_segments: u16,
     #[serde(default)]
     pub iommu_segments: Option<Vec<u16>>,
+    #[serde(default = "default_platformconfig_iommu_address_width_bits")]
+    pub iommu_address_width_bits: u8,
     #[serde(default)]
     pub serial_number: Option<String>,
     #[serde(default)]
     pub uuid: Option<String>,
     #[serde(default)]
     pub oem_strings: Option<Vec<String>>,
+    #[serde(default)]
+    pub seed: Option<String>,
+    #[serde(default = "default_platformconfig_acpi")]
+    pub acpi: bool,
+    #[serde(default)]
+    pub transport: VirtioTransportType,
+    #[serde(default)]
+    pub dsdt: Option<PathBuf>,
+    #[serde(default)]
+    pub dsdt_append: bool,
     #[cfg(feature = "tdx")]
     #[serde(default)]
     pub tdx: bool,
     #[cfg(feature = "sev_snp")]
     #[serde(default)]
     pub sev_snp: bool,
+    #[cfg(feature = "sev")]
+    #[serde(default)]
+    pub sev_es: bool,
 }
 
 impl Default for PlatformConfig {
@@ -99,13 +215,21 @@ impl Default for PlatformConfig {
         PlatformConfig {
             num_pci_segments: DEFAULT_NUM_PCI_SEGMENTS,
             iommu_segments: None,
+            iommu_address_width_bits: DEFAULT_IOMMU_ADDRESS_WIDTH_BITS,
             serial_number: None,
             uuid: None,
             oem_strings: None,
+            seed: None,
+            acpi: true,
+            transport: VirtioTransportType::default(),
+            dsdt: None,
+            dsdt_append: false,
             #[cfg(feature = "tdx")]
             tdx: false,
             #[cfg(feature = "sev_snp")]
             sev_snp: false,
+            #[cfg(feature = "sev")]
+            sev_es: false,
         }
     }
 }
@@ -139,6 +263,13 @@ pub enum HotplugMethod {
     VirtioMem,
 }
 
+/// Backend of the guest RAM which isn't backed by a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MemoryBackend {
+    /// Shared memfd sealed against any change of its size
+    Memfd,
+}
+
 fn default_memoryconfig_thp() -> bool {
     true
 }
@@ -147,6 +278,8 @@ fn default_memoryconfig_thp() -> bool {
 pub struct MemoryConfig {
     pub size: u64,
     #[serde(default)]
+    pub file: Option<PathBuf>,
+    #[serde(default)]
     pub mergeable: bool,
     #[serde(default)]
     pub hotplug_method: HotplugMethod,
@@ -166,6 +299,26 @@ pub struct MemoryConfig {
     pub zones: Option<Vec<MemoryZoneConfig>>,
     #[serde(default = "default_memoryconfig_thp")]
     pub thp: bool,
+    #[serde(default)]
+    pub prealloc_threads: Option<usize>,
+    #[serde(default)]
+    pub readonly_rom: Option<ReadonlyRomConfig>,
+    #[serde(default)]
+    pub numa_bind: Option<u32>,
+    #[serde(default)]
+    pub zero_on_free: bool,
+    #[serde(default)]
+    pub dump_on_oom: Option<PathBuf>,
+    #[serde(default)]
+    pub backend: Option<MemoryBackend>,
+    #[serde(default)]
+    pub max_ram: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReadonlyRomConfig {
+    pub address: u64,
+    pub file: PathBuf,
 }
 
 pub const DEFAULT_MEMORY_MB: u64 = 512;
@@ -174,6 +327,7 @@ impl Default for MemoryConfig {
     fn default() -> Self {
         MemoryConfig {
             size: DEFAULT_MEMORY_MB << 20,
+            file: None,
             mergeable: false,
             hotplug_method: HotplugMethod::Acpi,
             hotplug_size: None,
@@ -184,6 +338,13 @@ impl Default for MemoryConfig {
             prefault: false,
             zones: None,
             thp: true,
+            prealloc_threads: None,
+            readonly_rom: None,
+            numa_bind: None,
+            zero_on_free: false,
+            dump_on_oom: None,
+            backend: None,
+            max_ram: None,
         }
     }
 }
@@ -195,6 +356,28 @@ pub enum VhostMode {
     Server,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum CacheMode {
+    #[default]
+    Writeback,
+    Writethrough,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum DiskLocking {
+    #[default]
+    Flock,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum DiskMedia {
+    #[default]
+    Disk,
+    Cdrom,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct DiskConfig {
     pub path: Option<PathBuf>,
@@ -214,6 +397,10 @@ pub struct DiskConfig {
     #[serde(default)]
     pub rate_limiter_config: Option<RateLimiterConfig>,
     #[serde(default)]
+    pub read_rate_limiter_config: Option<RateLimiterConfig>,
+    #[serde(default)]
+    pub write_rate_limiter_config: Option<RateLimiterConfig>,
+    #[serde(default)]
     pub id: Option<String>,
     // For testing use only. Not exposed in API.
     #[serde(default)]
@@ -222,6 +409,44 @@ pub struct DiskConfig {
     pub pci_segment: u16,
     #[serde(default)]
     pub serial: Option<String>,
+    #[serde(default)]
+    pub wwn: Option<String>,
+    #[serde(default)]
+    pub cache: CacheMode,
+    #[serde(default)]
+    pub segment_max: Option<u32>,
+    #[serde(default)]
+    pub size_max: Option<u32>,
+    #[serde(default)]
+    pub media: DiskMedia,
+    #[serde(default)]
+    pub aio_max_inflight: Option<u32>,
+    #[serde(default)]
+    pub overlay: Option<PathBuf>,
+    #[serde(default)]
+    pub overlay_discard: bool,
+    #[serde(default)]
+    pub poll_queue: bool,
+    #[serde(default)]
+    pub poll_queue_us: Option<u64>,
+    #[serde(default)]
+    pub sync_on_flush_interval: u64,
+    #[serde(default)]
+    pub request_timeout: u64,
+    #[serde(default)]
+    pub locking: DiskLocking,
+    #[serde(default)]
+    pub zoned: bool,
+    #[serde(default)]
+    pub zone_size: Option<u64>,
+    #[serde(default)]
+    pub conventional_zones: Option<u32>,
+    #[serde(default)]
+    pub probe_alignment: bool,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub cache_mb: Option<u64>,
 }
 
 pub const DEFAULT_DISK_NUM_QUEUES: usize = 1;
@@ -236,6 +461,10 @@ pub fn default_diskconfig_queue_size() -> u16 {
     DEFAULT_DISK_QUEUE_SIZE
 }
 
+pub const DEFAULT_DISK_POLL_QUEUE_US: u64 = 50;
+
+pub const DEFAULT_DISK_ZONE_SIZE: u64 = 128 << 20;
+
 impl Default for DiskConfig {
     fn default() -> Self {
         Self {
@@ -250,22 +479,102 @@ impl Default for DiskConfig {
             id: None,
             disable_io_uring: false,
             rate_limiter_config: None,
+            read_rate_limiter_config: None,
+            write_rate_limiter_config: None,
             pci_segment: 0,
             serial: None,
+            wwn: None,
+            cache: CacheMode::Writeback,
+            segment_max: None,
+            size_max: None,
+            media: DiskMedia::Disk,
+            aio_max_inflight: None,
+            overlay: None,
+            overlay_discard: false,
+            poll_queue: false,
+            poll_queue_us: None,
+            sync_on_flush_interval: 0,
+            request_timeout: 0,
+            locking: DiskLocking::Flock,
+            zoned: false,
+            zone_size: None,
+            conventional_zones: None,
+            probe_alignment: false,
+            url: None,
+            cache_mb: None,
         }
     }
 }
 
+impl DiskConfig {
+    /// WWN in the form reported to the guest as the virtio-blk serial, for
+    /// lack of a dedicated field: lowercase hexadecimal digits prefixed with
+    /// "0x".
+    pub fn wwn_id(&self) -> Option<String> {
+        self.wwn.as_ref().map(|wwn| {
+            let digits = wwn
+                .strip_prefix("0x")
+                .or_else(|| wwn.strip_prefix("0X"))
+                .unwrap_or(wwn);
+            format!("0x{}", digits.to_lowercase())
+        })
+    }
+
+    /// Host directory the disk image is built from, given as a path
+    /// prefixed with "@".
+    pub fn directory(&self) -> Option<&Path> {
+        self.path
+            .as_ref()
+            .and_then(|path| path.as_os_str().as_bytes().strip_prefix(b"@"))
+            .map(|dir| Path::new(OsStr::from_bytes(dir)))
+    }
+
+    /// Whether the disk image itself is written to, which is not the case of
+    /// a read-only disk or of an image below an overlay.
+    pub fn writes_image(&self) -> bool {
+        !self.readonly && self.overlay.is_none()
+    }
+
+    /// How long the queue workers busy-poll for new requests before waiting
+    /// for a notification again, if at all.
+    pub fn poll_queue_window(&self) -> Option<Duration> {
+        self.poll_queue.then(|| {
+            Duration::from_micros(self.poll_queue_us.unwrap_or(DEFAULT_DISK_POLL_QUEUE_US))
+        })
+    }
+
+    /// How often the writes are synced to the backing file in the
+    /// background, if at all.
+    pub fn sync_interval(&self) -> Option<Duration> {
+        (self.sync_on_flush_interval != 0)
+            .then(|| Duration::from_millis(self.sync_on_flush_interval))
+    }
+
+    /// How long a request can be pending on the backing file before being
+    /// failed, if bounded at all.
+    pub fn request_timeout_duration(&self) -> Option<Duration> {
+        (self.request_timeout != 0).then(|| Duration::from_millis(self.request_timeout))
+    }
+
+    /// Layout of the zones of the disk, if exposed as a zoned device.
+    pub fn zone_layout(&self) -> Option<ZoneLayout> {
+        self.zoned.then(|| ZoneLayout {
+            zone_sectors: self.zone_size.unwrap_or(DEFAULT_DISK_ZONE_SIZE) / SECTOR_SIZE,
+            conventional_zones: self.conventional_zones.unwrap_or_default(),
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct NetConfig {
     #[serde(default = "default_netconfig_tap")]
     pub tap: Option<String>,
     #[serde(default = "default_netconfig_ip")]
-    pub ip: Ipv4Addr,
-    #[serde(default = "default_netconfig_mask")]
-    pub mask: Ipv4Addr,
-    #[serde(default = "default_netconfig_mac")]
-    pub mac: MacAddr,
+    pub ip: IpAddr,
+    #[serde(default)]
+    pub mask: Option<IpAddr>,
+    #[serde(default)]
+    pub mac: Option<MacAddr>,
     #[serde(default)]
     pub host_mac: Option<MacAddr>,
     #[serde(default)]
@@ -277,11 +586,17 @@ pub struct NetConfig {
     #[serde(default = "default_netconfig_queue_size")]
     pub queue_size: u16,
     #[serde(default)]
+    pub rx_queue_size: Option<u16>,
+    #[serde(default)]
+    pub tx_queue_size: Option<u16>,
+    #[serde(default)]
     pub vhost_user: bool,
     pub vhost_socket: Option<String>,
     #[serde(default)]
     pub vhost_mode: VhostMode,
     #[serde(default)]
+    pub vdpa: Option<PathBuf>,
+    #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
     pub fds: Option<Vec<i32>>,
@@ -295,6 +610,14 @@ pub struct NetConfig {
     pub offload_ufo: bool,
     #[serde(default = "default_netconfig_true")]
     pub offload_csum: bool,
+    #[serde(default = "default_netconfig_true")]
+    pub promisc: bool,
+    #[serde(default)]
+    pub host_name: Option<String>,
+    #[serde(default)]
+    pub rate_limit_group: Option<String>,
+    #[serde(default)]
+    pub vhost: bool,
 }
 
 pub fn default_netconfig_true() -> bool {
@@ -305,16 +628,8 @@ pub fn default_netconfig_tap() -> Option<String> {
     None
 }
 
-pub fn default_netconfig_ip() -> Ipv4Addr {
-    Ipv4Addr::new(192, 168, 249, 1)
-}
-
-pub fn default_netconfig_mask() -> Ipv4Addr {
-    Ipv4Addr::new(255, 255, 255, 0)
-}
-
-pub fn default_netconfig_mac() -> MacAddr {
-    MacAddr::local_random()
+pub fn default_netconfig_ip() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(192, 168, 249, 1))
 }
 
 pub const DEFAULT_NET_NUM_QUEUES: usize = 2;
@@ -334,16 +649,19 @@ impl Default for NetConfig {
         Self {
             tap: default_netconfig_tap(),
             ip: default_netconfig_ip(),
-            mask: default_netconfig_mask(),
-            mac: default_netconfig_mac(),
+            mask: None,
+            mac: None,
             host_mac: None,
             mtu: None,
             iommu: false,
             num_queues: default_netconfig_num_queues(),
             queue_size: default_netconfig_queue_size(),
+            rx_queue_size: None,
+            tx_queue_size: None,
             vhost_user: false,
             vhost_socket: None,
             vhost_mode: VhostMode::Client,
+            vdpa: None,
             id: None,
             fds: None,
             rate_limiter_config: None,
@@ -351,24 +669,80 @@ impl Default for NetConfig {
             offload_tso: true,
             offload_ufo: true,
             offload_csum: true,
+            promisc: true,
+            host_name: None,
+            rate_limit_group: None,
+            vhost: false,
         }
     }
 }
 
+impl NetConfig {
+    /// Netmask of the tap interface, derived from the family of its address
+    /// when not set.
+    pub fn netmask(&self) -> IpAddr {
+        self.mask
+            .unwrap_or_else(|| net_util::default_netmask(self.ip))
+    }
+
+    /// Size of the RX queues, falling back onto `queue_size` when not set.
+    pub fn rx_queue_size(&self) -> u16 {
+        self.rx_queue_size.unwrap_or(self.queue_size)
+    }
+
+    /// Size of the TX queues, falling back onto `queue_size` when not set.
+    pub fn tx_queue_size(&self) -> u16 {
+        self.tx_queue_size.unwrap_or(self.queue_size)
+    }
+
+    /// Bridge the created tap interface is enslaved to, given as a tap
+    /// name of the form "@bridge:<name>".
+    pub fn bridge(&self) -> Option<&str> {
+        self.tap
+            .as_deref()
+            .and_then(|tap| tap.strip_prefix("@bridge:"))
+    }
+}
+
+/// Rate limits shared by the network devices referencing the group.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimitGroupConfig {
+    pub id: String,
+    pub rate_limiter_config: RateLimiterConfig,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct RngConfig {
     pub src: PathBuf,
     #[serde(default)]
     pub iommu: bool,
+    #[serde(default)]
+    pub source: Option<RngSource>,
+    #[serde(default = "default_rngconfig_queue_size")]
+    pub queue_size: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RngSource {
+    /// Entropy gathering daemon listening on the given UNIX socket
+    Egd(PathBuf),
+    /// Jitter of the CPU execution time, collected by the VMM
+    Jitter,
 }
 
 pub const DEFAULT_RNG_SOURCE: &str = "/dev/urandom";
 
+pub fn default_rngconfig_queue_size() -> u16 {
+    virtio_devices::DEFAULT_RNG_QUEUE_SIZE
+}
+
 impl Default for RngConfig {
     fn default() -> Self {
         RngConfig {
             src: PathBuf::from(DEFAULT_RNG_SOURCE),
             iommu: false,
+            source: None,
+            queue_size: default_rngconfig_queue_size(),
         }
     }
 }
@@ -387,7 +761,10 @@ pub struct BalloonConfig {
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct FsConfig {
     pub tag: String,
+    #[serde(default)]
     pub socket: PathBuf,
+    #[serde(default)]
+    pub fd: Option<i32>,
     #[serde(default = "default_fsconfig_num_queues")]
     pub num_queues: usize,
     #[serde(default = "default_fsconfig_queue_size")]
@@ -396,12 +773,18 @@ pub struct FsConfig {
     pub id: Option<String>,
     #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default = "default_fsconfig_reconnect")]
+    pub reconnect: bool,
 }
 
 pub fn default_fsconfig_num_queues() -> usize {
     1
 }
 
+pub fn default_fsconfig_reconnect() -> bool {
+    true
+}
+
 pub fn default_fsconfig_queue_size() -> u16 {
     1024
 }
@@ -411,14 +794,22 @@ impl Default for FsConfig {
         Self {
             tag: "".to_owned(),
             socket: PathBuf::new(),
+            fd: None,
             num_queues: default_fsconfig_num_queues(),
             queue_size: default_fsconfig_queue_size(),
             id: None,
             pci_segment: 0,
+            reconnect: default_fsconfig_reconnect(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PmemMemPolicy {
+    Shared,
+    Private,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub struct PmemConfig {
     pub file: PathBuf,
@@ -432,6 +823,20 @@ pub struct PmemConfig {
     pub id: Option<String>,
     #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default)]
+    pub mem_policy: Option<PmemMemPolicy>,
+}
+
+impl PmemConfig {
+    /// Returns whether the backing file must be mapped MAP_PRIVATE. When no
+    /// explicit policy is given, it is inferred from `discard_writes`.
+    pub fn private_mapping(&self) -> bool {
+        match self.mem_policy {
+            Some(PmemMemPolicy::Private) => true,
+            Some(PmemMemPolicy::Shared) => false,
+            None => self.discard_writes,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -443,6 +848,13 @@ pub enum ConsoleOutputMode {
     Null,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum FlowControl {
+    #[default]
+    None,
+    RtsCts,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ConsoleConfig {
     #[serde(default = "default_consoleconfig_file")]
@@ -450,12 +862,50 @@ pub struct ConsoleConfig {
     pub mode: ConsoleOutputMode,
     #[serde(default)]
     pub iommu: bool,
+    #[serde(default)]
+    pub input: Option<ConsoleInputConfig>,
+    #[serde(default = "default_consoleconfig_resize")]
+    pub resize: bool,
+    #[serde(default)]
+    pub rotate: Option<ConsoleRotateConfig>,
+    #[serde(default)]
+    pub flow_control: FlowControl,
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConsoleInputConfig {
+    pub path: PathBuf,
+    /// Delay in milliseconds before the first line is sent
+    #[serde(default)]
+    pub delay: u64,
+    /// Delay in milliseconds between two consecutive lines
+    #[serde(default)]
+    pub pace: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConsoleRotateConfig {
+    /// Size in bytes beyond which the output file is rotated
+    pub size: u64,
+    /// Number of rotated files kept alongside the output file
+    #[serde(default = "default_consolerotateconfig_keep")]
+    pub keep: u32,
+}
+
+pub fn default_consolerotateconfig_keep() -> u32 {
+    1
 }
 
 pub fn default_consoleconfig_file() -> Option<PathBuf> {
     None
 }
 
+pub fn default_consoleconfig_resize() -> bool {
+    true
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub struct DeviceConfig {
     pub path: PathBuf,
@@ -493,6 +943,43 @@ pub fn default_vdpaconfig_num_queues() -> usize {
     1
 }
 
+// Unlike the virtio-block ones, the disks have no rate limiting, caching or
+// image format options, which are rejected rather than silently ignored.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScsiConfig {
+    pub disks: Vec<PathBuf>,
+    #[serde(default)]
+    pub readonly: bool,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default = "default_scsiconfig_queue_size")]
+    pub queue_size: u16,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+}
+
+pub fn default_scsiconfig_queue_size() -> u16 {
+    DEFAULT_SCSI_QUEUE_SIZE
+}
+
+pub const DEFAULT_SCSI_QUEUE_SIZE: u16 = 128;
+
+impl Default for ScsiConfig {
+    fn default() -> Self {
+        Self {
+            disks: Vec::new(),
+            readonly: false,
+            iommu: false,
+            queue_size: default_scsiconfig_queue_size(),
+            id: None,
+            pci_segment: 0,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub struct VsockConfig {
     pub cid: u64,
@@ -528,7 +1015,7 @@ pub struct NumaConfig {
     #[serde(default)]
     pub guest_numa_id: u32,
     #[serde(default)]
-    pub cpus: Option<Vec<u8>>,
+    pub cpus: Option<Vec<u32>>,
     #[serde(default)]
     pub distances: Option<Vec<NumaDistance>>,
     #[serde(default)]
@@ -546,8 +1033,29 @@ pub struct PayloadConfig {
     pub kernel: Option<PathBuf>,
     #[serde(default)]
     pub cmdline: Option<String>,
-    #[serde(default)]
-    pub initramfs: Option<PathBuf>,
+    #[serde(default, deserialize_with = "deserialize_initramfs")]
+    pub initramfs: Vec<PathBuf>,
+}
+
+// Segments are loaded back to back, so that e.g. an early microcode archive
+// can precede the main initrd. A single path is still accepted in place of
+// the list for compatibility with existing API clients.
+fn deserialize_initramfs<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Initramfs {
+        Single(PathBuf),
+        Segments(Vec<PathBuf>),
+    }
+
+    Ok(match Option::<Initramfs>::deserialize(deserializer)? {
+        None => Vec::new(),
+        Some(Initramfs::Single(path)) => vec![path],
+        Some(Initramfs::Segments(paths)) => paths,
+    })
 }
 
 pub fn default_serial() -> ConsoleConfig {
@@ -555,6 +1063,11 @@ pub fn default_serial() -> ConsoleConfig {
         file: None,
         mode: ConsoleOutputMode::Null,
         iommu: false,
+        input: None,
+        resize: true,
+        rotate: None,
+        flow_control: FlowControl::None,
+        timestamps: false,
     }
 }
 
@@ -563,6 +1076,11 @@ pub fn default_console() -> ConsoleConfig {
         file: None,
         mode: ConsoleOutputMode::Tty,
         iommu: false,
+        input: None,
+        resize: true,
+        rotate: None,
+        flow_control: FlowControl::None,
+        timestamps: false,
     }
 }
 
@@ -581,6 +1099,8 @@ pub struct VmConfig {
     pub disks: Option<Vec<DiskConfig>>,
     pub net: Option<Vec<NetConfig>>,
     #[serde(default)]
+    pub rate_limit_groups: Option<Vec<RateLimitGroupConfig>>,
+    #[serde(default)]
     pub rng: RngConfig,
     pub balloon: Option<BalloonConfig>,
     pub fs: Option<Vec<FsConfig>>,
@@ -592,6 +1112,8 @@ pub struct VmConfig {
     pub devices: Option<Vec<DeviceConfig>>,
     pub user_devices: Option<Vec<UserDeviceConfig>>,
     pub vdpa: Option<Vec<VdpaConfig>>,
+    #[serde(default)]
+    pub scsi: Option<Vec<ScsiConfig>>,
     pub vsock: Option<VsockConfig>,
     #[serde(default)]
     pub pvpanic: bool,
@@ -602,6 +1124,9 @@ pub struct VmConfig {
     pub numa: Option<Vec<NumaConfig>>,
     #[serde(default)]
     pub watchdog: bool,
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    #[serde(default)]
+    pub watchdog_coredump: Option<PathBuf>,
     #[cfg(feature = "guest_debug")]
     #[serde(default)]
     pub gdb: bool,